@@ -0,0 +1,196 @@
+use std::rc::Rc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use tracing::{info, warn};
+
+use crate::digitalocean::dns::{DigitalOceanDnsClient, RecordValue};
+use crate::digitalocean::error::Error;
+
+/// The label prepended to a record's name to name its companion audit record
+const AUDIT_LABEL: &str = "_dyn";
+
+/// What `--audit-txt` writes to the companion TXT record of each record a run updates: when the
+/// run happened and which host made it
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct AuditEntry {
+    /// Seconds since the Unix epoch
+    pub updated_at: u64,
+    pub host: String,
+}
+
+impl AuditEntry {
+    /// An entry for a run of this host happening now
+    pub fn now() -> AuditEntry {
+        AuditEntry {
+            updated_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs()),
+            host: host_name(),
+        }
+    }
+
+    /// The TXT data of the entry, e.g. `updated=2024-05-01T12:00:00Z host=router`
+    pub fn data(&self) -> String {
+        format!(
+            "updated={} host={}",
+            format_timestamp(self.updated_at),
+            self.host
+        )
+    }
+}
+
+/// The name of the companion audit record of a record, or `None` for a wildcard record, whose
+/// name cannot have a label prepended
+pub fn audit_record_name(record: &str) -> Option<String> {
+    match record {
+        "@" => Some(AUDIT_LABEL.to_string()),
+        record if record.split('.').any(|label| label == "*") => None,
+        record => Some(format!("{AUDIT_LABEL}.{record}")),
+    }
+}
+
+/// Write the entry to the companion audit record of a record, creating the audit record if it does
+/// not exist yet.  A failure is only logged, as the audit trail must not fail an update that
+/// otherwise succeeded.
+pub async fn write(
+    client: &Rc<dyn DigitalOceanDnsClient>,
+    domain: &str,
+    record: &str,
+    entry: &AuditEntry,
+    ttl: u16,
+    dry_run: bool,
+) {
+    let Some(name) = audit_record_name(record) else {
+        warn!(
+            "Not writing an audit record for the wildcard record {}.{}",
+            record, domain
+        );
+        return;
+    };
+    let value = RecordValue {
+        data: entry.data(),
+        priority: None,
+        port: None,
+        weight: None,
+    };
+    let result = async {
+        match client.get_record(domain, &name, "TXT").await? {
+            Some(existing) => {
+                client
+                    .update_record(domain, &existing, &value, &ttl, &dry_run)
+                    .await?
+            }
+            None => {
+                client
+                    .create_record(domain, &name, "TXT", &value, &ttl, &dry_run)
+                    .await?
+            }
+        };
+        Ok::<_, Error>(())
+    }
+    .await;
+    match result {
+        Ok(()) => info!("Recorded {} in {}.{} (TXT)", value.data, name, domain),
+        Err(e) => warn!("Unable to write audit record {}.{}: {}", name, domain, e),
+    }
+}
+
+/// The name of this host, as far as it can be found without asking the operating system directly
+pub(crate) fn host_name() -> String {
+    ["HOSTNAME", "COMPUTERNAME"]
+        .iter()
+        .filter_map(|var| std::env::var(var).ok())
+        .chain(
+            ["/proc/sys/kernel/hostname", "/etc/hostname"]
+                .iter()
+                .filter_map(|path| std::fs::read_to_string(path).ok()),
+        )
+        .map(|name| name.trim().to_string())
+        .find(|name| !name.is_empty())
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+/// Format seconds since the Unix epoch as an RFC 3339 UTC timestamp
+pub(crate) fn format_timestamp(secs: u64) -> String {
+    let (year, month, day) = civil_from_days(secs / 86400);
+    let secs_of_day = secs % 86400;
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        secs_of_day / 3600,
+        secs_of_day % 3600 / 60,
+        secs_of_day % 60
+    )
+}
+
+/// The year, month and day of the days since 1970-01-01, after Howard Hinnant's civil_from_days
+pub(crate) fn civil_from_days(days: u64) -> (u64, u64, u64) {
+    let z = days + 719468;
+    let era = z / 146097;
+    let day_of_era = z % 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    (year_of_era + era * 400 + u64::from(month <= 2), month, day)
+}
+
+#[cfg(test)]
+mod test {
+    use std::rc::Rc;
+
+    use crate::audit::{audit_record_name, format_timestamp, write, AuditEntry};
+    use crate::digitalocean::dns::DigitalOceanDnsClient;
+    use crate::digitalocean::mock::MockDnsClient;
+
+    #[test]
+    fn test_format_timestamp() {
+        assert_eq!("1970-01-01T00:00:00Z", format_timestamp(0));
+        assert_eq!("2000-02-29T23:59:59Z", format_timestamp(951868799));
+        assert_eq!("2024-12-31T12:30:05Z", format_timestamp(1735648205));
+    }
+
+    #[test]
+    fn test_audit_record_name() {
+        assert_eq!(Some("_dyn".to_string()), audit_record_name("@"));
+        assert_eq!(Some("_dyn.home".to_string()), audit_record_name("home"));
+        assert_eq!(
+            Some("_dyn.www.home".to_string()),
+            audit_record_name("www.home")
+        );
+        assert_eq!(None, audit_record_name("*"));
+        assert_eq!(None, audit_record_name("*.home"));
+    }
+
+    #[tokio::test]
+    async fn test_write() {
+        let client: Rc<dyn DigitalOceanDnsClient> =
+            Rc::new(MockDnsClient::new().with_domain("example.com"));
+        let entry = AuditEntry {
+            updated_at: 0,
+            host: "router".to_string(),
+        };
+
+        write(&client, "example.com", "home", &entry, 60, false).await;
+        let record = client
+            .get_record("example.com", "_dyn.home", "TXT")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!("updated=1970-01-01T00:00:00Z host=router", record.data);
+
+        // a later run replaces the entry rather than adding another
+        let entry = AuditEntry {
+            updated_at: 86400,
+            ..entry
+        };
+        write(&client, "example.com", "home", &entry, 60, false).await;
+        let records = client.get_records("example.com", "TXT").await.unwrap();
+        assert_eq!(1, records.len());
+        assert_eq!("updated=1970-01-02T00:00:00Z host=router", records[0].data);
+    }
+}
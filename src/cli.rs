@@ -1,251 +1,3361 @@
+use std::fmt::{Display, Formatter};
 use std::net::IpAddr;
+use std::path::PathBuf;
+use std::time::Duration;
 
-use clap::{crate_name, crate_version, ArgMatches, Id};
-use tracing::info;
+use clap::error::ErrorKind;
+use clap::{crate_name, crate_version, ArgAction, ArgMatches, Id};
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
 
+use crate::config;
+use crate::config::{Config, JobConfig};
+use crate::cron::Cron;
+use crate::digitalocean::api::RetryPolicy;
+use crate::digitalocean::dns::MIN_TTL;
+use crate::digitalocean::firewall::ALL_PORTS;
+use crate::email::EmailOptions;
+use crate::hooks::Hooks;
+use crate::http;
+use crate::http::HttpOptions;
 use crate::ip_retriever;
+use crate::ip_retriever::{IpFamily, IpProvider, IpSource};
+use crate::logging::{LogArgs, LogFormat};
+use crate::provider::Provider;
+use crate::secret::Secret;
+use crate::service;
 
 #[derive(Debug)]
+pub enum Invocation {
+    /// Update DigitalOcean resources with the detected IP address
+    Update(Box<Args>),
+    /// Only detect and print the IP address
+    Ip(IpArgs),
+    /// Compare the rules of a firewall with a rules file without changing anything
+    FirewallDiff(FirewallDiffArgs),
+    /// Attach a firewall to droplets by name
+    FirewallAssign(FirewallAssignArgs),
+    /// Delete DNS records
+    DnsDelete(DnsDeleteArgs),
+    /// List the DNS records of a domain
+    DnsList(DnsListArgs),
+    /// List the droplets of the account
+    DropletList(DropletListArgs),
+    /// Check that the API token is accepted and print the account it belongs to
+    Check(CheckArgs),
+    /// Check every dependency of an update without changing anything
+    Doctor(Box<DoctorArgs>),
+    /// Install the program as a service of the host's service manager
+    ServiceInstall(ServiceInstallArgs),
+    /// Print the log of address changes and updates
+    History(HistoryArgs),
+    /// Print the effective settings and exit
+    PrintConfig(Box<Config>),
+    /// Print a starter config file built from the flags of the invocation
+    ConfigInit(Box<ConfigInitArgs>),
+    /// Print a config file describing the current records and firewall rules
+    ConfigImport(Box<ConfigImportArgs>),
+}
+
+impl Invocation {
+    /// Where to log and how much
+    pub fn log_args(&self) -> LogArgs {
+        match self {
+            Invocation::Update(args) => args.log.clone(),
+            Invocation::Ip(ip_args) => ip_args.log.clone(),
+            Invocation::FirewallDiff(diff_args) => diff_args.log.clone(),
+            Invocation::FirewallAssign(assign_args) => assign_args.log.clone(),
+            Invocation::DnsDelete(delete_args) => delete_args.log.clone(),
+            Invocation::DnsList(list_args) => list_args.log.clone(),
+            Invocation::DropletList(list_args) => list_args.log.clone(),
+            Invocation::Check(check_args) => check_args.log.clone(),
+            Invocation::Doctor(doctor_args) => doctor_args.update.log.clone(),
+            Invocation::ServiceInstall(install_args) => install_args.log.clone(),
+            Invocation::History(history_args) => history_args.log.clone(),
+            Invocation::ConfigImport(import_args) => import_args.log.clone(),
+            Invocation::PrintConfig(_) | Invocation::ConfigInit(_) => LogArgs::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct Args {
-    pub token: String,
-    pub ip: IpAddr,
+    /// The API token, only missing when there are no targets besides those of jobs that all have
+    /// their own
+    pub token: Option<Secret>,
+    /// The API token for DNS targets hosted by Cloudflare, if any are
+    pub cloudflare_token: Option<Secret>,
+    pub ip: IpSpec,
+    pub verbose: bool,
+    pub log: LogArgs,
     pub dry_run: bool,
-    pub subcmd_args: SubcmdArgs,
+    pub check: bool,
+    /// Exit with `EXIT_CHANGED` instead of 0 when a resource was changed
+    pub detailed_exitcode: bool,
+    pub stats_json: bool,
+    pub profile: bool,
+    pub daemon: bool,
+    /// Report to the service manager of the host while running as a daemon
+    pub service: bool,
+    pub interval: u64,
+    /// How many checks in a row must detect a new address before it is published in daemon mode
+    pub stabilize_checks: u32,
+    pub verify: bool,
+    pub hooks: Hooks,
+    pub max_pages: Option<u32>,
+    /// How many objects to request in each page of a listing
+    pub per_page: u32,
+    /// How many pages of a listing to fetch at once
+    pub page_concurrency: usize,
+    pub retry: RetryPolicy,
+    pub http: HttpOptions,
+    /// Longest time a run may take before it is abandoned
+    pub deadline: Option<Duration>,
+    pub ip_out: Option<PathBuf>,
+    /// Where to keep what was last published to each target between runs
+    pub state_file: Option<PathBuf>,
+    /// Where to log every change of the detected address and every update applied
+    pub history_file: Option<PathBuf>,
+    /// A file locked while a run changes its targets, so that overlapping runs take turns
+    pub lock_file: Option<PathBuf>,
+    /// How long to wait for another run to release the lock file
+    pub lock_wait: Duration,
+    /// Where to cache the listings used to resolve names to IDs between runs, unless `--no-cache`
+    /// is given
+    pub cache_file: Option<PathBuf>,
+    /// How long a cached listing is used before it is fetched again
+    pub cache_ttl: Duration,
+    /// Where to report the outcome of each run for dead man's switch monitoring
+    pub heartbeat_url: Option<String>,
+    /// Where to email changes of address and repeated failures
+    pub email: Option<EmailOptions>,
+    /// The DNS records and firewall rules to update, either the one given on the command line or
+    /// every target in the config file
+    pub targets: Vec<SubcmdArgs>,
+    /// Groups of targets from the config file that the daemon updates on their own schedule
+    pub jobs: Vec<Job>,
 }
 
-#[derive(Debug)]
+/// Targets from the config file that the daemon checks on their own schedule
+#[derive(Debug, Clone)]
+pub struct Job {
+    pub name: String,
+    pub interval: u64,
+    /// When to check, instead of every `interval`
+    pub cron: Option<Cron>,
+    /// The longest random delay added to each check, if not the default
+    pub jitter: Option<Duration>,
+    /// The API token of the account holding the targets, if not the one of the other settings
+    pub token: Option<Secret>,
+    pub targets: Vec<SubcmdArgs>,
+}
+
+/// A DNS record or firewall rule to update.  In a config file each target is tagged with its
+/// `type` and has the same settings as the corresponding subcommand.
+#[derive(Deserialize, Serialize, Debug, Clone, Eq, PartialEq)]
+#[serde(tag = "type", rename_all = "lowercase")]
 pub enum SubcmdArgs {
     Dns(DnsArgs),
     Firewall(FirewallArgs),
+    #[serde(rename = "reserved-ip")]
+    ReservedIp(ReservedIpArgs),
+}
+
+/// Where the IP address to publish comes from
+#[derive(Debug, Clone)]
+pub enum IpSpec {
+    Literal(IpAddr),
+    /// Detect the address, asking the providers in order when detecting the external address
+    Detect(IpSource, Vec<IpProvider>),
+    /// Use the global-scope address of the named local network interface
+    Interface(String),
 }
 
 #[derive(Debug)]
-pub struct DnsArgs {
+pub struct IpArgs {
+    pub log: LogArgs,
+    pub source: IpSource,
+    pub providers: Vec<IpProvider>,
+    pub http: HttpOptions,
+    pub json: bool,
+    pub ip_out: Option<PathBuf>,
+}
+
+#[derive(Debug)]
+pub struct FirewallDiffArgs {
+    pub token: Secret,
+    pub log: LogArgs,
+    pub max_pages: Option<u32>,
+    /// How many objects to request in each page of a listing
+    pub per_page: u32,
+    /// How many pages of a listing to fetch at once
+    pub page_concurrency: usize,
+    pub retry: RetryPolicy,
+    pub http: HttpOptions,
+    pub name: String,
+    pub rules_file: PathBuf,
+    pub json: bool,
+}
+
+#[derive(Debug)]
+pub struct FirewallAssignArgs {
+    pub token: Secret,
+    pub log: LogArgs,
+    pub max_pages: Option<u32>,
+    /// How many objects to request in each page of a listing
+    pub per_page: u32,
+    /// How many pages of a listing to fetch at once
+    pub page_concurrency: usize,
+    pub retry: RetryPolicy,
+    pub http: HttpOptions,
+    pub dry_run: bool,
+    pub name: String,
+    pub droplets: Vec<String>,
+    /// Also detach the firewall from droplets that are not listed
+    pub exclusive: bool,
+}
+
+#[derive(Debug)]
+pub struct DnsDeleteArgs {
+    pub token: Secret,
+    pub log: LogArgs,
+    pub max_pages: Option<u32>,
+    /// How many objects to request in each page of a listing
+    pub per_page: u32,
+    /// How many pages of a listing to fetch at once
+    pub page_concurrency: usize,
+    pub retry: RetryPolicy,
+    pub http: HttpOptions,
+    pub dry_run: bool,
     pub record: String,
+    pub domain: Option<String>,
+    /// Treat the record as a fully qualified name even without a trailing dot
+    pub fqdn: bool,
+    pub rtype: String,
+    /// Only delete the record holding this value, rather than every record of the name and type
+    pub data: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct DnsListArgs {
+    pub token: Secret,
+    pub log: LogArgs,
+    pub max_pages: Option<u32>,
+    /// How many objects to request in each page of a listing
+    pub per_page: u32,
+    /// How many pages of a listing to fetch at once
+    pub page_concurrency: usize,
+    pub retry: RetryPolicy,
+    pub http: HttpOptions,
     pub domain: String,
+    /// Only list records of this type
+    pub rtype: Option<String>,
+    /// Only list records of this name within the domain
+    pub name: Option<String>,
+    pub json: bool,
+}
+
+#[derive(Debug)]
+pub struct DropletListArgs {
+    pub token: Secret,
+    pub log: LogArgs,
+    pub max_pages: Option<u32>,
+    /// How many objects to request in each page of a listing
+    pub per_page: u32,
+    /// How many pages of a listing to fetch at once
+    pub page_concurrency: usize,
+    pub retry: RetryPolicy,
+    pub http: HttpOptions,
+    pub json: bool,
+}
+
+#[derive(Debug)]
+pub struct CheckArgs {
+    pub token: Secret,
+    pub log: LogArgs,
+    pub retry: RetryPolicy,
+    pub http: HttpOptions,
+    pub json: bool,
+}
+
+#[derive(Debug)]
+pub struct HistoryArgs {
+    pub log: LogArgs,
+    pub history_file: PathBuf,
+    /// Only print this many of the most recent entries
+    pub limit: Option<usize>,
+    pub json: bool,
+}
+
+#[derive(Debug)]
+pub struct ConfigInitArgs {
+    /// The settings of the invocation, with a target for each `--record` and `--firewall`
+    pub config: Config,
+    /// The settings of an invocation without any flags, which the starter config leaves out
+    pub defaults: Config,
+}
+
+#[derive(Debug)]
+pub struct ConfigImportArgs {
+    pub token: Secret,
+    pub log: LogArgs,
+    pub max_pages: Option<u32>,
+    /// How many objects to request in each page of a listing
+    pub per_page: u32,
+    /// How many pages of a listing to fetch at once
+    pub page_concurrency: usize,
+    pub retry: RetryPolicy,
+    pub http: HttpOptions,
+    pub domains: Vec<String>,
+    pub firewalls: Vec<String>,
+    /// The record names whose A and AAAA records publish the detected address
+    pub dynamic: Vec<String>,
+    /// The ports of the firewall rules to import
+    pub ports: Vec<String>,
+    /// The settings of the invocation, to which the imported targets are added
+    pub config: Config,
+    /// The settings of an invocation without any flags, which the config file leaves out
+    pub defaults: Config,
+}
+
+#[derive(Debug)]
+pub struct ServiceInstallArgs {
+    pub log: LogArgs,
+    /// The config file whose targets the service keeps up to date
+    pub config: PathBuf,
+    /// The name to install the service under
+    pub name: String,
+}
+
+#[derive(Debug)]
+pub struct DoctorArgs {
+    /// The settings of the update whose dependencies are checked, with the targets of the config
+    /// file
+    pub update: Args,
+    pub json: bool,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Eq, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct DnsArgs {
+    pub record: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub domain: Option<String>,
+    #[serde(default = "default_rtype")]
     pub rtype: String,
+    /// The value of the record, instead of the detected IP address
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<String>,
+    /// Publish the public address of this droplet instead of the detected IP address
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub from_droplet: Option<String>,
+    /// Publish the address of this load balancer instead of the detected IP address
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub from_load_balancer: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub priority: Option<u16>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub port: Option<u16>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub weight: Option<u16>,
+    #[serde(default = "default_ttl")]
     pub ttl: u16,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lower_ttl: Option<u16>,
+    #[serde(default)]
+    pub aliases: Vec<String>,
+    #[serde(default)]
+    pub with_www: bool,
+    #[serde(default)]
+    pub check_delegation: bool,
+    #[serde(default)]
+    pub force: bool,
+    /// Create the record if it does not exist yet, instead of failing
+    #[serde(default = "default_create_if_missing")]
+    pub create_if_missing: bool,
+    /// Add the domain to the account if it is not there yet, instead of failing
+    #[serde(default)]
+    pub create_domain: bool,
+    /// Update the record of this name in every domain of the account that has one
+    #[serde(default)]
+    pub all_domains: bool,
+    /// Update both the A and AAAA records with the detected IPv4 and IPv6 addresses
+    #[serde(default)]
+    pub dual_stack: bool,
+    /// Treat the record as a fully qualified name even without a trailing dot, so that the domain
+    /// is removed from its end
+    #[serde(default)]
+    pub fqdn: bool,
+    /// Write when and from which host each record was last updated to its companion `_dyn` TXT
+    /// record
+    #[serde(default)]
+    pub audit_txt: bool,
+    /// The service that hosts the record
+    #[serde(default, skip_serializing_if = "Provider::is_default")]
+    pub provider: Provider,
 }
 
-#[derive(Debug)]
+/// The DNS record types that can be kept up to date
+pub const RECORD_TYPES: [&str; 6] = ["A", "AAAA", "CNAME", "MX", "SRV", "TXT"];
+
+fn default_rtype() -> String {
+    "A".to_string()
+}
+
+fn default_ttl() -> u16 {
+    60
+}
+
+fn default_create_if_missing() -> bool {
+    true
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Eq, PartialEq)]
+#[serde(deny_unknown_fields)]
 pub struct FirewallArgs {
     pub name: String,
+    /// Treat `name` as a glob pattern and update the rule of every firewall whose name matches it
+    #[serde(default)]
+    pub all_matching: bool,
     pub direction: Direction,
     pub port: String,
-    pub protocol: String,
+    pub protocols: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub addresses: Option<Vec<String>>,
+    /// VPCs whose private network ranges are allowed with the rule, in addition to `addresses`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vpcs: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub droplets: Option<Vec<String>>,
+    /// Tags whose droplets are allowed with the rule, in addition to `droplets`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub droplet_tags: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub kubernetes_clusters: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub load_balancers: Option<Vec<String>>,
+    /// Only resolve the names of droplets, Kubernetes clusters, and load balancers to resources
+    /// in the project with this name
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub project: Option<String>,
+    #[serde(default)]
+    pub prune_dangling: bool,
+    #[serde(default)]
+    pub dual_stack: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ipv6_prefix: Option<u8>,
+    /// Previously published addresses to remove from the rule.  When given, the other addresses
+    /// already in the rule are kept rather than replaced by `addresses`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub old_ips: Option<Vec<IpAddr>>,
+    /// Keep the addresses previously published to the rule for this many days after they were
+    /// last detected, then remove them, rather than removing them as soon as the address changes.
+    /// Needs the state file to remember when each address was last detected.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retain_days: Option<u32>,
+    #[serde(default)]
+    pub no_verify: bool,
+    /// Replace the rule by updating the whole firewall in one request rather than deleting the old
+    /// rule and then adding the new one
+    #[serde(default)]
+    pub atomic: bool,
+    /// Which of several rules covering the port and protocol to update, counting from 1
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rule_index: Option<u32>,
+    /// Only update a rule whose sources (or destinations, for an outbound rule) carry this tag
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub match_source_tag: Option<String>,
+    /// Tags to allow with the rule, added to its current tags
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tags: Option<Vec<String>>,
+    /// Make `tags` the only tags the rule allows instead of adding them
+    #[serde(default)]
+    pub replace_tags: bool,
 }
 
-#[derive(Debug)]
+/// A reserved IP to assign to a droplet.  The reserved IP is identified either by its address or
+/// by the droplet it is currently assigned to.
+#[derive(Deserialize, Serialize, Debug, Clone, Eq, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ReservedIpArgs {
+    pub droplet: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub address: Option<IpAddr>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub from_droplet: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
 pub enum Direction {
     Inbound,
     Outbound,
 }
 
-impl Args {
-    pub fn parse_args() -> Args {
-        let matches = clap::Command::new(crate_name!())
-            .version(crate_version!())
-            .author("Chris Lieb")
-            .arg(
-                clap::Arg::new("token")
-                    .required(true)
-                    .short('t')
-                    .long("token")
-                    .num_args(1)
-                    .env("DIGITAL_OCEAN_TOKEN")
-                    .help("The API token to use to auth with DigitalOcean"),
-            )
-            .arg(
-                clap::Arg::new("local")
-                    .short('l')
-                    .long("local")
-                    .num_args(0)
-                    .conflicts_with("ip")
-                    .help("Use the local IP address connected to the internet"),
-            )
-            .arg(
-                clap::Arg::new("ip")
-                    .long("ip")
-                    .num_args(1)
-                    .conflicts_with("local")
-                    .value_parser(clap::value_parser!(IpAddr))
-                    .help("Use this IP address when updating the record"),
-            )
-            .arg(
-                clap::Arg::new("dry_run")
-                    .short('n')
-                    .long("dry-run")
-                    .num_args(0)
-                    .help("Do everything except actually set the record"),
-            )
-            .subcommand(
-                clap::Command::new("dns")
-                    .arg(
-                        clap::Arg::new("RECORD")
-                            .required(true)
-                            .num_args(1)
-                            .help("The DNS record within the domain to update"),
-                    )
-                    .arg(
-                        clap::Arg::new("DOMAIN")
-                            .required(true)
-                            .num_args(1)
-                            .help("The domain that has the record to update"),
-                    )
-                    .arg(
-                        clap::Arg::new("rtype")
-                            .long("rtype")
-                            .num_args(1)
-                            .value_parser(["A", "AAAA"])
-                            .default_value("A")
-                            .help("The type of DNS record to set"),
-                    )
-                    .arg(
-                        clap::Arg::new("ttl")
-                            .long("ttl")
-                            .num_args(1)
-                            .default_value("60")
-                            .value_parser(clap::value_parser!(u16))
-                            .help("The TTL for the new DNS record"),
-                    ),
-            )
-            .subcommand(
-                clap::Command::new("firewall")
-                    .arg(
-                        clap::Arg::new("NAME")
-                            .required(true)
-                            .num_args(1)
-                            .help("The name of the firewall to update"),
-                    )
-                    .arg(
-                        clap::Arg::new("PORT")
-                            .required(true)
-                            .num_args(1)
-                            .help("The port or port range of the firewall rule to update"),
-                    )
-                    .arg(
-                        clap::Arg::new("PROTOCOL")
-                            .required(true)
-                            .num_args(1)
-                            .value_parser(["tcp", "udp", "icmp"])
-                            .help("The protocol of the firewall rule to update"),
-                    )
-                    .arg(
-                        clap::Arg::new("inbound")
-                            .long("inbound")
-                            .num_args(0)
-                            .help("Update the inbound rule for the specified port"),
-                    )
-                    .arg(
-                        clap::Arg::new("outbound")
-                            .long("outbound")
-                            .num_args(0)
-                            .help("Update the outbound rule for the specified port"),
-                    )
-                    .group(
-                        clap::ArgGroup::new("direction")
-                            .args(["inbound", "outbound"])
-                            .required(true),
-                    )
-                    .arg(
-                        clap::Arg::new("addresses")
-                            .long("addresses")
-                            .num_args(1)
-                            .help(
-                                "List of IPv4 addresses, IPv6 addresses, IPv4 CIDRs, and/or \
-                                IPv6 CIDRs to allow with the rule, separated by commas",
-                            ),
-                    )
-                    .arg(
-                        clap::Arg::new("droplets")
-                            .long("droplets")
-                            .num_args(1)
-                            .help(
-                                "List of droplet names to allow with the rule, separated by commas",
-                            ),
-                    )
-                    .arg(
-                        clap::Arg::new("kubernetes-clusters")
-                            .long("kubernetes-clusters")
-                            .num_args(1)
-                            .help("List of Kubernetes cluster names to allow with the rule, separated by commas")
+/// The command line interface of the program
+pub fn command() -> clap::Command {
+    clap::Command::new(crate_name!())
+        .version(crate_version!())
+        .author("Chris Lieb")
+        .arg(
+            clap::Arg::new("config")
+                .short('c')
+                .long("config")
+                .num_args(1)
+                .value_parser(clap::value_parser!(PathBuf))
+                .help(
+                    "Read settings from this YAML file.  Keys are the long names of the \
+                        global flags with underscores (e.g. token, max_pages, ip_out).  Flags on \
+                        the command line override the file, which overrides environment \
+                        variables.  A `targets` list of DNS records, firewall rules, and \
+                        reserved IPs (each with a `type` of dns, firewall, or reserved-ip and the \
+                        settings of that subcommand) is updated when no subcommand is given.  \
+                        With --daemon, each entry of a `jobs` list (with a name, its own \
+                        targets, and optionally an interval or a cron expression and a jitter in \
+                        seconds) is checked on its own schedule",
+                ),
+        )
+        .arg(
+            clap::Arg::new("print_config")
+                .long("print-config")
+                .num_args(0)
+                .help("Print the effective settings after merging all sources and exit"),
+        )
+        .arg(
+            clap::Arg::new("token")
+                .short('t')
+                .long("token")
+                .num_args(1)
+                .env("DIGITAL_OCEAN_TOKEN")
+                .value_parser(Secret::parse)
+                .help(
+                    "The API token to use to auth with DigitalOcean, not needed if every job of \
+                        the config file has its own and there are no other targets",
+                ),
+        )
+        .arg(
+            clap::Arg::new("token_file")
+                .long("token-file")
+                .value_name("PATH")
+                .num_args(1)
+                .env("DIGITAL_OCEAN_TOKEN_FILE")
+                .value_parser(clap::value_parser!(PathBuf))
+                .help(
+                    "Read the API token from this file instead of --token, so that it does not \
+                        appear in the environment or the process arguments.  Works with systemd's \
+                        LoadCredential as --token-file ${CREDENTIALS_DIRECTORY}/<name>",
+                ),
+        )
+        .arg(
+            clap::Arg::new("cloudflare_token")
+                .long("cloudflare-token")
+                .num_args(1)
+                .env("CLOUDFLARE_API_TOKEN")
+                .value_parser(Secret::parse)
+                .help(
+                    "The API token to use to auth with Cloudflare, for DNS records updated with \
+                        --provider cloudflare.  Needs the Zone:Read and DNS:Edit permissions",
+                ),
+        )
+        .arg(
+            clap::Arg::new("local")
+                .short('l')
+                .long("local")
+                .num_args(0)
+                .conflicts_with_all(["ip", "interface", "router"])
+                .help("Use the local IP address connected to the internet"),
+        )
+        .arg(
+            clap::Arg::new("router")
+                .long("router")
+                .num_args(0)
+                .conflicts_with_all(["ip", "interface"])
+                .help(
+                    "Ask the local router for its WAN IP address over UPnP/IGD instead of an \
+                        external service",
+                ),
+        )
+        .arg(
+            clap::Arg::new("interface")
+                .long("interface")
+                .value_name("NAME")
+                .num_args(1)
+                .conflicts_with_all(["ip", "router"])
+                .help(
+                    "Use the global-scope IP address of this local network interface, for hosts \
+                        where the default route is not the one to publish",
+                ),
+        )
+        .arg(
+            clap::Arg::new("ip")
+                .long("ip")
+                .num_args(1)
+                .conflicts_with_all(["local", "interface", "router"])
+                .value_parser(clap::value_parser!(IpAddr))
+                .help("Use this IP address when updating the record"),
+        )
+        .arg(
+            clap::Arg::new("ip_provider")
+                .long("ip-provider")
+                .value_name("PROVIDER")
+                .num_args(1)
+                .value_delimiter(',')
+                .action(ArgAction::Append)
+                .value_parser(IpProvider::parse)
+                .default_values(["ipify", "icanhazip", "cloudflare"])
+                .help(
+                    "Services to ask for the external IP address, in order until one of them \
+                        answers: ipify, icanhazip, cloudflare, opendns, or the URL of a service \
+                        that responds with just the address.  Separated by commas or given \
+                        multiple times",
+                ),
+        )
+        .arg(
+            clap::Arg::new("verbose")
+                .short('v')
+                .long("verbose")
+                .num_args(0)
+                .help("Log debug output, including progress through long listings"),
+        )
+        .arg(
+            clap::Arg::new("quiet")
+                .short('q')
+                .long("quiet")
+                .num_args(0)
+                .conflicts_with("verbose")
+                .help("Only log warnings and errors, for running from cron"),
+        )
+        .arg(
+            clap::Arg::new("log_file")
+                .long("log-file")
+                .value_name("PATH")
+                .num_args(1)
+                .value_parser(clap::value_parser!(PathBuf))
+                .help("Append the log to this file instead of writing it to stderr"),
+        )
+        .arg(
+            clap::Arg::new("log_format")
+                .long("log-format")
+                .value_name("FORMAT")
+                .num_args(1)
+                .value_parser(LogFormat::parse)
+                .default_value("text")
+                .help(
+                    "Write the log as human readable text, or as json with one object per \
+                        line for log aggregators such as Loki or Elasticsearch",
+                ),
+        )
+        .arg(
+            clap::Arg::new("dry_run")
+                .short('n')
+                .long("dry-run")
+                .num_args(0)
+                .help("Do everything except actually set the record"),
+        )
+        .arg(clap::Arg::new("check").long("check").num_args(0).help(
+            "Only verify that the DNS record or firewall rule matches the IP address, \
+                        exiting with status 2 if it does not.  With --daemon, checks at every \
+                        --interval and emails the targets that no longer match",
+        ))
+        .arg(
+            clap::Arg::new("detailed_exitcode")
+                .long("detailed-exitcode")
+                .num_args(0)
+                .help(
+                    "Exit with status 2 instead of 0 when a DNS record, firewall rule, or \
+                        reserved IP was changed, so that scripts can act only on changes.  A run \
+                        that changed nothing exits with 0, and errors exit with 1, or 75 if they \
+                        are expected to resolve themselves",
+                ),
+        )
+        .arg(clap::Arg::new("verify").long("verify").num_args(0).help(
+            "After changing the DNS record or firewall rule, fetch it again and fail \
+                        if it does not match what was requested",
+        ))
+        .arg(
+            clap::Arg::new("pre_hook")
+                .long("pre-hook")
+                .value_name("COMMAND")
+                .value_parser(clap::builder::NonEmptyStringValueParser::new())
+                .help(
+                    "Shell command to run before changing a DNS record or firewall rule; the \
+                        change is aborted if it fails.  The old and new addresses and the \
+                        resource are passed in the DO_DYN_DNS_OLD_IP, DO_DYN_DNS_NEW_IP, \
+                        DO_DYN_DNS_RESOURCE, and DO_DYN_DNS_KIND environment variables",
+                ),
+        )
+        .arg(
+            clap::Arg::new("post_hook")
+                .long("post-hook")
+                .value_name("COMMAND")
+                .value_parser(clap::builder::NonEmptyStringValueParser::new())
+                .help(
+                    "Shell command to run after changing a DNS record or firewall rule, with \
+                        the same environment variables as --pre-hook",
+                ),
+        )
+        .arg(
+            clap::Arg::new("stats_json")
+                .long("stats-json")
+                .num_args(0)
+                .help(
+                    "Print statistics about the run (API calls, pages fetched, bytes \
+                        received, and time spent in each phase) to stdout as JSON",
+                ),
+        )
+        .arg(clap::Arg::new("profile").long("profile").num_args(0).help(
+            "Log how long each phase of the run took (IP detection, domain and \
+                        record lookups, changes, verification) once it finishes",
+        ))
+        .arg(clap::Arg::new("daemon").long("daemon").num_args(0).help(
+            "Keep running, re-detecting the IP address every --interval seconds and \
+                        updating the DNS record or firewall rule only when it changes.  Exits \
+                        cleanly on SIGTERM or SIGINT",
+        ))
+        .arg(clap::Arg::new("service").long("service").num_args(0).help(
+            "Run as a service of the host's service manager, which implies --daemon.  \
+                        On Linux, readiness, status and watchdog pings are reported to systemd \
+                        for units with Type=notify and WatchdogSec; on Windows the program runs \
+                        under the Service Control Manager.  See the service install subcommand",
+        ))
+        .arg(
+            clap::Arg::new("interval")
+                .long("interval")
+                .value_name("SECS")
+                .num_args(1)
+                .value_parser(clap::value_parser!(u64).range(1..))
+                .default_value("300")
+                .help("How often to re-detect the IP address in --daemon mode"),
+        )
+        .arg(
+            clap::Arg::new("stabilize_checks")
+                .long("stabilize-checks")
+                .value_name("N")
+                .num_args(1)
+                .value_parser(clap::value_parser!(u32).range(1..))
+                .default_value("1")
+                .help(
+                    "In --daemon mode, only publish a new IP address once this many checks in a \
+                        row have detected it, so that a flapping connection does not cause a \
+                        change at every check",
+                ),
+        )
+        .arg(
+            clap::Arg::new("max_pages")
+                .long("max-pages")
+                .num_args(1)
+                .value_parser(clap::value_parser!(u32).range(1..))
+                .help(
+                    "Fail instead of fetching more than this many pages of any listing from \
+                        the DigitalOcean API",
+                ),
+        )
+        .arg(
+            clap::Arg::new("per_page")
+                .long("per-page")
+                .value_name("N")
+                .num_args(1)
+                .value_parser(clap::value_parser!(u32).range(1..=200))
+                .default_value("200")
+                .help(
+                    "Request this many objects in each page of a DigitalOcean API listing; \
+                        larger pages take fewer requests to list a large account or zone",
+                ),
+        )
+        .arg(
+            clap::Arg::new("page_concurrency")
+                .long("page-concurrency")
+                .value_name("N")
+                .num_args(1)
+                .value_parser(clap::value_parser!(u32).range(1..=16))
+                .default_value("1")
+                .help(
+                    "Fetch up to this many pages of a DigitalOcean API listing at once, which \
+                        speeds up accounts with hundreds of droplets or DNS records",
+                ),
+        )
+        .arg(
+            clap::Arg::new("max_attempts")
+                .long("max-attempts")
+                .value_name("N")
+                .num_args(1)
+                .value_parser(clap::value_parser!(u32).range(1..))
+                .default_value("3")
+                .help(
+                    "How many times to send a DigitalOcean API request that fails with a \
+                        connection error, rate limiting, or a server error before giving up",
+                ),
+        )
+        .arg(
+            clap::Arg::new("retry_backoff")
+                .long("retry-backoff")
+                .value_name("MS")
+                .num_args(1)
+                .value_parser(clap::value_parser!(u64))
+                .default_value("500")
+                .help(
+                    "Milliseconds to wait before the first retry of a failed API request; the \
+                        wait doubles with each further retry",
+                ),
+        )
+        .arg(
+            clap::Arg::new("timeout")
+                .long("timeout")
+                .value_name("SECS")
+                .num_args(1)
+                .value_parser(clap::value_parser!(u64).range(1..))
+                .help(
+                    "Seconds to wait for the response to each request to the DigitalOcean API \
+                        or an IP provider before giving up on it (default: 30 for the API, 10 \
+                        for IP providers)",
+                ),
+        )
+        .arg(
+            clap::Arg::new("proxy")
+                .long("proxy")
+                .value_name("URL")
+                .num_args(1)
+                .value_parser(http::validate_proxy)
+                .help(
+                    "Send every request through this http:// or https:// proxy.  Without it, \
+                        the HTTPS_PROXY, HTTP_PROXY, and NO_PROXY environment variables are used",
+                ),
+        )
+        .arg(
+            clap::Arg::new("trace_http")
+                .long("trace-http")
+                .num_args(0)
+                .help(
+                    "Log the method, path, status, and latency of each request to the \
+                        DigitalOcean API at debug level (shown with --verbose)",
+                ),
+        )
+        .arg(
+            clap::Arg::new("deadline")
+                .long("deadline")
+                .value_name("SECS")
+                .num_args(1)
+                .value_parser(clap::value_parser!(u64).range(1..))
+                .help(
+                    "Abandon the run if it has not finished after this many seconds, exiting \
+                        with status 75; in --daemon mode this limits each update",
+                ),
+        )
+        .arg(
+            clap::Arg::new("ip_out")
+                .long("ip-out")
+                .num_args(1)
+                .value_parser(clap::value_parser!(PathBuf))
+                .help("Write the detected IP address to this file after each run"),
+        )
+        .arg(
+            clap::Arg::new("state_file")
+                .long("state-file")
+                .value_name("PATH")
+                .num_args(1)
+                .value_parser(clap::value_parser!(PathBuf))
+                .help(
+                    "Remember the IP address published to each target in this file, skipping \
+                        targets whose address has not changed since the last run, removing \
+                        the previous address from firewall rules, and looking firewalls up by \
+                        the ID they had when first matched by name",
+                ),
+        )
+        .arg(
+            clap::Arg::new("history_file")
+                .long("history-file")
+                .value_name("PATH")
+                .num_args(1)
+                .value_parser(clap::value_parser!(PathBuf))
+                .help(
+                    "Append every change of the detected IP address and every update applied to \
+                        this file as JSON lines, moving it aside to PATH.1 once it reaches 1 MiB; \
+                        print it with the history subcommand",
+                ),
+        )
+        .arg(
+            clap::Arg::new("lock_file")
+                .long("lock-file")
+                .value_name("PATH")
+                .num_args(1)
+                .value_parser(clap::value_parser!(PathBuf))
+                .help(
+                    "Lock this file while updating, so that runs started while another is still \
+                        updating (such as overlapping cron jobs) do not interleave their changes",
+                ),
+        )
+        .arg(
+            clap::Arg::new("lock_wait")
+                .long("lock-wait")
+                .value_name("SECS")
+                .num_args(1)
+                .value_parser(clap::value_parser!(u64))
+                .default_value("0")
+                .help(
+                    "How long to wait for another run to release the --lock-file before giving \
+                        up with exit status 3",
+                ),
+        )
+        .arg(
+            clap::Arg::new("cache_file")
+                .long("cache-file")
+                .value_name("PATH")
+                .num_args(1)
+                .value_parser(clap::value_parser!(PathBuf))
+                .help(
+                    "Cache the droplet, Kubernetes cluster, and load balancer names looked up \
+                        for firewall rules in this file, so that they are not fetched on every \
+                        run",
+                ),
+        )
+        .arg(
+            clap::Arg::new("cache_ttl")
+                .long("cache-ttl")
+                .value_name("SECS")
+                .num_args(1)
+                .value_parser(clap::value_parser!(u64).range(1..))
+                .default_value("600")
+                .help("How long names in the --cache-file are used before they are fetched again"),
+        )
+        .arg(
+            clap::Arg::new("no_cache")
+                .long("no-cache")
+                .num_args(0)
+                .help("Ignore the --cache-file and fetch every name from the API"),
+        )
+        .arg(
+            clap::Arg::new("heartbeat_url")
+                .long("heartbeat-url")
+                .value_name("URL")
+                .num_args(1)
+                .value_parser(clap::builder::NonEmptyStringValueParser::new())
+                .help(
+                    "Ping this URL after each successful run, or its /fail endpoint after a \
+                        failed one, for dead man's switch monitoring such as healthchecks.io",
+                ),
+        )
+        .subcommand(
+            clap::Command::new("dns")
+                .args_conflicts_with_subcommands(true)
+                .subcommand_negates_reqs(true)
+                .subcommand(
+                    clap::Command::new("list")
+                        .about("List the DNS records of a domain")
+                        .arg(
+                            clap::Arg::new("DOMAIN")
+                                .required(true)
+                                .num_args(1)
+                                .help("The domain whose records to list"),
+                        )
+                        .arg(
+                            clap::Arg::new("rtype")
+                                .long("rtype")
+                                .visible_alias("type")
+                                .num_args(1)
+                                .help("Only list records of this type"),
+                        )
+                        .arg(clap::Arg::new("name").long("name").num_args(1).help(
+                            "Only list records of this name within the domain, such as \
+                                        www or @ for the apex",
+                        ))
+                        .arg(
+                            clap::Arg::new("json")
+                                .long("json")
+                                .num_args(0)
+                                .help("Print the records as JSON"),
+                        ),
+                )
+                .subcommand(
+                    clap::Command::new("delete")
+                        .about("Delete DNS records, such as those of a decommissioned host")
+                        .arg(clap::Arg::new("RECORD").required(true).num_args(1).help(
+                            "The DNS record within the domain to delete, or the fully qualified \
+                                name of the record if DOMAIN is omitted",
+                        ))
+                        .arg(
+                            clap::Arg::new("DOMAIN")
+                                .num_args(1)
+                                .help("The domain that has the record to delete"),
+                        )
+                        .arg(clap::Arg::new("fqdn").long("fqdn").num_args(0).help(
+                            "Treat RECORD as a fully qualified name even without a \
+                                        trailing dot",
+                        ))
+                        .arg(
+                            clap::Arg::new("rtype")
+                                .long("rtype")
+                                .num_args(1)
+                                .value_parser(RECORD_TYPES)
+                                .default_value("A")
+                                .help("The type of DNS record to delete"),
+                        )
+                        .arg(
+                            clap::Arg::new("data")
+                                .long("data")
+                                .value_name("VALUE")
+                                .num_args(1)
+                                .help(
+                                    "Only delete the record holding this value, rather than \
+                                        every record of the name and type",
+                                ),
+                        ),
+                )
+                .arg(clap::Arg::new("RECORD").required(true).num_args(1).help(
+                    "The DNS record within the domain to update, or the fully \
+                                qualified name of the record if DOMAIN is omitted or the name \
+                                ends in a dot.  Use @ for \
+                                the apex of the domain, and * or *.<name> for a wildcard \
+                                record, which answers for every name below it that has no \
+                                record of its own",
+                ))
+                .arg(
+                    clap::Arg::new("DOMAIN")
+                        .num_args(1)
+                        .help("The domain that has the record to update"),
+                )
+                .arg(
+                    clap::Arg::new("fqdn")
+                        .long("fqdn")
+                        .num_args(0)
+                        .conflicts_with("all-domains")
+                        .help(
+                            "Treat RECORD as a fully qualified name even without a trailing \
+                                dot, so that host.sub.example.com names the record host.sub of \
+                                the domain example.com",
+                        ),
+                )
+                .arg(
+                    clap::Arg::new("audit-txt")
+                        .long("audit-txt")
+                        .num_args(0)
+                        .help(
+                            "After updating each record, write the time of the update and the \
+                                name of this host to a TXT record named _dyn.<record>, for an \
+                                audit trail visible in DNS",
+                        ),
+                )
+                .arg(
+                    clap::Arg::new("all-domains")
+                        .long("all-domains")
+                        .num_args(0)
+                        .conflicts_with("DOMAIN")
+                        .help(
+                            "Update the record named RECORD in every domain of the account \
+                                that has one, for hosts that appear under several domains",
+                        ),
+                )
+                .arg(
+                    clap::Arg::new("rtype")
+                        .long("rtype")
+                        .num_args(1)
+                        .value_parser(RECORD_TYPES)
+                        .default_value("A")
+                        .help("The type of DNS record to set"),
+                )
+                .arg(
+                    clap::Arg::new("data")
+                        .long("data")
+                        .value_name("VALUE")
+                        .num_args(1)
+                        .value_parser(clap::builder::NonEmptyStringValueParser::new())
+                        .help(
+                            "The value to set the record to instead of the detected IP \
+                                address, which is required for CNAME, MX, SRV, and TXT records",
+                        ),
+                )
+                .arg(
+                    clap::Arg::new("from-droplet")
+                        .long("from-droplet")
+                        .value_name("NAME")
+                        .num_args(1)
+                        .value_parser(clap::builder::NonEmptyStringValueParser::new())
+                        .conflicts_with("data")
+                        .help(
+                            "Publish the public address of this droplet instead of the detected \
+                                IP address of this machine",
+                        ),
+                )
+                .arg(
+                    clap::Arg::new("from-load-balancer")
+                        .long("from-load-balancer")
+                        .value_name("NAME")
+                        .num_args(1)
+                        .value_parser(clap::builder::NonEmptyStringValueParser::new())
+                        .conflicts_with_all(["data", "from-droplet"])
+                        .help(
+                            "Publish the address of this load balancer instead of the detected \
+                                IP address of this machine, which points an apex record at a \
+                                load balancer where a CNAME cannot be used",
+                        ),
+                )
+                .arg(
+                    clap::Arg::new("priority")
+                        .long("priority")
+                        .num_args(1)
+                        .value_parser(clap::value_parser!(u16))
+                        .help("The priority of an MX or SRV record"),
+                )
+                .arg(
+                    clap::Arg::new("port")
+                        .long("port")
+                        .num_args(1)
+                        .value_parser(clap::value_parser!(u16))
+                        .help("The port of an SRV record"),
+                )
+                .arg(
+                    clap::Arg::new("weight")
+                        .long("weight")
+                        .num_args(1)
+                        .value_parser(clap::value_parser!(u16))
+                        .help("The weight of an SRV record"),
+                )
+                .arg(
+                    clap::Arg::new("ttl")
+                        .long("ttl")
+                        .num_args(1)
+                        .default_value("60")
+                        .value_parser(clap::value_parser!(u16))
+                        .help(
+                            "The TTL of the DNS record in seconds, which DigitalOcean requires to \
+                                be at least 30",
+                        ),
+                )
+                .arg(
+                    clap::Arg::new("lower-ttl")
+                        .long("lower-ttl")
+                        .num_args(1)
+                        .value_parser(clap::value_parser!(u16))
+                        .help(
+                            "When the IP address changes, first lower the record's TTL to this \
+                                value and wait for the old TTL to expire before updating it, then \
+                                restore the normal TTL",
+                        ),
+                )
+                .arg(
+                    clap::Arg::new("alias")
+                        .long("alias")
+                        .num_args(1)
+                        .value_delimiter(',')
+                        .action(ArgAction::Append)
+                        .value_parser(clap::builder::NonEmptyStringValueParser::new())
+                        .help(
+                            "List of companion records within the domain (e.g. www or *) to \
+                                keep pointing at the same address, separated by commas or given \
+                                multiple times",
+                        ),
+                )
+                .arg(
+                    clap::Arg::new("with-www")
+                        .long("with-www")
+                        .num_args(0)
+                        .help("Also keep the www companion of the record up to date"),
+                )
+                .arg(
+                    clap::Arg::new("check-delegation")
+                        .long("check-delegation")
+                        .num_args(0)
+                        .help(
+                            "Look up the public nameservers of the domain and warn if they \
+                                are not DigitalOcean's, since updates would not take effect",
+                        ),
+                )
+                .arg(clap::Arg::new("force").long("force").num_args(0).help(
+                    "Modify the record even if it appears to be managed by other \
+                                automation such as external-dns or Terraform",
+                ))
+                .arg(
+                    clap::Arg::new("create-if-missing")
+                        .long("create-if-missing")
+                        .value_name("BOOL")
+                        .num_args(1)
+                        .value_parser(clap::value_parser!(bool))
+                        .default_value("true")
+                        .help(
+                            "Create the record if it does not exist yet.  With \
+                                --create-if-missing=false a missing record is an error, which \
+                                guards against typos in the record name",
+                        ),
+                )
+                .arg(
+                    clap::Arg::new("create-domain")
+                        .long("create-domain")
+                        .num_args(0)
+                        .requires("DOMAIN")
+                        .help(
+                            "Add the domain to the DigitalOcean account if it is not there yet, \
+                                for first-time setup of a new zone.  The domain only resolves once \
+                                its registrar delegates it to DigitalOcean's name servers",
+                        ),
+                )
+                .arg(
+                    clap::Arg::new("dual-stack")
+                        .long("dual-stack")
+                        .num_args(0)
+                        .conflicts_with_all(["rtype", "data"])
+                        .help(
+                            "Detect both the IPv4 and IPv6 address of this machine and update \
+                                the A and AAAA records of the name",
+                        ),
+                )
+                .arg(
+                    clap::Arg::new("provider")
+                        .long("provider")
+                        .num_args(1)
+                        .value_parser(Provider::parse)
+                        .default_value(Provider::DigitalOcean.name())
+                        .help(
+                            "The service that hosts the record: digitalocean, or cloudflare with \
+                                the zone the record is in as DOMAIN",
+                        ),
+                ),
+        )
+        .subcommand(
+            clap::Command::new("firewall")
+                .args_conflicts_with_subcommands(true)
+                .subcommand_negates_reqs(true)
+                .subcommand(
+                    clap::Command::new("diff")
+                        .about(
+                            "Compare the rules of a firewall with a rules file and report \
+                                the differences without changing anything",
+                        )
+                        .arg(
+                            clap::Arg::new("NAME")
+                                .required(true)
+                                .num_args(1)
+                                .help("The name of the firewall to compare"),
+                        )
+                        .arg(
+                            clap::Arg::new("rules-file")
+                                .long("rules-file")
+                                .required(true)
+                                .value_name("PATH")
+                                .value_parser(clap::value_parser!(PathBuf))
+                                .help(
+                                    "YAML file declaring the inbound_rules and \
+                                        outbound_rules the firewall should have",
+                                ),
+                        )
+                        .arg(
+                            clap::Arg::new("json")
+                                .long("json")
+                                .num_args(0)
+                                .help("Print the differences as JSON"),
+                        ),
+                )
+                .subcommand(
+                    clap::Command::new("assign")
+                        .about("Attach a firewall to droplets by name")
+                        .arg(
+                            clap::Arg::new("NAME")
+                                .required(true)
+                                .num_args(1)
+                                .help("The name of the firewall to attach"),
+                        )
+                        .arg(
+                            clap::Arg::new("DROPLETS")
+                                .required(true)
+                                .num_args(1)
+                                .value_delimiter(',')
+                                .action(clap::ArgAction::Append)
+                                .help("Comma-delimited names of the droplets to attach it to"),
+                        )
+                        .arg(
+                            clap::Arg::new("exclusive")
+                                .long("exclusive")
+                                .num_args(0)
+                                .help(
+                                    "Also detach the firewall from any droplet that is not \
+                                        listed",
+                                ),
+                        ),
+                )
+                .arg(
+                    clap::Arg::new("NAME")
+                        .required(true)
+                        .num_args(1)
+                        .value_delimiter(',')
+                        .value_parser(clap::builder::NonEmptyStringValueParser::new())
+                        .help(
+                            "The name of the firewall to update, or a comma-separated list of \
+                                names to update the rule of each firewall",
+                        ),
+                )
+                .arg(
+                    clap::Arg::new("all-matching")
+                        .long("all-matching")
+                        .num_args(0)
+                        .help(
+                            "Treat NAME as a glob pattern, where * matches any characters and ? \
+                                matches one, and update every firewall whose name matches it",
+                        ),
+                )
+                .arg(
+                    clap::Arg::new("PORT")
+                        .required(true)
+                        .num_args(1)
+                        .value_parser(parse_ports)
+                        .requires_ifs([("all", "allow-all-ports"), (ALL_PORTS, "allow-all-ports")])
+                        .help(
+                            "The port or port range of the firewall rule to update, or a \
+                                comma-separated list of them (such as 22,80,8000-9000) to update \
+                                several rules at once, or \"all\" for a rule covering every port",
+                        ),
+                )
+                .arg(
+                    clap::Arg::new("allow-all-ports")
+                        .long("allow-all-ports")
+                        .num_args(0)
+                        .help(
+                            "Confirm that the rule being updated covers all ports, which is \
+                                required when PORT is \"all\"",
+                        ),
+                )
+                .arg(
+                    clap::Arg::new("PROTOCOL")
+                        .required(true)
+                        .num_args(1)
+                        .value_delimiter(',')
+                        .value_parser(["tcp", "udp", "icmp"])
+                        .help(
+                            "The protocol of the firewall rule to update, or a list of \
+                                protocols separated by commas to update the rule for each",
+                        ),
+                )
+                .arg(
+                    clap::Arg::new("inbound")
+                        .long("inbound")
+                        .num_args(0)
+                        .help("Update the inbound rule for the specified port"),
+                )
+                .arg(
+                    clap::Arg::new("outbound")
+                        .long("outbound")
+                        .num_args(0)
+                        .help("Update the outbound rule for the specified port"),
+                )
+                .group(
+                    clap::ArgGroup::new("direction")
+                        .args(["inbound", "outbound"])
+                        .required(true),
+                )
+                .arg(
+                    clap::Arg::new("addresses")
+                        .long("addresses")
+                        .num_args(1)
+                        .value_delimiter(',')
+                        .action(ArgAction::Append)
+                        .value_parser(parse_address)
+                        .help(
+                            "List of IPv4 addresses, IPv6 addresses, IPv4 CIDRs, and/or \
+                                IPv6 CIDRs to allow with the rule, separated by commas or given \
+                                multiple times",
+                        ),
+                )
+                .arg(
+                    clap::Arg::new("vpc")
+                        .long("vpc")
+                        .num_args(1)
+                        .value_delimiter(',')
+                        .action(ArgAction::Append)
+                        .value_parser(clap::builder::NonEmptyStringValueParser::new())
+                        .help(
+                            "Name of a VPC whose IP range is allowed with the rule, alongside \
+                                --addresses; may be given multiple times",
+                        ),
+                )
+                .arg(
+                    clap::Arg::new("droplets")
+                        .long("droplets")
+                        .num_args(1)
+                        .value_delimiter(',')
+                        .action(ArgAction::Append)
+                        .value_parser(clap::builder::NonEmptyStringValueParser::new())
+                        .help(
+                            "List of droplet names to allow with the rule, separated by commas \
+                                or given multiple times",
+                        ),
+                )
+                .arg(
+                    clap::Arg::new("droplet-tag")
+                        .long("droplet-tag")
+                        .num_args(1)
+                        .value_delimiter(',')
+                        .action(ArgAction::Append)
+                        .value_parser(clap::builder::NonEmptyStringValueParser::new())
+                        .help(
+                            "Allow every droplet carrying this tag with the rule, alongside any \
+                                from --droplets; may be given multiple times",
+                        ),
+                )
+                .arg(
+                    clap::Arg::new("kubernetes-clusters")
+                        .long("kubernetes-clusters")
+                        .num_args(1)
+                        .value_delimiter(',')
+                        .action(ArgAction::Append)
+                        .value_parser(clap::builder::NonEmptyStringValueParser::new())
+                        .help(
+                            "List of Kubernetes cluster names to allow with the rule, \
+                                separated by commas or given multiple times",
+                        ),
+                )
+                .arg(
+                    clap::Arg::new("tags")
+                        .long("tags")
+                        .num_args(1)
+                        .value_delimiter(',')
+                        .action(ArgAction::Append)
+                        .value_parser(clap::builder::NonEmptyStringValueParser::new())
+                        .help(
+                            "List of droplet tags to allow with the rule in addition to the \
+                                tags it already allows, separated by commas or given multiple \
+                                times",
+                        ),
+                )
+                .arg(
+                    clap::Arg::new("replace-tags")
+                        .long("replace-tags")
+                        .num_args(0)
+                        .requires("tags")
+                        .help(
+                            "Make the --tags the only tags the rule allows, removing any others \
+                                instead of keeping them",
+                        ),
+                )
+                .arg(
+                    clap::Arg::new("load-balancers")
+                        .long("load-balancers")
+                        .num_args(1)
+                        .value_delimiter(',')
+                        .action(ArgAction::Append)
+                        .value_parser(clap::builder::NonEmptyStringValueParser::new())
+                        .help(
+                            "List of load balancer names to allow with the rule, separated by \
+                                commas or given multiple times",
+                        ),
+                )
+                .arg(
+                    clap::Arg::new("project")
+                        .long("project")
+                        .num_args(1)
+                        .value_parser(clap::builder::NonEmptyStringValueParser::new())
+                        .help(
+                            "Only look up the names given to --droplets, --kubernetes-clusters, \
+                                and --load-balancers among the resources in this project",
+                        ),
+                )
+                .arg(
+                    clap::Arg::new("prune-dangling")
+                        .long("prune-dangling")
+                        .num_args(0)
+                        .help(
+                            "Remove references to deleted droplets, Kubernetes clusters, and \
+                                load balancers from all rules in the firewall",
+                        ),
+                )
+                .arg(
+                    clap::Arg::new("dual-stack")
+                        .long("dual-stack")
+                        .num_args(0)
+                        .help(
+                            "Detect both the IPv4 and IPv6 address of this machine and \
+                                publish both in the rule",
+                        ),
+                )
+                .arg(
+                    clap::Arg::new("ipv6-prefix")
+                        .long("ipv6-prefix")
+                        .value_name("LENGTH")
+                        .value_parser(["56", "64"])
+                        .help(
+                            "Publish the network prefix of this length for IPv6 addresses \
+                                instead of the single address, for hosts that rotate their \
+                                interface identifiers",
+                        ),
+                )
+                .arg(
+                    clap::Arg::new("old-ip")
+                        .long("old-ip")
+                        .value_name("IP")
+                        .num_args(1)
+                        .value_delimiter(',')
+                        .action(ArgAction::Append)
+                        .value_parser(clap::value_parser!(IpAddr))
+                        .help(
+                            "Previously published IP address to remove from the rule, \
+                                separated by commas or given multiple times.  The rule keeps its \
+                                other addresses instead of having them replaced by --addresses",
+                        ),
+                )
+                .arg(
+                    clap::Arg::new("retain-days")
+                        .long("retain-days")
+                        .value_name("DAYS")
+                        .num_args(1)
+                        .conflicts_with("old-ip")
+                        .value_parser(clap::value_parser!(u32))
+                        .help(
+                            "Keep the addresses previously published to the rule for this many \
+                                days after they were last detected, then remove them.  Needs \
+                                --state-file",
+                        ),
+                )
+                .arg(
+                    clap::Arg::new("no-verify")
+                        .long("no-verify")
+                        .num_args(0)
+                        .help("Do not re-fetch the firewall after modifying its rules"),
+                )
+                .arg(clap::Arg::new("atomic").long("atomic").num_args(0).help(
+                    "Replace the rule by updating the whole firewall in a single request, \
+                                so the firewall is never left without the rule if the update fails",
+                ))
+                .arg(
+                    clap::Arg::new("rule-index")
+                        .long("rule-index")
+                        .value_name("N")
+                        .num_args(1)
+                        .value_parser(clap::value_parser!(u32).range(1..))
+                        .help(
+                            "When several rules cover the port and protocol, update the Nth of \
+                                them in the order the firewall lists them, counting from 1",
+                        ),
+                )
+                .arg(
+                    clap::Arg::new("match-source-tag")
+                        .long("match-source-tag")
+                        .value_name("TAG")
+                        .num_args(1)
+                        .value_parser(clap::builder::NonEmptyStringValueParser::new())
+                        .help(
+                            "When several rules cover the port and protocol, update the one \
+                                whose sources (or destinations, for an outbound rule) include \
+                                this tag",
+                        ),
+                ),
+        )
+        .subcommand(
+            clap::Command::new("reserved-ip")
+                .about("Assign a reserved IP to a droplet")
+                .arg(
+                    clap::Arg::new("DROPLET")
+                        .required(true)
+                        .num_args(1)
+                        .help("The name of the droplet to assign the reserved IP to"),
+                )
+                .arg(
+                    clap::Arg::new("address")
+                        .long("address")
+                        .num_args(1)
+                        .value_parser(clap::value_parser!(IpAddr))
+                        .help("The address of the reserved IP to assign"),
+                )
+                .arg(
+                    clap::Arg::new("from-droplet")
+                        .long("from-droplet")
+                        .value_name("NAME")
+                        .num_args(1)
+                        .value_parser(clap::builder::NonEmptyStringValueParser::new())
+                        .help("Assign the reserved IP currently assigned to this droplet"),
+                )
+                .group(
+                    clap::ArgGroup::new("reserved-ip")
+                        .args(["address", "from-droplet"])
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            clap::Command::new("droplet")
+                .about("Inspect the droplets of the account")
+                .subcommand_required(true)
+                .subcommand(
+                    clap::Command::new("list")
+                        .about("List every droplet with its public addresses, region, and tags")
+                        .arg(
+                            clap::Arg::new("json")
+                                .long("json")
+                                .num_args(0)
+                                .help("Print the droplets as JSON"),
+                        ),
+                ),
+        )
+        .subcommand(
+            clap::Command::new("check")
+                .about("Check that the API token is accepted and print the account it belongs to")
+                .arg(
+                    clap::Arg::new("json")
+                        .long("json")
+                        .num_args(0)
+                        .help("Print the account as JSON"),
+                ),
+        )
+        .subcommand(
+            clap::Command::new("doctor")
+                .about(
+                    "Check IP detection, the API, the token, and every target of the config file \
+                        without changing anything",
+                )
+                .arg(
+                    clap::Arg::new("json")
+                        .long("json")
+                        .num_args(0)
+                        .help("Print the report as JSON"),
+                ),
+        )
+        .subcommand(
+            clap::Command::new("service")
+                .about("Manage running the program as a service")
+                .subcommand_required(true)
+                .subcommand(
+                    clap::Command::new("install")
+                        .about(
+                            "Install a service that keeps the targets of the --config file up to \
+                                date: print a systemd unit on Linux, or register the service \
+                                with the Service Control Manager on Windows",
+                        )
+                        .arg(
+                            clap::Arg::new("name")
+                                .long("name")
+                                .num_args(1)
+                                .value_parser(clap::builder::NonEmptyStringValueParser::new())
+                                .default_value(service::DEFAULT_SERVICE_NAME)
+                                .help("The name to install the service under"),
+                        ),
+                ),
+        )
+        .subcommand(
+            clap::Command::new("history")
+                .about(
+                    "Print the changes of the detected IP address and the updates applied, as \
+                        logged to the --history-file, oldest first",
+                )
+                .arg(
+                    clap::Arg::new("limit")
+                        .long("limit")
+                        .value_name("N")
+                        .num_args(1)
+                        .value_parser(clap::value_parser!(usize))
+                        .help("Only print the N most recent entries"),
+                )
+                .arg(
+                    clap::Arg::new("json")
+                        .long("json")
+                        .num_args(0)
+                        .help("Print the entries as JSON lines"),
+                ),
+        )
+        .subcommand(
+            clap::Command::new("config")
+                .about("Manage the config file")
+                .subcommand_required(true)
+                .subcommand(
+                    clap::Command::new("init")
+                        .about(
+                            "Print a starter config file holding the settings given with the \
+                                other flags and a target for each --record and --firewall.  The \
+                                API token is never written to it.",
+                        )
+                        .arg(
+                            clap::Arg::new("record")
+                                .long("record")
+                                .value_name("NAME")
+                                .num_args(1)
+                                .action(ArgAction::Append)
+                                .value_parser(clap::builder::NonEmptyStringValueParser::new())
+                                .help(
+                                    "Add a target updating the A record with this fully \
+                                        qualified name, such as home.example.com",
+                                ),
+                        )
+                        .arg(
+                            clap::Arg::new("firewall")
+                                .long("firewall")
+                                .value_name("NAME")
+                                .num_args(1)
+                                .action(ArgAction::Append)
+                                .value_parser(clap::builder::NonEmptyStringValueParser::new())
+                                .help(
+                                    "Add a target updating the inbound rule of the firewall with \
+                                        this name for --port and --protocol",
+                                ),
+                        )
+                        .arg(
+                            clap::Arg::new("port")
+                                .long("port")
+                                .num_args(1)
+                                .value_parser(parse_ports)
+                                .default_value("22")
+                                .help("The port or port range of the --firewall rules"),
+                        )
+                        .arg(
+                            clap::Arg::new("protocol")
+                                .long("protocol")
+                                .num_args(1)
+                                .value_delimiter(',')
+                                .value_parser(["tcp", "udp", "icmp"])
+                                .default_value("tcp")
+                                .help(
+                                    "The protocol of the --firewall rules, or a list of protocols \
+                                        separated by commas",
+                                ),
+                        ),
+                )
+                .subcommand(
+                    clap::Command::new("import")
+                        .about(
+                            "Print a config file with targets describing the current records of \
+                                each --domain and the rules of each --firewall for each --port, \
+                                along with the settings given with the other flags",
+                        )
+                        .arg(
+                            clap::Arg::new("domain")
+                                .long("domain")
+                                .num_args(1)
+                                .action(ArgAction::Append)
+                                .value_parser(clap::builder::NonEmptyStringValueParser::new())
+                                .help("Import the records of this domain"),
+                        )
+                        .arg(
+                            clap::Arg::new("firewall")
+                                .long("firewall")
+                                .value_name("NAME")
+                                .num_args(1)
+                                .action(ArgAction::Append)
+                                .value_parser(clap::builder::NonEmptyStringValueParser::new())
+                                .requires("import_port")
+                                .help(
+                                    "Import the rules of the firewall with this name for each \
+                                        --port",
+                                ),
+                        )
+                        .arg(
+                            clap::Arg::new("dynamic")
+                                .long("dynamic")
+                                .value_name("NAME")
+                                .num_args(1)
+                                .action(ArgAction::Append)
+                                .requires("domain")
+                                .value_parser(clap::builder::NonEmptyStringValueParser::new())
+                                .help(
+                                    "Publish the detected address to the A and AAAA records of \
+                                        this name, rather than keeping the address they hold",
+                                ),
+                        )
+                        .arg(
+                            clap::Arg::new("import_port")
+                                .long("port")
+                                .value_name("PORT")
+                                .num_args(1)
+                                .action(ArgAction::Append)
+                                .requires("firewall")
+                                .value_parser(parse_ports)
+                                .help(
+                                    "Import the --firewall rules for this port or port range, \
+                                        leaving out any address published by the targets of the \
+                                        --state-file",
+                                ),
+                        )
+                        .group(
+                            clap::ArgGroup::new("imported")
+                                .args(["domain", "firewall"])
+                                .multiple(true)
+                                .required(true),
+                        ),
+                ),
+        )
+        .subcommand(
+            clap::Command::new("ip")
+                .about("Detect the IP address of this machine and print it")
+                .arg(
+                    clap::Arg::new("source")
+                        .long("source")
+                        .num_args(1)
+                        .value_parser(IpSource::ALL.map(|s| s.name()))
+                        .default_value(IpSource::External.name())
+                        .help("How to detect the IP address"),
+                )
+                .arg(
+                    clap::Arg::new("json")
+                        .long("json")
+                        .num_args(0)
+                        .help("Print the result as JSON, including the result of every source"),
+                ),
+        )
+}
+
+impl Invocation {
+    pub fn parse_args() -> Invocation {
+        let mut cmd = command();
+        let matches = cmd.get_matches_mut();
+
+        let file_config = match matches.get_one::<PathBuf>("config") {
+            Some(path) => match Config::load(path) {
+                Ok(config) => config,
+                Err(e) => cmd.error(ErrorKind::InvalidValue, e).exit(),
+            },
+            None => Config::default(),
+        };
+        let settings = file_config.merge(&matches);
+
+        if matches.get_flag("print_config") {
+            return Invocation::PrintConfig(Box::new(settings));
+        }
+
+        if let Some(("config", config_match)) = matches.subcommand() {
+            if let Some(("init", init_match)) = config_match.subcommand() {
+                return Invocation::ConfigInit(Box::new(ConfigInitArgs {
+                    config: with_init_targets(settings, init_match),
+                    defaults: default_settings(),
+                }));
+            }
+        }
+
+        let log = LogArgs {
+            level: LogArgs::level(settings.verbose, settings.quiet),
+            file: settings.log_file.clone(),
+            format: settings
+                .log_format
+                .expect("--log-format has a default value"),
+        };
+
+        // the value parser only checks a proxy given on the command line
+        if let Some(Err(e)) = settings.proxy.as_deref().map(http::validate_proxy) {
+            cmd.error(ErrorKind::InvalidValue, e).exit();
+        }
+        if let Some(Err(e)) = settings.email.as_ref().map(EmailOptions::validate) {
+            cmd.error(ErrorKind::InvalidValue, e).exit();
+        }
+        let http = HttpOptions {
+            timeout: settings.timeout.map(Duration::from_secs),
+            proxy: settings.proxy.clone(),
+            trace: settings.trace_http,
+        };
+
+        if let Some(("ip", sub_match)) = matches.subcommand() {
+            return Invocation::Ip(IpArgs {
+                log,
+                source: IpSource::from_name(sub_match.get_one::<String>("source").unwrap())
+                    .unwrap(),
+                providers: settings
+                    .ip_provider
+                    .expect("--ip-provider has a default value"),
+                http,
+                json: sub_match.get_flag("json"),
+                ip_out: settings.ip_out,
+            });
+        }
+
+        if let Some(("service", sub_match)) = matches.subcommand() {
+            let Some(("install", install_match)) = sub_match.subcommand() else {
+                unreachable!("a service subcommand is required")
+            };
+            let config = match matches.get_one::<PathBuf>("config") {
+                Some(config) => config.clone(),
+                None => cmd
+                    .error(
+                        ErrorKind::MissingRequiredArgument,
+                        "the service keeps the targets of a config file up to date, so --config \
+                            is required",
                     )
-                    .arg(
-                        clap::Arg::new("load-balancers")
-                            .long("load-balancers")
-                            .num_args(1)
-                            .help("List of load balancer names to allow with the rule, separated by commas")
-                    ),
+                    .exit(),
+            };
+            return Invocation::ServiceInstall(ServiceInstallArgs {
+                log,
+                config,
+                name: install_match.get_one::<String>("name").unwrap().clone(),
+            });
+        }
+
+        if let Some(("history", history_match)) = matches.subcommand() {
+            let Some(history_file) = settings.history_file else {
+                cmd.error(
+                    ErrorKind::MissingRequiredArgument,
+                    "the history is read from the history file, so --history-file is required",
+                )
+                .exit()
+            };
+            return Invocation::History(HistoryArgs {
+                log,
+                history_file,
+                limit: history_match.get_one::<usize>("limit").copied(),
+                json: history_match.get_flag("json"),
+            });
+        }
+
+        if matches.subcommand().is_none() && settings.targets.is_empty() && settings.jobs.is_empty()
+        {
+            cmd.error(
+                ErrorKind::MissingSubcommand,
+                "a subcommand is required unless --print-config is given or the config file \
+                    defines targets or jobs",
             )
-            .subcommand_required(true)
-            .get_matches();
-
-        let literal_ip = matches.get_one::<IpAddr>("ip");
-        let local = matches.get_flag("local");
-
-        let ip = if let Some(lit) = literal_ip {
-            info!("Using user-provided IP address: {}", lit);
-            *lit
-        } else if local {
-            info!("Getting local IP address of machine...");
-            ip_retriever::get_local_ip().expect("Unable to retrieve local IP address")
-        } else {
-            info!("Getting public IP address of machine...");
-            ip_retriever::get_external_ip().expect("Unable to retrieve external IP address")
+            .exit();
+        }
+
+        let retry = RetryPolicy {
+            max_attempts: settings
+                .max_attempts
+                .expect("--max-attempts has a default value"),
+            initial_backoff: Duration::from_millis(
+                settings
+                    .retry_backoff
+                    .expect("--retry-backoff has a default value"),
+            ),
         };
-        info!("Will publish IP address: {:?}", ip);
 
-        let subcmd_args = match matches.subcommand() {
-            Some(("dns", sub_match)) => {
-                let rtype = sub_match.get_one::<String>("rtype").unwrap().clone();
-                if (ip.is_ipv4() && rtype != "A") || (ip.is_ipv6() && rtype != "AAAA") {
-                    panic!("Expected Rtype {rtype} but got {ip:?}")
-                }
+        let per_page = settings.per_page.expect("--per-page has a default value");
+        let page_concurrency = settings
+            .page_concurrency
+            .expect("--page-concurrency has a default value")
+            as usize;
 
-                SubcmdArgs::Dns(DnsArgs {
+        // without other targets, jobs that all have their own token don't need the global one
+        let jobs_own_tokens = matches!(matches.subcommand(), None | Some(("doctor", _)))
+            && settings.targets.is_empty()
+            && !settings.jobs.is_empty()
+            && (settings.jobs.iter()).all(|job| job.token.is_some() || job.token_file.is_some());
+        let token = match (settings.token, settings.token_file.clone()) {
+            (Some(_), Some(_)) => cmd
+                .error(
+                    ErrorKind::ArgumentConflict,
+                    "the API token cannot be given both directly and with a token file",
+                )
+                .exit(),
+            (Some(token), None) => Some(token),
+            (None, Some(path)) => match config::read_token_file(&path) {
+                Ok(token) => Some(token),
+                Err(e) => cmd.error(ErrorKind::InvalidValue, e).exit(),
+            },
+            (None, None) if jobs_own_tokens => None,
+            (None, None) => cmd
+                .error(
+                    ErrorKind::MissingRequiredArgument,
+                    "the API token must be provided with --token, --token-file, \
+                        DIGITAL_OCEAN_TOKEN or DIGITAL_OCEAN_TOKEN_FILE",
+                )
+                .exit(),
+        };
+        // every subcommand but the doctor calls the API with the global token
+        let api_token = || token.clone().expect("the API token is required");
+
+        if let Some(("firewall", sub_match)) = matches.subcommand() {
+            if let Some(("diff", diff_match)) = sub_match.subcommand() {
+                return Invocation::FirewallDiff(FirewallDiffArgs {
+                    token: api_token(),
+                    log,
+                    max_pages: settings.max_pages,
+                    per_page,
+                    page_concurrency,
+                    retry,
+                    http,
+                    name: diff_match.get_one::<String>("NAME").unwrap().clone(),
+                    rules_file: diff_match.get_one::<PathBuf>("rules-file").unwrap().clone(),
+                    json: diff_match.get_flag("json"),
+                });
+            }
+            if let Some(("assign", assign_match)) = sub_match.subcommand() {
+                return Invocation::FirewallAssign(FirewallAssignArgs {
+                    token: api_token(),
+                    log,
+                    max_pages: settings.max_pages,
+                    per_page,
+                    page_concurrency,
+                    retry,
+                    http,
+                    dry_run: settings.dry_run,
+                    name: assign_match.get_one::<String>("NAME").unwrap().clone(),
+                    droplets: get_values(assign_match, "DROPLETS").unwrap(),
+                    exclusive: assign_match.get_flag("exclusive"),
+                });
+            }
+        }
+
+        if let Some(("check", check_match)) = matches.subcommand() {
+            return Invocation::Check(CheckArgs {
+                token: api_token(),
+                log,
+                retry,
+                http,
+                json: check_match.get_flag("json"),
+            });
+        }
+
+        if let Some(("config", config_match)) = matches.subcommand() {
+            let Some(("import", import_match)) = config_match.subcommand() else {
+                unreachable!("config init is handled before the token is read")
+            };
+            return Invocation::ConfigImport(Box::new(ConfigImportArgs {
+                token: api_token(),
+                log,
+                max_pages: settings.max_pages,
+                per_page,
+                page_concurrency,
+                retry,
+                http,
+                domains: get_values(import_match, "domain").unwrap_or_default(),
+                firewalls: get_values(import_match, "firewall").unwrap_or_default(),
+                dynamic: get_values(import_match, "dynamic").unwrap_or_default(),
+                ports: get_values(import_match, "import_port").unwrap_or_default(),
+                config: Config {
+                    token: None,
+                    ..settings
+                },
+                defaults: default_settings(),
+            }));
+        }
+
+        if let Some(("dns", sub_match)) = matches.subcommand() {
+            if let Some(("list", list_match)) = sub_match.subcommand() {
+                return Invocation::DnsList(DnsListArgs {
+                    token: api_token(),
+                    log,
+                    max_pages: settings.max_pages,
+                    per_page,
+                    page_concurrency,
+                    retry,
+                    http,
+                    domain: list_match.get_one::<String>("DOMAIN").unwrap().clone(),
+                    rtype: list_match.get_one::<String>("rtype").cloned(),
+                    name: list_match.get_one::<String>("name").cloned(),
+                    json: list_match.get_flag("json"),
+                });
+            }
+            if let Some(("delete", delete_match)) = sub_match.subcommand() {
+                return Invocation::DnsDelete(DnsDeleteArgs {
+                    token: api_token(),
+                    log,
+                    max_pages: settings.max_pages,
+                    per_page,
+                    page_concurrency,
+                    retry,
+                    http,
+                    dry_run: settings.dry_run,
+                    record: delete_match.get_one::<String>("RECORD").unwrap().clone(),
+                    domain: delete_match.get_one::<String>("DOMAIN").cloned(),
+                    fqdn: delete_match.get_flag("fqdn"),
+                    rtype: delete_match.get_one::<String>("rtype").unwrap().clone(),
+                    data: delete_match.get_one::<String>("data").cloned(),
+                });
+            }
+        }
+
+        if let Some(("droplet", sub_match)) = matches.subcommand() {
+            if let Some(("list", list_match)) = sub_match.subcommand() {
+                return Invocation::DropletList(DropletListArgs {
+                    token: api_token(),
+                    log,
+                    max_pages: settings.max_pages,
+                    per_page,
+                    page_concurrency,
+                    retry,
+                    http,
+                    json: list_match.get_flag("json"),
+                });
+            }
+        }
+
+        let providers = settings
+            .ip_provider
+            .expect("--ip-provider has a default value");
+        let ip = match (settings.ip, settings.interface) {
+            (Some(lit), _) => IpSpec::Literal(lit),
+            (None, Some(interface)) => IpSpec::Interface(interface),
+            (None, None) if settings.local => IpSpec::Detect(IpSource::Local, providers),
+            (None, None) if settings.router => IpSpec::Detect(IpSource::Router, providers),
+            (None, None) => IpSpec::Detect(IpSource::External, providers),
+        };
+
+        let targets = match matches.subcommand() {
+            Some(("dns", sub_match)) => {
+                let dns_args = DnsArgs {
                     record: sub_match.get_one::<String>("RECORD").unwrap().clone(),
-                    domain: sub_match.get_one::<String>("DOMAIN").unwrap().clone(),
-                    rtype,
+                    domain: sub_match.get_one::<String>("DOMAIN").cloned(),
+                    rtype: sub_match.get_one::<String>("rtype").unwrap().clone(),
+                    data: sub_match.get_one::<String>("data").cloned(),
+                    from_droplet: sub_match.get_one::<String>("from-droplet").cloned(),
+                    from_load_balancer: sub_match.get_one::<String>("from-load-balancer").cloned(),
+                    priority: sub_match.get_one::<u16>("priority").copied(),
+                    port: sub_match.get_one::<u16>("port").copied(),
+                    weight: sub_match.get_one::<u16>("weight").copied(),
                     ttl: *sub_match
                         .get_one::<u16>("ttl")
                         .expect("Must provide integer for ttl"),
-                })
+                    lower_ttl: sub_match.get_one::<u16>("lower-ttl").copied(),
+                    aliases: get_values(sub_match, "alias").unwrap_or_default(),
+                    with_www: sub_match.get_flag("with-www"),
+                    check_delegation: sub_match.get_flag("check-delegation"),
+                    force: sub_match.get_flag("force"),
+                    create_if_missing: *sub_match
+                        .get_one::<bool>("create-if-missing")
+                        .expect("--create-if-missing has a default value"),
+                    create_domain: sub_match.get_flag("create-domain"),
+                    all_domains: sub_match.get_flag("all-domains"),
+                    dual_stack: sub_match.get_flag("dual-stack"),
+                    fqdn: sub_match.get_flag("fqdn"),
+                    audit_txt: sub_match.get_flag("audit-txt"),
+                    provider: *sub_match
+                        .get_one::<Provider>("provider")
+                        .expect("--provider has a default value"),
+                };
+                if let Err(e) = dns_args.validate() {
+                    cmd.error(ErrorKind::InvalidValue, e).exit();
+                }
+                vec![SubcmdArgs::Dns(dns_args)]
             }
-            Some(("firewall", sub_match)) => SubcmdArgs::Firewall(FirewallArgs {
-                name: sub_match.get_one::<String>("NAME").unwrap().clone(),
-                direction: match sub_match.get_one::<Id>("direction").unwrap().as_str() {
-                    "inbound" => Direction::Inbound,
-                    "outbound" => Direction::Outbound,
-                    _ => panic!("No direction specified"),
-                },
-                port: sub_match.get_one::<String>("PORT").unwrap().clone(),
-                protocol: sub_match.get_one::<String>("PROTOCOL").unwrap().clone(),
-                addresses: parse_csv(sub_match, "addresses"),
-                droplets: parse_csv(sub_match, "droplets"),
-                kubernetes_clusters: parse_csv(sub_match, "kubernetes-clusters"),
-                load_balancers: parse_csv(sub_match, "load-balancers"),
-            }),
-            // these situations should be impossible, but Rust can't tell since the subcommand
-            // matches are stringly-typed and it can't tell that we require a subcommand
+            Some(("firewall", sub_match)) => get_values(sub_match, "NAME")
+                .unwrap()
+                .into_iter()
+                .map(|name| {
+                    SubcmdArgs::Firewall(FirewallArgs {
+                        name,
+                        all_matching: sub_match.get_flag("all-matching"),
+                        direction: match sub_match.get_one::<Id>("direction").unwrap().as_str() {
+                            "inbound" => Direction::Inbound,
+                            "outbound" => Direction::Outbound,
+                            _ => panic!("No direction specified"),
+                        },
+                        port: match sub_match.get_one::<String>("PORT").unwrap().as_str() {
+                            "all" => ALL_PORTS.to_string(),
+                            port => port.to_string(),
+                        },
+                        protocols: get_values(sub_match, "PROTOCOL").unwrap(),
+                        addresses: get_values(sub_match, "addresses"),
+                        vpcs: get_values(sub_match, "vpc"),
+                        droplets: get_values(sub_match, "droplets"),
+                        droplet_tags: get_values(sub_match, "droplet-tag"),
+                        kubernetes_clusters: get_values(sub_match, "kubernetes-clusters"),
+                        load_balancers: get_values(sub_match, "load-balancers"),
+                        project: sub_match.get_one::<String>("project").cloned(),
+                        prune_dangling: sub_match.get_flag("prune-dangling"),
+                        dual_stack: sub_match.get_flag("dual-stack"),
+                        ipv6_prefix: sub_match
+                            .get_one::<String>("ipv6-prefix")
+                            .map(|len| len.parse().unwrap()),
+                        old_ips: sub_match
+                            .get_many::<IpAddr>("old-ip")
+                            .map(|ips| ips.copied().collect()),
+                        retain_days: sub_match.get_one::<u32>("retain-days").copied(),
+                        no_verify: sub_match.get_flag("no-verify"),
+                        atomic: sub_match.get_flag("atomic"),
+                        rule_index: sub_match.get_one::<u32>("rule-index").copied(),
+                        match_source_tag: sub_match.get_one::<String>("match-source-tag").cloned(),
+                        tags: get_values(sub_match, "tags"),
+                        replace_tags: sub_match.get_flag("replace-tags"),
+                    })
+                })
+                .collect(),
+            Some(("reserved-ip", sub_match)) => vec![SubcmdArgs::ReservedIp(ReservedIpArgs {
+                droplet: sub_match.get_one::<String>("DROPLET").unwrap().clone(),
+                address: sub_match.get_one::<IpAddr>("address").copied(),
+                from_droplet: sub_match.get_one::<String>("from-droplet").cloned(),
+            })],
+            // the doctor checks the targets of the config file
+            None | Some(("doctor", _)) => match settings
+                .targets
+                .into_iter()
+                .map(SubcmdArgs::validated)
+                .collect()
+            {
+                Ok(targets) => targets,
+                Err(e) => cmd
+                    .error(
+                        ErrorKind::InvalidValue,
+                        format!("invalid target in config file: {e}"),
+                    )
+                    .exit(),
+            },
+            // this situation should be impossible, but Rust can't tell since the subcommand
+            // matches are stringly-typed
             Some((cmd, _)) => panic!("Unknown subcommand detected: {}", cmd),
-            None => panic!("No subcommand specified"),
         };
 
-        Args {
-            token: matches.get_one::<String>("token").unwrap().clone(),
+        let daemon = settings.daemon || settings.service;
+        let interval = settings.interval.expect("--interval has a default value");
+        // like the targets, the jobs of the config file are replaced by a subcommand
+        let jobs: Vec<Job> = match matches.subcommand() {
+            // the doctor checks the tokens of the jobs too
+            None | Some(("doctor", _)) => match settings
+                .jobs
+                .into_iter()
+                .map(|job| Job::from_config(job, interval))
+                .collect()
+            {
+                Ok(jobs) => jobs,
+                Err(e) => cmd
+                    .error(
+                        ErrorKind::InvalidValue,
+                        format!("invalid job in config file: {e}"),
+                    )
+                    .exit(),
+            },
+            Some(_) => Vec::new(),
+        };
+        if !jobs.is_empty() && !daemon && matches.subcommand().is_none() {
+            cmd.error(
+                ErrorKind::ArgumentConflict,
+                "the jobs of the config file are only run with --daemon",
+            )
+            .exit();
+        }
+        let all_targets = || {
+            targets
+                .iter()
+                .chain(jobs.iter().flat_map(|job| job.targets.iter()))
+        };
+
+        let retains = all_targets().any(SubcmdArgs::retains_addresses);
+        if retains && settings.state_file.is_none() {
+            cmd.error(
+                ErrorKind::MissingRequiredArgument,
+                "--retain-days needs --state-file to remember when each address was last detected",
+            )
+            .exit();
+        }
+
+        let verify = settings.verify;
+        let no_verify = all_targets().any(|target| {
+            matches!(
+                target,
+                SubcmdArgs::Firewall(FirewallArgs {
+                    no_verify: true,
+                    atomic: false,
+                    ..
+                })
+            )
+        });
+        if verify && no_verify {
+            cmd.error(
+                ErrorKind::ArgumentConflict,
+                "--verify cannot be used with the firewall --no-verify flag",
+            )
+            .exit();
+        }
+
+        let cloudflare = all_targets().any(|target| {
+            matches!(
+                target,
+                SubcmdArgs::Dns(DnsArgs {
+                    provider: Provider::Cloudflare,
+                    ..
+                })
+            )
+        });
+        if cloudflare && settings.cloudflare_token.is_none() {
+            cmd.error(
+                ErrorKind::MissingRequiredArgument,
+                "the Cloudflare API token must be provided with --cloudflare-token or \
+                    CLOUDFLARE_API_TOKEN to update records hosted by Cloudflare",
+            )
+            .exit();
+        }
+
+        let args = Args {
+            token,
+            cloudflare_token: settings.cloudflare_token,
             ip,
-            dry_run: matches.get_flag("dry_run"),
-            subcmd_args,
+            verbose: settings.verbose,
+            log,
+            dry_run: settings.dry_run,
+            check: settings.check,
+            detailed_exitcode: settings.detailed_exitcode,
+            stats_json: settings.stats_json,
+            profile: settings.profile,
+            daemon,
+            service: settings.service,
+            interval,
+            stabilize_checks: settings
+                .stabilize_checks
+                .expect("--stabilize-checks has a default value"),
+            verify,
+            hooks: Hooks {
+                pre: settings.pre_hook,
+                post: settings.post_hook,
+            },
+            max_pages: settings.max_pages,
+            per_page,
+            page_concurrency,
+            retry,
+            http,
+            deadline: settings.deadline.map(Duration::from_secs),
+            ip_out: settings.ip_out,
+            state_file: settings.state_file,
+            history_file: settings.history_file,
+            lock_file: settings.lock_file,
+            lock_wait: Duration::from_secs(
+                settings.lock_wait.expect("--lock-wait has a default value"),
+            ),
+            cache_file: settings.cache_file.filter(|_| !settings.no_cache),
+            cache_ttl: Duration::from_secs(
+                settings.cache_ttl.expect("--cache-ttl has a default value"),
+            ),
+            heartbeat_url: settings.heartbeat_url,
+            email: settings.email,
+            targets,
+            jobs,
+        };
+        match matches.subcommand() {
+            Some(("doctor", doctor_match)) => Invocation::Doctor(Box::new(DoctorArgs {
+                update: args,
+                json: doctor_match.get_flag("json"),
+            })),
+            _ => Invocation::Update(Box::new(args)),
+        }
+    }
+}
+
+impl IpSpec {
+    /// Determine the IP address to publish, detecting it if necessary and failing if it cannot be
+    /// detected
+    pub async fn try_resolve(&self, http: &HttpOptions) -> Result<IpAddr, String> {
+        let ip = match self {
+            IpSpec::Literal(lit) => {
+                info!("Using user-provided IP address: {}", lit);
+                *lit
+            }
+            IpSpec::Detect(IpSource::Local, _) => {
+                info!("Getting local IP address of machine...");
+                ip_retriever::get_local_ip()
+                    .map_err(|e| format!("Unable to retrieve local IP address: {e}"))?
+            }
+            IpSpec::Detect(IpSource::External, providers) => {
+                info!("Getting public IP address of machine...");
+                ip_retriever::get_external_ip(providers, None, http)
+                    .await
+                    .map_err(|e| format!("Unable to retrieve external IP address: {e}"))?
+            }
+            IpSpec::Detect(IpSource::Router, _) => {
+                info!("Getting WAN IP address from router...");
+                ip_retriever::get_router_ip()
+                    .await
+                    .map_err(|e| format!("Unable to retrieve router IP address: {e}"))?
+            }
+            IpSpec::Interface(interface) => {
+                info!("Getting IP address of network interface {}...", interface);
+                ip_retriever::get_interface_ip(interface, None)
+                    .map_err(|e| format!("Unable to retrieve interface IP address: {e}"))?
+            }
+        };
+        info!("Will publish IP address: {:?}", ip);
+        Ok(ip)
+    }
+
+    /// Determine the IPv4 and IPv6 addresses to publish, detecting each family separately.
+    ///
+    /// A family that cannot be detected is skipped with a warning, so a host without IPv6
+    /// connectivity still publishes its IPv4 address.
+    pub async fn try_resolve_dual_stack(&self, http: &HttpOptions) -> Result<Vec<IpAddr>, String> {
+        let (v4, v6) = match self {
+            IpSpec::Literal(_) => {
+                warn!("A literal IP address was provided, only publishing that address");
+                return self.try_resolve(http).await.map(|ip| vec![ip]);
+            }
+            IpSpec::Detect(source, providers) => {
+                info!(
+                    "Getting IPv4 and IPv6 {} addresses of machine...",
+                    source.name()
+                );
+                tokio::join!(
+                    source.get_ip_for_family(IpFamily::V4, providers, http),
+                    source.get_ip_for_family(IpFamily::V6, providers, http)
+                )
+            }
+            IpSpec::Interface(interface) => {
+                info!(
+                    "Getting IPv4 and IPv6 addresses of network interface {}...",
+                    interface
+                );
+                (
+                    ip_retriever::get_interface_ip(interface, Some(IpFamily::V4)),
+                    ip_retriever::get_interface_ip(interface, Some(IpFamily::V6)),
+                )
+            }
+        };
+        let ips: Vec<IpAddr> = [(IpFamily::V4, v4), (IpFamily::V6, v6)]
+            .into_iter()
+            .filter_map(|(family, result)| match result {
+                Ok(ip) => Some(ip),
+                Err(e) => {
+                    warn!("Unable to retrieve {} address: {}", family.name(), e);
+                    None
+                }
+            })
+            .collect();
+        if ips.is_empty() {
+            return Err("Unable to retrieve either an IPv4 or IPv6 address".to_string());
+        }
+        info!("Will publish IP addresses: {:?}", ips);
+        Ok(ips)
+    }
+}
+
+impl Args {
+    /// The API token of the targets, which the command line requires unless every job has its
+    /// own token and there are no other targets
+    pub fn api_token(&self) -> &Secret {
+        self.token
+            .as_ref()
+            .expect("an API token is required for the targets")
+    }
+
+    /// The settings for running a job: these settings with the job's targets, schedule, and token
+    pub fn for_job(&self, job: &Job) -> Args {
+        Args {
+            token: job.token.clone().or_else(|| self.token.clone()),
+            interval: job.interval,
+            targets: job.targets.clone(),
+            jobs: Vec::new(),
+            ..self.clone()
+        }
+    }
+
+    /// Detect the IP address(es) to publish for the requested targets
+    pub async fn detect_ips(&self) -> Result<Vec<IpAddr>, String> {
+        let dual_stack = self.targets.iter().any(|target| match target {
+            SubcmdArgs::Dns(dns_args) => dns_args.dual_stack,
+            SubcmdArgs::Firewall(fw_args) => fw_args.dual_stack,
+            SubcmdArgs::ReservedIp(_) => false,
+        });
+        if dual_stack {
+            self.ip.try_resolve_dual_stack(&self.http).await
+        } else {
+            self.ip.try_resolve(&self.http).await.map(|ip| vec![ip])
+        }
+    }
+}
+
+impl Job {
+    /// Check a job read from the config file, taking the interval from the rest of the settings
+    /// unless the job has its own
+    fn from_config(job: JobConfig, interval: u64) -> Result<Job, String> {
+        let name = job.name;
+        if job.interval.is_some() && job.cron.is_some() {
+            return Err(format!("{name}: interval cannot be used with cron"));
+        }
+        if job.targets.is_empty() {
+            return Err(format!("{name}: targets must not be empty"));
+        }
+        let cron = job
+            .cron
+            .as_deref()
+            .map(Cron::parse)
+            .transpose()
+            .map_err(|e| format!("{name}: {e}"))?;
+        let token = match (job.token, job.token_file) {
+            (Some(_), Some(_)) => {
+                return Err(format!("{name}: token cannot be used with token_file"))
+            }
+            (token, None) => token,
+            (None, Some(path)) => {
+                Some(config::read_token_file(&path).map_err(|e| format!("{name}: {e}"))?)
+            }
+        };
+        let targets = job
+            .targets
+            .into_iter()
+            .map(SubcmdArgs::validated)
+            .collect::<Result<_, _>>()
+            .map_err(|e| format!("{name}: {e}"))?;
+        Ok(Job {
+            interval: job.interval.unwrap_or(interval),
+            cron,
+            jitter: job.jitter.map(Duration::from_secs),
+            token,
+            targets,
+            name,
+        })
+    }
+}
+
+impl SubcmdArgs {
+    /// A target with the given settings of a config file and the defaults for the others
+    pub fn from_fields(fields: Vec<(&str, serde_yaml::Value)>) -> SubcmdArgs {
+        let fields = fields.into_iter().map(|(key, value)| (key.into(), value));
+        serde_yaml::from_value(serde_yaml::Value::Mapping(fields.collect()))
+            .expect("the target has every required setting")
+    }
+
+    /// Whether the target publishes the detected IP address(es) of this machine, rather than the
+    /// address of another resource
+    pub fn follows_detected_ips(&self) -> bool {
+        match self {
+            SubcmdArgs::Dns(dns_args) => {
+                dns_args.from_droplet.is_none() && dns_args.from_load_balancer.is_none()
+            }
+            _ => true,
+        }
+    }
+
+    /// Whether the target keeps the addresses it held before for `--retain-days`, so that they
+    /// expire even while the detected address stays the same
+    pub fn retains_addresses(&self) -> bool {
+        matches!(
+            self,
+            SubcmdArgs::Firewall(FirewallArgs {
+                retain_days: Some(_),
+                ..
+            })
+        )
+    }
+
+    /// Check a target read from a config file with the same rules the command line applies to the
+    /// subcommand arguments
+    fn validated(self) -> Result<SubcmdArgs, String> {
+        match self {
+            SubcmdArgs::Dns(dns_args) => {
+                dns_args
+                    .validate()
+                    .map_err(|e| format!("{}: {}", dns_args.record, e))?;
+                Ok(SubcmdArgs::Dns(dns_args))
+            }
+            SubcmdArgs::Firewall(mut fw_args) => {
+                if fw_args.port == "all" {
+                    fw_args.port = ALL_PORTS.to_string();
+                }
+                parse_ports(&fw_args.port).map_err(|e| format!("{}: {}", fw_args.name, e))?;
+                if fw_args.protocols.is_empty() {
+                    return Err(format!("{}: protocols must not be empty", fw_args.name));
+                }
+                if let Some(protocol) = fw_args
+                    .protocols
+                    .iter()
+                    .find(|p| !["tcp", "udp", "icmp"].contains(&p.as_str()))
+                {
+                    return Err(format!(
+                        "{}: protocol must be tcp, udp, or icmp, not '{}'",
+                        fw_args.name, protocol
+                    ));
+                }
+                for address in fw_args.addresses.iter().flatten() {
+                    parse_address(address).map_err(|e| format!("{}: {}", fw_args.name, e))?;
+                }
+                if let Some(prefix) = fw_args.ipv6_prefix.filter(|p| ![56, 64].contains(p)) {
+                    return Err(format!(
+                        "{}: ipv6_prefix must be 56 or 64, not {}",
+                        fw_args.name, prefix
+                    ));
+                }
+                Ok(SubcmdArgs::Firewall(fw_args))
+            }
+            SubcmdArgs::ReservedIp(rip_args) => {
+                if rip_args.address.is_some() == rip_args.from_droplet.is_some() {
+                    return Err(format!(
+                        "{}: exactly one of address or from_droplet must be given",
+                        rip_args.droplet
+                    ));
+                }
+                Ok(SubcmdArgs::ReservedIp(rip_args))
+            }
+        }
+    }
+}
+
+impl Display for SubcmdArgs {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SubcmdArgs::Dns(dns_args) => {
+                let rtype = if dns_args.dual_stack {
+                    "A/AAAA"
+                } else {
+                    dns_args.rtype.as_str()
+                };
+                match dns_args.domain {
+                    Some(ref domain) => {
+                        write!(f, "DNS record {}.{} ({})", dns_args.record, domain, rtype)
+                    }
+                    None => write!(f, "DNS record {} ({})", dns_args.record, rtype),
+                }
+            }
+            SubcmdArgs::Firewall(fw_args) => write!(
+                f,
+                "{} {} {:?} rule for port {}",
+                if fw_args.all_matching {
+                    "firewalls matching"
+                } else {
+                    "firewall"
+                },
+                fw_args.name,
+                fw_args.direction,
+                fw_args.port
+            ),
+            SubcmdArgs::ReservedIp(rip_args) => match (&rip_args.address, &rip_args.from_droplet) {
+                (Some(address), _) => write!(
+                    f,
+                    "reserved IP {} for droplet {}",
+                    address, rip_args.droplet
+                ),
+                (None, from_droplet) => write!(
+                    f,
+                    "reserved IP of droplet {} for droplet {}",
+                    from_droplet.as_deref().unwrap_or_default(),
+                    rip_args.droplet
+                ),
+            },
+        }
+    }
+}
+
+/// Check that a record name is either `@` for the apex of the domain, or a host name whose
+/// leftmost label may be the `*` wildcard
+fn validate_record_name(name: &str) -> Result<(), String> {
+    if name == "@" {
+        return Ok(());
+    }
+    for (i, label) in name.split('.').enumerate() {
+        if label == "*" && i == 0 {
+            continue;
+        }
+        if label.contains('*') {
+            return Err(format!(
+                "a wildcard can only be the whole leftmost label of a record name, not '{name}'"
+            ));
+        }
+        if label.contains('@') {
+            return Err(format!(
+                "@ can only be used on its own for the apex of the domain, not in '{name}'"
+            ));
+        }
+        if label.is_empty()
+            || label.len() > 63
+            || !label
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            return Err(format!("'{name}' is not a valid record name"));
+        }
+    }
+    Ok(())
+}
+
+impl DnsArgs {
+    /// Whether the record holds an IP address, which is detected unless data is given
+    fn is_address(&self) -> bool {
+        self.rtype == "A" || self.rtype == "AAAA"
+    }
+
+    /// Check that the record names are valid, and that the data and the MX and SRV fields suit
+    /// the record type
+    fn validate(&self) -> Result<(), String> {
+        if self.provider == Provider::Cloudflare {
+            self.validate_cloudflare()?;
+        }
+        match (&self.domain, self.all_domains) {
+            (Some(_), true) => {
+                return Err("all_domains cannot be used with domain".to_string());
+            }
+            (None, true) if self.fqdn => {
+                return Err("fqdn cannot be used with all_domains".to_string());
+            }
+            (None, true) => validate_record_name(&self.record)?,
+            (None, false) if self.record == "@" => {
+                return Err("the domain is required to update the apex record @".to_string())
+            }
+            // a fully qualified name may end with the root
+            (_, false) => {
+                validate_record_name(self.record.strip_suffix('.').unwrap_or(&self.record))?
+            }
+        }
+        if self.create_domain && self.domain.is_none() {
+            return Err("create_domain requires the domain to be given".to_string());
+        }
+        for alias in &self.aliases {
+            validate_record_name(alias)?;
+        }
+        if self.with_www && self.record.starts_with('*') {
+            return Err("with_www cannot be used with a wildcard record".to_string());
+        }
+
+        for (name, ttl) in [("ttl", Some(self.ttl)), ("lower_ttl", self.lower_ttl)] {
+            if ttl.is_some_and(|ttl| ttl < MIN_TTL) {
+                return Err(format!("{} must be at least {} seconds", name, MIN_TTL));
+            }
+        }
+
+        if !RECORD_TYPES.contains(&self.rtype.as_str()) {
+            return Err(format!(
+                "rtype must be one of {}, not '{}'",
+                RECORD_TYPES.join(", "),
+                self.rtype
+            ));
+        }
+        if self.dual_stack && (self.data.is_some() || self.rtype != "A") {
+            return Err("dual_stack cannot be used with data or rtype".to_string());
+        }
+        match (&self.from_droplet, &self.from_load_balancer) {
+            (None, None) => (),
+            (Some(_), Some(_)) => {
+                return Err("from_droplet cannot be used with from_load_balancer".to_string())
+            }
+            _ if self.data.is_some() || !self.is_address() => {
+                return Err(
+                    "from_droplet and from_load_balancer can only be used for A and \
+                    AAAA records without data"
+                        .to_string(),
+                )
+            }
+            _ => (),
+        }
+        match self.data {
+            None if !self.is_address() => {
+                return Err(format!("data is required for {} records", self.rtype))
+            }
+            Some(ref data) if self.is_address() => match data.parse::<IpAddr>() {
+                Ok(ip) if ip.is_ipv6() == (self.rtype == "AAAA") => (),
+                _ => {
+                    return Err(format!(
+                        "'{}' is not a valid address for {} records",
+                        data, self.rtype
+                    ))
+                }
+            },
+            _ => (),
+        }
+        let is_srv = self.rtype == "SRV";
+        for (name, given, required) in [
+            (
+                "priority",
+                self.priority.is_some(),
+                is_srv || self.rtype == "MX",
+            ),
+            ("port", self.port.is_some(), is_srv),
+            ("weight", self.weight.is_some(), is_srv),
+        ] {
+            if given && !required {
+                return Err(format!("{} cannot be set for {} records", name, self.rtype));
+            } else if required && !given {
+                return Err(format!("{} is required for {} records", name, self.rtype));
+            }
+        }
+        Ok(())
+    }
+
+    /// Reject the settings that only DigitalOcean supports
+    fn validate_cloudflare(&self) -> Result<(), String> {
+        for (name, given) in [
+            ("from_droplet", self.from_droplet.is_some()),
+            ("from_load_balancer", self.from_load_balancer.is_some()),
+            ("lower_ttl", self.lower_ttl.is_some()),
+            ("all_domains", self.all_domains),
+            ("create_domain", self.create_domain),
+            ("check_delegation", self.check_delegation),
+            ("audit_txt", self.audit_txt),
+            ("force", self.force),
+        ] {
+            if given {
+                return Err(format!(
+                    "{name} cannot be used with the cloudflare provider"
+                ));
+            }
+        }
+        if self.rtype == "SRV" {
+            return Err("SRV records cannot be updated with the cloudflare provider".to_string());
+        }
+        Ok(())
+    }
+
+    /// Ensure the record type can hold the IP address
+    pub fn validate_rtype(&self, ip: &IpAddr) -> Result<(), crate::Error> {
+        if (ip.is_ipv4() && self.rtype != "A") || (ip.is_ipv6() && self.rtype != "AAAA") {
+            Err(crate::Error::RecordTypeMismatch {
+                rtype: self.rtype.clone(),
+                ip: *ip,
+            })
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl FirewallArgs {
+    /// The ports of the rules to update, from the comma-separated `port`, without duplicates
+    pub fn ports(&self) -> Vec<String> {
+        let mut ports: Vec<String> = Vec::new();
+        for port in self.port.split(',').map(str::trim) {
+            let port = if port == "all" { ALL_PORTS } else { port };
+            if !ports.iter().any(|p| p == port) {
+                ports.push(port.to_string());
+            }
         }
+        ports
     }
 }
 
-fn parse_csv(matches: &ArgMatches, arg_name: &str) -> Option<Vec<String>> {
+fn get_values(matches: &ArgMatches, arg_name: &str) -> Option<Vec<String>> {
     matches
-        .get_one::<String>(arg_name)
-        .map(|raw| raw.split(',').map(|x| x.to_string()).collect())
+        .get_many::<String>(arg_name)
+        .map(|values| values.cloned().collect())
+}
+
+/// The settings of an invocation without any flags, from the environment and defaults
+fn default_settings() -> Config {
+    Config::default().merge(&command().get_matches_from([crate_name!(), "config", "init"]))
+}
+
+/// Add a target to the settings for each `--record` and `--firewall` of `config init`, with the
+/// defaults of a target in a config file
+fn with_init_targets(mut settings: Config, init_match: &ArgMatches) -> Config {
+    let port = init_match.get_one::<String>("port").unwrap();
+    let protocols: Vec<String> = init_match
+        .get_many::<String>("protocol")
+        .unwrap()
+        .cloned()
+        .collect();
+    let records = init_match.get_many::<String>("record").unwrap_or_default();
+    let firewalls = init_match
+        .get_many::<String>("firewall")
+        .unwrap_or_default();
+    settings.targets.extend(records.map(|record| {
+        SubcmdArgs::from_fields(vec![
+            ("type", "dns".into()),
+            ("record", record.as_str().into()),
+        ])
+    }));
+    settings.targets.extend(firewalls.map(|name| {
+        SubcmdArgs::from_fields(vec![
+            ("type", "firewall".into()),
+            ("name", name.as_str().into()),
+            ("direction", "inbound".into()),
+            ("port", port.as_str().into()),
+            ("protocols", protocols.clone().into()),
+        ])
+    }));
+    settings
+}
+
+/// Validate a comma-separated list of firewall ports, each a port, a port range such as
+/// `8000-9000`, or "all" for a rule covering every port.  "all" cannot be combined with other
+/// ports.
+fn parse_ports(raw: &str) -> Result<String, String> {
+    let ports: Vec<&str> = raw.split(',').map(str::trim).collect();
+    let is_all = |port: &str| port == "all" || port == ALL_PORTS;
+    let port_number = |port: &str| port.parse::<u16>().ok().filter(|p| *p > 0);
+    for port in &ports {
+        let valid = match port.split_once('-') {
+            _ if is_all(port) => true,
+            Some((from, to)) => matches!(
+                (port_number(from), port_number(to)),
+                (Some(from), Some(to)) if from <= to
+            ),
+            None => port_number(port).is_some(),
+        };
+        if !valid {
+            return Err(format!("'{port}' is not a port or port range"));
+        }
+    }
+    if ports.len() > 1 && ports.iter().any(|port| is_all(port)) {
+        return Err("\"all\" cannot be combined with other ports".to_string());
+    }
+    Ok(raw.to_string())
+}
+
+/// Validate that a firewall address is an IP address or a CIDR block.
+fn parse_address(raw: &str) -> Result<String, String> {
+    let (addr, prefix) = match raw.split_once('/') {
+        Some((addr, prefix)) => (addr, Some(prefix)),
+        None => (raw, None),
+    };
+    let ip = addr
+        .parse::<IpAddr>()
+        .map_err(|_| format!("'{raw}' is not an IP address or CIDR"))?;
+    if let Some(prefix) = prefix {
+        let max_prefix = if ip.is_ipv4() { 32 } else { 128 };
+        match prefix.parse::<u8>() {
+            Ok(p) if p <= max_prefix => (),
+            _ => return Err(format!("'{raw}' has an invalid prefix length")),
+        }
+    }
+    Ok(raw.to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+    use std::time::Duration;
+
+    use crate::cli::{
+        command, get_values, parse_address, parse_ports, with_init_targets, Direction, DnsArgs,
+        FirewallArgs, Job, ReservedIpArgs, SubcmdArgs,
+    };
+    use crate::config::{Config, JobConfig};
+    use crate::cron::Cron;
+    use crate::digitalocean::firewall::ALL_PORTS;
+    use crate::provider::Provider;
+    use crate::secret::Secret;
+
+    #[test]
+    fn test_job_from_config() {
+        let job = |interval: Option<u64>, cron: Option<&str>| JobConfig {
+            name: "web".to_string(),
+            interval,
+            cron: cron.map(str::to_string),
+            jitter: Some(30),
+            token: None,
+            token_file: None,
+            targets: vec![firewall("all", &["tcp"], &[])],
+        };
+
+        let resolved = Job::from_config(job(None, Some("*/15 * * * *")), 300).unwrap();
+        assert_eq!(300, resolved.interval);
+        assert_eq!(Some(Cron::parse("*/15 * * * *").unwrap()), resolved.cron);
+        assert_eq!(Some(Duration::from_secs(30)), resolved.jitter);
+        // the targets are checked like those of the config file
+        assert!(matches!(
+            &resolved.targets[..],
+            [SubcmdArgs::Firewall(FirewallArgs { port, .. })] if port == ALL_PORTS
+        ));
+
+        assert_eq!(
+            60,
+            Job::from_config(job(Some(60), None), 300).unwrap().interval
+        );
+        assert!(Job::from_config(job(Some(60), Some("* * * * *")), 300).is_err());
+        assert!(Job::from_config(job(None, Some("every minute")), 300).is_err());
+        assert!(Job::from_config(
+            JobConfig {
+                targets: vec![],
+                ..job(None, None)
+            },
+            300
+        )
+        .is_err());
+
+        // a job may use the token of another account
+        assert_eq!(None, resolved.token);
+        let dir = std::env::temp_dir().join(format!("dyn-dns-job-token-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let token_file = dir.join("token");
+        fs::write(&token_file, "team-token\n").unwrap();
+        let with_token_file = JobConfig {
+            token_file: Some(token_file),
+            ..job(None, None)
+        };
+        let resolved = Job::from_config(with_token_file.clone(), 300).unwrap();
+        assert_eq!(
+            Some("team-token"),
+            resolved.token.as_ref().map(Secret::expose)
+        );
+        assert!(Job::from_config(
+            JobConfig {
+                token: Some(Secret::new("other".to_string())),
+                ..with_token_file
+            },
+            300
+        )
+        .is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_with_init_targets() {
+        let matches = command()
+            .try_get_matches_from([
+                "dyn-dns",
+                "config",
+                "init",
+                "--record",
+                "home.example.com",
+                "--firewall",
+                "office",
+                "--protocol",
+                "tcp,udp",
+            ])
+            .unwrap();
+        let Some(("init", init_match)) = matches.subcommand_matches("config").unwrap().subcommand()
+        else {
+            panic!("expected the init subcommand");
+        };
+        let config = with_init_targets(Config::default(), init_match);
+        assert!(matches!(
+            &config.targets[..],
+            [
+                SubcmdArgs::Dns(DnsArgs { record, domain: None, create_if_missing: true, .. }),
+                SubcmdArgs::Firewall(FirewallArgs {
+                    name,
+                    direction: Direction::Inbound,
+                    port,
+                    protocols,
+                    ..
+                }),
+            ] if record == "home.example.com" && name == "office" && port == "22"
+                && protocols == &["tcp", "udp"]
+        ));
+    }
+
+    #[test]
+    fn test_parse_ports() {
+        assert!(parse_ports("22").is_ok());
+        assert!(parse_ports("22,80,443,8000-9000").is_ok());
+        assert!(parse_ports("all").is_ok());
+        assert!(parse_ports("0").is_ok());
+        assert!(parse_ports("22,all").is_err());
+        assert!(parse_ports("9000-8000").is_err());
+        assert!(parse_ports("22,").is_err());
+        assert!(parse_ports("ssh").is_err());
+        assert!(parse_ports("70000").is_err());
+
+        let fw_args = FirewallArgs {
+            name: "web".to_string(),
+            all_matching: false,
+            direction: Direction::Inbound,
+            port: "22, 80,22,8000-9000".to_string(),
+            protocols: vec!["tcp".to_string()],
+            addresses: None,
+            vpcs: None,
+            droplets: None,
+            droplet_tags: None,
+            kubernetes_clusters: None,
+            load_balancers: None,
+            project: None,
+            prune_dangling: false,
+            dual_stack: false,
+            ipv6_prefix: None,
+            old_ips: None,
+            retain_days: None,
+            no_verify: false,
+            atomic: false,
+            rule_index: None,
+            match_source_tag: None,
+            tags: None,
+            replace_tags: false,
+        };
+        assert_eq!(vec!["22", "80", "8000-9000"], fw_args.ports());
+    }
+
+    #[test]
+    fn test_parse_address() {
+        assert_eq!(Ok("1.2.3.4".to_string()), parse_address("1.2.3.4"));
+        assert_eq!(Ok("10.0.0.0/8".to_string()), parse_address("10.0.0.0/8"));
+        assert_eq!(Ok("2001:db8::1".to_string()), parse_address("2001:db8::1"));
+        assert_eq!(
+            Ok("2001:db8::/64".to_string()),
+            parse_address("2001:db8::/64")
+        );
+        assert!(parse_address("foo").is_err());
+        assert!(parse_address("1.2.3.4/33").is_err());
+        assert!(parse_address("2001:db8::/129").is_err());
+        assert!(parse_address("1.2.3.4/").is_err());
+    }
+
+    #[test]
+    fn test_parse_kubernetes_clusters() {
+        let matches = command()
+            .try_get_matches_from([
+                "dyn-dns",
+                "firewall",
+                "web",
+                "443",
+                "tcp",
+                "--inbound",
+                "--kubernetes-clusters",
+                "prod,staging",
+                "--kubernetes-clusters",
+                "dev",
+            ])
+            .unwrap();
+        let (_, sub_match) = matches.subcommand().unwrap();
+        assert_eq!(
+            Some(vec![
+                "prod".to_string(),
+                "staging".to_string(),
+                "dev".to_string()
+            ]),
+            get_values(sub_match, "kubernetes-clusters")
+        );
+    }
+
+    fn firewall(port: &str, protocols: &[&str], addresses: &[&str]) -> SubcmdArgs {
+        SubcmdArgs::Firewall(FirewallArgs {
+            name: "web".to_string(),
+            all_matching: false,
+            direction: Direction::Inbound,
+            port: port.to_string(),
+            protocols: protocols.iter().map(|p| p.to_string()).collect(),
+            addresses: Some(addresses.iter().map(|a| a.to_string()).collect()),
+            vpcs: None,
+            droplets: None,
+            droplet_tags: None,
+            kubernetes_clusters: None,
+            load_balancers: None,
+            project: None,
+            prune_dangling: false,
+            dual_stack: false,
+            ipv6_prefix: None,
+            old_ips: None,
+            retain_days: None,
+            no_verify: false,
+            atomic: false,
+            rule_index: None,
+            match_source_tag: None,
+            tags: None,
+            replace_tags: false,
+        })
+    }
+
+    #[test]
+    fn test_validate_target() {
+        assert_eq!(
+            Ok(firewall(ALL_PORTS, &["tcp"], &["1.2.3.4"])),
+            firewall("all", &["tcp"], &["1.2.3.4"]).validated()
+        );
+        assert!(firewall("22", &[], &["1.2.3.4"]).validated().is_err());
+        assert!(firewall("22", &["sctp"], &["1.2.3.4"]).validated().is_err());
+        assert!(firewall("22", &["tcp"], &["foo"]).validated().is_err());
+
+        let reserved_ip = |address: Option<&str>, from_droplet: Option<&str>| {
+            SubcmdArgs::ReservedIp(ReservedIpArgs {
+                droplet: "web".to_string(),
+                address: address.map(|a| a.parse().unwrap()),
+                from_droplet: from_droplet.map(|d| d.to_string()),
+            })
+        };
+        assert!(reserved_ip(Some("1.2.3.4"), None).validated().is_ok());
+        assert!(reserved_ip(None, Some("old")).validated().is_ok());
+        assert!(reserved_ip(None, None).validated().is_err());
+        assert!(reserved_ip(Some("1.2.3.4"), Some("old"))
+            .validated()
+            .is_err());
+    }
+
+    fn dns(rtype: &str, data: Option<&str>, priority: Option<u16>, port: Option<u16>) -> DnsArgs {
+        DnsArgs {
+            record: "home".to_string(),
+            domain: None,
+            rtype: rtype.to_string(),
+            data: data.map(|d| d.to_string()),
+            from_droplet: None,
+            from_load_balancer: None,
+            priority,
+            port,
+            weight: port,
+            ttl: 60,
+            lower_ttl: None,
+            aliases: vec![],
+            with_www: false,
+            check_delegation: false,
+            force: false,
+            create_if_missing: true,
+            create_domain: false,
+            all_domains: false,
+            dual_stack: false,
+            fqdn: false,
+            audit_txt: false,
+            provider: Provider::DigitalOcean,
+        }
+    }
+
+    #[test]
+    fn test_validate_record_name() {
+        let named = |record: &str, domain: Option<&str>| DnsArgs {
+            record: record.to_string(),
+            domain: domain.map(|d| d.to_string()),
+            ..dns("A", None, None, None)
+        };
+        assert!(named("@", Some("example.com")).validate().is_ok());
+        assert!(named("*", Some("example.com")).validate().is_ok());
+        assert!(named("*.dev", Some("example.com")).validate().is_ok());
+        assert!(named("_acme-challenge", Some("example.com"))
+            .validate()
+            .is_ok());
+        assert!(named("*.example.com.", None).validate().is_ok());
+        assert!(named("@", None).validate().is_err());
+        assert!(DnsArgs {
+            all_domains: true,
+            ..named("@", None)
+        }
+        .validate()
+        .is_ok());
+        assert!(DnsArgs {
+            all_domains: true,
+            ..named("home", Some("example.com"))
+        }
+        .validate()
+        .is_err());
+        assert!(named("home.example.com.", Some("example.com"))
+            .validate()
+            .is_ok());
+        assert!(DnsArgs {
+            all_domains: true,
+            fqdn: true,
+            ..named("home", None)
+        }
+        .validate()
+        .is_err());
+        assert!(named("dev.*", Some("example.com")).validate().is_err());
+        assert!(named("*dev", Some("example.com")).validate().is_err());
+        assert!(named("@.dev", Some("example.com")).validate().is_err());
+        assert!(named("home..dev", Some("example.com")).validate().is_err());
+        assert!(named("", Some("example.com")).validate().is_err());
+        assert!(DnsArgs {
+            with_www: true,
+            ..named("*", Some("example.com"))
+        }
+        .validate()
+        .is_err());
+        assert!(DnsArgs {
+            aliases: vec!["www.*".to_string()],
+            ..named("@", Some("example.com"))
+        }
+        .validate()
+        .is_err());
+    }
+
+    #[test]
+    fn test_validate_dns() {
+        assert!(dns("A", None, None, None).validate().is_ok());
+        assert!(dns("AAAA", Some("2001:db8::1"), None, None)
+            .validate()
+            .is_ok());
+        assert!(dns("A", Some("2001:db8::1"), None, None)
+            .validate()
+            .is_err());
+        assert!(dns("A", Some("1.2.3.4"), Some(10), None)
+            .validate()
+            .is_err());
+        assert!(dns("TXT", Some("v=spf1 -all"), None, None)
+            .validate()
+            .is_ok());
+        assert!(dns("TXT", None, None, None).validate().is_err());
+        assert!(dns("CNAME", Some("example.com."), None, None)
+            .validate()
+            .is_ok());
+        assert!(dns("MX", Some("mail.example.com."), Some(10), None)
+            .validate()
+            .is_ok());
+        assert!(dns("MX", Some("mail.example.com."), None, None)
+            .validate()
+            .is_err());
+        assert!(dns("MX", Some("mail.example.com."), Some(10), Some(25))
+            .validate()
+            .is_err());
+        assert!(dns("SRV", Some("sip.example.com."), Some(10), Some(5060))
+            .validate()
+            .is_ok());
+        assert!(dns("SRV", Some("sip.example.com."), Some(10), None)
+            .validate()
+            .is_err());
+        assert!(dns("NS", Some("ns1.example.com."), None, None)
+            .validate()
+            .is_err());
+
+        let from_droplet = |rtype: &str, data: Option<&str>| DnsArgs {
+            from_droplet: Some("web".to_string()),
+            ..dns(rtype, data, None, None)
+        };
+        assert!(from_droplet("AAAA", None).validate().is_ok());
+        assert!(DnsArgs {
+            from_load_balancer: Some("lb".to_string()),
+            ..from_droplet("A", None)
+        }
+        .validate()
+        .is_err());
+        assert!(from_droplet("A", Some("1.2.3.4")).validate().is_err());
+        assert!(from_droplet("CNAME", Some("example.com."))
+            .validate()
+            .is_err());
+
+        assert!(DnsArgs {
+            ttl: 30,
+            ..dns("A", None, None, None)
+        }
+        .validate()
+        .is_ok());
+        assert!(DnsArgs {
+            ttl: 10,
+            ..dns("A", None, None, None)
+        }
+        .validate()
+        .is_err());
+        assert!(DnsArgs {
+            lower_ttl: Some(5),
+            ..dns("A", None, None, None)
+        }
+        .validate()
+        .is_err());
+        assert!(DnsArgs {
+            create_domain: true,
+            ..dns("A", None, None, None)
+        }
+        .validate()
+        .is_err());
+    }
+
+    #[test]
+    fn test_validate_cloudflare_dns() {
+        let cloudflare = |rtype: &str, data: Option<&str>, priority: Option<u16>| DnsArgs {
+            provider: Provider::Cloudflare,
+            ..dns(rtype, data, priority, None)
+        };
+        assert!(cloudflare("A", None, None).validate().is_ok());
+        assert!(cloudflare("MX", Some("mail.example.com."), Some(10))
+            .validate()
+            .is_ok());
+        assert!(DnsArgs {
+            provider: Provider::Cloudflare,
+            ..dns("SRV", Some("sip.example.com."), Some(10), Some(5060))
+        }
+        .validate()
+        .is_err());
+        assert!(DnsArgs {
+            from_droplet: Some("web".to_string()),
+            ..cloudflare("A", None, None)
+        }
+        .validate()
+        .is_err());
+        assert!(DnsArgs {
+            all_domains: true,
+            ..cloudflare("A", None, None)
+        }
+        .validate()
+        .is_err());
+        assert!(DnsArgs {
+            check_delegation: true,
+            ..cloudflare("A", None, None)
+        }
+        .validate()
+        .is_err());
+        for (name, args) in [
+            (
+                "from_droplet",
+                DnsArgs {
+                    from_droplet: Some("web".to_string()),
+                    ..cloudflare("AAAA", None, None)
+                },
+            ),
+            (
+                "audit_txt",
+                DnsArgs {
+                    audit_txt: true,
+                    ..cloudflare("A", None, None)
+                },
+            ),
+            (
+                "force",
+                DnsArgs {
+                    force: true,
+                    ..cloudflare("A", None, None)
+                },
+            ),
+            (
+                "lower_ttl",
+                DnsArgs {
+                    lower_ttl: Some(60),
+                    ..cloudflare("A", None, None)
+                },
+            ),
+            (
+                "create_domain",
+                DnsArgs {
+                    create_domain: true,
+                    ..cloudflare("A", None, None)
+                },
+            ),
+        ] {
+            assert_eq!(
+                Err(format!(
+                    "{name} cannot be used with the cloudflare provider"
+                )),
+                args.validate()
+            );
+        }
+    }
 }
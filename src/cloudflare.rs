@@ -0,0 +1,629 @@
+//! A client for the DNS records of zones hosted by Cloudflare, so that records kept there can be
+//! updated alongside those kept by DigitalOcean.
+
+use async_trait::async_trait;
+use reqwest::{Client, Method, RequestBuilder, Response, StatusCode};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use tracing::info;
+use url::Url;
+
+use crate::digitalocean::api::DEFAULT_TIMEOUT;
+use crate::digitalocean::dns::RecordValue;
+use crate::digitalocean::error::{ApiError, Error, Failure};
+use crate::http::HttpOptions;
+use crate::secret::Secret;
+
+/// Number of zones requested in each page of the zone listing, the most the API allows
+const ZONES_PER_PAGE: u32 = 50;
+
+#[async_trait(?Send)]
+pub trait CloudflareDnsClient {
+    async fn get_zones(&self) -> Result<Vec<Zone>, Error>;
+
+    async fn get_zone(&self, name: &str) -> Result<Option<Zone>, Error>;
+
+    async fn get_records(
+        &self,
+        zone: &Zone,
+        fqdn: &str,
+        rtype: &str,
+    ) -> Result<Vec<CloudflareRecord>, Error>;
+
+    async fn create_record(
+        &self,
+        zone: &Zone,
+        fqdn: &str,
+        rtype: &str,
+        value: &RecordValue,
+        ttl: &u16,
+        dry_run: &bool,
+    ) -> Result<CloudflareRecord, Error>;
+
+    async fn update_record(
+        &self,
+        zone: &Zone,
+        record: &CloudflareRecord,
+        value: &RecordValue,
+        ttl: &u16,
+        dry_run: &bool,
+    ) -> Result<CloudflareRecord, Error>;
+}
+
+pub struct CloudflareDnsClientImpl {
+    http: Client,
+    base_url: Url,
+    token: Secret,
+}
+
+impl CloudflareDnsClientImpl {
+    pub fn new(token: Secret, http: &HttpOptions) -> CloudflareDnsClientImpl {
+        CloudflareDnsClientImpl {
+            http: http.client(DEFAULT_TIMEOUT),
+            base_url: Url::parse("https://api.cloudflare.com/client/v4/").unwrap(),
+            token,
+        }
+    }
+
+    #[cfg(test)]
+    pub fn new_for_test(token: String, base_url: String) -> CloudflareDnsClientImpl {
+        CloudflareDnsClientImpl {
+            http: Client::new(),
+            base_url: Url::parse(format!("{}/client/v4/", base_url).as_str()).unwrap(),
+            token: Secret::new(token),
+        }
+    }
+
+    fn request(&self, method: Method, endpoint: &str) -> RequestBuilder {
+        self.http
+            .request(method, self.base_url.join(endpoint).unwrap())
+            .bearer_auth(self.token.expose())
+    }
+
+    /// Read the result of a request, failing with the status if the API did not succeed
+    async fn read<T: DeserializeOwned>(request: RequestBuilder) -> Result<T, Error> {
+        Ok(request
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<CloudflareResp<T>>()
+            .await?
+            .result)
+    }
+
+    /// Send a change, failing with the reasons the API gave if it was not made
+    async fn change(
+        request: RequestBuilder,
+        failure: fn(Failure) -> Error,
+    ) -> Result<CloudflareRecord, Error> {
+        let resp = request.send().await?;
+        if resp.status().is_success() {
+            Ok(resp
+                .json::<CloudflareResp<CloudflareRecord>>()
+                .await?
+                .result)
+        } else {
+            Err(failure(Failure::Rejected(api_error(resp).await)))
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl CloudflareDnsClient for CloudflareDnsClientImpl {
+    /// Get every zone the token has access to
+    async fn get_zones(&self) -> Result<Vec<Zone>, Error> {
+        let mut zones = Vec::new();
+        let mut page = 1;
+        loop {
+            let resp = self
+                .request(
+                    Method::GET,
+                    format!("zones?page={}&per_page={}", page, ZONES_PER_PAGE).as_str(),
+                )
+                .send()
+                .await?
+                .error_for_status()?
+                .json::<CloudflareResp<Vec<Zone>>>()
+                .await?;
+            zones.extend(resp.result);
+            match resp.result_info {
+                Some(info) if info.page < info.total_pages => page = info.page + 1,
+                _ => return Ok(zones),
+            }
+        }
+    }
+
+    /// Get the zone of a domain, if the token has access to it
+    async fn get_zone(&self, name: &str) -> Result<Option<Zone>, Error> {
+        let zones: Vec<Zone> =
+            Self::read(self.request(Method::GET, format!("zones?name={}", name).as_str())).await?;
+        Ok(zones
+            .into_iter()
+            .find(|zone| zone.name.eq_ignore_ascii_case(name)))
+    }
+
+    /// Get the records of a type with a fully qualified name
+    async fn get_records(
+        &self,
+        zone: &Zone,
+        fqdn: &str,
+        rtype: &str,
+    ) -> Result<Vec<CloudflareRecord>, Error> {
+        Self::read(
+            self.request(
+                Method::GET,
+                format!(
+                    "zones/{}/dns_records?name={}&type={}",
+                    zone.id,
+                    fqdn.to_lowercase(),
+                    rtype
+                )
+                .as_str(),
+            ),
+        )
+        .await
+    }
+
+    /// Create a record holding the value
+    async fn create_record(
+        &self,
+        zone: &Zone,
+        fqdn: &str,
+        rtype: &str,
+        value: &RecordValue,
+        ttl: &u16,
+        dry_run: &bool,
+    ) -> Result<CloudflareRecord, Error> {
+        let body = RecordPostBody {
+            typ: rtype.to_string(),
+            name: fqdn.to_string(),
+            content: value.data.clone(),
+            ttl: u32::from(*ttl),
+            priority: value.priority,
+        };
+        if *dry_run {
+            info!("DRY RUN: Create record {} ({}) -> {}", fqdn, rtype, value);
+            Ok(CloudflareRecord {
+                id: "".to_string(),
+                typ: body.typ,
+                name: body.name,
+                content: body.content,
+                ttl: body.ttl,
+                proxied: false,
+                priority: body.priority,
+            })
+        } else {
+            Self::change(
+                self.request(
+                    Method::POST,
+                    format!("zones/{}/dns_records", zone.id).as_str(),
+                )
+                .json(&body),
+                Error::CreateDns,
+            )
+            .await
+        }
+    }
+
+    /// Update the value and TTL of an existing record
+    async fn update_record(
+        &self,
+        zone: &Zone,
+        record: &CloudflareRecord,
+        value: &RecordValue,
+        ttl: &u16,
+        dry_run: &bool,
+    ) -> Result<CloudflareRecord, Error> {
+        let body = RecordPatchBody {
+            content: value.data.clone(),
+            ttl: u32::from(*ttl),
+            priority: value.priority,
+        };
+        if *dry_run {
+            info!(
+                "DRY RUN: Updating record {} ({}) to {}",
+                record.name, record.typ, value
+            );
+            Ok(CloudflareRecord {
+                content: body.content,
+                ttl: body.ttl,
+                priority: body.priority.or(record.priority),
+                ..record.clone()
+            })
+        } else {
+            Self::change(
+                self.request(
+                    Method::PATCH,
+                    format!("zones/{}/dns_records/{}", zone.id, record.id).as_str(),
+                )
+                .json(&body),
+                Error::UpdateDns,
+            )
+            .await
+        }
+    }
+}
+
+/// Capture the reasons Cloudflare gave for rejecting a request
+async fn api_error(resp: Response) -> ApiError {
+    let status = resp.status();
+    let url = resp.url().to_string();
+    let request_id = resp
+        .headers()
+        .get("cf-ray")
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.to_string());
+    let errors = resp
+        .json::<CloudflareResp<serde_json::Value>>()
+        .await
+        .map(|body| body.errors)
+        .unwrap_or_default();
+    let message = if !errors.is_empty() {
+        Some(
+            errors
+                .iter()
+                .map(|e| e.message.as_str())
+                .collect::<Vec<_>>()
+                .join("; "),
+        )
+    } else if status == StatusCode::UNAUTHORIZED || status == StatusCode::FORBIDDEN {
+        Some("the Cloudflare API token was not accepted".to_string())
+    } else {
+        None
+    };
+    ApiError {
+        status: status.as_u16(),
+        id: errors.first().map(|e| e.code.to_string()),
+        message,
+        request_id,
+        url,
+    }
+}
+
+// every response
+
+#[derive(Deserialize, Debug)]
+struct CloudflareResp<T> {
+    #[serde(default)]
+    errors: Vec<CloudflareError>,
+    result: T,
+    result_info: Option<ResultInfo>,
+}
+
+#[derive(Deserialize, Debug)]
+struct CloudflareError {
+    code: u32,
+    message: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct ResultInfo {
+    page: u32,
+    total_pages: u32,
+}
+
+// /zones
+
+#[derive(Deserialize, Debug, Eq, PartialEq, Clone)]
+pub struct Zone {
+    /// Identifier of the zone.
+    pub id: String,
+    /// The domain name of the zone.
+    pub name: String,
+}
+
+// /zones/{zone_id}/dns_records
+
+#[derive(Deserialize, Debug, Eq, PartialEq, Clone)]
+pub struct CloudflareRecord {
+    /// Identifier of the record.
+    pub id: String,
+    /// Record type.
+    #[serde(rename = "type")]
+    pub typ: String,
+    /// Complete DNS record name, including the zone name, in Punycode.
+    pub name: String,
+    /// A valid IPv4 address for A records, IPv6 address for AAAA records, or the data of other
+    /// records.
+    pub content: String,
+    /// Time To Live (TTL) of the DNS record in seconds. Setting to 1 means 'automatic'.
+    pub ttl: u32,
+    /// Whether the record is receiving the performance and security benefits of Cloudflare.
+    #[serde(default)]
+    pub proxied: bool,
+    /// Required for MX, SRV and URI records; unused by other record types.
+    #[serde(default)]
+    pub priority: Option<u16>,
+}
+
+impl CloudflareRecord {
+    /// Whether the record already holds the value
+    pub fn holds(&self, value: &RecordValue) -> bool {
+        value.matches_data(&self.typ, &self.content)
+            && value.priority.is_none_or(|p| self.priority == Some(p))
+    }
+}
+
+#[derive(Serialize, Debug)]
+struct RecordPostBody {
+    #[serde(rename = "type")]
+    typ: String,
+    name: String,
+    content: String,
+    ttl: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    priority: Option<u16>,
+}
+
+#[derive(Serialize, Debug)]
+struct RecordPatchBody {
+    content: String,
+    ttl: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    priority: Option<u16>,
+}
+
+/// Zone and record objects shared by the tests of the Cloudflare client and provider
+#[cfg(test)]
+pub(crate) mod fixtures {
+    use crate::cloudflare::{CloudflareRecord, Zone};
+
+    pub const ZONE_ID: &str = "023e105f4ecef8ad9ca31a8372d0c353";
+    pub const RECORD_ID: &str = "372e67954025e0ba6aaa6d586b9e0b59";
+
+    /// The `example.com` zone
+    pub fn zone() -> Zone {
+        Zone {
+            id: ZONE_ID.to_string(),
+            name: "example.com".to_string(),
+        }
+    }
+
+    pub fn zone_json() -> serde_json::Value {
+        json!({
+            "id": ZONE_ID,
+            "name": "example.com",
+            "status": "active"
+        })
+    }
+
+    /// The `home.example.com` A record
+    pub fn record(content: &str) -> CloudflareRecord {
+        CloudflareRecord {
+            id: RECORD_ID.to_string(),
+            typ: "A".to_string(),
+            name: "home.example.com".to_string(),
+            content: content.to_string(),
+            ttl: 60,
+            proxied: false,
+            priority: None,
+        }
+    }
+
+    pub fn record_json(content: &str) -> serde_json::Value {
+        json!({
+            "id": RECORD_ID,
+            "zone_id": ZONE_ID,
+            "name": "home.example.com",
+            "type": "A",
+            "content": content,
+            "proxied": false,
+            "ttl": 60,
+            "locked": false
+        })
+    }
+
+    /// The body of a successful response carrying `result`
+    pub fn response(result: serde_json::Value) -> String {
+        serde_json::to_string(&json!({
+            "success": true,
+            "errors": [],
+            "result": result
+        }))
+        .unwrap()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::cloudflare::fixtures::{
+        record, record_json, response, zone, zone_json, RECORD_ID, ZONE_ID,
+    };
+    use crate::cloudflare::{CloudflareDnsClient, CloudflareDnsClientImpl};
+    use crate::digitalocean::dns::RecordValue;
+    use crate::digitalocean::error::{Error, Failure};
+    use reqwest::Method;
+
+    fn value(data: &str) -> RecordValue {
+        RecordValue {
+            data: data.to_string(),
+            priority: None,
+            port: None,
+            weight: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_get_zones() {
+        let mut server = mockito::Server::new_async().await;
+        let mut pages = Vec::new();
+        for (page, name) in [(1, "example.com"), (2, "example.org")] {
+            pages.push(
+                server
+                    .mock(
+                        "GET",
+                        format!("/client/v4/zones?page={page}&per_page=50").as_str(),
+                    )
+                    .match_header("Authorization", "Bearer foo")
+                    .with_status(200)
+                    .with_header("Content-Type", "application/json")
+                    .with_body(
+                        serde_json::to_string(&json!({
+                            "success": true,
+                            "errors": [],
+                            "messages": [],
+                            "result": [{"id": format!("zone-{page}"), "name": name}],
+                            "result_info": {
+                                "page": page,
+                                "per_page": 50,
+                                "count": 1,
+                                "total_count": 2,
+                                "total_pages": 2
+                            }
+                        }))
+                        .unwrap(),
+                    )
+                    .create_async()
+                    .await,
+            );
+        }
+
+        let resp = CloudflareDnsClientImpl::new_for_test("foo".to_string(), server.url())
+            .get_zones()
+            .await;
+        assert_eq!(
+            Ok(vec!["example.com".to_string(), "example.org".to_string()]),
+            resp.map(|zones| zones.into_iter().map(|z| z.name).collect::<Vec<_>>())
+        );
+        for m in pages {
+            m.assert_async().await;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_get_zone() {
+        let mut server = mockito::Server::new_async().await;
+        let _m = server
+            .mock("GET", "/client/v4/zones?name=example.com")
+            .match_header("Authorization", "Bearer foo")
+            .with_status(200)
+            .with_header("Content-Type", "application/json")
+            .with_body(response(json!([zone_json()])))
+            .create_async()
+            .await;
+
+        let resp = CloudflareDnsClientImpl::new_for_test("foo".to_string(), server.url())
+            .get_zone("example.com")
+            .await;
+        assert_eq!(Ok(Some(zone())), resp);
+        _m.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_get_records() {
+        let mut server = mockito::Server::new_async().await;
+        let _m = server
+            .mock(
+                "GET",
+                format!("/client/v4/zones/{ZONE_ID}/dns_records?name=home.example.com&type=A")
+                    .as_str(),
+            )
+            .match_header("Authorization", "Bearer foo")
+            .with_status(200)
+            .with_header("Content-Type", "application/json")
+            .with_body(response(json!([record_json("1.2.3.4")])))
+            .create_async()
+            .await;
+
+        let resp = CloudflareDnsClientImpl::new_for_test("foo".to_string(), server.url())
+            .get_records(&zone(), "Home.example.com", "A")
+            .await;
+        assert_eq!(Ok(vec![record("1.2.3.4")]), resp);
+        _m.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_update_record() {
+        let mut server = mockito::Server::new_async().await;
+        let _m = server
+            .mock(
+                "PATCH",
+                format!("/client/v4/zones/{ZONE_ID}/dns_records/{RECORD_ID}").as_str(),
+            )
+            .match_header("Authorization", "Bearer foo")
+            .match_body(mockito::Matcher::Json(json!({
+                "content": "2.3.4.5",
+                "ttl": 60
+            })))
+            .with_status(200)
+            .with_header("Content-Type", "application/json")
+            .with_body(response(record_json("2.3.4.5")))
+            .create_async()
+            .await;
+
+        let resp = CloudflareDnsClientImpl::new_for_test("foo".to_string(), server.url())
+            .update_record(&zone(), &record("1.2.3.4"), &value("2.3.4.5"), &60, &false)
+            .await;
+        assert_eq!(Ok(record("2.3.4.5")), resp);
+        _m.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_create_record_rejected() {
+        let mut server = mockito::Server::new_async().await;
+        let _m = server
+            .mock(
+                "POST",
+                format!("/client/v4/zones/{ZONE_ID}/dns_records").as_str(),
+            )
+            .match_body(mockito::Matcher::Json(json!({
+                "type": "A",
+                "name": "home.example.com",
+                "content": "1.2.3.4",
+                "ttl": 60
+            })))
+            .with_status(400)
+            .with_header("Content-Type", "application/json")
+            .with_body(
+                serde_json::to_string(&json!({
+                    "success": false,
+                    "errors": [{"code": 81057, "message": "Record already exists."}],
+                    "messages": [],
+                    "result": null
+                }))
+                .unwrap(),
+            )
+            .create_async()
+            .await;
+
+        let resp = CloudflareDnsClientImpl::new_for_test("foo".to_string(), server.url())
+            .create_record(
+                &zone(),
+                "home.example.com",
+                "A",
+                &value("1.2.3.4"),
+                &60,
+                &false,
+            )
+            .await;
+        match resp {
+            Err(Error::CreateDns(Failure::Rejected(e))) => {
+                assert_eq!(400, e.status);
+                assert_eq!(Some("81057".to_string()), e.id);
+                assert_eq!(Some("Record already exists.".to_string()), e.message);
+            }
+            other => panic!("Unexpected result {other:?}"),
+        }
+        _m.assert_async().await;
+    }
+
+    #[test]
+    fn test_token_is_redacted() {
+        let client = CloudflareDnsClientImpl::new_for_test(
+            "cf_secret".to_string(),
+            "http://127.0.0.1:1".to_string(),
+        );
+        let request = client.request(Method::GET, "zones");
+        assert!(!format!("{:?}", request).contains("cf_secret"));
+    }
+
+    #[test]
+    fn test_holds() {
+        assert!(record("1.2.3.4").holds(&value("1.2.3.4")));
+        assert!(!record("1.2.3.4").holds(&value("2.3.4.5")));
+        assert!(!record("1.2.3.4").holds(&RecordValue {
+            priority: Some(10),
+            ..value("1.2.3.4")
+        }));
+    }
+}
@@ -0,0 +1,713 @@
+use std::fs;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+
+use clap::parser::ValueSource;
+use clap::ArgMatches;
+use serde::{Deserialize, Serialize};
+use serde_yaml::Value;
+
+use crate::cli::SubcmdArgs;
+use crate::email::EmailOptions;
+use crate::ip_retriever::IpProvider;
+use crate::logging::LogFormat;
+use crate::secret::Secret;
+
+/// Settings that can be given in a config file as well as on the command line.  Values on the
+/// command line override the config file, which overrides environment variables.
+#[derive(Deserialize, Serialize, Debug, Default, Clone, Eq, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token: Option<Secret>,
+    /// File holding the API token, read instead of `token`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token_file: Option<PathBuf>,
+    /// API token for DNS targets hosted by Cloudflare
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cloudflare_token: Option<Secret>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ip: Option<IpAddr>,
+    pub local: bool,
+    pub router: bool,
+    /// Local network interface whose address to publish
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub interface: Option<String>,
+    /// Services to ask for the external IP address, in order
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ip_provider: Option<Vec<IpProvider>>,
+    pub verbose: bool,
+    pub quiet: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub log_file: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub log_format: Option<LogFormat>,
+    pub dry_run: bool,
+    pub check: bool,
+    pub detailed_exitcode: bool,
+    pub verify: bool,
+    pub stats_json: bool,
+    pub profile: bool,
+    pub daemon: bool,
+    pub service: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub interval: Option<u64>,
+    /// Checks in a row that must detect a new address before it is published
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stabilize_checks: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_pages: Option<u32>,
+    /// How many objects to request in each page of a listing
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub per_page: Option<u32>,
+    /// How many pages of a listing to fetch at once
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub page_concurrency: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_attempts: Option<u32>,
+    /// Milliseconds to wait before the first retry of a failed API request
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retry_backoff: Option<u64>,
+    /// Seconds to wait for the response to each HTTP request
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub proxy: Option<String>,
+    pub trace_http: bool,
+    /// Seconds a run may take before it is abandoned
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deadline: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ip_out: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub state_file: Option<PathBuf>,
+    /// Where to keep a log of every change of the detected address and every update applied
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub history_file: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lock_file: Option<PathBuf>,
+    /// Seconds to wait for another run to release the lock file
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lock_wait: Option<u64>,
+    /// Where to cache the listings used to resolve names to IDs between runs
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cache_file: Option<PathBuf>,
+    /// Seconds a cached listing is used before it is fetched again
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cache_ttl: Option<u64>,
+    pub no_cache: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub heartbeat_url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pre_hook: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub post_hook: Option<String>,
+    /// Where to send email notifications, which can only be set in the config file
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub email: Option<EmailOptions>,
+    /// DNS records and firewall rules to update when no subcommand is given
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub targets: Vec<SubcmdArgs>,
+    /// Groups of targets that `--daemon` updates on their own schedule, alongside `targets`
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub jobs: Vec<JobConfig>,
+}
+
+/// Targets that `--daemon` checks on their own schedule, as given in the `jobs` list of the config
+/// file.  Settings that are not given are taken from the rest of the config file.
+#[derive(Deserialize, Serialize, Debug, Clone, Eq, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct JobConfig {
+    /// The name of the job in the log
+    pub name: String,
+    /// Seconds between checks, instead of `interval`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub interval: Option<u64>,
+    /// When to check, as a cron expression in UTC, instead of every `interval`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cron: Option<String>,
+    /// The longest random delay in seconds added to each check, instead of a tenth of the interval
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub jitter: Option<u64>,
+    /// API token of the account holding the targets, instead of `token`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token: Option<Secret>,
+    /// File holding the API token of the account holding the targets, read instead of `token`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token_file: Option<PathBuf>,
+    pub targets: Vec<SubcmdArgs>,
+}
+
+impl Config {
+    /// Read settings from a YAML config file
+    pub fn load(path: &Path) -> Result<Config, String> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| format!("unable to read {}: {}", path.display(), e))?;
+        serde_yaml::from_str(&contents)
+            .map_err(|e| format!("unable to parse {}: {}", path.display(), e))
+    }
+
+    /// Combine the settings from the config file with the parsed command line, preferring values
+    /// given on the command line, then values from the config file, then values from environment
+    /// variables and defaults.
+    pub fn merge(self, matches: &ArgMatches) -> Config {
+        let (ip, local, router, interface) = if ["ip", "local", "router", "interface"]
+            .iter()
+            .any(|id| from_command_line(matches, id))
+        {
+            (
+                matches.get_one::<IpAddr>("ip").copied(),
+                matches.get_flag("local"),
+                matches.get_flag("router"),
+                matches.get_one::<String>("interface").cloned(),
+            )
+        } else {
+            (self.ip, self.local, self.router, self.interface)
+        };
+        // a token given one way replaces a token file given by a source of lower precedence, and
+        // the other way around
+        let (token, token_file) = if ["token", "token_file"]
+            .iter()
+            .any(|id| from_command_line(matches, id))
+        {
+            (
+                matches
+                    .get_one::<Secret>("token")
+                    .filter(|_| from_command_line(matches, "token"))
+                    .cloned(),
+                matches
+                    .get_one::<PathBuf>("token_file")
+                    .filter(|_| from_command_line(matches, "token_file"))
+                    .cloned(),
+            )
+        } else if self.token.is_some() || self.token_file.is_some() {
+            (self.token, self.token_file)
+        } else {
+            (
+                matches.get_one::<Secret>("token").cloned(),
+                matches.get_one::<PathBuf>("token_file").cloned(),
+            )
+        };
+
+        Config {
+            token,
+            token_file,
+            cloudflare_token: merged(matches, "cloudflare_token", self.cloudflare_token),
+            ip,
+            local,
+            router,
+            interface,
+            ip_provider: merged_many(matches, "ip_provider", self.ip_provider),
+            verbose: self.verbose || matches.get_flag("verbose"),
+            quiet: self.quiet || matches.get_flag("quiet"),
+            log_file: merged(matches, "log_file", self.log_file),
+            log_format: merged(matches, "log_format", self.log_format),
+            dry_run: self.dry_run || matches.get_flag("dry_run"),
+            check: self.check || matches.get_flag("check"),
+            detailed_exitcode: self.detailed_exitcode || matches.get_flag("detailed_exitcode"),
+            verify: self.verify || matches.get_flag("verify"),
+            stats_json: self.stats_json || matches.get_flag("stats_json"),
+            profile: self.profile || matches.get_flag("profile"),
+            daemon: self.daemon || matches.get_flag("daemon"),
+            service: self.service || matches.get_flag("service"),
+            interval: merged(matches, "interval", self.interval),
+            stabilize_checks: merged(matches, "stabilize_checks", self.stabilize_checks),
+            max_pages: merged(matches, "max_pages", self.max_pages),
+            per_page: merged(matches, "per_page", self.per_page),
+            page_concurrency: merged(matches, "page_concurrency", self.page_concurrency),
+            max_attempts: merged(matches, "max_attempts", self.max_attempts),
+            retry_backoff: merged(matches, "retry_backoff", self.retry_backoff),
+            timeout: merged(matches, "timeout", self.timeout),
+            proxy: merged(matches, "proxy", self.proxy),
+            trace_http: self.trace_http || matches.get_flag("trace_http"),
+            deadline: merged(matches, "deadline", self.deadline),
+            ip_out: merged(matches, "ip_out", self.ip_out),
+            state_file: merged(matches, "state_file", self.state_file),
+            history_file: merged(matches, "history_file", self.history_file),
+            lock_file: merged(matches, "lock_file", self.lock_file),
+            lock_wait: merged(matches, "lock_wait", self.lock_wait),
+            cache_file: merged(matches, "cache_file", self.cache_file),
+            cache_ttl: merged(matches, "cache_ttl", self.cache_ttl),
+            no_cache: self.no_cache || matches.get_flag("no_cache"),
+            heartbeat_url: merged(matches, "heartbeat_url", self.heartbeat_url),
+            pre_hook: merged(matches, "pre_hook", self.pre_hook),
+            post_hook: merged(matches, "post_hook", self.post_hook),
+            email: self.email,
+            targets: self.targets,
+            jobs: self.jobs,
+        }
+    }
+}
+
+/// A starter config file holding the settings of `config` that differ from `defaults`, and its
+/// targets without the settings that are off.  The tokens and the email password are never
+/// written; comments say where to give them instead.
+pub fn starter(config: &Config, defaults: &Config) -> String {
+    let mut starter = String::from("# Starter config file, to be given with --config\n");
+    if config.token_file.is_none() {
+        starter +=
+            "# The API token is not written here: set DIGITAL_OCEAN_TOKEN, or token_file to \
+            read it from a file\n";
+    }
+    if config.cloudflare_token.is_some() {
+        starter += "# The Cloudflare API token is not written here: set CLOUDFLARE_API_TOKEN\n";
+    }
+    if config
+        .email
+        .as_ref()
+        .is_some_and(|email| email.password.is_some())
+    {
+        starter += "# The email password is not written here: add it to the email settings\n";
+    }
+    if config.jobs.iter().any(|job| job.token.is_some()) {
+        starter += "# The API tokens of the jobs are not written here: set token_file for each \
+            job instead\n";
+    }
+
+    let config = Config {
+        token: None,
+        cloudflare_token: None,
+        email: config.email.clone().map(|email| EmailOptions {
+            password: None,
+            ..email
+        }),
+        jobs: config
+            .jobs
+            .iter()
+            .map(|job| JobConfig {
+                token: None,
+                ..job.clone()
+            })
+            .collect(),
+        ..config.clone()
+    };
+    let to_mapping = |config: &Config| match serde_yaml::to_value(config) {
+        Ok(Value::Mapping(mapping)) => mapping,
+        _ => unreachable!("the settings serialize to a mapping"),
+    };
+    let defaults = to_mapping(defaults);
+    let mut settings = to_mapping(&config);
+    settings.retain(|key, value| defaults.get(key) != Some(value));
+    // the targets are always written, as a place to add more
+    let targets = settings
+        .entry("targets".into())
+        .or_insert_with(|| Value::Sequence(Vec::new()));
+    if let Value::Sequence(targets) = targets {
+        for target in targets.iter_mut().filter_map(Value::as_mapping_mut) {
+            target.retain(|_, value| {
+                value != &false && !value.as_sequence().is_some_and(Vec::is_empty)
+            });
+        }
+    }
+    starter + &serde_yaml::to_string(&settings).expect("the settings serialize to YAML")
+}
+
+/// Read an API token from a file, such as a root-only file or a credential passed in by systemd's
+/// `LoadCredential`.  Surrounding whitespace such as a trailing newline is ignored.  The contents
+/// of the file are never included in the error.
+pub fn read_token_file(path: &Path) -> Result<Secret, String> {
+    let contents = fs::read_to_string(path)
+        .map_err(|e| format!("unable to read token file {}: {}", path.display(), e))?;
+    let token = contents.trim();
+    if token.is_empty() {
+        Err(format!("token file {} is empty", path.display()))
+    } else if token.chars().any(|c| c.is_whitespace() || c.is_control()) {
+        Err(format!(
+            "token file {} must hold only the API token on a single line",
+            path.display()
+        ))
+    } else {
+        Ok(Secret::new(token.to_string()))
+    }
+}
+
+fn from_command_line(matches: &ArgMatches, id: &str) -> bool {
+    matches.value_source(id) == Some(ValueSource::CommandLine)
+}
+
+fn merged<T: Clone + Send + Sync + 'static>(
+    matches: &ArgMatches,
+    id: &str,
+    from_file: Option<T>,
+) -> Option<T> {
+    if from_command_line(matches, id) {
+        matches.get_one::<T>(id).cloned()
+    } else {
+        from_file.or_else(|| matches.get_one::<T>(id).cloned())
+    }
+}
+
+/// Like `merged`, for settings that take a list of values
+fn merged_many<T: Clone + Send + Sync + 'static>(
+    matches: &ArgMatches,
+    id: &str,
+    from_file: Option<Vec<T>>,
+) -> Option<Vec<T>> {
+    let from_matches = || {
+        matches
+            .get_many::<T>(id)
+            .map(|values| values.cloned().collect())
+    };
+    if from_command_line(matches, id) {
+        from_matches()
+    } else {
+        from_file.or_else(from_matches)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+    use std::net::IpAddr;
+    use std::path::PathBuf;
+
+    use crate::cli::{command, Direction, DnsArgs, FirewallArgs, SubcmdArgs};
+    use crate::config::{read_token_file, starter, Config, JobConfig};
+    use crate::email::SmtpSecurity;
+    use crate::ip_retriever::IpProvider;
+    use crate::provider::Provider;
+    use crate::secret::Secret;
+
+    fn file_config() -> Config {
+        serde_yaml::from_str(
+            "token: from-file\n\
+             ip: 1.2.3.4\n\
+             max_pages: 5\n\
+             ip_provider: [opendns]\n\
+             verbose: true\n",
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_parse_config() {
+        assert_eq!(
+            Config {
+                token: Some(Secret::new("from-file".to_string())),
+                ip: Some("1.2.3.4".parse::<IpAddr>().unwrap()),
+                max_pages: Some(5),
+                ip_provider: Some(vec![IpProvider::OpenDns]),
+                verbose: true,
+                ..Default::default()
+            },
+            file_config()
+        );
+        assert!(serde_yaml::from_str::<Config>("unknown: 1\n").is_err());
+    }
+
+    #[test]
+    fn test_parse_targets() {
+        let config: Config = serde_yaml::from_str(
+            "targets:\n  \
+               - type: dns\n    \
+                 record: main\n    \
+                 domain: example.com\n  \
+               - type: firewall\n    \
+                 name: web\n    \
+                 direction: inbound\n    \
+                 port: \"22\"\n    \
+                 protocols: [tcp]\n",
+        )
+        .unwrap();
+        assert_eq!(
+            vec![
+                SubcmdArgs::Dns(DnsArgs {
+                    record: "main".to_string(),
+                    domain: Some("example.com".to_string()),
+                    rtype: "A".to_string(),
+                    data: None,
+                    from_droplet: None,
+                    from_load_balancer: None,
+                    priority: None,
+                    port: None,
+                    weight: None,
+                    ttl: 60,
+                    lower_ttl: None,
+                    aliases: vec![],
+                    with_www: false,
+                    check_delegation: false,
+                    force: false,
+                    create_if_missing: true,
+                    create_domain: false,
+                    all_domains: false,
+                    dual_stack: false,
+                    fqdn: false,
+                    audit_txt: false,
+                    provider: Provider::DigitalOcean,
+                }),
+                SubcmdArgs::Firewall(FirewallArgs {
+                    name: "web".to_string(),
+                    all_matching: false,
+                    direction: Direction::Inbound,
+                    port: "22".to_string(),
+                    protocols: vec!["tcp".to_string()],
+                    addresses: None,
+                    vpcs: None,
+                    droplets: None,
+                    droplet_tags: None,
+                    kubernetes_clusters: None,
+                    load_balancers: None,
+                    project: None,
+                    prune_dangling: false,
+                    dual_stack: false,
+                    ipv6_prefix: None,
+                    old_ips: None,
+                    retain_days: None,
+                    no_verify: false,
+                    atomic: false,
+                    rule_index: None,
+                    match_source_tag: None,
+                    tags: None,
+                    replace_tags: false,
+                }),
+            ],
+            config.targets
+        );
+        assert!(
+            serde_yaml::from_str::<Config>("targets:\n  - type: dns\n    rcord: main\n").is_err()
+        );
+
+        let config: Config = serde_yaml::from_str(
+            "targets:\n  \
+               - type: dns\n    \
+                 record: main\n    \
+                 domain: example.com\n    \
+                 provider: cloudflare\n",
+        )
+        .unwrap();
+        assert!(matches!(
+            config.targets[0],
+            SubcmdArgs::Dns(DnsArgs {
+                provider: Provider::Cloudflare,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_parse_jobs() {
+        let config: Config = serde_yaml::from_str(
+            "interval: 300\n\
+             jobs:\n  \
+               - name: dns\n    \
+                 interval: 60\n    \
+                 targets:\n      \
+                   - type: dns\n        \
+                     record: main\n        \
+                     domain: example.com\n  \
+               - name: firewall\n    \
+                 cron: \"*/15 * * * *\"\n    \
+                 jitter: 30\n    \
+                 token_file: /etc/dyn-dns/team-token\n    \
+                 targets:\n      \
+                   - type: firewall\n        \
+                     name: web\n        \
+                     direction: inbound\n        \
+                     port: \"22\"\n        \
+                     protocols: [tcp]\n",
+        )
+        .unwrap();
+        assert_eq!(2, config.jobs.len());
+        assert_eq!(
+            JobConfig {
+                name: "dns".to_string(),
+                interval: Some(60),
+                cron: None,
+                jitter: None,
+                token: None,
+                token_file: None,
+                targets: config.jobs[0].targets.clone(),
+            },
+            config.jobs[0]
+        );
+        assert_eq!(Some("*/15 * * * *".to_string()), config.jobs[1].cron);
+        assert_eq!(Some(30), config.jobs[1].jitter);
+        assert_eq!(
+            Some(PathBuf::from("/etc/dyn-dns/team-token")),
+            config.jobs[1].token_file
+        );
+        assert!(matches!(
+            config.jobs[1].targets[..],
+            [SubcmdArgs::Firewall(_)]
+        ));
+        assert!(serde_yaml::from_str::<Config>("jobs:\n  - name: dns\n    every: 5\n").is_err());
+    }
+
+    #[test]
+    fn test_merge_prefers_command_line() {
+        let matches = command()
+            .try_get_matches_from([
+                "dyn-dns",
+                "--max-pages",
+                "2",
+                "--max-attempts",
+                "5",
+                "--timeout",
+                "15",
+                "--local",
+                "ip",
+            ])
+            .unwrap();
+        let config = file_config().merge(&matches);
+        assert_eq!(Some(2), config.max_pages);
+        assert_eq!(Some(5), config.max_attempts);
+        assert_eq!(Some(15), config.timeout);
+        assert_eq!(None, config.deadline);
+        assert_eq!(None, config.ip);
+        assert!(config.local);
+        assert!(config.verbose);
+        assert_eq!(Some("from-file"), config.token.as_ref().map(Secret::expose));
+    }
+
+    #[test]
+    fn test_merge_interface_overrides_file_ip() {
+        let matches = command()
+            .try_get_matches_from(["dyn-dns", "--interface", "eth1", "ip"])
+            .unwrap();
+        let config = file_config().merge(&matches);
+        assert_eq!(None, config.ip);
+        assert!(!config.local);
+        assert!(!config.router);
+        assert_eq!(Some("eth1".to_string()), config.interface);
+    }
+
+    #[test]
+    fn test_merge_falls_back_to_file() {
+        let matches = command().try_get_matches_from(["dyn-dns", "ip"]).unwrap();
+        let config = file_config().merge(&matches);
+        assert_eq!(Some(5), config.max_pages);
+        assert_eq!(Some("1.2.3.4".parse::<IpAddr>().unwrap()), config.ip);
+        assert!(!config.local);
+        assert_eq!(Some(3), config.max_attempts);
+        assert_eq!(Some(500), config.retry_backoff);
+        assert_eq!(Some(vec![IpProvider::OpenDns]), config.ip_provider);
+    }
+
+    #[test]
+    fn test_print_config_redacts_tokens() {
+        let config = Config {
+            cloudflare_token: Some(Secret::new("cf-secret".to_string())),
+            ..file_config()
+        };
+        let printed = serde_yaml::to_string(&config).unwrap();
+        assert!(printed.contains("token: <redacted>"));
+        assert!(printed.contains("cloudflare_token: <redacted>"));
+        assert!(!printed.contains("from-file"));
+        assert!(!printed.contains("cf-secret"));
+        assert!(!format!("{:?}", config).contains("from-file"));
+    }
+
+    #[test]
+    fn test_starter() {
+        let defaults = Config::default().merge(
+            &command()
+                .try_get_matches_from(["dyn-dns", "config", "init"])
+                .unwrap(),
+        );
+        let config = Config {
+            token: Some(Secret::new("from-flag".to_string())),
+            daemon: true,
+            ..defaults.clone()
+        };
+        let written = starter(&config, &defaults);
+        assert!(!written.contains("from-flag"));
+        assert!(written.contains("DIGITAL_OCEAN_TOKEN"));
+        // only the settings that differ from the defaults are written
+        assert!(written.contains("daemon: true"));
+        assert!(!written.contains("interval"));
+
+        let config = Config {
+            token_file: Some(PathBuf::from("/etc/dyn-dns/token")),
+            ..file_config()
+        };
+        let written = starter(&config, &defaults);
+        assert!(!written.contains("DIGITAL_OCEAN_TOKEN"));
+        let parsed: Config = serde_yaml::from_str(&written).unwrap();
+        assert_eq!(
+            Config {
+                token: None,
+                ..config
+            },
+            parsed
+        );
+    }
+
+    #[test]
+    fn test_parse_email() {
+        let config: Config = serde_yaml::from_str(
+            "email:\n  server: smtp.example.com\n  username: me@example.com\n  \
+                password: hunter2\n  from: me@example.com\n  to: [you@example.com]\n",
+        )
+        .unwrap();
+        let matches = command().try_get_matches_from(["dyn-dns", "ip"]).unwrap();
+        let email = config.merge(&matches).email.unwrap();
+        assert_eq!(SmtpSecurity::Starttls, email.security);
+        assert!(email.on_change);
+        assert_eq!(3, email.after_failures);
+        assert_eq!(Some("hunter2"), email.password.as_ref().map(Secret::expose));
+
+        let printed = serde_yaml::to_string(&email).unwrap();
+        assert!(printed.contains("password: <redacted>"));
+        assert!(!printed.contains("hunter2"));
+    }
+
+    #[test]
+    fn test_merge_token_file() {
+        let matches = command()
+            .try_get_matches_from(["dyn-dns", "--token-file", "/run/token", "ip"])
+            .unwrap();
+        let config = file_config().merge(&matches);
+        assert_eq!(None, config.token);
+        assert_eq!(Some(PathBuf::from("/run/token")), config.token_file);
+
+        let file_config: Config = serde_yaml::from_str("token_file: /etc/token\n").unwrap();
+        let matches = command()
+            .try_get_matches_from(["dyn-dns", "--token", "abc", "ip"])
+            .unwrap();
+        let config = file_config.clone().merge(&matches);
+        assert_eq!(Some("abc"), config.token.as_ref().map(Secret::expose));
+        assert_eq!(None, config.token_file);
+
+        let matches = command().try_get_matches_from(["dyn-dns", "ip"]).unwrap();
+        let config = file_config.merge(&matches);
+        assert_eq!(Some(PathBuf::from("/etc/token")), config.token_file);
+    }
+
+    #[test]
+    fn test_read_token_file() {
+        let dir = std::env::temp_dir().join(format!("dyn-dns-token-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("token");
+
+        fs::write(&path, "dop_v1_abc123\n").unwrap();
+        assert_eq!("dop_v1_abc123", read_token_file(&path).unwrap().expose());
+
+        fs::write(&path, " \n").unwrap();
+        assert!(read_token_file(&path).unwrap_err().contains("is empty"));
+
+        fs::write(&path, "dop_v1_abc123\ndop_v1_def456\n").unwrap();
+        let e = read_token_file(&path).unwrap_err();
+        assert!(e.contains("single line"));
+        assert!(!e.contains("dop_v1"));
+
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(read_token_file(&path)
+            .unwrap_err()
+            .starts_with("unable to read token file"));
+    }
+
+    #[test]
+    fn test_merge_without_file_uses_defaults() {
+        let matches = command().try_get_matches_from(["dyn-dns", "ip"]).unwrap();
+        let config = Config::default().merge(&matches);
+        assert_eq!(Some(3), config.max_attempts);
+        assert_eq!(
+            Some(vec![
+                IpProvider::Ipify,
+                IpProvider::Icanhazip,
+                IpProvider::Cloudflare
+            ]),
+            config.ip_provider
+        );
+    }
+}
@@ -0,0 +1,461 @@
+use tracing::warn;
+
+use crate::cli::{SubcmdArgs, RECORD_TYPES};
+use crate::digitalocean::dns::DomainRecord;
+use crate::digitalocean::firewall::{Firewall, FirewallRuleTarget, ALL_PORTS};
+use crate::firewall_address;
+use crate::state::State;
+
+/// A target for each record name of the domain, holding the data the records have now.  Only the
+/// A and AAAA records of the `dynamic` names become a target publishing the detected address,
+/// updating both when the name has both.  A name with several records of a type is left out, as a
+/// target only updates a single record.
+pub fn record_targets(
+    domain: &str,
+    records: &[DomainRecord],
+    dynamic: &[String],
+) -> Vec<SubcmdArgs> {
+    let mut targets = Vec::new();
+    let mut names: Vec<&str> = Vec::new();
+    for record in records {
+        if !names.contains(&record.name.as_str()) {
+            names.push(&record.name);
+        }
+    }
+    for name in names {
+        let of_type = |typ: &str| -> Vec<&DomainRecord> {
+            records
+                .iter()
+                .filter(|r| r.name == name && r.typ.eq_ignore_ascii_case(typ))
+                .collect()
+        };
+        let is_dynamic = dynamic.iter().any(|d| d.eq_ignore_ascii_case(name));
+        let (a, aaaa) = (of_type("A"), of_type("AAAA"));
+        let ttl = a.iter().chain(&aaaa).map(|r| r.ttl).min();
+        match ttl {
+            Some(_) if is_dynamic && (a.len() > 1 || aaaa.len() > 1) => warn!(
+                "Skipping the A and AAAA records of {}, as a target only updates one record of \
+                    each type",
+                name
+            ),
+            Some(ttl) if is_dynamic => targets.push(SubcmdArgs::from_fields(vec![
+                ("type", "dns".into()),
+                ("record", name.into()),
+                ("domain", domain.into()),
+                ("rtype", if a.is_empty() { "AAAA" } else { "A" }.into()),
+                ("ttl", ttl.into()),
+                ("dual_stack", (!a.is_empty() && !aaaa.is_empty()).into()),
+            ])),
+            _ => {}
+        }
+        let static_types = RECORD_TYPES
+            .iter()
+            .filter(|t| !is_dynamic || !["A", "AAAA"].contains(t));
+        for typ in static_types {
+            match of_type(typ)[..] {
+                [] => {}
+                [record] => {
+                    let mut fields = vec![
+                        ("type", "dns".into()),
+                        ("record", name.into()),
+                        ("domain", domain.into()),
+                        ("rtype", (*typ).into()),
+                        ("data", record.data.as_str().into()),
+                        ("ttl", record.ttl.into()),
+                    ];
+                    for (key, value) in [
+                        ("priority", record.priority),
+                        ("port", record.port),
+                        ("weight", record.weight),
+                    ] {
+                        fields.extend(value.map(|value| (key, value.into())));
+                    }
+                    targets.push(SubcmdArgs::from_fields(fields));
+                }
+                ref several => warn!(
+                    "Skipping the {} {} records of {}, as a target only updates one record",
+                    several.len(),
+                    typ,
+                    name
+                ),
+            }
+        }
+    }
+    targets
+}
+
+/// The addresses that the targets of the state file have published to the named firewall, both
+/// their current addresses and those they have since retired
+pub fn published_addresses(state: &State, firewall: &str) -> Vec<String> {
+    state
+        .targets
+        .iter()
+        .filter_map(|published| match &published.target {
+            SubcmdArgs::Firewall(fw_args) if fw_args.name == firewall => Some((
+                fw_args.ipv6_prefix,
+                published
+                    .ips
+                    .iter()
+                    .chain(published.retired.iter().map(|r| &r.ip)),
+            )),
+            _ => None,
+        })
+        .flat_map(|(ipv6_prefix, ips)| ips.map(move |ip| firewall_address(ip, ipv6_prefix)))
+        .collect()
+}
+
+/// A target for the rules of the firewall for the selected `ports` allowing the same ports with
+/// the same addresses and tags, covering the protocols of those rules.  The `published` addresses
+/// are left out of the targets, as updating them publishes the detected address in their place.
+/// Droplets, load balancers, and Kubernetes clusters are referenced by ID in the rules but by name
+/// in targets, so they are left out with a warning.
+pub fn firewall_targets(
+    firewall: &Firewall,
+    ports: &[String],
+    published: &[String],
+) -> Vec<SubcmdArgs> {
+    let inbound = firewall.inbound_rules.iter().flatten().map(|rule| {
+        (
+            "inbound",
+            rule.protocol.as_str(),
+            rule.ports.as_str(),
+            &rule.sources,
+        )
+    });
+    let outbound = firewall.outbound_rules.iter().flatten().map(|rule| {
+        (
+            "outbound",
+            rule.protocol.as_str(),
+            rule.ports.as_str(),
+            &rule.destinations,
+        )
+    });
+    let selected = |rule_ports: &str| {
+        ports.iter().any(|port| match port.as_str() {
+            "all" => rule_ports == ALL_PORTS,
+            port => rule_ports == port,
+        })
+    };
+
+    // the targets are built in the order of their first rule
+    let mut groups: Vec<(&str, &str, &FirewallRuleTarget, Vec<&str>)> = Vec::new();
+    for (direction, protocol, ports, target) in inbound.chain(outbound) {
+        if !selected(ports) {
+            continue;
+        }
+        if [
+            target.droplet_ids.as_ref().map(Vec::len),
+            target.load_balancer_uids.as_ref().map(Vec::len),
+            target.kubernetes_ids.as_ref().map(Vec::len),
+        ]
+        .iter()
+        .any(|count| count.is_some_and(|count| count > 0))
+        {
+            warn!(
+                "The {} {} rule for ports {} of firewall {} references droplets, load balancers, \
+                    or Kubernetes clusters by ID, which are not imported",
+                direction, protocol, ports, firewall.name
+            );
+        }
+        let same_as =
+            |t: &FirewallRuleTarget| t.addresses == target.addresses && t.tags == target.tags;
+        match groups
+            .iter_mut()
+            .find(|(d, p, t, _)| *d == direction && *p == ports && same_as(t))
+        {
+            Some((_, _, _, protocols)) => protocols.push(protocol),
+            None => groups.push((direction, ports, target, vec![protocol])),
+        }
+    }
+
+    groups
+        .into_iter()
+        .map(|(direction, ports, target, protocols)| {
+            let port = if ports == ALL_PORTS { "all" } else { ports };
+            let mut fields = vec![
+                ("type", "firewall".into()),
+                ("name", firewall.name.as_str().into()),
+                ("direction", direction.into()),
+                ("port", port.into()),
+                ("protocols", protocols.into()),
+            ];
+            let addresses: Vec<String> = (target.addresses.iter().flatten())
+                .filter(|address| !published.contains(address))
+                .cloned()
+                .collect();
+            if !addresses.is_empty() {
+                fields.push(("addresses", addresses.into()));
+            }
+            if let Some(tags) = target.tags.clone().filter(|t| !t.is_empty()) {
+                fields.push(("droplet_tags", tags.into()));
+            }
+            SubcmdArgs::from_fields(fields)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::{IpAddr, Ipv4Addr};
+    use std::rc::Rc;
+
+    use crate::cli::{DnsArgs, FirewallArgs, SubcmdArgs};
+    use crate::config_import::{firewall_targets, published_addresses, record_targets};
+    use crate::digitalocean::dns::DomainRecord;
+    use crate::digitalocean::firewall::{
+        Firewall, FirewallInboundRule, FirewallOutboundRule, FirewallRuleTarget,
+    };
+    use crate::digitalocean::mock::MockDnsClient;
+    use crate::state::State;
+    use crate::{update_dns, DigitalOceanClient, UpdateOptions};
+
+    fn record(name: &str, typ: &str, data: &str) -> DomainRecord {
+        DomainRecord {
+            id: 1,
+            typ: typ.to_string(),
+            name: name.to_string(),
+            data: data.to_string(),
+            priority: None,
+            port: None,
+            ttl: 300,
+            weight: None,
+            flags: None,
+            tag: None,
+        }
+    }
+
+    fn target(addresses: &[&str]) -> FirewallRuleTarget {
+        FirewallRuleTarget {
+            addresses: Some(addresses.iter().map(|a| a.to_string()).collect()),
+            droplet_ids: None,
+            load_balancer_uids: None,
+            kubernetes_ids: None,
+            tags: None,
+        }
+    }
+
+    fn inbound(protocol: &str, ports: &str, addresses: &[&str]) -> FirewallInboundRule {
+        FirewallInboundRule {
+            protocol: protocol.to_string(),
+            ports: ports.to_string(),
+            sources: target(addresses),
+        }
+    }
+
+    #[test]
+    fn test_record_targets() {
+        let records = vec![
+            record("@", "NS", "ns1.digitalocean.com"),
+            record("home", "A", "1.2.3.4"),
+            record("home", "AAAA", "2001:db8::1"),
+            record("vpn", "AAAA", "2001:db8::2"),
+            record("www", "CNAME", "example.com."),
+            record("@", "TXT", "v=spf1 -all"),
+            record("@", "TXT", "google-site-verification=abc"),
+            DomainRecord {
+                priority: Some(10),
+                ..record("@", "MX", "mail.example.com.")
+            },
+        ];
+        let targets = record_targets("example.com", &records, &["Home".to_string()]);
+
+        let summary: Vec<(&str, &str, Option<&str>, bool)> = targets
+            .iter()
+            .map(|target| match target {
+                SubcmdArgs::Dns(DnsArgs {
+                    record,
+                    domain,
+                    rtype,
+                    data,
+                    ttl: 300,
+                    dual_stack,
+                    ..
+                }) if domain.as_deref() == Some("example.com") => (
+                    record.as_str(),
+                    rtype.as_str(),
+                    data.as_deref(),
+                    *dual_stack,
+                ),
+                _ => panic!("unexpected target {target:?}"),
+            })
+            .collect();
+        // the NS records and the several TXT records of the apex are left out, and only the
+        // records of the dynamic name publish the detected address
+        assert_eq!(
+            vec![
+                ("@", "MX", Some("mail.example.com."), false),
+                ("home", "A", None, true),
+                ("vpn", "AAAA", Some("2001:db8::2"), false),
+                ("www", "CNAME", Some("example.com."), false),
+            ],
+            summary
+        );
+        assert!(matches!(
+            &targets[0],
+            SubcmdArgs::Dns(DnsArgs {
+                priority: Some(10),
+                ..
+            })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_record_targets_unchanged() {
+        let records = vec![
+            DomainRecord {
+                id: 1,
+                ..record("@", "A", "1.2.3.4")
+            },
+            DomainRecord {
+                id: 2,
+                ..record("www", "A", "1.2.3.4")
+            },
+            DomainRecord {
+                id: 3,
+                ..record("mail", "A", "5.6.7.8")
+            },
+            DomainRecord {
+                id: 4,
+                ..record("pool", "A", "9.9.9.1")
+            },
+            DomainRecord {
+                id: 5,
+                ..record("pool", "A", "9.9.9.2")
+            },
+        ];
+        let mut dns = MockDnsClient::new().with_domain("example.com");
+        for record in &records {
+            dns = dns.with_record("example.com", record.clone());
+        }
+        let dns = Rc::new(dns);
+        let mut client =
+            DigitalOceanClient::new_for_test("foo".to_string(), "http://localhost".to_string());
+        client.dns = dns.clone();
+
+        // updating the imported targets to a new address leaves every record as it was
+        let targets = record_targets("example.com", &records, &[]);
+        assert_eq!(3, targets.len());
+        let detected: IpAddr = Ipv4Addr::new(8, 8, 8, 8).into();
+        for target in targets {
+            let SubcmdArgs::Dns(dns_args) = target else {
+                panic!("unexpected target {target:?}");
+            };
+            update_dns(&client, dns_args, &[detected], &UpdateOptions::default())
+                .await
+                .unwrap();
+        }
+        assert_eq!(records, dns.records("example.com"));
+    }
+
+    #[test]
+    fn test_firewall_targets() {
+        let firewall = Firewall {
+            id: "fw".to_string(),
+            status: "".to_string(),
+            created_at: "".to_string(),
+            pending_changes: vec![],
+            name: "home-fw".to_string(),
+            droplet_ids: None,
+            tags: None,
+            inbound_rules: Some(vec![
+                inbound("tcp", "22", &["1.2.3.4", "10.0.0.0/8"]),
+                inbound("udp", "22", &["1.2.3.4", "10.0.0.0/8"]),
+                inbound("tcp", "80", &["0.0.0.0/0", "::/0"]),
+                inbound("tcp", "443", &["5.6.7.8"]),
+                FirewallInboundRule {
+                    sources: FirewallRuleTarget {
+                        tags: Some(vec!["web".to_string()]),
+                        ..target(&["5.6.7.8"])
+                    },
+                    ..inbound("udp", "443", &[])
+                },
+            ]),
+            outbound_rules: Some(vec![FirewallOutboundRule {
+                protocol: "icmp".to_string(),
+                ports: "0".to_string(),
+                destinations: target(&["0.0.0.0/0"]),
+            }]),
+        };
+
+        let summary = |targets: &[SubcmdArgs]| -> Vec<String> {
+            targets
+                .iter()
+                .map(|target| match target {
+                    SubcmdArgs::Firewall(FirewallArgs {
+                        name,
+                        direction,
+                        port,
+                        protocols,
+                        addresses,
+                        ..
+                    }) if name == "home-fw" => format!(
+                        "{:?} {} {} {}",
+                        direction,
+                        port,
+                        protocols.join(","),
+                        addresses
+                            .iter()
+                            .flatten()
+                            .cloned()
+                            .collect::<Vec<_>>()
+                            .join(",")
+                    ),
+                    _ => panic!("unexpected target {target:?}"),
+                })
+                .collect()
+        };
+
+        // the rules differing only in their protocol share a target, and the published address is
+        // left for the update to replace
+        let targets = firewall_targets(
+            &firewall,
+            &["22".to_string(), "443".to_string()],
+            &["1.2.3.4".to_string()],
+        );
+        assert_eq!(
+            vec![
+                "Inbound 22 tcp,udp 10.0.0.0/8",
+                "Inbound 443 tcp 5.6.7.8",
+                "Inbound 443 udp 5.6.7.8",
+            ],
+            summary(&targets)
+        );
+        assert!(matches!(
+            &targets[2],
+            SubcmdArgs::Firewall(FirewallArgs { droplet_tags: Some(tags), .. }) if tags == &["web"]
+        ));
+        assert_eq!(
+            vec!["Outbound all icmp 0.0.0.0/0"],
+            summary(&firewall_targets(&firewall, &["all".to_string()], &[]))
+        );
+    }
+
+    #[test]
+    fn test_published_addresses() {
+        let target = |name: &str, ipv6_prefix: Option<u8>| {
+            let mut fields = vec![
+                ("type", "firewall".into()),
+                ("name", name.into()),
+                ("direction", "inbound".into()),
+                ("port", "22".into()),
+                ("protocols", vec!["tcp"].into()),
+                ("retain_days", 1.into()),
+            ];
+            fields.extend(ipv6_prefix.map(|prefix| ("ipv6_prefix", prefix.into())));
+            SubcmdArgs::from_fields(fields)
+        };
+        let mut state = State::default();
+        state.record(&target("home-fw", None), &["1.2.3.4".parse().unwrap()]);
+        state.record(&target("home-fw", None), &["5.6.7.8".parse().unwrap()]);
+        state.record(
+            &target("home-fw", Some(64)),
+            &["2001:db8::1".parse().unwrap()],
+        );
+        state.record(&target("office-fw", None), &["9.9.9.9".parse().unwrap()]);
+
+        assert_eq!(
+            vec!["5.6.7.8", "1.2.3.4", "2001:db8::/64"],
+            published_addresses(&state, "home-fw")
+        );
+    }
+}
@@ -0,0 +1,200 @@
+//! Cron expressions for scheduling the checks of a daemon job.  Only the five standard fields are
+//! supported, each a `*` or a comma-separated list of values and ranges with an optional `/step`,
+//! and the times are in UTC.
+
+use std::fmt::{Display, Formatter};
+
+use crate::audit::civil_from_days;
+
+/// How many days ahead to look for a time matching the expression, enough to reach any 29 February
+const MAX_DAYS_AHEAD: u64 = 4 * 366;
+
+/// A parsed cron expression, holding the values each field matches as bit sets
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Cron {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    /// Whether the day of the month is restricted, as a day then only has to match one of the
+    /// day of the month and the day of the week
+    days_restricted: bool,
+    weekdays_restricted: bool,
+}
+
+impl Cron {
+    /// Parse an expression of the form `minute hour day-of-month month day-of-week`
+    pub fn parse(expr: &str) -> Result<Cron, String> {
+        let fields: Vec<&str> = expr.split_whitespace().collect();
+        let [minutes, hours, days, months, weekdays] = fields[..] else {
+            return Err(format!(
+                "cron expression '{expr}' must have 5 fields: minute, hour, day of month, month, \
+                    and day of week"
+            ));
+        };
+        let invalid = |e: String| format!("invalid cron expression '{expr}': {e}");
+        // Sunday may be given as either 0 or 7
+        let weekday_bits = parse_field(weekdays, "day of week", 0, 7).map_err(invalid)?;
+        Ok(Cron {
+            minutes: parse_field(minutes, "minute", 0, 59).map_err(invalid)?,
+            hours: parse_field(hours, "hour", 0, 23).map_err(invalid)?,
+            days: parse_field(days, "day of month", 1, 31).map_err(invalid)?,
+            months: parse_field(months, "month", 1, 12).map_err(invalid)?,
+            weekdays: (weekday_bits | weekday_bits >> 7) & 0x7f,
+            days_restricted: days != "*",
+            weekdays_restricted: weekdays != "*",
+        })
+    }
+
+    /// The first time matching the expression after `after`, both in seconds since the Unix epoch,
+    /// or `None` if no day in the next four years matches
+    pub fn next_after(&self, after: u64) -> Option<u64> {
+        let first_day = after / 86400;
+        (first_day..first_day + MAX_DAYS_AHEAD)
+            .filter(|&day| self.matches_day(day))
+            .flat_map(|day| {
+                (0..24u64)
+                    .filter(|hour| self.hours & 1 << hour != 0)
+                    .flat_map(move |hour| {
+                        (0..60u64)
+                            .filter(|minute| self.minutes & 1 << minute != 0)
+                            .map(move |minute| day * 86400 + hour * 3600 + minute * 60)
+                    })
+            })
+            .find(|&time| time > after)
+    }
+
+    fn matches_day(&self, day: u64) -> bool {
+        let (_, month, day_of_month) = civil_from_days(day);
+        // 1970-01-01 was a Thursday
+        let weekday = (day + 4) % 7;
+        let matches_day = self.days & 1 << day_of_month != 0;
+        let matches_weekday = self.weekdays & 1 << weekday != 0;
+        self.months & 1 << month != 0
+            && match (self.days_restricted, self.weekdays_restricted) {
+                (true, true) => matches_day || matches_weekday,
+                _ => matches_day && matches_weekday,
+            }
+    }
+}
+
+impl Display for Cron {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let field = |bits: u64, min: u64, max: u64| {
+            if (min..=max).all(|value| bits & 1 << value != 0) {
+                return "*".to_string();
+            }
+            // consecutive values are shown as a range
+            let mut ranges: Vec<(u64, u64)> = Vec::new();
+            for value in (min..=max).filter(|value| bits & 1 << value != 0) {
+                match ranges.last_mut() {
+                    Some((_, last)) if *last + 1 == value => *last = value,
+                    _ => ranges.push((value, value)),
+                }
+            }
+            ranges
+                .iter()
+                .map(|&(first, last)| {
+                    if first == last {
+                        first.to_string()
+                    } else {
+                        format!("{first}-{last}")
+                    }
+                })
+                .collect::<Vec<_>>()
+                .join(",")
+        };
+        write!(
+            f,
+            "{} {} {} {} {}",
+            field(self.minutes, 0, 59),
+            field(self.hours, 0, 23),
+            field(self.days, 1, 31),
+            field(self.months, 1, 12),
+            field(self.weekdays, 0, 6)
+        )
+    }
+}
+
+/// The values a field matches, as a bit set
+fn parse_field(field: &str, name: &str, min: u64, max: u64) -> Result<u64, String> {
+    let parse = |value: &str| match value.parse::<u64>() {
+        Ok(value) if (min..=max).contains(&value) => Ok(value),
+        _ => Err(format!(
+            "{name} must be between {min} and {max}, not '{value}'"
+        )),
+    };
+    let mut bits = 0;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => match step.parse::<u64>() {
+                Ok(step) if step > 0 => (range, step),
+                _ => return Err(format!("invalid step '{step}' for the {name}")),
+            },
+            None => (part, 1),
+        };
+        let (first, last) = match range.split_once('-') {
+            _ if range == "*" => (min, max),
+            Some((first, last)) => (parse(first)?, parse(last)?),
+            // a single value with a step runs to the end of the range, as in other crons
+            None if step > 1 => (parse(range)?, max),
+            None => (parse(range)?, parse(range)?),
+        };
+        if first > last {
+            return Err(format!("invalid range '{range}' for the {name}"));
+        }
+        for value in (first..=last).step_by(step as usize) {
+            bits |= 1 << value;
+        }
+    }
+    Ok(bits)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::cron::Cron;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            "0,15,30,45 * * * *",
+            Cron::parse("*/15 * * * *").unwrap().to_string()
+        );
+        assert_eq!(
+            "30 9-17 * * 1-5",
+            Cron::parse("30 9-17 * * 1-5").unwrap().to_string()
+        );
+        assert_eq!("0 0 1 1 0", Cron::parse("0 0 1 1 7").unwrap().to_string());
+        assert!(Cron::parse("* * * *").is_err());
+        assert!(Cron::parse("60 * * * *").is_err());
+        assert!(Cron::parse("* * 0 * *").is_err());
+        assert!(Cron::parse("*/0 * * * *").is_err());
+        assert!(Cron::parse("5-1 * * * *").is_err());
+        assert!(Cron::parse("mon * * * *").is_err());
+    }
+
+    #[test]
+    fn test_next_after() {
+        // Wednesday 2024-05-01 12:34:56 UTC
+        let now = 1714566896;
+        let every_quarter = Cron::parse("*/15 * * * *").unwrap();
+        // 12:45:00
+        assert_eq!(Some(1714567500), every_quarter.next_after(now));
+        // a time that matches exactly is not repeated
+        assert_eq!(Some(1714568400), every_quarter.next_after(1714567500));
+
+        // the next weekday at 09:00 is Thursday 2024-05-02
+        let weekdays = Cron::parse("0 9 * * 1-5").unwrap();
+        assert_eq!(Some(1714640400), weekdays.next_after(now));
+
+        // with both days restricted either one matches: Sunday 2024-05-05 comes before the 15th
+        let either = Cron::parse("0 0 15 * 0").unwrap();
+        assert_eq!(Some(1714867200), either.next_after(now));
+
+        // 29 February is years away
+        let leap_day = Cron::parse("0 0 29 2 *").unwrap();
+        assert_eq!(Some(1835395200), leap_day.next_after(now));
+        assert_eq!(None, Cron::parse("0 0 31 2 *").unwrap().next_after(now));
+    }
+}
@@ -0,0 +1,387 @@
+use std::fmt::Display;
+use std::future::Future;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use tracing::{debug, error, info, warn};
+
+use crate::cron::Cron;
+use crate::service::ServiceNotifier;
+
+/// How often the daemon re-detects the IP address
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Schedule {
+    pub interval: Duration,
+    /// When to check, instead of every `interval`
+    pub cron: Option<Cron>,
+    /// The longest random delay added to each check, or a tenth of the interval if not given
+    pub jitter: Option<Duration>,
+    /// How many checks in a row must detect a new address before it is published
+    pub stabilize_checks: u32,
+    /// Update at every check, even while the address stays the same, so that the addresses that
+    /// targets retain are removed once they expire
+    pub update_unchanged: bool,
+}
+
+impl Schedule {
+    /// The delay before the next check: the interval, or the time until the cron expression next
+    /// matches, plus a random jitter so that many hosts started at the same time don't all call the
+    /// API at once.
+    fn next_delay(&self) -> Duration {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let delay = match self.cron.and_then(|cron| cron.next_after(now.as_secs())) {
+            Some(next) => Duration::from_secs(next).saturating_sub(now),
+            None => self.interval,
+        };
+        let max_jitter = self.jitter.unwrap_or(self.interval / 10).as_millis() as u64;
+        let jitter = if max_jitter == 0 {
+            0
+        } else {
+            let seed = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.subsec_nanos() as u64);
+            seed % max_jitter
+        };
+        delay + Duration::from_millis(jitter)
+    }
+
+    /// How often the checks run, for logging
+    fn describe(&self) -> String {
+        match self.cron {
+            Some(cron) => format!("at '{cron}' (UTC)"),
+            None => format!("every {}s", self.interval.as_secs()),
+        }
+    }
+}
+
+/// Exit code used when a second signal forces the process to exit (128 + SIGINT, as a shell would)
+const EXIT_FORCED: i32 = 130;
+
+/// Set a flag when SIGTERM or SIGINT (Ctrl-C on Windows) is received, instead of exiting, so that
+/// an API change in flight is completed rather than leaving a firewall rule with its old address
+/// removed but its new one not yet added.  A second signal exits immediately.
+pub fn shutdown_flag() -> Arc<AtomicBool> {
+    let flag = Arc::new(AtomicBool::new(false));
+    for signal in [signal_hook::consts::SIGTERM, signal_hook::consts::SIGINT] {
+        // registered first, so it only sees the flag once an earlier signal has set it
+        signal_hook::flag::register_conditional_shutdown(signal, EXIT_FORCED, Arc::clone(&flag))
+            .expect("Unable to register signal handler");
+        signal_hook::flag::register(signal, Arc::clone(&flag))
+            .expect("Unable to register signal handler");
+    }
+    flag
+}
+
+/// Repeatedly detect the IP address and call `update` whenever it differs from the last address
+/// that was successfully published, or at every check if the schedule updates unchanged addresses,
+/// until `shutdown` is set.  Once an address has been published,
+/// a new one is only published after `stabilize_checks` checks in a row have detected it.  Failed
+/// detections and updates are retried at the next check.  The service manager is kept informed
+/// through `notifier`.
+pub async fn run<D, DF, U, UF, E>(
+    schedule: Schedule,
+    shutdown: &AtomicBool,
+    notifier: &ServiceNotifier,
+    mut detect: D,
+    mut update: U,
+) where
+    D: FnMut() -> DF,
+    DF: Future<Output = Result<Vec<IpAddr>, String>>,
+    U: FnMut(Vec<IpAddr>) -> UF,
+    UF: Future<Output = Result<(), E>>,
+    E: Display,
+{
+    info!("Checking the IP address {}", schedule.describe());
+    notifier.ready();
+    let mut published: Option<Vec<IpAddr>> = None;
+    // a new address, and how many checks in a row have detected it
+    let mut pending: Option<(Vec<IpAddr>, u32)> = None;
+    while !shutdown.load(Ordering::SeqCst) {
+        match keeping_alive(detect(), notifier).await {
+            Ok(ips) if published.as_ref() == Some(&ips) => {
+                pending = None;
+                debug!("IP address unchanged ({:?})", ips);
+                if schedule.update_unchanged {
+                    if let Err(e) = keeping_alive(update(ips), notifier).await {
+                        notifier.status(&format!("Update failed: {e}"));
+                        error!("Update failed, will retry at the next check: {}", e)
+                    }
+                }
+            }
+            Ok(ips) => {
+                let seen = match pending {
+                    Some((ref pending_ips, seen)) if *pending_ips == ips => seen + 1,
+                    _ => 1,
+                };
+                pending = Some((ips.clone(), seen));
+                if published.is_some() && seen < schedule.stabilize_checks {
+                    info!(
+                        "IP address changed to {}, waiting for {} more check(s) to detect it \
+                            before publishing it",
+                        describe(&ips),
+                        schedule.stabilize_checks - seen
+                    );
+                } else {
+                    match keeping_alive(update(ips.clone()), notifier).await {
+                        Ok(()) => {
+                            notifier.status(&format!("Published {}", describe(&ips)));
+                            published = Some(ips);
+                            pending = None;
+                        }
+                        Err(e) => {
+                            notifier.status(&format!("Update failed: {e}"));
+                            error!("Update failed, will retry at the next check: {}", e)
+                        }
+                    }
+                }
+            }
+            Err(e) => warn!(
+                "Unable to detect IP address, will retry at the next check: {}",
+                e
+            ),
+        }
+        sleep_until_shutdown(schedule.next_delay(), shutdown, notifier).await;
+    }
+    info!("Received shutdown request, exiting");
+    notifier.stopping();
+}
+
+fn describe(ips: &[IpAddr]) -> String {
+    ips.iter()
+        .map(IpAddr::to_string)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Await the future, sending watchdog pings while it runs
+async fn keeping_alive<F: Future>(future: F, notifier: &ServiceNotifier) -> F::Output {
+    let mut future = std::pin::pin!(future);
+    loop {
+        tokio::select! {
+            output = &mut future => return output,
+            _ = tokio::time::sleep(Duration::from_millis(250)) => notifier.keep_alive(),
+        }
+    }
+}
+
+/// Sleep for the delay, waking early if `shutdown` is set
+async fn sleep_until_shutdown(delay: Duration, shutdown: &AtomicBool, notifier: &ServiceNotifier) {
+    let deadline = Instant::now() + delay;
+    while !shutdown.load(Ordering::SeqCst) {
+        notifier.keep_alive();
+        let now = Instant::now();
+        if now >= deadline {
+            break;
+        }
+        tokio::time::sleep((deadline - now).min(Duration::from_millis(250))).await;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::cell::RefCell;
+    use std::net::IpAddr;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::time::Duration;
+
+    use crate::cron::Cron;
+    use crate::daemon::{run, Schedule};
+    use crate::service::ServiceNotifier;
+
+    #[test]
+    fn test_next_delay() {
+        let schedule = Schedule {
+            interval: Duration::from_secs(100),
+            cron: None,
+            jitter: None,
+            stabilize_checks: 1,
+            update_unchanged: false,
+        };
+        for _ in 0..10 {
+            let delay = schedule.next_delay();
+            assert!(delay >= Duration::from_secs(100));
+            assert!(delay < Duration::from_secs(110));
+        }
+        let schedule = Schedule {
+            interval: Duration::ZERO,
+            cron: None,
+            jitter: None,
+            stabilize_checks: 1,
+            update_unchanged: false,
+        };
+        assert_eq!(Duration::ZERO, schedule.next_delay());
+
+        let schedule = Schedule {
+            interval: Duration::from_secs(100),
+            cron: None,
+            jitter: Some(Duration::from_secs(30)),
+            stabilize_checks: 1,
+            update_unchanged: false,
+        };
+        for _ in 0..10 {
+            let delay = schedule.next_delay();
+            assert!(delay >= Duration::from_secs(100));
+            assert!(delay < Duration::from_secs(130));
+        }
+
+        // a check every minute is at most a minute away, plus no jitter
+        let schedule = Schedule {
+            interval: Duration::from_secs(3600),
+            cron: Some(Cron::parse("* * * * *").unwrap()),
+            jitter: Some(Duration::ZERO),
+            stabilize_checks: 1,
+            update_unchanged: false,
+        };
+        assert!(schedule.next_delay() <= Duration::from_secs(60));
+    }
+
+    #[tokio::test]
+    async fn test_run_only_updates_on_change() {
+        let a: IpAddr = "1.1.1.1".parse().unwrap();
+        let b: IpAddr = "2.2.2.2".parse().unwrap();
+        let detections = RefCell::new(vec![
+            Ok(vec![a]),
+            Ok(vec![a]),
+            Err("offline".to_string()),
+            Ok(vec![b]),
+            Ok(vec![b]),
+            Ok(vec![b]),
+            Ok(vec![a]),
+        ]);
+        let shutdown = AtomicBool::new(false);
+        let updates = RefCell::new(vec![]);
+        let mut fail_next_update = false;
+
+        run(
+            Schedule {
+                interval: Duration::ZERO,
+                cron: None,
+                jitter: None,
+                stabilize_checks: 1,
+                update_unchanged: false,
+            },
+            &shutdown,
+            &ServiceNotifier::default(),
+            || {
+                let mut detections = detections.borrow_mut();
+                let next = detections.remove(0);
+                if detections.is_empty() {
+                    shutdown.store(true, Ordering::SeqCst);
+                }
+                async { next }
+            },
+            |ips: Vec<IpAddr>| {
+                // the first update of b fails and is retried at the next check
+                fail_next_update = !fail_next_update && ips == [b];
+                updates.borrow_mut().push(ips);
+                let result = if fail_next_update {
+                    Err("API unavailable")
+                } else {
+                    Ok(())
+                };
+                async move { result }
+            },
+        )
+        .await;
+
+        assert_eq!(
+            vec![vec![a], vec![b], vec![b], vec![a]],
+            updates.into_inner()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_run_updates_unchanged() {
+        let a: IpAddr = "1.1.1.1".parse().unwrap();
+        let detections = RefCell::new(vec![
+            Ok(vec![a]),
+            Ok(vec![a]),
+            Err("offline".to_string()),
+            Ok(vec![a]),
+        ]);
+        let shutdown = AtomicBool::new(false);
+        let updates = RefCell::new(vec![]);
+
+        run(
+            Schedule {
+                interval: Duration::ZERO,
+                cron: None,
+                jitter: None,
+                stabilize_checks: 3,
+                update_unchanged: true,
+            },
+            &shutdown,
+            &ServiceNotifier::default(),
+            || {
+                let mut detections = detections.borrow_mut();
+                let next = detections.remove(0);
+                if detections.is_empty() {
+                    shutdown.store(true, Ordering::SeqCst);
+                }
+                async { next }
+            },
+            |ips: Vec<IpAddr>| {
+                updates.borrow_mut().push(ips);
+                async { Ok::<(), String>(()) }
+            },
+        )
+        .await;
+
+        // every detection of the published address updates, without waiting for it to stabilize
+        assert_eq!(vec![vec![a], vec![a], vec![a]], updates.into_inner());
+    }
+
+    #[tokio::test]
+    async fn test_run_waits_for_address_to_stabilize() {
+        let a: IpAddr = "1.1.1.1".parse().unwrap();
+        let b: IpAddr = "2.2.2.2".parse().unwrap();
+        let c: IpAddr = "3.3.3.3".parse().unwrap();
+        let detections = RefCell::new(vec![
+            Ok(vec![a]),
+            // flapping between addresses never publishes either of them
+            Ok(vec![b]),
+            Ok(vec![c]),
+            Ok(vec![b]),
+            Ok(vec![a]),
+            // a failed detection does not reset the count
+            Ok(vec![c]),
+            Err("offline".to_string()),
+            Ok(vec![c]),
+            Ok(vec![c]),
+        ]);
+        let shutdown = AtomicBool::new(false);
+        let updates = RefCell::new(vec![]);
+
+        run(
+            Schedule {
+                interval: Duration::ZERO,
+                cron: None,
+                jitter: None,
+                stabilize_checks: 3,
+                update_unchanged: false,
+            },
+            &shutdown,
+            &ServiceNotifier::default(),
+            || {
+                let mut detections = detections.borrow_mut();
+                let next = detections.remove(0);
+                if detections.is_empty() {
+                    shutdown.store(true, Ordering::SeqCst);
+                }
+                async { next }
+            },
+            |ips: Vec<IpAddr>| {
+                updates.borrow_mut().push(ips);
+                async { Ok::<(), String>(()) }
+            },
+        )
+        .await;
+
+        // the first address is published at once
+        assert_eq!(vec![vec![a], vec![c]], updates.into_inner());
+    }
+}
@@ -0,0 +1,156 @@
+use reqwest::ClientBuilder;
+use serde::Deserialize;
+use tracing::{info, warn};
+
+/// The nameservers that serve domains hosted by DigitalOcean
+const DIGITALOCEAN_NAMESERVERS: [&str; 3] = [
+    "ns1.digitalocean.com",
+    "ns2.digitalocean.com",
+    "ns3.digitalocean.com",
+];
+
+/// The DNS-over-HTTPS JSON API used to look up the public delegation of a domain
+const DOH_URL: &str = "https://dns.google/resolve";
+
+/// Record type number of NS records
+const NS_RECORD_TYPE: u16 = 2;
+
+/// Warn if the public nameservers of the domain are not DigitalOcean's, in which case updates made
+/// through the API will not be visible to the rest of the internet.
+pub async fn warn_if_not_delegated(domain: &str) {
+    match get_nameservers(DOH_URL, domain).await {
+        Ok(nameservers) if is_delegated_to_digitalocean(&nameservers) => {
+            info!("Domain {} is delegated to DigitalOcean", domain)
+        }
+        Ok(nameservers) if nameservers.is_empty() => warn!(
+            "Domain {} has no public nameservers; updates will not take effect publicly",
+            domain
+        ),
+        Ok(nameservers) => warn!(
+            "Domain {} is delegated to {} rather than DigitalOcean; updates will not take effect \
+            publicly",
+            domain,
+            nameservers.join(", ")
+        ),
+        Err(e) => warn!(
+            "Unable to verify the delegation of domain {}: {}",
+            domain, e
+        ),
+    }
+}
+
+/// Look up the public nameservers of a domain, normalized to lowercase without a trailing dot
+async fn get_nameservers(base_url: &str, domain: &str) -> Result<Vec<String>, reqwest::Error> {
+    let client = ClientBuilder::default()
+        .build()
+        .expect("Unable to construct HTTP client");
+    let resp = client
+        .get(base_url)
+        .query(&[("name", domain), ("type", "NS")])
+        .header("Accept", "application/dns-json")
+        .send()
+        .await?
+        .error_for_status()?
+        .json::<DohResponse>()
+        .await?;
+    Ok(resp
+        .answer
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|a| a.typ == NS_RECORD_TYPE)
+        .map(|a| a.data.trim_end_matches('.').to_lowercase())
+        .collect())
+}
+
+fn is_delegated_to_digitalocean(nameservers: &[String]) -> bool {
+    !nameservers.is_empty()
+        && nameservers
+            .iter()
+            .all(|ns| DIGITALOCEAN_NAMESERVERS.contains(&ns.as_str()))
+}
+
+#[derive(Deserialize, Debug)]
+struct DohResponse {
+    #[serde(rename = "Answer")]
+    answer: Option<Vec<DohAnswer>>,
+}
+
+#[derive(Deserialize, Debug)]
+struct DohAnswer {
+    #[serde(rename = "type")]
+    typ: u16,
+    data: String,
+}
+
+#[cfg(test)]
+mod test {
+    use crate::delegation::{get_nameservers, is_delegated_to_digitalocean};
+
+    #[tokio::test]
+    async fn test_get_nameservers() {
+        let mut server = mockito::Server::new_async().await;
+        let _m = server
+            .mock("GET", "/resolve?name=example.com&type=NS")
+            .with_status(200)
+            .with_header("content-type", "application/dns-json")
+            .with_body(
+                json!({
+                    "Status": 0,
+                    "Answer": [
+                        {
+                            "name": "example.com.",
+                            "type": 2,
+                            "TTL": 1800,
+                            "data": "NS1.DigitalOcean.com."
+                        },
+                        {
+                            "name": "example.com.",
+                            "type": 2,
+                            "TTL": 1800,
+                            "data": "ns2.digitalocean.com."
+                        }
+                    ]
+                })
+                .to_string(),
+            )
+            .create_async()
+            .await;
+
+        let resp = get_nameservers(&format!("{}/resolve", server.url()), "example.com").await;
+        assert_eq!(
+            Ok(vec![
+                "ns1.digitalocean.com".to_string(),
+                "ns2.digitalocean.com".to_string()
+            ]),
+            resp.map_err(|e| e.to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_get_nameservers_no_answer() {
+        let mut server = mockito::Server::new_async().await;
+        let _m = server
+            .mock("GET", "/resolve?name=example.com&type=NS")
+            .with_status(200)
+            .with_header("content-type", "application/dns-json")
+            .with_body(json!({"Status": 3}).to_string())
+            .create_async()
+            .await;
+
+        let resp = get_nameservers(&format!("{}/resolve", server.url()), "example.com").await;
+        assert_eq!(Ok(vec![]), resp.map_err(|e| e.to_string()));
+    }
+
+    #[test]
+    fn test_is_delegated_to_digitalocean() {
+        assert!(is_delegated_to_digitalocean(&[
+            "ns1.digitalocean.com".to_string(),
+            "ns3.digitalocean.com".to_string()
+        ]));
+        assert!(!is_delegated_to_digitalocean(&[
+            "ns1.digitalocean.com".to_string(),
+            "ns1.example.net".to_string()
+        ]));
+        assert!(!is_delegated_to_digitalocean(&[]));
+    }
+}
@@ -0,0 +1,145 @@
+use async_trait::async_trait;
+
+use reqwest::Method;
+use serde::{Deserialize, Serialize};
+
+use crate::digitalocean::api::{lenient, DigitalOceanApiClient};
+use crate::digitalocean::error::Error;
+
+#[async_trait(?Send)]
+pub trait DigitalOceanAccountClient {
+    async fn get_account(&self) -> Result<Account, Error>;
+}
+
+pub struct DigitalOceanAccountClientImpl {
+    api: DigitalOceanApiClient,
+}
+
+impl DigitalOceanAccountClientImpl {
+    pub fn new(api: DigitalOceanApiClient) -> DigitalOceanAccountClientImpl {
+        DigitalOceanAccountClientImpl { api }
+    }
+}
+
+#[async_trait(?Send)]
+impl DigitalOceanAccountClient for DigitalOceanAccountClientImpl {
+    /// Get info on the account the API token belongs to.  This needs no scope beyond a valid
+    /// token, so it shows whether the token is accepted at all.
+    async fn get_account(&self) -> Result<Account, Error> {
+        let request = self
+            .api
+            .get_request_builder(Method::GET, self.api.get_url("/v2/account"));
+        Ok(self
+            .api
+            .send(request)
+            .await?
+            .error_for_status()?
+            .json::<AccountResp>()
+            .await?
+            .account)
+    }
+}
+
+// /v2/account
+
+#[derive(Deserialize, Debug)]
+struct AccountResp {
+    account: Account,
+}
+
+#[derive(Deserialize, Serialize, Debug, Eq, PartialEq, Clone)]
+pub struct Account {
+    /// The email address used to sign in to the account.
+    pub email: String,
+    /// The unique universal identifier of the account.
+    #[serde(default, deserialize_with = "lenient")]
+    pub uuid: String,
+    /// Whether the email address has been verified.
+    #[serde(default, deserialize_with = "lenient")]
+    pub email_verified: bool,
+    /// The status of the account. This can be "active", "warning", or "locked".
+    pub status: String,
+    /// A human-readable explanation of the account's status.
+    #[serde(default, deserialize_with = "lenient")]
+    pub status_message: String,
+}
+
+impl Account {
+    /// Whether the account is locked, in which case the API refuses to change its resources
+    pub fn is_locked(&self) -> bool {
+        self.status == "locked"
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::digitalocean::account::Account;
+    use crate::digitalocean::error::Error;
+    use crate::digitalocean::DigitalOceanClient;
+
+    #[tokio::test]
+    async fn test_get_account() {
+        let mut server = mockito::Server::new_async().await;
+        let _m = server
+            .mock("GET", "/v2/account")
+            .match_header("Authorization", "Bearer foo")
+            .with_status(200)
+            .with_header("Content-Type", "application/json")
+            .with_body(
+                serde_json::to_string(&json!({
+                    "account": {
+                        "droplet_limit": 25,
+                        "floating_ip_limit": 5,
+                        "email": "sammy@digitalocean.com",
+                        "name": "Sammy the Shark",
+                        "uuid": "b6fr89dbf6d9156cace5f3c78dc9851d957381ef",
+                        "email_verified": true,
+                        "status": "active",
+                        "status_message": " ",
+                        "team": {
+                            "uuid": "5df3e3004a17e242b7c20ca6c9fc25b701a47ece",
+                            "name": "My Team"
+                        }
+                    }
+                }))
+                .unwrap(),
+            )
+            .create_async()
+            .await;
+
+        let resp = DigitalOceanClient::new_for_test("foo".to_string(), server.url())
+            .account
+            .get_account()
+            .await;
+        assert_eq!(
+            Ok(Account {
+                email: "sammy@digitalocean.com".to_string(),
+                uuid: "b6fr89dbf6d9156cace5f3c78dc9851d957381ef".to_string(),
+                email_verified: true,
+                status: "active".to_string(),
+                status_message: " ".to_string(),
+            }),
+            resp
+        );
+        _m.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_get_account_invalid_token() {
+        let mut server = mockito::Server::new_async().await;
+        let _m = server
+            .mock("GET", "/v2/account")
+            .with_status(401)
+            .with_header("Content-Type", "application/json")
+            .with_body(r#"{"id":"Unauthorized","message":"Unable to authenticate you"}"#)
+            .create_async()
+            .await;
+
+        let resp = DigitalOceanClient::new_for_test("foo".to_string(), server.url())
+            .account
+            .get_account()
+            .await;
+        assert!(matches!(resp, Err(Error::Unauthorized(_))));
+        _m.assert_async().await;
+    }
+}
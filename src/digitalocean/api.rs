@@ -1,108 +1,443 @@
-use crate::digitalocean::error::Error;
-use reqwest::blocking::{ClientBuilder, RequestBuilder};
-use reqwest::Method;
+use crate::digitalocean::error::{is_retriable_status, ApiError, Error};
+use crate::http::HttpOptions;
+use crate::secret::Secret;
+use futures_util::{stream, StreamExt, TryStreamExt};
+use reqwest::{Client, Method, RequestBuilder, Response, StatusCode};
 use serde::de::DeserializeOwned;
-use serde::Deserialize;
+use serde::{Deserialize, Deserializer};
+use std::cell::Cell;
+use std::rc::Rc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tracing::{debug, warn};
 use url::Url;
 
 #[derive(Clone)]
 pub struct DigitalOceanApiClient {
+    http: Client,
     base_url: Url,
     force_https: bool,
-    token: String,
+    token: Secret,
+    max_pages: Option<u32>,
+    per_page: Option<u32>,
+    page_concurrency: usize,
+    retry: RetryPolicy,
+    trace_http: bool,
+    stats: Rc<ApiStats>,
+}
+
+/// How requests that fail with a transient error (a connection failure, timeout, 429, or 5xx) are
+/// retried.  The delay before each retry doubles, starting from `initial_backoff`, except that a
+/// 429 response waits as long as the API asks.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct RetryPolicy {
+    /// Total number of times a request is sent, including the first attempt
+    pub max_attempts: u32,
+    pub initial_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(500),
+        }
+    }
+}
+
+/// Longest time to wait for the response to a request unless another timeout is configured.  A
+/// request that times out is retried like one that failed to connect.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Number of objects requested in each page of a listing unless another size is configured.  This
+/// is the most the API will return in one page.
+pub const DEFAULT_PER_PAGE: u32 = 200;
+
+/// Longest time to wait between two attempts of the same request
+const MAX_RETRY_BACKOFF: Duration = Duration::from_secs(30);
+
+/// How many times a page of a listing that is still rate limited once `send` has given up on it is
+/// requested again before the listing fails
+const MAX_PAGE_RESUMES: u32 = 5;
+
+impl RetryPolicy {
+    /// The delay before sending the request again after the given (1-based) failed attempt
+    fn backoff(&self, attempt: u32) -> Duration {
+        self.initial_backoff
+            .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+            .min(MAX_RETRY_BACKOFF)
+    }
+}
+
+/// Counters of the API traffic made by every client sharing a `DigitalOceanApiClient`
+#[derive(Debug, Default)]
+pub struct ApiStats {
+    calls: Cell<u32>,
+    pages: Cell<u32>,
+    bytes: Cell<u64>,
+}
+
+impl ApiStats {
+    /// Number of requests sent to the API
+    pub fn calls(&self) -> u32 {
+        self.calls.get()
+    }
+
+    /// Number of pages of listings fetched
+    pub fn pages(&self) -> u32 {
+        self.pages.get()
+    }
+
+    /// Number of response body bytes received, as reported by the API in Content-Length
+    pub fn bytes(&self) -> u64 {
+        self.bytes.get()
+    }
+
+    fn record_call(&self, bytes: Option<u64>) {
+        self.calls.set(self.calls.get() + 1);
+        self.bytes.set(self.bytes.get() + bytes.unwrap_or(0));
+    }
+
+    fn record_page(&self) {
+        self.pages.set(self.pages.get() + 1);
+    }
 }
 
 impl DigitalOceanApiClient {
-    pub fn new(token: String) -> DigitalOceanApiClient {
+    pub fn new(token: Secret) -> DigitalOceanApiClient {
         DigitalOceanApiClient {
+            http: HttpOptions::default().client(DEFAULT_TIMEOUT),
             base_url: Url::parse("https://api.digitalocean.com").unwrap(),
             force_https: true,
             token,
+            max_pages: None,
+            per_page: Some(DEFAULT_PER_PAGE),
+            page_concurrency: 1,
+            retry: RetryPolicy::default(),
+            trace_http: false,
+            stats: Rc::new(ApiStats::default()),
+        }
+    }
+
+    /// Retry requests that fail with a transient error according to the policy
+    pub fn with_retry(self, retry: RetryPolicy) -> DigitalOceanApiClient {
+        DigitalOceanApiClient { retry, ..self }
+    }
+
+    /// Send requests with this HTTP client, which carries settings such as the request timeout
+    pub fn with_http_client(self, http: Client) -> DigitalOceanApiClient {
+        DigitalOceanApiClient { http, ..self }
+    }
+
+    /// Log the method, path, status, and latency of every request at debug level
+    pub fn with_trace_http(self, trace_http: bool) -> DigitalOceanApiClient {
+        DigitalOceanApiClient { trace_http, ..self }
+    }
+
+    /// Limit how many pages any listing will fetch before failing
+    pub fn with_max_pages(self, max_pages: Option<u32>) -> DigitalOceanApiClient {
+        DigitalOceanApiClient { max_pages, ..self }
+    }
+
+    /// Request this many objects in each page of a listing, capped at the most the API allows, or
+    /// leave the page size to the API's default of 20
+    pub fn with_per_page(self, per_page: Option<u32>) -> DigitalOceanApiClient {
+        DigitalOceanApiClient {
+            per_page: per_page.map(|n| n.clamp(1, DEFAULT_PER_PAGE)),
+            ..self
+        }
+    }
+
+    /// Fetch up to this many pages of a listing at once once the first page shows how many there
+    /// are, rather than following the links from one page to the next
+    pub fn with_page_concurrency(self, page_concurrency: usize) -> DigitalOceanApiClient {
+        DigitalOceanApiClient {
+            page_concurrency: page_concurrency.max(1),
+            ..self
+        }
+    }
+
+    /// Fail if fetching another page of a listing would exceed the page limit
+    fn check_page_limit(&self, pages: u32, kind: &str) -> Result<(), Error> {
+        match self.max_pages {
+            Some(max_pages) if pages >= max_pages => Err(Error::PageLimit(format!(
+                "Listing {kind} exceeded the limit of {max_pages} page(s)"
+            ))),
+            _ => Ok(()),
         }
     }
 
+    /// The URL of the first page of a listing, asking for the configured page size.  The links to
+    /// later pages carry the page size along.
+    fn first_page_url(&self, url: String) -> String {
+        match self.per_page {
+            Some(per_page) => {
+                set_query_param(&url, "per_page", &per_page.to_string()).unwrap_or(url)
+            }
+            None => url,
+        }
+    }
+
+    /// The traffic counters shared by every clone of this client
+    pub fn stats(&self) -> &ApiStats {
+        &self.stats
+    }
+
     pub fn get_url(&self, endpoint: &str) -> String {
         self.base_url.join(endpoint).unwrap().to_string()
     }
 
+    /// Send a request, retrying it with exponential backoff while it fails with a transient error.
+    /// A response showing that the token was not accepted fails with `Error::Unauthorized`; any
+    /// other response to the last attempt is returned as-is, so callers still see the final error.
+    pub async fn send(&self, request: RequestBuilder) -> Result<Response, Error> {
+        let resp = self.send_with_retry(request).await?;
+        match resp.status() {
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => {
+                Err(Error::Unauthorized(ApiError::from_response(resp).await))
+            }
+            _ => Ok(resp),
+        }
+    }
+
+    /// Send a request, retrying it while it fails with a transient error.  A POST is only retried
+    /// when it cannot have been applied, that is when the connection failed or the API turned it
+    /// away with 429, since replaying one that timed out or failed with a 5xx after DigitalOcean
+    /// applied it would create a duplicate record or rule.
+    async fn send_with_retry(&self, request: RequestBuilder) -> Result<Response, reqwest::Error> {
+        let idempotent = request
+            .try_clone()
+            .and_then(|r| r.build().ok())
+            .is_some_and(|r| r.method() != Method::POST);
+        let mut attempt = 1;
+        loop {
+            // requests with a streaming body can't be cloned, so they are only sent once
+            let retry = match request.try_clone() {
+                Some(retry) if attempt < self.retry.max_attempts => retry,
+                _ => return self.send_once(request).await,
+            };
+            let (failure, delay) = match self.send_once(retry).await {
+                Ok(resp)
+                    if resp.status() == StatusCode::TOO_MANY_REQUESTS
+                        || (idempotent && is_retriable_status(resp.status().as_u16())) =>
+                {
+                    (
+                        format!("status {}", resp.status()),
+                        rate_limit_delay(&resp, now()).unwrap_or(self.retry.backoff(attempt)),
+                    )
+                }
+                Err(e) if e.is_connect() || (idempotent && e.is_timeout()) => {
+                    (e.to_string(), self.retry.backoff(attempt))
+                }
+                result => return result,
+            };
+            warn!(
+                "API request failed ({}); retrying in {}ms (attempt {}/{})",
+                failure,
+                delay.as_millis(),
+                attempt + 1,
+                self.retry.max_attempts
+            );
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+
+    /// Send a request once, then pause if the response shows that the rate limit is nearly
+    /// exhausted so that later requests in the run don't hit it.
+    async fn send_once(&self, request: RequestBuilder) -> Result<Response, reqwest::Error> {
+        let resp = if self.trace_http {
+            traced(request).await?
+        } else {
+            request.send().await?
+        };
+        self.stats.record_call(resp.content_length());
+        let header = |name: &str| {
+            resp.headers()
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.parse::<u64>().ok())
+        };
+        if let (Some(remaining), Some(reset)) =
+            (header("ratelimit-remaining"), header("ratelimit-reset"))
+        {
+            if let Some(delay) = throttle_delay(remaining, reset, now()) {
+                warn!(
+                    "Only {} API requests remain in the rate limit; waiting {}s",
+                    remaining,
+                    delay.as_secs()
+                );
+                tokio::time::sleep(delay).await;
+            }
+        }
+        Ok(resp)
+    }
+
     pub fn get_request_builder(&self, method: Method, url: String) -> RequestBuilder {
         let mut real_url = url;
         if self.force_https {
             real_url = real_url.replace("http://", "https://");
         }
 
-        ClientBuilder::new()
-            .build()
-            .unwrap()
+        self.http
             .request(method, real_url)
-            .header("Authorization", format!("Bearer {}", self.token))
+            .bearer_auth(self.token.expose())
     }
 
-    pub fn get_all_objects<R: DeserializeOwned, T, TE, LE>(
+    pub async fn get_all_objects<R: DeserializeOwned, T, TE, LE, ME>(
         &self,
         url: String,
         value_extractor: TE,
         link_extractor: LE,
+        meta_extractor: ME,
     ) -> Result<Vec<T>, Error>
     where
         TE: Fn(R) -> Vec<T>,
         LE: Fn(&R) -> Links,
+        ME: Fn(&R) -> &Meta,
     {
-        let mut url = url;
+        let kind = listing_kind(&url);
+        let mut url = self.first_page_url(url);
         let mut exit = false;
         let mut objects: Vec<T> = Vec::new();
+        let mut pages = 0;
+
+        let mut total = 0;
 
         while !exit {
             let resp = self
-                .get_request_builder(Method::GET, url.clone())
-                .send()?
-                .json::<R>()?;
+                .get_page::<R>(&url)
+                .await
+                .map_err(|e| listing_error(e, &kind, pages, objects.len(), total))?;
 
             let links = link_extractor(&resp);
-            objects.extend(value_extractor(resp).into_iter());
-            if links.pages.is_some() && links.pages.clone().unwrap().next.is_some() {
-                url = links.pages.unwrap().next.unwrap();
-            } else {
-                exit = true;
+            total = meta_extractor(&resp).total;
+            objects.extend(value_extractor(resp));
+            debug!("Fetched {}/{} {}", objects.len(), total, kind);
+            pages += 1;
+            self.stats.record_page();
+            match links.pages.and_then(|p| p.next) {
+                Some(next) => {
+                    self.check_page_limit(pages, &kind)?;
+                    if self.page_concurrency > 1 && pages == 1 && !objects.is_empty() {
+                        let last_page = (total as usize).div_ceil(objects.len()) as u32;
+                        if let Some(urls) = (2..=last_page)
+                            .map(|page| page_url(&next, page))
+                            .collect::<Option<Vec<String>>>()
+                        {
+                            self.check_page_limit(last_page - 1, &kind)?;
+                            let rest =
+                                self.get_pages(urls, &value_extractor).await.map_err(|e| {
+                                    listing_error(e, &kind, pages, objects.len(), total)
+                                })?;
+                            objects.extend(rest);
+                            debug!("Fetched {}/{} {}", objects.len(), total, kind);
+                            break;
+                        }
+                    }
+                    url = next
+                }
+                None => exit = true,
             }
         }
 
         Ok(objects)
     }
 
-    pub fn get_object_by_name<R: DeserializeOwned, T, TE, LE, NE>(
+    /// Fetch the pages of a listing at the given URLs, with up to `page_concurrency` requests in
+    /// flight, returning their objects in the order of the pages
+    async fn get_pages<R: DeserializeOwned, T, TE>(
+        &self,
+        urls: Vec<String>,
+        value_extractor: &TE,
+    ) -> Result<Vec<T>, Error>
+    where
+        TE: Fn(R) -> Vec<T>,
+    {
+        let pages: Vec<Vec<T>> = stream::iter(urls)
+            .map(|url| async move {
+                let resp = self.get_page::<R>(&url).await?;
+                self.stats.record_page();
+                Ok::<_, Error>(value_extractor(resp))
+            })
+            .buffered(self.page_concurrency)
+            .try_collect()
+            .await?;
+        Ok(pages.into_iter().flatten().collect())
+    }
+
+    /// Fetch one page of a listing.  A page that is still rate limited once `send` has given up
+    /// on it is requested again after the delay the API asks for, so that the listing resumes from
+    /// that page rather than failing part way.
+    async fn get_page<R: DeserializeOwned>(&self, url: &str) -> Result<R, Error> {
+        let mut resumes = 0;
+        loop {
+            let resp = self
+                .send(self.get_request_builder(Method::GET, url.to_string()))
+                .await?;
+            if resp.status() != StatusCode::TOO_MANY_REQUESTS || resumes == MAX_PAGE_RESUMES {
+                return Ok(resp.error_for_status()?.json::<R>().await?);
+            }
+            let delay = rate_limit_delay(&resp, now()).unwrap_or(self.retry.backoff(1));
+            resumes += 1;
+            warn!(
+                "Still rate limited by the API; resuming the listing in {}s (attempt {}/{})",
+                delay.as_secs(),
+                resumes,
+                MAX_PAGE_RESUMES
+            );
+            tokio::time::sleep(delay).await;
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub async fn get_object_by_name<R: DeserializeOwned, T, TE, LE, ME, NE>(
         &self,
         name: &str,
         url: String,
         value_extractor: TE,
         link_extractor: LE,
+        meta_extractor: ME,
         name_checker: NE,
     ) -> Result<Option<T>, Error>
     where
         TE: Fn(R) -> Vec<T>,
         LE: Fn(&R) -> Links,
+        ME: Fn(&R) -> &Meta,
         NE: Fn(&T, &str) -> bool,
     {
-        let mut url = url;
+        let kind = listing_kind(&url);
+        let mut url = self.first_page_url(url);
         let mut exit = false;
         let mut obj: Option<T> = None;
+        let mut searched = 0;
+        let mut pages = 0;
+
+        let mut total = 0;
 
         while !exit {
             let resp = self
-                .get_request_builder(Method::GET, url.clone())
-                .send()?
-                .json::<R>()?;
+                .get_page::<R>(&url)
+                .await
+                .map_err(|e| listing_error(e, &kind, pages, searched, total))?;
 
             let links = link_extractor(&resp);
-            obj = value_extractor(resp)
-                .into_iter()
-                .find(|v| name_checker(v, name));
+            total = meta_extractor(&resp).total;
+            let objects = value_extractor(resp);
+            searched += objects.len();
+            debug!("Searched {}/{} {} for {}", searched, total, kind, name);
+            obj = objects.into_iter().find(|v| name_checker(v, name));
+            pages += 1;
+            self.stats.record_page();
             if obj.is_some() {
                 exit = true;
-            } else if links.pages.is_some() && links.pages.clone().unwrap().next.is_some() {
-                url = links.pages.unwrap().next.unwrap();
             } else {
-                exit = true;
+                match links.pages.and_then(|p| p.next) {
+                    Some(next) => {
+                        self.check_page_limit(pages, &kind)?;
+                        url = next
+                    }
+                    None => exit = true,
+                }
             }
         }
 
@@ -112,9 +447,141 @@ impl DigitalOceanApiClient {
     #[cfg(test)]
     pub fn new_for_test(token: String, base_url: String) -> DigitalOceanApiClient {
         DigitalOceanApiClient {
+            http: HttpOptions::default().client(DEFAULT_TIMEOUT),
             base_url: Url::parse(base_url.as_str()).unwrap(),
             force_https: false,
-            token,
+            token: Secret::new(token),
+            max_pages: None,
+            // left to the API's default so that mocks can match listing URLs exactly
+            per_page: None,
+            page_concurrency: 1,
+            retry: RetryPolicy {
+                max_attempts: 3,
+                initial_backoff: Duration::ZERO,
+            },
+            trace_http: false,
+            stats: Rc::new(ApiStats::default()),
+        }
+    }
+}
+
+/// Send a request, logging its method, path, and outcome with how long it took
+async fn traced(request: RequestBuilder) -> Result<Response, reqwest::Error> {
+    let (client, request) = request.build_split();
+    let request = request?;
+    let method = request.method().clone();
+    let path = match request.url().query() {
+        Some(query) => format!("{}?{}", request.url().path(), query),
+        None => request.url().path().to_string(),
+    };
+    let started = Instant::now();
+    let result = client.execute(request).await;
+    let elapsed = started.elapsed().as_millis();
+    match result {
+        Ok(ref resp) => debug!("{} {} -> {} in {}ms", method, path, resp.status(), elapsed),
+        Err(ref e) => debug!("{} {} -> failed in {}ms: {}", method, path, elapsed, e),
+    }
+    result
+}
+
+/// Seconds since the Unix epoch
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+/// How long a rate limited response asks to wait before the next request: the seconds given by
+/// `Retry-After`, or else the time until `ratelimit-reset` (in seconds since the epoch)
+fn rate_limit_delay(resp: &Response, now: u64) -> Option<Duration> {
+    if resp.status() != StatusCode::TOO_MANY_REQUESTS {
+        return None;
+    }
+    let header = |name: &str| {
+        resp.headers()
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.trim().parse::<u64>().ok())
+    };
+    let secs = header("retry-after").or_else(|| header("ratelimit-reset")?.checked_sub(now))?;
+    Some(Duration::from_secs(secs).min(MAX_THROTTLE_DELAY))
+}
+
+/// Add what a listing had fetched to an error fetching one of its pages, unless it failed on the
+/// first page
+fn listing_error(e: Error, kind: &str, pages: u32, fetched: usize, total: u32) -> Error {
+    if pages == 0 {
+        e
+    } else {
+        Error::Listing {
+            kind: kind.to_string(),
+            pages,
+            fetched,
+            total,
+            source: Box::new(e),
+        }
+    }
+}
+
+/// Remaining request budget at or below which requests are delayed
+const RATE_LIMIT_LOW_WATERMARK: u64 = 10;
+/// Longest time to wait for the rate limit to recover before continuing anyway
+const MAX_THROTTLE_DELAY: Duration = Duration::from_secs(60);
+
+/// How long to wait before the next request, given the remaining request budget and the time (in
+/// seconds since the epoch) at which the oldest request stops counting against the limit.
+fn throttle_delay(remaining: u64, reset: u64, now: u64) -> Option<Duration> {
+    if remaining > RATE_LIMIT_LOW_WATERMARK || reset <= now {
+        None
+    } else {
+        Some(Duration::from_secs(reset - now).min(MAX_THROTTLE_DELAY))
+    }
+}
+
+/// The URL of a page of a listing, made from the URL of another of its pages
+fn page_url(other_page: &str, page: u32) -> Option<String> {
+    set_query_param(other_page, "page", &page.to_string())
+}
+
+/// The URL with the query parameter set to the value, replacing any value it already had
+fn set_query_param(url: &str, key: &str, value: &str) -> Option<String> {
+    let mut url = Url::parse(url).ok()?;
+    let query: Vec<(String, String)> = url
+        .query_pairs()
+        .filter(|(k, _)| k != key)
+        .map(|(k, v)| (k.into_owned(), v.into_owned()))
+        .collect();
+    url.query_pairs_mut()
+        .clear()
+        .extend_pairs(query)
+        .append_pair(key, value);
+    Some(url.to_string())
+}
+
+/// A human-readable name for the kind of objects returned by a listing URL, for progress messages
+fn listing_kind(url: &str) -> String {
+    Url::parse(url)
+        .ok()
+        .and_then(|u| u.path_segments()?.next_back().map(|s| s.replace('_', " ")))
+        .unwrap_or_else(|| "objects".to_string())
+}
+
+/// Deserialize a non-essential field of an API object, falling back to the field's default if it
+/// is null or has an unexpected shape rather than failing to deserialize the whole object.  Use
+/// together with `#[serde(default)]` so that missing fields are tolerated as well.
+pub fn lenient<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: DeserializeOwned + Default,
+{
+    let value = serde_json::Value::deserialize(deserializer)?;
+    match T::deserialize(&value) {
+        Ok(v) => Ok(v),
+        Err(e) => {
+            if !value.is_null() {
+                debug!("Ignoring unexpected value {}: {}", value, e);
+            }
+            Ok(T::default())
         }
     }
 }
@@ -148,3 +615,285 @@ pub struct ErrorResponse {
     pub message: String,
     pub request_id: Option<String>,
 }
+
+#[cfg(test)]
+mod test {
+    use crate::digitalocean::api::{
+        listing_kind, page_url, set_query_param, throttle_delay, DigitalOceanApiClient, RetryPolicy,
+    };
+    use crate::digitalocean::error::{ApiError, Error};
+    use mockito::Server;
+    use reqwest::Method;
+    use std::time::Duration;
+
+    #[test]
+    fn test_listing_kind() {
+        assert_eq!(
+            "droplets",
+            listing_kind("https://api.digitalocean.com/v2/droplets?page=2")
+        );
+        assert_eq!(
+            "load balancers",
+            listing_kind("https://api.digitalocean.com/v2/load_balancers")
+        );
+        assert_eq!(
+            "records",
+            listing_kind("https://api.digitalocean.com/v2/domains/example.com/records?type=A")
+        );
+        assert_eq!("objects", listing_kind("not a url"));
+    }
+
+    #[test]
+    fn test_page_url() {
+        assert_eq!(
+            Some("https://api.digitalocean.com/v2/droplets?per_page=20&page=5".to_string()),
+            page_url(
+                "https://api.digitalocean.com/v2/droplets?page=2&per_page=20",
+                5
+            )
+        );
+        assert_eq!(
+            Some("https://api.digitalocean.com/v2/droplets?page=3".to_string()),
+            page_url("https://api.digitalocean.com/v2/droplets", 3)
+        );
+        assert_eq!(None, page_url("not a url", 2));
+    }
+
+    #[test]
+    fn test_set_query_param() {
+        assert_eq!(
+            Some(
+                "https://api.digitalocean.com/v2/domains/example.com/records?type=A&per_page=200"
+                    .to_string()
+            ),
+            set_query_param(
+                "https://api.digitalocean.com/v2/domains/example.com/records?type=A",
+                "per_page",
+                "200"
+            )
+        );
+        assert_eq!(
+            Some("https://api.digitalocean.com/v2/droplets?page=2&per_page=50".to_string()),
+            set_query_param(
+                "https://api.digitalocean.com/v2/droplets?per_page=20&page=2",
+                "per_page",
+                "50"
+            )
+        );
+    }
+
+    #[test]
+    fn test_throttle_delay() {
+        assert_eq!(None, throttle_delay(4000, 1000, 900));
+        assert_eq!(None, throttle_delay(11, 1000, 900));
+        assert_eq!(Some(Duration::from_secs(30)), throttle_delay(10, 1000, 970));
+        assert_eq!(Some(Duration::from_secs(60)), throttle_delay(0, 1000, 900));
+        assert_eq!(None, throttle_delay(0, 1000, 1000));
+    }
+
+    #[test]
+    fn test_backoff() {
+        let policy = RetryPolicy {
+            max_attempts: 10,
+            initial_backoff: Duration::from_millis(500),
+        };
+        assert_eq!(Duration::from_millis(500), policy.backoff(1));
+        assert_eq!(Duration::from_millis(1000), policy.backoff(2));
+        assert_eq!(Duration::from_millis(4000), policy.backoff(4));
+        assert_eq!(Duration::from_secs(30), policy.backoff(8));
+        assert_eq!(Duration::from_secs(30), policy.backoff(100));
+    }
+
+    #[tokio::test]
+    async fn test_send_retries_transient_errors() {
+        let mut server = Server::new_async().await;
+        let unavailable = server
+            .mock("GET", "/v2/account")
+            .with_status(503)
+            .expect(2)
+            .create_async()
+            .await;
+        let ok = server
+            .mock("GET", "/v2/account")
+            .with_status(200)
+            .create_async()
+            .await;
+
+        let client = DigitalOceanApiClient::new_for_test("foo".to_string(), server.url());
+        let resp = client
+            .send(client.get_request_builder(Method::GET, client.get_url("/v2/account")))
+            .await
+            .unwrap();
+        assert_eq!(200, resp.status().as_u16());
+        assert_eq!(3, client.stats().calls());
+        unavailable.assert_async().await;
+        ok.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_send_waits_as_long_as_rate_limit_asks() {
+        let mut server = Server::new_async().await;
+        let limited = server
+            .mock("GET", "/v2/account")
+            .with_status(429)
+            .with_header("Retry-After", "0")
+            .expect(1)
+            .create_async()
+            .await;
+        let ok = server
+            .mock("GET", "/v2/account")
+            .with_status(200)
+            .create_async()
+            .await;
+
+        // the test would time out waiting for the backoff if Retry-After were ignored
+        let client = DigitalOceanApiClient::new_for_test("foo".to_string(), server.url())
+            .with_retry(RetryPolicy {
+                max_attempts: 2,
+                initial_backoff: Duration::from_secs(3600),
+            });
+        let resp = tokio::time::timeout(
+            Duration::from_secs(10),
+            client.send(client.get_request_builder(Method::GET, client.get_url("/v2/account"))),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        assert_eq!(200, resp.status().as_u16());
+        limited.assert_async().await;
+        ok.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_send_does_not_replay_failed_post() {
+        let mut server = Server::new_async().await;
+        let timed_out = server
+            .mock("POST", "/v2/domains/example.com/records")
+            .with_status(504)
+            .expect(1)
+            .create_async()
+            .await;
+
+        let client = DigitalOceanApiClient::new_for_test("foo".to_string(), server.url());
+        let resp = client
+            .send(client.get_request_builder(
+                Method::POST,
+                client.get_url("/v2/domains/example.com/records"),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(504, resp.status().as_u16());
+        assert_eq!(1, client.stats().calls());
+        timed_out.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_send_identifies_itself() {
+        let mut server = Server::new_async().await;
+        let ok = server
+            .mock("GET", "/v2/account")
+            .match_header(
+                "user-agent",
+                format!("digitalocean-dyn-dns/{}", env!("CARGO_PKG_VERSION")).as_str(),
+            )
+            .with_status(200)
+            .create_async()
+            .await;
+
+        // tracing the request leaves it unchanged
+        let client = DigitalOceanApiClient::new_for_test("foo".to_string(), server.url())
+            .with_trace_http(true);
+        let resp = client
+            .send(client.get_request_builder(Method::GET, client.get_url("/v2/account")))
+            .await
+            .unwrap();
+        assert_eq!(200, resp.status().as_u16());
+        assert_eq!(1, client.stats().calls());
+        ok.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_send_gives_up_after_max_attempts() {
+        let mut server = Server::new_async().await;
+        let unavailable = server
+            .mock("GET", "/v2/account")
+            .with_status(429)
+            .expect(2)
+            .create_async()
+            .await;
+
+        let client = DigitalOceanApiClient::new_for_test("foo".to_string(), server.url())
+            .with_retry(RetryPolicy {
+                max_attempts: 2,
+                initial_backoff: Duration::ZERO,
+            });
+        let resp = client
+            .send(client.get_request_builder(Method::GET, client.get_url("/v2/account")))
+            .await
+            .unwrap();
+        assert_eq!(429, resp.status().as_u16());
+        unavailable.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_send_does_not_retry_client_errors() {
+        let mut server = Server::new_async().await;
+        let not_found = server
+            .mock("GET", "/v2/account")
+            .with_status(404)
+            .expect(1)
+            .create_async()
+            .await;
+
+        let client = DigitalOceanApiClient::new_for_test("foo".to_string(), server.url());
+        let resp = client
+            .send(client.get_request_builder(Method::GET, client.get_url("/v2/account")))
+            .await
+            .unwrap();
+        assert_eq!(404, resp.status().as_u16());
+        not_found.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_send_rejects_unauthorized() {
+        let mut server = Server::new_async().await;
+        let unauthorized = server
+            .mock("GET", "/v2/domains")
+            .with_status(401)
+            .with_header("Content-Type", "application/json")
+            .with_body(r#"{"id":"Unauthorized","message":"Unable to authenticate you"}"#)
+            .expect(1)
+            .create_async()
+            .await;
+
+        let client = DigitalOceanApiClient::new_for_test("foo".to_string(), server.url());
+        let resp = client
+            .send(client.get_request_builder(Method::GET, client.get_url("/v2/domains")))
+            .await;
+        assert_eq!(
+            Some(Error::Unauthorized(ApiError {
+                status: 401,
+                id: Some("Unauthorized".to_string()),
+                message: Some("Unable to authenticate you".to_string()),
+                request_id: None,
+                url: format!("{}/v2/domains", server.url()),
+            })),
+            resp.err()
+        );
+        unauthorized.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_token_is_redacted() {
+        let client = DigitalOceanApiClient::new_for_test(
+            "dop_v1_secret".to_string(),
+            "http://127.0.0.1:1".into(),
+        );
+        let request = client.get_request_builder(Method::GET, client.get_url("/v2/account"));
+        assert!(!format!("{:?}", request).contains("dop_v1_secret"));
+
+        let e = client.send(request).await.unwrap_err();
+        assert!(!format!("{}", e).contains("dop_v1_secret"));
+        assert!(!format!("{:?}", e).contains("dop_v1_secret"));
+    }
+}
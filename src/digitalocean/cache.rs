@@ -0,0 +1,319 @@
+//! An on-disk cache of the listings used to resolve droplet, Kubernetes cluster, and load balancer
+//! names to IDs, so that `--daemon` mode doesn't page through every listing on each interval when
+//! the names rarely change.  Only the ID and name of each object are cached; the full listings
+//! carry live state such as addresses and status, and are always fetched from the API.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use async_trait::async_trait;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use tokio::sync::OnceCell;
+use tracing::{debug, warn};
+
+use crate::digitalocean::account::DigitalOceanAccountClient;
+use crate::digitalocean::droplet::{DigitalOceanDropletClient, Droplet, DropletSummary};
+use crate::digitalocean::error::Error;
+use crate::digitalocean::kubernetes::{
+    DigitalOceanKubernetesClient, KubernetesCluster, KubernetesClusterSummary,
+};
+use crate::digitalocean::loadbalancer::{
+    DigitalOceanLoadbalancerClient, Loadbalancer, LoadbalancerSummary,
+};
+use crate::state::save_json;
+
+/// The contents of the cache file
+#[derive(Deserialize, Serialize, Debug, Default)]
+struct CacheFile {
+    /// The cached listings of each account, keyed by the account's UUID so that a different token
+    /// never resolves names against another account's resources
+    #[serde(default)]
+    accounts: HashMap<String, HashMap<String, CachedListing>>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+struct CachedListing {
+    /// Seconds since the Unix epoch when the listing was fetched
+    fetched_at: u64,
+    items: serde_json::Value,
+}
+
+impl CacheFile {
+    /// Read the cache file, treating a missing or unreadable file as empty
+    fn load(path: &Path) -> CacheFile {
+        match std::fs::read_to_string(path) {
+            Ok(contents) => serde_json::from_str(&contents).unwrap_or_else(|e| {
+                warn!("Ignoring unreadable cache file {}: {}", path.display(), e);
+                CacheFile::default()
+            }),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => CacheFile::default(),
+            Err(e) => {
+                warn!("Unable to read cache file {}: {}", path.display(), e);
+                CacheFile::default()
+            }
+        }
+    }
+}
+
+/// Listings kept in a file between runs and reused until they are older than the TTL
+pub struct ListCache {
+    path: PathBuf,
+    ttl: Duration,
+    account_client: Rc<dyn DigitalOceanAccountClient>,
+    account: OnceCell<String>,
+}
+
+impl ListCache {
+    pub fn new(
+        path: PathBuf,
+        ttl: Duration,
+        account_client: Rc<dyn DigitalOceanAccountClient>,
+    ) -> ListCache {
+        ListCache {
+            path,
+            ttl,
+            account_client,
+            account: OnceCell::new(),
+        }
+    }
+
+    /// The key of the account the token belongs to, looked up once and only when a listing is
+    /// needed
+    async fn account(&self) -> Result<&String, Error> {
+        self.account
+            .get_or_try_init(|| async {
+                let account = self.account_client.get_account().await?;
+                Ok(if account.uuid.is_empty() {
+                    account.email
+                } else {
+                    account.uuid
+                })
+            })
+            .await
+    }
+
+    /// Get a listing from the cache if it was fetched within the TTL, otherwise fetch it and
+    /// store it in the cache.  Failing to write the cache only logs a warning.
+    async fn get_or_fetch<T, F, FR>(&self, listing: &str, fetch: F) -> Result<Vec<T>, Error>
+    where
+        T: Serialize + DeserializeOwned,
+        F: FnOnce() -> FR,
+        FR: Future<Output = Result<Vec<T>, Error>>,
+    {
+        let account = self.account().await?.clone();
+        let cached = CacheFile::load(&self.path)
+            .accounts
+            .get_mut(&account)
+            .and_then(|listings| listings.remove(listing))
+            .filter(|cached| now().saturating_sub(cached.fetched_at) < self.ttl.as_secs());
+        if let Some(cached) = cached {
+            match serde_json::from_value(cached.items) {
+                Ok(items) => {
+                    debug!("Using cached {} from {}", listing, self.path.display());
+                    return Ok(items);
+                }
+                Err(e) => debug!("Ignoring unreadable cached {}: {}", listing, e),
+            }
+        }
+
+        let items = fetch().await?;
+        // re-read the file right before writing it, as other listings may have been cached while
+        // this one was being fetched
+        let mut file = CacheFile::load(&self.path);
+        match serde_json::to_value(&items) {
+            Ok(value) => {
+                file.accounts.entry(account).or_default().insert(
+                    listing.to_string(),
+                    CachedListing {
+                        fetched_at: now(),
+                        items: value,
+                    },
+                );
+                if let Err(e) = save_json(&file, &self.path) {
+                    warn!("Unable to write cache file {}: {}", self.path.display(), e);
+                }
+            }
+            Err(e) => warn!("Unable to cache {}: {}", listing, e),
+        }
+        Ok(items)
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+/// A droplet client that serves the droplet names from the cache
+pub struct CachedDropletClient {
+    pub inner: Rc<dyn DigitalOceanDropletClient>,
+    pub cache: Rc<ListCache>,
+}
+
+#[async_trait(?Send)]
+impl DigitalOceanDropletClient for CachedDropletClient {
+    async fn get_droplets(&self) -> Result<Vec<Droplet>, Error> {
+        self.inner.get_droplets().await
+    }
+
+    async fn get_droplet_summaries(&self) -> Result<Vec<DropletSummary>, Error> {
+        self.cache
+            .get_or_fetch("droplets", || self.inner.get_droplet_summaries())
+            .await
+    }
+
+    /// Tags are added and removed far more often than droplets are renamed, so the droplets
+    /// carrying a tag are always fetched
+    async fn get_droplet_summaries_by_tag(&self, tag: &str) -> Result<Vec<DropletSummary>, Error> {
+        self.inner.get_droplet_summaries_by_tag(tag).await
+    }
+}
+
+/// A Kubernetes client that serves the cluster names from the cache
+pub struct CachedKubernetesClient {
+    pub inner: Rc<dyn DigitalOceanKubernetesClient>,
+    pub cache: Rc<ListCache>,
+}
+
+#[async_trait(?Send)]
+impl DigitalOceanKubernetesClient for CachedKubernetesClient {
+    async fn get_kubernetes_clusters(&self) -> Result<Vec<KubernetesCluster>, Error> {
+        self.inner.get_kubernetes_clusters().await
+    }
+
+    async fn get_kubernetes_cluster_summaries(
+        &self,
+    ) -> Result<Vec<KubernetesClusterSummary>, Error> {
+        self.cache
+            .get_or_fetch("kubernetes_clusters", || {
+                self.inner.get_kubernetes_cluster_summaries()
+            })
+            .await
+    }
+}
+
+/// A load balancer client that serves the load balancer names from the cache
+pub struct CachedLoadbalancerClient {
+    pub inner: Rc<dyn DigitalOceanLoadbalancerClient>,
+    pub cache: Rc<ListCache>,
+}
+
+#[async_trait(?Send)]
+impl DigitalOceanLoadbalancerClient for CachedLoadbalancerClient {
+    async fn get_load_balancers(&self) -> Result<Vec<Loadbalancer>, Error> {
+        self.inner.get_load_balancers().await
+    }
+
+    async fn get_load_balancer_summaries(&self) -> Result<Vec<LoadbalancerSummary>, Error> {
+        self.cache
+            .get_or_fetch("load_balancers", || {
+                self.inner.get_load_balancer_summaries()
+            })
+            .await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::cell::Cell;
+    use std::fs;
+    use std::path::Path;
+    use std::rc::Rc;
+    use std::time::Duration;
+
+    use async_trait::async_trait;
+
+    use crate::digitalocean::account::Account;
+    use crate::digitalocean::cache::{CachedDropletClient, ListCache};
+    use crate::digitalocean::droplet::{DigitalOceanDropletClient, Droplet, DropletSummary};
+    use crate::digitalocean::error::Error;
+    use crate::digitalocean::mock::MockAccountClient;
+
+    /// Counts how often the droplet names are fetched
+    #[derive(Default)]
+    struct CountingDropletClient {
+        fetches: Cell<u32>,
+    }
+
+    #[async_trait(?Send)]
+    impl DigitalOceanDropletClient for CountingDropletClient {
+        async fn get_droplets(&self) -> Result<Vec<Droplet>, Error> {
+            panic!("Should not fetch the full droplet listing")
+        }
+
+        async fn get_droplet_summaries(&self) -> Result<Vec<DropletSummary>, Error> {
+            self.fetches.set(self.fetches.get() + 1);
+            Ok(vec![DropletSummary {
+                id: 42,
+                name: "web".to_string(),
+            }])
+        }
+    }
+
+    fn cached_client(
+        path: &Path,
+        ttl: Duration,
+        uuid: &str,
+        inner: Rc<CountingDropletClient>,
+    ) -> CachedDropletClient {
+        let account = Account {
+            email: "me@example.com".to_string(),
+            uuid: uuid.to_string(),
+            email_verified: true,
+            status: "active".to_string(),
+            status_message: "".to_string(),
+        };
+        CachedDropletClient {
+            inner,
+            cache: Rc::new(ListCache::new(
+                path.to_path_buf(),
+                ttl,
+                Rc::new(MockAccountClient::new(account)),
+            )),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_cached_droplet_summaries() {
+        let dir = std::env::temp_dir().join(format!("dyn-dns-cache-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("cache.json");
+        let inner = Rc::new(CountingDropletClient::default());
+        let expected = vec![DropletSummary {
+            id: 42,
+            name: "web".to_string(),
+        }];
+
+        let client = cached_client(&path, Duration::from_secs(60), "a", inner.clone());
+        assert_eq!(Ok(expected.clone()), client.get_droplet_summaries().await);
+        assert_eq!(1, inner.fetches.get());
+        // a later run reads the names from the file
+        let client = cached_client(&path, Duration::from_secs(60), "a", inner.clone());
+        assert_eq!(Ok(expected.clone()), client.get_droplet_summaries().await);
+        assert_eq!(1, inner.fetches.get());
+
+        // another account does not see the names cached for the first one
+        let client = cached_client(&path, Duration::from_secs(60), "b", inner.clone());
+        assert_eq!(Ok(expected.clone()), client.get_droplet_summaries().await);
+        assert_eq!(2, inner.fetches.get());
+
+        // names older than the TTL are fetched again
+        let client = cached_client(&path, Duration::ZERO, "a", inner.clone());
+        assert_eq!(Ok(expected.clone()), client.get_droplet_summaries().await);
+        assert_eq!(3, inner.fetches.get());
+
+        // an unreadable cache file is replaced
+        fs::write(&path, "not json").unwrap();
+        let client = cached_client(&path, Duration::from_secs(60), "a", inner.clone());
+        assert_eq!(Ok(expected), client.get_droplet_summaries().await);
+        assert_eq!(4, inner.fetches.get());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
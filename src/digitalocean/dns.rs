@@ -1,40 +1,88 @@
+use async_trait::async_trait;
+use std::fmt::{Display, Formatter};
 use std::net::IpAddr;
 
-use reqwest::Method;
+use reqwest::{Method, StatusCode};
 use serde::{Deserialize, Serialize};
-use tracing::info;
+use tracing::{debug, info, warn};
 
-use crate::digitalocean::api::{DigitalOceanApiClient, Links, Meta};
-use crate::digitalocean::error::Error;
+use crate::digitalocean::api::{lenient, DigitalOceanApiClient, Links, Meta};
+use crate::digitalocean::error::{ApiError, Error, Failure};
 
+/// The shortest TTL DigitalOcean accepts for a DNS record, in seconds
+pub const MIN_TTL: u16 = 30;
+
+#[async_trait(?Send)]
 pub trait DigitalOceanDnsClient {
-    fn get_domain(&self, domain: &str) -> Result<Option<Domain>, Error>;
+    async fn get_domains(&self) -> Result<Vec<Domain>, Error>;
+
+    async fn get_domain(&self, domain: &str) -> Result<Option<Domain>, Error>;
 
-    fn get_record(
+    async fn create_domain(&self, domain: &str, dry_run: &bool) -> Result<Domain, Error>;
+
+    async fn get_record(
         &self,
         domain: &str,
         record: &str,
         rtype: &str,
     ) -> Result<Option<DomainRecord>, Error>;
 
-    fn update_record(
+    async fn get_records(&self, domain: &str, rtype: &str) -> Result<Vec<DomainRecord>, Error>;
+
+    async fn get_all_records(&self, domain: &str) -> Result<Vec<DomainRecord>, Error>;
+
+    /// Get the records with a name within a domain, optionally only those of a type.
+    async fn get_records_by_name(
+        &self,
+        domain: &str,
+        name: &str,
+        rtype: Option<&str>,
+    ) -> Result<Vec<DomainRecord>, Error> {
+        let Some(name) = relative_record_name(name, domain, false) else {
+            return Ok(vec![]);
+        };
+        Ok(self
+            .get_all_records(domain)
+            .await?
+            .into_iter()
+            .filter(|r| r.name.eq_ignore_ascii_case(&name))
+            .filter(|r| rtype.is_none_or(|rtype| r.typ.eq_ignore_ascii_case(rtype)))
+            .collect())
+    }
+
+    async fn update_record(
+        &self,
+        domain: &str,
+        record: &DomainRecord,
+        value: &RecordValue,
+        ttl: &u16,
+        dry_run: &bool,
+    ) -> Result<DomainRecord, Error>;
+
+    async fn update_record_ttl(
         &self,
         domain: &str,
         record: &DomainRecord,
-        value: &IpAddr,
         ttl: &u16,
         dry_run: &bool,
     ) -> Result<DomainRecord, Error>;
 
-    fn create_record(
+    async fn create_record(
         &self,
         domain: &str,
         record: &str,
         rtype: &str,
-        value: &IpAddr,
+        value: &RecordValue,
         ttl: &u16,
         dry_run: &bool,
     ) -> Result<DomainRecord, Error>;
+
+    async fn delete_record(
+        &self,
+        domain: &str,
+        record: &DomainRecord,
+        dry_run: &bool,
+    ) -> Result<(), Error>;
 }
 
 pub struct DigitalOceanDnsClientImpl {
@@ -47,57 +95,168 @@ impl DigitalOceanDnsClientImpl {
     }
 }
 
+#[async_trait(?Send)]
 impl DigitalOceanDnsClient for DigitalOceanDnsClientImpl {
+    /// Get all domains controlled by this DigitalOcean account
+    async fn get_domains(&self) -> Result<Vec<Domain>, Error> {
+        self.api
+            .get_all_objects(
+                self.api.get_url("/v2/domains"),
+                |r: DomainsResp| r.domains,
+                |r: &DomainsResp| r.links.clone(),
+                |r: &DomainsResp| &r.meta,
+            )
+            .await
+    }
+
     /// Check to see if a domain is controlled by this DigitalOcean account
-    fn get_domain(&self, domain: &str) -> Result<Option<Domain>, Error> {
-        let mut url = self.api.get_url("/v2/domains");
-        let mut exit = false;
-        let mut obj: Option<Domain> = None;
+    async fn get_domain(&self, domain: &str) -> Result<Option<Domain>, Error> {
+        self.api
+            .get_object_by_name(
+                domain,
+                self.api.get_url("/v2/domains"),
+                |r: DomainsResp| r.domains,
+                |r: &DomainsResp| r.links.clone(),
+                |r: &DomainsResp| &r.meta,
+                |d: &Domain, name: &str| d.name == *name,
+            )
+            .await
+    }
 
-        while !exit {
-            let resp = self
+    /// Add a domain to this DigitalOcean account, so that its records can be managed.  The domain
+    /// only resolves once its registrar delegates it to DigitalOcean's name servers.
+    async fn create_domain(&self, domain: &str, dry_run: &bool) -> Result<Domain, Error> {
+        if *dry_run {
+            info!("DRY RUN: Create domain {}", domain);
+            Ok(Domain {
+                name: domain.to_string(),
+                ttl: 0,
+                zone_file: "".to_string(),
+            })
+        } else {
+            let request = self
                 .api
-                .get_request_builder(Method::GET, url.clone())
-                .send()?
-                .json::<DomainsResp>()?;
-
-            obj = resp.domains.into_iter().find(|d| d.name == *domain);
-            if obj.is_some() {
-                exit = true;
-            } else if resp.links.pages.is_some() && resp.links.pages.clone().unwrap().next.is_some()
-            {
-                url = resp.links.pages.unwrap().next.unwrap();
+                .get_request_builder(Method::POST, self.api.get_url("/v2/domains"))
+                .json(&DomainPostBody {
+                    name: domain.to_string(),
+                });
+            let resp = self.api.send(request).await?;
+            if !resp.status().is_success() {
+                return Err(Error::CreateDomain(Failure::Rejected(
+                    ApiError::from_response(resp).await,
+                )));
+            }
+            let resp = resp.json::<DomainResp>().await?;
+            if resp.domain.name.eq_ignore_ascii_case(domain) {
+                Ok(resp.domain)
             } else {
-                exit = true;
+                Err(Error::CreateDomain(Failure::Unexpected(
+                    "new domain does not have the requested name".to_string(),
+                )))
             }
         }
-
-        Ok(obj)
     }
 
-    /// Check to see if a domain is controlled by this DigitalOcean account
-    fn get_record(
+    /// Find the record of a type with a name within the domain, ignoring case.  The name may also
+    /// be given fully qualified with a trailing dot.
+    async fn get_record(
         &self,
         domain: &str,
         record: &str,
         rtype: &str,
     ) -> Result<Option<DomainRecord>, Error> {
-        self.api.get_object_by_name(
-            record,
-            self.api
-                .get_url(format!("/v2/domains/{}/records?type={}", domain, rtype).as_str()),
-            |r: DomainRecordsResp| r.domain_records,
-            |r: &DomainRecordsResp| r.links.clone(),
-            |t: &DomainRecord, name: &str| t.name == *name,
-        )
+        let Some(record) = relative_record_name(record, domain, false) else {
+            return Ok(None);
+        };
+        let record = record.as_str();
+        // the API's name filter finds the record in one request however large the zone is, but
+        // the records are still searched one page at a time if it comes back empty
+        if let Some(found) = self
+            .get_records_by_name(domain, record, Some(rtype))
+            .await?
+            .into_iter()
+            .find(|r| r.name.eq_ignore_ascii_case(record))
+        {
+            return Ok(Some(found));
+        }
+        debug!(
+            "Filtering by name found no {} record {} in {}; searching every {} record",
+            rtype, record, domain, rtype
+        );
+        self.api
+            .get_object_by_name(
+                record,
+                self.api
+                    .get_url(format!("/v2/domains/{}/records?type={}", domain, rtype).as_str()),
+                |r: DomainRecordsResp| r.domain_records,
+                |r: &DomainRecordsResp| r.links.clone(),
+                |r: &DomainRecordsResp| &r.meta,
+                |t: &DomainRecord, name: &str| t.name.eq_ignore_ascii_case(name),
+            )
+            .await
+    }
+
+    /// Get all records of a type within a domain
+    async fn get_records(&self, domain: &str, rtype: &str) -> Result<Vec<DomainRecord>, Error> {
+        self.api
+            .get_all_objects(
+                self.api
+                    .get_url(format!("/v2/domains/{}/records?type={}", domain, rtype).as_str()),
+                |r: DomainRecordsResp| r.domain_records,
+                |r: &DomainRecordsResp| r.links.clone(),
+                |r: &DomainRecordsResp| &r.meta,
+            )
+            .await
+    }
+
+    /// Get every record of a domain, of any type
+    async fn get_all_records(&self, domain: &str) -> Result<Vec<DomainRecord>, Error> {
+        self.api
+            .get_all_objects(
+                self.api
+                    .get_url(format!("/v2/domains/{}/records", domain).as_str()),
+                |r: DomainRecordsResp| r.domain_records,
+                |r: &DomainRecordsResp| r.links.clone(),
+                |r: &DomainRecordsResp| &r.meta,
+            )
+            .await
+    }
+
+    /// Get the records with a name within a domain, letting the API do the filtering.
+    async fn get_records_by_name(
+        &self,
+        domain: &str,
+        name: &str,
+        rtype: Option<&str>,
+    ) -> Result<Vec<DomainRecord>, Error> {
+        let Some(name) = relative_record_name(name, domain, false) else {
+            return Ok(vec![]);
+        };
+        // the API only filters on the fully qualified name
+        let mut endpoint = format!(
+            "/v2/domains/{}/records?name={}",
+            domain,
+            record_fqdn(&name, domain)
+        );
+        if let Some(rtype) = rtype {
+            endpoint.push_str(format!("&type={}", rtype).as_str());
+        }
+        self.api
+            .get_all_objects(
+                self.api.get_url(endpoint.as_str()),
+                |r: DomainRecordsResp| r.domain_records,
+                |r: &DomainRecordsResp| r.links.clone(),
+                |r: &DomainRecordsResp| &r.meta,
+            )
+            .await
     }
 
-    /// Update an existing DNS A/AAAA record to point to a new IP address
-    fn update_record(
+    /// Update the value and TTL of an existing DNS record
+    async fn update_record(
         &self,
         domain: &str,
         record: &DomainRecord,
-        value: &IpAddr,
+        value: &RecordValue,
         ttl: &u16,
         dry_run: &bool,
     ) -> Result<DomainRecord, Error> {
@@ -122,31 +281,90 @@ impl DigitalOceanDnsClient for DigitalOceanDnsClientImpl {
             let url = self
                 .api
                 .get_url(format!("/v2/domains/{}/records/{}", domain, record.id).as_str());
-            let resp = self
+            let request =
+                self.api
+                    .get_request_builder(Method::PUT, url)
+                    .json(&DomainRecordPutBody {
+                        data: value.data.clone(),
+                        priority: value.priority,
+                        port: value.port,
+                        weight: value.weight,
+                        ttl: *ttl,
+                    });
+            let resp = self.api.send(request).await?;
+            if !resp.status().is_success() {
+                return Err(Error::UpdateDns(Failure::Rejected(
+                    ApiError::from_response(resp).await,
+                )));
+            }
+            let resp = resp.json::<DomainRecordsModifyResp>().await?;
+            if value.matches(&resp.domain_record) && resp.domain_record.ttl == *ttl {
+                Ok(resp.domain_record)
+            } else {
+                Err(Error::UpdateDns(Failure::Unexpected(
+                    "new value not reflected in updated DNS record".to_string(),
+                )))
+            }
+        }
+    }
+
+    /// Change the TTL of an existing DNS record without touching its data
+    async fn update_record_ttl(
+        &self,
+        domain: &str,
+        record: &DomainRecord,
+        ttl: &u16,
+        dry_run: &bool,
+    ) -> Result<DomainRecord, Error> {
+        if *dry_run {
+            info!(
+                "DRY RUN: Updating TTL for {}.{} to {}",
+                record.name, domain, ttl
+            );
+            Ok(DomainRecord {
+                id: 0,
+                typ: "".to_string(),
+                name: "".to_string(),
+                data: "".to_string(),
+                priority: None,
+                port: None,
+                ttl: *ttl,
+                weight: None,
+                flags: None,
+                tag: None,
+            })
+        } else {
+            let url = self
+                .api
+                .get_url(format!("/v2/domains/{}/records/{}", domain, record.id).as_str());
+            let request = self
                 .api
                 .get_request_builder(Method::PUT, url)
-                .json(&DomainRecordPutBody {
-                    data: value.to_string(),
-                })
-                .send()?
-                .json::<DomainRecordsModifyResp>()?;
-            if resp.domain_record.data.parse::<IpAddr>()? == *value {
+                .json(&DomainRecordTtlPutBody { ttl: *ttl });
+            let resp = self.api.send(request).await?;
+            if !resp.status().is_success() {
+                return Err(Error::UpdateDns(Failure::Rejected(
+                    ApiError::from_response(resp).await,
+                )));
+            }
+            let resp = resp.json::<DomainRecordsModifyResp>().await?;
+            if resp.domain_record.ttl == *ttl {
                 Ok(resp.domain_record)
             } else {
-                Err(Error::UpdateDns(
-                    "New IP address not reflected in updated DNS record".to_string(),
-                ))
+                Err(Error::UpdateDns(Failure::Unexpected(
+                    "new TTL not reflected in updated DNS record".to_string(),
+                )))
             }
         }
     }
 
-    /// Create a new DNS A/AAAA record to point to an IP address
-    fn create_record(
+    /// Create a new DNS record holding a value
+    async fn create_record(
         &self,
         domain: &str,
         record: &str,
         rtype: &str,
-        value: &IpAddr,
+        value: &RecordValue,
         ttl: &u16,
         dry_run: &bool,
     ) -> Result<DomainRecord, Error> {
@@ -171,28 +389,67 @@ impl DigitalOceanDnsClient for DigitalOceanDnsClientImpl {
             let url = self
                 .api
                 .get_url(format!("/v2/domains/{}/records", domain).as_str());
-            let resp = self
-                .api
-                .get_request_builder(Method::POST, url)
-                .json(&DomainRecordPostBody {
-                    typ: rtype.to_string(),
-                    name: record.to_string(),
-                    data: value.to_string(),
-                    priority: None,
-                    port: None,
-                    ttl: 60,
-                    weight: None,
-                    flags: None,
-                    tag: None,
-                })
-                .send()?
-                .json::<DomainRecordsModifyResp>()?;
-            if resp.domain_record.data.parse::<IpAddr>()? == *value {
+            let request =
+                self.api
+                    .get_request_builder(Method::POST, url)
+                    .json(&DomainRecordPostBody {
+                        typ: rtype.to_string(),
+                        name: record.to_string(),
+                        data: value.data.clone(),
+                        priority: value.priority,
+                        port: value.port,
+                        ttl: *ttl,
+                        weight: value.weight,
+                        flags: None,
+                        tag: None,
+                    });
+            let resp = self.api.send(request).await?;
+            if !resp.status().is_success() {
+                return Err(Error::CreateDns(Failure::Rejected(
+                    ApiError::from_response(resp).await,
+                )));
+            }
+            let resp = resp.json::<DomainRecordsModifyResp>().await?;
+            if value.matches(&resp.domain_record) {
+                if resp.domain_record.ttl != *ttl {
+                    warn!(
+                        "New record {}.{} was given a TTL of {} instead of the requested {}",
+                        record, domain, resp.domain_record.ttl, ttl
+                    );
+                }
                 Ok(resp.domain_record)
             } else {
-                Err(Error::CreateDns(
-                    "New IP address not reflected in new DNS record".to_string(),
-                ))
+                Err(Error::CreateDns(Failure::Unexpected(
+                    "new value not reflected in new DNS record".to_string(),
+                )))
+            }
+        }
+    }
+
+    /// Delete a DNS record
+    async fn delete_record(
+        &self,
+        domain: &str,
+        record: &DomainRecord,
+        dry_run: &bool,
+    ) -> Result<(), Error> {
+        if *dry_run {
+            info!(
+                "DRY RUN: Delete {} record {}.{} ({})",
+                record.typ, record.name, domain, record.data
+            );
+            Ok(())
+        } else {
+            let url = self
+                .api
+                .get_url(format!("/v2/domains/{}/records/{}", domain, record.id).as_str());
+            let request = self.api.get_request_builder(Method::DELETE, url);
+            let resp = self.api.send(request).await?;
+            match resp.status() {
+                StatusCode::NO_CONTENT => Ok(()),
+                _ => Err(Error::DeleteDns(Failure::Rejected(
+                    ApiError::from_response(resp).await,
+                ))),
             }
         }
     }
@@ -203,12 +460,21 @@ impl DigitalOceanDnsClient for DigitalOceanDnsClientImpl {
 #[derive(Deserialize, Debug)]
 struct DomainsResp {
     domains: Vec<Domain>,
-    #[allow(dead_code)]
     meta: Meta,
     links: Links,
 }
 
-#[derive(Deserialize, Debug, Eq, PartialEq)]
+#[derive(Deserialize, Debug)]
+struct DomainResp {
+    domain: Domain,
+}
+
+#[derive(Serialize, Debug)]
+struct DomainPostBody {
+    pub name: String,
+}
+
+#[derive(Deserialize, Debug, Clone, Eq, PartialEq)]
 pub struct Domain {
     /// The name of the domain itself.  This should follow the standard domain format of domain.TLD.
     /// For instance, example.com is a valid domain name.
@@ -219,7 +485,9 @@ pub struct Domain {
     /// This attribute contains the complete contents of the zone file for the selected domain.
     /// Individual domain record resources should be used to get more granular control over records.
     /// However, this attribute can also be used to get information about the SOA record, which is
-    /// created automatically and is not accessible as an individual record resource.
+    /// created automatically and is not accessible as an individual record resource.  It is null
+    /// for a domain that was just created.
+    #[serde(default, deserialize_with = "lenient")]
     pub zone_file: String,
 }
 
@@ -228,7 +496,6 @@ pub struct Domain {
 #[derive(Deserialize, Debug)]
 struct DomainRecordsResp {
     domain_records: Vec<DomainRecord>,
-    #[allow(dead_code)]
     meta: Meta,
     links: Links,
 }
@@ -238,7 +505,7 @@ struct DomainRecordsModifyResp {
     domain_record: DomainRecord,
 }
 
-#[derive(Deserialize, Debug, Eq, PartialEq)]
+#[derive(Deserialize, Debug, Eq, PartialEq, Clone)]
 pub struct DomainRecord {
     /// A unique identifier for each domain record.
     pub id: u32,
@@ -266,6 +533,94 @@ pub struct DomainRecord {
     pub tag: Option<String>,
 }
 
+/// The fully qualified name of a record, given its name within the domain.  The apex of the domain
+/// is named `@`.
+pub fn record_fqdn(name: &str, domain: &str) -> String {
+    if name == "@" {
+        domain.to_string()
+    } else {
+        format!("{}.{}", name, domain)
+    }
+}
+
+/// The name of a record within its domain, in the lower case DigitalOcean stores names in.  A name
+/// ending in a dot, or any name when `fqdn` is set, is fully qualified and has the domain removed
+/// from its end, so `host.sub` and `host.sub.example.com.` name the same record of `example.com`.
+/// Other names are taken as relative to the domain, except for the domain itself, which names the
+/// apex `@`.  Returns `None` for a fully qualified name outside the domain.
+pub fn relative_record_name(record: &str, domain: &str, fqdn: bool) -> Option<String> {
+    let fqdn = fqdn || record.ends_with('.');
+    let name = record.trim_end_matches('.').to_lowercase();
+    let domain = domain.trim_end_matches('.').to_lowercase();
+    if name == domain || name == "@" {
+        Some("@".to_string())
+    } else if fqdn {
+        name.strip_suffix(domain.as_str())
+            .and_then(|n| n.strip_suffix('.'))
+            .filter(|n| !n.is_empty())
+            .map(|n| n.to_string())
+    } else {
+        Some(name)
+    }
+}
+
+/// The value a DNS record should hold: its data, plus the priority of MX and SRV records and the
+/// port and weight of SRV records
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct RecordValue {
+    pub data: String,
+    pub priority: Option<u16>,
+    pub port: Option<u16>,
+    pub weight: Option<u16>,
+}
+
+impl RecordValue {
+    /// Whether a record already holds this value.  IP addresses are compared as addresses so that
+    /// different spellings of the same IPv6 address match, and host names are compared without
+    /// their trailing dot.
+    pub fn matches(&self, record: &DomainRecord) -> bool {
+        self.matches_data(&record.typ, &record.data)
+            && self.priority.is_none_or(|p| record.priority == Some(p))
+            && self.port.is_none_or(|p| record.port == Some(p))
+            && self.weight.is_none_or(|w| record.weight == Some(w))
+    }
+
+    /// Whether the data of a record of the type is this value's data
+    pub fn matches_data(&self, rtype: &str, data: &str) -> bool {
+        match (self.data.parse::<IpAddr>(), data.parse::<IpAddr>()) {
+            (Ok(expected), Ok(actual)) => expected == actual,
+            _ if rtype == "TXT" => self.data == data,
+            _ => self.data.trim_end_matches('.') == data.trim_end_matches('.'),
+        }
+    }
+}
+
+impl From<IpAddr> for RecordValue {
+    fn from(ip: IpAddr) -> Self {
+        RecordValue {
+            data: ip.to_string(),
+            priority: None,
+            port: None,
+            weight: None,
+        }
+    }
+}
+
+impl Display for RecordValue {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if let Some(priority) = self.priority {
+            write!(f, "{priority} ")?;
+        }
+        if let Some(weight) = self.weight {
+            write!(f, "{weight} ")?;
+        }
+        if let Some(port) = self.port {
+            write!(f, "{port} ")?;
+        }
+        write!(f, "{}", self.data)
+    }
+}
+
 #[derive(Serialize, Debug)]
 pub struct DomainRecordPostBody {
     /// The type of the DNS record. For example: A, CNAME, TXT, ...
@@ -295,20 +650,113 @@ pub struct DomainRecordPostBody {
 #[derive(Serialize, Debug)]
 struct DomainRecordPutBody {
     pub data: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub priority: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub port: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub weight: Option<u16>,
+    pub ttl: u16,
+}
+
+#[derive(Serialize, Debug)]
+struct DomainRecordTtlPutBody {
+    pub ttl: u16,
 }
 
 #[cfg(test)]
 mod test {
-    use std::net::Ipv4Addr;
+    use std::net::{IpAddr, Ipv4Addr};
 
     use mockito;
 
-    use crate::digitalocean::dns::{Domain, DomainRecord};
+    use crate::digitalocean::api::{DigitalOceanApiClient, DEFAULT_PER_PAGE};
+    use crate::digitalocean::dns::{
+        record_fqdn, relative_record_name, Domain, DomainRecord, RecordValue,
+    };
+    use crate::digitalocean::error::{Error, RequestError, RequestErrorKind};
     use crate::digitalocean::DigitalOceanClient;
 
-    #[test]
-    fn test_get_domain_simple_found() {
-        let mut server = mockito::Server::new();
+    #[tokio::test]
+    async fn test_get_domains() {
+        let mut server = mockito::Server::new_async().await;
+        let _m = server
+            .mock("GET", "/v2/domains")
+            .match_header("Authorization", "Bearer foo")
+            .with_status(200)
+            .with_header("Content-Type", "application/json")
+            .with_body(
+                serde_json::to_string(&json!({
+                    "domains": [
+                        {
+                            "name": "google.com",
+                            "ttl": 40,
+                            "zone_file": "blargh!"
+                        }
+                    ],
+                    "meta": {
+                        "total": 2
+                    },
+                    "links": {
+                        "pages": {
+                            "next": format!("{}/v2/domains?page=2", server.url())
+                        }
+                    }
+                }))
+                .unwrap(),
+            )
+            .create_async()
+            .await;
+        let _m_page2 = server
+            .mock("GET", "/v2/domains?page=2")
+            .match_header("Authorization", "Bearer foo")
+            .with_status(200)
+            .with_header("Content-Type", "application/json")
+            .with_body(
+                serde_json::to_string(&json!({
+                    "domains": [
+                        {
+                            "name": "yahoo.com",
+                            "ttl": 100,
+                            "zone_file": "oof"
+                        }
+                    ],
+                    "meta": {
+                        "total": 2
+                    },
+                    "links": {}
+                }))
+                .unwrap(),
+            )
+            .create_async()
+            .await;
+
+        let resp = DigitalOceanClient::new_for_test("foo".to_string(), server.url())
+            .dns
+            .get_domains()
+            .await;
+        assert_eq!(
+            Ok(vec![
+                Domain {
+                    name: "google.com".to_string(),
+                    ttl: 40,
+                    zone_file: "blargh!".to_string()
+                },
+                Domain {
+                    name: "yahoo.com".to_string(),
+                    ttl: 100,
+                    zone_file: "oof".to_string()
+                }
+            ]),
+            resp
+        );
+        _m.assert_async().await;
+        _m_page2.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_get_domain_simple_found() {
+        let mut server = mockito::Server::new_async().await;
         let _m = server
             .mock("GET", "/v2/domains")
             .match_header("Authorization", "Bearer foo")
@@ -335,11 +783,13 @@ mod test {
                 }))
                 .unwrap(),
             )
-            .create();
+            .create_async()
+            .await;
 
         let resp = DigitalOceanClient::new_for_test("foo".to_string(), server.url())
             .dns
-            .get_domain(&"yahoo.com".to_string());
+            .get_domain("yahoo.com")
+            .await;
         assert_eq!(
             Ok(Some(Domain {
                 name: "yahoo.com".to_string(),
@@ -348,12 +798,12 @@ mod test {
             })),
             resp
         );
-        _m.assert();
+        _m.assert_async().await;
     }
 
-    #[test]
-    fn test_get_domain_paginated_found() {
-        let mut server = mockito::Server::new();
+    #[tokio::test]
+    async fn test_get_domain_paginated_found() {
+        let mut server = mockito::Server::new_async().await;
         let _m = server
             .mock("GET", "/v2/domains")
             .match_header("Authorization", "Bearer foo")
@@ -379,7 +829,8 @@ mod test {
                 }))
                 .unwrap(),
             )
-            .create();
+            .create_async()
+            .await;
         let _m_page2 = server
             .mock("GET", "/v2/domains?page=2")
             .match_header("Authorization", "Bearer foo")
@@ -401,11 +852,13 @@ mod test {
                 }))
                 .unwrap(),
             )
-            .create();
+            .create_async()
+            .await;
 
         let resp = DigitalOceanClient::new_for_test("foo".to_string(), server.url())
             .dns
-            .get_domain(&"yahoo.com".to_string());
+            .get_domain("yahoo.com")
+            .await;
         assert_eq!(
             Ok(Some(Domain {
                 name: "yahoo.com".to_string(),
@@ -414,13 +867,13 @@ mod test {
             })),
             resp
         );
-        _m.assert();
-        _m_page2.assert();
+        _m.assert_async().await;
+        _m_page2.assert_async().await;
     }
 
-    #[test]
-    fn test_get_domain_missing() {
-        let mut server = mockito::Server::new();
+    #[tokio::test]
+    async fn test_get_domain_missing() {
+        let mut server = mockito::Server::new_async().await;
         let _m = server
             .mock("GET", "/v2/domains")
             .match_header("Authorization", "Bearer foo")
@@ -436,67 +889,248 @@ mod test {
                 }))
                 .unwrap(),
             )
-            .create();
+            .create_async()
+            .await;
 
         let resp = DigitalOceanClient::new_for_test("foo".to_string(), server.url())
             .dns
-            .get_domain(&"yahoo.com".to_string());
+            .get_domain("yahoo.com")
+            .await;
         assert_eq!(Ok(None), resp);
-        _m.assert();
+        _m.assert_async().await;
     }
 
-    #[test]
-    fn test_get_record_simple_found() {
-        let mut server = mockito::Server::new();
+    #[tokio::test]
+    async fn test_get_domain_bad_response() {
+        let mut server = mockito::Server::new_async().await;
         let _m = server
-            .mock("GET", "/v2/domains/google.com/records?type=A")
+            .mock("GET", "/v2/domains")
             .match_header("Authorization", "Bearer foo")
             .with_status(200)
             .with_header("Content-Type", "application/json")
+            .with_body("{\"domains\": 42}")
+            .create_async()
+            .await;
+
+        let resp = DigitalOceanClient::new_for_test("foo".to_string(), server.url())
+            .dns
+            .get_domain("yahoo.com")
+            .await;
+        assert_eq!(
+            Err(Error::Request(RequestError {
+                status: None,
+                url: None,
+                message: "error decoding response body".to_string(),
+                kind: RequestErrorKind::Decode,
+            })),
+            resp
+        );
+        _m.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_create_domain() {
+        let mut server = mockito::Server::new_async().await;
+        let _m = server
+            .mock("POST", "/v2/domains")
+            .match_header("Authorization", "Bearer foo")
+            .match_header("Content-Type", "application/json")
+            .match_body(mockito::Matcher::Json(json!({
+                "name": "example.com"
+            })))
+            .with_status(201)
+            .with_header("Content-Type", "application/json")
             .with_body(
                 serde_json::to_string(&json!({
-                    "domain_records": [
-                        {
-                            "id": 123,
-                            "type": "A",
-                            "name": "@",
-                            "data": "1.2.3.4",
-                            "priority": null,
-                            "port": null,
-                            "ttl": 40,
-                            "weight": null,
-                            "flags": null,
-                            "tag": null
-                        },
-                        {
-                            "id": 234,
-                            "type": "A",
-                            "name": "foo",
-                            "data": "2.3.4.5",
-                            "priority": null,
-                            "port": null,
-                            "ttl": 100,
-                            "weight": null,
-                            "flags": null,
-                            "tag": null
-                        }
-                    ],
-                    "meta": {
-                        "total": 2
-                    },
-                    "links": {}
+                    "domain": {
+                        "name": "example.com",
+                        "ttl": 1800,
+                        "zone_file": null
+                    }
                 }))
                 .unwrap(),
             )
-            .create();
+            .create_async()
+            .await;
 
         let resp = DigitalOceanClient::new_for_test("foo".to_string(), server.url())
             .dns
-            .get_record(
-                &"google.com".to_string(),
-                &"foo".to_string(),
-                &"A".to_string(),
-            );
+            .create_domain("example.com", &false)
+            .await;
+        assert_eq!(
+            Ok(Domain {
+                name: "example.com".to_string(),
+                ttl: 1800,
+                zone_file: "".to_string(),
+            }),
+            resp
+        );
+        _m.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_create_domain_rejected() {
+        let mut server = mockito::Server::new_async().await;
+        let _m = server
+            .mock("POST", "/v2/domains")
+            .with_status(422)
+            .with_header("Content-Type", "application/json")
+            .with_body(r#"{"id":"unprocessable_entity","message":"Name already exists"}"#)
+            .create_async()
+            .await;
+
+        let resp = DigitalOceanClient::new_for_test("foo".to_string(), server.url())
+            .dns
+            .create_domain("example.com", &false)
+            .await;
+        assert!(matches!(resp, Err(Error::CreateDomain(_))));
+        _m.assert_async().await;
+    }
+
+    /// Answer the name-filtered lookup of an A record with the given records
+    async fn mock_name_filter(
+        server: &mut mockito::ServerGuard,
+        fqdn: &str,
+        records: serde_json::Value,
+    ) -> mockito::Mock {
+        server
+            .mock("GET", "/v2/domains/google.com/records")
+            .match_query(mockito::Matcher::AllOf(vec![
+                mockito::Matcher::UrlEncoded("name".to_string(), fqdn.to_string()),
+                mockito::Matcher::UrlEncoded("type".to_string(), "A".to_string()),
+            ]))
+            .match_header("Authorization", "Bearer foo")
+            .with_status(200)
+            .with_header("Content-Type", "application/json")
+            .with_body(
+                serde_json::to_string(&json!({
+                    "domain_records": records,
+                    "meta": {
+                        "total": records.as_array().map_or(0, |r| r.len())
+                    },
+                    "links": {}
+                }))
+                .unwrap(),
+            )
+            .create_async()
+            .await
+    }
+
+    #[tokio::test]
+    async fn test_get_record_filtered() {
+        let mut server = mockito::Server::new_async().await;
+        let _m = mock_name_filter(
+            &mut server,
+            "foo.google.com",
+            json!([{
+                "id": 234,
+                "type": "A",
+                "name": "foo",
+                "data": "2.3.4.5",
+                "priority": null,
+                "port": null,
+                "ttl": 100,
+                "weight": null,
+                "flags": null,
+                "tag": null
+            }]),
+        )
+        .await;
+        let _m_scan = server
+            .mock("GET", "/v2/domains/google.com/records?type=A")
+            .expect(0)
+            .create_async()
+            .await;
+
+        let resp = DigitalOceanClient::new_for_test("foo".to_string(), server.url())
+            .dns
+            .get_record("google.com", "foo", "A")
+            .await;
+        assert_eq!(Ok(Some(234)), resp.map(|r| r.map(|r| r.id)));
+        _m.assert_async().await;
+        _m_scan.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_get_record_fully_qualified() {
+        let mut server = mockito::Server::new_async().await;
+        let _m = mock_name_filter(
+            &mut server,
+            "foo.google.com",
+            json!([{
+                "id": 234,
+                "type": "A",
+                "name": "foo",
+                "data": "2.3.4.5",
+                "priority": null,
+                "port": null,
+                "ttl": 100,
+                "weight": null,
+                "flags": null,
+                "tag": null
+            }]),
+        )
+        .await;
+
+        let resp = DigitalOceanClient::new_for_test("foo".to_string(), server.url())
+            .dns
+            .get_record("google.com", "Foo.Google.com.", "A")
+            .await;
+        assert_eq!(Ok(Some(234)), resp.map(|r| r.map(|r| r.id)));
+        _m.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_get_record_simple_found() {
+        let mut server = mockito::Server::new_async().await;
+        let _m_filter = mock_name_filter(&mut server, "foo.google.com", json!([])).await;
+        let _m = server
+            .mock("GET", "/v2/domains/google.com/records?type=A")
+            .match_header("Authorization", "Bearer foo")
+            .with_status(200)
+            .with_header("Content-Type", "application/json")
+            .with_body(
+                serde_json::to_string(&json!({
+                    "domain_records": [
+                        {
+                            "id": 123,
+                            "type": "A",
+                            "name": "@",
+                            "data": "1.2.3.4",
+                            "priority": null,
+                            "port": null,
+                            "ttl": 40,
+                            "weight": null,
+                            "flags": null,
+                            "tag": null
+                        },
+                        {
+                            "id": 234,
+                            "type": "A",
+                            "name": "foo",
+                            "data": "2.3.4.5",
+                            "priority": null,
+                            "port": null,
+                            "ttl": 100,
+                            "weight": null,
+                            "flags": null,
+                            "tag": null
+                        }
+                    ],
+                    "meta": {
+                        "total": 2
+                    },
+                    "links": {}
+                }))
+                .unwrap(),
+            )
+            .create_async()
+            .await;
+
+        let resp = DigitalOceanClient::new_for_test("foo".to_string(), server.url())
+            .dns
+            .get_record("google.com", "foo", "A")
+            .await;
         assert_eq!(
             Ok(Some(DomainRecord {
                 id: 234,
@@ -512,12 +1146,13 @@ mod test {
             })),
             resp
         );
-        _m.assert();
+        _m.assert_async().await;
     }
 
-    #[test]
-    fn test_get_record_paginated_found() {
-        let mut server = mockito::Server::new();
+    #[tokio::test]
+    async fn test_get_record_paginated_found() {
+        let mut server = mockito::Server::new_async().await;
+        let _m_filter = mock_name_filter(&mut server, "foo.google.com", json!([])).await;
         let _m = server
             .mock("GET", "/v2/domains/google.com/records?type=A")
             .match_header("Authorization", "Bearer foo")
@@ -550,7 +1185,7 @@ mod test {
                 }))
                     .unwrap(),
             )
-            .create();
+            .create_async().await;
         let _m_page2 = server
             .mock("GET", "/v2/domains/google.com/records?type=A&page=2")
             .match_header("Authorization", "Bearer foo")
@@ -579,15 +1214,13 @@ mod test {
                 }))
                 .unwrap(),
             )
-            .create();
+            .create_async()
+            .await;
 
         let resp = DigitalOceanClient::new_for_test("foo".to_string(), server.url())
             .dns
-            .get_record(
-                &"google.com".to_string(),
-                &"foo".to_string(),
-                &"A".to_string(),
-            );
+            .get_record("google.com", "foo", "A")
+            .await;
         assert_eq!(
             Ok(Some(DomainRecord {
                 id: 234,
@@ -603,13 +1236,14 @@ mod test {
             })),
             resp
         );
-        _m.assert();
-        _m_page2.assert();
+        _m.assert_async().await;
+        _m_page2.assert_async().await;
     }
 
-    #[test]
-    fn test_get_record_missing() {
-        let mut server = mockito::Server::new();
+    #[tokio::test]
+    async fn test_get_record_missing() {
+        let mut server = mockito::Server::new_async().await;
+        let _m_filter = mock_name_filter(&mut server, "foo.google.com", json!([])).await;
         let _m = server
             .mock("GET", "/v2/domains/google.com/records?type=A")
             .match_header("Authorization", "Bearer foo")
@@ -625,28 +1259,222 @@ mod test {
                 }))
                 .unwrap(),
             )
-            .create();
+            .create_async()
+            .await;
 
         let resp = DigitalOceanClient::new_for_test("foo".to_string(), server.url())
             .dns
-            .get_record(
-                &"google.com".to_string(),
-                &"foo".to_string(),
-                &"A".to_string(),
-            );
+            .get_record("google.com", "foo", "A")
+            .await;
         assert_eq!(Ok(None), resp);
-        _m.assert();
+        _m.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_get_records() {
+        let mut server = mockito::Server::new_async().await;
+        let _m = server
+            .mock("GET", "/v2/domains/google.com/records?type=TXT")
+            .match_header("Authorization", "Bearer foo")
+            .with_status(200)
+            .with_header("Content-Type", "application/json")
+            .with_body(
+                serde_json::to_string(&json!({
+                    "domain_records": [
+                        {
+                            "id": 123,
+                            "type": "TXT",
+                            "name": "foo",
+                            "data": "heritage=external-dns",
+                            "priority": null,
+                            "port": null,
+                            "ttl": 40,
+                            "weight": null,
+                            "flags": null,
+                            "tag": null
+                        }
+                    ],
+                    "meta": {
+                        "total": 1
+                    },
+                    "links": {}
+                }))
+                .unwrap(),
+            )
+            .create_async()
+            .await;
+
+        let resp = DigitalOceanClient::new_for_test("foo".to_string(), server.url())
+            .dns
+            .get_records("google.com", "TXT")
+            .await;
+        assert_eq!(
+            Ok(vec![DomainRecord {
+                id: 123,
+                typ: "TXT".to_string(),
+                name: "foo".to_string(),
+                data: "heritage=external-dns".to_string(),
+                priority: None,
+                port: None,
+                ttl: 40,
+                weight: None,
+                flags: None,
+                tag: None
+            }]),
+            resp
+        );
+        _m.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_get_all_records() {
+        let mut server = mockito::Server::new_async().await;
+        let _m = server
+            .mock("GET", "/v2/domains/google.com/records")
+            .match_header("Authorization", "Bearer foo")
+            .with_status(200)
+            .with_header("Content-Type", "application/json")
+            .with_body(
+                serde_json::to_string(&json!({
+                    "domain_records": [
+                        {
+                            "id": 123,
+                            "type": "A",
+                            "name": "foo",
+                            "data": "1.2.3.4",
+                            "priority": null,
+                            "port": null,
+                            "ttl": 60,
+                            "weight": null,
+                            "flags": null,
+                            "tag": null
+                        },
+                        {
+                            "id": 234,
+                            "type": "MX",
+                            "name": "@",
+                            "data": "mail.google.com.",
+                            "priority": 10,
+                            "port": null,
+                            "ttl": 1800,
+                            "weight": null,
+                            "flags": null,
+                            "tag": null
+                        }
+                    ],
+                    "meta": {
+                        "total": 2
+                    },
+                    "links": {}
+                }))
+                .unwrap(),
+            )
+            .create_async()
+            .await;
+
+        let resp = DigitalOceanClient::new_for_test("foo".to_string(), server.url())
+            .dns
+            .get_all_records("google.com")
+            .await
+            .unwrap();
+        assert_eq!(
+            vec![(123, "A"), (234, "MX")],
+            resp.iter()
+                .map(|r| (r.id, r.typ.as_str()))
+                .collect::<Vec<_>>()
+        );
+        _m.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_get_records_by_name() {
+        let mut server = mockito::Server::new_async().await;
+        let _m = server
+            .mock("GET", "/v2/domains/google.com/records")
+            .match_query(mockito::Matcher::AllOf(vec![
+                mockito::Matcher::UrlEncoded("name".to_string(), "www.google.com".to_string()),
+                mockito::Matcher::UrlEncoded("type".to_string(), "A".to_string()),
+                mockito::Matcher::UrlEncoded("per_page".to_string(), "200".to_string()),
+            ]))
+            .match_header("Authorization", "Bearer foo")
+            .with_status(200)
+            .with_header("Content-Type", "application/json")
+            .with_body(
+                serde_json::to_string(&json!({
+                    "domain_records": [
+                        {
+                            "id": 123,
+                            "type": "A",
+                            "name": "www",
+                            "data": "1.2.3.4",
+                            "priority": null,
+                            "port": null,
+                            "ttl": 60,
+                            "weight": null,
+                            "flags": null,
+                            "tag": null
+                        }
+                    ],
+                    "meta": {
+                        "total": 1
+                    },
+                    "links": {}
+                }))
+                .unwrap(),
+            )
+            .create_async()
+            .await;
+
+        let resp = DigitalOceanClient::new_for_client(
+            DigitalOceanApiClient::new_for_test("foo".to_string(), server.url())
+                .with_per_page(Some(DEFAULT_PER_PAGE)),
+        )
+        .dns
+        .get_records_by_name("google.com", "WWW", Some("A"))
+        .await
+        .unwrap();
+        assert_eq!(vec![123], resp.iter().map(|r| r.id).collect::<Vec<_>>());
+        _m.assert_async().await;
     }
 
     #[test]
-    fn test_update_record() {
-        let mut server = mockito::Server::new();
+    fn test_relative_record_name() {
+        let name = |record, fqdn| relative_record_name(record, "example.com", fqdn);
+        assert_eq!(Some("host.sub".to_string()), name("host.sub", false));
+        assert_eq!(
+            Some("host.sub".to_string()),
+            name("Host.Sub.Example.com.", false)
+        );
+        assert_eq!(
+            Some("host.sub".to_string()),
+            name("host.sub.example.com", true)
+        );
+        assert_eq!(
+            Some("host.sub.example.com".to_string()),
+            name("host.sub.example.com", false)
+        );
+        assert_eq!(Some("@".to_string()), name("example.com", false));
+        assert_eq!(Some("@".to_string()), name("@", true));
+        assert_eq!(None, name("host.example.org.", false));
+        assert_eq!(None, name("notexample.com", true));
+    }
+
+    #[test]
+    fn test_record_fqdn() {
+        assert_eq!("www.google.com", record_fqdn("www", "google.com"));
+        assert_eq!("google.com", record_fqdn("@", "google.com"));
+    }
+
+    #[tokio::test]
+    async fn test_update_record() {
+        let mut server = mockito::Server::new_async().await;
         let _m = server
             .mock("PUT", "/v2/domains/google.com/records/234")
             .match_header("Authorization", "Bearer foo")
             .match_header("Content-Type", "application/json")
             .match_body(mockito::Matcher::Json(json!({
-                "data": "2.3.4.5"
+                "data": "2.3.4.5",
+                "ttl": 60
             })))
             .with_status(200)
             .with_header("Content-Type", "application/json")
@@ -667,7 +1495,8 @@ mod test {
                 }))
                 .unwrap(),
             )
-            .create();
+            .create_async()
+            .await;
 
         let orig_record = DomainRecord {
             id: 234,
@@ -684,12 +1513,13 @@ mod test {
         let resp = DigitalOceanClient::new_for_test("foo".to_string(), server.url())
             .dns
             .update_record(
-                &"google.com".to_string(),
+                "google.com",
                 &orig_record,
-                &Ipv4Addr::new(2, 3, 4, 5).into(),
+                &IpAddr::from(Ipv4Addr::new(2, 3, 4, 5)).into(),
                 &60,
                 &false,
-            );
+            )
+            .await;
         assert_eq!(
             Ok(DomainRecord {
                 id: 234,
@@ -705,12 +1535,78 @@ mod test {
             }),
             resp
         );
-        _m.assert();
+        _m.assert_async().await;
     }
 
-    #[test]
-    fn test_create_record() {
-        let mut server = mockito::Server::new();
+    #[tokio::test]
+    async fn test_update_record_ttl() {
+        let mut server = mockito::Server::new_async().await;
+        let _m = server
+            .mock("PUT", "/v2/domains/google.com/records/234")
+            .match_header("Authorization", "Bearer foo")
+            .match_header("Content-Type", "application/json")
+            .match_body(mockito::Matcher::Json(json!({
+                "ttl": 30
+            })))
+            .with_status(200)
+            .with_header("Content-Type", "application/json")
+            .with_body(
+                serde_json::to_string(&json!({
+                    "domain_record": {
+                        "id": 234,
+                        "type": "A",
+                        "name": "foo",
+                        "data": "1.2.3.4",
+                        "priority": null,
+                        "port": null,
+                        "ttl": 30,
+                        "weight": null,
+                        "flags": null,
+                        "tag": null
+                    }
+                }))
+                .unwrap(),
+            )
+            .create_async()
+            .await;
+
+        let orig_record = DomainRecord {
+            id: 234,
+            typ: "A".to_string(),
+            name: "foo".to_string(),
+            data: "1.2.3.4".to_string(),
+            priority: None,
+            port: None,
+            ttl: 3600,
+            weight: None,
+            flags: None,
+            tag: None,
+        };
+        let resp = DigitalOceanClient::new_for_test("foo".to_string(), server.url())
+            .dns
+            .update_record_ttl("google.com", &orig_record, &30, &false)
+            .await;
+        assert_eq!(
+            Ok(DomainRecord {
+                id: 234,
+                typ: "A".to_string(),
+                name: "foo".to_string(),
+                data: "1.2.3.4".to_string(),
+                priority: None,
+                port: None,
+                ttl: 30,
+                weight: None,
+                flags: None,
+                tag: None
+            }),
+            resp
+        );
+        _m.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_create_record() {
+        let mut server = mockito::Server::new_async().await;
         let _m = server
             .mock("POST", "/v2/domains/google.com/records")
             .match_header("Authorization", "Bearer foo")
@@ -721,7 +1617,7 @@ mod test {
                 "data": "1.2.3.4",
                 "priority": null,
                 "port": null,
-                "ttl": 60,
+                "ttl": 100,
                 "weight": null,
                 "flags": null,
                 "tag": null
@@ -745,18 +1641,20 @@ mod test {
                 }))
                 .unwrap(),
             )
-            .create();
+            .create_async()
+            .await;
 
         let resp = DigitalOceanClient::new_for_test("foo".to_string(), server.url())
             .dns
             .create_record(
-                &"google.com".to_string(),
-                &"foo".to_string(),
-                &"A".to_string(),
-                &Ipv4Addr::new(1, 2, 3, 4).into(),
+                "google.com",
+                "foo",
+                "A",
+                &IpAddr::from(Ipv4Addr::new(1, 2, 3, 4)).into(),
                 &100,
                 &false,
-            );
+            )
+            .await;
         assert_eq!(
             Ok(DomainRecord {
                 id: 234,
@@ -772,6 +1670,165 @@ mod test {
             }),
             resp
         );
-        _m.assert();
+        _m.assert_async().await;
+    }
+
+    #[test]
+    fn test_record_value_matches() {
+        let record = |typ: &str, data: &str, priority: Option<u16>| DomainRecord {
+            id: 1,
+            typ: typ.to_string(),
+            name: "foo".to_string(),
+            data: data.to_string(),
+            priority,
+            port: None,
+            ttl: 60,
+            weight: None,
+            flags: None,
+            tag: None,
+        };
+        let value = |data: &str, priority: Option<u16>| RecordValue {
+            data: data.to_string(),
+            priority,
+            port: None,
+            weight: None,
+        };
+
+        assert!(value("2001:db8::1", None).matches(&record("AAAA", "2001:DB8:0::1", None)));
+        assert!(value("example.com.", None).matches(&record("CNAME", "example.com", None)));
+        assert!(!value("example.com.", None).matches(&record("CNAME", "example.org", None)));
+        assert!(value("v=spf1 -all", None).matches(&record("TXT", "v=spf1 -all", None)));
+        assert!(!value("v=spf1 -all.", None).matches(&record("TXT", "v=spf1 -all", None)));
+        assert!(value("mail", Some(10)).matches(&record("MX", "mail", Some(10))));
+        assert!(!value("mail", Some(20)).matches(&record("MX", "mail", Some(10))));
+        assert_eq!("10 mail", value("mail", Some(10)).to_string());
+    }
+
+    #[tokio::test]
+    async fn test_delete_record() {
+        let mut server = mockito::Server::new_async().await;
+        let _m = server
+            .mock("DELETE", "/v2/domains/google.com/records/234")
+            .match_header("Authorization", "Bearer foo")
+            .with_status(204)
+            .create_async()
+            .await;
+
+        let record = DomainRecord {
+            id: 234,
+            typ: "A".to_string(),
+            name: "foo".to_string(),
+            data: "1.2.3.4".to_string(),
+            priority: None,
+            port: None,
+            ttl: 60,
+            weight: None,
+            flags: None,
+            tag: None,
+        };
+        let client = DigitalOceanClient::new_for_test("foo".to_string(), server.url());
+        assert_eq!(
+            Ok(()),
+            client.dns.delete_record("google.com", &record, &true).await
+        );
+        assert_eq!(
+            Ok(()),
+            client
+                .dns
+                .delete_record("google.com", &record, &false)
+                .await
+        );
+        _m.expect(1).assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_delete_record_rejected() {
+        let mut server = mockito::Server::new_async().await;
+        let _m = server
+            .mock("DELETE", "/v2/domains/google.com/records/234")
+            .with_status(404)
+            .with_header("Content-Type", "application/json")
+            .with_body(
+                serde_json::to_string(&json!({
+                    "id": "not_found",
+                    "message": "The resource you were accessing could not be found."
+                }))
+                .unwrap(),
+            )
+            .create_async()
+            .await;
+
+        let record = DomainRecord {
+            id: 234,
+            typ: "A".to_string(),
+            name: "foo".to_string(),
+            data: "1.2.3.4".to_string(),
+            priority: None,
+            port: None,
+            ttl: 60,
+            weight: None,
+            flags: None,
+            tag: None,
+        };
+        let resp = DigitalOceanClient::new_for_test("foo".to_string(), server.url())
+            .dns
+            .delete_record("google.com", &record, &false)
+            .await;
+        assert!(matches!(resp, Err(Error::DeleteDns(_))));
+        _m.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_create_mx_record() {
+        let mut server = mockito::Server::new_async().await;
+        let _m = server
+            .mock("POST", "/v2/domains/google.com/records")
+            .match_header("Authorization", "Bearer foo")
+            .match_body(mockito::Matcher::PartialJson(json!({
+                "type": "MX",
+                "name": "@",
+                "data": "mail.google.com.",
+                "priority": 10
+            })))
+            .with_status(200)
+            .with_header("Content-Type", "application/json")
+            .with_body(
+                serde_json::to_string(&json!({
+                    "domain_record": {
+                        "id": 234,
+                        "type": "MX",
+                        "name": "@",
+                        "data": "mail.google.com",
+                        "priority": 10,
+                        "port": null,
+                        "ttl": 60,
+                        "weight": null,
+                        "flags": null,
+                        "tag": null
+                    }
+                }))
+                .unwrap(),
+            )
+            .create_async()
+            .await;
+
+        let resp = DigitalOceanClient::new_for_test("foo".to_string(), server.url())
+            .dns
+            .create_record(
+                "google.com",
+                "@",
+                "MX",
+                &RecordValue {
+                    data: "mail.google.com.".to_string(),
+                    priority: Some(10),
+                    port: None,
+                    weight: None,
+                },
+                &60,
+                &false,
+            )
+            .await;
+        assert_eq!(Some(10), resp.unwrap().priority);
+        _m.assert_async().await;
     }
 }
@@ -1,9 +1,39 @@
-use crate::digitalocean::api::{DigitalOceanApiClient, Links, Meta};
+use crate::digitalocean::api::{lenient, DigitalOceanApiClient, Links, Meta};
 use crate::digitalocean::error::Error;
-use serde::Deserialize;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
 
+#[async_trait(?Send)]
 pub trait DigitalOceanDropletClient {
-    fn get_droplets(&self) -> Result<Vec<Droplet>, Error>;
+    async fn get_droplets(&self) -> Result<Vec<Droplet>, Error>;
+
+    /// Get just the ID and name of every droplet, which is much cheaper to deserialize than the
+    /// full listing when resolving names to IDs.
+    async fn get_droplet_summaries(&self) -> Result<Vec<DropletSummary>, Error> {
+        Ok(self
+            .get_droplets()
+            .await?
+            .into_iter()
+            .map(|o| DropletSummary {
+                id: o.id,
+                name: o.name,
+            })
+            .collect())
+    }
+
+    /// Get the ID and name of every droplet carrying the tag.
+    async fn get_droplet_summaries_by_tag(&self, tag: &str) -> Result<Vec<DropletSummary>, Error> {
+        Ok(self
+            .get_droplets()
+            .await?
+            .into_iter()
+            .filter(|o| o.tags.iter().any(|t| t == tag))
+            .map(|o| DropletSummary {
+                id: o.id,
+                name: o.name,
+            })
+            .collect())
+    }
 }
 
 pub struct DigitalOceanDropletClientImpl {
@@ -16,14 +46,43 @@ impl DigitalOceanDropletClientImpl {
     }
 }
 
+#[async_trait(?Send)]
 impl DigitalOceanDropletClient for DigitalOceanDropletClientImpl {
     /// Get info on all droplets.
-    fn get_droplets(&self) -> Result<Vec<Droplet>, Error> {
-        self.api.get_all_objects(
-            self.api.get_url("/v2/droplets"),
-            |r: DropletsResp| r.droplets,
-            |r: &DropletsResp| r.links.clone(),
-        )
+    async fn get_droplets(&self) -> Result<Vec<Droplet>, Error> {
+        self.api
+            .get_all_objects(
+                self.api.get_url("/v2/droplets"),
+                |r: DropletsResp| r.droplets,
+                |r: &DropletsResp| r.links.clone(),
+                |r: &DropletsResp| &r.meta,
+            )
+            .await
+    }
+
+    /// Get the ID and name of all droplets.
+    async fn get_droplet_summaries(&self) -> Result<Vec<DropletSummary>, Error> {
+        self.api
+            .get_all_objects(
+                self.api.get_url("/v2/droplets"),
+                |r: DropletSummariesResp| r.droplets,
+                |r: &DropletSummariesResp| r.links.clone(),
+                |r: &DropletSummariesResp| &r.meta,
+            )
+            .await
+    }
+
+    /// Get the ID and name of the droplets carrying the tag, letting the API do the filtering.
+    async fn get_droplet_summaries_by_tag(&self, tag: &str) -> Result<Vec<DropletSummary>, Error> {
+        self.api
+            .get_all_objects(
+                self.api
+                    .get_url(format!("/v2/droplets?tag_name={}", tag).as_str()),
+                |r: DropletSummariesResp| r.droplets,
+                |r: &DropletSummariesResp| r.links.clone(),
+                |r: &DropletSummariesResp| &r.meta,
+            )
+            .await
     }
 }
 
@@ -32,11 +91,24 @@ impl DigitalOceanDropletClient for DigitalOceanDropletClientImpl {
 #[derive(Deserialize, Debug)]
 struct DropletsResp {
     droplets: Vec<Droplet>,
-    #[allow(dead_code)]
     meta: Meta,
     links: Links,
 }
 
+#[derive(Deserialize, Debug)]
+struct DropletSummariesResp {
+    droplets: Vec<DropletSummary>,
+    meta: Meta,
+    links: Links,
+}
+
+/// The identifying fields of a Droplet
+#[derive(Deserialize, Serialize, Debug, Eq, PartialEq, Clone)]
+pub struct DropletSummary {
+    pub id: u32,
+    pub name: String,
+}
+
 #[derive(Deserialize, Debug, PartialEq, Clone)]
 #[allow(dead_code)]
 pub struct Droplet {
@@ -46,55 +118,94 @@ pub struct Droplet {
     /// The human-readable name set for the Droplet instance.
     pub name: String,
     /// Memory of the Droplet in megabytes. (multiple of 8)
+    #[serde(default, deserialize_with = "lenient")]
     pub memory: u32,
     /// The number of virtual CPUs.
+    #[serde(default, deserialize_with = "lenient")]
     pub vcpus: u8,
     /// The size of the Droplet's disk in gigabytes.
+    #[serde(default, deserialize_with = "lenient")]
     pub disk: u16,
     /// A boolean value indicating whether the Droplet has been locked, preventing actions by users.
+    #[serde(default, deserialize_with = "lenient")]
     pub locked: bool,
     /// A status string indicating the state of the Droplet instance. This may be "new", "active",
     /// "off", or "archive".
+    #[serde(default, deserialize_with = "lenient")]
     pub status: String,
     /// These Droplets will have this attribute set to null. The current kernel for Droplets with
     /// externally managed kernels. This will initially be set to the kernel of the base image when
     /// the Droplet is created.
+    #[serde(default, deserialize_with = "lenient")]
     #[deprecated(note = "All Droplets created after March 2017 use internal kernels by default.")]
     pub kernel: Option<DropletKernel>,
     /// A time value given in ISO8601 combined date and time format that represents when the Droplet
     /// was created.
+    #[serde(default, deserialize_with = "lenient")]
     pub created_at: String,
     /// An array of features enabled on this Droplet.
+    #[serde(default, deserialize_with = "lenient")]
     pub features: Vec<String>,
     /// An array of backup IDs of any backups that have been taken of the Droplet instance. Droplet
     /// backups are enabled at the time of the instance creation.
+    #[serde(default, deserialize_with = "lenient")]
     pub backup_ids: Vec<u32>,
     /// The details of the Droplet's backups feature, if backups are configured for the Droplet.
     /// This object contains keys for the start and end times of the window during which the backup
     /// will start.
+    #[serde(default, deserialize_with = "lenient")]
     pub next_backup_window: Option<DropletNextBackupWindow>,
     /// An array of snapshot IDs of any snapshots created from the Droplet instance.
+    #[serde(default, deserialize_with = "lenient")]
     pub snapshot_ids: Vec<u32>,
+    #[serde(default, deserialize_with = "lenient")]
     pub image: DropletImage,
     /// A flat array including the unique identifier for each Block Storage volume attached to the
     /// Droplet.
+    #[serde(default, deserialize_with = "lenient")]
     pub volume_ids: Vec<String>,
+    #[serde(default, deserialize_with = "lenient")]
     pub size: DropletSize,
     /// The unique slug identifier for the size of this Droplet.
+    #[serde(default, deserialize_with = "lenient")]
     pub size_slug: String,
     /// The details of the network that are configured for the Droplet instance. This is an object
     /// that contains keys for IPv4 and IPv6. The value of each of these is an array that contains
     /// objects describing an individual IP resource allocated to the Droplet. These will define
     /// attributes like the IP address, netmask, and gateway of the specific network depending on
     /// the type of network it is.
+    #[serde(default, deserialize_with = "lenient")]
     pub networks: DropletNetworks,
+    #[serde(default, deserialize_with = "lenient")]
     pub region: DropletRegion,
     /// An array of Tags the Droplet has been tagged with.
+    #[serde(default, deserialize_with = "lenient")]
     pub tags: Vec<String>,
     /// A string specifying the UUID of the VPC to which the Droplet is assigned.
+    #[serde(default, deserialize_with = "lenient")]
     pub vpc_uuid: String,
 }
 
+impl Droplet {
+    /// The public IPv4 address of the droplet, if it has one
+    pub fn public_ipv4(&self) -> Option<&str> {
+        self.networks
+            .v4
+            .iter()
+            .find(|n| n.typ == "public")
+            .map(|n| n.ip_address.as_str())
+    }
+
+    /// The public IPv6 address of the droplet, if IPv6 is enabled on it
+    pub fn public_ipv6(&self) -> Option<&str> {
+        self.networks
+            .v6
+            .iter()
+            .find(|n| n.typ == "public")
+            .map(|n| n.ip_address.as_str())
+    }
+}
+
 #[derive(Deserialize, Debug, Eq, PartialEq, Clone)]
 #[allow(dead_code)]
 pub struct DropletKernel {
@@ -118,7 +229,7 @@ pub struct DropletNextBackupWindow {
     pub end: String,
 }
 
-#[derive(Deserialize, Debug, PartialEq, Clone)]
+#[derive(Deserialize, Debug, PartialEq, Clone, Default)]
 #[allow(dead_code)]
 pub struct DropletImage {
     /// A unique number that can be used to identify and reference a specific image.
@@ -169,7 +280,7 @@ pub struct DropletImage {
     pub error_message: Option<String>,
 }
 
-#[derive(Deserialize, Debug, PartialEq, Clone)]
+#[derive(Deserialize, Debug, PartialEq, Clone, Default)]
 #[allow(dead_code)]
 pub struct DropletSize {
     /// A human-readable string that is used to uniquely identify each size.
@@ -200,7 +311,7 @@ pub struct DropletSize {
     pub description: String,
 }
 
-#[derive(Deserialize, Debug, Eq, PartialEq, Clone)]
+#[derive(Deserialize, Debug, Eq, PartialEq, Clone, Default)]
 #[allow(dead_code)]
 pub struct DropletNetworks {
     pub v4: Vec<DropletNetworkV4>,
@@ -240,7 +351,7 @@ pub struct DropletNetworkV6 {
     pub typ: String,
 }
 
-#[derive(Deserialize, Debug, Eq, PartialEq, Clone)]
+#[derive(Deserialize, Debug, Eq, PartialEq, Clone, Default)]
 #[allow(dead_code)]
 pub struct DropletRegion {
     /// The display name of the region. This will be a full name that is used in the control panel
@@ -259,10 +370,12 @@ pub struct DropletRegion {
 
 #[cfg(test)]
 mod test {
+    use crate::digitalocean::api::DigitalOceanApiClient;
     use crate::digitalocean::droplet::{
-        Droplet, DropletImage, DropletNetworkV4, DropletNetworks, DropletNextBackupWindow,
-        DropletRegion, DropletSize,
+        Droplet, DropletImage, DropletNetworkV4, DropletNetworkV6, DropletNetworks,
+        DropletNextBackupWindow, DropletRegion, DropletSize, DropletSummary,
     };
+    use crate::digitalocean::error::Error;
     use crate::digitalocean::DigitalOceanClient;
 
     fn get_droplet_1_json() -> serde_json::Value {
@@ -543,9 +656,9 @@ mod test {
         }
     }
 
-    #[test]
-    fn test_get_droplets() {
-        let mut server = mockito::Server::new();
+    #[tokio::test]
+    async fn test_get_droplets() {
+        let mut server = mockito::Server::new_async().await;
         let _m = server
             .mock("GET", "/v2/droplets")
             .match_header("Authorization", "Bearer foo")
@@ -564,18 +677,151 @@ mod test {
                 }))
                 .unwrap(),
             )
-            .create();
+            .create_async()
+            .await;
 
         let resp = DigitalOceanClient::new_for_test("foo".to_string(), server.url())
             .droplet
-            .get_droplets();
+            .get_droplets()
+            .await;
         assert_eq!(Ok(vec![get_droplet_1_obj(), get_droplet_2_obj()]), resp);
-        _m.assert();
+        _m.assert_async().await;
     }
 
-    #[test]
-    fn test_get_droplets_paginated() {
-        let mut server = mockito::Server::new();
+    #[tokio::test]
+    async fn test_get_droplet_summaries() {
+        let mut server = mockito::Server::new_async().await;
+        let _m = server
+            .mock("GET", "/v2/droplets")
+            .match_header("Authorization", "Bearer foo")
+            .with_status(200)
+            .with_header("Content-Type", "application/json")
+            .with_body(
+                serde_json::to_string(&json!({
+                    "droplets": [
+                        get_droplet_1_json(),
+                        get_droplet_2_json(),
+                    ],
+                    "meta": {
+                        "total": 2
+                    },
+                    "links": {}
+                }))
+                .unwrap(),
+            )
+            .create_async()
+            .await;
+
+        let resp = DigitalOceanClient::new_for_test("foo".to_string(), server.url())
+            .droplet
+            .get_droplet_summaries()
+            .await;
+        assert_eq!(
+            Ok(vec![
+                DropletSummary {
+                    id: get_droplet_1_obj().id,
+                    name: get_droplet_1_obj().name,
+                },
+                DropletSummary {
+                    id: get_droplet_2_obj().id,
+                    name: get_droplet_2_obj().name,
+                },
+            ]),
+            resp
+        );
+        _m.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_get_droplet_summaries_by_tag() {
+        let mut server = mockito::Server::new_async().await;
+        let _m = server
+            .mock("GET", "/v2/droplets")
+            .match_query(mockito::Matcher::UrlEncoded(
+                "tag_name".to_string(),
+                "web".to_string(),
+            ))
+            .match_header("Authorization", "Bearer foo")
+            .with_status(200)
+            .with_header("Content-Type", "application/json")
+            .with_body(
+                serde_json::to_string(&json!({
+                    "droplets": [
+                        get_droplet_2_json(),
+                    ],
+                    "meta": {
+                        "total": 1
+                    },
+                    "links": {}
+                }))
+                .unwrap(),
+            )
+            .create_async()
+            .await;
+
+        let resp = DigitalOceanClient::new_for_test("foo".to_string(), server.url())
+            .droplet
+            .get_droplet_summaries_by_tag("web")
+            .await;
+        assert_eq!(
+            Ok(vec![DropletSummary {
+                id: get_droplet_2_obj().id,
+                name: get_droplet_2_obj().name,
+            }]),
+            resp
+        );
+        _m.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_get_droplets_tolerant() {
+        let mut server = mockito::Server::new_async().await;
+        let _m = server
+            .mock("GET", "/v2/droplets")
+            .match_header("Authorization", "Bearer foo")
+            .with_status(200)
+            .with_header("Content-Type", "application/json")
+            .with_body(
+                serde_json::to_string(&json!({
+                    "droplets": [
+                        {
+                            "id": 3,
+                            "name": "baz",
+                            "memory": null,
+                            "image": null,
+                            "networks": "unexpected",
+                            "tags": [1, 2],
+                            "some_new_field": true
+                        }
+                    ],
+                    "meta": {
+                        "total": 1
+                    },
+                    "links": {}
+                }))
+                .unwrap(),
+            )
+            .create_async()
+            .await;
+
+        let resp = DigitalOceanClient::new_for_test("foo".to_string(), server.url())
+            .droplet
+            .get_droplets()
+            .await
+            .unwrap();
+        assert_eq!(1, resp.len());
+        assert_eq!(3, resp[0].id);
+        assert_eq!("baz", resp[0].name);
+        assert_eq!(0, resp[0].memory);
+        assert_eq!(DropletImage::default(), resp[0].image);
+        assert_eq!(DropletNetworks::default(), resp[0].networks);
+        assert!(resp[0].tags.is_empty());
+        _m.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_get_droplets_paginated() {
+        let mut server = mockito::Server::new_async().await;
         let _m = server
             .mock("GET", "/v2/droplets")
             .match_header("Authorization", "Bearer foo")
@@ -597,7 +843,8 @@ mod test {
                 }))
                 .unwrap(),
             )
-            .create();
+            .create_async()
+            .await;
         let _m_page2 = server
             .mock("GET", "/v2/droplets?page=2")
             .match_header("Authorization", "Bearer foo")
@@ -615,13 +862,281 @@ mod test {
                 }))
                 .unwrap(),
             )
-            .create();
+            .create_async()
+            .await;
+
+        let client = DigitalOceanClient::new_for_test("foo".to_string(), server.url());
+        let resp = client.droplet.get_droplets().await;
+        assert_eq!(Ok(vec![get_droplet_1_obj(), get_droplet_2_obj()]), resp);
+        assert_eq!(2, client.stats().calls());
+        assert_eq!(2, client.stats().pages());
+        assert!(client.stats().bytes() > 0);
+        _m.assert_async().await;
+        _m_page2.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_get_droplets_page_limit() {
+        let mut server = mockito::Server::new_async().await;
+        let _m = server
+            .mock("GET", "/v2/droplets")
+            .match_header("Authorization", "Bearer foo")
+            .with_status(200)
+            .with_header("Content-Type", "application/json")
+            .with_body(
+                serde_json::to_string(&json!({
+                    "droplets": [
+                        get_droplet_1_json(),
+                    ],
+                    "meta": {
+                        "total": 2
+                    },
+                    "links": {
+                        "pages": {
+                            "next": format!("{}/v2/droplets?page=2", server.url())
+                        }
+                    }
+                }))
+                .unwrap(),
+            )
+            .create_async()
+            .await;
+        let _m_page2 = server
+            .mock("GET", "/v2/droplets?page=2")
+            .expect(0)
+            .create_async()
+            .await;
+
+        let resp = DigitalOceanClient::new_for_client(
+            DigitalOceanApiClient::new_for_test("foo".to_string(), server.url())
+                .with_max_pages(Some(1)),
+        )
+        .droplet
+        .get_droplets()
+        .await;
+        assert_eq!(
+            Err(Error::PageLimit(
+                "Listing droplets exceeded the limit of 1 page(s)".to_string()
+            )),
+            resp
+        );
+        _m.assert_async().await;
+        _m_page2.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_get_droplets_resumes_rate_limited_page() {
+        let mut server = mockito::Server::new_async().await;
+        let _m = server
+            .mock("GET", "/v2/droplets")
+            .with_status(200)
+            .with_header("Content-Type", "application/json")
+            .with_body(
+                serde_json::to_string(&json!({
+                    "droplets": [
+                        get_droplet_1_json(),
+                    ],
+                    "meta": {
+                        "total": 2
+                    },
+                    "links": {
+                        "pages": {
+                            "next": format!("{}/v2/droplets?page=2", server.url())
+                        }
+                    }
+                }))
+                .unwrap(),
+            )
+            .expect(1)
+            .create_async()
+            .await;
+        // more 429s than `send` will retry, so the page has to be resumed
+        let _m_limited = server
+            .mock("GET", "/v2/droplets?page=2")
+            .with_status(429)
+            .with_header("Retry-After", "0")
+            .expect(4)
+            .create_async()
+            .await;
+        let _m_page2 = server
+            .mock("GET", "/v2/droplets?page=2")
+            .with_status(200)
+            .with_header("Content-Type", "application/json")
+            .with_body(
+                serde_json::to_string(&json!({
+                    "droplets": [
+                        get_droplet_2_json(),
+                    ],
+                    "meta": {
+                        "total": 2
+                    },
+                    "links": {}
+                }))
+                .unwrap(),
+            )
+            .create_async()
+            .await;
+
+        let client = DigitalOceanClient::new_for_test("foo".to_string(), server.url());
+        let resp = client.droplet.get_droplets().await;
+        assert_eq!(Ok(vec![get_droplet_1_obj(), get_droplet_2_obj()]), resp);
+        assert_eq!(2, client.stats().pages());
+        _m.assert_async().await;
+        _m_limited.assert_async().await;
+        _m_page2.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_get_droplets_reports_partial_progress() {
+        let mut server = mockito::Server::new_async().await;
+        let _m = server
+            .mock("GET", "/v2/droplets")
+            .with_status(200)
+            .with_header("Content-Type", "application/json")
+            .with_body(
+                serde_json::to_string(&json!({
+                    "droplets": [
+                        get_droplet_1_json(),
+                    ],
+                    "meta": {
+                        "total": 2
+                    },
+                    "links": {
+                        "pages": {
+                            "next": format!("{}/v2/droplets?page=2", server.url())
+                        }
+                    }
+                }))
+                .unwrap(),
+            )
+            .create_async()
+            .await;
+        let _m_page2 = server
+            .mock("GET", "/v2/droplets?page=2")
+            .with_status(429)
+            .with_header("Retry-After", "0")
+            .create_async()
+            .await;
 
         let resp = DigitalOceanClient::new_for_test("foo".to_string(), server.url())
             .droplet
-            .get_droplets();
-        assert_eq!(Ok(vec![get_droplet_1_obj(), get_droplet_2_obj()]), resp);
-        _m.assert();
-        _m_page2.assert();
+            .get_droplets()
+            .await;
+        let Err(Error::Listing {
+            kind,
+            pages,
+            fetched,
+            total,
+            source,
+        }) = resp
+        else {
+            panic!("Expected the listing to fail part way, got {resp:?}")
+        };
+        assert_eq!(
+            ("droplets", 1, 1, 2),
+            (kind.as_str(), pages, fetched, total)
+        );
+        assert_eq!(
+            Some(429),
+            match *source {
+                Error::Request(ref e) => e.status,
+                _ => None,
+            }
+        );
+        // rate limiting is transient, so the run can be retried
+        assert!(source.is_retriable());
+        _m.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_get_droplet_summaries_concurrent_pages() {
+        let mut server = mockito::Server::new_async().await;
+        let page = |id: u32, next: Option<String>| {
+            serde_json::to_string(&json!({
+                "droplets": [{"id": id, "name": format!("droplet-{id}")}],
+                "meta": {
+                    "total": 3
+                },
+                "links": {
+                    "pages": {
+                        "next": next
+                    }
+                }
+            }))
+            .unwrap()
+        };
+        let _m = server
+            .mock("GET", "/v2/droplets")
+            .match_query(mockito::Matcher::Missing)
+            .match_header("Authorization", "Bearer foo")
+            .with_status(200)
+            .with_header("Content-Type", "application/json")
+            .with_body(page(
+                1,
+                Some(format!("{}/v2/droplets?page=2&per_page=1", server.url())),
+            ))
+            .create_async()
+            .await;
+        let mut later_pages = Vec::new();
+        for id in [2, 3] {
+            later_pages.push(
+                server
+                    .mock("GET", "/v2/droplets")
+                    .match_query(mockito::Matcher::AllOf(vec![
+                        mockito::Matcher::UrlEncoded("page".to_string(), id.to_string()),
+                        mockito::Matcher::UrlEncoded("per_page".to_string(), "1".to_string()),
+                    ]))
+                    .match_header("Authorization", "Bearer foo")
+                    .with_status(200)
+                    .with_header("Content-Type", "application/json")
+                    .with_body(page(
+                        id,
+                        (id < 3).then(|| format!("{}/v2/droplets?page=3", server.url())),
+                    ))
+                    .create_async()
+                    .await,
+            );
+        }
+
+        let resp = DigitalOceanClient::new_for_client(
+            DigitalOceanApiClient::new_for_test("foo".to_string(), server.url())
+                .with_page_concurrency(4),
+        )
+        .droplet
+        .get_droplet_summaries()
+        .await;
+        assert_eq!(
+            Ok(vec![1, 2, 3]),
+            resp.map(|droplets| droplets.iter().map(|d| d.id).collect::<Vec<_>>())
+        );
+        _m.assert_async().await;
+        for m in later_pages {
+            m.assert_async().await;
+        }
+    }
+
+    #[test]
+    fn test_public_ips() {
+        let mut droplet = get_droplet_1_obj();
+        assert_eq!(Some("1.2.3.4"), droplet.public_ipv4());
+        assert_eq!(None, droplet.public_ipv6());
+
+        droplet.networks.v4.insert(
+            0,
+            DropletNetworkV4 {
+                ip_address: "10.0.0.2".to_string(),
+                netmask: "255.255.0.0".to_string(),
+                gateway: None,
+                typ: "private".to_string(),
+            },
+        );
+        droplet.networks.v6.push(DropletNetworkV6 {
+            ip_address: "2604:a880::1".to_string(),
+            netmask: 64,
+            gateway: Some("2604:a880::".to_string()),
+            typ: "public".to_string(),
+        });
+        assert_eq!(Some("1.2.3.4"), droplet.public_ipv4());
+        assert_eq!(Some("2604:a880::1"), droplet.public_ipv6());
     }
 }
@@ -1,42 +1,334 @@
-#[derive(Debug)]
+use reqwest::Response;
+use serde::Serialize;
+
+use crate::digitalocean::api::ErrorResponse;
+
+#[derive(thiserror::Error, Debug, Clone, PartialEq)]
 #[allow(dead_code)]
 pub enum Error {
-    Request(reqwest::Error),
-    IpParse(std::net::AddrParseError),
-    UpdateDns(String),
-    CreateDns(String),
-    DeleteFirewallRule(String),
-    CreateFirewallRule(String),
+    #[error("request failed: {0}")]
+    Request(RequestError),
+    #[error("invalid IP address: {0}")]
+    IpParse(#[from] std::net::AddrParseError),
+    #[error("unable to update DNS record: {0}")]
+    UpdateDns(Failure),
+    #[error("unable to create DNS record: {0}")]
+    CreateDns(Failure),
+    #[error("unable to delete DNS record: {0}")]
+    DeleteDns(Failure),
+    #[error("unable to create domain: {0}")]
+    CreateDomain(Failure),
+    #[error("unable to delete firewall rules: {0}")]
+    DeleteFirewallRule(Failure),
+    #[error("unable to add firewall rules: {0}")]
+    CreateFirewallRule(Failure),
+    #[error("unable to update firewall: {0}")]
+    UpdateFirewall(Failure),
+    #[error("unable to assign droplets to firewall: {0}")]
+    AssignFirewallDroplets(Failure),
+    #[error("unable to remove droplets from firewall: {0}")]
+    UnassignFirewallDroplets(Failure),
+    #[error("unable to assign reserved IP: {0}")]
+    AssignReservedIp(Failure),
+    #[error("{0}")]
+    PageLimit(String),
+    /// Fetching a page of a listing failed after earlier pages had been fetched
+    #[error(
+        "listing {kind} failed after fetching {fetched} of {total} in {pages} page(s): {source}"
+    )]
+    Listing {
+        kind: String,
+        /// How many pages had been fetched
+        pages: u32,
+        /// How many objects those pages held
+        fetched: usize,
+        /// How many objects the listing holds in total
+        total: u32,
+        source: Box<Error>,
+    },
+    /// The API did not accept the token, or the token lacks a scope the request needs
+    #[error("{}", unauthorized_message(.0))]
+    Unauthorized(ApiError),
 }
 
-impl From<reqwest::Error> for Error {
-    fn from(e: reqwest::Error) -> Self {
-        Error::Request(e)
+/// Explain an authentication or authorization failure and what token the requests need
+fn unauthorized_message(error: &ApiError) -> String {
+    let diagnosis = if error.status == 403 {
+        "the API token is not allowed to make this request"
+    } else {
+        "the API token is invalid, expired, or revoked"
+    };
+    format!(
+        "{diagnosis} ({error}); use a token with read and write access to domains and firewalls \
+         (the domain:read, domain:update, domain:create, firewall:read, and firewall:update scopes)"
+    )
+}
+
+/// Why a change requested from the API was not made
+#[derive(thiserror::Error, Debug, Clone, Eq, PartialEq)]
+pub enum Failure {
+    /// The API responded to the request with an error
+    #[error(transparent)]
+    Rejected(ApiError),
+    /// The API accepted the request, but what it returned does not reflect the change
+    #[error("{0}")]
+    Unexpected(String),
+}
+
+/// The error response of a request the API rejected, with the reason DigitalOcean gave for it
+#[derive(thiserror::Error, Serialize, Debug, Clone, Eq, PartialEq)]
+pub struct ApiError {
+    /// The HTTP status code of the response.
+    pub status: u16,
+    /// DigitalOcean's identifier of the kind of error, such as `unprocessable_entity`.
+    pub id: Option<String>,
+    /// DigitalOcean's description of what was wrong with the request.
+    pub message: Option<String>,
+    /// The ID DigitalOcean support can use to look up the request.
+    pub request_id: Option<String>,
+    /// The URL of the rejected request.
+    pub url: String,
+}
+
+impl ApiError {
+    /// Capture the error response to a request.  A body that is not the usual error object leaves
+    /// the id and message empty rather than failing.
+    pub async fn from_response(resp: Response) -> ApiError {
+        let status = resp.status().as_u16();
+        let url = resp.url().to_string();
+        match resp.json::<ErrorResponse>().await {
+            Ok(body) => ApiError {
+                status,
+                id: Some(body.id),
+                message: Some(body.message),
+                request_id: body.request_id,
+                url,
+            },
+            Err(_) => ApiError {
+                status,
+                id: None,
+                message: None,
+                request_id: None,
+                url,
+            },
+        }
+    }
+}
+
+impl std::fmt::Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "API responded with status {}", self.status)?;
+        match (&self.id, &self.message) {
+            (Some(id), Some(message)) => write!(f, " ({id}): {message}")?,
+            (Some(id), None) => write!(f, " ({id})")?,
+            (None, Some(message)) => write!(f, ": {message}")?,
+            (None, None) => (),
+        }
+        write!(f, " [{}]", self.url)?;
+        if let Some(ref request_id) = self.request_id {
+            write!(f, " (request ID {request_id})")?;
+        }
+        Ok(())
     }
 }
 
-impl From<std::net::AddrParseError> for Error {
-    fn from(e: std::net::AddrParseError) -> Self {
-        Error::IpParse(e)
+/// An owned snapshot of the details of a failed HTTP request, so that request failures can be
+/// compared, logged, and serialized without holding on to the underlying `reqwest::Error`.
+#[derive(Serialize, Debug, Clone, Eq, PartialEq)]
+pub struct RequestError {
+    /// The HTTP status code returned by the server, if a response was received.
+    pub status: Option<u16>,
+    /// The URL of the request that failed, if known.
+    pub url: Option<String>,
+    /// A human-readable description of the failure.
+    pub message: String,
+    /// The broad category of the failure.
+    pub kind: RequestErrorKind,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, Eq, PartialEq)]
+pub enum RequestErrorKind {
+    /// Unable to establish a connection to the server.
+    Connect,
+    /// The request or response did not complete in time.
+    Timeout,
+    /// The server responded with an error status code.
+    Status,
+    /// The response body could not be decoded.
+    Decode,
+    /// Any other failure, such as an invalid request.
+    Other,
+}
+
+impl RequestError {
+    /// Whether retrying the same request later could reasonably succeed.  Network failures,
+    /// timeouts, rate limiting (429), and server-side (5xx) errors are retriable; client errors
+    /// such as failed validation or authentication are not.
+    pub fn is_retriable(&self) -> bool {
+        match self.kind {
+            RequestErrorKind::Connect | RequestErrorKind::Timeout => true,
+            RequestErrorKind::Status => self.status.is_some_and(is_retriable_status),
+            RequestErrorKind::Decode | RequestErrorKind::Other => false,
+        }
+    }
+}
+
+/// Whether an HTTP status code indicates a transient failure.
+pub fn is_retriable_status(status: u16) -> bool {
+    status == 429 || (500..600).contains(&status)
+}
+
+impl From<reqwest::Error> for RequestError {
+    fn from(e: reqwest::Error) -> Self {
+        RequestError {
+            status: e.status().map(|s| s.as_u16()),
+            url: e.url().map(|u| u.to_string()),
+            message: e.to_string(),
+            kind: if e.is_timeout() {
+                RequestErrorKind::Timeout
+            } else if e.is_connect() {
+                RequestErrorKind::Connect
+            } else if e.is_status() {
+                RequestErrorKind::Status
+            } else if e.is_decode() {
+                RequestErrorKind::Decode
+            } else {
+                RequestErrorKind::Other
+            },
+        }
     }
 }
 
-impl std::fmt::Display for Error {
+impl std::fmt::Display for RequestError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{self:?}")
+        write!(f, "{}", self.message)?;
+        if let Some(status) = self.status {
+            write!(f, " (status {status})")?;
+        }
+        if let Some(ref url) = self.url {
+            write!(f, " [{url}]")?;
+        }
+        Ok(())
     }
 }
 
-impl PartialEq for Error {
-    fn eq(&self, other: &Self) -> bool {
-        match (self, other) {
-            (Self::Request(_), Self::Request(_)) => false,
-            (Self::IpParse(e1), Self::IpParse(e2)) => e1.to_string() == e2.to_string(),
-            (Self::UpdateDns(e1), Self::UpdateDns(e2)) => e1 == e2,
-            (Self::CreateDns(e1), Self::CreateDns(e2)) => e1 == e2,
-            (Self::DeleteFirewallRule(e1), Self::DeleteFirewallRule(e2)) => e1 == e2,
-            (Self::CreateFirewallRule(e1), Self::CreateFirewallRule(e2)) => e1 == e2,
+impl From<reqwest::Error> for Error {
+    fn from(e: reqwest::Error) -> Self {
+        Error::Request(e.into())
+    }
+}
+
+impl Error {
+    /// Whether the operation that produced this error could reasonably succeed if attempted again.
+    pub fn is_retriable(&self) -> bool {
+        match self {
+            Error::Request(e) => e.is_retriable(),
+            Error::Listing { source, .. } => source.is_retriable(),
+            // a change the API turned away because of rate limiting or a server-side error
+            Error::UpdateDns(Failure::Rejected(api))
+            | Error::CreateDns(Failure::Rejected(api))
+            | Error::DeleteDns(Failure::Rejected(api))
+            | Error::CreateDomain(Failure::Rejected(api))
+            | Error::DeleteFirewallRule(Failure::Rejected(api))
+            | Error::CreateFirewallRule(Failure::Rejected(api))
+            | Error::UpdateFirewall(Failure::Rejected(api))
+            | Error::AssignFirewallDroplets(Failure::Rejected(api))
+            | Error::UnassignFirewallDroplets(Failure::Rejected(api))
+            | Error::AssignReservedIp(Failure::Rejected(api)) => is_retriable_status(api.status),
             _ => false,
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use crate::digitalocean::error::{ApiError, Error, Failure, RequestError, RequestErrorKind};
+
+    fn rejected(status: u16) -> Failure {
+        Failure::Rejected(ApiError {
+            status,
+            id: None,
+            message: None,
+            request_id: None,
+            url: "https://api.digitalocean.com/v2/firewalls/fw1".to_string(),
+        })
+    }
+
+    fn request_error(kind: RequestErrorKind, status: Option<u16>) -> Error {
+        Error::Request(RequestError {
+            status,
+            url: None,
+            message: "foo".to_string(),
+            kind,
+        })
+    }
+
+    #[test]
+    fn test_retriable_network() {
+        assert!(request_error(RequestErrorKind::Connect, None).is_retriable());
+        assert!(request_error(RequestErrorKind::Timeout, None).is_retriable());
+    }
+
+    #[test]
+    fn test_retriable_status() {
+        assert!(request_error(RequestErrorKind::Status, Some(429)).is_retriable());
+        assert!(request_error(RequestErrorKind::Status, Some(500)).is_retriable());
+        assert!(request_error(RequestErrorKind::Status, Some(503)).is_retriable());
+        assert!(!request_error(RequestErrorKind::Status, Some(400)).is_retriable());
+        assert!(!request_error(RequestErrorKind::Status, Some(401)).is_retriable());
+        assert!(!request_error(RequestErrorKind::Status, Some(404)).is_retriable());
+        assert!(Error::UpdateFirewall(rejected(503)).is_retriable());
+        assert!(Error::CreateDns(rejected(429)).is_retriable());
+    }
+
+    #[test]
+    fn test_not_retriable() {
+        assert!(!request_error(RequestErrorKind::Decode, None).is_retriable());
+        assert!(!request_error(RequestErrorKind::Other, None).is_retriable());
+        assert!(!Error::UpdateDns(Failure::Unexpected("foo".to_string())).is_retriable());
+        assert!(!Error::DeleteFirewallRule(Failure::Unexpected("foo".to_string())).is_retriable());
+        assert!(!Error::UpdateFirewall(rejected(422)).is_retriable());
+        assert!(!Error::UpdateDns(rejected(422)).is_retriable());
+    }
+
+    #[test]
+    fn test_api_error_display() {
+        let rejected = Error::UpdateFirewall(Failure::Rejected(ApiError {
+            status: 422,
+            id: Some("unprocessable_entity".to_string()),
+            message: Some("invalid port".to_string()),
+            request_id: Some("abc123".to_string()),
+            url: "https://api.digitalocean.com/v2/firewalls/fw1".to_string(),
+        }));
+        assert_eq!(
+            "unable to update firewall: API responded with status 422 (unprocessable_entity): \
+             invalid port [https://api.digitalocean.com/v2/firewalls/fw1] (request ID abc123)",
+            rejected.to_string()
+        );
+
+        let no_body = Error::CreateDns(Failure::Rejected(ApiError {
+            status: 502,
+            id: None,
+            message: None,
+            request_id: None,
+            url: "https://api.digitalocean.com/v2/domains/example.com/records".to_string(),
+        }));
+        assert_eq!(
+            "unable to create DNS record: API responded with status 502 \
+             [https://api.digitalocean.com/v2/domains/example.com/records]",
+            no_body.to_string()
+        );
+
+        let forbidden = Error::Unauthorized(ApiError {
+            status: 403,
+            id: Some("forbidden".to_string()),
+            message: Some("You are not authorized to perform this operation".to_string()),
+            request_id: None,
+            url: "https://api.digitalocean.com/v2/firewalls".to_string(),
+        });
+        assert!(forbidden
+            .to_string()
+            .starts_with("the API token is not allowed to make this request"));
+        assert!(forbidden.to_string().contains("firewall:update"));
+    }
+}
@@ -1,13 +1,22 @@
-use crate::digitalocean::api::{DigitalOceanApiClient, ErrorResponse, Links, Meta};
-use crate::digitalocean::error::Error;
+use crate::digitalocean::api::{lenient, DigitalOceanApiClient, Links, Meta};
+use crate::digitalocean::error::{ApiError, Error, Failure};
+use async_trait::async_trait;
 use reqwest::{Method, StatusCode};
 use serde::{Deserialize, Serialize};
 use tracing::info;
 
+/// The value of `ports` for a rule that covers every port of its protocol.
+pub const ALL_PORTS: &str = "0";
+
+#[async_trait(?Send)]
 pub trait DigitalOceanFirewallClient {
-    fn get_firewall(&self, name: String) -> Result<Option<Firewall>, Error>;
+    async fn get_firewalls(&self) -> Result<Vec<Firewall>, Error>;
+
+    async fn get_firewall(&self, name: String) -> Result<Option<Firewall>, Error>;
 
-    fn delete_firewall_rule(
+    async fn get_firewall_by_id(&self, id: &str) -> Result<Option<Firewall>, Error>;
+
+    async fn delete_firewall_rule(
         &self,
         id: &str,
         inbound_rules: Option<Vec<FirewallInboundRule>>,
@@ -15,13 +24,30 @@ pub trait DigitalOceanFirewallClient {
         dry_run: &bool,
     ) -> Result<(), Error>;
 
-    fn add_firewall_rule(
+    async fn add_firewall_rule(
         &self,
         id: &str,
         inbound_rules: Option<Vec<FirewallInboundRule>>,
         outbound_rules: Option<Vec<FirewallOutboundRule>>,
         dry_run: &bool,
     ) -> Result<(), Error>;
+
+    async fn update_firewall(&self, firewall: &Firewall, dry_run: &bool)
+        -> Result<Firewall, Error>;
+
+    async fn assign_droplets(
+        &self,
+        id: &str,
+        droplet_ids: &[u32],
+        dry_run: &bool,
+    ) -> Result<(), Error>;
+
+    async fn unassign_droplets(
+        &self,
+        id: &str,
+        droplet_ids: &[u32],
+        dry_run: &bool,
+    ) -> Result<(), Error>;
 }
 
 pub struct DigitalOceanFirewallClientImpl {
@@ -32,22 +58,84 @@ impl DigitalOceanFirewallClientImpl {
     pub fn new(api: DigitalOceanApiClient) -> DigitalOceanFirewallClientImpl {
         DigitalOceanFirewallClientImpl { api }
     }
+
+    /// Send a request to the droplets of the firewall identified by `id`, which either attaches or
+    /// detaches them depending on the method.
+    async fn send_droplets(
+        &self,
+        method: Method,
+        id: &str,
+        droplet_ids: &[u32],
+        error: fn(Failure) -> Error,
+    ) -> Result<(), Error> {
+        let url = self
+            .api
+            .get_url(format!("/v2/firewalls/{}/droplets", id).as_str());
+
+        let request = self
+            .api
+            .get_request_builder(method, url)
+            .json(&FirewallDropletsBody {
+                droplet_ids: droplet_ids.to_vec(),
+            });
+        let resp = self.api.send(request).await?;
+        match resp.status() {
+            StatusCode::NO_CONTENT => Ok(()),
+            _ => Err(error(Failure::Rejected(
+                ApiError::from_response(resp).await,
+            ))),
+        }
+    }
 }
 
+#[async_trait(?Send)]
 impl DigitalOceanFirewallClient for DigitalOceanFirewallClientImpl {
+    /// Get the current configuration of every firewall.
+    async fn get_firewalls(&self) -> Result<Vec<Firewall>, Error> {
+        self.api
+            .get_all_objects(
+                self.api.get_url("/v2/firewalls"),
+                |r: FirewallsResp| r.firewalls,
+                |r: &FirewallsResp| r.links.clone(),
+                |r: &FirewallsResp| &r.meta,
+            )
+            .await
+    }
+
     /// Get the named firewall's current configuration.
-    fn get_firewall(&self, name: String) -> Result<Option<Firewall>, Error> {
-        self.api.get_object_by_name(
-            name.as_str(),
-            self.api.get_url("/v2/firewalls"),
-            |r: FirewallsResp| r.firewalls,
-            |r: &FirewallsResp| r.links.clone(),
-            |t: &Firewall, name: &str| t.name == *name,
-        )
+    async fn get_firewall(&self, name: String) -> Result<Option<Firewall>, Error> {
+        self.api
+            .get_object_by_name(
+                name.as_str(),
+                self.api.get_url("/v2/firewalls"),
+                |r: FirewallsResp| r.firewalls,
+                |r: &FirewallsResp| r.links.clone(),
+                |r: &FirewallsResp| &r.meta,
+                |t: &Firewall, name: &str| t.name == *name,
+            )
+            .await
+    }
+
+    /// Get the current configuration of the firewall identified by `id`, without paging through
+    /// every firewall on the account.
+    async fn get_firewall_by_id(&self, id: &str) -> Result<Option<Firewall>, Error> {
+        let url = self.api.get_url(format!("/v2/firewalls/{}", id).as_str());
+        let request = self.api.get_request_builder(Method::GET, url);
+        let resp = self.api.send(request).await?;
+        if resp.status() == StatusCode::NOT_FOUND {
+            Ok(None)
+        } else {
+            Ok(Some(
+                resp.error_for_status()?
+                    .json::<FirewallResp>()
+                    .await?
+                    .firewall,
+            ))
+        }
     }
 
     /// Delete the provided rules from the firewall identified by `id`.
-    fn delete_firewall_rule(
+    async fn delete_firewall_rule(
         &self,
         id: &str,
         inbound_rules: Option<Vec<FirewallInboundRule>>,
@@ -65,30 +153,26 @@ impl DigitalOceanFirewallClient for DigitalOceanFirewallClientImpl {
                 .api
                 .get_url(format!("/v2/firewalls/{}/rules", id).as_str());
 
-            let resp = self
-                .api
-                .get_request_builder(Method::DELETE, url)
-                .json(&FirewallRuleBody {
-                    inbound_rules,
-                    outbound_rules,
-                })
-                .send()?;
+            let request =
+                self.api
+                    .get_request_builder(Method::DELETE, url)
+                    .json(&FirewallRuleBody {
+                        inbound_rules,
+                        outbound_rules,
+                    });
+            let resp = self.api.send(request).await?;
             match resp.status() {
                 StatusCode::NO_CONTENT => Ok(()),
-                code => {
-                    let error = resp.json::<ErrorResponse>()?;
-                    Err(Error::DeleteFirewallRule(format!(
-                        "Got unexpected HTTP error from API ({}): {:?}",
-                        code, error
-                    )))
-                }
+                _ => Err(Error::DeleteFirewallRule(Failure::Rejected(
+                    ApiError::from_response(resp).await,
+                ))),
             }
         }
     }
 
     /// Add rules to the firewall identified by `id`.  Note that rules are defined by their entire
     /// definition, so calling this will never overwrite an existing rule.
-    fn add_firewall_rule(
+    async fn add_firewall_rule(
         &self,
         id: &str,
         inbound_rules: Option<Vec<FirewallInboundRule>>,
@@ -106,26 +190,112 @@ impl DigitalOceanFirewallClient for DigitalOceanFirewallClientImpl {
                 .api
                 .get_url(format!("/v2/firewalls/{}/rules", id).as_str());
 
-            let resp = self
+            let request = self
                 .api
                 .get_request_builder(Method::POST, url)
                 .json(&FirewallRuleBody {
                     inbound_rules,
                     outbound_rules,
-                })
-                .send()?;
+                });
+            let resp = self.api.send(request).await?;
             match resp.status() {
                 StatusCode::NO_CONTENT => Ok(()),
-                code => {
-                    let error = resp.json::<ErrorResponse>()?;
-                    Err(Error::CreateFirewallRule(format!(
-                        "Got unexpected HTTP error from API ({}): {:?}",
-                        code, error
-                    )))
-                }
+                _ => Err(Error::CreateFirewallRule(Failure::Rejected(
+                    ApiError::from_response(resp).await,
+                ))),
+            }
+        }
+    }
+
+    /// Replace the whole configuration of a firewall, including every one of its rules, in a single
+    /// request.  Unlike deleting and then adding rules, the firewall is never left without a rule
+    /// if the request fails.  A firewall whose droplets or tags could not be read is refused, as
+    /// replacing its configuration would detach every droplet and tag from it.
+    async fn update_firewall(
+        &self,
+        firewall: &Firewall,
+        dry_run: &bool,
+    ) -> Result<Firewall, Error> {
+        let (Some(droplet_ids), Some(tags)) = (&firewall.droplet_ids, &firewall.tags) else {
+            return Err(Error::UpdateFirewall(Failure::Unexpected(format!(
+                "the droplets or tags of firewall {} could not be read, so replacing its \
+                    configuration would detach them",
+                firewall.id
+            ))));
+        };
+        if *dry_run {
+            info!(
+                "DRY RUN: Replacing rules of firewall {}\ninbound: {:#?}\noutbound: {:#?}",
+                firewall.id, firewall.inbound_rules, firewall.outbound_rules
+            );
+            Ok(firewall.clone())
+        } else {
+            let url = self
+                .api
+                .get_url(format!("/v2/firewalls/{}", firewall.id).as_str());
+
+            let request = self
+                .api
+                .get_request_builder(Method::PUT, url)
+                .json(&FirewallPutBody {
+                    name: firewall.name.clone(),
+                    droplet_ids: droplet_ids.clone(),
+                    tags: tags.clone(),
+                    inbound_rules: firewall.inbound_rules.clone().unwrap_or_default(),
+                    outbound_rules: firewall.outbound_rules.clone().unwrap_or_default(),
+                });
+            let resp = self.api.send(request).await?;
+            match resp.status() {
+                StatusCode::OK => Ok(resp.json::<FirewallResp>().await?.firewall),
+                _ => Err(Error::UpdateFirewall(Failure::Rejected(
+                    ApiError::from_response(resp).await,
+                ))),
             }
         }
     }
+
+    /// Attach droplets to the firewall identified by `id`, so that its rules apply to them.
+    async fn assign_droplets(
+        &self,
+        id: &str,
+        droplet_ids: &[u32],
+        dry_run: &bool,
+    ) -> Result<(), Error> {
+        if *dry_run {
+            info!(
+                "DRY RUN: Assigning droplets {:?} to firewall {}",
+                droplet_ids, id
+            );
+            Ok(())
+        } else {
+            self.send_droplets(Method::POST, id, droplet_ids, Error::AssignFirewallDroplets)
+                .await
+        }
+    }
+
+    /// Detach droplets from the firewall identified by `id`.
+    async fn unassign_droplets(
+        &self,
+        id: &str,
+        droplet_ids: &[u32],
+        dry_run: &bool,
+    ) -> Result<(), Error> {
+        if *dry_run {
+            info!(
+                "DRY RUN: Removing droplets {:?} from firewall {}",
+                droplet_ids, id
+            );
+            Ok(())
+        } else {
+            self.send_droplets(
+                Method::DELETE,
+                id,
+                droplet_ids,
+                Error::UnassignFirewallDroplets,
+            )
+            .await
+        }
+    }
 }
 
 // /v2/firewalls
@@ -133,11 +303,17 @@ impl DigitalOceanFirewallClient for DigitalOceanFirewallClientImpl {
 #[derive(Deserialize, Debug)]
 struct FirewallsResp {
     firewalls: Vec<Firewall>,
-    #[allow(dead_code)]
     meta: Meta,
     links: Links,
 }
 
+// /v2/firewalls/{id}
+
+#[derive(Deserialize, Debug)]
+struct FirewallResp {
+    firewall: Firewall,
+}
+
 #[derive(Deserialize, Debug, Eq, PartialEq, Clone)]
 #[allow(dead_code)]
 pub struct Firewall {
@@ -145,21 +321,26 @@ pub struct Firewall {
     pub id: String,
     /// A status string indicating the current state of the firewall. This can be "waiting",
     /// "succeeded", or "failed".
+    #[serde(default, deserialize_with = "lenient")]
     pub status: String,
     /// A time value given in ISO8601 combined date and time format that represents when the
     /// firewall was created.
+    #[serde(default, deserialize_with = "lenient")]
     pub created_at: String,
     /// An array of objects each containing the fields "droplet_id", "removing", and "status". It is
     /// provided to detail exactly which Droplets are having their security policies updated. When
     /// empty, all changes have been successfully applied.
+    #[serde(default, deserialize_with = "lenient")]
     pub pending_changes: Vec<FirewallPendingChange>,
     /// A human-readable name for a firewall. The name must begin with an alphanumeric character.
     /// Subsequent characters must either be alphanumeric characters, a period (.), or a dash (-).
     pub name: String,
     /// An array containing the IDs of the Droplets assigned to the firewall.
+    #[serde(default, deserialize_with = "lenient")]
     pub droplet_ids: Option<Vec<u32>>,
     /// A flat array of tag names as strings to be applied to the resource. Tag names may be for
     /// either existing or new tags.
+    #[serde(default, deserialize_with = "lenient")]
     pub tags: Option<Vec<String>>,
     pub inbound_rules: Option<Vec<FirewallInboundRule>>,
     pub outbound_rules: Option<Vec<FirewallOutboundRule>>,
@@ -230,11 +411,29 @@ pub struct FirewallRuleBody {
     pub outbound_rules: Option<Vec<FirewallOutboundRule>>,
 }
 
+/// The full configuration of a firewall, as accepted by `PUT /v2/firewalls/{id}`
+#[derive(Serialize, Debug, Eq, PartialEq)]
+struct FirewallPutBody {
+    name: String,
+    droplet_ids: Vec<u32>,
+    tags: Vec<String>,
+    inbound_rules: Vec<FirewallInboundRule>,
+    outbound_rules: Vec<FirewallOutboundRule>,
+}
+
+// /v2/firewalls/{id}/droplets
+
+#[derive(Serialize, Debug, Eq, PartialEq)]
+struct FirewallDropletsBody {
+    droplet_ids: Vec<u32>,
+}
+
 #[cfg(test)]
 mod test {
     use mockito;
     use reqwest::StatusCode;
 
+    use crate::digitalocean::error::{ApiError, Error, Failure};
     use crate::digitalocean::firewall::{Firewall, FirewallInboundRule, FirewallRuleTarget};
     use crate::digitalocean::DigitalOceanClient;
 
@@ -314,9 +513,46 @@ mod test {
         })
     }
 
-    #[test]
-    fn test_get_firewall() {
-        let mut server = mockito::Server::new();
+    #[tokio::test]
+    async fn test_get_firewalls() {
+        let mut server = mockito::Server::new_async().await;
+        let _m = server
+            .mock("GET", "/v2/firewalls")
+            .match_header("Authorization", "Bearer foo")
+            .with_status(200)
+            .with_header("Content-Type", "application/json")
+            .with_body(
+                serde_json::to_string(&json!({
+                    "firewalls": [
+                        get_firewall_1_json(),
+                        get_firewall_2_json(),
+                    ],
+                    "meta": {
+                        "total": 2
+                    },
+                    "links": {}
+                }))
+                .unwrap(),
+            )
+            .create_async()
+            .await;
+
+        let resp = DigitalOceanClient::new_for_test("foo".to_string(), server.url())
+            .firewall
+            .get_firewalls()
+            .await
+            .unwrap();
+        assert_eq!(
+            vec!["fw1", "fw2"],
+            resp.iter().map(|f| f.id.as_str()).collect::<Vec<_>>()
+        );
+        assert_eq!(get_firewall_2_obj(), resp[1]);
+        _m.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_get_firewall() {
+        let mut server = mockito::Server::new_async().await;
         let _m = server
             .mock("GET", "/v2/firewalls")
             .match_header("Authorization", "Bearer foo")
@@ -335,18 +571,20 @@ mod test {
                 }))
                 .unwrap(),
             )
-            .create();
+            .create_async()
+            .await;
 
         let resp = DigitalOceanClient::new_for_test("foo".to_string(), server.url())
             .firewall
-            .get_firewall("FW 2".to_string());
+            .get_firewall("FW 2".to_string())
+            .await;
         assert_eq!(Ok(Some(get_firewall_2_obj())), resp);
-        _m.assert();
+        _m.assert_async().await;
     }
 
-    #[test]
-    fn test_get_firewall_paginated() {
-        let mut server = mockito::Server::new();
+    #[tokio::test]
+    async fn test_get_firewall_paginated() {
+        let mut server = mockito::Server::new_async().await;
         let _m = server
             .mock("GET", "/v2/firewalls")
             .match_header("Authorization", "Bearer foo")
@@ -368,7 +606,8 @@ mod test {
                 }))
                 .unwrap(),
             )
-            .create();
+            .create_async()
+            .await;
         let _m_page2 = server
             .mock("GET", "/v2/firewalls?page=2")
             .match_header("Authorization", "Bearer foo")
@@ -386,19 +625,21 @@ mod test {
                 }))
                 .unwrap(),
             )
-            .create();
+            .create_async()
+            .await;
 
         let resp = DigitalOceanClient::new_for_test("foo".to_string(), server.url())
             .firewall
-            .get_firewall("FW 2".to_string());
+            .get_firewall("FW 2".to_string())
+            .await;
         assert_eq!(Ok(Some(get_firewall_2_obj())), resp);
-        _m.assert();
-        _m_page2.assert();
+        _m.assert_async().await;
+        _m_page2.assert_async().await;
     }
 
-    #[test]
-    fn test_get_firewall_missing() {
-        let mut server = mockito::Server::new();
+    #[tokio::test]
+    async fn test_get_firewall_missing() {
+        let mut server = mockito::Server::new_async().await;
         let _m = server
             .mock("GET", "/v2/firewalls")
             .match_header("Authorization", "Bearer foo")
@@ -414,18 +655,71 @@ mod test {
                 }))
                 .unwrap(),
             )
-            .create();
+            .create_async()
+            .await;
 
         let resp = DigitalOceanClient::new_for_test("foo".to_string(), server.url())
             .firewall
-            .get_firewall("FW 2".to_string());
+            .get_firewall("FW 2".to_string())
+            .await;
         assert_eq!(Ok(None), resp);
-        _m.assert();
+        _m.assert_async().await;
     }
 
-    #[test]
-    fn test_delete_firewall() {
-        let mut server = mockito::Server::new();
+    #[tokio::test]
+    async fn test_get_firewall_by_id() {
+        let mut server = mockito::Server::new_async().await;
+        let _m = server
+            .mock("GET", "/v2/firewalls/fw2")
+            .match_header("Authorization", "Bearer foo")
+            .with_status(200)
+            .with_header("Content-Type", "application/json")
+            .with_body(
+                serde_json::to_string(&json!({
+                    "firewall": get_firewall_2_json(),
+                }))
+                .unwrap(),
+            )
+            .create_async()
+            .await;
+
+        let resp = DigitalOceanClient::new_for_test("foo".to_string(), server.url())
+            .firewall
+            .get_firewall_by_id("fw2")
+            .await;
+        assert_eq!(Ok(Some(get_firewall_2_obj())), resp);
+        _m.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_get_firewall_by_id_missing() {
+        let mut server = mockito::Server::new_async().await;
+        let _m = server
+            .mock("GET", "/v2/firewalls/fw3")
+            .match_header("Authorization", "Bearer foo")
+            .with_status(404)
+            .with_header("Content-Type", "application/json")
+            .with_body(
+                serde_json::to_string(&json!({
+                    "id": "not_found",
+                    "message": "The resource you were accessing could not be found."
+                }))
+                .unwrap(),
+            )
+            .create_async()
+            .await;
+
+        let resp = DigitalOceanClient::new_for_test("foo".to_string(), server.url())
+            .firewall
+            .get_firewall_by_id("fw3")
+            .await;
+        assert_eq!(Ok(None), resp);
+        _m.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_delete_firewall() {
+        let mut server = mockito::Server::new_async().await;
         let _m = server
             .mock("DELETE", "/v2/firewalls/fw2/rules")
             .match_header("Authorization", "Bearer foo")
@@ -444,12 +738,13 @@ mod test {
                 }],
             })))
             .with_status(StatusCode::NO_CONTENT.as_u16() as usize)
-            .create();
+            .create_async()
+            .await;
 
         let resp = DigitalOceanClient::new_for_test("foo".to_string(), server.url())
             .firewall
             .delete_firewall_rule(
-                &"fw2",
+                "fw2",
                 Some(vec![FirewallInboundRule {
                     protocol: "tcp".to_string(),
                     ports: "443".to_string(),
@@ -463,14 +758,15 @@ mod test {
                 }]),
                 None,
                 &false,
-            );
+            )
+            .await;
         assert_eq!(Ok(()), resp);
-        _m.assert();
+        _m.assert_async().await;
     }
 
-    #[test]
-    fn test_create_firewall() {
-        let mut server = mockito::Server::new();
+    #[tokio::test]
+    async fn test_create_firewall() {
+        let mut server = mockito::Server::new_async().await;
         let _m = server
             .mock("POST", "/v2/firewalls/fw2/rules")
             .match_header("Authorization", "Bearer foo")
@@ -489,12 +785,13 @@ mod test {
                 }],
             })))
             .with_status(StatusCode::NO_CONTENT.as_u16() as usize)
-            .create();
+            .create_async()
+            .await;
 
         let resp = DigitalOceanClient::new_for_test("foo".to_string(), server.url())
             .firewall
             .add_firewall_rule(
-                &"fw2",
+                "fw2",
                 Some(vec![FirewallInboundRule {
                     protocol: "tcp".to_string(),
                     ports: "443".to_string(),
@@ -508,8 +805,176 @@ mod test {
                 }]),
                 None,
                 &false,
-            );
+            )
+            .await;
         assert_eq!(Ok(()), resp);
-        _m.assert();
+        _m.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_update_firewall() {
+        let mut server = mockito::Server::new_async().await;
+        let _m = server
+            .mock("PUT", "/v2/firewalls/fw2")
+            .match_header("Authorization", "Bearer foo")
+            .match_header("Content-Type", "application/json")
+            .match_body(mockito::Matcher::Json(json!({
+                "name": "FW 2",
+                "droplet_ids": [42],
+                "tags": ["foo"],
+                "inbound_rules": [{
+                    "protocol": "tcp",
+                    "ports": "80",
+                    "sources": {
+                        "addresses": ["8.8.8.8"],
+                    },
+                }],
+                "outbound_rules": [],
+            })))
+            .with_status(200)
+            .with_header("Content-Type", "application/json")
+            .with_body(
+                serde_json::to_string(&json!({
+                    "firewall": get_firewall_2_json(),
+                }))
+                .unwrap(),
+            )
+            .create_async()
+            .await;
+
+        let resp = DigitalOceanClient::new_for_test("foo".to_string(), server.url())
+            .firewall
+            .update_firewall(&get_firewall_2_obj(), &false)
+            .await;
+        assert_eq!(Ok(get_firewall_2_obj()), resp);
+        _m.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_update_firewall_rejected() {
+        let mut server = mockito::Server::new_async().await;
+        let _m = server
+            .mock("PUT", "/v2/firewalls/fw2")
+            .with_status(422)
+            .with_header("Content-Type", "application/json")
+            .with_body(
+                serde_json::to_string(&json!({
+                    "id": "unprocessable_entity",
+                    "message": "invalid rule"
+                }))
+                .unwrap(),
+            )
+            .create_async()
+            .await;
+
+        let resp = DigitalOceanClient::new_for_test("foo".to_string(), server.url())
+            .firewall
+            .update_firewall(&get_firewall_2_obj(), &false)
+            .await;
+        assert!(matches!(resp, Err(Error::UpdateFirewall(_))));
+        _m.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_update_firewall_refuses_unreadable_droplets() {
+        let mut firewall = get_firewall_2_json();
+        firewall["droplet_ids"] = json!("not a list");
+        let mut server = mockito::Server::new_async().await;
+        let _m_get = server
+            .mock("GET", "/v2/firewalls/fw2")
+            .with_status(200)
+            .with_header("Content-Type", "application/json")
+            .with_body(serde_json::to_string(&json!({ "firewall": firewall })).unwrap())
+            .create_async()
+            .await;
+        let _m_put = server
+            .mock("PUT", "/v2/firewalls/fw2")
+            .expect(0)
+            .create_async()
+            .await;
+
+        let client = DigitalOceanClient::new_for_test("foo".to_string(), server.url());
+        let firewall = client
+            .firewall
+            .get_firewall_by_id("fw2")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(None, firewall.droplet_ids);
+        let resp = client.firewall.update_firewall(&firewall, &false).await;
+        assert!(matches!(
+            resp,
+            Err(Error::UpdateFirewall(Failure::Unexpected(_)))
+        ));
+        _m_get.assert_async().await;
+        _m_put.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_assign_droplets() {
+        let mut server = mockito::Server::new_async().await;
+        let _m_assign = server
+            .mock("POST", "/v2/firewalls/fw2/droplets")
+            .match_header("Authorization", "Bearer foo")
+            .match_body(mockito::Matcher::Json(json!({"droplet_ids": [42, 43]})))
+            .with_status(StatusCode::NO_CONTENT.as_u16() as usize)
+            .create_async()
+            .await;
+        let _m_unassign = server
+            .mock("DELETE", "/v2/firewalls/fw2/droplets")
+            .match_header("Authorization", "Bearer foo")
+            .match_body(mockito::Matcher::Json(json!({"droplet_ids": [5]})))
+            .with_status(StatusCode::NO_CONTENT.as_u16() as usize)
+            .create_async()
+            .await;
+
+        let client = DigitalOceanClient::new_for_test("foo".to_string(), server.url());
+        assert_eq!(
+            Ok(()),
+            client
+                .firewall
+                .assign_droplets("fw2", &[42, 43], &false)
+                .await
+        );
+        assert_eq!(
+            Ok(()),
+            client.firewall.unassign_droplets("fw2", &[5], &false).await
+        );
+        _m_assign.assert_async().await;
+        _m_unassign.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_assign_droplets_rejected() {
+        let mut server = mockito::Server::new_async().await;
+        let _m = server
+            .mock("POST", "/v2/firewalls/fw2/droplets")
+            .with_status(422)
+            .with_header("Content-Type", "application/json")
+            .with_body(
+                serde_json::to_string(&json!({
+                    "id": "unprocessable_entity",
+                    "message": "droplet 42 does not exist",
+                }))
+                .unwrap(),
+            )
+            .create_async()
+            .await;
+
+        let resp = DigitalOceanClient::new_for_test("foo".to_string(), server.url())
+            .firewall
+            .assign_droplets("fw2", &[42], &false)
+            .await;
+        assert_eq!(
+            Err(Error::AssignFirewallDroplets(Failure::Rejected(ApiError {
+                status: 422,
+                id: Some("unprocessable_entity".to_string()),
+                message: Some("droplet 42 does not exist".to_string()),
+                request_id: None,
+                url: format!("{}/v2/firewalls/fw2/droplets", server.url()),
+            }))),
+            resp
+        );
+        _m.assert_async().await;
     }
 }
@@ -1,10 +1,28 @@
-use crate::digitalocean::api::{DigitalOceanApiClient, Links, Meta};
+use crate::digitalocean::api::{lenient, DigitalOceanApiClient, Links, Meta};
 use crate::digitalocean::error::Error;
-use serde::Deserialize;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
+#[async_trait(?Send)]
 pub trait DigitalOceanKubernetesClient {
-    fn get_kubernetes_clusters(&self) -> Result<Vec<KubernetesCluster>, Error>;
+    async fn get_kubernetes_clusters(&self) -> Result<Vec<KubernetesCluster>, Error>;
+
+    /// Get just the ID and name of every Kubernetes cluster, which is much cheaper to deserialize
+    /// than the full listing when resolving names to IDs.
+    async fn get_kubernetes_cluster_summaries(
+        &self,
+    ) -> Result<Vec<KubernetesClusterSummary>, Error> {
+        Ok(self
+            .get_kubernetes_clusters()
+            .await?
+            .into_iter()
+            .map(|o| KubernetesClusterSummary {
+                id: o.id,
+                name: o.name,
+            })
+            .collect())
+    }
 }
 
 pub struct DigitalOceanKubernetesClientImpl {
@@ -17,14 +35,32 @@ impl DigitalOceanKubernetesClientImpl {
     }
 }
 
+#[async_trait(?Send)]
 impl DigitalOceanKubernetesClient for DigitalOceanKubernetesClientImpl {
     /// Get info on all kubernetes clusters.
-    fn get_kubernetes_clusters(&self) -> Result<Vec<KubernetesCluster>, Error> {
-        self.api.get_all_objects(
-            self.api.get_url("/v2/kubernetes/clusters"),
-            |r: KubernetesClusterResp| r.kubernetes_clusters,
-            |r: &KubernetesClusterResp| r.links.clone(),
-        )
+    async fn get_kubernetes_clusters(&self) -> Result<Vec<KubernetesCluster>, Error> {
+        self.api
+            .get_all_objects(
+                self.api.get_url("/v2/kubernetes/clusters"),
+                |r: KubernetesClusterResp| r.kubernetes_clusters,
+                |r: &KubernetesClusterResp| r.links.clone(),
+                |r: &KubernetesClusterResp| &r.meta,
+            )
+            .await
+    }
+
+    /// Get the ID and name of all kubernetes clusters.
+    async fn get_kubernetes_cluster_summaries(
+        &self,
+    ) -> Result<Vec<KubernetesClusterSummary>, Error> {
+        self.api
+            .get_all_objects(
+                self.api.get_url("/v2/kubernetes/clusters"),
+                |r: KubernetesClusterSummariesResp| r.kubernetes_clusters,
+                |r: &KubernetesClusterSummariesResp| r.links.clone(),
+                |r: &KubernetesClusterSummariesResp| &r.meta,
+            )
+            .await
     }
 }
 
@@ -33,11 +69,24 @@ impl DigitalOceanKubernetesClient for DigitalOceanKubernetesClientImpl {
 #[derive(Deserialize, Debug)]
 struct KubernetesClusterResp {
     kubernetes_clusters: Vec<KubernetesCluster>,
-    #[allow(dead_code)]
     meta: Meta,
     links: Links,
 }
 
+#[derive(Deserialize, Debug)]
+struct KubernetesClusterSummariesResp {
+    kubernetes_clusters: Vec<KubernetesClusterSummary>,
+    meta: Meta,
+    links: Links,
+}
+
+/// The identifying fields of a Kubernetes cluster
+#[derive(Deserialize, Serialize, Debug, Eq, PartialEq, Clone)]
+pub struct KubernetesClusterSummary {
+    pub id: String,
+    pub name: String,
+}
+
 #[derive(Deserialize, Debug, Eq, PartialEq, Clone)]
 #[allow(dead_code)]
 pub struct KubernetesCluster {
@@ -46,52 +95,69 @@ pub struct KubernetesCluster {
     /// A human-readable name for a Kubernetes cluster.
     pub name: String,
     /// The slug identifier for the region where the Kubernetes cluster is located.
+    #[serde(default, deserialize_with = "lenient")]
     pub region: String,
     /// The slug identifier for the version of Kubernetes used for the cluster. If set to a minor
     /// version (e.g. "1.14"), the latest version within it will be used (e.g. "1.14.6-do.1"); if
     /// set to "latest", the latest published version will be used. See the /v2/kubernetes/options
     /// endpoint to find all currently available versions.
+    #[serde(default, deserialize_with = "lenient")]
     pub version: String,
     /// The range of IP addresses in the overlay network of the Kubernetes cluster in CIDR notation.
+    #[serde(default, deserialize_with = "lenient")]
     pub cluster_subnet: String,
     /// The range of assignable IP addresses for services running in the Kubernetes cluster in CIDR
     /// notation.
+    #[serde(default, deserialize_with = "lenient")]
     pub service_subnet: String,
     /// A string specifying the UUID of the VPC to which the Kubernetes cluster is assigned.
+    #[serde(default, deserialize_with = "lenient")]
     pub vpc_uuid: String,
     /// The public IPv4 address of the Kubernetes master node. This will not be set if high
     /// availability is configured on the cluster (v1.21+)
+    #[serde(default, deserialize_with = "lenient")]
     pub ipv4: Option<String>,
     /// The base URL of the API server on the Kubernetes master node.
+    #[serde(default, deserialize_with = "lenient")]
     pub endpoint: String,
     /// An array of tags applied to the Kubernetes cluster. All clusters are automatically tagged
     /// k8s and k8s:$K8S_CLUSTER_ID.
+    #[serde(default, deserialize_with = "lenient")]
     pub tags: Vec<String>,
     /// An object specifying the details of the worker nodes available to the Kubernetes cluster.
+    #[serde(default, deserialize_with = "lenient")]
     pub node_pools: Vec<KubernetesClusterNodePool>,
     /// An object specifying the maintenance window policy for the Kubernetes cluster.
+    #[serde(default, deserialize_with = "lenient")]
     pub maintenance_policy: Option<KubernetesClusterMaintenancePolicy>,
     /// A boolean value indicating whether the cluster will be automatically upgraded to new patch
     /// releases during its maintenance window.
+    #[serde(default, deserialize_with = "lenient")]
     pub auto_upgrade: bool,
     /// An object containing a state attribute whose value is set to a string indicating the current
     /// status of the cluster.
+    #[serde(default, deserialize_with = "lenient")]
     pub status: KubernetesClusterStatus,
     /// A time value given in ISO8601 combined date and time format that represents when the
     /// Kubernetes cluster was created.
+    #[serde(default, deserialize_with = "lenient")]
     pub created_at: String,
     /// A time value given in ISO8601 combined date and time format that represents when the
     /// Kubernetes cluster was last updated.
+    #[serde(default, deserialize_with = "lenient")]
     pub updated_at: String,
     /// A boolean value indicating whether surge upgrade is enabled/disabled for the cluster. Surge
     /// upgrade makes cluster upgrades fast and reliable by bringing up new nodes before destroying
     /// the outdated nodes.
+    #[serde(default, deserialize_with = "lenient")]
     pub surge_upgrade: bool,
     /// A boolean value indicating whether the control plane is run in a highly available
     /// configuration in the cluster. Highly available control planes incur less downtime. The
     /// property cannot be disabled.
+    #[serde(default, deserialize_with = "lenient")]
     pub ha: bool,
     /// A read-only boolean value indicating if a container registry is integrated with the cluster.
+    #[serde(default, deserialize_with = "lenient")]
     pub registry_enabled: bool,
 }
 
@@ -186,7 +252,7 @@ pub struct KubernetesClusterMaintenancePolicy {
     pub day: String,
 }
 
-#[derive(Deserialize, Debug, Eq, PartialEq, Clone)]
+#[derive(Deserialize, Debug, Eq, PartialEq, Clone, Default)]
 #[allow(dead_code)]
 pub struct KubernetesClusterStatus {
     /// A string indicating the current status of the cluster.
@@ -434,9 +500,9 @@ mod test {
         }
     }
 
-    #[test]
-    fn test_get_clusters() {
-        let mut server = mockito::Server::new();
+    #[tokio::test]
+    async fn test_get_clusters() {
+        let mut server = mockito::Server::new_async().await;
         let _m = server
             .mock("GET", "/v2/kubernetes/clusters")
             .match_header("Authorization", "Bearer foo")
@@ -455,18 +521,20 @@ mod test {
                 }))
                 .unwrap(),
             )
-            .create();
+            .create_async()
+            .await;
 
         let resp = DigitalOceanClient::new_for_test("foo".to_string(), server.url())
             .kubernetes
-            .get_kubernetes_clusters();
+            .get_kubernetes_clusters()
+            .await;
         assert_eq!(Ok(vec![get_cluster_1_obj(), get_cluster_2_obj()]), resp);
-        _m.assert();
+        _m.assert_async().await;
     }
 
-    #[test]
-    fn test_get_clusters_paginated() {
-        let mut server = mockito::Server::new();
+    #[tokio::test]
+    async fn test_get_clusters_paginated() {
+        let mut server = mockito::Server::new_async().await;
         let _m = server
             .mock("GET", "/v2/kubernetes/clusters")
             .match_header("Authorization", "Bearer foo")
@@ -488,7 +556,8 @@ mod test {
                 }))
                 .unwrap(),
             )
-            .create();
+            .create_async()
+            .await;
         let _m_page2 = server
             .mock("GET", "/v2/kubernetes/clusters?page=2")
             .match_header("Authorization", "Bearer foo")
@@ -506,13 +575,15 @@ mod test {
                 }))
                 .unwrap(),
             )
-            .create();
+            .create_async()
+            .await;
 
         let resp = DigitalOceanClient::new_for_test("foo".to_string(), server.url())
             .kubernetes
-            .get_kubernetes_clusters();
+            .get_kubernetes_clusters()
+            .await;
         assert_eq!(Ok(vec![get_cluster_1_obj(), get_cluster_2_obj()]), resp);
-        _m.assert();
-        _m_page2.assert();
+        _m.assert_async().await;
+        _m_page2.assert_async().await;
     }
 }
@@ -1,9 +1,25 @@
-use crate::digitalocean::api::{DigitalOceanApiClient, Links, Meta};
+use crate::digitalocean::api::{lenient, DigitalOceanApiClient, Links, Meta};
 use crate::digitalocean::error::Error;
-use serde::Deserialize;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
 
+#[async_trait(?Send)]
 pub trait DigitalOceanLoadbalancerClient {
-    fn get_load_balancers(&self) -> Result<Vec<Loadbalancer>, Error>;
+    async fn get_load_balancers(&self) -> Result<Vec<Loadbalancer>, Error>;
+
+    /// Get just the ID and name of every load balancer, which is much cheaper to deserialize than
+    /// the full listing when resolving names to IDs.
+    async fn get_load_balancer_summaries(&self) -> Result<Vec<LoadbalancerSummary>, Error> {
+        Ok(self
+            .get_load_balancers()
+            .await?
+            .into_iter()
+            .map(|o| LoadbalancerSummary {
+                id: o.id,
+                name: o.name,
+            })
+            .collect())
+    }
 }
 
 pub struct DigitalOceanLoadbalancerClientImpl {
@@ -16,14 +32,30 @@ impl DigitalOceanLoadbalancerClientImpl {
     }
 }
 
+#[async_trait(?Send)]
 impl DigitalOceanLoadbalancerClient for DigitalOceanLoadbalancerClientImpl {
     /// Get info on all load balancers.
-    fn get_load_balancers(&self) -> Result<Vec<Loadbalancer>, Error> {
-        self.api.get_all_objects(
-            self.api.get_url("/v2/load_balancers"),
-            |r: LoadbalancersResp| r.load_balancers,
-            |r: &LoadbalancersResp| r.links.clone(),
-        )
+    async fn get_load_balancers(&self) -> Result<Vec<Loadbalancer>, Error> {
+        self.api
+            .get_all_objects(
+                self.api.get_url("/v2/load_balancers"),
+                |r: LoadbalancersResp| r.load_balancers,
+                |r: &LoadbalancersResp| r.links.clone(),
+                |r: &LoadbalancersResp| &r.meta,
+            )
+            .await
+    }
+
+    /// Get the ID and name of all load balancers.
+    async fn get_load_balancer_summaries(&self) -> Result<Vec<LoadbalancerSummary>, Error> {
+        self.api
+            .get_all_objects(
+                self.api.get_url("/v2/load_balancers"),
+                |r: LoadbalancerSummariesResp| r.load_balancers,
+                |r: &LoadbalancerSummariesResp| r.links.clone(),
+                |r: &LoadbalancerSummariesResp| &r.meta,
+            )
+            .await
     }
 }
 
@@ -32,11 +64,24 @@ impl DigitalOceanLoadbalancerClient for DigitalOceanLoadbalancerClientImpl {
 #[derive(Deserialize, Debug)]
 struct LoadbalancersResp {
     load_balancers: Vec<Loadbalancer>,
-    #[allow(dead_code)]
     meta: Meta,
     links: Links,
 }
 
+#[derive(Deserialize, Debug)]
+struct LoadbalancerSummariesResp {
+    load_balancers: Vec<LoadbalancerSummary>,
+    meta: Meta,
+    links: Links,
+}
+
+/// The identifying fields of a load balancer
+#[derive(Deserialize, Serialize, Debug, Eq, PartialEq, Clone)]
+pub struct LoadbalancerSummary {
+    pub id: String,
+    pub name: String,
+}
+
 #[derive(Deserialize, Debug, Eq, PartialEq, Clone)]
 #[allow(dead_code)]
 pub struct Loadbalancer {
@@ -47,8 +92,10 @@ pub struct Loadbalancer {
     /// The ID of the project that the load balancer is associated with. If no ID is provided at
     /// creation, the load balancer associates with the user's default project. If an invalid
     /// project ID is provided, the load balancer will not be created
+    #[serde(default, deserialize_with = "lenient")]
     pub project_id: String,
     /// An attribute containing the public-facing IP address of the load balancer.
+    #[serde(default, deserialize_with = "lenient")]
     pub ip: String,
     /// How many nodes the load balancer contains. Each additional node increases the load
     /// balancer's ability to manage more connections. Load balancers can be scaled up or down, and
@@ -56,60 +103,80 @@ pub struct Loadbalancer {
     /// currently not available in the AMS2, NYC2, or SFO1 regions. Use the size field to scale load
     /// balancers that reside in these regions.
     /// range [ 1 .. 100 ]
+    #[serde(default, deserialize_with = "lenient")]
     pub size_unit: u8,
     /// This field has been replaced by the size_unit field for all regions except in AMS2, NYC2,
     /// and SFO1. Each available load balancer size now equates to the load balancer having a set
     /// number of nodes.
     ///
-    ///     lb-small = 1 node
-    ///     lb-medium = 3 nodes
-    ///     lb-large = 6 nodes
+    /// ```text
+    /// lb-small = 1 node
+    /// lb-medium = 3 nodes
+    /// lb-large = 6 nodes
+    /// ```
     ///
     /// You can resize load balancers after creation up to once per hour. You cannot resize a load
     /// balancer within the first hour of its creation.
+    #[serde(default, deserialize_with = "lenient")]
     #[deprecated]
     pub size: Option<String>,
     /// This field has been deprecated. You can no longer specify an algorithm for load balancers.
     /// values: "round_robin" "least_connections"
+    #[serde(default, deserialize_with = "lenient")]
     #[deprecated]
     pub algorithm: Option<String>,
     /// A status string indicating the current state of the load balancer. This can be new, active,
     /// or errored.
+    #[serde(default, deserialize_with = "lenient")]
     pub status: String,
     /// A time value given in ISO8601 combined date and time format that represents when the load
     /// balancer was created.
+    #[serde(default, deserialize_with = "lenient")]
     pub created_at: String,
     /// An array of objects specifying the forwarding rules for a load balancer.
+    #[serde(default, deserialize_with = "lenient")]
     pub forwarding_rules: Vec<LoadbalancerForwardingRule>,
     /// An object specifying health check settings for the load balancer.
+    #[serde(default, deserialize_with = "lenient")]
     pub health_check: LoadbalancerHealthCheck,
     /// An object specifying sticky sessions settings for the load balancer.
+    #[serde(default, deserialize_with = "lenient")]
     pub sticky_sessions: LoadbalancerStickySessions,
     /// A boolean value indicating whether HTTP requests to the load balancer on port 80 will be
     /// redirected to HTTPS on port 443.
+    #[serde(default, deserialize_with = "lenient")]
     pub redirect_http_to_https: bool,
     /// A boolean value indicating whether PROXY Protocol is in use.
+    #[serde(default, deserialize_with = "lenient")]
     pub enable_proxy_protocol: bool,
     /// A boolean value indicating whether HTTP keepalive connections are maintained to target
     /// Droplets.
+    #[serde(default, deserialize_with = "lenient")]
     pub enable_backend_keepalive: bool,
     /// An integer value which configures the idle timeout for HTTP requests to the target droplets
     /// range [ 30 .. 60 ]
+    #[serde(default, deserialize_with = "lenient")]
     pub http_idle_timeout_seconds: u16,
     /// A string specifying the UUID of the VPC to which the load balancer is assigned.
+    #[serde(default, deserialize_with = "lenient")]
     pub vpc_uuid: String,
     /// A boolean value indicating whether to disable automatic DNS record creation for Let's
     /// Encrypt certificates that are added to the load balancer.
+    #[serde(default, deserialize_with = "lenient")]
     pub disable_lets_encrypt_dns_records: bool,
     /// An object specifying allow and deny rules to control traffic to the load balancer.
+    #[serde(default, deserialize_with = "lenient")]
     pub firewall: LoadbalancerFirewall,
     /// The region where the load balancer instance is located. When setting a region, the value
     /// should be the slug identifier for the region. When you query a load balancer, an entire
     /// region object will be returned.
+    #[serde(default, deserialize_with = "lenient")]
     pub region: LoadbalancerRegion,
     /// An array containing the IDs of the Droplets assigned to the load balancer.
+    #[serde(default, deserialize_with = "lenient")]
     pub droplet_ids: Vec<u32>,
     /// The name of a Droplet tag corresponding to Droplets assigned to the load balancer.
+    #[serde(default, deserialize_with = "lenient")]
     pub tag: String,
 }
 
@@ -138,7 +205,7 @@ pub struct LoadbalancerForwardingRule {
     pub tls_passthrough: bool,
 }
 
-#[derive(Deserialize, Debug, Eq, PartialEq, Clone)]
+#[derive(Deserialize, Debug, Eq, PartialEq, Clone, Default)]
 #[allow(dead_code)]
 pub struct LoadbalancerHealthCheck {
     /// The protocol used for health checks sent to the backend Droplets. The possible values are
@@ -162,7 +229,7 @@ pub struct LoadbalancerHealthCheck {
     pub healthy_threshold: u8,
 }
 
-#[derive(Deserialize, Debug, Eq, PartialEq, Clone)]
+#[derive(Deserialize, Debug, Eq, PartialEq, Clone, Default)]
 #[allow(dead_code)]
 pub struct LoadbalancerStickySessions {
     /// An attribute indicating how and if requests from a client will be persistently served by the
@@ -177,7 +244,7 @@ pub struct LoadbalancerStickySessions {
     pub cookie_ttl_seconds: Option<u32>,
 }
 
-#[derive(Deserialize, Debug, Eq, PartialEq, Clone)]
+#[derive(Deserialize, Debug, Eq, PartialEq, Clone, Default)]
 #[allow(dead_code)]
 pub struct LoadbalancerFirewall {
     /// the rules for denying traffic to the load balancer (in the form 'ip:1.2.3.4' or
@@ -188,7 +255,7 @@ pub struct LoadbalancerFirewall {
     pub allow: Vec<String>,
 }
 
-#[derive(Deserialize, Debug, Eq, PartialEq, Clone)]
+#[derive(Deserialize, Debug, Eq, PartialEq, Clone, Default)]
 #[allow(dead_code)]
 pub struct LoadbalancerRegion {
     /// The display name of the region. This will be a full name that is used in the control panel
@@ -435,9 +502,9 @@ mod test {
         }
     }
 
-    #[test]
-    fn test_get_lbs() {
-        let mut server = mockito::Server::new();
+    #[tokio::test]
+    async fn test_get_lbs() {
+        let mut server = mockito::Server::new_async().await;
         let _m = server
             .mock("GET", "/v2/load_balancers")
             .match_header("Authorization", "Bearer foo")
@@ -456,21 +523,23 @@ mod test {
                 }))
                 .unwrap(),
             )
-            .create();
+            .create_async()
+            .await;
 
         let resp = DigitalOceanClient::new_for_test("foo".to_string(), server.url())
             .load_balancer
-            .get_load_balancers();
+            .get_load_balancers()
+            .await;
         assert_eq!(
             Ok(vec![get_load_balancer_1_obj(), get_load_balancer_2_obj()]),
             resp
         );
-        _m.assert();
+        _m.assert_async().await;
     }
 
-    #[test]
-    fn test_get_lbs_paginated() {
-        let mut server = mockito::Server::new();
+    #[tokio::test]
+    async fn test_get_lbs_paginated() {
+        let mut server = mockito::Server::new_async().await;
         let _m = server
             .mock("GET", "/v2/load_balancers")
             .match_header("Authorization", "Bearer foo")
@@ -492,7 +561,8 @@ mod test {
                 }))
                 .unwrap(),
             )
-            .create();
+            .create_async()
+            .await;
         let _m_page2 = server
             .mock("GET", "/v2/load_balancers?page=2")
             .match_header("Authorization", "Bearer foo")
@@ -510,16 +580,18 @@ mod test {
                 }))
                 .unwrap(),
             )
-            .create();
+            .create_async()
+            .await;
 
         let resp = DigitalOceanClient::new_for_test("foo".to_string(), server.url())
             .load_balancer
-            .get_load_balancers();
+            .get_load_balancers()
+            .await;
         assert_eq!(
             Ok(vec![get_load_balancer_1_obj(), get_load_balancer_2_obj()]),
             resp
         );
-        _m.assert();
-        _m_page2.assert();
+        _m.assert_async().await;
+        _m_page2.assert_async().await;
     }
 }
@@ -0,0 +1,739 @@
+//! In-memory implementations of the DigitalOcean clients, for unit testing code that uses the
+//! clients without standing up a mock HTTP server.  Each mock is built up from the objects it
+//! should report and applies any modifications to its own state, honoring `dry_run`.
+
+// not every mock is exercised by this crate's own tests
+#![allow(dead_code)]
+
+use std::cell::{Cell, RefCell};
+use std::net::IpAddr;
+
+use async_trait::async_trait;
+
+use crate::digitalocean::account::{Account, DigitalOceanAccountClient};
+use crate::digitalocean::dns::{
+    relative_record_name, DigitalOceanDnsClient, Domain, DomainRecord, RecordValue,
+};
+use crate::digitalocean::droplet::{DigitalOceanDropletClient, Droplet};
+use crate::digitalocean::error::{ApiError, Error, Failure};
+use crate::digitalocean::firewall::{
+    DigitalOceanFirewallClient, Firewall, FirewallInboundRule, FirewallOutboundRule,
+};
+use crate::digitalocean::kubernetes::{DigitalOceanKubernetesClient, KubernetesCluster};
+use crate::digitalocean::loadbalancer::{DigitalOceanLoadbalancerClient, Loadbalancer};
+use crate::digitalocean::project::{DigitalOceanProjectsClient, Project, ProjectResource};
+use crate::digitalocean::reserved_ip::{
+    DigitalOceanReservedIpClient, ReservedIp, ReservedIpAction, ReservedIpDroplet,
+};
+use crate::digitalocean::tags::{DigitalOceanTagsClient, Tag};
+use crate::digitalocean::vpc::{DigitalOceanVpcClient, Vpc};
+
+/// The rejection the API responds with when the object at `path` does not exist
+fn not_found(path: String) -> Failure {
+    Failure::Rejected(ApiError {
+        status: 404,
+        id: Some("not_found".to_string()),
+        message: Some("The resource you were accessing could not be found.".to_string()),
+        request_id: None,
+        url: format!("https://api.digitalocean.com{path}"),
+    })
+}
+
+pub struct MockAccountClient {
+    account: Account,
+}
+
+impl MockAccountClient {
+    pub fn new(account: Account) -> MockAccountClient {
+        MockAccountClient { account }
+    }
+}
+
+#[async_trait(?Send)]
+impl DigitalOceanAccountClient for MockAccountClient {
+    async fn get_account(&self) -> Result<Account, Error> {
+        Ok(self.account.clone())
+    }
+}
+
+#[derive(Default)]
+pub struct MockDnsClient {
+    domains: RefCell<Vec<Domain>>,
+    records: RefCell<Vec<(String, DomainRecord)>>,
+    next_id: Cell<u32>,
+}
+
+impl MockDnsClient {
+    pub fn new() -> MockDnsClient {
+        MockDnsClient {
+            next_id: Cell::new(1),
+            ..Default::default()
+        }
+    }
+
+    pub fn with_domain(self, name: &str) -> MockDnsClient {
+        self.domains.borrow_mut().push(Domain {
+            name: name.to_string(),
+            ttl: 1800,
+            zone_file: String::new(),
+        });
+        self
+    }
+
+    pub fn with_record(self, domain: &str, record: DomainRecord) -> MockDnsClient {
+        self.next_id.set(self.next_id.get().max(record.id + 1));
+        self.records.borrow_mut().push((domain.to_string(), record));
+        self
+    }
+
+    /// The records currently held for a domain, including any modifications
+    pub fn records(&self, domain: &str) -> Vec<DomainRecord> {
+        self.records
+            .borrow()
+            .iter()
+            .filter(|(d, _)| d == domain)
+            .map(|(_, r)| r.clone())
+            .collect()
+    }
+
+    fn replace_record(&self, domain: &str, record: DomainRecord) -> Result<DomainRecord, Error> {
+        let mut records = self.records.borrow_mut();
+        let existing = records
+            .iter_mut()
+            .find(|(d, r)| d == domain && r.id == record.id)
+            .ok_or_else(|| {
+                Error::UpdateDns(not_found(format!(
+                    "/v2/domains/{}/records/{}",
+                    domain, record.id
+                )))
+            })?;
+        existing.1 = record.clone();
+        Ok(record)
+    }
+}
+
+#[async_trait(?Send)]
+impl DigitalOceanDnsClient for MockDnsClient {
+    async fn get_domains(&self) -> Result<Vec<Domain>, Error> {
+        Ok(self.domains.borrow().clone())
+    }
+
+    async fn get_domain(&self, domain: &str) -> Result<Option<Domain>, Error> {
+        Ok(self
+            .domains
+            .borrow()
+            .iter()
+            .find(|d| d.name == domain)
+            .cloned())
+    }
+
+    async fn create_domain(&self, domain: &str, dry_run: &bool) -> Result<Domain, Error> {
+        let created = Domain {
+            name: domain.to_string(),
+            ttl: 1800,
+            zone_file: String::new(),
+        };
+        if !*dry_run {
+            self.domains.borrow_mut().push(created.clone());
+        }
+        Ok(created)
+    }
+
+    async fn get_record(
+        &self,
+        domain: &str,
+        record: &str,
+        rtype: &str,
+    ) -> Result<Option<DomainRecord>, Error> {
+        let Some(record) = relative_record_name(record, domain, false) else {
+            return Ok(None);
+        };
+        Ok(self
+            .records(domain)
+            .into_iter()
+            .find(|r| r.name.eq_ignore_ascii_case(&record) && r.typ == rtype))
+    }
+
+    async fn get_all_records(&self, domain: &str) -> Result<Vec<DomainRecord>, Error> {
+        Ok(self.records(domain))
+    }
+
+    async fn get_records(&self, domain: &str, rtype: &str) -> Result<Vec<DomainRecord>, Error> {
+        Ok(self
+            .records(domain)
+            .into_iter()
+            .filter(|r| r.typ == rtype)
+            .collect())
+    }
+
+    async fn update_record(
+        &self,
+        domain: &str,
+        record: &DomainRecord,
+        value: &RecordValue,
+        ttl: &u16,
+        dry_run: &bool,
+    ) -> Result<DomainRecord, Error> {
+        let updated = DomainRecord {
+            data: value.data.clone(),
+            priority: value.priority.or(record.priority),
+            port: value.port.or(record.port),
+            weight: value.weight.or(record.weight),
+            ttl: *ttl,
+            ..record.clone()
+        };
+        if *dry_run {
+            Ok(updated)
+        } else {
+            self.replace_record(domain, updated)
+        }
+    }
+
+    async fn update_record_ttl(
+        &self,
+        domain: &str,
+        record: &DomainRecord,
+        ttl: &u16,
+        dry_run: &bool,
+    ) -> Result<DomainRecord, Error> {
+        let updated = DomainRecord {
+            ttl: *ttl,
+            ..record.clone()
+        };
+        if *dry_run {
+            Ok(updated)
+        } else {
+            self.replace_record(domain, updated)
+        }
+    }
+
+    async fn create_record(
+        &self,
+        domain: &str,
+        record: &str,
+        rtype: &str,
+        value: &RecordValue,
+        ttl: &u16,
+        dry_run: &bool,
+    ) -> Result<DomainRecord, Error> {
+        let created = DomainRecord {
+            id: self.next_id.get(),
+            typ: rtype.to_string(),
+            name: record.to_string(),
+            data: value.data.clone(),
+            priority: value.priority,
+            port: value.port,
+            ttl: *ttl,
+            weight: value.weight,
+            flags: None,
+            tag: None,
+        };
+        if !*dry_run {
+            self.next_id.set(created.id + 1);
+            self.records
+                .borrow_mut()
+                .push((domain.to_string(), created.clone()));
+        }
+        Ok(created)
+    }
+
+    async fn delete_record(
+        &self,
+        domain: &str,
+        record: &DomainRecord,
+        dry_run: &bool,
+    ) -> Result<(), Error> {
+        let mut records = self.records.borrow_mut();
+        let index = records
+            .iter()
+            .position(|(d, r)| d == domain && r.id == record.id)
+            .ok_or_else(|| {
+                Error::DeleteDns(not_found(format!(
+                    "/v2/domains/{}/records/{}",
+                    domain, record.id
+                )))
+            })?;
+        if !*dry_run {
+            records.remove(index);
+        }
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+pub struct MockDropletClient {
+    droplets: Vec<Droplet>,
+}
+
+impl MockDropletClient {
+    pub fn new() -> MockDropletClient {
+        Default::default()
+    }
+
+    pub fn with_droplet(mut self, droplet: Droplet) -> MockDropletClient {
+        self.droplets.push(droplet);
+        self
+    }
+}
+
+#[async_trait(?Send)]
+impl DigitalOceanDropletClient for MockDropletClient {
+    async fn get_droplets(&self) -> Result<Vec<Droplet>, Error> {
+        Ok(self.droplets.clone())
+    }
+}
+
+#[derive(Default)]
+pub struct MockFirewallClient {
+    firewalls: RefCell<Vec<Firewall>>,
+}
+
+impl MockFirewallClient {
+    pub fn new() -> MockFirewallClient {
+        Default::default()
+    }
+
+    pub fn with_firewall(self, firewall: Firewall) -> MockFirewallClient {
+        self.firewalls.borrow_mut().push(firewall);
+        self
+    }
+
+    /// The firewalls currently held, including any modifications
+    pub fn firewalls(&self) -> Vec<Firewall> {
+        self.firewalls.borrow().clone()
+    }
+
+    fn modify_firewall<F: FnOnce(&mut Firewall)>(&self, id: &str, f: F) -> Result<(), Error> {
+        let mut firewalls = self.firewalls.borrow_mut();
+        let firewall = firewalls.iter_mut().find(|f| f.id == id).ok_or_else(|| {
+            Error::CreateFirewallRule(not_found(format!("/v2/firewalls/{id}/rules")))
+        })?;
+        f(firewall);
+        Ok(())
+    }
+}
+
+#[async_trait(?Send)]
+impl DigitalOceanFirewallClient for MockFirewallClient {
+    async fn get_firewalls(&self) -> Result<Vec<Firewall>, Error> {
+        Ok(self.firewalls())
+    }
+
+    async fn get_firewall(&self, name: String) -> Result<Option<Firewall>, Error> {
+        Ok(self
+            .firewalls
+            .borrow()
+            .iter()
+            .find(|f| f.name == name)
+            .cloned())
+    }
+
+    async fn get_firewall_by_id(&self, id: &str) -> Result<Option<Firewall>, Error> {
+        Ok(self.firewalls.borrow().iter().find(|f| f.id == id).cloned())
+    }
+
+    async fn delete_firewall_rule(
+        &self,
+        id: &str,
+        inbound_rules: Option<Vec<FirewallInboundRule>>,
+        outbound_rules: Option<Vec<FirewallOutboundRule>>,
+        dry_run: &bool,
+    ) -> Result<(), Error> {
+        if *dry_run {
+            return Ok(());
+        }
+        self.modify_firewall(id, |firewall| {
+            let inbound_rules = inbound_rules.unwrap_or_default();
+            let outbound_rules = outbound_rules.unwrap_or_default();
+            if let Some(ref mut rules) = firewall.inbound_rules {
+                rules.retain(|r| !inbound_rules.contains(r));
+            }
+            if let Some(ref mut rules) = firewall.outbound_rules {
+                rules.retain(|r| !outbound_rules.contains(r));
+            }
+        })
+    }
+
+    async fn add_firewall_rule(
+        &self,
+        id: &str,
+        inbound_rules: Option<Vec<FirewallInboundRule>>,
+        outbound_rules: Option<Vec<FirewallOutboundRule>>,
+        dry_run: &bool,
+    ) -> Result<(), Error> {
+        if *dry_run {
+            return Ok(());
+        }
+        self.modify_firewall(id, |firewall| {
+            if let Some(rules) = inbound_rules {
+                firewall
+                    .inbound_rules
+                    .get_or_insert_with(Vec::new)
+                    .extend(rules);
+            }
+            if let Some(rules) = outbound_rules {
+                firewall
+                    .outbound_rules
+                    .get_or_insert_with(Vec::new)
+                    .extend(rules);
+            }
+        })
+    }
+
+    async fn update_firewall(
+        &self,
+        firewall: &Firewall,
+        dry_run: &bool,
+    ) -> Result<Firewall, Error> {
+        if *dry_run {
+            return Ok(firewall.clone());
+        }
+        self.modify_firewall(&firewall.id, |f| *f = firewall.clone())?;
+        Ok(firewall.clone())
+    }
+
+    async fn assign_droplets(
+        &self,
+        id: &str,
+        droplet_ids: &[u32],
+        dry_run: &bool,
+    ) -> Result<(), Error> {
+        if *dry_run {
+            return Ok(());
+        }
+        self.modify_firewall(id, |firewall| {
+            let attached = firewall.droplet_ids.get_or_insert_with(Vec::new);
+            for id in droplet_ids {
+                if !attached.contains(id) {
+                    attached.push(*id);
+                }
+            }
+        })
+    }
+
+    async fn unassign_droplets(
+        &self,
+        id: &str,
+        droplet_ids: &[u32],
+        dry_run: &bool,
+    ) -> Result<(), Error> {
+        if *dry_run {
+            return Ok(());
+        }
+        self.modify_firewall(id, |firewall| {
+            if let Some(ref mut attached) = firewall.droplet_ids {
+                attached.retain(|id| !droplet_ids.contains(id));
+            }
+        })
+    }
+}
+
+#[derive(Default)]
+pub struct MockKubernetesClient {
+    clusters: Vec<KubernetesCluster>,
+}
+
+impl MockKubernetesClient {
+    pub fn new() -> MockKubernetesClient {
+        Default::default()
+    }
+
+    pub fn with_cluster(mut self, cluster: KubernetesCluster) -> MockKubernetesClient {
+        self.clusters.push(cluster);
+        self
+    }
+}
+
+#[async_trait(?Send)]
+impl DigitalOceanKubernetesClient for MockKubernetesClient {
+    async fn get_kubernetes_clusters(&self) -> Result<Vec<KubernetesCluster>, Error> {
+        Ok(self.clusters.clone())
+    }
+}
+
+#[derive(Default)]
+pub struct MockLoadbalancerClient {
+    load_balancers: Vec<Loadbalancer>,
+}
+
+impl MockLoadbalancerClient {
+    pub fn new() -> MockLoadbalancerClient {
+        Default::default()
+    }
+
+    pub fn with_load_balancer(mut self, load_balancer: Loadbalancer) -> MockLoadbalancerClient {
+        self.load_balancers.push(load_balancer);
+        self
+    }
+}
+
+#[async_trait(?Send)]
+impl DigitalOceanLoadbalancerClient for MockLoadbalancerClient {
+    async fn get_load_balancers(&self) -> Result<Vec<Loadbalancer>, Error> {
+        Ok(self.load_balancers.clone())
+    }
+}
+
+#[derive(Default)]
+pub struct MockProjectsClient {
+    projects: Vec<(Project, Vec<ProjectResource>)>,
+}
+
+impl MockProjectsClient {
+    pub fn new() -> MockProjectsClient {
+        Default::default()
+    }
+
+    pub fn with_project(
+        mut self,
+        project: Project,
+        resources: Vec<ProjectResource>,
+    ) -> MockProjectsClient {
+        self.projects.push((project, resources));
+        self
+    }
+}
+
+#[async_trait(?Send)]
+impl DigitalOceanProjectsClient for MockProjectsClient {
+    async fn get_projects(&self) -> Result<Vec<Project>, Error> {
+        Ok(self.projects.iter().map(|(p, _)| p.clone()).collect())
+    }
+
+    /// A project the mock was not given is reported as having no resources
+    async fn get_project_resources(&self, project_id: &str) -> Result<Vec<ProjectResource>, Error> {
+        Ok(self
+            .projects
+            .iter()
+            .find(|(p, _)| p.id == project_id)
+            .map(|(_, resources)| resources.clone())
+            .unwrap_or_default())
+    }
+}
+
+#[derive(Default)]
+pub struct MockReservedIpClient {
+    reserved_ips: RefCell<Vec<ReservedIp>>,
+    next_action_id: Cell<u64>,
+}
+
+impl MockReservedIpClient {
+    pub fn new() -> MockReservedIpClient {
+        Default::default()
+    }
+
+    pub fn with_reserved_ip(self, reserved_ip: ReservedIp) -> MockReservedIpClient {
+        self.reserved_ips.borrow_mut().push(reserved_ip);
+        self
+    }
+
+    /// The current state of every reserved IP
+    pub fn reserved_ips(&self) -> Vec<ReservedIp> {
+        self.reserved_ips.borrow().clone()
+    }
+}
+
+#[async_trait(?Send)]
+impl DigitalOceanReservedIpClient for MockReservedIpClient {
+    async fn get_reserved_ips(&self) -> Result<Vec<ReservedIp>, Error> {
+        Ok(self.reserved_ips())
+    }
+
+    /// Assign the reserved IP immediately.  The mock doesn't know droplet names, so the assigned
+    /// droplet is recorded with an empty name.
+    async fn assign_reserved_ip(
+        &self,
+        ip: &IpAddr,
+        droplet_id: &u32,
+        dry_run: &bool,
+    ) -> Result<ReservedIpAction, Error> {
+        let mut reserved_ips = self.reserved_ips.borrow_mut();
+        let reserved_ip = reserved_ips
+            .iter_mut()
+            .find(|r| r.ip == *ip)
+            .ok_or_else(|| {
+                Error::AssignReservedIp(not_found(format!("/v2/reserved_ips/{ip}/actions")))
+            })?;
+        if !*dry_run {
+            reserved_ip.droplet = Some(ReservedIpDroplet {
+                id: *droplet_id,
+                name: String::new(),
+            });
+        }
+        self.next_action_id.set(self.next_action_id.get() + 1);
+        Ok(ReservedIpAction {
+            id: self.next_action_id.get(),
+            status: "completed".to_string(),
+            typ: "assign_ip".to_string(),
+            resource_id: 0,
+        })
+    }
+}
+
+#[derive(Default)]
+pub struct MockTagsClient {
+    tags: Vec<Tag>,
+}
+
+impl MockTagsClient {
+    pub fn new() -> MockTagsClient {
+        Default::default()
+    }
+
+    pub fn with_tag(mut self, tag: Tag) -> MockTagsClient {
+        self.tags.push(tag);
+        self
+    }
+}
+
+#[async_trait(?Send)]
+impl DigitalOceanTagsClient for MockTagsClient {
+    async fn get_tags(&self) -> Result<Vec<Tag>, Error> {
+        Ok(self.tags.clone())
+    }
+
+    async fn get_tag(&self, name: &str) -> Result<Option<Tag>, Error> {
+        Ok(self.tags.iter().find(|t| t.name == name).cloned())
+    }
+}
+
+#[derive(Default)]
+pub struct MockVpcClient {
+    vpcs: Vec<Vpc>,
+}
+
+impl MockVpcClient {
+    pub fn new() -> MockVpcClient {
+        Default::default()
+    }
+
+    pub fn with_vpc(mut self, vpc: Vpc) -> MockVpcClient {
+        self.vpcs.push(vpc);
+        self
+    }
+}
+
+#[async_trait(?Send)]
+impl DigitalOceanVpcClient for MockVpcClient {
+    async fn get_vpcs(&self) -> Result<Vec<Vpc>, Error> {
+        Ok(self.vpcs.clone())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::{IpAddr, Ipv4Addr};
+
+    use crate::digitalocean::dns::{DigitalOceanDnsClient, DomainRecord};
+    use crate::digitalocean::firewall::{
+        DigitalOceanFirewallClient, Firewall, FirewallInboundRule, FirewallRuleTarget,
+    };
+    use crate::digitalocean::mock::{MockDnsClient, MockFirewallClient};
+
+    fn record(id: u32, name: &str, data: &str) -> DomainRecord {
+        DomainRecord {
+            id,
+            typ: "A".to_string(),
+            name: name.to_string(),
+            data: data.to_string(),
+            priority: None,
+            port: None,
+            ttl: 60,
+            weight: None,
+            flags: None,
+            tag: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_dns_update_and_create() {
+        let client = MockDnsClient::new()
+            .with_domain("example.com")
+            .with_record("example.com", record(5, "home", "1.1.1.1"));
+        let ip: IpAddr = Ipv4Addr::new(2, 2, 2, 2).into();
+
+        assert!(client.get_domain("example.com").await.unwrap().is_some());
+        assert!(client.get_domain("example.org").await.unwrap().is_none());
+
+        let existing = client
+            .get_record("example.com", "home", "A")
+            .await
+            .unwrap()
+            .unwrap();
+        client
+            .update_record("example.com", &existing, &ip.into(), &30, &true)
+            .await
+            .unwrap();
+        assert_eq!(
+            vec![record(5, "home", "1.1.1.1")],
+            client.records("example.com")
+        );
+        client
+            .update_record("example.com", &existing, &ip.into(), &60, &false)
+            .await
+            .unwrap();
+        assert_eq!(
+            vec![record(5, "home", "2.2.2.2")],
+            client.records("example.com")
+        );
+
+        let created = client
+            .create_record("example.com", "vpn", "A", &ip.into(), &60, &false)
+            .await
+            .unwrap();
+        assert_eq!(record(6, "vpn", "2.2.2.2"), created);
+        assert_eq!(
+            2,
+            client.get_records("example.com", "A").await.unwrap().len()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_firewall_replace_rule() {
+        let rule = |address: &str| FirewallInboundRule {
+            protocol: "tcp".to_string(),
+            ports: "22".to_string(),
+            sources: FirewallRuleTarget {
+                addresses: Some(vec![address.to_string()]),
+                droplet_ids: None,
+                load_balancer_uids: None,
+                kubernetes_ids: None,
+                tags: None,
+            },
+        };
+        let client = MockFirewallClient::new().with_firewall(Firewall {
+            id: "fw".to_string(),
+            status: "succeeded".to_string(),
+            created_at: "".to_string(),
+            pending_changes: vec![],
+            name: "home".to_string(),
+            droplet_ids: None,
+            tags: None,
+            inbound_rules: Some(vec![rule("1.1.1.1")]),
+            outbound_rules: None,
+        });
+
+        client
+            .delete_firewall_rule("fw", Some(vec![rule("1.1.1.1")]), None, &false)
+            .await
+            .unwrap();
+        client
+            .add_firewall_rule("fw", Some(vec![rule("2.2.2.2")]), None, &false)
+            .await
+            .unwrap();
+        assert_eq!(
+            Some(vec![rule("2.2.2.2")]),
+            client
+                .get_firewall("home".to_string())
+                .await
+                .unwrap()
+                .unwrap()
+                .inbound_rules
+        );
+        assert!(client
+            .add_firewall_rule("missing", None, None, &false)
+            .await
+            .is_err());
+        assert_eq!(1, client.firewalls().len());
+    }
+}
@@ -1,4 +1,8 @@
-use crate::digitalocean::api::DigitalOceanApiClient;
+use crate::digitalocean::account::{DigitalOceanAccountClient, DigitalOceanAccountClientImpl};
+use crate::digitalocean::api::{ApiStats, DigitalOceanApiClient, RetryPolicy, DEFAULT_TIMEOUT};
+use crate::digitalocean::cache::{
+    CachedDropletClient, CachedKubernetesClient, CachedLoadbalancerClient, ListCache,
+};
 use crate::digitalocean::dns::{DigitalOceanDnsClient, DigitalOceanDnsClientImpl};
 use crate::digitalocean::droplet::{DigitalOceanDropletClient, DigitalOceanDropletClientImpl};
 use crate::digitalocean::firewall::{DigitalOceanFirewallClient, DigitalOceanFirewallClientImpl};
@@ -8,39 +12,108 @@ use crate::digitalocean::kubernetes::{
 use crate::digitalocean::loadbalancer::{
     DigitalOceanLoadbalancerClient, DigitalOceanLoadbalancerClientImpl,
 };
+use crate::digitalocean::project::{DigitalOceanProjectsClient, DigitalOceanProjectsClientImpl};
+use crate::digitalocean::reserved_ip::{
+    DigitalOceanReservedIpClient, DigitalOceanReservedIpClientImpl,
+};
+use crate::digitalocean::tags::{DigitalOceanTagsClient, DigitalOceanTagsClientImpl};
+use crate::digitalocean::vpc::{DigitalOceanVpcClient, DigitalOceanVpcClientImpl};
+use crate::http::HttpOptions;
+use crate::secret::Secret;
+use std::path::PathBuf;
 use std::rc::Rc;
+use std::time::Duration;
 
+pub mod account;
 pub mod api;
+pub mod cache;
 pub mod dns;
 pub mod droplet;
 pub mod error;
 pub mod firewall;
 pub mod kubernetes;
 pub mod loadbalancer;
+#[cfg(any(test, feature = "test-utils"))]
+pub mod mock;
+pub mod project;
+pub mod reserved_ip;
+pub mod tags;
+pub mod vpc;
 
 #[allow(dead_code)]
 pub struct DigitalOceanClient {
     api: DigitalOceanApiClient,
+    pub account: Rc<dyn DigitalOceanAccountClient>,
     pub dns: Rc<dyn DigitalOceanDnsClient>,
     pub droplet: Rc<dyn DigitalOceanDropletClient>,
     pub firewall: Rc<dyn DigitalOceanFirewallClient>,
     pub kubernetes: Rc<dyn DigitalOceanKubernetesClient>,
     pub load_balancer: Rc<dyn DigitalOceanLoadbalancerClient>,
+    pub project: Rc<dyn DigitalOceanProjectsClient>,
+    pub reserved_ip: Rc<dyn DigitalOceanReservedIpClient>,
+    pub tags: Rc<dyn DigitalOceanTagsClient>,
+    pub vpc: Rc<dyn DigitalOceanVpcClient>,
 }
 
 impl DigitalOceanClient {
-    pub fn new(token: String) -> DigitalOceanClient {
-        DigitalOceanClient::new_for_client(DigitalOceanApiClient::new(token))
+    pub fn new(
+        token: Secret,
+        max_pages: Option<u32>,
+        per_page: u32,
+        page_concurrency: usize,
+        retry: RetryPolicy,
+        http: &HttpOptions,
+    ) -> DigitalOceanClient {
+        DigitalOceanClient::new_for_client(
+            DigitalOceanApiClient::new(token)
+                .with_http_client(http.client(DEFAULT_TIMEOUT))
+                .with_trace_http(http.trace)
+                .with_max_pages(max_pages)
+                .with_per_page(Some(per_page))
+                .with_page_concurrency(page_concurrency)
+                .with_retry(retry),
+        )
+    }
+
+    /// Serve the listings used to resolve names to IDs from the cache file, if one is given,
+    /// fetching them again once they are older than `ttl`
+    pub fn with_cache(mut self, path: Option<PathBuf>, ttl: Duration) -> DigitalOceanClient {
+        if let Some(path) = path {
+            let cache = Rc::new(ListCache::new(path, ttl, self.account.clone()));
+            self.droplet = Rc::new(CachedDropletClient {
+                inner: self.droplet,
+                cache: cache.clone(),
+            });
+            self.kubernetes = Rc::new(CachedKubernetesClient {
+                inner: self.kubernetes,
+                cache: cache.clone(),
+            });
+            self.load_balancer = Rc::new(CachedLoadbalancerClient {
+                inner: self.load_balancer,
+                cache,
+            });
+        }
+        self
+    }
+
+    /// Counters of the API traffic made through this client so far
+    pub fn stats(&self) -> &ApiStats {
+        self.api.stats()
     }
 
     fn new_for_client(api: DigitalOceanApiClient) -> DigitalOceanClient {
         DigitalOceanClient {
             api: api.clone(),
+            account: Rc::new(DigitalOceanAccountClientImpl::new(api.clone())),
             dns: Rc::new(DigitalOceanDnsClientImpl::new(api.clone())),
             droplet: Rc::new(DigitalOceanDropletClientImpl::new(api.clone())),
             firewall: Rc::new(DigitalOceanFirewallClientImpl::new(api.clone())),
             kubernetes: Rc::new(DigitalOceanKubernetesClientImpl::new(api.clone())),
-            load_balancer: Rc::new(DigitalOceanLoadbalancerClientImpl::new(api)),
+            load_balancer: Rc::new(DigitalOceanLoadbalancerClientImpl::new(api.clone())),
+            project: Rc::new(DigitalOceanProjectsClientImpl::new(api.clone())),
+            reserved_ip: Rc::new(DigitalOceanReservedIpClientImpl::new(api.clone())),
+            tags: Rc::new(DigitalOceanTagsClientImpl::new(api.clone())),
+            vpc: Rc::new(DigitalOceanVpcClientImpl::new(api)),
         }
     }
 
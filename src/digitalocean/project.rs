@@ -0,0 +1,245 @@
+use crate::digitalocean::api::{lenient, DigitalOceanApiClient, Links, Meta};
+use crate::digitalocean::error::Error;
+use async_trait::async_trait;
+use serde::Deserialize;
+
+#[async_trait(?Send)]
+pub trait DigitalOceanProjectsClient {
+    async fn get_projects(&self) -> Result<Vec<Project>, Error>;
+
+    async fn get_project_resources(&self, project_id: &str) -> Result<Vec<ProjectResource>, Error>;
+}
+
+pub struct DigitalOceanProjectsClientImpl {
+    api: DigitalOceanApiClient,
+}
+
+impl DigitalOceanProjectsClientImpl {
+    pub fn new(api: DigitalOceanApiClient) -> DigitalOceanProjectsClientImpl {
+        DigitalOceanProjectsClientImpl { api }
+    }
+}
+
+#[async_trait(?Send)]
+impl DigitalOceanProjectsClient for DigitalOceanProjectsClientImpl {
+    /// Get info on all projects.
+    async fn get_projects(&self) -> Result<Vec<Project>, Error> {
+        self.api
+            .get_all_objects(
+                self.api.get_url("/v2/projects"),
+                |r: ProjectsResp| r.projects,
+                |r: &ProjectsResp| r.links.clone(),
+                |r: &ProjectsResp| &r.meta,
+            )
+            .await
+    }
+
+    /// Get the resources assigned to a project.
+    async fn get_project_resources(&self, project_id: &str) -> Result<Vec<ProjectResource>, Error> {
+        self.api
+            .get_all_objects(
+                self.api
+                    .get_url(format!("/v2/projects/{project_id}/resources").as_str()),
+                |r: ProjectResourcesResp| r.resources,
+                |r: &ProjectResourcesResp| r.links.clone(),
+                |r: &ProjectResourcesResp| &r.meta,
+            )
+            .await
+    }
+}
+
+// /v2/projects
+
+#[derive(Deserialize, Debug)]
+struct ProjectsResp {
+    projects: Vec<Project>,
+    meta: Meta,
+    links: Links,
+}
+
+#[derive(Deserialize, Debug, Eq, PartialEq, Clone)]
+#[allow(dead_code)]
+pub struct Project {
+    /// The unique universal identifier of this project.
+    pub id: String,
+    /// The human-readable name for the project. The maximum length is 175 characters and the name
+    /// must be unique.
+    pub name: String,
+    /// The description of the project. The maximum length is 255 characters.
+    #[serde(default, deserialize_with = "lenient")]
+    pub description: String,
+    /// The purpose of the project. The maximum length is 255 characters.
+    #[serde(default, deserialize_with = "lenient")]
+    pub purpose: String,
+    /// The environment of the project's resources.
+    /// values: "Development" "Staging" "Production"
+    #[serde(default, deserialize_with = "lenient")]
+    pub environment: String,
+    /// If true, all resources will be added to this project if no project is specified.
+    #[serde(default, deserialize_with = "lenient")]
+    pub is_default: bool,
+}
+
+// /v2/projects/{id}/resources
+
+#[derive(Deserialize, Debug)]
+struct ProjectResourcesResp {
+    resources: Vec<ProjectResource>,
+    meta: Meta,
+    links: Links,
+}
+
+#[derive(Deserialize, Debug, Eq, PartialEq, Clone)]
+#[allow(dead_code)]
+pub struct ProjectResource {
+    /// The uniform resource name (URN) for the resource in the format do:resource_type:resource_id.
+    pub urn: String,
+    /// A time value given in ISO8601 combined date and time format that represents when the
+    /// project was created.
+    #[serde(default, deserialize_with = "lenient")]
+    pub assigned_at: String,
+    /// The status of assigning and fetching the resources.
+    /// values: "ok" "not_found" "assigned" "already_assigned" "service_down"
+    #[serde(default, deserialize_with = "lenient")]
+    pub status: String,
+}
+
+impl ProjectResource {
+    /// The URN DigitalOcean uses to refer to a droplet within a project
+    pub fn droplet_urn(id: u32) -> String {
+        format!("do:droplet:{id}")
+    }
+
+    /// The URN DigitalOcean uses to refer to a Kubernetes cluster within a project
+    pub fn kubernetes_urn(id: &str) -> String {
+        format!("do:kubernetes:{id}")
+    }
+
+    /// The URN DigitalOcean uses to refer to a load balancer within a project
+    pub fn load_balancer_urn(id: &str) -> String {
+        format!("do:loadbalancer:{id}")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::digitalocean::project::{Project, ProjectResource};
+    use crate::digitalocean::DigitalOceanClient;
+
+    #[tokio::test]
+    async fn test_get_projects() {
+        let mut server = mockito::Server::new_async().await;
+        let _m = server
+            .mock("GET", "/v2/projects")
+            .match_header("Authorization", "Bearer foo")
+            .with_status(200)
+            .with_header("Content-Type", "application/json")
+            .with_body(
+                serde_json::to_string(&json!({
+                    "projects": [{
+                        "id": "4e1bfbc3-dc3e-41f2-a18f-1b4d7ba71679",
+                        "owner_uuid": "99525febec065ca37b2ffe4f852fd2b2581895e7",
+                        "owner_id": 258992,
+                        "name": "my-web-api",
+                        "description": "My website API",
+                        "purpose": "Service or API",
+                        "environment": "Production",
+                        "created_at": "2018-09-27T20:10:35Z",
+                        "updated_at": "2018-09-27T20:10:35Z",
+                        "is_default": false,
+                    }, {
+                        "id": "addb4547-6bab-419a-8542-76263a033cf6",
+                        "name": "Default",
+                        "description": null,
+                        "is_default": true,
+                    }],
+                    "meta": {
+                        "total": 2
+                    },
+                    "links": {}
+                }))
+                .unwrap(),
+            )
+            .create_async()
+            .await;
+
+        let resp = DigitalOceanClient::new_for_test("foo".to_string(), server.url())
+            .project
+            .get_projects()
+            .await;
+        assert_eq!(
+            Ok(vec![
+                Project {
+                    id: "4e1bfbc3-dc3e-41f2-a18f-1b4d7ba71679".to_string(),
+                    name: "my-web-api".to_string(),
+                    description: "My website API".to_string(),
+                    purpose: "Service or API".to_string(),
+                    environment: "Production".to_string(),
+                    is_default: false,
+                },
+                Project {
+                    id: "addb4547-6bab-419a-8542-76263a033cf6".to_string(),
+                    name: "Default".to_string(),
+                    description: "".to_string(),
+                    purpose: "".to_string(),
+                    environment: "".to_string(),
+                    is_default: true,
+                },
+            ]),
+            resp
+        );
+        _m.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_get_project_resources() {
+        let mut server = mockito::Server::new_async().await;
+        let _m = server
+            .mock("GET", "/v2/projects/4e1bfbc3/resources")
+            .match_header("Authorization", "Bearer foo")
+            .with_status(200)
+            .with_header("Content-Type", "application/json")
+            .with_body(
+                serde_json::to_string(&json!({
+                    "resources": [{
+                        "urn": "do:droplet:13457723",
+                        "assigned_at": "2018-09-28T19:26:37Z",
+                        "links": {
+                            "self": "https://api.digitalocean.com/v2/droplets/13457723"
+                        },
+                        "status": "ok",
+                    }, {
+                        "urn": "do:loadbalancer:4de7ac8b-495b-4884-9a69-1050c6793cd6",
+                    }],
+                    "meta": {
+                        "total": 2
+                    },
+                    "links": {}
+                }))
+                .unwrap(),
+            )
+            .create_async()
+            .await;
+
+        let resp = DigitalOceanClient::new_for_test("foo".to_string(), server.url())
+            .project
+            .get_project_resources("4e1bfbc3")
+            .await;
+        assert_eq!(
+            Ok(vec![
+                ProjectResource {
+                    urn: ProjectResource::droplet_urn(13457723),
+                    assigned_at: "2018-09-28T19:26:37Z".to_string(),
+                    status: "ok".to_string(),
+                },
+                ProjectResource {
+                    urn: ProjectResource::load_balancer_urn("4de7ac8b-495b-4884-9a69-1050c6793cd6"),
+                    assigned_at: "".to_string(),
+                    status: "".to_string(),
+                },
+            ]),
+            resp
+        );
+        _m.assert_async().await;
+    }
+}
@@ -0,0 +1,353 @@
+use async_trait::async_trait;
+use std::net::IpAddr;
+
+use reqwest::Method;
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::digitalocean::api::{lenient, DigitalOceanApiClient, Links, Meta};
+use crate::digitalocean::error::{ApiError, Error, Failure};
+
+#[async_trait(?Send)]
+pub trait DigitalOceanReservedIpClient {
+    async fn get_reserved_ips(&self) -> Result<Vec<ReservedIp>, Error>;
+
+    async fn assign_reserved_ip(
+        &self,
+        ip: &IpAddr,
+        droplet_id: &u32,
+        dry_run: &bool,
+    ) -> Result<ReservedIpAction, Error>;
+}
+
+pub struct DigitalOceanReservedIpClientImpl {
+    api: DigitalOceanApiClient,
+}
+
+impl DigitalOceanReservedIpClientImpl {
+    pub fn new(api: DigitalOceanApiClient) -> DigitalOceanReservedIpClientImpl {
+        DigitalOceanReservedIpClientImpl { api }
+    }
+}
+
+#[async_trait(?Send)]
+impl DigitalOceanReservedIpClient for DigitalOceanReservedIpClientImpl {
+    /// Get info on all reserved IPs
+    async fn get_reserved_ips(&self) -> Result<Vec<ReservedIp>, Error> {
+        self.api
+            .get_all_objects(
+                self.api.get_url("/v2/reserved_ips"),
+                |r: ReservedIpsResp| r.reserved_ips,
+                |r: &ReservedIpsResp| r.links.clone(),
+                |r: &ReservedIpsResp| &r.meta,
+            )
+            .await
+    }
+
+    /// Assign a reserved IP to a droplet, unassigning it from any droplet it is currently
+    /// assigned to.  The API completes the assignment asynchronously; the returned action tracks
+    /// its progress.
+    async fn assign_reserved_ip(
+        &self,
+        ip: &IpAddr,
+        droplet_id: &u32,
+        dry_run: &bool,
+    ) -> Result<ReservedIpAction, Error> {
+        if *dry_run {
+            info!(
+                "DRY RUN: Assigning reserved IP {} to droplet {}",
+                ip, droplet_id
+            );
+            Ok(ReservedIpAction {
+                id: 0,
+                status: "".to_string(),
+                typ: ASSIGN_ACTION.to_string(),
+                resource_id: 0,
+            })
+        } else {
+            let url = self
+                .api
+                .get_url(format!("/v2/reserved_ips/{}/actions", ip).as_str());
+            let request =
+                self.api
+                    .get_request_builder(Method::POST, url)
+                    .json(&ReservedIpActionPostBody {
+                        typ: "assign".to_string(),
+                        droplet_id: *droplet_id,
+                    });
+            let resp = self.api.send(request).await?;
+            if !resp.status().is_success() {
+                return Err(Error::AssignReservedIp(Failure::Rejected(
+                    ApiError::from_response(resp).await,
+                )));
+            }
+            let resp = resp.json::<ReservedIpActionResp>().await?;
+            if resp.action.typ == ASSIGN_ACTION && resp.action.status != "errored" {
+                Ok(resp.action)
+            } else {
+                Err(Error::AssignReservedIp(Failure::Unexpected(format!(
+                    "assigning reserved IP {} returned a {} action with status {}",
+                    ip, resp.action.typ, resp.action.status
+                ))))
+            }
+        }
+    }
+}
+
+/// The type of the action the API starts when a reserved IP is assigned
+const ASSIGN_ACTION: &str = "assign_ip";
+
+// /v2/reserved_ips
+
+#[derive(Deserialize, Debug)]
+struct ReservedIpsResp {
+    reserved_ips: Vec<ReservedIp>,
+    meta: Meta,
+    links: Links,
+}
+
+#[derive(Deserialize, Debug, Eq, PartialEq, Clone)]
+pub struct ReservedIp {
+    /// The public IP address of the reserved IP.
+    pub ip: IpAddr,
+    /// The Droplet that the reserved IP has been assigned to, or null if it is unassigned.
+    #[serde(default, deserialize_with = "lenient")]
+    pub droplet: Option<ReservedIpDroplet>,
+    /// The region that the reserved IP is reserved to.
+    #[serde(default, deserialize_with = "lenient")]
+    pub region: ReservedIpRegion,
+    /// A boolean value indicating whether or not the reserved IP has pending actions preventing
+    /// new ones from being submitted.
+    #[serde(default, deserialize_with = "lenient")]
+    pub locked: bool,
+}
+
+/// The identifying fields of the Droplet a reserved IP is assigned to
+#[derive(Deserialize, Debug, Eq, PartialEq, Clone)]
+pub struct ReservedIpDroplet {
+    pub id: u32,
+    pub name: String,
+}
+
+#[derive(Deserialize, Debug, Default, Eq, PartialEq, Clone)]
+pub struct ReservedIpRegion {
+    /// The display name of the region.
+    pub name: String,
+    /// A human-readable string that is used as a unique identifier for each region.
+    pub slug: String,
+}
+
+// /v2/reserved_ips/[ip]/actions
+
+#[derive(Serialize, Debug)]
+struct ReservedIpActionPostBody {
+    #[serde(rename = "type")]
+    typ: String,
+    droplet_id: u32,
+}
+
+#[derive(Deserialize, Debug)]
+struct ReservedIpActionResp {
+    action: ReservedIpAction,
+}
+
+#[derive(Deserialize, Debug, Eq, PartialEq, Clone)]
+pub struct ReservedIpAction {
+    /// A unique numeric ID that can be used to identify and reference an action.
+    pub id: u64,
+    /// The current status of the action. This can be "in-progress", "completed", or "errored".
+    pub status: String,
+    /// The type of action that the event is executing.
+    #[serde(rename = "type")]
+    pub typ: String,
+    /// A unique identifier for the resource that the action is associated with.
+    #[serde(default, deserialize_with = "lenient")]
+    pub resource_id: u64,
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::IpAddr;
+
+    use crate::digitalocean::error::Error;
+    use crate::digitalocean::reserved_ip::{
+        ReservedIp, ReservedIpAction, ReservedIpDroplet, ReservedIpRegion,
+    };
+    use crate::digitalocean::DigitalOceanClient;
+
+    fn get_reserved_ip_1_json() -> serde_json::Value {
+        json!({
+            "ip": "45.55.96.47",
+            "droplet": {
+                "id": 3164444,
+                "name": "example.com"
+            },
+            "region": {
+                "name": "New York 3",
+                "slug": "nyc3"
+            },
+            "locked": false,
+            "project_id": "746c6152-2fa2-11ed-92d3-27aaa54e4988"
+        })
+    }
+
+    fn get_reserved_ip_1_obj() -> ReservedIp {
+        ReservedIp {
+            ip: "45.55.96.47".parse().unwrap(),
+            droplet: Some(ReservedIpDroplet {
+                id: 3164444,
+                name: "example.com".to_string(),
+            }),
+            region: ReservedIpRegion {
+                name: "New York 3".to_string(),
+                slug: "nyc3".to_string(),
+            },
+            locked: false,
+        }
+    }
+
+    fn get_reserved_ip_2_json() -> serde_json::Value {
+        json!({
+            "ip": "2001:db8::1",
+            "droplet": null,
+            "region": {
+                "name": "New York 3",
+                "slug": "nyc3"
+            },
+            "locked": true
+        })
+    }
+
+    fn get_reserved_ip_2_obj() -> ReservedIp {
+        ReservedIp {
+            ip: "2001:db8::1".parse().unwrap(),
+            droplet: None,
+            region: ReservedIpRegion {
+                name: "New York 3".to_string(),
+                slug: "nyc3".to_string(),
+            },
+            locked: true,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_get_reserved_ips() {
+        let mut server = mockito::Server::new_async().await;
+        let _m = server
+            .mock("GET", "/v2/reserved_ips")
+            .match_header("Authorization", "Bearer foo")
+            .with_status(200)
+            .with_header("Content-Type", "application/json")
+            .with_body(
+                serde_json::to_string(&json!({
+                    "reserved_ips": [
+                        get_reserved_ip_1_json(),
+                        get_reserved_ip_2_json(),
+                    ],
+                    "meta": {
+                        "total": 2
+                    },
+                    "links": {}
+                }))
+                .unwrap(),
+            )
+            .create_async()
+            .await;
+
+        let resp = DigitalOceanClient::new_for_test("foo".to_string(), server.url())
+            .reserved_ip
+            .get_reserved_ips()
+            .await;
+        assert_eq!(
+            Ok(vec![get_reserved_ip_1_obj(), get_reserved_ip_2_obj()]),
+            resp
+        );
+        _m.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_assign_reserved_ip() {
+        let mut server = mockito::Server::new_async().await;
+        let _m = server
+            .mock("POST", "/v2/reserved_ips/45.55.96.47/actions")
+            .match_header("Authorization", "Bearer foo")
+            .match_body(mockito::Matcher::Json(json!({
+                "type": "assign",
+                "droplet_id": 8219222
+            })))
+            .with_status(201)
+            .with_header("Content-Type", "application/json")
+            .with_body(
+                serde_json::to_string(&json!({
+                    "action": {
+                        "id": 68212728,
+                        "status": "in-progress",
+                        "type": "assign_ip",
+                        "started_at": "2015-10-15T17:45:44Z",
+                        "completed_at": null,
+                        "resource_id": 758603823,
+                        "resource_type": "reserved_ip",
+                        "region_slug": "nyc3"
+                    }
+                }))
+                .unwrap(),
+            )
+            .create_async()
+            .await;
+
+        let resp = DigitalOceanClient::new_for_test("foo".to_string(), server.url())
+            .reserved_ip
+            .assign_reserved_ip(&"45.55.96.47".parse::<IpAddr>().unwrap(), &8219222, &false)
+            .await;
+        assert_eq!(
+            Ok(ReservedIpAction {
+                id: 68212728,
+                status: "in-progress".to_string(),
+                typ: "assign_ip".to_string(),
+                resource_id: 758603823,
+            }),
+            resp
+        );
+        _m.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_assign_reserved_ip_errored() {
+        let mut server = mockito::Server::new_async().await;
+        let _m = server
+            .mock("POST", "/v2/reserved_ips/45.55.96.47/actions")
+            .with_status(201)
+            .with_header("Content-Type", "application/json")
+            .with_body(
+                serde_json::to_string(&json!({
+                    "action": {
+                        "id": 68212728,
+                        "status": "errored",
+                        "type": "assign_ip",
+                        "resource_id": 758603823
+                    }
+                }))
+                .unwrap(),
+            )
+            .create_async()
+            .await;
+
+        let resp = DigitalOceanClient::new_for_test("foo".to_string(), server.url())
+            .reserved_ip
+            .assign_reserved_ip(&"45.55.96.47".parse::<IpAddr>().unwrap(), &8219222, &false)
+            .await;
+        assert!(matches!(resp, Err(Error::AssignReservedIp(_))));
+        _m.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_assign_reserved_ip_dry_run() {
+        let server = mockito::Server::new_async().await;
+
+        let resp = DigitalOceanClient::new_for_test("foo".to_string(), server.url())
+            .reserved_ip
+            .assign_reserved_ip(&"45.55.96.47".parse::<IpAddr>().unwrap(), &8219222, &true)
+            .await;
+        assert!(resp.is_ok());
+    }
+}
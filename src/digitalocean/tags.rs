@@ -0,0 +1,238 @@
+use crate::digitalocean::api::{lenient, DigitalOceanApiClient, Links, Meta};
+use crate::digitalocean::error::Error;
+use async_trait::async_trait;
+use reqwest::{Method, StatusCode};
+use serde::Deserialize;
+
+#[async_trait(?Send)]
+pub trait DigitalOceanTagsClient {
+    async fn get_tags(&self) -> Result<Vec<Tag>, Error>;
+
+    async fn get_tag(&self, name: &str) -> Result<Option<Tag>, Error>;
+}
+
+pub struct DigitalOceanTagsClientImpl {
+    api: DigitalOceanApiClient,
+}
+
+impl DigitalOceanTagsClientImpl {
+    pub fn new(api: DigitalOceanApiClient) -> DigitalOceanTagsClientImpl {
+        DigitalOceanTagsClientImpl { api }
+    }
+}
+
+#[async_trait(?Send)]
+impl DigitalOceanTagsClient for DigitalOceanTagsClientImpl {
+    /// Get info on all tags.
+    async fn get_tags(&self) -> Result<Vec<Tag>, Error> {
+        self.api
+            .get_all_objects(
+                self.api.get_url("/v2/tags"),
+                |r: TagsResp| r.tags,
+                |r: &TagsResp| r.links.clone(),
+                |r: &TagsResp| &r.meta,
+            )
+            .await
+    }
+
+    /// Get the named tag and a count of the resources carrying it, if the tag exists.
+    async fn get_tag(&self, name: &str) -> Result<Option<Tag>, Error> {
+        let url = self.api.get_url(format!("/v2/tags/{}", name).as_str());
+        let request = self.api.get_request_builder(Method::GET, url);
+        let resp = self.api.send(request).await?;
+        if resp.status() == StatusCode::NOT_FOUND {
+            Ok(None)
+        } else {
+            Ok(Some(resp.error_for_status()?.json::<TagResp>().await?.tag))
+        }
+    }
+}
+
+// /v2/tags
+
+#[derive(Deserialize, Debug)]
+struct TagsResp {
+    tags: Vec<Tag>,
+    meta: Meta,
+    links: Links,
+}
+
+// /v2/tags/{name}
+
+#[derive(Deserialize, Debug)]
+struct TagResp {
+    tag: Tag,
+}
+
+#[derive(Deserialize, Debug, Eq, PartialEq, Clone)]
+#[allow(dead_code)]
+pub struct Tag {
+    /// The name of the tag. Tags may contain letters, numbers, colons, dashes, and underscores.
+    /// There is a limit of 255 characters per tag.
+    pub name: String,
+    /// An embedded object containing key value pairs of resource type and resource statistics. It
+    /// also includes a count of the total number of resources tagged with the current tag as well
+    /// as a `last_tagged_uri` attribute set to the last resource tagged with the current tag.
+    #[serde(default, deserialize_with = "lenient")]
+    pub resources: TagResources,
+}
+
+#[derive(Deserialize, Debug, Eq, PartialEq, Clone, Default)]
+#[allow(dead_code)]
+pub struct TagResources {
+    /// The number of tagged objects for this type of resource.
+    #[serde(default, deserialize_with = "lenient")]
+    pub count: u32,
+    /// The URI for the last tagged object for this type of resource.
+    #[serde(default, deserialize_with = "lenient")]
+    pub last_tagged_uri: String,
+    /// Tagged Droplet resources.
+    #[serde(default, deserialize_with = "lenient")]
+    pub droplets: TagResourceCount,
+}
+
+#[derive(Deserialize, Debug, Eq, PartialEq, Clone, Default)]
+#[allow(dead_code)]
+pub struct TagResourceCount {
+    /// The number of tagged objects for this type of resource.
+    #[serde(default, deserialize_with = "lenient")]
+    pub count: u32,
+    /// The URI for the last tagged object for this type of resource.
+    #[serde(default, deserialize_with = "lenient")]
+    pub last_tagged_uri: String,
+}
+
+#[cfg(test)]
+mod test {
+    use crate::digitalocean::tags::{Tag, TagResourceCount, TagResources};
+    use crate::digitalocean::DigitalOceanClient;
+
+    fn get_tag_json() -> serde_json::Value {
+        json!({
+            "name": "extra-awesome",
+            "resources": {
+                "count": 1,
+                "last_tagged_uri": "https://api.digitalocean.com/v2/droplets/14",
+                "droplets": {
+                    "count": 1,
+                    "last_tagged_uri": "https://api.digitalocean.com/v2/droplets/14"
+                },
+                "volumes": {
+                    "count": 0
+                },
+                "volume_snapshots": {
+                    "count": 0
+                },
+                "databases": {
+                    "count": 0
+                }
+            }
+        })
+    }
+
+    fn get_tag_obj() -> Tag {
+        Tag {
+            name: "extra-awesome".to_string(),
+            resources: TagResources {
+                count: 1,
+                last_tagged_uri: "https://api.digitalocean.com/v2/droplets/14".to_string(),
+                droplets: TagResourceCount {
+                    count: 1,
+                    last_tagged_uri: "https://api.digitalocean.com/v2/droplets/14".to_string(),
+                },
+            },
+        }
+    }
+
+    #[tokio::test]
+    async fn test_get_tags() {
+        let mut server = mockito::Server::new_async().await;
+        let _m = server
+            .mock("GET", "/v2/tags")
+            .match_header("Authorization", "Bearer foo")
+            .with_status(200)
+            .with_header("Content-Type", "application/json")
+            .with_body(
+                serde_json::to_string(&json!({
+                    "tags": [
+                        get_tag_json(),
+                        {
+                            "name": "unused",
+                            "resources": {
+                                "count": 0
+                            }
+                        }
+                    ],
+                    "meta": {
+                        "total": 2
+                    },
+                    "links": {}
+                }))
+                .unwrap(),
+            )
+            .create_async()
+            .await;
+
+        let resp = DigitalOceanClient::new_for_test("foo".to_string(), server.url())
+            .tags
+            .get_tags()
+            .await;
+        assert_eq!(
+            Ok(vec![
+                get_tag_obj(),
+                Tag {
+                    name: "unused".to_string(),
+                    resources: TagResources::default(),
+                }
+            ]),
+            resp
+        );
+        _m.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_get_tag() {
+        let mut server = mockito::Server::new_async().await;
+        let _m = server
+            .mock("GET", "/v2/tags/extra-awesome")
+            .match_header("Authorization", "Bearer foo")
+            .with_status(200)
+            .with_header("Content-Type", "application/json")
+            .with_body(serde_json::to_string(&json!({ "tag": get_tag_json() })).unwrap())
+            .create_async()
+            .await;
+
+        let resp = DigitalOceanClient::new_for_test("foo".to_string(), server.url())
+            .tags
+            .get_tag("extra-awesome")
+            .await;
+        assert_eq!(Ok(Some(get_tag_obj())), resp);
+        _m.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_get_tag_missing() {
+        let mut server = mockito::Server::new_async().await;
+        let _m = server
+            .mock("GET", "/v2/tags/nope")
+            .match_header("Authorization", "Bearer foo")
+            .with_status(404)
+            .with_header("Content-Type", "application/json")
+            .with_body(
+                serde_json::to_string(&json!({
+                    "id": "not_found",
+                    "message": "The resource you were accessing could not be found."
+                }))
+                .unwrap(),
+            )
+            .create_async()
+            .await;
+
+        let resp = DigitalOceanClient::new_for_test("foo".to_string(), server.url())
+            .tags
+            .get_tag("nope")
+            .await;
+        assert_eq!(Ok(None), resp);
+        _m.assert_async().await;
+    }
+}
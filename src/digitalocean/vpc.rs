@@ -0,0 +1,149 @@
+use crate::digitalocean::api::{lenient, DigitalOceanApiClient, Links, Meta};
+use crate::digitalocean::error::Error;
+use async_trait::async_trait;
+use serde::Deserialize;
+
+#[async_trait(?Send)]
+pub trait DigitalOceanVpcClient {
+    async fn get_vpcs(&self) -> Result<Vec<Vpc>, Error>;
+}
+
+pub struct DigitalOceanVpcClientImpl {
+    api: DigitalOceanApiClient,
+}
+
+impl DigitalOceanVpcClientImpl {
+    pub fn new(api: DigitalOceanApiClient) -> DigitalOceanVpcClientImpl {
+        DigitalOceanVpcClientImpl { api }
+    }
+}
+
+#[async_trait(?Send)]
+impl DigitalOceanVpcClient for DigitalOceanVpcClientImpl {
+    /// Get info on all VPCs.
+    async fn get_vpcs(&self) -> Result<Vec<Vpc>, Error> {
+        self.api
+            .get_all_objects(
+                self.api.get_url("/v2/vpcs"),
+                |r: VpcsResp| r.vpcs,
+                |r: &VpcsResp| r.links.clone(),
+                |r: &VpcsResp| &r.meta,
+            )
+            .await
+    }
+}
+
+// /v2/vpcs
+
+#[derive(Deserialize, Debug)]
+struct VpcsResp {
+    vpcs: Vec<Vpc>,
+    meta: Meta,
+    links: Links,
+}
+
+#[derive(Deserialize, Debug, Eq, PartialEq, Clone)]
+#[allow(dead_code)]
+pub struct Vpc {
+    /// A unique ID that can be used to identify and reference the VPC.
+    pub id: String,
+    /// The name of the VPC. Must be unique and may only contain alphanumeric characters, dashes,
+    /// and periods.
+    pub name: String,
+    /// The range of IP addresses in the VPC in CIDR notation. Network ranges cannot overlap with
+    /// other networks in the same account and must be in range of private addresses as defined
+    /// in RFC1918.
+    pub ip_range: String,
+    /// The slug identifier for the region where the VPC will be created.
+    #[serde(default, deserialize_with = "lenient")]
+    pub region: String,
+    /// A free-form text field for describing the VPC's purpose. It may be a maximum of 255
+    /// characters.
+    #[serde(default, deserialize_with = "lenient")]
+    pub description: String,
+    /// The uniform resource name (URN) for the resource in the format do:resource_type:resource_id.
+    #[serde(default, deserialize_with = "lenient")]
+    pub urn: String,
+    /// A boolean value indicating whether or not the VPC is the default network for the region.
+    /// All applicable resources are placed into the default VPC network unless otherwise
+    /// specified during their creation.
+    #[serde(default, deserialize_with = "lenient")]
+    pub default: bool,
+    /// A time value given in ISO8601 combined date and time format.
+    #[serde(default, deserialize_with = "lenient")]
+    pub created_at: String,
+}
+
+#[cfg(test)]
+mod test {
+    use crate::digitalocean::vpc::Vpc;
+    use crate::digitalocean::DigitalOceanClient;
+
+    #[tokio::test]
+    async fn test_get_vpcs() {
+        let mut server = mockito::Server::new_async().await;
+        let _m = server
+            .mock("GET", "/v2/vpcs")
+            .match_header("Authorization", "Bearer foo")
+            .with_status(200)
+            .with_header("Content-Type", "application/json")
+            .with_body(
+                serde_json::to_string(&json!({
+                    "vpcs": [{
+                        "name": "env.prod-vpc",
+                        "description": "VPC for production environment",
+                        "region": "nyc1",
+                        "ip_range": "10.10.10.0/24",
+                        "id": "5a4981aa-9653-4bd1-bef5-d6bff52042e4",
+                        "urn": "do:vpc:5a4981aa-9653-4bd1-bef5-d6bff52042e4",
+                        "default": false,
+                        "created_at": "2020-03-13T19:20:47.442049222Z",
+                    }, {
+                        "name": "default-nyc3",
+                        "region": "nyc3",
+                        "ip_range": "10.116.0.0/20",
+                        "id": "e0fe0f4d-596a-465e-a902-571ce57b79fa",
+                        "default": true,
+                    }],
+                    "meta": {
+                        "total": 2
+                    },
+                    "links": {}
+                }))
+                .unwrap(),
+            )
+            .create_async()
+            .await;
+
+        let resp = DigitalOceanClient::new_for_test("foo".to_string(), server.url())
+            .vpc
+            .get_vpcs()
+            .await;
+        assert_eq!(
+            Ok(vec![
+                Vpc {
+                    id: "5a4981aa-9653-4bd1-bef5-d6bff52042e4".to_string(),
+                    name: "env.prod-vpc".to_string(),
+                    ip_range: "10.10.10.0/24".to_string(),
+                    region: "nyc1".to_string(),
+                    description: "VPC for production environment".to_string(),
+                    urn: "do:vpc:5a4981aa-9653-4bd1-bef5-d6bff52042e4".to_string(),
+                    default: false,
+                    created_at: "2020-03-13T19:20:47.442049222Z".to_string(),
+                },
+                Vpc {
+                    id: "e0fe0f4d-596a-465e-a902-571ce57b79fa".to_string(),
+                    name: "default-nyc3".to_string(),
+                    ip_range: "10.116.0.0/20".to_string(),
+                    region: "nyc3".to_string(),
+                    description: "".to_string(),
+                    urn: "".to_string(),
+                    default: true,
+                    created_at: "".to_string(),
+                },
+            ]),
+            resp
+        );
+        _m.assert_async().await;
+    }
+}
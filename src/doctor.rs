@@ -0,0 +1,328 @@
+use std::net::IpAddr;
+
+use serde::Serialize;
+
+use crate::cli::SubcmdArgs;
+use crate::digitalocean::error;
+use crate::digitalocean::DigitalOceanClient;
+use crate::provider::DnsProviders;
+use crate::state::FirewallIds;
+use crate::{check, check_account, Error};
+
+/// How one diagnostic of `doctor` turned out
+#[derive(Serialize, Debug, Clone, Copy, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Outcome {
+    Pass,
+    Fail,
+    /// Not run because a diagnostic it depends on failed
+    Skip,
+}
+
+/// The outcome of one diagnostic, with what was found or why it failed
+#[derive(Serialize, Debug, Clone, Eq, PartialEq)]
+pub struct Diagnosis {
+    pub name: String,
+    pub outcome: Outcome,
+    pub detail: String,
+}
+
+impl Diagnosis {
+    fn new(name: &str, outcome: Outcome, detail: String) -> Diagnosis {
+        Diagnosis {
+            name: name.to_string(),
+            outcome,
+            detail,
+        }
+    }
+}
+
+/// Check the detection of the IP address(es), returning them if they were detected
+pub fn diagnose_detection(
+    detected: Result<Vec<IpAddr>, String>,
+) -> (Diagnosis, Option<Vec<IpAddr>>) {
+    match detected {
+        Ok(ips) => {
+            let detail = ips.iter().map(|ip| ip.to_string()).collect::<Vec<_>>();
+            (
+                Diagnosis::new("IP detection", Outcome::Pass, detail.join(", ")),
+                Some(ips),
+            )
+        }
+        Err(e) => (Diagnosis::new("IP detection", Outcome::Fail, e), None),
+    }
+}
+
+/// Check every dependency of an update besides the IP detection without changing anything: the API,
+/// the token of the client, and each target updated with it.  Targets are only checked once the
+/// token is accepted, and the targets that publish the detected IP address(es) only once they were
+/// detected.
+pub async fn diagnose_targets(
+    client: &DigitalOceanClient,
+    providers: &DnsProviders<'_>,
+    ips: Option<&[IpAddr]>,
+    targets: &[SubcmdArgs],
+) -> Vec<Diagnosis> {
+    let mut diagnoses = Vec::new();
+    let account = check_account(client.account.as_ref()).await;
+    let api_skipped = match account {
+        Ok(account) => {
+            diagnoses.push(Diagnosis::new(
+                "DigitalOcean API",
+                Outcome::Pass,
+                "reachable".to_string(),
+            ));
+            diagnoses.push(Diagnosis::new(
+                "API token",
+                Outcome::Pass,
+                format!(
+                    "accepted for account {} ({})",
+                    account.email, account.status
+                ),
+            ));
+            None
+        }
+        Err(Error::Client(error::Error::Request(e))) => {
+            diagnoses.push(Diagnosis::new(
+                "DigitalOcean API",
+                Outcome::Fail,
+                e.to_string(),
+            ));
+            diagnoses.push(Diagnosis::new(
+                "API token",
+                Outcome::Skip,
+                "the API is unreachable".to_string(),
+            ));
+            Some("the API is unreachable")
+        }
+        Err(e) => {
+            diagnoses.push(Diagnosis::new(
+                "DigitalOcean API",
+                Outcome::Pass,
+                "reachable".to_string(),
+            ));
+            diagnoses.push(Diagnosis::new("API token", Outcome::Fail, e.to_string()));
+            Some("the API token was not accepted")
+        }
+    };
+
+    for target in targets {
+        let name = target.to_string();
+        let ips = match (api_skipped, ips) {
+            (Some(reason), _) => {
+                diagnoses.push(Diagnosis::new(&name, Outcome::Skip, reason.to_string()));
+                continue;
+            }
+            (None, Some(ips)) => ips,
+            (None, None) if !target.follows_detected_ips() => &[],
+            (None, None) => {
+                diagnoses.push(Diagnosis::new(
+                    &name,
+                    Outcome::Skip,
+                    "no IP address was detected".to_string(),
+                ));
+                continue;
+            }
+        };
+        diagnoses.push(
+            match check(
+                client,
+                providers,
+                target.clone(),
+                ips,
+                &FirewallIds::default(),
+            )
+            .await
+            {
+                Ok(true) => Diagnosis::new(&name, Outcome::Pass, "up to date".to_string()),
+                Ok(false) => Diagnosis::new(
+                    &name,
+                    Outcome::Pass,
+                    "found; the next update will change it".to_string(),
+                ),
+                Err(e) => Diagnosis::new(&name, Outcome::Fail, e.to_string()),
+            },
+        );
+    }
+
+    diagnoses
+}
+
+/// Name the diagnoses after the job of the config file they belong to
+pub fn for_job(job: &str, diagnoses: Vec<Diagnosis>) -> Vec<Diagnosis> {
+    diagnoses
+        .into_iter()
+        .map(|diagnosis| Diagnosis {
+            name: format!("job {job}: {}", diagnosis.name),
+            ..diagnosis
+        })
+        .collect()
+}
+
+/// Lay out the diagnoses as one line each, led by their outcome
+pub fn format_report(diagnoses: &[Diagnosis]) -> String {
+    diagnoses
+        .iter()
+        .map(|diagnosis| {
+            let outcome = match diagnosis.outcome {
+                Outcome::Pass => "PASS",
+                Outcome::Fail => "FAIL",
+                Outcome::Skip => "SKIP",
+            };
+            format!("{outcome}  {}: {}\n", diagnosis.name, diagnosis.detail)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::IpAddr;
+    use std::rc::Rc;
+
+    use crate::cli::{DnsArgs, SubcmdArgs};
+    use crate::digitalocean::account::Account;
+    use crate::digitalocean::dns::DomainRecord;
+    use crate::digitalocean::mock::{MockAccountClient, MockDnsClient};
+    use crate::digitalocean::DigitalOceanClient;
+    use crate::doctor::{
+        diagnose_detection, diagnose_targets, for_job, format_report, Diagnosis, Outcome,
+    };
+    use crate::http::HttpOptions;
+    use crate::provider::{DnsProviders, Provider};
+
+    fn dns_target(record: &str) -> SubcmdArgs {
+        SubcmdArgs::Dns(DnsArgs {
+            record: record.to_string(),
+            domain: Some("example.com".to_string()),
+            rtype: "A".to_string(),
+            data: None,
+            from_droplet: None,
+            from_load_balancer: None,
+            priority: None,
+            port: None,
+            weight: None,
+            ttl: 60,
+            lower_ttl: None,
+            aliases: vec![],
+            with_www: false,
+            check_delegation: false,
+            force: false,
+            create_if_missing: true,
+            create_domain: false,
+            all_domains: false,
+            dual_stack: false,
+            fqdn: false,
+            audit_txt: false,
+            provider: Provider::DigitalOcean,
+        })
+    }
+
+    fn client(server_url: String) -> DigitalOceanClient {
+        let mut client = DigitalOceanClient::new_for_test("foo".to_string(), server_url);
+        client.account = Rc::new(MockAccountClient::new(Account {
+            email: "sammy@digitalocean.com".to_string(),
+            uuid: "b6fr89dbf6d9156cace5f3c78dc9851d957381ef".to_string(),
+            email_verified: true,
+            status: "active".to_string(),
+            status_message: "".to_string(),
+        }));
+        client.dns = Rc::new(MockDnsClient::new().with_domain("example.com").with_record(
+            "example.com",
+            DomainRecord {
+                id: 1,
+                typ: "A".to_string(),
+                name: "home".to_string(),
+                data: "1.2.3.4".to_string(),
+                priority: None,
+                port: None,
+                ttl: 60,
+                weight: None,
+                flags: None,
+                tag: None,
+            },
+        ));
+        client
+    }
+
+    #[tokio::test]
+    async fn test_diagnose() {
+        let server = mockito::Server::new_async().await;
+        let ip = "1.2.3.4".parse::<IpAddr>().unwrap();
+        let client = client(server.url());
+        let providers = DnsProviders::new(&client, None, &HttpOptions::default());
+        let (detection, ips) = diagnose_detection(Ok(vec![ip]));
+        let mut diagnoses = vec![detection];
+        let targets = [dns_target("home"), dns_target("vpn")];
+        diagnoses.extend(diagnose_targets(&client, &providers, ips.as_deref(), &targets).await);
+        assert_eq!(
+            vec![
+                Outcome::Pass,
+                Outcome::Pass,
+                Outcome::Pass,
+                Outcome::Pass,
+                Outcome::Pass
+            ],
+            diagnoses.iter().map(|d| d.outcome).collect::<Vec<_>>()
+        );
+        assert_eq!("up to date", diagnoses[3].detail);
+        assert_eq!("found; the next update will change it", diagnoses[4].detail);
+    }
+
+    #[tokio::test]
+    async fn test_diagnose_skips_targets() {
+        let mut server = mockito::Server::new_async().await;
+        let _m = server
+            .mock("GET", "/v2/account")
+            .with_status(401)
+            .with_header("Content-Type", "application/json")
+            .with_body(r#"{"id":"Unauthorized","message":"Unable to authenticate you"}"#)
+            .create_async()
+            .await;
+
+        let client = DigitalOceanClient::new_for_test("foo".to_string(), server.url());
+        let providers = DnsProviders::new(&client, None, &HttpOptions::default());
+        let (detection, ips) = diagnose_detection(Err("no address".to_string()));
+        let mut diagnoses = vec![detection];
+        let targets = [dns_target("home")];
+        diagnoses.extend(diagnose_targets(&client, &providers, ips.as_deref(), &targets).await);
+        assert_eq!(
+            vec![
+                ("IP detection", Outcome::Fail),
+                ("DigitalOcean API", Outcome::Pass),
+                ("API token", Outcome::Fail),
+                ("DNS record home.example.com (A)", Outcome::Skip),
+            ],
+            diagnoses
+                .iter()
+                .map(|d| (d.name.as_str(), d.outcome))
+                .collect::<Vec<_>>()
+        );
+        // the token of each job is diagnosed under the name of the job
+        let diagnoses = for_job("office", diagnoses.split_off(1));
+        assert_eq!(
+            vec![
+                "job office: DigitalOcean API",
+                "job office: API token",
+                "job office: DNS record home.example.com (A)",
+            ],
+            diagnoses
+                .iter()
+                .map(|d| d.name.as_str())
+                .collect::<Vec<_>>()
+        );
+        _m.assert_async().await;
+    }
+
+    #[test]
+    fn test_format_report() {
+        let diagnoses = vec![
+            Diagnosis::new("IP detection", Outcome::Pass, "1.2.3.4".to_string()),
+            Diagnosis::new("API token", Outcome::Fail, "not accepted".to_string()),
+        ];
+        assert_eq!(
+            "PASS  IP detection: 1.2.3.4\n\
+             FAIL  API token: not accepted\n",
+            format_report(&diagnoses)
+        );
+    }
+}
@@ -0,0 +1,92 @@
+use serde::Serialize;
+
+use crate::digitalocean::droplet::Droplet;
+
+/// The inventory details of one droplet, as printed by `droplet list`
+#[derive(Serialize, Debug, Clone, Eq, PartialEq)]
+pub struct DropletRow {
+    pub name: String,
+    pub ipv4: Option<String>,
+    pub ipv6: Option<String>,
+    pub region: String,
+    pub tags: Vec<String>,
+}
+
+impl From<&Droplet> for DropletRow {
+    fn from(droplet: &Droplet) -> Self {
+        DropletRow {
+            name: droplet.name.clone(),
+            ipv4: droplet.public_ipv4().map(|ip| ip.to_string()),
+            ipv6: droplet.public_ipv6().map(|ip| ip.to_string()),
+            region: droplet.region.slug.clone(),
+            tags: droplet.tags.clone(),
+        }
+    }
+}
+
+/// Lay out the droplets as a table with a header and one aligned line per droplet.  Missing values
+/// are shown as `-`.
+pub fn format_table(rows: &[DropletRow]) -> String {
+    let cells: Vec<[String; 5]> =
+        std::iter::once(["NAME", "IPV4", "IPV6", "REGION", "TAGS"].map(String::from))
+            .chain(rows.iter().map(|row| {
+                [
+                    row.name.clone(),
+                    row.ipv4.clone().unwrap_or_else(|| "-".to_string()),
+                    row.ipv6.clone().unwrap_or_else(|| "-".to_string()),
+                    row.region.clone(),
+                    if row.tags.is_empty() {
+                        "-".to_string()
+                    } else {
+                        row.tags.join(",")
+                    },
+                ]
+            }))
+            .collect();
+    let widths: Vec<usize> = (0..5)
+        .map(|i| cells.iter().map(|c| c[i].len()).max().unwrap_or(0))
+        .collect();
+
+    cells
+        .iter()
+        .map(|line| {
+            let padded: Vec<String> = line
+                .iter()
+                .zip(&widths)
+                .map(|(cell, width)| format!("{cell:width$}"))
+                .collect();
+            format!("{}\n", padded.join("  ").trim_end())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::droplet_list::{format_table, DropletRow};
+
+    #[test]
+    fn test_format_table() {
+        let rows = vec![
+            DropletRow {
+                name: "web".to_string(),
+                ipv4: Some("1.2.3.4".to_string()),
+                ipv6: Some("2604:a880::1".to_string()),
+                region: "nyc1".to_string(),
+                tags: vec!["prod".to_string(), "http".to_string()],
+            },
+            DropletRow {
+                name: "database".to_string(),
+                ipv4: None,
+                ipv6: None,
+                region: "sfo3".to_string(),
+                tags: vec![],
+            },
+        ];
+        assert_eq!(
+            "NAME      IPV4     IPV6          REGION  TAGS\n\
+             web       1.2.3.4  2604:a880::1  nyc1    prod,http\n\
+             database  -        -             sfo3    -\n",
+            format_table(&rows)
+        );
+    }
+}
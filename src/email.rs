@@ -0,0 +1,698 @@
+//! Email notifications sent over SMTP when a run changes the IP address the targets hold, or when
+//! updates keep failing.  The SMTP conversation is kept to what a submission server needs: TLS or
+//! STARTTLS, `AUTH PLAIN`, and a single plain text message.
+
+use std::net::IpAddr;
+use std::ops::RangeInclusive;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio_rustls::rustls::pki_types::ServerName;
+use tokio_rustls::rustls::{ClientConfig, RootCertStore};
+use tokio_rustls::TlsConnector;
+use tracing::{debug, info, warn};
+
+use crate::audit::{civil_from_days, host_name};
+use crate::hooks::Change;
+use crate::secret::Secret;
+
+/// How long to wait for the mail server to respond before giving up on the message
+const SMTP_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The name this host introduces itself with, as it may not have a name the server could resolve
+const HELO_NAME: &str = "localhost";
+
+/// The most UTF-8 bytes put in one RFC 2047 encoded word, keeping the word within 75 characters
+const ENCODED_WORD_BYTES: usize = 45;
+
+/// How the connection to the mail server is secured
+#[derive(Deserialize, Serialize, Debug, Default, Clone, Copy, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SmtpSecurity {
+    /// TLS from the start of the connection, usually on port 465
+    Tls,
+    /// A plain connection upgraded with STARTTLS, usually on port 587
+    #[default]
+    Starttls,
+    /// No encryption at all, only for a relay on a trusted network
+    None,
+}
+
+impl SmtpSecurity {
+    fn default_port(&self) -> u16 {
+        match self {
+            SmtpSecurity::Tls => 465,
+            SmtpSecurity::Starttls => 587,
+            SmtpSecurity::None => 25,
+        }
+    }
+}
+
+/// Where and when to send email notifications, as given in the `email` section of the config file
+#[derive(Deserialize, Serialize, Debug, Clone, Eq, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct EmailOptions {
+    /// Host name of the SMTP server to send through
+    pub server: String,
+    /// Port of the SMTP server, instead of the usual port for the security
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub port: Option<u16>,
+    #[serde(default)]
+    pub security: SmtpSecurity,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password: Option<Secret>,
+    pub from: String,
+    pub to: Vec<String>,
+    /// Send an email whenever a run changes the IP address of a target
+    #[serde(default = "default_on_change")]
+    pub on_change: bool,
+    /// In `--daemon` mode, send an email once this many updates in a row have failed, or never if
+    /// zero
+    #[serde(default = "default_after_failures")]
+    pub after_failures: u32,
+    /// The subject of the email sent when the IP address changes, with the [`PLACEHOLDERS`]
+    /// filled in
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub subject: Option<String>,
+    /// The text of the email sent when the IP address changes, with the [`PLACEHOLDERS`] filled in
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body: Option<String>,
+}
+
+/// The names that `{name}` is replaced with the value of in the `subject` and `body` templates:
+/// the addresses held before and after the change, the DNS records and firewall rules changed, and
+/// the name of this host
+pub const PLACEHOLDERS: [&str; 5] = ["old_ip", "new_ip", "record", "firewall", "hostname"];
+
+const DEFAULT_SUBJECT: &str = "IP address changed to {new_ip}";
+
+const DEFAULT_BODY: &str = "The DNS records and firewall rules were updated to point at {new_ip}.";
+
+fn default_on_change() -> bool {
+    true
+}
+
+fn default_after_failures() -> u32 {
+    3
+}
+
+impl EmailOptions {
+    /// Check that the settings describe a message that can be sent
+    pub fn validate(&self) -> Result<(), String> {
+        if self.to.is_empty() {
+            return Err("email needs at least one address to send to".to_string());
+        }
+        for address in self.to.iter().chain([&self.from]) {
+            if !address.contains('@') || address.contains(['\r', '\n', '<', '>']) {
+                return Err(format!("invalid email address {address}"));
+            }
+        }
+        if self.username.is_some() != self.password.is_some() {
+            return Err("email needs both a username and a password to log in".to_string());
+        }
+        for template in self.subject.iter().chain(&self.body) {
+            if let Some(unknown) = placeholders(template).find(|p| !PLACEHOLDERS.contains(p)) {
+                return Err(format!(
+                    "unknown placeholder {{{unknown}}} in email template; use one of {}",
+                    PLACEHOLDERS.join(", ")
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The names of the `{name}` placeholders in a template
+fn placeholders(template: &str) -> impl Iterator<Item = &str> {
+    template.split('{').skip(1).filter_map(|rest| {
+        rest.split_once('}').map(|(name, _)| name).filter(|name| {
+            !name.is_empty() && name.chars().all(|c| c == '_' || c.is_alphanumeric())
+        })
+    })
+}
+
+/// Fill in the placeholders of a template with their values
+fn render(template: &str, values: &[(&str, String)]) -> String {
+    values
+        .iter()
+        .fold(template.to_string(), |text, (name, value)| {
+            text.replace(&format!("{{{name}}}"), value)
+        })
+}
+
+/// Tell the recipients that a run changed the IP address of its targets, with the `subject` and
+/// `body` templates filled in from the `changes` made
+pub async fn notify_change(
+    options: &EmailOptions,
+    ips: &[IpAddr],
+    changes: &[Change],
+    dry_run: bool,
+) {
+    if !options.on_change {
+        return;
+    }
+    let values = change_values(ips, changes, &host_name());
+    notify(
+        options,
+        &render(
+            options.subject.as_deref().unwrap_or(DEFAULT_SUBJECT),
+            &values,
+        ),
+        &render(options.body.as_deref().unwrap_or(DEFAULT_BODY), &values),
+        dry_run,
+    )
+    .await;
+}
+
+/// The value of each of the [`PLACEHOLDERS`] for a run that made the `changes`
+fn change_values(
+    ips: &[IpAddr],
+    changes: &[Change],
+    hostname: &str,
+) -> Vec<(&'static str, String)> {
+    let joined = |values: Vec<&str>| {
+        let mut unique: Vec<&str> = Vec::new();
+        for value in values.into_iter().filter(|value| !value.is_empty()) {
+            if !unique.contains(&value) {
+                unique.push(value);
+            }
+        }
+        unique.join(", ")
+    };
+    let resources = |kind: &str| {
+        joined(
+            changes
+                .iter()
+                .filter(|change| change.kind == kind)
+                .map(|change| change.resource.as_str())
+                .collect(),
+        )
+    };
+    vec![
+        (
+            "old_ip",
+            joined(
+                changes
+                    .iter()
+                    .map(|change| change.old_ip.as_str())
+                    .collect(),
+            ),
+        ),
+        (
+            "new_ip",
+            ips.iter()
+                .map(IpAddr::to_string)
+                .collect::<Vec<_>>()
+                .join(", "),
+        ),
+        ("record", resources("dns")),
+        ("firewall", resources("firewall")),
+        ("hostname", hostname.to_string()),
+    ]
+}
+
+/// Tell the recipients that the last `failures` updates all failed, the last one with `error`
+pub async fn notify_failures(options: &EmailOptions, failures: u32, error: &str, dry_run: bool) {
+    notify(
+        options,
+        &format!("{failures} updates in a row failed"),
+        &format!(
+            "The last {failures} attempts to update the DNS records and firewall rules \
+            failed.\n\nThe last one failed with: {error}"
+        ),
+        dry_run,
+    )
+    .await;
+}
+
+/// Tell the recipients that a `--check` found the targets no longer matching the IP address
+pub async fn notify_drift(options: &EmailOptions, targets: &[String], dry_run: bool) {
+    notify(
+        options,
+        &format!("{} target(s) no longer match the IP address", targets.len()),
+        &format!(
+            "A check found these targets no longer matching the IP address:\n\n{}",
+            targets.join("\n")
+        ),
+        dry_run,
+    )
+    .await;
+}
+
+/// Send a notification.  A message that cannot be sent is only logged, as it must not fail a run
+/// that otherwise succeeded.
+async fn notify(options: &EmailOptions, subject: &str, body: &str, dry_run: bool) {
+    let recipients = options.to.join(", ");
+    if dry_run {
+        info!("DRY RUN: Emailing {}: {}", recipients, subject);
+        return;
+    }
+    debug!("Emailing {}: {}", recipients, subject);
+    let message = message(options, subject, body);
+    match tokio::time::timeout(SMTP_TIMEOUT, send(options, &message)).await {
+        Ok(Ok(())) => info!("Emailed {}: {}", recipients, subject),
+        Ok(Err(e)) => warn!("Unable to email {}: {}", recipients, e),
+        Err(_) => warn!(
+            "Unable to email {}: no response from {} within {}s",
+            recipients,
+            options.server,
+            SMTP_TIMEOUT.as_secs()
+        ),
+    }
+}
+
+/// The message to send, with its headers, lines ending in CRLF, and lines starting with a dot
+/// escaped as SMTP requires
+fn message(options: &EmailOptions, subject: &str, body: &str) -> String {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    let mut message = format!(
+        "From: {}\r\nTo: {}\r\nSubject: digitalocean-dyn-dns: {}\r\nDate: {}\r\n\
+        Message-ID: <{}.{}@{}>\r\nMIME-Version: 1.0\r\n\
+        Content-Type: text/plain; charset=utf-8\r\n\r\n",
+        options.from,
+        options.to.join(", "),
+        encode_header(&subject.replace(['\r', '\n'], " ")),
+        format_date(secs),
+        secs,
+        std::process::id(),
+        options.from.rsplit('@').next().unwrap_or("localhost")
+    );
+    for line in body.lines() {
+        if line.starts_with('.') {
+            message.push('.');
+        }
+        message.push_str(line);
+        message.push_str("\r\n");
+    }
+    message
+}
+
+/// A header value as is if it is ASCII, or else as RFC 2047 encoded words, as headers may not hold
+/// anything else
+fn encode_header(value: &str) -> String {
+    if value.is_ascii() {
+        return value.to_string();
+    }
+    let mut words = Vec::new();
+    let mut word = String::new();
+    for c in value.chars() {
+        // a character must not be split across words
+        if word.len() + c.len_utf8() > ENCODED_WORD_BYTES {
+            words.push(std::mem::take(&mut word));
+        }
+        word.push(c);
+    }
+    words.push(word);
+    words
+        .iter()
+        .map(|word| format!("=?utf-8?B?{}?=", STANDARD.encode(word)))
+        .collect::<Vec<_>>()
+        .join("\r\n ")
+}
+
+/// Format seconds since the Unix epoch as an RFC 5322 date in UTC, e.g.
+/// `Thu, 01 Jan 1970 00:00:00 +0000`
+fn format_date(secs: u64) -> String {
+    const WEEKDAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+    let days = secs / 86400;
+    let (year, month, day) = civil_from_days(days);
+    let secs_of_day = secs % 86400;
+    format!(
+        "{}, {:02} {} {} {:02}:{:02}:{:02} +0000",
+        WEEKDAYS[(days % 7) as usize],
+        day,
+        MONTHS[(month - 1) as usize],
+        year,
+        secs_of_day / 3600,
+        secs_of_day % 3600 / 60,
+        secs_of_day % 60
+    )
+}
+
+async fn send(options: &EmailOptions, message: &str) -> Result<(), String> {
+    let port = options
+        .port
+        .unwrap_or_else(|| options.security.default_port());
+    let tcp = TcpStream::connect((options.server.as_str(), port))
+        .await
+        .map_err(|e| format!("unable to connect to {}:{}: {}", options.server, port, e))?;
+    match options.security {
+        SmtpSecurity::Tls => {
+            let mut smtp = Smtp::new(tls(&options.server, tcp).await?);
+            smtp.greeting().await?;
+            smtp.deliver(options, message).await
+        }
+        SmtpSecurity::Starttls => {
+            let mut smtp = Smtp::new(tcp);
+            smtp.greeting().await?;
+            smtp.command(&format!("EHLO {HELO_NAME}"), 250).await?;
+            smtp.command("STARTTLS", 220).await?;
+            let tcp = smtp.stream.into_inner();
+            Smtp::new(tls(&options.server, tcp).await?)
+                .deliver(options, message)
+                .await
+        }
+        SmtpSecurity::None => {
+            let mut smtp = Smtp::new(tcp);
+            smtp.greeting().await?;
+            smtp.deliver(options, message).await
+        }
+    }
+}
+
+/// Secure the connection, trusting the same certificate authorities as the API clients
+async fn tls(
+    server: &str,
+    tcp: TcpStream,
+) -> Result<tokio_rustls::client::TlsStream<TcpStream>, String> {
+    let roots = RootCertStore {
+        roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+    };
+    let config = ClientConfig::builder_with_provider(Arc::new(
+        tokio_rustls::rustls::crypto::ring::default_provider(),
+    ))
+    .with_safe_default_protocol_versions()
+    .map_err(|e| format!("unable to set up TLS: {e}"))?
+    .with_root_certificates(roots)
+    .with_no_client_auth();
+    let name = ServerName::try_from(server.to_string())
+        .map_err(|e| format!("invalid mail server name {server}: {e}"))?;
+    TlsConnector::from(Arc::new(config))
+        .connect(name, tcp)
+        .await
+        .map_err(|e| format!("unable to secure the connection to {server}: {e}"))
+}
+
+/// One SMTP session over a connection
+struct Smtp<S> {
+    stream: BufReader<S>,
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> Smtp<S> {
+    fn new(stream: S) -> Smtp<S> {
+        Smtp {
+            stream: BufReader::new(stream),
+        }
+    }
+
+    async fn greeting(&mut self) -> Result<(), String> {
+        self.expect(220).await.map(|_| ())
+    }
+
+    /// Introduce this host, log in, and send the message
+    async fn deliver(&mut self, options: &EmailOptions, message: &str) -> Result<(), String> {
+        self.command(&format!("EHLO {HELO_NAME}"), 250).await?;
+        if let (Some(username), Some(password)) = (&options.username, &options.password) {
+            let credentials = STANDARD.encode(format!("\0{}\0{}", username, password.expose()));
+            self.send_line(&format!("AUTH PLAIN {credentials}")).await?;
+            self.expect(235)
+                .await
+                .map_err(|e| format!("unable to log in as {username}: {e}"))?;
+        }
+        self.command(&format!("MAIL FROM:<{}>", options.from), 250)
+            .await?;
+        for to in &options.to {
+            // 251 means the server will forward the message to the recipient
+            self.send_line(&format!("RCPT TO:<{to}>")).await?;
+            self.expect_any(250..=259)
+                .await
+                .map_err(|e| format!("RCPT failed for {to}: {e}"))?;
+        }
+        self.command("DATA", 354).await?;
+        self.stream
+            .write_all(format!("{message}.\r\n").as_bytes())
+            .await
+            .map_err(|e| e.to_string())?;
+        self.expect(250).await?;
+        // the message has been accepted, so a failure to say goodbye does not matter
+        let _ = self.command("QUIT", 221).await;
+        Ok(())
+    }
+
+    /// Send a command and fail unless the server replies with the expected code
+    async fn command(&mut self, command: &str, expected: u16) -> Result<String, String> {
+        self.send_line(command).await?;
+        self.expect(expected)
+            .await
+            .map_err(|e| format!("{} failed: {}", verb(command), e))
+    }
+
+    async fn send_line(&mut self, line: &str) -> Result<(), String> {
+        self.stream
+            .write_all(format!("{line}\r\n").as_bytes())
+            .await
+            .map_err(|e| e.to_string())?;
+        self.stream.flush().await.map_err(|e| e.to_string())
+    }
+
+    /// Read a reply, which may span several lines, and fail unless it has the expected code
+    async fn expect(&mut self, expected: u16) -> Result<String, String> {
+        self.expect_any(expected..=expected).await
+    }
+
+    /// Read a reply, which may span several lines, and fail unless its code is in `expected`
+    async fn expect_any(&mut self, expected: RangeInclusive<u16>) -> Result<String, String> {
+        let mut reply = String::new();
+        loop {
+            let mut line = String::new();
+            if self
+                .stream
+                .read_line(&mut line)
+                .await
+                .map_err(|e| e.to_string())?
+                == 0
+            {
+                return Err("the server closed the connection".to_string());
+            }
+            reply.push_str(&line);
+            // the last line of a reply has a space after the code, the others a dash
+            if line.as_bytes().get(3) != Some(&b'-') {
+                break;
+            }
+        }
+        match reply.get(..3).and_then(|code| code.parse::<u16>().ok()) {
+            Some(code) if expected.contains(&code) => Ok(reply),
+            _ => Err(format!("the server replied {}", reply.trim_end())),
+        }
+    }
+}
+
+/// The verb of a command, without any arguments that could hold credentials
+fn verb(command: &str) -> &str {
+    command.split([' ', ':']).next().unwrap_or(command)
+}
+
+#[cfg(test)]
+mod test {
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+
+    use crate::email::{
+        change_values, encode_header, format_date, message, render, send, EmailOptions,
+        SmtpSecurity, DEFAULT_BODY, DEFAULT_SUBJECT,
+    };
+    use crate::hooks::Change;
+    use crate::secret::Secret;
+
+    fn options(port: u16) -> EmailOptions {
+        EmailOptions {
+            server: "127.0.0.1".to_string(),
+            port: Some(port),
+            security: SmtpSecurity::None,
+            username: Some("me@example.com".to_string()),
+            password: Some(Secret::new("hunter2".to_string())),
+            from: "me@example.com".to_string(),
+            to: vec!["you@example.com".to_string()],
+            on_change: true,
+            after_failures: 3,
+            subject: None,
+            body: None,
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(options(25).validate().is_ok());
+        assert!(EmailOptions {
+            to: vec![],
+            ..options(25)
+        }
+        .validate()
+        .is_err());
+        assert!(EmailOptions {
+            to: vec!["you@example.com>\r\nRCPT TO:<them@example.com".to_string()],
+            ..options(25)
+        }
+        .validate()
+        .is_err());
+        assert!(EmailOptions {
+            password: None,
+            ..options(25)
+        }
+        .validate()
+        .is_err());
+    }
+
+    #[test]
+    fn test_validate_templates() {
+        let templated = |subject: &str| EmailOptions {
+            subject: Some(subject.to_string()),
+            body: Some("{record} on {hostname} now at {new_ip}".to_string()),
+            ..options(25)
+        };
+        assert!(templated("{old_ip} -> {new_ip} ({firewall})")
+            .validate()
+            .is_ok());
+        // braces that are not a placeholder are left alone
+        assert!(templated("{} {not a placeholder}").validate().is_ok());
+        assert!(templated("{new_address}").validate().is_err());
+    }
+
+    #[test]
+    fn test_render() {
+        let change = |kind: &'static str, resource: &str, old_ip: &str| Change {
+            kind,
+            resource: resource.to_string(),
+            old_ip: old_ip.to_string(),
+            new_ip: "5.6.7.8".to_string(),
+        };
+        let changes = vec![
+            change("dns", "home.example.com (A)", "1.2.3.4"),
+            change("dns", "vpn.example.com (A)", ""),
+            change(
+                "firewall",
+                "home-fw inbound rule for port 22 (tcp)",
+                "1.2.3.4",
+            ),
+        ];
+        let values = change_values(&["5.6.7.8".parse().unwrap()], &changes, "pi");
+
+        assert_eq!(
+            "pi: 1.2.3.4 -> 5.6.7.8",
+            render("{hostname}: {old_ip} -> {new_ip}", &values)
+        );
+        assert_eq!(
+            "home.example.com (A), vpn.example.com (A) | {other}",
+            render("{record} | {other}", &values)
+        );
+        assert_eq!(
+            "home-fw inbound rule for port 22 (tcp)",
+            render("{firewall}", &values)
+        );
+        assert_eq!(
+            "IP address changed to 5.6.7.8",
+            render(DEFAULT_SUBJECT, &values)
+        );
+        assert_eq!(
+            "The DNS records and firewall rules were updated to point at 5.6.7.8.",
+            render(DEFAULT_BODY, &values)
+        );
+        assert_eq!(
+            "no changes: ",
+            render("no changes: {record}", &change_values(&[], &[], "pi"))
+        );
+    }
+
+    #[test]
+    fn test_message() {
+        let plain = message(&options(25), "IP changed", "first\n.second");
+        assert!(plain.starts_with("From: me@example.com\r\nTo: you@example.com\r\n"));
+        assert!(plain.contains("Subject: digitalocean-dyn-dns: IP changed\r\nDate: "));
+        assert!(plain.ends_with("\r\n\r\nfirst\r\n..second\r\n"));
+
+        let encoded = message(&options(25), "Adresse geändert", "");
+        assert!(encoded
+            .contains("Subject: digitalocean-dyn-dns: =?utf-8?B?QWRyZXNzZSBnZcOkbmRlcnQ=?=\r\n"));
+    }
+
+    #[test]
+    fn test_encode_header() {
+        assert_eq!("IP changed", encode_header("IP changed"));
+        // long values are split between characters into words of at most 75 characters
+        let encoded = encode_header(&"é".repeat(25));
+        let words: Vec<&str> = encoded.split("\r\n ").collect();
+        assert_eq!(2, words.len());
+        assert!(words
+            .iter()
+            .all(|w| w.len() <= 75 && w.starts_with("=?utf-8?B?")));
+        assert_eq!("=?utf-8?B?w6nDqcOp?=", words[1]);
+    }
+
+    #[test]
+    fn test_format_date() {
+        assert_eq!("Thu, 01 Jan 1970 00:00:00 +0000", format_date(0));
+        assert_eq!("Wed, 01 May 2024 12:34:56 +0000", format_date(1714566896));
+        assert_eq!("Thu, 29 Feb 2024 23:59:59 +0000", format_date(1709251199));
+    }
+
+    #[tokio::test]
+    async fn test_send() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = async {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut stream = BufReader::new(stream);
+            let mut received = Vec::new();
+            stream.write_all(b"220 mail.example.com\r\n").await.unwrap();
+            loop {
+                let mut line = String::new();
+                stream.read_line(&mut line).await.unwrap();
+                let line = line.trim_end().to_string();
+                let reply: &[u8] = match line.as_str() {
+                    "EHLO localhost" => b"250-mail.example.com\r\n250 AUTH PLAIN\r\n",
+                    l if l.starts_with("AUTH PLAIN ") => b"235 OK\r\n",
+                    "DATA" => b"354 go ahead\r\n",
+                    "." => b"250 queued\r\n",
+                    "QUIT" => b"221 bye\r\n",
+                    "RCPT TO:<them@example.org>" => b"251 will forward\r\n",
+                    l if l.starts_with("MAIL") || l.starts_with("RCPT") => b"250 OK\r\n",
+                    _ => b"",
+                };
+                received.push(line.clone());
+                stream.write_all(reply).await.unwrap();
+                if line == "QUIT" {
+                    return received;
+                }
+            }
+        };
+
+        let options = EmailOptions {
+            to: vec![
+                "you@example.com".to_string(),
+                "them@example.org".to_string(),
+            ],
+            ..options(port)
+        };
+        let (received, sent) = tokio::join!(server, send(&options, "Subject: hi\r\n\r\nhi\r\n"));
+        sent.unwrap();
+        assert_eq!(
+            vec![
+                "EHLO localhost",
+                // base64 of "\0me@example.com\0hunter2"
+                "AUTH PLAIN AG1lQGV4YW1wbGUuY29tAGh1bnRlcjI=",
+                "MAIL FROM:<me@example.com>",
+                "RCPT TO:<you@example.com>",
+                "RCPT TO:<them@example.org>",
+                "DATA",
+                "Subject: hi",
+                "",
+                "hi",
+                ".",
+                "QUIT",
+            ],
+            received
+        );
+    }
+}
@@ -0,0 +1,307 @@
+use std::collections::BTreeSet;
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::digitalocean::firewall::{
+    Firewall, FirewallInboundRule, FirewallOutboundRule, FirewallRuleTarget, ALL_PORTS,
+};
+
+/// The rules a firewall is declared to have, as read from a rules file
+#[derive(Deserialize, Debug, Default, Eq, PartialEq)]
+pub struct RulesSpec {
+    #[serde(default)]
+    pub inbound_rules: Vec<FirewallInboundRule>,
+    #[serde(default)]
+    pub outbound_rules: Vec<FirewallOutboundRule>,
+}
+
+/// Read the declared rules of a firewall from a YAML file
+pub fn load_rules_file(path: &Path) -> Result<RulesSpec, String> {
+    let contents = fs::read_to_string(path)
+        .map_err(|e| format!("unable to read {}: {}", path.display(), e))?;
+    serde_yaml::from_str(&contents)
+        .map_err(|e| format!("unable to parse {}: {}", path.display(), e))
+}
+
+#[derive(Serialize, Debug, Clone, Copy, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum DiffKind {
+    /// The rule is declared but not present on the firewall
+    Add,
+    /// The rule is present on the firewall but not declared
+    Remove,
+    /// The rule is present on the firewall with different targets than declared
+    Change,
+}
+
+/// One difference between the live rules of a firewall and the declared rules
+#[derive(Serialize, Debug, Clone, Eq, PartialEq)]
+pub struct RuleDiff {
+    pub kind: DiffKind,
+    pub direction: &'static str,
+    pub protocol: String,
+    pub ports: String,
+    /// Targets that applying the declared rules would add
+    pub added: FirewallRuleTarget,
+    /// Targets that applying the declared rules would remove
+    pub removed: FirewallRuleTarget,
+}
+
+impl Display for RuleDiff {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let marker = match self.kind {
+            DiffKind::Add => '+',
+            DiffKind::Remove => '-',
+            DiffKind::Change => '~',
+        };
+        write!(
+            f,
+            "{} {} {}/{}",
+            marker, self.direction, self.protocol, self.ports
+        )?;
+        for (sign, target) in [('+', &self.added), ('-', &self.removed)] {
+            for (label, values) in target_values(target) {
+                if !values.is_empty() {
+                    write!(f, " {}{}=[{}]", sign, label, values.join(", "))?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Compare the rules of a live firewall with the declared rules.  Rules are matched up by
+/// direction, protocol, and ports.
+pub fn diff_firewall(firewall: &Firewall, spec: &RulesSpec) -> Vec<RuleDiff> {
+    let live_inbound: Vec<_> = firewall
+        .inbound_rules
+        .iter()
+        .flatten()
+        .map(|r| (r.protocol.clone(), r.ports.clone(), r.sources.clone()))
+        .collect();
+    let declared_inbound: Vec<_> = spec
+        .inbound_rules
+        .iter()
+        .map(|r| (r.protocol.clone(), r.ports.clone(), r.sources.clone()))
+        .collect();
+    let live_outbound: Vec<_> = firewall
+        .outbound_rules
+        .iter()
+        .flatten()
+        .map(|r| (r.protocol.clone(), r.ports.clone(), r.destinations.clone()))
+        .collect();
+    let declared_outbound: Vec<_> = spec
+        .outbound_rules
+        .iter()
+        .map(|r| (r.protocol.clone(), r.ports.clone(), r.destinations.clone()))
+        .collect();
+
+    let mut diffs = diff_rules("inbound", &live_inbound, &declared_inbound);
+    diffs.extend(diff_rules("outbound", &live_outbound, &declared_outbound));
+    diffs
+}
+
+type Rule = (String, String, FirewallRuleTarget);
+
+fn diff_rules(direction: &'static str, live: &[Rule], declared: &[Rule]) -> Vec<RuleDiff> {
+    let same_rule =
+        |a: &Rule, b: &Rule| a.0 == b.0 && normalize_ports(&a.1) == normalize_ports(&b.1);
+    let empty = FirewallRuleTarget {
+        addresses: None,
+        droplet_ids: None,
+        load_balancer_uids: None,
+        kubernetes_ids: None,
+        tags: None,
+    };
+
+    let mut diffs = Vec::new();
+    for rule in declared {
+        match live.iter().find(|l| same_rule(l, rule)) {
+            Some(current) => {
+                let added = target_difference(&rule.2, &current.2);
+                let removed = target_difference(&current.2, &rule.2);
+                if added != empty || removed != empty {
+                    diffs.push(RuleDiff {
+                        kind: DiffKind::Change,
+                        direction,
+                        protocol: rule.0.clone(),
+                        ports: rule.1.clone(),
+                        added,
+                        removed,
+                    });
+                }
+            }
+            None => diffs.push(RuleDiff {
+                kind: DiffKind::Add,
+                direction,
+                protocol: rule.0.clone(),
+                ports: rule.1.clone(),
+                added: target_difference(&rule.2, &empty),
+                removed: empty.clone(),
+            }),
+        }
+    }
+    for rule in live {
+        if !declared.iter().any(|d| same_rule(d, rule)) {
+            diffs.push(RuleDiff {
+                kind: DiffKind::Remove,
+                direction,
+                protocol: rule.0.clone(),
+                ports: rule.1.clone(),
+                added: empty.clone(),
+                removed: target_difference(&rule.2, &empty),
+            });
+        }
+    }
+    diffs
+}
+
+/// Treat the different spellings of "every port" as the same ports
+fn normalize_ports(ports: &str) -> &str {
+    if ports.is_empty() || ports.eq_ignore_ascii_case("all") {
+        ALL_PORTS
+    } else {
+        ports
+    }
+}
+
+/// The targets present in `a` but not in `b`, with empty lists left out
+fn target_difference(a: &FirewallRuleTarget, b: &FirewallRuleTarget) -> FirewallRuleTarget {
+    fn difference<T: Ord + Clone>(a: &Option<Vec<T>>, b: &Option<Vec<T>>) -> Option<Vec<T>> {
+        let b: BTreeSet<&T> = b.iter().flatten().collect();
+        let values: Vec<T> = a
+            .iter()
+            .flatten()
+            .collect::<BTreeSet<&T>>()
+            .into_iter()
+            .filter(|v| !b.contains(v))
+            .cloned()
+            .collect();
+        (!values.is_empty()).then_some(values)
+    }
+
+    FirewallRuleTarget {
+        addresses: difference(&a.addresses, &b.addresses),
+        droplet_ids: difference(&a.droplet_ids, &b.droplet_ids),
+        load_balancer_uids: difference(&a.load_balancer_uids, &b.load_balancer_uids),
+        kubernetes_ids: difference(&a.kubernetes_ids, &b.kubernetes_ids),
+        tags: difference(&a.tags, &b.tags),
+    }
+}
+
+fn target_values(target: &FirewallRuleTarget) -> [(&'static str, Vec<String>); 5] {
+    fn strings<T: ToString>(values: &Option<Vec<T>>) -> Vec<String> {
+        values.iter().flatten().map(|v| v.to_string()).collect()
+    }
+
+    [
+        ("addresses", strings(&target.addresses)),
+        ("droplet_ids", strings(&target.droplet_ids)),
+        ("load_balancer_uids", strings(&target.load_balancer_uids)),
+        ("kubernetes_ids", strings(&target.kubernetes_ids)),
+        ("tags", strings(&target.tags)),
+    ]
+}
+
+#[cfg(test)]
+mod test {
+    use crate::digitalocean::firewall::{
+        Firewall, FirewallInboundRule, FirewallOutboundRule, FirewallRuleTarget,
+    };
+    use crate::firewall_diff::{diff_firewall, DiffKind, RulesSpec};
+
+    fn target(addresses: &[&str]) -> FirewallRuleTarget {
+        FirewallRuleTarget {
+            addresses: Some(addresses.iter().map(|a| a.to_string()).collect()),
+            droplet_ids: None,
+            load_balancer_uids: None,
+            kubernetes_ids: None,
+            tags: None,
+        }
+    }
+
+    fn inbound(protocol: &str, ports: &str, addresses: &[&str]) -> FirewallInboundRule {
+        FirewallInboundRule {
+            protocol: protocol.to_string(),
+            ports: ports.to_string(),
+            sources: target(addresses),
+        }
+    }
+
+    #[test]
+    fn test_parse_rules_spec() {
+        let spec: RulesSpec = serde_yaml::from_str(
+            "inbound_rules:\n  \
+               - protocol: tcp\n    \
+                 ports: \"22\"\n    \
+                 sources:\n      \
+                   addresses: [1.2.3.4]\n",
+        )
+        .unwrap();
+        assert_eq!(
+            RulesSpec {
+                inbound_rules: vec![inbound("tcp", "22", &["1.2.3.4"])],
+                outbound_rules: vec![],
+            },
+            spec
+        );
+    }
+
+    #[test]
+    fn test_diff_firewall() {
+        let firewall = Firewall {
+            id: "foo".to_string(),
+            status: "".to_string(),
+            created_at: "".to_string(),
+            pending_changes: vec![],
+            name: "Foo".to_string(),
+            droplet_ids: None,
+            tags: None,
+            inbound_rules: Some(vec![
+                inbound("tcp", "22", &["1.1.1.1", "2.2.2.2"]),
+                inbound("tcp", "80", &["0.0.0.0/0"]),
+                inbound("udp", "all", &["3.3.3.3"]),
+            ]),
+            outbound_rules: Some(vec![FirewallOutboundRule {
+                protocol: "tcp".to_string(),
+                ports: "0".to_string(),
+                destinations: target(&["0.0.0.0/0"]),
+            }]),
+        };
+        let spec = RulesSpec {
+            inbound_rules: vec![
+                inbound("tcp", "22", &["2.2.2.2", "4.4.4.4"]),
+                inbound("tcp", "80", &["0.0.0.0/0"]),
+                inbound("udp", "0", &["3.3.3.3"]),
+                inbound("tcp", "443", &["0.0.0.0/0"]),
+            ],
+            outbound_rules: vec![],
+        };
+
+        let diffs = diff_firewall(&firewall, &spec);
+        assert_eq!(
+            vec![
+                (DiffKind::Change, "inbound", "22"),
+                (DiffKind::Add, "inbound", "443"),
+                (DiffKind::Remove, "outbound", "0"),
+            ],
+            diffs
+                .iter()
+                .map(|d| (d.kind, d.direction, d.ports.as_str()))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(target(&["4.4.4.4"]), diffs[0].added);
+        assert_eq!(target(&["1.1.1.1"]), diffs[0].removed);
+        assert_eq!(
+            "~ inbound tcp/22 +addresses=[4.4.4.4] -addresses=[1.1.1.1]",
+            diffs[0].to_string()
+        );
+        assert_eq!(
+            "- outbound tcp/0 -addresses=[0.0.0.0/0]",
+            diffs[2].to_string()
+        );
+    }
+}
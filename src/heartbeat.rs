@@ -0,0 +1,72 @@
+use std::time::Duration;
+
+use tracing::{debug, info, warn};
+
+use crate::http::HttpOptions;
+
+/// How long to wait for the monitoring service to respond to a ping
+const PING_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The URL to ping for the outcome of a run: the heartbeat URL itself after a successful run, or
+/// its `/fail` endpoint after a failed one, as healthchecks.io and compatible services expect
+fn ping_url(url: &str, success: bool) -> String {
+    if success {
+        url.to_string()
+    } else {
+        format!("{}/fail", url.trim_end_matches('/'))
+    }
+}
+
+/// Tell a dead man's switch monitoring service how a run went.  A ping that cannot be delivered
+/// is only logged, as it must not fail a run that otherwise succeeded.
+pub async fn ping(url: &str, success: bool, dry_run: bool, http: &HttpOptions) {
+    let url = ping_url(url, success);
+    if dry_run {
+        info!("DRY RUN: Pinging heartbeat URL {}", url);
+        return;
+    }
+
+    debug!("Pinging heartbeat URL {}", url);
+    let client = http.client(PING_TIMEOUT);
+    let result = async { client.get(&url).send().await?.error_for_status() }.await;
+    if let Err(e) = result {
+        warn!("Unable to ping heartbeat URL {}: {}", url, e);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::heartbeat::{ping, ping_url};
+    use crate::http::HttpOptions;
+
+    #[test]
+    fn test_ping_url() {
+        let url = "https://hc-ping.com/1234";
+        assert_eq!(url, ping_url(url, true));
+        assert_eq!("https://hc-ping.com/1234/fail", ping_url(url, false));
+        assert_eq!(
+            "https://hc-ping.com/1234/fail",
+            ping_url("https://hc-ping.com/1234/", false)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_ping() {
+        let mut server = mockito::Server::new_async().await;
+        let success = server.mock("GET", "/check").expect(1).create_async().await;
+        let fail = server
+            .mock("GET", "/check/fail")
+            .expect(1)
+            .create_async()
+            .await;
+        let url = format!("{}/check", server.url());
+
+        let http = HttpOptions::default();
+        ping(&url, true, false, &http).await;
+        ping(&url, false, false, &http).await;
+        ping(&url, false, true, &http).await;
+
+        success.assert_async().await;
+        fail.assert_async().await;
+    }
+}
@@ -0,0 +1,217 @@
+use std::fs;
+use std::fs::OpenOptions;
+use std::io;
+use std::io::Write;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::audit::format_timestamp;
+
+/// Once the history file reaches this size it is moved aside to `<file>.1`, replacing any older
+/// history there, and a new file is started
+const MAX_HISTORY_BYTES: u64 = 1024 * 1024;
+
+/// One line of the `--history-file`
+#[derive(Deserialize, Serialize, Debug, Clone, Eq, PartialEq)]
+pub struct HistoryEntry {
+    /// When the event happened, as an RFC 3339 UTC timestamp
+    pub at: String,
+    #[serde(flatten)]
+    pub event: HistoryEvent,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Eq, PartialEq)]
+#[serde(tag = "event", rename_all = "lowercase")]
+pub enum HistoryEvent {
+    /// The detected address(es) differed from those detected before
+    Detected { ips: Vec<IpAddr> },
+    /// A target was changed to hold the address(es)
+    Updated { target: String, ips: Vec<IpAddr> },
+}
+
+impl HistoryEntry {
+    fn now(event: HistoryEvent) -> HistoryEntry {
+        HistoryEntry {
+            at: format_timestamp(
+                SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map_or(0, |d| d.as_secs()),
+            ),
+            event,
+        }
+    }
+
+    /// The entry as a line of `history` output, e.g. `2024-05-01T12:00:00Z  detected  1.2.3.4`
+    pub fn describe(&self) -> String {
+        fn addresses(ips: &[IpAddr]) -> String {
+            ips.iter()
+                .map(IpAddr::to_string)
+                .collect::<Vec<_>>()
+                .join(", ")
+        }
+
+        match self.event {
+            HistoryEvent::Detected { ref ips } => {
+                format!("{}  detected  {}", self.at, addresses(ips))
+            }
+            HistoryEvent::Updated {
+                ref target,
+                ref ips,
+            } => format!("{}  updated   {} to {}", self.at, target, addresses(ips)),
+        }
+    }
+}
+
+/// Record the detected address(es) unless they are the ones detected last.  A failure is only
+/// logged, as the history must not fail a run.
+pub fn record_detection(path: &Path, ips: &[IpAddr]) {
+    let last = load(path).ok().and_then(|entries| {
+        entries
+            .into_iter()
+            .rev()
+            .find_map(|entry| match entry.event {
+                HistoryEvent::Detected { ips } => Some(ips),
+                HistoryEvent::Updated { .. } => None,
+            })
+    });
+    if last.as_deref() != Some(ips) {
+        append(path, HistoryEvent::Detected { ips: ips.to_vec() });
+    }
+}
+
+/// Record that a target was changed to hold the address(es).  A failure is only logged, as the
+/// history must not fail a run.
+pub fn record_update(path: &Path, target: &str, ips: &[IpAddr]) {
+    append(
+        path,
+        HistoryEvent::Updated {
+            target: target.to_string(),
+            ips: ips.to_vec(),
+        },
+    );
+}
+
+/// Read the whole history, oldest first, including the part that has been rotated out.  Lines
+/// that cannot be parsed are skipped with a warning.
+pub fn load(path: &Path) -> Result<Vec<HistoryEntry>, io::Error> {
+    let mut entries = Vec::new();
+    for file in [rotated_path(path), path.to_path_buf()] {
+        let contents = match fs::read_to_string(&file) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e),
+        };
+        for line in contents.lines().filter(|line| !line.trim().is_empty()) {
+            match serde_json::from_str(line) {
+                Ok(entry) => entries.push(entry),
+                Err(e) => warn!("Skipping unreadable line of {}: {}", file.display(), e),
+            }
+        }
+    }
+    Ok(entries)
+}
+
+fn append(path: &Path, event: HistoryEvent) {
+    let entry = HistoryEntry::now(event);
+    let result = (|| {
+        if fs::metadata(path).is_ok_and(|m| m.len() >= MAX_HISTORY_BYTES) {
+            fs::rename(path, rotated_path(path))?;
+        }
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        writeln!(file, "{}", serde_json::to_string(&entry)?)
+    })();
+    if let Err(e) = result {
+        warn!("Unable to write history file {}: {}", path.display(), e);
+    }
+}
+
+fn rotated_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(".1");
+    PathBuf::from(name)
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+    use std::net::IpAddr;
+
+    use crate::history::{
+        load, record_detection, record_update, rotated_path, HistoryEntry, HistoryEvent,
+        MAX_HISTORY_BYTES,
+    };
+
+    #[test]
+    fn test_record_and_load() {
+        let dir = std::env::temp_dir().join(format!("dyn-dns-history-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("history.jsonl");
+        let ip1: Vec<IpAddr> = vec!["1.2.3.4".parse().unwrap()];
+        let ip2: Vec<IpAddr> = vec!["5.6.7.8".parse().unwrap()];
+
+        assert_eq!(Vec::<HistoryEntry>::new(), load(&path).unwrap());
+
+        record_detection(&path, &ip1);
+        record_update(&path, "DNS record home.example.com (A)", &ip1);
+        // detecting the same address again is not a change
+        record_detection(&path, &ip1);
+        record_detection(&path, &ip2);
+
+        let events: Vec<HistoryEvent> = load(&path)
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.event)
+            .collect();
+        assert_eq!(
+            vec![
+                HistoryEvent::Detected { ips: ip1.clone() },
+                HistoryEvent::Updated {
+                    target: "DNS record home.example.com (A)".to_string(),
+                    ips: ip1.clone(),
+                },
+                HistoryEvent::Detected { ips: ip2.clone() },
+            ],
+            events
+        );
+
+        // a full file is rotated, but its entries are still read and compared against
+        let mut contents = fs::read_to_string(&path).unwrap();
+        contents.push_str(&"\n".repeat(MAX_HISTORY_BYTES as usize));
+        fs::write(&path, contents).unwrap();
+        record_detection(&path, &ip2);
+        record_detection(&path, &ip1);
+        assert!(rotated_path(&path).exists());
+        assert_eq!(1, fs::read_to_string(&path).unwrap().lines().count());
+        assert_eq!(4, load(&path).unwrap().len());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_describe() {
+        let entry = HistoryEntry {
+            at: "2024-05-01T12:00:00Z".to_string(),
+            event: HistoryEvent::Updated {
+                target: "DNS record home.example.com (A/AAAA)".to_string(),
+                ips: vec!["1.2.3.4".parse().unwrap(), "2001:db8::1".parse().unwrap()],
+            },
+        };
+        assert_eq!(
+            "2024-05-01T12:00:00Z  updated   DNS record home.example.com (A/AAAA) to 1.2.3.4, \
+                2001:db8::1",
+            entry.describe()
+        );
+        assert_eq!(
+            concat!(
+                r#"{"at":"2024-05-01T12:00:00Z","event":"updated","#,
+                r#""target":"DNS record home.example.com (A/AAAA)","#,
+                r#""ips":["1.2.3.4","2001:db8::1"]}"#
+            ),
+            serde_json::to_string(&entry).unwrap()
+        );
+    }
+}
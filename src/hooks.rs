@@ -0,0 +1,116 @@
+use std::process::Command;
+
+use tracing::info;
+
+/// Details of a change passed to hook commands through environment variables
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Change {
+    /// The kind of resource being changed, either "dns" or "firewall"
+    pub kind: &'static str,
+    /// A description of the resource being changed, such as the record or firewall rule
+    pub resource: String,
+    /// The address(es) the resource held before the change, comma separated, or the data of a DNS
+    /// record that doesn't hold an address
+    pub old_ip: String,
+    /// The address(es) the resource will hold after the change, comma separated, or the data of a
+    /// DNS record that doesn't hold an address
+    pub new_ip: String,
+}
+
+impl Change {
+    fn env(&self) -> [(&'static str, &str); 4] {
+        [
+            ("DO_DYN_DNS_KIND", self.kind),
+            ("DO_DYN_DNS_RESOURCE", self.resource.as_str()),
+            ("DO_DYN_DNS_OLD_IP", self.old_ip.as_str()),
+            ("DO_DYN_DNS_NEW_IP", self.new_ip.as_str()),
+        ]
+    }
+}
+
+/// Shell commands to run before and after a change is applied
+#[derive(Debug, Default, Clone)]
+pub struct Hooks {
+    pub pre: Option<String>,
+    pub post: Option<String>,
+}
+
+impl Hooks {
+    /// Run the pre-change hook, if any.  A failing hook prevents the change from being applied.
+    pub fn before(&self, change: &Change, dry_run: bool) -> Result<(), String> {
+        run_hook("pre-hook", self.pre.as_deref(), change, dry_run)
+    }
+
+    /// Run the post-change hook, if any
+    pub fn after(&self, change: &Change, dry_run: bool) -> Result<(), String> {
+        run_hook("post-hook", self.post.as_deref(), change, dry_run)
+    }
+}
+
+fn run_hook(
+    name: &str,
+    command: Option<&str>,
+    change: &Change,
+    dry_run: bool,
+) -> Result<(), String> {
+    let command = match command {
+        Some(command) => command,
+        None => return Ok(()),
+    };
+    if dry_run {
+        info!("DRY RUN: Running {} `{}` for {:?}", name, command, change);
+        return Ok(());
+    }
+
+    info!("Running {} `{}`", name, command);
+    let status = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .envs(change.env())
+        .status()
+        .map_err(|e| format!("unable to run {} `{}`: {}", name, command, e))?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(format!("{} `{}` failed with {}", name, command, status))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::hooks::{Change, Hooks};
+
+    fn change() -> Change {
+        Change {
+            kind: "dns",
+            resource: "main.example.com (A)".to_string(),
+            old_ip: "1.1.1.1".to_string(),
+            new_ip: "2.2.2.2".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_hooks_receive_change() {
+        let hooks = Hooks {
+            pre: Some(
+                "test \"$DO_DYN_DNS_KIND $DO_DYN_DNS_RESOURCE $DO_DYN_DNS_OLD_IP\" = \
+                 \"dns main.example.com (A) 1.1.1.1\""
+                    .to_string(),
+            ),
+            post: Some("test \"$DO_DYN_DNS_NEW_IP\" = 2.2.2.2".to_string()),
+        };
+        assert_eq!(Ok(()), hooks.before(&change(), false));
+        assert_eq!(Ok(()), hooks.after(&change(), false));
+    }
+
+    #[test]
+    fn test_hooks_failure() {
+        let hooks = Hooks {
+            pre: Some("exit 3".to_string()),
+            post: None,
+        };
+        assert!(hooks.before(&change(), false).is_err());
+        assert_eq!(Ok(()), hooks.before(&change(), true));
+        assert_eq!(Ok(()), hooks.after(&change(), false));
+    }
+}
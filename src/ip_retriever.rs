@@ -1,7 +1,93 @@
-use reqwest::blocking::ClientBuilder;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
 
+use std::fmt::{Display, Formatter};
+use std::fs;
 use std::io;
+use std::io::Write;
 use std::net::{IpAddr, UdpSocket};
+use std::path::Path;
+use std::time::Duration;
+
+use crate::http::HttpOptions;
+
+/// A method of detecting the IP address of this host
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum IpSource {
+    /// The address this host is seen as on the internet
+    External,
+    /// The address of the local network interface used to connect to the internet
+    Local,
+    /// The WAN address the local router reports over UPnP/IGD
+    Router,
+}
+
+impl IpSource {
+    pub const ALL: [IpSource; 3] = [IpSource::External, IpSource::Local, IpSource::Router];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            IpSource::External => "external",
+            IpSource::Local => "local",
+            IpSource::Router => "router",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<IpSource> {
+        IpSource::ALL.into_iter().find(|s| s.name() == name)
+    }
+
+    /// Detect the IP address of this host using this source, asking the providers in order for the
+    /// external address
+    pub async fn get_ip(
+        &self,
+        providers: &[IpProvider],
+        http: &HttpOptions,
+    ) -> Result<IpAddr, String> {
+        match self {
+            IpSource::External => get_external_ip(providers, None, http).await,
+            IpSource::Local => get_local_ip().map_err(|e| e.to_string()),
+            IpSource::Router => get_router_ip().await,
+        }
+    }
+
+    /// Detect the IP address of this host in a specific address family using this source
+    pub async fn get_ip_for_family(
+        &self,
+        family: IpFamily,
+        providers: &[IpProvider],
+        http: &HttpOptions,
+    ) -> Result<IpAddr, String> {
+        let ip = match self {
+            IpSource::External => get_external_ip(providers, Some(family), http).await,
+            IpSource::Local => get_local_ip_for_family(family).map_err(|e| e.to_string()),
+            IpSource::Router => get_router_ip().await,
+        }?;
+        match (family, ip) {
+            (IpFamily::V4, IpAddr::V4(_)) | (IpFamily::V6, IpAddr::V6(_)) => Ok(ip),
+            _ => Err(format!(
+                "expected an {} address but got {ip}",
+                family.name()
+            )),
+        }
+    }
+}
+
+/// An IP address family
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum IpFamily {
+    V4,
+    V6,
+}
+
+impl IpFamily {
+    pub fn name(&self) -> &'static str {
+        match self {
+            IpFamily::V4 => "IPv4",
+            IpFamily::V6 => "IPv6",
+        }
+    }
+}
 
 /// Get the IP address of the local network interface used to connect to the internet
 pub fn get_local_ip() -> Result<IpAddr, io::Error> {
@@ -12,16 +98,428 @@ pub fn get_local_ip() -> Result<IpAddr, io::Error> {
     Ok(socket.local_addr()?.ip())
 }
 
-/// Get the IP address that is seen for this host on the internet
-pub fn get_external_ip() -> Result<IpAddr, reqwest::Error> {
-    let client = ClientBuilder::default()
-        .build()
-        .expect("Unable to construct HTTP client");
-    Ok(client
-        .get("http://ipinfo.io/ip")
-        .send()?
-        .text()?
-        .trim()
-        .parse::<IpAddr>()
-        .unwrap())
+/// Get the IP address of the local network interface used to connect to the internet over a
+/// specific address family
+pub fn get_local_ip_for_family(family: IpFamily) -> Result<IpAddr, io::Error> {
+    let socket = match family {
+        IpFamily::V4 => {
+            let socket = UdpSocket::bind("0.0.0.0:0")?;
+            socket.connect("8.8.8.8:80")?;
+            socket
+        }
+        IpFamily::V6 => {
+            let socket = UdpSocket::bind("[::]:0")?;
+            socket.connect("[2001:4860:4860::8888]:80")?;
+            socket
+        }
+    };
+    Ok(socket.local_addr()?.ip())
+}
+
+/// How long to wait for the router to answer the UPnP discovery request
+const ROUTER_SEARCH_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Get the WAN IP address of the local router by discovering it over UPnP and asking it with the
+/// IGD `GetExternalIPAddress` action
+pub async fn get_router_ip() -> Result<IpAddr, String> {
+    let gateway = igd_next::aio::tokio::search_gateway(igd_next::SearchOptions {
+        timeout: Some(ROUTER_SEARCH_TIMEOUT),
+        ..Default::default()
+    })
+    .await
+    .map_err(|e| format!("unable to find a UPnP router: {e}"))?;
+    debug!("Found UPnP router at {}", gateway.addr);
+    let ip = gateway
+        .get_external_ip()
+        .await
+        .map_err(|e| format!("router at {} did not report its address: {e}", gateway.addr))?;
+    if ip.is_unspecified() {
+        // routers report 0.0.0.0 while the WAN link is down
+        return Err(format!(
+            "router at {} does not have a WAN address",
+            gateway.addr
+        ));
+    }
+    Ok(ip)
+}
+
+/// Get the global-scope IP address of a named local network interface, preferring an IPv4
+/// address unless a family is requested
+pub fn get_interface_ip(interface: &str, family: Option<IpFamily>) -> Result<IpAddr, String> {
+    let addrs = if_addrs::get_if_addrs()
+        .map_err(|e| format!("unable to list network interfaces: {e}"))?
+        .into_iter()
+        .map(|iface| (iface.name.clone(), iface.ip()));
+    select_interface_ip(addrs, interface, family)
+}
+
+/// Pick the address to publish for an interface from a listing of (interface name, address) pairs
+fn select_interface_ip(
+    addrs: impl IntoIterator<Item = (String, IpAddr)>,
+    interface: &str,
+    family: Option<IpFamily>,
+) -> Result<IpAddr, String> {
+    let addrs: Vec<IpAddr> = addrs
+        .into_iter()
+        .filter(|(name, _)| name == interface)
+        .map(|(_, ip)| ip)
+        .collect();
+    if addrs.is_empty() {
+        return Err(format!("no network interface named {interface}"));
+    }
+    let global = |ip: &&IpAddr| is_global_scope(ip);
+    let found = match family {
+        Some(IpFamily::V4) => addrs.iter().filter(global).find(|ip| ip.is_ipv4()),
+        Some(IpFamily::V6) => addrs.iter().filter(global).find(|ip| ip.is_ipv6()),
+        None => addrs
+            .iter()
+            .filter(global)
+            .find(|ip| ip.is_ipv4())
+            .or_else(|| addrs.iter().find(|ip| is_global_scope(ip))),
+    };
+    found.copied().ok_or_else(|| match family {
+        Some(family) => format!(
+            "network interface {interface} has no global {} address",
+            family.name()
+        ),
+        None => format!("network interface {interface} has no global address"),
+    })
+}
+
+/// Whether an interface address is usable from outside the link it is on: not loopback,
+/// unspecified, multicast or link-local
+fn is_global_scope(ip: &IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(v4) => {
+            !(v4.is_loopback() || v4.is_unspecified() || v4.is_multicast() || v4.is_link_local())
+        }
+        IpAddr::V6(v6) => {
+            !(v6.is_loopback()
+                || v6.is_unspecified()
+                || v6.is_multicast()
+                || v6.is_unicast_link_local())
+        }
+    }
+}
+
+/// A web service that reports the address this host connects to it from
+#[derive(Deserialize, Serialize, Debug, Clone, Eq, PartialEq)]
+#[serde(try_from = "String", into = "String")]
+pub enum IpProvider {
+    Ipify,
+    Icanhazip,
+    /// Cloudflare's trace endpoint, which reports the address on an `ip=` line
+    Cloudflare,
+    OpenDns,
+    /// Any URL that responds with just the address
+    Custom(String),
+}
+
+impl IpProvider {
+    pub fn name(&self) -> &str {
+        match self {
+            IpProvider::Ipify => "ipify",
+            IpProvider::Icanhazip => "icanhazip",
+            IpProvider::Cloudflare => "cloudflare",
+            IpProvider::OpenDns => "opendns",
+            IpProvider::Custom(url) => url,
+        }
+    }
+
+    /// Parse the name of a provider, or a URL of a custom provider
+    pub fn parse(name: &str) -> Result<IpProvider, String> {
+        match name {
+            "ipify" => Ok(IpProvider::Ipify),
+            "icanhazip" => Ok(IpProvider::Icanhazip),
+            "cloudflare" => Ok(IpProvider::Cloudflare),
+            "opendns" => Ok(IpProvider::OpenDns),
+            url if url.starts_with("http://") || url.starts_with("https://") => {
+                Ok(IpProvider::Custom(url.to_string()))
+            }
+            _ => Err(format!(
+                "'{name}' is not one of ipify, icanhazip, cloudflare, or opendns, or an http(s) URL"
+            )),
+        }
+    }
+
+    /// The URL to ask for the address, restricted to a family if the provider supports it
+    fn url(&self, family: Option<IpFamily>) -> &str {
+        match (self, family) {
+            (IpProvider::Ipify, None) => "https://api64.ipify.org",
+            (IpProvider::Ipify, Some(IpFamily::V4)) => "https://api.ipify.org",
+            (IpProvider::Ipify, Some(IpFamily::V6)) => "https://api6.ipify.org",
+            (IpProvider::Icanhazip, None) => "https://icanhazip.com",
+            (IpProvider::Icanhazip, Some(IpFamily::V4)) => "https://ipv4.icanhazip.com",
+            (IpProvider::Icanhazip, Some(IpFamily::V6)) => "https://ipv6.icanhazip.com",
+            (IpProvider::Cloudflare, Some(IpFamily::V6)) => {
+                "https://[2606:4700:4700::1111]/cdn-cgi/trace"
+            }
+            (IpProvider::Cloudflare, _) => "https://1.1.1.1/cdn-cgi/trace",
+            (IpProvider::OpenDns, _) => "https://diagnostic.opendns.com/myip",
+            (IpProvider::Custom(url), _) => url,
+        }
+    }
+
+    /// Extract the address from the body of the provider's response
+    fn parse_response(&self, body: &str) -> Result<IpAddr, String> {
+        let text = match self {
+            IpProvider::Cloudflare => body
+                .lines()
+                .find_map(|line| line.strip_prefix("ip="))
+                .ok_or_else(|| "no ip= line in trace".to_string())?,
+            _ => body,
+        }
+        .trim();
+        let ip = text
+            .parse::<IpAddr>()
+            .map_err(|e| format!("{e}: {text:.64}"))?;
+        if ip.is_loopback() || ip.is_unspecified() || ip.is_multicast() {
+            Err(format!("{ip} is not a public address"))
+        } else {
+            Ok(ip)
+        }
+    }
+
+    /// Ask the provider for the address of this host
+    async fn get_ip(&self, family: Option<IpFamily>, http: &HttpOptions) -> Result<IpAddr, String> {
+        let client = http.client(PROVIDER_TIMEOUT);
+        let body = async {
+            client
+                .get(self.url(family))
+                .send()
+                .await?
+                .error_for_status()?
+                .text()
+                .await
+        }
+        .await
+        .map_err(|e| e.to_string())?;
+        let ip = self.parse_response(&body)?;
+        match family {
+            Some(IpFamily::V4) if ip.is_ipv6() => Err(format!("{ip} is not an IPv4 address")),
+            Some(IpFamily::V6) if ip.is_ipv4() => Err(format!("{ip} is not an IPv6 address")),
+            _ => Ok(ip),
+        }
+    }
+}
+
+impl TryFrom<String> for IpProvider {
+    type Error = String;
+
+    fn try_from(name: String) -> Result<Self, Self::Error> {
+        IpProvider::parse(&name)
+    }
+}
+
+impl From<IpProvider> for String {
+    fn from(provider: IpProvider) -> Self {
+        provider.name().to_string()
+    }
+}
+
+impl Display for IpProvider {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// Longest time to wait for a single provider before moving on to the next one, unless another
+/// timeout is configured
+const PROVIDER_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Get the IP address that is seen for this host on the internet, optionally restricted to an
+/// address family.  The providers are asked in order until one of them returns an address.
+pub async fn get_external_ip(
+    providers: &[IpProvider],
+    family: Option<IpFamily>,
+    http: &HttpOptions,
+) -> Result<IpAddr, String> {
+    let mut errors = Vec::new();
+    for provider in providers {
+        match provider.get_ip(family, http).await {
+            Ok(ip) => {
+                debug!("IP provider {} reported {}", provider, ip);
+                return Ok(ip);
+            }
+            Err(e) => {
+                warn!("IP provider {} failed: {}", provider, e);
+                errors.push(format!("{provider}: {e}"));
+            }
+        }
+    }
+    Err(format!(
+        "no IP provider returned an address ({})",
+        errors.join("; ")
+    ))
+}
+
+/// The outcome of detecting the IP address with a single source
+#[derive(Serialize, Debug, Clone)]
+pub struct SourceResult {
+    pub source: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub address: Option<IpAddr>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl SourceResult {
+    pub async fn detect(
+        source: IpSource,
+        providers: &[IpProvider],
+        http: &HttpOptions,
+    ) -> SourceResult {
+        let result = source.get_ip(providers, http).await;
+        SourceResult {
+            source: source.name(),
+            address: result.as_ref().ok().copied(),
+            error: result.err(),
+        }
+    }
+}
+
+/// Write the IP address to a file, replacing it atomically so that readers never see a partially
+/// written address
+pub fn write_ip_file(path: &Path, ip: &IpAddr) -> Result<(), io::Error> {
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "path is not a file"))?;
+    let mut tmp_name = file_name.to_os_string();
+    tmp_name.push(".tmp");
+    let tmp_path = path.with_file_name(tmp_name);
+
+    let mut file = fs::File::create(&tmp_path)?;
+    writeln!(file, "{ip}")?;
+    file.sync_all()?;
+    fs::rename(&tmp_path, path)
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+    use std::net::IpAddr;
+
+    use crate::http::HttpOptions;
+    use crate::ip_retriever::{
+        get_external_ip, select_interface_ip, write_ip_file, IpFamily, IpProvider,
+    };
+
+    #[test]
+    fn test_write_ip_file() {
+        let dir = std::env::temp_dir().join(format!("do-dyn-dns-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("ip");
+
+        write_ip_file(&path, &"1.2.3.4".parse::<IpAddr>().unwrap()).unwrap();
+        assert_eq!("1.2.3.4\n", fs::read_to_string(&path).unwrap());
+        write_ip_file(&path, &"2001:db8::1".parse::<IpAddr>().unwrap()).unwrap();
+        assert_eq!("2001:db8::1\n", fs::read_to_string(&path).unwrap());
+        assert_eq!(1, fs::read_dir(&dir).unwrap().count());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_parse_provider() {
+        assert_eq!(Ok(IpProvider::Ipify), IpProvider::parse("ipify"));
+        assert_eq!(Ok(IpProvider::OpenDns), IpProvider::parse("opendns"));
+        assert_eq!(
+            Ok(IpProvider::Custom("https://example.com/ip".to_string())),
+            IpProvider::parse("https://example.com/ip")
+        );
+        assert!(IpProvider::parse("ipinfo").is_err());
+    }
+
+    #[test]
+    fn test_parse_response() {
+        assert_eq!(
+            Ok("1.2.3.4".parse::<IpAddr>().unwrap()),
+            IpProvider::Ipify.parse_response("1.2.3.4\n")
+        );
+        assert_eq!(
+            Ok("2001:db8::1".parse::<IpAddr>().unwrap()),
+            IpProvider::Cloudflare.parse_response("fl=1\nh=1.1.1.1\nip=2001:db8::1\nts=1\n")
+        );
+        assert!(IpProvider::Cloudflare
+            .parse_response("fl=1\nh=1.1.1.1\n")
+            .is_err());
+        assert!(IpProvider::Icanhazip
+            .parse_response("<html>rate limited</html>")
+            .is_err());
+        assert!(IpProvider::Icanhazip.parse_response("127.0.0.1").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_get_external_ip_falls_back() {
+        let mut server = mockito::Server::new_async().await;
+        let _down = server
+            .mock("GET", "/down")
+            .with_status(503)
+            .create_async()
+            .await;
+        let _garbage = server
+            .mock("GET", "/garbage")
+            .with_body("<html></html>")
+            .create_async()
+            .await;
+        let _v4 = server
+            .mock("GET", "/v4")
+            .with_body("1.2.3.4\n")
+            .create_async()
+            .await;
+        let provider = |path: &str| IpProvider::Custom(format!("{}{}", server.url(), path));
+
+        assert_eq!(
+            Ok("1.2.3.4".parse::<IpAddr>().unwrap()),
+            get_external_ip(
+                &[provider("/down"), provider("/garbage"), provider("/v4")],
+                None,
+                &HttpOptions::default()
+            )
+            .await
+        );
+        // an address of the wrong family counts as a failure
+        assert!(get_external_ip(
+            &[provider("/v4")],
+            Some(IpFamily::V6),
+            &HttpOptions::default()
+        )
+        .await
+        .is_err());
+        assert!(get_external_ip(&[], None, &HttpOptions::default())
+            .await
+            .is_err());
+    }
+
+    #[test]
+    fn test_select_interface_ip() {
+        let addrs = || {
+            [
+                ("lo", "127.0.0.1"),
+                ("lo", "::1"),
+                ("eth0", "fe80::1"),
+                ("eth0", "2001:db8::10"),
+                ("eth0", "192.168.1.10"),
+                ("eth1", "169.254.3.4"),
+                ("eth1", "fe80::2"),
+                ("wg0", "10.0.0.2"),
+            ]
+            .map(|(name, ip)| (name.to_string(), ip.parse::<IpAddr>().unwrap()))
+        };
+        let ip = |s: &str| Ok(s.parse::<IpAddr>().unwrap());
+
+        assert_eq!(
+            ip("192.168.1.10"),
+            select_interface_ip(addrs(), "eth0", None)
+        );
+        assert_eq!(
+            ip("2001:db8::10"),
+            select_interface_ip(addrs(), "eth0", Some(IpFamily::V6))
+        );
+        assert_eq!(ip("10.0.0.2"), select_interface_ip(addrs(), "wg0", None));
+        assert!(select_interface_ip(addrs(), "wg0", Some(IpFamily::V6)).is_err());
+        assert!(select_interface_ip(addrs(), "eth1", None).is_err());
+        assert!(select_interface_ip(addrs(), "lo", None).is_err());
+        assert!(select_interface_ip(addrs(), "eth2", None).is_err());
+    }
 }
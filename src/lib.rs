@@ -0,0 +1,7235 @@
+//! Keep DigitalOcean DNS records, firewall rules and reserved IPs pointed at the IP address of
+//! this host.
+//!
+//! The `digitalocean-dyn-dns` binary is a thin command line wrapper around this crate.  To embed
+//! the update logic elsewhere, create a [`DigitalOceanClient`], detect the address with
+//! [`ip_retriever`] and call [`update_dns`] or [`update_firewall`].
+#![forbid(unsafe_code)]
+
+#[cfg(test)]
+extern crate approx;
+extern crate clap;
+#[cfg(test)]
+extern crate mockito;
+extern crate reqwest;
+extern crate serde;
+#[cfg(not(test))]
+extern crate serde_json;
+#[cfg(test)]
+#[macro_use]
+extern crate serde_json;
+extern crate tracing;
+
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet};
+use std::fmt::Display;
+use std::future::Future;
+use std::hash::Hash;
+use std::net::{IpAddr, Ipv6Addr};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use tracing::{error, info, warn};
+
+use crate::audit::AuditEntry;
+use crate::cli::{
+    CheckArgs, ConfigImportArgs, Direction, DnsArgs, DnsDeleteArgs, DnsListArgs, DoctorArgs,
+    DropletListArgs, FirewallArgs, FirewallAssignArgs, FirewallDiffArgs, HistoryArgs,
+    ReservedIpArgs, SubcmdArgs,
+};
+use crate::digitalocean::account::{Account, DigitalOceanAccountClient};
+use crate::digitalocean::api::DEFAULT_PER_PAGE;
+use crate::digitalocean::dns::{
+    relative_record_name, DigitalOceanDnsClient, DomainRecord, RecordValue,
+};
+use crate::digitalocean::droplet::{DigitalOceanDropletClient, DropletSummary};
+use crate::digitalocean::firewall::{
+    DigitalOceanFirewallClient, Firewall, FirewallInboundRule, FirewallOutboundRule,
+    FirewallRuleTarget, ALL_PORTS,
+};
+use crate::digitalocean::kubernetes::DigitalOceanKubernetesClient;
+use crate::digitalocean::loadbalancer::DigitalOceanLoadbalancerClient;
+use crate::digitalocean::project::{DigitalOceanProjectsClient, ProjectResource};
+use crate::digitalocean::reserved_ip::{DigitalOceanReservedIpClient, ReservedIp};
+use crate::digitalocean::tags::DigitalOceanTagsClient;
+pub use crate::digitalocean::DigitalOceanClient;
+use crate::hooks::{Change, Hooks};
+use crate::lock::RunLock;
+use crate::names::ResourceNames;
+use crate::provider::{DnsProviders, Provider};
+use crate::service::ServiceNotifier;
+use crate::state::{FirewallIds, State, TargetState};
+use crate::stats::RunStats;
+
+mod audit;
+pub mod cli;
+pub mod cloudflare;
+pub mod config;
+mod config_import;
+pub mod cron;
+pub mod daemon;
+mod delegation;
+pub mod digitalocean;
+mod doctor;
+mod droplet_list;
+pub mod email;
+mod firewall_diff;
+pub mod heartbeat;
+pub mod history;
+pub mod hooks;
+pub mod http;
+pub mod ip_retriever;
+pub mod lock;
+pub mod logging;
+mod names;
+pub mod provider;
+mod record_list;
+pub mod secret;
+pub mod service;
+pub mod state;
+pub mod stats;
+
+/// Settings shared by every update made through [`update_dns`] and [`update_firewall`]
+#[derive(Debug, Default, Clone)]
+pub struct UpdateOptions {
+    /// Log the changes that would be made without making them
+    pub dry_run: bool,
+    /// Re-read each resource after changing it and fail if it does not hold the new value
+    pub verify: bool,
+    /// Commands to run before and after each change
+    pub hooks: Hooks,
+    /// Set when shutting down, abandoning any wait such as for the previous TTL of a record
+    pub shutdown: Arc<AtomicBool>,
+}
+
+/// Bring a DNS record, and any aliases of it, in line with the given IP address(es).  The record
+/// holds the first address of its family unless `dns_args` gives the data explicitly.
+pub async fn update_dns(
+    client: &DigitalOceanClient,
+    dns_args: DnsArgs,
+    ips: &[IpAddr],
+    options: &UpdateOptions,
+) -> Result<(), Error> {
+    run_dns_target(client, dns_args, ips, options, &RunStats::start()).await
+}
+
+/// Point a firewall rule at the given IP address(es), replacing the addresses it previously
+/// allowed
+pub async fn update_firewall(
+    client: &DigitalOceanClient,
+    fw_args: FirewallArgs,
+    ips: &[IpAddr],
+    options: &UpdateOptions,
+) -> Result<(), Error> {
+    run_firewall(
+        client,
+        fw_args,
+        ips,
+        &RefCell::default(),
+        options.verify,
+        &options.hooks,
+        &RunStats::start(),
+        options.dry_run,
+    )
+    .await
+}
+
+/// Run `future` to completion, abandoning it with `Error::DeadlineExceeded` if it takes longer than
+/// the deadline
+pub async fn within_deadline<T>(
+    deadline: Option<Duration>,
+    future: impl Future<Output = Result<T, Error>>,
+) -> Result<T, Error> {
+    match deadline {
+        Some(deadline) => tokio::time::timeout(deadline, future)
+            .await
+            .unwrap_or_else(|_| Err(Error::DeadlineExceeded(deadline)))
+            .inspect_err(|e| {
+                if matches!(e, Error::DeadlineExceeded(_)) {
+                    error!("{}", e)
+                }
+            }),
+        None => future.await,
+    }
+}
+
+/// Keep every target in line with the IP address(es) until `shutdown` is set, updating them
+/// whenever the detected address changes.  The targets of each job are checked on the job's own
+/// schedule, and the others every `--interval`.  Fails only if the API token is not accepted; a
+/// transient failure to check it is left to the first update to retry.  Once as many updates in a
+/// row as the email settings allow have failed, the failure is emailed.
+pub async fn run_daemon(
+    args: &cli::Args,
+    shutdown: &Arc<AtomicBool>,
+    notifier: &ServiceNotifier,
+) -> Result<(), Error> {
+    // the account of each token is checked before any update, the global one only if it is used
+    let global = !args.targets.is_empty() || args.jobs.iter().any(|job| job.token.is_none());
+    let own_tokens = args.jobs.iter().filter(|job| job.token.is_some());
+    let accounts =
+        (global.then(|| args.clone()).into_iter()).chain(own_tokens.map(|job| args.for_job(job)));
+    for account_args in accounts {
+        if let Err(e) = verify_account(&account_args).await {
+            if !e.is_retriable() {
+                return Err(e);
+            }
+        }
+    }
+    let mut runs = Vec::new();
+    if !args.targets.is_empty() {
+        let schedule = daemon::Schedule {
+            interval: Duration::from_secs(args.interval),
+            cron: None,
+            jitter: None,
+            stabilize_checks: args.stabilize_checks,
+            update_unchanged: args.check || args.targets.iter().any(SubcmdArgs::retains_addresses),
+        };
+        runs.push((
+            cli::Args {
+                jobs: Vec::new(),
+                ..args.clone()
+            },
+            schedule,
+        ));
+    }
+    for job in &args.jobs {
+        info!(
+            "Job {}: {}",
+            job.name,
+            job.targets
+                .iter()
+                .map(SubcmdArgs::to_string)
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+        let schedule = daemon::Schedule {
+            interval: Duration::from_secs(job.interval),
+            cron: job.cron,
+            jitter: job.jitter,
+            stabilize_checks: args.stabilize_checks,
+            update_unchanged: args.check || job.targets.iter().any(SubcmdArgs::retains_addresses),
+        };
+        runs.push((args.for_job(job), schedule));
+    }
+    // the updates of different jobs take turns, so that they don't overwrite each other's state
+    let updating = tokio::sync::Mutex::new(());
+    futures_util::future::join_all(
+        runs.iter()
+            .map(|(args, schedule)| run_job(args, *schedule, shutdown, notifier, &updating)),
+    )
+    .await;
+    Ok(())
+}
+
+/// Keep the targets of `args` up to date on the schedule until `shutdown` is set
+async fn run_job(
+    args: &cli::Args,
+    schedule: daemon::Schedule,
+    shutdown: &Arc<AtomicBool>,
+    notifier: &ServiceNotifier,
+    updating: &tokio::sync::Mutex<()>,
+) {
+    let failures = &Cell::new(0);
+    // the targets whose drift was last emailed, so that it is only emailed again once it changes
+    let reported_drift: &RefCell<Vec<String>> = &RefCell::default();
+    daemon::run(
+        schedule,
+        shutdown,
+        notifier,
+        || async {
+            let ips = args.detect_ips().await;
+            if let (Ok(ips), Some(path)) = (&ips, &args.history_file) {
+                history::record_detection(path, ips);
+            }
+            ips
+        },
+        |ips| async move {
+            let _updating = updating.lock().await;
+            let result = within_deadline(
+                args.deadline,
+                run_once(args, &ips, RunStats::start(), shutdown),
+            )
+            .await
+            .map(|_| ());
+            match (&result, &args.email) {
+                (Ok(()), _) => {
+                    failures.set(0);
+                    reported_drift.borrow_mut().clear();
+                }
+                (Err(Error::Drift(targets)), email) => {
+                    failures.set(0);
+                    if let Some(email) = email
+                        .as_ref()
+                        .filter(|_| *reported_drift.borrow() != *targets)
+                    {
+                        email::notify_drift(email, targets, args.dry_run).await;
+                    }
+                    *reported_drift.borrow_mut() = targets.clone();
+                }
+                (Err(e), Some(email)) => {
+                    failures.set(failures.get() + 1);
+                    if failures.get() == email.after_failures {
+                        email::notify_failures(email, failures.get(), &e.to_string(), args.dry_run)
+                            .await;
+                    }
+                }
+                (Err(_), None) => {}
+            }
+            result
+        },
+    )
+    .await;
+}
+
+/// Bring every target in line with the detected IP address(es) once, then report the statistics
+/// of the run and ping the `--heartbeat-url` with its outcome.  Once `shutdown` is set, the target
+/// being updated is finished but no further targets are started.  The `--lock-file`, if given, is
+/// held for the whole run, and the detected address and every target changed are logged to the
+/// `--history-file`.  Returns whether any resource was changed.
+pub async fn run_once(
+    args: &cli::Args,
+    ips: &[IpAddr],
+    stats: RunStats,
+    shutdown: &Arc<AtomicBool>,
+) -> Result<bool, Error> {
+    // a run that finds the lock held changes nothing, so it leaves the heartbeat to the run that
+    // holds it
+    let _lock = match args.lock_file {
+        Some(ref path) => match RunLock::acquire(path, args.lock_wait).await {
+            Ok(Some(lock)) => Some(lock),
+            Ok(None) => {
+                warn!("Another run holds {}, not updating", path.display());
+                return Err(Error::Locked(path.clone()));
+            }
+            Err(e) => {
+                error!("Unable to lock {}: {}", path.display(), e);
+                return Err(Error::LockFile());
+            }
+        },
+        None => None,
+    };
+    if let Some(ref path) = args.history_file {
+        history::record_detection(path, ips);
+    }
+    let result = update_targets(args, ips, stats, shutdown).await;
+    if let Some(ref url) = args.heartbeat_url {
+        heartbeat::ping(url, result.is_ok(), args.dry_run, &args.http).await;
+    }
+    result
+}
+
+async fn update_targets(
+    args: &cli::Args,
+    ips: &[IpAddr],
+    stats: RunStats,
+    shutdown: &Arc<AtomicBool>,
+) -> Result<bool, Error> {
+    let client = digitalocean::DigitalOceanClient::new(
+        args.api_token().clone(),
+        args.max_pages,
+        args.per_page,
+        args.page_concurrency,
+        args.retry,
+        &args.http,
+    )
+    .with_cache(args.cache_file.clone(), args.cache_ttl);
+    let providers = DnsProviders::new(&client, args.cloudflare_token.clone(), &args.http);
+    let options = UpdateOptions {
+        dry_run: args.dry_run,
+        verify: args.verify,
+        hooks: args.hooks.clone(),
+        shutdown: Arc::clone(shutdown),
+    };
+
+    let mut state = match args.state_file {
+        Some(ref path) => Some(State::load(path).map_err(|e| {
+            error!("Unable to read state file {}: {}", path.display(), e);
+            Error::StateFile()
+        })?),
+        None => None,
+    };
+    let firewall_ids = RefCell::new(
+        state
+            .as_ref()
+            .map(|state| state.firewall_ids(args.api_token()))
+            .unwrap_or_default(),
+    );
+
+    let mut results: Vec<Result<(), Error>> = Vec::new();
+    for target in &args.targets {
+        if shutdown.load(Ordering::SeqCst) {
+            warn!("{}: shutting down, not updated", target);
+            results.push(Err(Error::Interrupted()));
+            continue;
+        }
+        let previous = state.as_ref().and_then(|state| state.get(target));
+        let expired = previous
+            .map(|previous| expired_ips(target, previous))
+            .unwrap_or_default();
+        if !args.check
+            && target.follows_detected_ips()
+            && expired.is_empty()
+            && previous.is_some_and(|previous| previous.ips == ips)
+        {
+            info!(
+                "{}: already holds {:?} since a previous run, skipping",
+                target, ips
+            );
+            results.push(Ok(()));
+            continue;
+        }
+        if !expired.is_empty() {
+            info!(
+                "{}: removing {:?}, which have not been detected within the retention period",
+                target, expired
+            );
+        }
+        let to_run = match previous {
+            Some(previous) => with_previous_ips(target.clone(), previous),
+            None => target.clone(),
+        };
+        let changes = stats.changes();
+        let result = run_target(
+            args,
+            &options,
+            &client,
+            &providers,
+            to_run,
+            ips,
+            &firewall_ids,
+            &stats,
+        )
+        .await;
+        if let (Ok(()), Some(path)) = (&result, &args.history_file) {
+            if !args.dry_run && !args.check && stats.changes() > changes {
+                history::record_update(path, &target.to_string(), ips);
+            }
+        }
+        if let (Ok(()), Some(ref mut state)) = (&result, &mut state) {
+            if !args.dry_run && !args.check {
+                state.record(target, ips);
+                state.forget(target, &expired);
+            }
+        }
+        results.push(result);
+    }
+    if let Some(ref mut state) = state {
+        if !args.dry_run && !args.check {
+            state.set_firewall_ids(args.api_token(), firewall_ids.into_inner());
+        }
+    }
+    if results.len() > 1 {
+        for (target, result) in args.targets.iter().zip(&results) {
+            match result {
+                Ok(()) => info!("{}: succeeded", target),
+                Err(e) => error!("{}: failed: {}", target, e),
+            }
+        }
+    }
+
+    let ip_out_written = stats
+        .time("write IP", async {
+            write_ip_out(args.ip_out.as_deref(), &ips[0])
+        })
+        .await;
+    let state_saved = match (&state, &args.state_file) {
+        (Some(state), Some(path)) if !args.dry_run => state
+            .save(path)
+            .inspect_err(|e| error!("Unable to write state file {}: {}", path.display(), e))
+            .is_ok(),
+        _ => true,
+    };
+
+    let report = stats.report(client.stats());
+    if args.verbose {
+        report.log();
+    }
+    if args.profile {
+        report.log_profile();
+    }
+    if args.stats_json {
+        println!("{}", serde_json::to_string(&report).unwrap());
+    }
+
+    // report the most severe failure, which has the lowest exit code, naming every target that
+    // drifted if that is the failure
+    let drifted: Vec<String> = results
+        .iter()
+        .filter_map(|result| match result {
+            Err(Error::Drift(targets)) => Some(targets.clone()),
+            _ => None,
+        })
+        .flatten()
+        .collect();
+    if let Some(e) = results
+        .into_iter()
+        .filter_map(Result::err)
+        .min_by_key(exit_code)
+    {
+        return match e {
+            Error::Drift(_) => Err(Error::Drift(drifted)),
+            e => Err(e),
+        };
+    }
+    if !ip_out_written {
+        Err(Error::IpOut())
+    } else if !state_saved {
+        Err(Error::StateFile())
+    } else {
+        if let (true, Some(email)) = (report.changes > 0, &args.email) {
+            email::notify_change(email, ips, &stats.changed(), args.dry_run).await;
+        }
+        Ok(report.changes > 0)
+    }
+}
+
+/// Have a firewall rule drop the address(es) published by a previous run, unless the stale
+/// addresses were given explicitly.  With `retain_days`, only the addresses retired longer ago
+/// than that are dropped.
+fn with_previous_ips(target: SubcmdArgs, previous: &TargetState) -> SubcmdArgs {
+    let old_ips = match target {
+        SubcmdArgs::Firewall(FirewallArgs {
+            retain_days: Some(_),
+            ..
+        }) => expired_ips(&target, previous),
+        _ => previous.ips.clone(),
+    };
+    match target {
+        SubcmdArgs::Firewall(fw_args) if fw_args.old_ips.is_none() => {
+            SubcmdArgs::Firewall(FirewallArgs {
+                old_ips: Some(old_ips),
+                ..fw_args
+            })
+        }
+        target => target,
+    }
+}
+
+/// The addresses a firewall rule with `retain_days` has kept for longer than that since they were
+/// last detected
+fn expired_ips(target: &SubcmdArgs, previous: &TargetState) -> Vec<IpAddr> {
+    match target {
+        SubcmdArgs::Firewall(FirewallArgs {
+            retain_days: Some(days),
+            ..
+        }) => previous.expired(
+            Duration::from_secs(u64::from(*days) * 24 * 60 * 60),
+            state::now(),
+        ),
+        _ => Vec::new(),
+    }
+}
+
+/// Bring one DNS record or firewall rule in line with the detected IP address(es)
+#[allow(clippy::too_many_arguments)]
+async fn run_target(
+    args: &cli::Args,
+    options: &UpdateOptions,
+    client: &DigitalOceanClient,
+    providers: &DnsProviders<'_>,
+    target: SubcmdArgs,
+    ips: &[IpAddr],
+    firewall_ids: &RefCell<FirewallIds>,
+    stats: &RunStats,
+) -> Result<(), Error> {
+    match target {
+        target if args.check => {
+            let firewall_ids = firewall_ids.borrow().clone();
+            let name = target.to_string();
+            stats
+                .time(
+                    "check",
+                    check(client, providers, target, ips, &firewall_ids),
+                )
+                .await
+                .and_then(|in_sync| {
+                    if in_sync {
+                        Ok(())
+                    } else {
+                        Err(Error::Drift(vec![name]))
+                    }
+                })
+        }
+        SubcmdArgs::Dns(dns_args) => {
+            providers
+                .get(dns_args.provider)?
+                .update_dns(dns_args, ips, options, stats)
+                .await
+        }
+        SubcmdArgs::Firewall(fw_args) => {
+            run_firewall(
+                client,
+                fw_args,
+                ips,
+                firewall_ids,
+                options.verify,
+                &options.hooks,
+                stats,
+                options.dry_run,
+            )
+            .await
+        }
+        SubcmdArgs::ReservedIp(rip_args) => run_reserved_ip(
+            client.reserved_ip.clone(),
+            client.droplet.clone(),
+            rip_args,
+            options.verify,
+            stats,
+            options.dry_run,
+        )
+        .await
+        .inspect_err(|e| error!("Encountered error while assigning reserved IP: {}", e)),
+    }
+}
+
+/// Bring a DNS record and its aliases in line with the detected IP address(es)
+async fn run_dns_target(
+    client: &DigitalOceanClient,
+    dns_args: DnsArgs,
+    ips: &[IpAddr],
+    options: &UpdateOptions,
+    stats: &RunStats,
+) -> Result<(), Error> {
+    async {
+        let ips = source_ips(client, &dns_args, ips).await?;
+        let values = dns_values(&dns_args, &ips)?;
+        let audit = dns_args.audit_txt.then(AuditEntry::now);
+        let records = stats
+            .time(
+                "domain lookup",
+                resolve_records(client.dns.clone(), &dns_args, &values),
+            )
+            .await?;
+        for (record, domain) in records {
+            if dns_args.check_delegation {
+                delegation::warn_if_not_delegated(&domain).await;
+            }
+            for record in with_aliases(record, &dns_args.aliases, dns_args.with_www) {
+                for (rtype, value) in &values {
+                    run_dns(
+                        client.dns.clone(),
+                        domain.clone(),
+                        record.clone(),
+                        rtype.clone(),
+                        value.clone(),
+                        dns_args.ttl,
+                        dns_args.lower_ttl,
+                        dns_args.force,
+                        dns_args.create_if_missing,
+                        dns_args.create_domain,
+                        options.verify,
+                        &options.hooks,
+                        &options.shutdown,
+                        stats,
+                        options.dry_run,
+                    )
+                    .await?;
+                }
+                if let Some(ref audit) = audit {
+                    audit::write(
+                        &client.dns,
+                        &domain,
+                        &record,
+                        audit,
+                        dns_args.ttl,
+                        options.dry_run,
+                    )
+                    .await;
+                }
+            }
+        }
+        Ok(())
+    }
+    .await
+    .inspect_err(|e| error!("Encountered error while updating DNS record: {}", e))
+}
+
+/// Print the differences between the rules of a firewall and a rules file, failing with
+/// `Error::Drift` if there are any.
+pub async fn print_firewall_diff(diff_args: FirewallDiffArgs) -> Result<(), Error> {
+    let spec = firewall_diff::load_rules_file(&diff_args.rules_file)
+        .map_err(Error::RulesFile)
+        .inspect_err(|e| error!("Encountered error while reading rules file: {}", e))?;
+    let client = digitalocean::DigitalOceanClient::new(
+        diff_args.token,
+        diff_args.max_pages,
+        diff_args.per_page,
+        diff_args.page_concurrency,
+        diff_args.retry,
+        &diff_args.http,
+    );
+    let firewall = client
+        .firewall
+        .get_firewall(diff_args.name)
+        .await?
+        .ok_or(Error::FirewallNotFound())
+        .inspect_err(|e| error!("Encountered error while fetching firewall: {}", e))?;
+
+    let diffs = firewall_diff::diff_firewall(&firewall, &spec);
+    if diff_args.json {
+        println!("{}", serde_json::to_string(&diffs).unwrap());
+    } else {
+        for diff in &diffs {
+            println!("{diff}");
+        }
+    }
+
+    if diffs.is_empty() {
+        info!("Firewall {} matches the rules file", firewall.name);
+        Ok(())
+    } else {
+        info!(
+            "Firewall {} differs from the rules file in {} rule(s)",
+            firewall.name,
+            diffs.len()
+        );
+        Err(Error::Drift(vec![firewall.name]))
+    }
+}
+
+/// Delete the DNS records named on the command line.
+pub async fn delete_dns(delete_args: DnsDeleteArgs) -> Result<(), Error> {
+    let client = digitalocean::DigitalOceanClient::new(
+        delete_args.token,
+        delete_args.max_pages,
+        delete_args.per_page,
+        delete_args.page_concurrency,
+        delete_args.retry,
+        &delete_args.http,
+    );
+    delete_records(
+        client.dns,
+        delete_args.record,
+        delete_args.domain,
+        delete_args.fqdn,
+        delete_args.rtype,
+        delete_args.data,
+        delete_args.dry_run,
+    )
+    .await
+    .map(|_| ())
+    .inspect_err(|e| error!("Encountered error while deleting DNS records: {}", e))
+}
+
+/// Delete every record of the name and type, or only the one holding `data` when it is given.
+/// Fails if there is no such record, so that a typo in the name is not mistaken for success.
+async fn delete_records(
+    client: Rc<dyn DigitalOceanDnsClient>,
+    record: String,
+    domain: Option<String>,
+    fqdn: bool,
+    rtype: String,
+    data: Option<String>,
+    dry_run: bool,
+) -> Result<Vec<DomainRecord>, Error> {
+    let (record_name, domain) = resolve_record_name(client.clone(), record, domain, fqdn).await?;
+    let records: Vec<DomainRecord> = client
+        .get_records(&domain, &rtype)
+        .await?
+        .into_iter()
+        .filter(|r| {
+            r.name.eq_ignore_ascii_case(&record_name)
+                && data.as_ref().is_none_or(|data| r.data == *data)
+        })
+        .collect();
+    if records.is_empty() {
+        return Err(Error::RecordNotFound(format!(
+            "{}.{} ({})",
+            record_name, domain, rtype
+        )));
+    }
+    for record in &records {
+        info!(
+            "Deleting record {}.{} ({}) holding {}",
+            record_name, domain, rtype, record.data
+        );
+        client.delete_record(&domain, record, &dry_run).await?;
+    }
+    Ok(records)
+}
+
+/// Attach a firewall to the droplets named on the command line.
+pub async fn assign_firewall(assign_args: FirewallAssignArgs) -> Result<(), Error> {
+    let client = digitalocean::DigitalOceanClient::new(
+        assign_args.token,
+        assign_args.max_pages,
+        assign_args.per_page,
+        assign_args.page_concurrency,
+        assign_args.retry,
+        &assign_args.http,
+    );
+    attach_droplets(
+        client.firewall,
+        client.droplet,
+        assign_args.name,
+        assign_args.droplets,
+        assign_args.exclusive,
+        assign_args.dry_run,
+    )
+    .await
+    .inspect_err(|e| error!("Encountered error while assigning firewall: {}", e))
+}
+
+/// Attach a firewall to the named droplets that it is not attached to yet, and when `exclusive`
+/// is set, detach it from every other droplet.
+async fn attach_droplets(
+    fw_client: Rc<dyn DigitalOceanFirewallClient>,
+    droplet_client: Rc<dyn DigitalOceanDropletClient>,
+    name: String,
+    droplets: Vec<String>,
+    exclusive: bool,
+    dry_run: bool,
+) -> Result<(), Error> {
+    let firewall = fw_client
+        .get_firewall(name)
+        .await?
+        .ok_or(Error::FirewallNotFound())?;
+    let wanted = names_to_ids(
+        "droplet",
+        || droplet_client.get_droplet_summaries(),
+        Some(droplets),
+        |_| true,
+        |d| d.name.clone(),
+        |d| d.id,
+    )
+    .await?
+    .unwrap_or_default();
+    let attached = firewall.droplet_ids.clone().unwrap_or_default();
+
+    let to_assign: Vec<u32> = wanted
+        .iter()
+        .filter(|id| !attached.contains(id))
+        .copied()
+        .collect();
+    let to_unassign: Vec<u32> = if exclusive {
+        attached
+            .iter()
+            .filter(|id| !wanted.contains(id))
+            .copied()
+            .collect()
+    } else {
+        vec![]
+    };
+
+    if to_assign.is_empty() && to_unassign.is_empty() {
+        info!(
+            "Firewall {} is already attached to the requested droplets",
+            firewall.name
+        );
+        return Ok(());
+    }
+    if !to_assign.is_empty() {
+        info!(
+            "Attaching firewall {} to droplets {:?}",
+            firewall.name, to_assign
+        );
+        fw_client
+            .assign_droplets(&firewall.id, &to_assign, &dry_run)
+            .await?;
+    }
+    if !to_unassign.is_empty() {
+        info!(
+            "Detaching firewall {} from droplets {:?}",
+            firewall.name, to_unassign
+        );
+        fw_client
+            .unassign_droplets(&firewall.id, &to_unassign, &dry_run)
+            .await?;
+    }
+    Ok(())
+}
+
+/// Print the entries of the `--history-file`, oldest first.
+pub fn print_history(history_args: HistoryArgs) -> Result<(), Error> {
+    let entries = history::load(&history_args.history_file).map_err(|e| {
+        error!(
+            "Unable to read history file {}: {}",
+            history_args.history_file.display(),
+            e
+        );
+        Error::HistoryFile()
+    })?;
+    let skip = history_args
+        .limit
+        .map_or(0, |limit| entries.len().saturating_sub(limit));
+    for entry in &entries[skip..] {
+        if history_args.json {
+            println!("{}", serde_json::to_string(entry).unwrap());
+        } else {
+            println!("{}", entry.describe());
+        }
+    }
+    Ok(())
+}
+
+/// Print every droplet of the account with its public addresses, region, and tags.
+pub async fn print_droplets(list_args: DropletListArgs) -> Result<(), Error> {
+    let client = digitalocean::DigitalOceanClient::new(
+        list_args.token,
+        list_args.max_pages,
+        list_args.per_page,
+        list_args.page_concurrency,
+        list_args.retry,
+        &list_args.http,
+    );
+    let droplets = client
+        .droplet
+        .get_droplets()
+        .await
+        .inspect_err(|e| error!("Encountered error while listing droplets: {}", e))?;
+
+    let rows: Vec<droplet_list::DropletRow> = droplets.iter().map(Into::into).collect();
+    if list_args.json {
+        println!("{}", serde_json::to_string(&rows).unwrap());
+    } else {
+        print!("{}", droplet_list::format_table(&rows));
+    }
+    Ok(())
+}
+
+/// Print the records of a domain that pass the filters, as a table or as JSON.
+pub async fn print_records(list_args: DnsListArgs) -> Result<(), Error> {
+    let client = digitalocean::DigitalOceanClient::new(
+        list_args.token,
+        list_args.max_pages,
+        list_args.per_page,
+        list_args.page_concurrency,
+        list_args.retry,
+        &list_args.http,
+    );
+    // a name is looked up through the API's filter, so that large zones aren't listed in full
+    let records = match &list_args.name {
+        Some(name) => {
+            client
+                .dns
+                .get_records_by_name(&list_args.domain, name, list_args.rtype.as_deref())
+                .await
+        }
+        None => client.dns.get_all_records(&list_args.domain).await,
+    }
+    .inspect_err(|e| error!("Encountered error while listing DNS records: {}", e))?;
+
+    let rows: Vec<record_list::RecordRow> = records
+        .iter()
+        .filter(|record| {
+            record_list::matches_filters(
+                record,
+                list_args.rtype.as_deref(),
+                list_args.name.as_deref(),
+            )
+        })
+        .map(Into::into)
+        .collect();
+    if list_args.json {
+        println!("{}", serde_json::to_string(&rows).unwrap());
+    } else {
+        print!("{}", record_list::format_table(&rows));
+    }
+    Ok(())
+}
+
+/// Print a config file with targets describing the current records of the domains and the selected
+/// rules of the firewalls, along with the settings of the invocation.  The addresses published to
+/// the firewalls according to the state file are left out of their targets.
+pub async fn print_imported_config(import_args: ConfigImportArgs) -> Result<(), Error> {
+    let client = digitalocean::DigitalOceanClient::new(
+        import_args.token,
+        import_args.max_pages,
+        import_args.per_page,
+        import_args.page_concurrency,
+        import_args.retry,
+        &import_args.http,
+    );
+    let mut config = import_args.config;
+    let state = match config.state_file {
+        Some(ref path) => State::load(path).map_err(|e| {
+            error!("Unable to read state file {}: {}", path.display(), e);
+            Error::StateFile()
+        })?,
+        None => State::default(),
+    };
+    for domain in &import_args.domains {
+        let records = client
+            .dns
+            .get_all_records(domain)
+            .await
+            .inspect_err(|e| error!("Encountered error while listing DNS records: {}", e))?;
+        config.targets.extend(config_import::record_targets(
+            domain,
+            &records,
+            &import_args.dynamic,
+        ));
+    }
+    for name in &import_args.firewalls {
+        let firewall = client
+            .firewall
+            .get_firewall(name.clone())
+            .await?
+            .ok_or(Error::FirewallNotFound())
+            .inspect_err(|e| error!("Encountered error while fetching firewall {}: {}", name, e))?;
+        config.targets.extend(config_import::firewall_targets(
+            &firewall,
+            &import_args.ports,
+            &config_import::published_addresses(&state, name),
+        ));
+    }
+    print!("{}", config::starter(&config, &import_args.defaults));
+    Ok(())
+}
+
+/// Check the API token and print the account it belongs to.
+pub async fn print_account(check_args: CheckArgs) -> Result<(), Error> {
+    let client = digitalocean::DigitalOceanClient::new(
+        check_args.token,
+        None,
+        DEFAULT_PER_PAGE,
+        1,
+        check_args.retry,
+        &check_args.http,
+    );
+    let account = check_account(client.account.as_ref())
+        .await
+        .inspect_err(|e| error!("Unable to check the API token: {}", e))?;
+
+    if check_args.json {
+        println!("{}", serde_json::to_string(&account).unwrap());
+    } else {
+        println!("email:  {}", account.email);
+        println!("status: {}", account.status);
+    }
+    Ok(())
+}
+
+/// Check every dependency of an update without changing anything and print a pass/fail report,
+/// returning whether no check failed.  The token of each job of the config file is checked along
+/// with its targets.
+pub async fn print_diagnoses(doctor_args: DoctorArgs) -> bool {
+    let args = &doctor_args.update;
+    let every_target = cli::Args {
+        targets: (args.targets.iter())
+            .chain(args.jobs.iter().flat_map(|job| job.targets.iter()))
+            .cloned()
+            .collect(),
+        ..args.clone()
+    };
+    let (detection, ips) = doctor::diagnose_detection(every_target.detect_ips().await);
+    let mut diagnoses = vec![detection];
+    // the global token is only checked if it is used
+    if !args.targets.is_empty()
+        || args.jobs.is_empty()
+        || args.jobs.iter().any(|job| job.token.is_none())
+    {
+        diagnoses.extend(diagnose_targets(args, ips.as_deref()).await);
+    }
+    for job in &args.jobs {
+        let job_diagnoses = diagnose_targets(&args.for_job(job), ips.as_deref()).await;
+        diagnoses.extend(doctor::for_job(&job.name, job_diagnoses));
+    }
+
+    if doctor_args.json {
+        println!("{}", serde_json::to_string(&diagnoses).unwrap());
+    } else {
+        print!("{}", doctor::format_report(&diagnoses));
+    }
+    diagnoses
+        .iter()
+        .all(|diagnosis| diagnosis.outcome != doctor::Outcome::Fail)
+}
+
+/// Check the API token of the arguments and their targets
+async fn diagnose_targets(args: &cli::Args, ips: Option<&[IpAddr]>) -> Vec<doctor::Diagnosis> {
+    let client = digitalocean::DigitalOceanClient::new(
+        args.api_token().clone(),
+        args.max_pages,
+        args.per_page,
+        args.page_concurrency,
+        args.retry,
+        &args.http,
+    );
+    let providers = DnsProviders::new(&client, args.cloudflare_token.clone(), &args.http);
+    doctor::diagnose_targets(&client, &providers, ips, &args.targets).await
+}
+
+/// Check the API token before a run starts, so that a token that is not accepted fails the run
+/// before anything is detected or changed.
+pub async fn verify_account(args: &cli::Args) -> Result<(), Error> {
+    let client = digitalocean::DigitalOceanClient::new(
+        args.api_token().clone(),
+        None,
+        DEFAULT_PER_PAGE,
+        1,
+        args.retry,
+        &args.http,
+    );
+    let account = check_account(client.account.as_ref())
+        .await
+        .inspect_err(|e| error!("Unable to check the API token: {}", e))?;
+    info!("Using the API token of account {}", account.email);
+    Ok(())
+}
+
+/// Fetch the account the API token belongs to, failing if the account is locked and warning if
+/// DigitalOcean reports a problem with it.
+async fn check_account(account_client: &dyn DigitalOceanAccountClient) -> Result<Account, Error> {
+    let account = account_client.get_account().await?;
+    if account.is_locked() {
+        return Err(Error::AccountLocked {
+            email: account.email,
+            message: account.status_message.trim().to_string(),
+        });
+    }
+    if account.status != "active" {
+        warn!(
+            "Account {} has status {}: {}",
+            account.email,
+            account.status,
+            account.status_message.trim()
+        );
+    }
+    Ok(account)
+}
+
+/// Write the detected IP address to the file requested with `--ip-out`, if any, returning whether
+/// the write succeeded.
+pub fn write_ip_out(path: Option<&Path>, ip: &IpAddr) -> bool {
+    match path {
+        Some(path) => ip_retriever::write_ip_file(path, ip)
+            .inspect(|_| info!("Wrote IP address to {}", path.display()))
+            .inspect_err(|e| error!("Unable to write IP address to {}: {}", path.display(), e))
+            .is_ok(),
+        None => true,
+    }
+}
+
+/// Exit code used when the run failed in a way that is not expected to resolve itself.
+pub const EXIT_FATAL: i32 = 1;
+/// Exit code used by `--check` when the resources do not match the IP address.
+pub const EXIT_DRIFT: i32 = 2;
+/// Exit code used with `--detailed-exitcode` when the run changed at least one resource.  It
+/// matches `EXIT_DRIFT`, so that 2 always means the resources did not already hold the address;
+/// a run that changed nothing exits with 0.
+pub const EXIT_CHANGED: i32 = 2;
+/// Exit code used when the run failed due to a transient condition and can be retried later
+/// (EX_TEMPFAIL from sysexits.h).
+pub const EXIT_RETRIABLE: i32 = 75;
+/// Exit code used when another run still held the `--lock-file` after waiting for it, so this run
+/// changed nothing.
+pub const EXIT_LOCKED: i32 = 3;
+
+/// The exit code the binary reports for a failed run
+pub fn exit_code(e: &Error) -> i32 {
+    if matches!(e, Error::Drift(_)) {
+        EXIT_DRIFT
+    } else if matches!(e, Error::Locked(_)) {
+        EXIT_LOCKED
+    } else if e.is_retriable() {
+        EXIT_RETRIABLE
+    } else {
+        EXIT_FATAL
+    }
+}
+
+/// The address(es) a DNS record should point at: the public address(es) of the droplet named with
+/// `--from-droplet` or of the load balancer named with `--from-load-balancer`, or else the detected
+/// address(es) of this machine
+async fn source_ips(
+    client: &DigitalOceanClient,
+    dns_args: &DnsArgs,
+    ips: &[IpAddr],
+) -> Result<Vec<IpAddr>, Error> {
+    match (&dns_args.from_droplet, &dns_args.from_load_balancer) {
+        (Some(name), _) => droplet_ips(client.droplet.clone(), name, dns_args).await,
+        (None, Some(name)) => load_balancer_ips(client.load_balancer.clone(), name, dns_args).await,
+        (None, None) => Ok(ips.to_vec()),
+    }
+}
+
+/// The address of a load balancer, if the record can hold it.  The address is empty until the
+/// load balancer has been provisioned.
+async fn load_balancer_ips(
+    lb_client: Rc<dyn DigitalOceanLoadbalancerClient>,
+    name: &str,
+    dns_args: &DnsArgs,
+) -> Result<Vec<IpAddr>, Error> {
+    let load_balancer = lb_client
+        .get_load_balancers()
+        .await?
+        .into_iter()
+        .find(|lb| lb.name == name)
+        .ok_or_else(|| Error::ObjectNotFound {
+            kind: "load balancer",
+            name: name.to_string(),
+        })?;
+    let ips = match load_balancer.ip.as_str() {
+        "" => vec![],
+        ip => vec![ip.parse()?],
+    };
+    record_ips(ips, dns_args, &format!("load balancer {name}"))
+}
+
+/// The public address(es) of a droplet that the record can hold
+async fn droplet_ips(
+    droplet_client: Rc<dyn DigitalOceanDropletClient>,
+    name: &str,
+    dns_args: &DnsArgs,
+) -> Result<Vec<IpAddr>, Error> {
+    let droplet = droplet_client
+        .get_droplets()
+        .await?
+        .into_iter()
+        .find(|d| d.name == name)
+        .ok_or(Error::DropletNotFound())?;
+    let ips = [droplet.public_ipv4(), droplet.public_ipv6()]
+        .into_iter()
+        .flatten()
+        .map(|ip| ip.parse())
+        .collect::<Result<Vec<IpAddr>, _>>()?;
+    record_ips(ips, dns_args, &format!("droplet {name}"))
+}
+
+/// Keep only the addresses of the families the record holds, failing if none are left
+fn record_ips(ips: Vec<IpAddr>, dns_args: &DnsArgs, source: &str) -> Result<Vec<IpAddr>, Error> {
+    let ips: Vec<IpAddr> = ips
+        .into_iter()
+        .filter(|ip| dns_args.dual_stack || ip.is_ipv6() == (dns_args.rtype == "AAAA"))
+        .collect();
+    if ips.is_empty() {
+        Err(Error::NoPublicAddress(format!(
+            "{} has no public address for {} records",
+            source, dns_args.rtype
+        )))
+    } else {
+        Ok(ips)
+    }
+}
+
+/// The record type(s) to update and the value each should hold: the data given for the record, or
+/// else the detected IP address of the record's family.  In dual-stack mode there is an A and an
+/// AAAA record for every detected address family.
+fn dns_values(dns_args: &DnsArgs, ips: &[IpAddr]) -> Result<Vec<(String, RecordValue)>, Error> {
+    Ok(match dns_args.data {
+        Some(ref data) => vec![(
+            dns_args.rtype.clone(),
+            RecordValue {
+                data: data.clone(),
+                priority: dns_args.priority,
+                port: dns_args.port,
+                weight: dns_args.weight,
+            },
+        )],
+        None if dns_args.dual_stack => ips
+            .iter()
+            .map(|ip| {
+                let rtype = if ip.is_ipv6() { "AAAA" } else { "A" };
+                (rtype.to_string(), (*ip).into())
+            })
+            .collect(),
+        None => {
+            // dual-stack detection for another target may have found addresses of both families,
+            // or only addresses of the other family
+            let ip = *ips
+                .iter()
+                .find(|ip| ip.is_ipv6() == (dns_args.rtype == "AAAA"))
+                .or(ips.first())
+                .ok_or_else(|| Error::NoPublicAddress("no IP address was detected".to_string()))?;
+            dns_args.validate_rtype(&ip)?;
+            vec![(dns_args.rtype.clone(), ip.into())]
+        }
+    })
+}
+
+/// The record name and domain of each record to update: the one named by the arguments, or with
+/// `--all-domains` the record of that name in every domain that has one of the requested types
+async fn resolve_records(
+    client: Rc<dyn DigitalOceanDnsClient>,
+    dns_args: &DnsArgs,
+    values: &[(String, RecordValue)],
+) -> Result<Vec<(String, String)>, Error> {
+    if !dns_args.all_domains {
+        return Ok(vec![
+            resolve_record_name(
+                client,
+                dns_args.record.clone(),
+                dns_args.domain.clone(),
+                dns_args.fqdn,
+            )
+            .await?,
+        ]);
+    }
+
+    let mut records = Vec::new();
+    for domain in client.get_domains().await? {
+        for (rtype, _) in values {
+            if client
+                .get_record(&domain.name, &dns_args.record, rtype)
+                .await?
+                .is_some()
+            {
+                records.push((dns_args.record.clone(), domain.name));
+                break;
+            }
+        }
+    }
+    if records.is_empty() {
+        return Err(Error::RecordNotFound(format!(
+            "{} ({}) in any domain",
+            dns_args.record,
+            values
+                .iter()
+                .map(|(rtype, _)| rtype.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        )));
+    }
+    info!(
+        "Found record {} in domains {}",
+        dns_args.record,
+        records
+            .iter()
+            .map(|(_, domain)| domain.as_str())
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+    Ok(records)
+}
+
+/// Determine the record name and domain to update.  When a domain is given, `record` is taken
+/// as relative to it unless it ends in a dot or `fqdn` is set.  When no domain is given, `record`
+/// is treated as a fully qualified name and split at the longest domain in the account that
+/// contains it.
+async fn resolve_record_name(
+    client: Rc<dyn DigitalOceanDnsClient>,
+    record: String,
+    domain: Option<String>,
+    fqdn: bool,
+) -> Result<(String, String), Error> {
+    match domain {
+        Some(domain) => match relative_record_name(&record, &domain, fqdn) {
+            Some(record_name) => Ok((record_name, domain)),
+            None => Err(Error::RecordOutsideDomain { record, domain }),
+        },
+        None => {
+            let domains = client
+                .get_domains()
+                .await?
+                .into_iter()
+                .map(|d| d.name)
+                .collect::<Vec<String>>();
+            let (record_name, domain) =
+                split_fqdn(&record, &domains).ok_or(Error::DomainNotFound())?;
+            info!(
+                "Resolved {} to record {} in domain {}",
+                record, record_name, domain
+            );
+            Ok((record_name, domain))
+        }
+    }
+}
+
+/// Split a fully qualified name into a record name and the longest of `domains` that contains it.
+/// The apex of a domain is returned as the record name `@`.
+fn split_fqdn(fqdn: &str, domains: &[String]) -> Option<(String, String)> {
+    let fqdn = fqdn.trim_end_matches('.').to_lowercase();
+    domains
+        .iter()
+        .filter_map(|domain| {
+            let lower_domain = domain.to_lowercase();
+            if fqdn == lower_domain {
+                Some(("@".to_string(), domain.clone()))
+            } else {
+                fqdn.strip_suffix(lower_domain.as_str())
+                    .and_then(|r| r.strip_suffix('.'))
+                    .filter(|r| !r.is_empty())
+                    .map(|r| (r.to_string(), domain.clone()))
+            }
+        })
+        .max_by_key(|(_, domain)| domain.len())
+}
+
+/// The record name followed by the names of its companion records, which are kept pointing at the
+/// same address.  The `www` companion of the apex is `www`, and of any other record is
+/// `www.<record>`.
+fn with_aliases(record: String, aliases: &[String], with_www: bool) -> Vec<String> {
+    let www = if record == "@" {
+        "www".to_string()
+    } else {
+        format!("www.{record}")
+    };
+    let mut records = vec![record];
+    for alias in aliases.iter().cloned().chain(with_www.then_some(www)) {
+        if !records.contains(&alias) {
+            records.push(alias);
+        }
+    }
+    records
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn run_dns(
+    client: Rc<dyn DigitalOceanDnsClient>,
+    domain: String,
+    record_name: String,
+    rtype: String,
+    value: RecordValue,
+    ttl: u16,
+    lower_ttl: Option<u16>,
+    force: bool,
+    create_if_missing: bool,
+    create_domain: bool,
+    verify: bool,
+    hooks: &Hooks,
+    shutdown: &AtomicBool,
+    stats: &RunStats,
+    dry_run: bool,
+) -> Result<DomainRecord, Error> {
+    let domain_created = match stats
+        .time("domain lookup", client.get_domain(&domain))
+        .await?
+    {
+        Some(_) => false,
+        None if create_domain => {
+            info!("Will create domain {}", domain);
+            stats
+                .time("mutation", client.create_domain(&domain, &dry_run))
+                .await?;
+            info!(
+                "Created domain {}; it resolves once its registrar delegates it to DigitalOcean",
+                domain
+            );
+            stats.record_change(None);
+            true
+        }
+        None => return Err(Error::DomainNotFound()),
+    };
+    // a domain that was just created has no records yet, and a dry run did not create it at all
+    let existing = if domain_created {
+        None
+    } else {
+        stats
+            .time(
+                "record lookup",
+                client.get_record(&domain, &record_name, &rtype),
+            )
+            .await?
+    };
+    match existing {
+        Some(record) => {
+            // a record already holding the value may still need its TTL brought in line
+            let ttl_only = value.matches(&record);
+            if ttl_only && record.ttl == ttl {
+                info!(
+                    "Record {}.{} ({}) already set to {}",
+                    record_name, domain, rtype, value
+                );
+                Ok(record)
+            } else {
+                if ttl_only {
+                    info!(
+                        "Will update TTL of record {}.{} ({}) from {} to {}",
+                        record_name, domain, rtype, record.ttl, ttl
+                    );
+                } else {
+                    info!(
+                        "Will update record_name {}.{} ({}) to {}",
+                        record_name, domain, rtype, value
+                    );
+                }
+                ensure_not_managed_externally(&client, &domain, &record_name, &rtype, force)
+                    .await?;
+                // the hooks are told about changes of address, which a new TTL alone is not
+                let change = (!ttl_only).then(|| Change {
+                    kind: "dns",
+                    resource: format!("{}.{} ({})", record_name, domain, rtype),
+                    old_ip: record.data.clone(),
+                    new_ip: value.data.clone(),
+                });
+                if let Some(ref change) = change {
+                    stats
+                        .time("hooks", async { hooks.before(change, dry_run) })
+                        .await
+                        .map_err(Error::Hook)?;
+                }
+                let record = stats
+                    .time("mutation", async {
+                        match lower_ttl {
+                            Some(lower_ttl) if !ttl_only && record.ttl > lower_ttl => {
+                                ensure_record_unchanged(&client, &domain, &record).await?;
+                                info!(
+                                    "Lowering TTL of {}.{} ({}) from {} to {} ahead of the change",
+                                    record_name, domain, rtype, record.ttl, lower_ttl
+                                );
+                                client
+                                    .update_record_ttl(&domain, &record, &lower_ttl, &dry_run)
+                                    .await?;
+                                wait_for_ttl(record.ttl, dry_run, shutdown).await?;
+                                ensure_record_unchanged(&client, &domain, &record).await?;
+                                let record = client
+                                    .update_record(&domain, &record, &value, &lower_ttl, &dry_run)
+                                    .await?;
+                                info!(
+                                    "Restoring TTL of {}.{} ({}) to {}",
+                                    record_name, domain, rtype, ttl
+                                );
+                                Ok::<_, Error>(
+                                    client
+                                        .update_record_ttl(&domain, &record, &ttl, &dry_run)
+                                        .await?,
+                                )
+                            }
+                            _ => {
+                                ensure_record_unchanged(&client, &domain, &record).await?;
+                                Ok(client
+                                    .update_record(&domain, &record, &value, &ttl, &dry_run)
+                                    .await?)
+                            }
+                        }
+                    })
+                    .await?;
+                info!("Successfully updated record!");
+                stats.record_change(change.as_ref());
+                if verify && !dry_run {
+                    stats
+                        .time(
+                            "verification",
+                            verify_record(&client, &domain, &record, &value),
+                        )
+                        .await?;
+                }
+                if let Some(ref change) = change {
+                    stats
+                        .time("hooks", async { hooks.after(change, dry_run) })
+                        .await
+                        .map_err(Error::Hook)?;
+                }
+                Ok(record)
+            }
+        }
+        None if !create_if_missing => Err(Error::RecordNotFound(format!(
+            "{}.{} ({})",
+            record_name, domain, rtype
+        ))),
+        None => {
+            info!(
+                "Will create new record {}.{} ({}) -> {}",
+                record_name, domain, rtype, value
+            );
+            ensure_not_managed_externally(&client, &domain, &record_name, &rtype, force).await?;
+            let change = Change {
+                kind: "dns",
+                resource: format!("{}.{} ({})", record_name, domain, rtype),
+                old_ip: "".to_string(),
+                new_ip: value.data.clone(),
+            };
+            stats
+                .time("hooks", async { hooks.before(&change, dry_run) })
+                .await
+                .map_err(Error::Hook)?;
+            let record = stats
+                .time(
+                    "mutation",
+                    client.create_record(&domain, &record_name, &rtype, &value, &ttl, &dry_run),
+                )
+                .await?;
+            info!(
+                "Successfully created new record! ({}, TTL {}s)",
+                record.id, record.ttl
+            );
+            stats.record_change(Some(&change));
+            if verify && !dry_run {
+                stats
+                    .time(
+                        "verification",
+                        verify_record(&client, &domain, &record, &value),
+                    )
+                    .await?;
+            }
+            stats
+                .time("hooks", async { hooks.after(&change, dry_run) })
+                .await
+                .map_err(Error::Hook)?;
+            Ok(record)
+        }
+    }
+}
+
+/// Re-query a record after writing it and fail if any of its stored fields differ from what the
+/// write returned, or if it does not hold the requested value.
+async fn verify_record(
+    client: &Rc<dyn DigitalOceanDnsClient>,
+    domain: &str,
+    written: &DomainRecord,
+    value: &RecordValue,
+) -> Result<(), Error> {
+    let stored = client
+        .get_record(domain, &written.name, &written.typ)
+        .await?
+        .ok_or_else(|| {
+            Error::VerificationFailed(format!(
+                "record {}.{} ({}) not found after writing it",
+                written.name, domain, written.typ
+            ))
+        })?;
+
+    let mut mismatches = Vec::new();
+    if stored.id != written.id {
+        mismatches.push(format!("id: expected {}, got {}", written.id, stored.id));
+    }
+    if !value.matches(&stored) {
+        mismatches.push(format!("data: expected {}, got {}", value, stored.data));
+    }
+    if stored.ttl != written.ttl {
+        mismatches.push(format!("ttl: expected {}, got {}", written.ttl, stored.ttl));
+    }
+
+    if mismatches.is_empty() {
+        info!(
+            "Verified record {}.{} ({})",
+            written.name, domain, written.typ
+        );
+        Ok(())
+    } else {
+        Err(Error::VerificationFailed(format!(
+            "record {}.{} ({}) does not match what was written: {}",
+            written.name,
+            domain,
+            written.typ,
+            mismatches.join(", ")
+        )))
+    }
+}
+
+/// Verify that the DNS record or firewall rules already match the IP address without changing
+/// anything, returning whether they do.
+async fn check(
+    client: &DigitalOceanClient,
+    providers: &DnsProviders<'_>,
+    subcmd_args: SubcmdArgs,
+    ips: &[IpAddr],
+    firewall_ids: &FirewallIds,
+) -> Result<bool, Error> {
+    match subcmd_args {
+        SubcmdArgs::Dns(dns_args) => {
+            providers
+                .get(dns_args.provider)?
+                .check_dns(dns_args, ips)
+                .await
+        }
+        SubcmdArgs::Firewall(fw_args) => check_firewall(client, fw_args, ips, firewall_ids)
+            .await
+            .inspect_err(|e| error!("Encountered error while checking firewall: {}", e)),
+        SubcmdArgs::ReservedIp(rip_args) => {
+            check_reserved_ip(client.reserved_ip.clone(), client.droplet.clone(), rip_args)
+                .await
+                .inspect_err(|e| error!("Encountered error while checking reserved IP: {}", e))
+        }
+    }
+}
+
+/// Check whether a DNS record hosted by DigitalOcean and its aliases already hold the IP
+/// address(es), returning whether they do
+async fn check_dns_target(
+    client: &DigitalOceanClient,
+    dns_args: DnsArgs,
+    ips: &[IpAddr],
+) -> Result<bool, Error> {
+    async {
+        let ips = source_ips(client, &dns_args, ips).await?;
+        let values = dns_values(&dns_args, &ips)?;
+        let mut in_sync = true;
+        for (record, domain) in resolve_records(client.dns.clone(), &dns_args, &values).await? {
+            if dns_args.check_delegation {
+                delegation::warn_if_not_delegated(&domain).await;
+            }
+            for record in with_aliases(record, &dns_args.aliases, dns_args.with_www) {
+                for (rtype, value) in &values {
+                    in_sync &= check_dns(
+                        client.dns.clone(),
+                        domain.clone(),
+                        record.clone(),
+                        rtype.clone(),
+                        value.clone(),
+                        dns_args.ttl,
+                    )
+                    .await?;
+                }
+            }
+        }
+        Ok(in_sync)
+    }
+    .await
+    .inspect_err(|e| error!("Encountered error while checking DNS record: {}", e))
+}
+
+async fn check_dns(
+    client: Rc<dyn DigitalOceanDnsClient>,
+    domain: String,
+    record_name: String,
+    rtype: String,
+    value: RecordValue,
+    ttl: u16,
+) -> Result<bool, Error> {
+    client
+        .get_domain(&domain)
+        .await?
+        .ok_or(Error::DomainNotFound())?;
+    match client.get_record(&domain, &record_name, &rtype).await? {
+        Some(record) => {
+            if value.matches(&record) && record.ttl == ttl {
+                info!(
+                    "Record {}.{} ({}) matches {}",
+                    record_name, domain, rtype, value
+                );
+                Ok(true)
+            } else if value.matches(&record) {
+                warn!(
+                    "Record {}.{} ({}) has a TTL of {} but should have {}",
+                    record_name, domain, rtype, record.ttl, ttl
+                );
+                Ok(false)
+            } else {
+                warn!(
+                    "Record {}.{} ({}) is set to {} but should be {}",
+                    record_name, domain, rtype, record.data, value
+                );
+                Ok(false)
+            }
+        }
+        None => {
+            warn!(
+                "Record {}.{} ({}) does not exist but should be {}",
+                record_name, domain, rtype, value
+            );
+            Ok(false)
+        }
+    }
+}
+
+/// Contents of TXT records used by other tools to mark records they manage
+const OWNERSHIP_MARKERS: [(&str, &str); 2] = [
+    ("heritage=external-dns", "external-dns"),
+    ("managed-by=terraform", "Terraform"),
+];
+
+/// Fail if the record appears to be managed by other automation, unless forced, to avoid
+/// fighting with it over the record's value.
+async fn ensure_not_managed_externally(
+    client: &Rc<dyn DigitalOceanDnsClient>,
+    domain: &str,
+    record_name: &str,
+    rtype: &str,
+    force: bool,
+) -> Result<(), Error> {
+    let txt_records = client.get_records(domain, "TXT").await?;
+    match external_owner(&txt_records, record_name, rtype) {
+        Some(owner) if force => {
+            warn!(
+                "Record {}.{} ({}) appears to be managed by {}; modifying anyway",
+                record_name, domain, rtype, owner
+            );
+            Ok(())
+        }
+        Some(owner) => Err(Error::ManagedExternally(format!(
+            "record {}.{} ({}) appears to be managed by {}; use --force to modify it",
+            record_name, domain, rtype, owner
+        ))),
+        None => Ok(()),
+    }
+}
+
+/// Find the tool that owns a record according to the ownership TXT records in its domain.
+/// external-dns places its TXT record either at the same name as the record or at a name
+/// prefixed with the lowercase record type (e.g. `a-www`).
+fn external_owner(
+    txt_records: &[DomainRecord],
+    record_name: &str,
+    rtype: &str,
+) -> Option<&'static str> {
+    let prefixed_name = format!("{}-{}", rtype.to_lowercase(), record_name);
+    txt_records
+        .iter()
+        .filter(|r| {
+            r.name.eq_ignore_ascii_case(record_name) || r.name.eq_ignore_ascii_case(&prefixed_name)
+        })
+        .find_map(|r| {
+            OWNERSHIP_MARKERS
+                .iter()
+                .find(|(marker, _)| r.data.contains(marker))
+                .map(|(_, owner)| *owner)
+        })
+}
+
+/// Re-fetch a record immediately before modifying it and fail if it was changed or removed by
+/// someone else since it was read, rather than clobbering their edit.
+async fn ensure_record_unchanged(
+    client: &Rc<dyn DigitalOceanDnsClient>,
+    domain: &str,
+    record: &DomainRecord,
+) -> Result<(), Error> {
+    match client.get_record(domain, &record.name, &record.typ).await? {
+        Some(current) if current.id == record.id && current.data == record.data => Ok(()),
+        Some(current) => Err(Error::ConcurrentModification(format!(
+            "record {}.{} ({}) changed from {} to {} while updating",
+            record.name, domain, record.typ, record.data, current.data
+        ))),
+        None => Err(Error::ConcurrentModification(format!(
+            "record {}.{} ({}) was removed while updating",
+            record.name, domain, record.typ
+        ))),
+    }
+}
+
+/// The longest wait for the previous TTL of a record to expire, so that a record with a long TTL
+/// does not hold up the other targets for hours
+const MAX_TTL_WAIT: Duration = Duration::from_secs(300);
+
+/// Wait long enough for resolvers to have expired any copy of a record cached with the given TTL,
+/// up to [`MAX_TTL_WAIT`].  Fails if `shutdown` is set while waiting, leaving the record with its
+/// lowered TTL for the next run to update.
+async fn wait_for_ttl(ttl: u16, dry_run: bool, shutdown: &AtomicBool) -> Result<(), Error> {
+    let wait = Duration::from_secs(ttl.into()).min(MAX_TTL_WAIT);
+    if wait.as_secs() < ttl.into() {
+        warn!(
+            "Waiting only {}s for the previous TTL of {}s to expire",
+            wait.as_secs(),
+            ttl
+        );
+    }
+    if dry_run {
+        info!(
+            "DRY RUN: Waiting {}s for the previous TTL to expire",
+            wait.as_secs()
+        );
+        return Ok(());
+    }
+    info!("Waiting {}s for the previous TTL to expire", wait.as_secs());
+    let deadline = Instant::now() + wait;
+    loop {
+        if shutdown.load(Ordering::SeqCst) {
+            warn!("Stopped waiting for the previous TTL to expire to shut down");
+            return Err(Error::Interrupted());
+        }
+        let now = Instant::now();
+        if now >= deadline {
+            break;
+        }
+        tokio::time::sleep((deadline - now).min(Duration::from_millis(250))).await;
+    }
+    Ok(())
+}
+
+/// Update the firewall rules of each firewall named by the arguments
+#[allow(clippy::too_many_arguments)]
+async fn run_firewall(
+    client: &DigitalOceanClient,
+    fw_args: FirewallArgs,
+    ips: &[IpAddr],
+    firewall_ids: &RefCell<FirewallIds>,
+    verify: bool,
+    hooks: &Hooks,
+    stats: &RunStats,
+    dry_run: bool,
+) -> Result<(), Error> {
+    let firewalls = stats
+        .time(
+            "firewall lookup",
+            resolve_firewalls(client.firewall.clone(), fw_args),
+        )
+        .await?;
+    for fw_args in firewalls {
+        run_firewall_rules(
+            client,
+            fw_args,
+            ips,
+            firewall_ids,
+            verify,
+            hooks,
+            stats,
+            dry_run,
+        )
+        .await?;
+    }
+    Ok(())
+}
+
+/// The arguments for each firewall to update: the one named by the arguments, or with
+/// `--all-matching` every firewall whose name matches the glob pattern
+async fn resolve_firewalls(
+    client: Rc<dyn DigitalOceanFirewallClient>,
+    fw_args: FirewallArgs,
+) -> Result<Vec<FirewallArgs>, Error> {
+    if !fw_args.all_matching {
+        return Ok(vec![fw_args]);
+    }
+
+    let names: Vec<String> = client
+        .get_firewalls()
+        .await?
+        .into_iter()
+        .map(|firewall| firewall.name)
+        .filter(|name| glob_matches(&fw_args.name, name))
+        .collect();
+    if names.is_empty() {
+        return Err(Error::ObjectNotFound {
+            kind: "firewall",
+            name: fw_args.name,
+        });
+    }
+    info!(
+        "Found firewalls {} matching {}",
+        names.join(", "),
+        fw_args.name
+    );
+    Ok(names
+        .into_iter()
+        .map(|name| FirewallArgs {
+            name,
+            all_matching: false,
+            ..fw_args.clone()
+        })
+        .collect())
+}
+
+/// Whether `name` matches the glob `pattern`, where `*` matches any run of characters and `?`
+/// matches any single character
+fn glob_matches(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut p, mut n) = (0, 0);
+    // where to resume after the last `*` if the rest of the pattern stops matching
+    let mut backtrack = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, n));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match backtrack {
+                Some((star, matched)) => {
+                    p = star + 1;
+                    n = matched + 1;
+                    backtrack = Some((star, matched + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Update the firewall rule for each of the requested ports and protocols, then optionally prune
+/// dangling references from the firewall.
+#[allow(clippy::too_many_arguments)]
+async fn run_firewall_rules(
+    client: &DigitalOceanClient,
+    fw_args: FirewallArgs,
+    ips: &[IpAddr],
+    firewall_ids: &RefCell<FirewallIds>,
+    verify: bool,
+    hooks: &Hooks,
+    stats: &RunStats,
+    dry_run: bool,
+) -> Result<(), Error> {
+    // every rule is looked up before any is changed, so that all of them are replaced together
+    let tracked_id = firewall_ids.borrow().get(&fw_args.name).map(str::to_string);
+    let (current_firewall, targets) = stats
+        .time("firewall lookup", async {
+            tokio::try_join!(
+                find_firewall(client, &fw_args.name, tracked_id.as_deref()),
+                resolve_rule_targets(client, &fw_args)
+            )
+        })
+        .await
+        .inspect_err(|e| error!("Encountered error while constructing firewall rules: {}", e))?;
+    firewall_ids
+        .borrow_mut()
+        .track(&current_firewall.name, &current_firewall.id);
+    let mut inbound_rules = Vec::new();
+    let mut outbound_rules = Vec::new();
+    let mut changes = Vec::new();
+    for port in fw_args.ports() {
+        for protocol in &fw_args.protocols {
+            let (inbound_rule, outbound_rule) = stats
+                .time(
+                    "firewall lookup",
+                    build_firewall_args(
+                        client,
+                        &fw_args,
+                        &current_firewall,
+                        &targets,
+                        &port,
+                        protocol,
+                        ips,
+                    ),
+                )
+                .await
+                .inspect_err(|e| {
+                    error!("Encountered error while constructing firewall rules: {}", e)
+                })?;
+            let already_replaced = inbound_rule
+                .as_ref()
+                .is_some_and(|(rule, _)| inbound_rules.iter().any(|(r, _)| r == rule))
+                || outbound_rule
+                    .as_ref()
+                    .is_some_and(|(rule, _)| outbound_rules.iter().any(|(r, _)| r == rule));
+            match firewall_change(&current_firewall, &inbound_rule, &outbound_rule) {
+                Some(change) if !already_replaced => {
+                    changes.push(change);
+                    inbound_rules.extend(inbound_rule);
+                    outbound_rules.extend(outbound_rule);
+                }
+                Some(_) => (),
+                None => info!(
+                    "Firewall {} {:?} rule for port {} ({}) already up to date",
+                    current_firewall.name, fw_args.direction, port, protocol
+                ),
+            }
+        }
+    }
+
+    let firewall = if changes.is_empty() {
+        current_firewall
+    } else {
+        for change in &changes {
+            stats
+                .time("hooks", async { hooks.before(change, dry_run) })
+                .await
+                .map_err(Error::Hook)?;
+        }
+        let requested_inbound_rules: Vec<FirewallInboundRule> =
+            inbound_rules.iter().map(|(_, new)| new.clone()).collect();
+        let requested_outbound_rules: Vec<FirewallOutboundRule> =
+            outbound_rules.iter().map(|(_, new)| new.clone()).collect();
+        let targets: Vec<&FirewallRuleTarget> = inbound_rules
+            .iter()
+            .flat_map(|(old, new)| [&old.sources, &new.sources])
+            .chain(
+                outbound_rules
+                    .iter()
+                    .flat_map(|(old, new)| [&old.destinations, &new.destinations]),
+            )
+            .collect();
+        let names = ResourceNames::lookup(
+            &client.droplet,
+            &client.kubernetes,
+            &client.load_balancer,
+            &targets,
+        )
+        .await;
+        let updated_firewall = stats
+            .time(
+                "mutation",
+                replace_firewall_rules(
+                    client.firewall.clone(),
+                    current_firewall,
+                    inbound_rules,
+                    outbound_rules,
+                    &names,
+                    fw_args.atomic,
+                    verify || !fw_args.no_verify,
+                    dry_run,
+                ),
+            )
+            .await
+            .inspect_err(|e| error!("Encountered error while updating firewall: {}", e))?;
+        for change in &changes {
+            stats.record_change(Some(change));
+        }
+        if verify && !dry_run {
+            stats
+                .time("verification", async {
+                    for rule in &requested_inbound_rules {
+                        verify_firewall_rules(&updated_firewall, Some(rule), None)?;
+                    }
+                    for rule in &requested_outbound_rules {
+                        verify_firewall_rules(&updated_firewall, None, Some(rule))?;
+                    }
+                    Ok(())
+                })
+                .await
+                .inspect_err(|e: &Error| {
+                    error!("Encountered error while verifying firewall: {}", e)
+                })?;
+        }
+        for change in &changes {
+            stats
+                .time("hooks", async { hooks.after(change, dry_run) })
+                .await
+                .map_err(Error::Hook)?;
+        }
+        updated_firewall
+    };
+
+    if fw_args.prune_dangling {
+        let mut firewall = firewall;
+        if fw_args.no_verify {
+            // the firewall returned without verification predates the update, so refresh it
+            // before deciding which references to prune
+            firewall = client
+                .firewall
+                .get_firewall_by_id(firewall.id.as_str())
+                .await?
+                .unwrap_or(firewall);
+        }
+        stats
+            .time(
+                "pruning",
+                prune_dangling_references(
+                    client.firewall.clone(),
+                    client.droplet.clone(),
+                    client.kubernetes.clone(),
+                    client.load_balancer.clone(),
+                    firewall,
+                    dry_run,
+                ),
+            )
+            .await
+            .inspect_err(|e| error!("Encountered error while pruning firewall: {}", e))?;
+    }
+
+    Ok(())
+}
+
+/// Describe the change a rule replacement makes for the hooks, or `None` if the replacement leaves
+/// the rule's targets as they are.
+fn firewall_change(
+    firewall: &Firewall,
+    inbound_rule: &Option<(FirewallInboundRule, FirewallInboundRule)>,
+    outbound_rule: &Option<(FirewallOutboundRule, FirewallOutboundRule)>,
+) -> Option<Change> {
+    let (direction, protocol, ports, old, new) = match (inbound_rule, outbound_rule) {
+        (Some((rule, new_rule)), _) => (
+            "inbound",
+            &rule.protocol,
+            &rule.ports,
+            &rule.sources,
+            &new_rule.sources,
+        ),
+        (_, Some((rule, new_rule))) => (
+            "outbound",
+            &rule.protocol,
+            &rule.ports,
+            &rule.destinations,
+            &new_rule.destinations,
+        ),
+        (None, None) => return None,
+    };
+    if targets_match(old, new) {
+        return None;
+    }
+    Some(Change {
+        kind: "firewall",
+        resource: format!(
+            "{} {} rule for port {} ({})",
+            firewall.name, direction, ports, protocol
+        ),
+        old_ip: old
+            .addresses
+            .iter()
+            .flatten()
+            .cloned()
+            .collect::<Vec<_>>()
+            .join(","),
+        new_ip: new
+            .addresses
+            .iter()
+            .flatten()
+            .cloned()
+            .collect::<Vec<_>>()
+            .join(","),
+    })
+}
+
+async fn check_firewall(
+    client: &DigitalOceanClient,
+    fw_args: FirewallArgs,
+    ips: &[IpAddr],
+    firewall_ids: &FirewallIds,
+) -> Result<bool, Error> {
+    let mut in_sync = true;
+    for fw_args in resolve_firewalls(client.firewall.clone(), fw_args).await? {
+        in_sync &= check_firewall_rules(client, fw_args, ips, firewall_ids).await?;
+    }
+    Ok(in_sync)
+}
+
+async fn check_firewall_rules(
+    client: &DigitalOceanClient,
+    fw_args: FirewallArgs,
+    ips: &[IpAddr],
+    firewall_ids: &FirewallIds,
+) -> Result<bool, Error> {
+    let firewall = find_firewall(client, &fw_args.name, firewall_ids.get(&fw_args.name)).await?;
+    let targets = resolve_rule_targets(client, &fw_args).await?;
+    let mut in_sync = true;
+    for (port, protocol) in fw_args
+        .ports()
+        .into_iter()
+        .flat_map(|port| fw_args.protocols.iter().map(move |p| (port.clone(), p)))
+    {
+        let (inbound_rule, outbound_rule) =
+            build_firewall_args(client, &fw_args, &firewall, &targets, &port, protocol, ips)
+                .await?;
+        let rule_in_sync = match (inbound_rule, outbound_rule) {
+            (Some((rule, new_rule)), _) => targets_match(&rule.sources, &new_rule.sources),
+            (_, Some((rule, new_rule))) => {
+                targets_match(&rule.destinations, &new_rule.destinations)
+            }
+            (None, None) => true,
+        };
+        if rule_in_sync {
+            info!(
+                "Firewall {} rule for port {} ({}) matches",
+                firewall.name, port, protocol
+            );
+        } else {
+            warn!(
+                "Firewall {} rule for port {} ({}) does not match the expected targets",
+                firewall.name, port, protocol
+            );
+            in_sync = false;
+        }
+    }
+    Ok(in_sync)
+}
+
+/// Fail unless the re-fetched firewall contains a rule matching each requested rule in protocol,
+/// ports, and every kind of target.
+fn verify_firewall_rules(
+    firewall: &Firewall,
+    inbound_rule: Option<&FirewallInboundRule>,
+    outbound_rule: Option<&FirewallOutboundRule>,
+) -> Result<(), Error> {
+    if let Some(expected) = inbound_rule {
+        let found = firewall.inbound_rules.iter().flatten().any(|r| {
+            r.protocol == expected.protocol
+                && r.ports == expected.ports
+                && targets_match(&r.sources, &expected.sources)
+        });
+        if !found {
+            return Err(Error::VerificationFailed(format!(
+                "firewall {} has no inbound rule matching {:?}",
+                firewall.name, expected
+            )));
+        }
+    }
+    if let Some(expected) = outbound_rule {
+        let found = firewall.outbound_rules.iter().flatten().any(|r| {
+            r.protocol == expected.protocol
+                && r.ports == expected.ports
+                && targets_match(&r.destinations, &expected.destinations)
+        });
+        if !found {
+            return Err(Error::VerificationFailed(format!(
+                "firewall {} has no outbound rule matching {:?}",
+                firewall.name, expected
+            )));
+        }
+    }
+    info!("Verified rules of firewall {}", firewall.name);
+    Ok(())
+}
+
+/// Whether two rule targets refer to the same addresses and resources, ignoring ordering and
+/// treating missing lists as empty.
+fn targets_match(a: &FirewallRuleTarget, b: &FirewallRuleTarget) -> bool {
+    fn as_set<T: Eq + Hash + Clone>(values: &Option<Vec<T>>) -> HashSet<T> {
+        values.iter().flatten().cloned().collect()
+    }
+
+    as_set(&a.addresses) == as_set(&b.addresses)
+        && as_set(&a.droplet_ids) == as_set(&b.droplet_ids)
+        && as_set(&a.kubernetes_ids) == as_set(&b.kubernetes_ids)
+        && as_set(&a.load_balancer_uids) == as_set(&b.load_balancer_uids)
+        && as_set(&a.tags) == as_set(&b.tags)
+}
+
+/// The firewall address to publish for a detected IP address, widening IPv6 addresses to their
+/// network prefix if requested.
+fn firewall_address(ip: &IpAddr, ipv6_prefix: Option<u8>) -> String {
+    match (ip, ipv6_prefix) {
+        (IpAddr::V6(v6), Some(len)) => {
+            let mask = u128::MAX << (128 - u32::from(len));
+            format!("{}/{}", Ipv6Addr::from(u128::from(*v6) & mask), len)
+        }
+        _ => ip.to_string(),
+    }
+}
+
+/// The addresses and resource IDs that a firewall target's rules should hold besides the detected
+/// address(es), resolved from the names given once for all of the target's ports and protocols
+#[derive(Debug, Default)]
+struct RuleTargets {
+    addresses: Option<Vec<String>>,
+    droplet_ids: Option<Vec<u32>>,
+    kubernetes_cluster_ids: Option<Vec<String>>,
+    load_balancer_ids: Option<Vec<String>>,
+}
+
+/// The firewall a target names, failing if it does not exist.  A firewall tracked by its ID is
+/// looked up by that ID instead, failing if it was deleted or no longer has the name, rather than
+/// matching another firewall that has taken the name since.
+async fn find_firewall(
+    client: &DigitalOceanClient,
+    name: &str,
+    tracked_id: Option<&str>,
+) -> Result<Firewall, Error> {
+    let Some(id) = tracked_id else {
+        return client
+            .firewall
+            .get_firewall(name.to_string())
+            .await?
+            .ok_or(Error::FirewallNotFound());
+    };
+    match client.firewall.get_firewall_by_id(id).await? {
+        Some(firewall) if firewall.name == name => Ok(firewall),
+        Some(firewall) => Err(Error::TrackedFirewall(format!(
+            "tracked firewall {name} (ID {id}) was renamed to {}; update the name of the target \
+                to keep updating it",
+            firewall.name
+        ))),
+        None => Err(Error::TrackedFirewall(format!(
+            "tracked firewall {name} (ID {id}) is missing; if it was replaced, remove it from the \
+                firewalls of the state file to match a firewall by name again"
+        ))),
+    }
+}
+
+/// Resolve the VPCs, droplets, droplet tags, Kubernetes clusters, and load balancers a target
+/// names to the addresses and IDs its rules should hold, limited to the target's project if it
+/// gives one
+async fn resolve_rule_targets(
+    client: &DigitalOceanClient,
+    fw_args: &FirewallArgs,
+) -> Result<RuleTargets, Error> {
+    let scope = match fw_args.project {
+        Some(ref project)
+            if fw_args.droplets.is_some()
+                || fw_args.droplet_tags.is_some()
+                || fw_args.kubernetes_clusters.is_some()
+                || fw_args.load_balancers.is_some() =>
+        {
+            Some(project_resources(&client.project, project).await?)
+        }
+        _ => None,
+    };
+    let in_project = |urn: String| scope.as_ref().is_none_or(|s| s.contains(&urn));
+    let (vpc_ranges, droplet_ids, tagged_droplet_ids, kubernetes_cluster_ids, load_balancer_ids) =
+        tokio::try_join!(
+            names_to_ids(
+                "VPC",
+                || client.vpc.get_vpcs(),
+                fw_args.vpcs.clone(),
+                |_| true,
+                |v| v.name.clone(),
+                |v| v.ip_range.clone(),
+            ),
+            names_to_ids(
+                "droplet",
+                || client.droplet.get_droplet_summaries(),
+                fw_args.droplets.clone(),
+                |d| in_project(ProjectResource::droplet_urn(d.id)),
+                |d| d.name.clone(),
+                |d| d.id,
+            ),
+            tags_to_droplet_ids(
+                &client.tags,
+                &client.droplet,
+                fw_args.droplet_tags.clone(),
+                |d| in_project(ProjectResource::droplet_urn(d.id))
+            ),
+            names_to_ids(
+                "Kubernetes cluster",
+                || client.kubernetes.get_kubernetes_cluster_summaries(),
+                fw_args.kubernetes_clusters.clone(),
+                |d| in_project(ProjectResource::kubernetes_urn(&d.id)),
+                |d| d.name.clone(),
+                |d| d.id.clone(),
+            ),
+            names_to_ids(
+                "load balancer",
+                || client.load_balancer.get_load_balancer_summaries(),
+                fw_args.load_balancers.clone(),
+                |d| in_project(ProjectResource::load_balancer_urn(&d.id)),
+                |d| d.name.clone(),
+                |d| d.id.clone(),
+            ),
+        )?;
+    Ok(RuleTargets {
+        addresses: merge_ids(fw_args.addresses.clone(), vpc_ranges),
+        droplet_ids: merge_ids(droplet_ids, tagged_droplet_ids),
+        kubernetes_cluster_ids,
+        load_balancer_ids,
+    })
+}
+
+/// Find the firewall's rule for the port and protocol in the target's direction, and build the
+/// rule that replaces it, returning both.  Only the rule for the target's direction is returned.
+#[allow(clippy::type_complexity)]
+async fn build_firewall_args(
+    client: &DigitalOceanClient,
+    fw_args: &FirewallArgs,
+    firewall: &Firewall,
+    targets: &RuleTargets,
+    port: &str,
+    protocol: &str,
+    ips: &[IpAddr],
+) -> Result<
+    (
+        Option<(FirewallInboundRule, FirewallInboundRule)>,
+        Option<(FirewallOutboundRule, FirewallOutboundRule)>,
+    ),
+    Error,
+> {
+    if port == ALL_PORTS {
+        warn!(
+            "Updating the {} rule covering ALL ports; the new sources/destinations will be able \
+            to reach every port",
+            protocol
+        );
+    }
+
+    let rule_not_found = || Error::RuleNotFound {
+        firewall: fw_args.name.clone(),
+        direction: fw_args.direction,
+        port: port.to_string(),
+        protocol: protocol.to_string(),
+    };
+    let new_target = |current: &FirewallRuleTarget| FirewallRuleTarget {
+        addresses: Some(rule_addresses(
+            &current.addresses,
+            &targets.addresses,
+            ips,
+            &fw_args.old_ips,
+            fw_args.ipv6_prefix,
+        )),
+        droplet_ids: targets.droplet_ids.clone(),
+        kubernetes_ids: targets.kubernetes_cluster_ids.clone(),
+        load_balancer_uids: targets.load_balancer_ids.clone(),
+        tags: rule_tags(&current.tags, &fw_args.tags, fw_args.replace_tags),
+    };
+
+    match fw_args.direction {
+        Direction::Inbound => {
+            let inbound_rule = select_rule(
+                firewall
+                    .inbound_rules
+                    .iter()
+                    .flatten()
+                    .filter(|x| ports_match(&x.ports, port) && x.protocol == protocol)
+                    .collect(),
+                |rule| &rule.sources,
+                fw_args.rule_index,
+                fw_args.match_source_tag.as_deref(),
+            )
+            .cloned()
+            .ok_or_else(rule_not_found)?;
+            warn_dangling_references(
+                &client.droplet,
+                &client.kubernetes,
+                &client.load_balancer,
+                &inbound_rule.sources,
+            )
+            .await?;
+            let new_inbound_rule = FirewallInboundRule {
+                protocol: inbound_rule.protocol.clone(),
+                ports: inbound_rule.ports.clone(),
+                sources: new_target(&inbound_rule.sources),
+            };
+
+            Ok((Some((inbound_rule, new_inbound_rule)), None))
+        }
+        Direction::Outbound => {
+            let outbound_rule = select_rule(
+                firewall
+                    .outbound_rules
+                    .iter()
+                    .flatten()
+                    .filter(|x| ports_match(&x.ports, port) && x.protocol == protocol)
+                    .collect(),
+                |rule| &rule.destinations,
+                fw_args.rule_index,
+                fw_args.match_source_tag.as_deref(),
+            )
+            .cloned()
+            .ok_or_else(rule_not_found)?;
+            warn_dangling_references(
+                &client.droplet,
+                &client.kubernetes,
+                &client.load_balancer,
+                &outbound_rule.destinations,
+            )
+            .await?;
+            let new_outbound_rule = FirewallOutboundRule {
+                protocol: outbound_rule.protocol.clone(),
+                ports: outbound_rule.ports.clone(),
+                destinations: new_target(&outbound_rule.destinations),
+            };
+
+            Ok((None, Some((outbound_rule, new_outbound_rule))))
+        }
+    }
+}
+
+/// Pick the rule to update from those covering the port and protocol: the `rule_index`th of them
+/// counting from 1, among those whose targets carry `match_tag` if given.  Without a
+/// `rule_index` the first is picked, with a warning if others would also match.
+fn select_rule<'a, R>(
+    candidates: Vec<&'a R>,
+    targets: impl Fn(&R) -> &FirewallRuleTarget,
+    rule_index: Option<u32>,
+    match_tag: Option<&str>,
+) -> Option<&'a R> {
+    let candidates: Vec<&R> = candidates
+        .into_iter()
+        .filter(|rule| {
+            match_tag.is_none_or(|tag| targets(rule).tags.iter().flatten().any(|t| t == tag))
+        })
+        .collect();
+    match rule_index {
+        Some(index) => candidates.get((index as usize).checked_sub(1)?).copied(),
+        None => {
+            if candidates.len() > 1 {
+                warn!(
+                    "{} rules cover the port and protocol; updating the first of them.  Pass \
+                        --rule-index or --match-source-tag to pick another",
+                    candidates.len()
+                );
+            }
+            candidates.first().copied()
+        }
+    }
+}
+
+/// The tags a rule should allow: the given tags added to the rule's current tags, or instead of
+/// them when replacing.  Without any given tags the current tags are kept.
+fn rule_tags(
+    current: &Option<Vec<String>>,
+    tags: &Option<Vec<String>>,
+    replace_tags: bool,
+) -> Option<Vec<String>> {
+    match tags {
+        None => current.clone(),
+        Some(tags) if replace_tags => Some(tags.clone()),
+        Some(tags) => {
+            let mut all_tags = current.clone().unwrap_or_default();
+            for tag in tags {
+                if !all_tags.contains(tag) {
+                    all_tags.push(tag.clone());
+                }
+            }
+            Some(all_tags)
+        }
+    }
+}
+
+/// The addresses a rule should allow: the given addresses followed by the detected IP address(es).
+/// When the previously published addresses are known, the rule's current addresses are kept as
+/// well, except for the previously published ones, instead of being replaced.
+fn rule_addresses(
+    current: &Option<Vec<String>>,
+    addresses: &Option<Vec<String>>,
+    ips: &[IpAddr],
+    old_ips: &Option<Vec<IpAddr>>,
+    ipv6_prefix: Option<u8>,
+) -> Vec<String> {
+    let kept = match old_ips {
+        Some(old_ips) => {
+            let stale: Vec<String> = old_ips
+                .iter()
+                .map(|ip| firewall_address(ip, ipv6_prefix))
+                .collect();
+            current
+                .iter()
+                .flatten()
+                .filter(|address| !stale.contains(address))
+                .cloned()
+                .collect()
+        }
+        None => Vec::new(),
+    };
+    let mut all_addresses: Vec<String> = Vec::new();
+    for address in kept
+        .into_iter()
+        .chain(addresses.iter().flatten().cloned())
+        .chain(ips.iter().map(|ip| firewall_address(ip, ipv6_prefix)))
+    {
+        if !all_addresses.contains(&address) {
+            all_addresses.push(address);
+        }
+    }
+    all_addresses
+}
+
+/// Whether a rule's ports match the requested port, treating the different ways DigitalOcean
+/// reports a rule covering all ports as equivalent.
+fn ports_match(rule_ports: &str, port: &str) -> bool {
+    if port == ALL_PORTS {
+        rule_ports == ALL_PORTS || rule_ports.is_empty() || rule_ports.eq_ignore_ascii_case("all")
+    } else {
+        rule_ports == port
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn replace_firewall_rules(
+    fw_client: Rc<dyn DigitalOceanFirewallClient>,
+    firewall: Firewall,
+    inbound_rule_replacements: Vec<(FirewallInboundRule, FirewallInboundRule)>,
+    outbound_rule_replacements: Vec<(FirewallOutboundRule, FirewallOutboundRule)>,
+    names: &ResourceNames,
+    atomic: bool,
+    verify: bool,
+    dry_run: bool,
+) -> Result<Firewall, Error> {
+    fn split<T: Clone>(replacements: &[(T, T)]) -> (Option<Vec<T>>, Option<Vec<T>>) {
+        if replacements.is_empty() {
+            (None, None)
+        } else {
+            (
+                Some(replacements.iter().map(|(old, _)| old.clone()).collect()),
+                Some(replacements.iter().map(|(_, new)| new.clone()).collect()),
+            )
+        }
+    }
+    let (inbound_rule, new_inbound_rule) = split(&inbound_rule_replacements);
+    let (outbound_rule, new_outbound_rule) = split(&outbound_rule_replacements);
+
+    let current =
+        ensure_rules_unchanged(&fw_client, &firewall, &inbound_rule, &outbound_rule).await?;
+
+    if atomic {
+        let desired = Firewall {
+            inbound_rules: with_rules_replaced(
+                current.inbound_rules.clone(),
+                inbound_rule_replacements,
+            ),
+            outbound_rules: with_rules_replaced(
+                current.outbound_rules.clone(),
+                outbound_rule_replacements,
+            ),
+            ..current
+        };
+        info!("Replacing rules of firewall {}", firewall.name);
+        log_rules(names, &new_inbound_rule, &new_outbound_rule);
+        fw_client.update_firewall(&desired, &dry_run).await?;
+    } else {
+        info!("Deleting rules on firewall {}", firewall.name);
+        log_rules(names, &inbound_rule, &outbound_rule);
+        fw_client
+            .delete_firewall_rule(
+                firewall.id.as_str(),
+                inbound_rule.clone(),
+                outbound_rule.clone(),
+                &dry_run,
+            )
+            .await?;
+
+        info!("Creating rules on firewall {}", firewall.name);
+        log_rules(names, &new_inbound_rule, &new_outbound_rule);
+        if let Err(e) = fw_client
+            .add_firewall_rule(
+                firewall.id.as_str(),
+                new_inbound_rule,
+                new_outbound_rule,
+                &dry_run,
+            )
+            .await
+        {
+            // put the deleted rule back rather than leave the firewall without it
+            error!(
+                "Unable to create the new rule on firewall {}, restoring the deleted rule",
+                firewall.id
+            );
+            if let Err(rollback_error) = fw_client
+                .add_firewall_rule(firewall.id.as_str(), inbound_rule, outbound_rule, &dry_run)
+                .await
+            {
+                error!(
+                    "Unable to restore the deleted rule on firewall {}: {}",
+                    firewall.id, rollback_error
+                );
+            }
+            return Err(e.into());
+        }
+    }
+
+    if !verify {
+        return Ok(firewall);
+    }
+    info!("Fetching updated firewall");
+    let updated_firewall = fw_client
+        .get_firewall_by_id(firewall.id.as_str())
+        .await?
+        .ok_or(Error::FirewallNotFound())?;
+
+    Ok(updated_firewall)
+}
+
+/// Log each rule, naming the resources it refers to by ID
+fn log_rules(
+    names: &ResourceNames,
+    inbound_rules: &Option<Vec<FirewallInboundRule>>,
+    outbound_rules: &Option<Vec<FirewallOutboundRule>>,
+) {
+    for rule in inbound_rules.iter().flatten() {
+        info!("  inbound: {}", names.describe_inbound(rule));
+    }
+    for rule in outbound_rules.iter().flatten() {
+        info!("  outbound: {}", names.describe_outbound(rule));
+    }
+}
+
+/// Swap rules for their replacements in the rules of a firewall, keeping their positions
+fn with_rules_replaced<T: PartialEq>(
+    rules: Option<Vec<T>>,
+    replacements: Vec<(T, T)>,
+) -> Option<Vec<T>> {
+    if replacements.is_empty() {
+        return rules;
+    }
+    let mut replacements: Vec<(T, Option<T>)> = replacements
+        .into_iter()
+        .map(|(old, new)| (old, Some(new)))
+        .collect();
+    Some(
+        rules
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(
+                |rule| match replacements.iter_mut().find(|(old, _)| *old == rule) {
+                    Some((_, new)) => new.take(),
+                    None => Some(rule),
+                },
+            )
+            .collect(),
+    )
+}
+
+/// Re-fetch a firewall immediately before modifying it and fail if any of the rules about to be
+/// replaced were changed or removed by someone else since the firewall was read.  Returns the
+/// re-fetched firewall.
+async fn ensure_rules_unchanged(
+    fw_client: &Rc<dyn DigitalOceanFirewallClient>,
+    firewall: &Firewall,
+    inbound_rules: &Option<Vec<FirewallInboundRule>>,
+    outbound_rules: &Option<Vec<FirewallOutboundRule>>,
+) -> Result<Firewall, Error> {
+    fn all_present<T: PartialEq>(expected: &Option<Vec<T>>, current: &Option<Vec<T>>) -> bool {
+        expected
+            .iter()
+            .flatten()
+            .all(|rule| current.iter().flatten().any(|r| r == rule))
+    }
+
+    let current = fw_client
+        .get_firewall_by_id(firewall.id.as_str())
+        .await?
+        .ok_or_else(|| {
+            Error::ConcurrentModification(format!(
+                "firewall {} was removed while updating",
+                firewall.name
+            ))
+        })?;
+    if all_present(inbound_rules, &current.inbound_rules)
+        && all_present(outbound_rules, &current.outbound_rules)
+    {
+        Ok(current)
+    } else {
+        Err(Error::ConcurrentModification(format!(
+            "rules of firewall {} changed while updating",
+            firewall.name
+        )))
+    }
+}
+
+/// Look up the IDs of the droplets carrying any of the tags, only fetching the droplets if any
+/// tags were given.  Only droplets accepted by `filter` are considered.
+async fn tags_to_droplet_ids<FF>(
+    tags_client: &Rc<dyn DigitalOceanTagsClient>,
+    droplet_client: &Rc<dyn DigitalOceanDropletClient>,
+    tags: Option<Vec<String>>,
+    filter: FF,
+) -> Result<Option<Vec<u32>>, Error>
+where
+    FF: Fn(&DropletSummary) -> bool,
+{
+    let tags = match tags {
+        Some(tags) => tags,
+        None => return Ok(None),
+    };
+    let mut ids = Vec::new();
+    for tag in tags {
+        if tags_client.get_tag(&tag).await?.is_none() {
+            return Err(Error::ObjectNotFound {
+                kind: "tag",
+                name: tag,
+            });
+        }
+        let droplets = droplet_client.get_droplet_summaries_by_tag(&tag).await?;
+        if droplets.is_empty() {
+            warn!("No droplets carry the tag {}", tag);
+        }
+        ids.extend(droplets.iter().filter(|d| filter(d)).map(|d| d.id));
+    }
+    Ok(Some(ids))
+}
+
+/// Combine two optional lists of IDs, dropping duplicates; `None` only if both are
+fn merge_ids<K: Eq>(ids: Option<Vec<K>>, more: Option<Vec<K>>) -> Option<Vec<K>> {
+    match (ids, more) {
+        (None, None) => None,
+        (ids, more) => {
+            let mut merged = ids.unwrap_or_default();
+            for id in more.into_iter().flatten() {
+                if !merged.contains(&id) {
+                    merged.push(id);
+                }
+            }
+            Some(merged)
+        }
+    }
+}
+
+/// The URNs of every resource in the project with the given name
+async fn project_resources(
+    project_client: &Rc<dyn DigitalOceanProjectsClient>,
+    project: &str,
+) -> Result<HashSet<String>, Error> {
+    let id = project_client
+        .get_projects()
+        .await?
+        .into_iter()
+        .find(|p| p.name == project)
+        .ok_or_else(|| Error::ObjectNotFound {
+            kind: "project",
+            name: project.to_string(),
+        })?
+        .id;
+    Ok(project_client
+        .get_project_resources(&id)
+        .await?
+        .into_iter()
+        .map(|r| r.urn)
+        .collect())
+}
+
+/// Look up the IDs of the named objects, only fetching the objects if any names were given.  Only
+/// objects accepted by `filter` are considered, so that a name can be looked up within a project.
+async fn names_to_ids<K, N, T, OF, OR, FF, KF, NF>(
+    kind: &'static str,
+    get_objects: OF,
+    names: Option<Vec<N>>,
+    filter: FF,
+    extract_name: NF,
+    extract_key: KF,
+) -> Result<Option<Vec<K>>, Error>
+where
+    N: Eq + Hash + Display,
+    OF: FnOnce() -> OR,
+    OR: Future<Output = Result<Vec<T>, digitalocean::error::Error>>,
+    FF: Fn(&T) -> bool,
+    KF: Fn(&T) -> K,
+    NF: Fn(&T) -> N,
+{
+    let names = match names {
+        Some(names) => names,
+        None => return Ok(None),
+    };
+    let by_name = get_objects()
+        .await?
+        .into_iter()
+        .filter(|d| filter(d))
+        .map(|d| (extract_name(&d), d))
+        .collect::<HashMap<N, T>>();
+    names
+        .into_iter()
+        .map(|name| match by_name.get(&name) {
+            Some(d) => Ok(extract_key(d)),
+            None => Err(Error::ObjectNotFound {
+                kind,
+                name: name.to_string(),
+            }),
+        })
+        .collect::<Result<Vec<K>, Error>>()
+        .map(Some)
+}
+
+/// Resource IDs referenced by a firewall rule target that no longer exist in the account.
+#[derive(Debug, Default, Eq, PartialEq)]
+struct DanglingReferences {
+    droplet_ids: Vec<u32>,
+    kubernetes_ids: Vec<String>,
+    load_balancer_uids: Vec<String>,
+}
+
+impl DanglingReferences {
+    fn is_empty(&self) -> bool {
+        self.droplet_ids.is_empty()
+            && self.kubernetes_ids.is_empty()
+            && self.load_balancer_uids.is_empty()
+    }
+
+    /// Build a copy of `target` with all dangling references removed.
+    fn prune(&self, target: &FirewallRuleTarget) -> FirewallRuleTarget {
+        fn retain<T: Clone + PartialEq>(ids: &Option<Vec<T>>, dangling: &[T]) -> Option<Vec<T>> {
+            ids.as_ref()
+                .map(|ids| {
+                    ids.iter()
+                        .filter(|id| !dangling.contains(id))
+                        .cloned()
+                        .collect::<Vec<T>>()
+                })
+                .filter(|ids| !ids.is_empty())
+        }
+
+        FirewallRuleTarget {
+            addresses: target.addresses.clone(),
+            droplet_ids: retain(&target.droplet_ids, &self.droplet_ids),
+            load_balancer_uids: retain(&target.load_balancer_uids, &self.load_balancer_uids),
+            kubernetes_ids: retain(&target.kubernetes_ids, &self.kubernetes_ids),
+            tags: target.tags.clone(),
+        }
+    }
+}
+
+/// Whether a rule target no longer allows traffic from/to anything.
+fn is_empty_target(target: &FirewallRuleTarget) -> bool {
+    fn is_empty<T>(x: &Option<Vec<T>>) -> bool {
+        x.as_ref().is_none_or(|v| v.is_empty())
+    }
+
+    is_empty(&target.addresses)
+        && is_empty(&target.droplet_ids)
+        && is_empty(&target.load_balancer_uids)
+        && is_empty(&target.kubernetes_ids)
+        && is_empty(&target.tags)
+}
+
+/// Cross-check the resource IDs referenced by each of `targets` against the live inventory of the
+/// account.  Each inventory is only fetched if at least one target references that kind of
+/// resource.
+async fn find_dangling_references(
+    droplet_client: &Rc<dyn DigitalOceanDropletClient>,
+    kubernetes_client: &Rc<dyn DigitalOceanKubernetesClient>,
+    load_balancer_client: &Rc<dyn DigitalOceanLoadbalancerClient>,
+    targets: &[&FirewallRuleTarget],
+) -> Result<Vec<DanglingReferences>, Error> {
+    fn referenced<T>(
+        targets: &[&FirewallRuleTarget],
+        f: impl Fn(&FirewallRuleTarget) -> &Option<Vec<T>>,
+    ) -> bool {
+        targets
+            .iter()
+            .any(|t| f(t).as_ref().is_some_and(|ids| !ids.is_empty()))
+    }
+
+    fn missing<T: Clone + Eq + Hash>(ids: &Option<Vec<T>>, live: &Option<HashSet<T>>) -> Vec<T> {
+        match (ids, live) {
+            (Some(ids), Some(live)) => ids
+                .iter()
+                .filter(|id| !live.contains(id))
+                .cloned()
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    let live_droplets = async {
+        if referenced(targets, |t| &t.droplet_ids) {
+            let droplets = droplet_client.get_droplet_summaries().await?;
+            Ok(Some(droplets.into_iter().map(|d| d.id).collect()))
+        } else {
+            Ok::<Option<HashSet<u32>>, Error>(None)
+        }
+    };
+    let live_clusters = async {
+        if referenced(targets, |t| &t.kubernetes_ids) {
+            let clusters = kubernetes_client.get_kubernetes_cluster_summaries().await?;
+            Ok(Some(clusters.into_iter().map(|k| k.id).collect()))
+        } else {
+            Ok::<Option<HashSet<String>>, Error>(None)
+        }
+    };
+    let live_load_balancers = async {
+        if referenced(targets, |t| &t.load_balancer_uids) {
+            let load_balancers = load_balancer_client.get_load_balancer_summaries().await?;
+            Ok(Some(load_balancers.into_iter().map(|l| l.id).collect()))
+        } else {
+            Ok::<Option<HashSet<String>>, Error>(None)
+        }
+    };
+    let (live_droplets, live_clusters, live_load_balancers) =
+        tokio::try_join!(live_droplets, live_clusters, live_load_balancers)?;
+
+    Ok(targets
+        .iter()
+        .map(|t| DanglingReferences {
+            droplet_ids: missing(&t.droplet_ids, &live_droplets),
+            kubernetes_ids: missing(&t.kubernetes_ids, &live_clusters),
+            load_balancer_uids: missing(&t.load_balancer_uids, &live_load_balancers),
+        })
+        .collect())
+}
+
+async fn warn_dangling_references(
+    droplet_client: &Rc<dyn DigitalOceanDropletClient>,
+    kubernetes_client: &Rc<dyn DigitalOceanKubernetesClient>,
+    load_balancer_client: &Rc<dyn DigitalOceanLoadbalancerClient>,
+    target: &FirewallRuleTarget,
+) -> Result<(), Error> {
+    let dangling = find_dangling_references(
+        droplet_client,
+        kubernetes_client,
+        load_balancer_client,
+        &[target],
+    )
+    .await?
+    .remove(0);
+    if !dangling.is_empty() {
+        warn!(
+            "Firewall rule references resources that no longer exist (use --prune-dangling to \
+            remove them)\n{:#?}",
+            dangling
+        );
+    }
+    Ok(())
+}
+
+/// Remove references to deleted droplets, Kubernetes clusters, and load balancers from every rule
+/// in the firewall.  Rules that would be left without any sources/destinations are deleted.
+async fn prune_dangling_references(
+    fw_client: Rc<dyn DigitalOceanFirewallClient>,
+    droplet_client: Rc<dyn DigitalOceanDropletClient>,
+    kubernetes_client: Rc<dyn DigitalOceanKubernetesClient>,
+    load_balancer_client: Rc<dyn DigitalOceanLoadbalancerClient>,
+    firewall: Firewall,
+    dry_run: bool,
+) -> Result<Firewall, Error> {
+    let inbound_rules = firewall.inbound_rules.clone().unwrap_or_default();
+    let outbound_rules = firewall.outbound_rules.clone().unwrap_or_default();
+    let targets = inbound_rules
+        .iter()
+        .map(|r| &r.sources)
+        .chain(outbound_rules.iter().map(|r| &r.destinations))
+        .collect::<Vec<&FirewallRuleTarget>>();
+    let dangling = find_dangling_references(
+        &droplet_client,
+        &kubernetes_client,
+        &load_balancer_client,
+        &targets,
+    )
+    .await?;
+    let (inbound_dangling, outbound_dangling) = dangling.split_at(inbound_rules.len());
+
+    let mut old_inbound_rules = Vec::new();
+    let mut new_inbound_rules = Vec::new();
+    for (rule, dangling) in inbound_rules.iter().zip(inbound_dangling) {
+        if !dangling.is_empty() {
+            old_inbound_rules.push(rule.clone());
+            let sources = dangling.prune(&rule.sources);
+            if is_empty_target(&sources) {
+                warn!(
+                    "Inbound rule will be removed since it only references deleted \
+                        resources\n{:#?}",
+                    rule
+                );
+            } else {
+                new_inbound_rules.push(FirewallInboundRule {
+                    sources,
+                    ..rule.clone()
+                });
+            }
+        }
+    }
+    let mut old_outbound_rules = Vec::new();
+    let mut new_outbound_rules = Vec::new();
+    for (rule, dangling) in outbound_rules.iter().zip(outbound_dangling) {
+        if !dangling.is_empty() {
+            old_outbound_rules.push(rule.clone());
+            let destinations = dangling.prune(&rule.destinations);
+            if is_empty_target(&destinations) {
+                warn!(
+                    "Outbound rule will be removed since it only references deleted \
+                        resources\n{:#?}",
+                    rule
+                );
+            } else {
+                new_outbound_rules.push(FirewallOutboundRule {
+                    destinations,
+                    ..rule.clone()
+                });
+            }
+        }
+    }
+
+    if old_inbound_rules.is_empty() && old_outbound_rules.is_empty() {
+        info!("No dangling references found on firewall {}", firewall.id);
+        return Ok(firewall);
+    }
+
+    info!(
+        "Pruning dangling references from {} rule(s) on firewall {}",
+        old_inbound_rules.len() + old_outbound_rules.len(),
+        firewall.id
+    );
+    let old_inbound_rules = Some(old_inbound_rules).filter(|r| !r.is_empty());
+    let old_outbound_rules = Some(old_outbound_rules).filter(|r| !r.is_empty());
+    ensure_rules_unchanged(
+        &fw_client,
+        &firewall,
+        &old_inbound_rules,
+        &old_outbound_rules,
+    )
+    .await?;
+    fw_client
+        .delete_firewall_rule(
+            firewall.id.as_str(),
+            old_inbound_rules,
+            old_outbound_rules,
+            &dry_run,
+        )
+        .await?;
+    if !new_inbound_rules.is_empty() || !new_outbound_rules.is_empty() {
+        fw_client
+            .add_firewall_rule(
+                firewall.id.as_str(),
+                Some(new_inbound_rules).filter(|r| !r.is_empty()),
+                Some(new_outbound_rules).filter(|r| !r.is_empty()),
+                &dry_run,
+            )
+            .await?;
+    }
+
+    info!("Fetching pruned firewall");
+    let pruned_firewall = fw_client
+        .get_firewall(firewall.name.clone())
+        .await?
+        .ok_or(Error::FirewallNotFound())?;
+
+    Ok(pruned_firewall)
+}
+
+/// Find the reserved IP to reassign and the ID of the droplet it should be assigned to
+async fn resolve_reserved_ip(
+    reserved_ip_client: &Rc<dyn DigitalOceanReservedIpClient>,
+    droplet_client: &Rc<dyn DigitalOceanDropletClient>,
+    rip_args: &ReservedIpArgs,
+) -> Result<(ReservedIp, u32), Error> {
+    let (reserved_ips, droplets) = tokio::try_join!(
+        reserved_ip_client.get_reserved_ips(),
+        droplet_client.get_droplet_summaries()
+    )?;
+    let reserved_ip = reserved_ips
+        .into_iter()
+        .find(|r| match (&rip_args.address, &rip_args.from_droplet) {
+            (Some(address), _) => r.ip == *address,
+            (None, Some(from_droplet)) => {
+                r.droplet.as_ref().is_some_and(|d| d.name == *from_droplet)
+            }
+            (None, None) => false,
+        })
+        .ok_or(Error::ReservedIpNotFound())?;
+    let droplet_id = droplets
+        .into_iter()
+        .find(|d| d.name == rip_args.droplet)
+        .map(|d| d.id)
+        .ok_or(Error::DropletNotFound())?;
+    Ok((reserved_ip, droplet_id))
+}
+
+/// Assign a reserved IP to a droplet unless it is already assigned to it
+async fn run_reserved_ip(
+    reserved_ip_client: Rc<dyn DigitalOceanReservedIpClient>,
+    droplet_client: Rc<dyn DigitalOceanDropletClient>,
+    rip_args: ReservedIpArgs,
+    verify: bool,
+    stats: &RunStats,
+    dry_run: bool,
+) -> Result<(), Error> {
+    let (reserved_ip, droplet_id) = stats
+        .time(
+            "reserved IP lookup",
+            resolve_reserved_ip(&reserved_ip_client, &droplet_client, &rip_args),
+        )
+        .await?;
+    match reserved_ip.droplet {
+        Some(ref droplet) if droplet.id == droplet_id => {
+            info!(
+                "Reserved IP {} already assigned to droplet {}",
+                reserved_ip.ip, rip_args.droplet
+            );
+            return Ok(());
+        }
+        Some(ref droplet) => info!(
+            "Will reassign reserved IP {} from droplet {} to droplet {}",
+            reserved_ip.ip, droplet.name, rip_args.droplet
+        ),
+        None => info!(
+            "Will assign unassigned reserved IP {} to droplet {}",
+            reserved_ip.ip, rip_args.droplet
+        ),
+    }
+    if reserved_ip.locked {
+        return Err(Error::ConcurrentModification(format!(
+            "reserved IP {} has a pending action",
+            reserved_ip.ip
+        )));
+    }
+    stats
+        .time(
+            "mutation",
+            reserved_ip_client.assign_reserved_ip(&reserved_ip.ip, &droplet_id, &dry_run),
+        )
+        .await?;
+    info!("Successfully assigned reserved IP!");
+    stats.record_change(None);
+    if verify && !dry_run {
+        stats
+            .time(
+                "verification",
+                verify_reserved_ip(&reserved_ip_client, &reserved_ip.ip, droplet_id),
+            )
+            .await?;
+    }
+    Ok(())
+}
+
+/// Re-query a reserved IP after assigning it and fail if it is assigned to another droplet.  The
+/// API assigns reserved IPs asynchronously, so an assignment that is still in progress is
+/// accepted.
+async fn verify_reserved_ip(
+    client: &Rc<dyn DigitalOceanReservedIpClient>,
+    ip: &IpAddr,
+    droplet_id: u32,
+) -> Result<(), Error> {
+    let stored = client
+        .get_reserved_ips()
+        .await?
+        .into_iter()
+        .find(|r| r.ip == *ip)
+        .ok_or_else(|| {
+            Error::VerificationFailed(format!("reserved IP {ip} not found after assigning it"))
+        })?;
+    match stored.droplet {
+        Some(droplet) if droplet.id == droplet_id => {
+            info!("Verified assignment of reserved IP {}", ip);
+            Ok(())
+        }
+        _ if stored.locked => {
+            info!("Assignment of reserved IP {} is still in progress", ip);
+            Ok(())
+        }
+        droplet => Err(Error::VerificationFailed(format!(
+            "reserved IP {} is assigned to {:?} instead of droplet {}",
+            ip,
+            droplet.map(|d| d.name),
+            droplet_id
+        ))),
+    }
+}
+
+/// Whether the reserved IP is already assigned to the droplet
+async fn check_reserved_ip(
+    reserved_ip_client: Rc<dyn DigitalOceanReservedIpClient>,
+    droplet_client: Rc<dyn DigitalOceanDropletClient>,
+    rip_args: ReservedIpArgs,
+) -> Result<bool, Error> {
+    let (reserved_ip, droplet_id) =
+        resolve_reserved_ip(&reserved_ip_client, &droplet_client, &rip_args).await?;
+    match reserved_ip.droplet {
+        Some(droplet) if droplet.id == droplet_id => {
+            info!(
+                "Reserved IP {} is assigned to droplet {}",
+                reserved_ip.ip, rip_args.droplet
+            );
+            Ok(true)
+        }
+        droplet => {
+            warn!(
+                "Reserved IP {} is assigned to {} but should be assigned to droplet {}",
+                reserved_ip.ip,
+                droplet.map_or("no droplet".to_string(), |d| format!("droplet {}", d.name)),
+                rip_args.droplet
+            );
+            Ok(false)
+        }
+    }
+}
+
+/// Why bringing a target in line with the IP address failed
+#[allow(dead_code)]
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    /// The DigitalOcean or Cloudflare API could not be reached or rejected a request
+    #[error(transparent)]
+    Client(#[from] digitalocean::error::Error),
+    #[error("invalid IP address: {0}")]
+    AddrParseErr(#[from] std::net::AddrParseError),
+    #[error("domain not found")]
+    DomainNotFound(),
+    #[error("firewall not found")]
+    FirewallNotFound(),
+    /// A firewall tracked by its ID in the state file was renamed or deleted
+    #[error("{0}")]
+    TrackedFirewall(String),
+    #[error("reserved IP not found")]
+    ReservedIpNotFound(),
+    #[error("droplet not found")]
+    DropletNotFound(),
+    /// The resources being checked, named here, do not match the IP address
+    #[error("the resources checked do not match the IP address: {}", .0.join(", "))]
+    Drift(Vec<String>),
+    /// A resource was changed by someone else between being read and being modified
+    #[error("{0}")]
+    ConcurrentModification(String),
+    /// A record is managed by other automation and modifying it was not forced
+    #[error("{0}")]
+    ManagedExternally(String),
+    /// A fully qualified record name does not end with the domain it was given for
+    #[error("{record} is not a name within the domain {domain}")]
+    RecordOutsideDomain { record: String, domain: String },
+    /// A record does not exist and creating it was not allowed
+    #[error("DNS record {0} does not exist; create the record first, or check the name for typos")]
+    RecordNotFound(String),
+    /// The resource whose address a record should hold has no public address of its family
+    #[error("{0}")]
+    NoPublicAddress(String),
+    /// The record type cannot hold the only address(es) available for it, such as an AAAA record
+    /// when only an IPv4 address was detected
+    #[error("a {rtype} record cannot hold {ip}; no address of its family was found")]
+    RecordTypeMismatch { rtype: String, ip: IpAddr },
+    /// The state stored by the API after a change does not match what was requested
+    #[error("verification failed: {0}")]
+    VerificationFailed(String),
+    /// A pre- or post-change hook command failed
+    #[error("{0}")]
+    Hook(String),
+    /// The declared firewall rules could not be read
+    #[error("{0}")]
+    RulesFile(String),
+    /// The detected IP address could not be written to the `--ip-out` file
+    #[error("unable to write the IP address file")]
+    IpOut(),
+    /// The `--state-file` could not be read or written
+    #[error("unable to read or write the state file")]
+    StateFile(),
+    /// The `--history-file` could not be read
+    #[error("unable to read the history file")]
+    HistoryFile(),
+    /// The `--lock-file` could not be opened or locked
+    #[error("unable to use the lock file")]
+    LockFile(),
+    /// Another run held the `--lock-file` for longer than this run would wait
+    #[error("another run holds the lock file {}", .0.display())]
+    Locked(PathBuf),
+    /// The run did not finish within the `--deadline`
+    #[error("the run did not finish within the deadline of {}s", .0.as_secs())]
+    DeadlineExceeded(Duration),
+    /// The firewall has no rule for the requested direction, port and protocol
+    #[error(
+        "firewall {firewall} has no {} {protocol} rule for port {port}; create the rule first, or \
+            check the direction, port and protocols given",
+        match direction {
+            Direction::Inbound => "inbound",
+            Direction::Outbound => "outbound",
+        }
+    )]
+    RuleNotFound {
+        firewall: String,
+        direction: Direction,
+        port: String,
+        protocol: String,
+    },
+    /// The account the API token belongs to is locked, so none of its resources can be changed
+    #[error("the DigitalOcean account {email} is locked: {message}")]
+    AccountLocked { email: String, message: String },
+    /// A droplet, Kubernetes cluster or load balancer named in a firewall rule does not exist
+    #[error("no {kind} named {name} exists; check the name for typos")]
+    ObjectNotFound { kind: &'static str, name: String },
+    /// The run was asked to stop before it reached the target
+    #[error("the run was stopped before the target was updated")]
+    Interrupted(),
+    /// A DNS target names a provider whose API token was not given
+    #[error("no API token was given for the {0} provider")]
+    ProviderNotConfigured(Provider),
+}
+
+impl Error {
+    /// Whether a later run may succeed without anything being changed
+    pub fn is_retriable(&self) -> bool {
+        match self {
+            Error::Client(e) => e.is_retriable(),
+            // a later run will plan against the new state
+            Error::ConcurrentModification(_) => true,
+            Error::DeadlineExceeded(_) => true,
+            Error::Interrupted() => true,
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod account_test {
+    use crate::digitalocean::account::Account;
+    use crate::digitalocean::mock::MockAccountClient;
+    use crate::{check_account, Error};
+
+    fn account(status: &str, status_message: &str) -> Account {
+        Account {
+            email: "sammy@digitalocean.com".to_string(),
+            uuid: "b6fr89dbf6d9156cace5f3c78dc9851d957381ef".to_string(),
+            email_verified: true,
+            status: status.to_string(),
+            status_message: status_message.to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_check_account() {
+        let active = account("active", " ");
+        assert_eq!(
+            active,
+            check_account(&MockAccountClient::new(active.clone()))
+                .await
+                .unwrap()
+        );
+
+        let warning = account("warning", "Your payment is overdue");
+        assert!(check_account(&MockAccountClient::new(warning))
+            .await
+            .is_ok());
+
+        match check_account(&MockAccountClient::new(account(
+            "locked",
+            "Your account has been locked ",
+        )))
+        .await
+        {
+            Err(Error::AccountLocked { email, message }) => {
+                assert_eq!("sammy@digitalocean.com", email);
+                assert_eq!("Your account has been locked", message);
+            }
+            x => panic!("Expected locked account error but got {:?}", x),
+        }
+    }
+}
+
+#[cfg(test)]
+mod dns_test {
+    use async_trait::async_trait;
+    use std::cell::RefCell;
+    use std::net::{IpAddr, Ipv4Addr};
+    use std::rc::Rc;
+    use std::sync::atomic::AtomicBool;
+
+    use crate::cli::DnsArgs;
+    use crate::digitalocean::dns::{DigitalOceanDnsClient, Domain, DomainRecord, RecordValue};
+    use crate::digitalocean::droplet::Droplet;
+    use crate::digitalocean::error::{Error, Failure};
+    use crate::digitalocean::loadbalancer::Loadbalancer;
+    use crate::digitalocean::mock::{MockDnsClient, MockDropletClient, MockLoadbalancerClient};
+    use crate::hooks::Hooks;
+    use crate::provider::Provider;
+    use crate::stats::RunStats;
+    use crate::{
+        check_dns, delete_records, dns_values, droplet_ips, ensure_record_unchanged,
+        external_owner, load_balancer_ips, resolve_records, run_dns, split_fqdn, update_dns,
+        verify_record, with_aliases, DigitalOceanClient, UpdateOptions,
+    };
+
+    #[tokio::test]
+    async fn test_update_dns() {
+        let mut server = mockito::Server::new_async().await;
+        let _m_domains = server
+            .mock("GET", "/v2/domains")
+            .with_status(200)
+            .with_header("Content-Type", "application/json")
+            .with_body(
+                serde_json::to_string(&json!({
+                    "domains": [{"name": "google.com", "ttl": 1800, "zone_file": ""}],
+                    "meta": {"total": 1},
+                    "links": {}
+                }))
+                .unwrap(),
+            )
+            .expect(2)
+            .create_async()
+            .await;
+        let _m_owners = server
+            .mock("GET", "/v2/domains/google.com/records?type=TXT")
+            .with_status(200)
+            .with_header("Content-Type", "application/json")
+            .with_body(
+                serde_json::to_string(&json!({
+                    "domain_records": [],
+                    "meta": {"total": 0},
+                    "links": {}
+                }))
+                .unwrap(),
+            )
+            .create_async()
+            .await;
+        let _m_filter = server
+            .mock(
+                "GET",
+                "/v2/domains/google.com/records?name=home.google.com&type=A",
+            )
+            .with_status(200)
+            .with_header("Content-Type", "application/json")
+            .with_body(
+                serde_json::to_string(&json!({
+                    "domain_records": [],
+                    "meta": {"total": 0},
+                    "links": {}
+                }))
+                .unwrap(),
+            )
+            .create_async()
+            .await;
+        let _m_records = server
+            .mock("GET", "/v2/domains/google.com/records?type=A")
+            .with_status(200)
+            .with_header("Content-Type", "application/json")
+            .with_body(
+                serde_json::to_string(&json!({
+                    "domain_records": [],
+                    "meta": {"total": 0},
+                    "links": {}
+                }))
+                .unwrap(),
+            )
+            .create_async()
+            .await;
+        let _m_create = server
+            .mock("POST", "/v2/domains/google.com/records")
+            .match_body(mockito::Matcher::PartialJson(json!({
+                "type": "A",
+                "name": "home",
+                "data": "1.2.3.4"
+            })))
+            .with_status(201)
+            .with_header("Content-Type", "application/json")
+            .with_body(
+                serde_json::to_string(&json!({
+                    "domain_record": {
+                        "id": 234,
+                        "type": "A",
+                        "name": "home",
+                        "data": "1.2.3.4",
+                        "priority": null,
+                        "port": null,
+                        "ttl": 60,
+                        "weight": null,
+                        "flags": null,
+                        "tag": null
+                    }
+                }))
+                .unwrap(),
+            )
+            .create_async()
+            .await;
+
+        let client = DigitalOceanClient::new_for_test("foo".to_string(), server.url());
+        let dns_args: DnsArgs =
+            serde_json::from_value(json!({"record": "home.google.com"})).unwrap();
+        let result = update_dns(
+            &client,
+            dns_args,
+            &["1.2.3.4".parse().unwrap()],
+            &UpdateOptions::default(),
+        )
+        .await;
+        assert!(result.is_ok(), "{result:?}");
+        _m_domains.assert_async().await;
+        _m_filter.assert_async().await;
+        _m_records.assert_async().await;
+        _m_create.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_create_record() {
+        let id = 123;
+        let domain = "google.com".to_string();
+        let record_name = "main".to_string();
+        let rtype = "A".to_string();
+        let ip_addr: IpAddr = Ipv4Addr::new(8, 8, 8, 8).into();
+
+        let client = TestDnsClientImpl {
+            id,
+            domain: domain.clone(),
+            record: record_name.clone(),
+            rtype: rtype.clone(),
+            ip_addr,
+            get_domain_is_ok: true,
+            get_domain_is_some: true,
+            get_record_is_ok: true,
+            get_record_is_some: false,
+            update_record_is_ok: false,
+            create_record_is_ok: true,
+            record_ttl: 60,
+            ttl_updates: RefCell::new(vec![]),
+        };
+
+        let record = run_dns(
+            Rc::new(client),
+            domain.clone(),
+            record_name.clone(),
+            rtype.clone(),
+            ip_addr.into(),
+            60,
+            None,
+            false,
+            true,
+            false,
+            false,
+            &Hooks::default(),
+            &AtomicBool::default(),
+            &RunStats::start(),
+            false,
+        )
+        .await;
+
+        assert_eq!(
+            record.unwrap(),
+            DomainRecord {
+                id,
+                typ: rtype,
+                name: record_name,
+                data: ip_addr.to_string(),
+                priority: None,
+                port: None,
+                ttl: 60,
+                weight: None,
+                flags: None,
+                tag: None
+            }
+        )
+    }
+
+    #[tokio::test]
+    async fn test_update_record_ttl_drift() {
+        let record = DomainRecord {
+            id: 1,
+            typ: "A".to_string(),
+            name: "home".to_string(),
+            data: "8.8.8.8".to_string(),
+            priority: None,
+            port: None,
+            ttl: 3600,
+            weight: None,
+            flags: None,
+            tag: None,
+        };
+        let client = Rc::new(
+            MockDnsClient::new()
+                .with_domain("example.com")
+                .with_record("example.com", record.clone()),
+        );
+        let ip_addr: IpAddr = Ipv4Addr::new(8, 8, 8, 8).into();
+        let updated = run_dns(
+            client.clone(),
+            "example.com".to_string(),
+            "home".to_string(),
+            "A".to_string(),
+            ip_addr.into(),
+            60,
+            None,
+            false,
+            true,
+            false,
+            true,
+            &Hooks::default(),
+            &AtomicBool::default(),
+            &RunStats::start(),
+            false,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(DomainRecord { ttl: 60, ..record }, updated);
+        assert_eq!(
+            Some(60),
+            client
+                .get_record("example.com", "home", "A")
+                .await
+                .unwrap()
+                .map(|r| r.ttl)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_create_record_in_new_domain() {
+        let client = Rc::new(MockDnsClient::new());
+        let ip_addr: IpAddr = Ipv4Addr::new(8, 8, 8, 8).into();
+        let hooks = Hooks::default();
+        let shutdown = AtomicBool::default();
+        let stats = RunStats::start();
+        let create = |create_domain: bool, dry_run: bool| {
+            run_dns(
+                client.clone(),
+                "example.com".to_string(),
+                "home".to_string(),
+                "A".to_string(),
+                ip_addr.into(),
+                60,
+                None,
+                false,
+                true,
+                create_domain,
+                false,
+                &hooks,
+                &shutdown,
+                &stats,
+                dry_run,
+            )
+        };
+
+        assert!(matches!(
+            create(false, false).await,
+            Err(crate::Error::DomainNotFound())
+        ));
+        create(true, true).await.unwrap();
+        assert!(client.get_domain("example.com").await.unwrap().is_none());
+
+        let record = create(true, false).await.unwrap();
+        assert!(client.get_domain("example.com").await.unwrap().is_some());
+        assert_eq!(
+            Some(record),
+            client.get_record("example.com", "home", "A").await.unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_delete_records() {
+        let record = |id: u32, name: &str, data: &str| DomainRecord {
+            id,
+            typ: "A".to_string(),
+            name: name.to_string(),
+            data: data.to_string(),
+            priority: None,
+            port: None,
+            ttl: 60,
+            weight: None,
+            flags: None,
+            tag: None,
+        };
+        let client = Rc::new(
+            MockDnsClient::new()
+                .with_domain("example.com")
+                .with_record("example.com", record(1, "home", "1.1.1.1"))
+                .with_record("example.com", record(2, "home", "2.2.2.2"))
+                .with_record("example.com", record(3, "vpn", "1.1.1.1")),
+        );
+        let delete = |record: &str, data: Option<&str>, dry_run: bool| {
+            delete_records(
+                client.clone(),
+                record.to_string(),
+                Some("example.com".to_string()),
+                false,
+                "A".to_string(),
+                data.map(|d| d.to_string()),
+                dry_run,
+            )
+        };
+
+        assert_eq!(2, delete("home", None, true).await.unwrap().len());
+        assert_eq!(3, client.records("example.com").len());
+
+        assert_eq!(
+            vec![record(2, "home", "2.2.2.2")],
+            delete("home", Some("2.2.2.2"), false).await.unwrap()
+        );
+        assert_eq!(
+            vec![record(1, "home", "1.1.1.1"), record(3, "vpn", "1.1.1.1")],
+            client.records("example.com")
+        );
+
+        // a fully qualified name is matched regardless of case
+        assert_eq!(
+            vec![record(1, "home", "1.1.1.1")],
+            delete("Home.Example.com.", None, true).await.unwrap()
+        );
+
+        assert!(matches!(
+            delete("mail", None, false).await,
+            Err(crate::Error::RecordNotFound(_))
+        ));
+        assert!(matches!(
+            delete("home.example.org.", None, false).await,
+            Err(crate::Error::RecordOutsideDomain { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_create_record_not_allowed() {
+        let domain = "google.com".to_string();
+        let record_name = "main".to_string();
+        let rtype = "A".to_string();
+        let ip_addr: IpAddr = Ipv4Addr::new(8, 8, 8, 8).into();
+
+        let client = TestDnsClientImpl {
+            id: 123,
+            domain: domain.clone(),
+            record: record_name.clone(),
+            rtype: rtype.clone(),
+            ip_addr,
+            get_domain_is_ok: true,
+            get_domain_is_some: true,
+            get_record_is_ok: true,
+            get_record_is_some: false,
+            update_record_is_ok: false,
+            create_record_is_ok: true,
+            record_ttl: 60,
+            ttl_updates: RefCell::new(vec![]),
+        };
+
+        let record = run_dns(
+            Rc::new(client),
+            domain,
+            record_name,
+            rtype,
+            ip_addr.into(),
+            60,
+            None,
+            false,
+            false,
+            false,
+            false,
+            &Hooks::default(),
+            &AtomicBool::default(),
+            &RunStats::start(),
+            false,
+        )
+        .await;
+
+        assert!(
+            matches!(
+                record,
+                Err(crate::Error::RecordNotFound(ref r)) if r == "main.google.com (A)"
+            ),
+            "{record:?}"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_update_record() {
+        let id = 123;
+        let domain = "google.com".to_string();
+        let record_name = "main".to_string();
+        let rtype = "A".to_string();
+        let ip_addr: IpAddr = Ipv4Addr::new(8, 8, 8, 8).into();
+        let new_ip_addr: IpAddr = Ipv4Addr::new(4, 4, 4, 4).into();
+
+        let client = TestDnsClientImpl {
+            id,
+            domain: domain.clone(),
+            record: record_name.clone(),
+            rtype: rtype.clone(),
+            ip_addr,
+            get_domain_is_ok: true,
+            get_domain_is_some: true,
+            get_record_is_ok: true,
+            get_record_is_some: true,
+            update_record_is_ok: true,
+            create_record_is_ok: false,
+            record_ttl: 60,
+            ttl_updates: RefCell::new(vec![]),
+        };
+
+        let record = run_dns(
+            Rc::new(client),
+            domain.clone(),
+            record_name.clone(),
+            rtype.clone(),
+            new_ip_addr.into(),
+            60,
+            None,
+            false,
+            true,
+            false,
+            false,
+            &Hooks::default(),
+            &AtomicBool::default(),
+            &RunStats::start(),
+            false,
+        )
+        .await;
+
+        assert_eq!(
+            record.unwrap(),
+            DomainRecord {
+                id,
+                typ: rtype,
+                name: record_name,
+                data: new_ip_addr.to_string(),
+                priority: None,
+                port: None,
+                ttl: 60,
+                weight: None,
+                flags: None,
+                tag: None
+            }
+        )
+    }
+
+    #[tokio::test]
+    async fn test_update_record_lower_ttl() {
+        let id = 123;
+        let domain = "google.com".to_string();
+        let record_name = "main".to_string();
+        let rtype = "A".to_string();
+        let ip_addr: IpAddr = Ipv4Addr::new(8, 8, 8, 8).into();
+        let new_ip_addr: IpAddr = Ipv4Addr::new(4, 4, 4, 4).into();
+
+        let client = Rc::new(TestDnsClientImpl {
+            id,
+            domain: domain.clone(),
+            record: record_name.clone(),
+            rtype: rtype.clone(),
+            ip_addr,
+            get_domain_is_ok: true,
+            get_domain_is_some: true,
+            get_record_is_ok: true,
+            get_record_is_some: true,
+            update_record_is_ok: true,
+            create_record_is_ok: false,
+            record_ttl: 1,
+            ttl_updates: RefCell::new(vec![]),
+        });
+
+        let record = run_dns(
+            client.clone(),
+            domain.clone(),
+            record_name.clone(),
+            rtype.clone(),
+            new_ip_addr.into(),
+            60,
+            Some(0),
+            false,
+            true,
+            false,
+            false,
+            &Hooks::default(),
+            &AtomicBool::default(),
+            &RunStats::start(),
+            false,
+        )
+        .await;
+
+        assert_eq!(
+            record.unwrap(),
+            DomainRecord {
+                id,
+                typ: rtype,
+                name: record_name,
+                data: new_ip_addr.to_string(),
+                priority: None,
+                port: None,
+                ttl: 60,
+                weight: None,
+                flags: None,
+                tag: None
+            }
+        );
+        assert_eq!(vec![0, 60], *client.ttl_updates.borrow());
+    }
+
+    #[tokio::test]
+    async fn test_update_record_lower_ttl_interrupted() {
+        let domain = "google.com".to_string();
+        let record_name = "main".to_string();
+        let rtype = "A".to_string();
+        let new_ip_addr: IpAddr = Ipv4Addr::new(4, 4, 4, 4).into();
+
+        let client = Rc::new(TestDnsClientImpl {
+            id: 123,
+            domain: domain.clone(),
+            record: record_name.clone(),
+            rtype: rtype.clone(),
+            ip_addr: Ipv4Addr::new(8, 8, 8, 8).into(),
+            get_domain_is_ok: true,
+            get_domain_is_some: true,
+            get_record_is_ok: true,
+            get_record_is_some: true,
+            update_record_is_ok: true,
+            create_record_is_ok: false,
+            record_ttl: 3600,
+            ttl_updates: RefCell::new(vec![]),
+        });
+
+        // shutting down abandons the wait for the previous TTL, leaving the lowered TTL in place
+        let record = run_dns(
+            client.clone(),
+            domain,
+            record_name,
+            rtype,
+            new_ip_addr.into(),
+            60,
+            Some(0),
+            false,
+            true,
+            false,
+            false,
+            &Hooks::default(),
+            &AtomicBool::new(true),
+            &RunStats::start(),
+            false,
+        )
+        .await;
+
+        assert!(matches!(record, Err(crate::Error::Interrupted())));
+        assert_eq!(vec![0], *client.ttl_updates.borrow());
+    }
+
+    #[tokio::test]
+    async fn test_no_op() {
+        let id = 123;
+        let domain = "google.com".to_string();
+        let record_name = "main".to_string();
+        let rtype = "A".to_string();
+        let ip_addr: IpAddr = Ipv4Addr::new(8, 8, 8, 8).into();
+        let new_ip_addr: IpAddr = Ipv4Addr::new(8, 8, 8, 8).into();
+
+        let client = TestDnsClientImpl {
+            id,
+            domain: domain.clone(),
+            record: record_name.clone(),
+            rtype: rtype.clone(),
+            ip_addr,
+            get_domain_is_ok: true,
+            get_domain_is_some: true,
+            get_record_is_ok: true,
+            get_record_is_some: true,
+            update_record_is_ok: false,
+            create_record_is_ok: false,
+            record_ttl: 60,
+            ttl_updates: RefCell::new(vec![]),
+        };
+
+        let record = run_dns(
+            Rc::new(client),
+            domain.clone(),
+            record_name.clone(),
+            rtype.clone(),
+            new_ip_addr.into(),
+            60,
+            None,
+            false,
+            true,
+            false,
+            false,
+            &Hooks::default(),
+            &AtomicBool::default(),
+            &RunStats::start(),
+            false,
+        )
+        .await;
+
+        assert_eq!(
+            record.unwrap(),
+            DomainRecord {
+                id,
+                typ: rtype,
+                name: record_name,
+                data: new_ip_addr.to_string(),
+                priority: None,
+                port: None,
+                ttl: 60,
+                weight: None,
+                flags: None,
+                tag: None
+            }
+        )
+    }
+
+    #[tokio::test]
+    async fn test_check_dns() {
+        let domain = "google.com".to_string();
+        let record_name = "main".to_string();
+        let rtype = "A".to_string();
+        let ip_addr: IpAddr = Ipv4Addr::new(8, 8, 8, 8).into();
+
+        let client = |get_record_is_some| {
+            Rc::new(TestDnsClientImpl {
+                id: 123,
+                domain: domain.clone(),
+                record: record_name.clone(),
+                rtype: rtype.clone(),
+                ip_addr,
+                get_domain_is_ok: true,
+                get_domain_is_some: true,
+                get_record_is_ok: true,
+                get_record_is_some,
+                update_record_is_ok: false,
+                create_record_is_ok: false,
+                record_ttl: 60,
+                ttl_updates: RefCell::new(vec![]),
+            })
+        };
+
+        assert_eq!(
+            Ok(true),
+            check_dns(
+                client(true),
+                domain.clone(),
+                record_name.clone(),
+                rtype.clone(),
+                ip_addr.into(),
+                60
+            )
+            .await
+            .map_err(|e| e.to_string())
+        );
+        assert_eq!(
+            Ok(false),
+            check_dns(
+                client(true),
+                domain.clone(),
+                record_name.clone(),
+                rtype.clone(),
+                IpAddr::from(Ipv4Addr::new(4, 4, 4, 4)).into(),
+                60
+            )
+            .await
+            .map_err(|e| e.to_string())
+        );
+        assert_eq!(
+            Ok(false),
+            check_dns(
+                client(false),
+                domain.clone(),
+                record_name.clone(),
+                rtype.clone(),
+                ip_addr.into(),
+                60
+            )
+            .await
+            .map_err(|e| e.to_string())
+        );
+        // the record holds the address but with another TTL
+        assert_eq!(
+            Ok(false),
+            check_dns(
+                client(true),
+                domain.clone(),
+                record_name.clone(),
+                rtype.clone(),
+                ip_addr.into(),
+                300
+            )
+            .await
+            .map_err(|e| e.to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_ensure_record_unchanged() {
+        let domain = "google.com".to_string();
+        let record = DomainRecord {
+            id: 123,
+            typ: "A".to_string(),
+            name: "main".to_string(),
+            data: "8.8.8.8".to_string(),
+            priority: None,
+            port: None,
+            ttl: 60,
+            weight: None,
+            flags: None,
+            tag: None,
+        };
+        let client: Rc<dyn DigitalOceanDnsClient> = Rc::new(TestDnsClientImpl {
+            id: 123,
+            domain: domain.clone(),
+            record: record.name.clone(),
+            rtype: record.typ.clone(),
+            ip_addr: Ipv4Addr::new(8, 8, 8, 8).into(),
+            get_domain_is_ok: true,
+            get_domain_is_some: true,
+            get_record_is_ok: true,
+            get_record_is_some: true,
+            update_record_is_ok: false,
+            create_record_is_ok: false,
+            record_ttl: 1,
+            ttl_updates: RefCell::new(vec![]),
+        });
+
+        assert!(ensure_record_unchanged(&client, &domain, &record)
+            .await
+            .is_ok());
+        match ensure_record_unchanged(
+            &client,
+            &domain,
+            &DomainRecord {
+                data: "4.4.4.4".to_string(),
+                ..record.clone()
+            },
+        )
+        .await
+        {
+            Err(crate::Error::ConcurrentModification(_)) => (),
+            x => panic!("Expected concurrent modification error but got {:?}", x),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_verify_record() {
+        let domain = "google.com".to_string();
+        let ip: IpAddr = Ipv4Addr::new(8, 8, 8, 8).into();
+        let written = DomainRecord {
+            id: 123,
+            typ: "A".to_string(),
+            name: "main".to_string(),
+            data: ip.to_string(),
+            priority: None,
+            port: None,
+            ttl: 60,
+            weight: None,
+            flags: None,
+            tag: None,
+        };
+        let client: Rc<dyn DigitalOceanDnsClient> = Rc::new(TestDnsClientImpl {
+            id: 123,
+            domain: domain.clone(),
+            record: written.name.clone(),
+            rtype: written.typ.clone(),
+            ip_addr: ip,
+            get_domain_is_ok: true,
+            get_domain_is_some: true,
+            get_record_is_ok: true,
+            get_record_is_some: true,
+            update_record_is_ok: false,
+            create_record_is_ok: false,
+            record_ttl: 60,
+            ttl_updates: RefCell::new(vec![]),
+        });
+
+        assert!(verify_record(&client, &domain, &written, &ip.into())
+            .await
+            .is_ok());
+        match verify_record(
+            &client,
+            &domain,
+            &DomainRecord {
+                ttl: 300,
+                ..written.clone()
+            },
+            &ip.into(),
+        )
+        .await
+        {
+            Err(crate::Error::VerificationFailed(msg)) => assert!(msg.contains("ttl")),
+            x => panic!("Expected verification failure but got {:?}", x),
+        }
+        match verify_record(
+            &client,
+            &domain,
+            &written,
+            &IpAddr::from(Ipv4Addr::new(4, 4, 4, 4)).into(),
+        )
+        .await
+        {
+            Err(crate::Error::VerificationFailed(msg)) => assert!(msg.contains("data")),
+            x => panic!("Expected verification failure but got {:?}", x),
+        }
+    }
+
+    #[test]
+    fn test_external_owner() {
+        let txt = |name: &str, data: &str| DomainRecord {
+            id: 1,
+            typ: "TXT".to_string(),
+            name: name.to_string(),
+            data: data.to_string(),
+            priority: None,
+            port: None,
+            ttl: 60,
+            weight: None,
+            flags: None,
+            tag: None,
+        };
+        let records = vec![
+            txt("www", "heritage=external-dns,external-dns/owner=default"),
+            txt("a-api", "\"heritage=external-dns,external-dns/owner=k8s\""),
+            txt("db", "managed-by=terraform"),
+            txt("home", "v=spf1 -all"),
+        ];
+
+        assert_eq!(Some("external-dns"), external_owner(&records, "www", "A"));
+        assert_eq!(Some("external-dns"), external_owner(&records, "api", "A"));
+        assert_eq!(None, external_owner(&records, "api", "AAAA"));
+        assert_eq!(Some("Terraform"), external_owner(&records, "db", "AAAA"));
+        assert_eq!(None, external_owner(&records, "home", "A"));
+        assert_eq!(None, external_owner(&records, "vpn", "A"));
+        // names are compared like the record lookup compares them, ignoring case
+        assert_eq!(Some("external-dns"), external_owner(&records, "WWW", "A"));
+        assert_eq!(Some("external-dns"), external_owner(&records, "Api", "A"));
+    }
+
+    #[test]
+    fn test_dns_values_dual_stack() {
+        let v4: IpAddr = Ipv4Addr::new(1, 2, 3, 4).into();
+        let v6: IpAddr = "2001:db8::1".parse().unwrap();
+        let mut dns_args = DnsArgs {
+            record: "home".to_string(),
+            domain: None,
+            rtype: "A".to_string(),
+            data: None,
+            from_droplet: None,
+            from_load_balancer: None,
+            priority: None,
+            port: None,
+            weight: None,
+            ttl: 60,
+            lower_ttl: None,
+            aliases: vec![],
+            with_www: false,
+            check_delegation: false,
+            force: false,
+            create_if_missing: true,
+            create_domain: false,
+            all_domains: false,
+            dual_stack: false,
+            fqdn: false,
+            audit_txt: false,
+            provider: Provider::DigitalOcean,
+        };
+
+        assert_eq!(
+            vec![("A".to_string(), RecordValue::from(v4))],
+            dns_values(&dns_args, &[v4, v6]).unwrap()
+        );
+        // an AAAA record fails on its own rather than being given the IPv4 address
+        dns_args.rtype = "AAAA".to_string();
+        assert!(matches!(
+            dns_values(&dns_args, &[v4]),
+            Err(crate::Error::RecordTypeMismatch { ref rtype, ip }) if rtype == "AAAA" && ip == v4
+        ));
+        dns_args.rtype = "A".to_string();
+        dns_args.dual_stack = true;
+        assert_eq!(
+            vec![
+                ("A".to_string(), RecordValue::from(v4)),
+                ("AAAA".to_string(), RecordValue::from(v6)),
+            ],
+            dns_values(&dns_args, &[v4, v6]).unwrap()
+        );
+        // a family that could not be detected is skipped
+        assert_eq!(
+            vec![("AAAA".to_string(), RecordValue::from(v6))],
+            dns_values(&dns_args, &[v6]).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_with_aliases() {
+        assert_eq!(
+            vec!["@".to_string()],
+            with_aliases("@".to_string(), &[], false)
+        );
+        assert_eq!(
+            vec!["@".to_string(), "www".to_string()],
+            with_aliases("@".to_string(), &[], true)
+        );
+        assert_eq!(
+            vec!["home".to_string(), "www.home".to_string()],
+            with_aliases("home".to_string(), &[], true)
+        );
+        assert_eq!(
+            vec!["@".to_string(), "*".to_string(), "www".to_string()],
+            with_aliases("@".to_string(), &["*".to_string(), "www".to_string()], true)
+        );
+    }
+
+    #[test]
+    fn test_split_fqdn() {
+        let domains = vec!["example.com".to_string(), "sub.example.com".to_string()];
+        assert_eq!(
+            Some(("vpn".to_string(), "example.com".to_string())),
+            split_fqdn("vpn.example.com", &domains)
+        );
+        assert_eq!(
+            Some(("a.b".to_string(), "example.com".to_string())),
+            split_fqdn("a.b.example.com.", &domains)
+        );
+        assert_eq!(
+            Some(("vpn".to_string(), "sub.example.com".to_string())),
+            split_fqdn("VPN.Sub.Example.com", &domains)
+        );
+        assert_eq!(
+            Some(("@".to_string(), "example.com".to_string())),
+            split_fqdn("example.com", &domains)
+        );
+        assert_eq!(None, split_fqdn("vpn.notexample.com", &domains));
+        assert_eq!(None, split_fqdn("vpn.example.org", &domains));
+    }
+
+    #[tokio::test]
+    async fn test_resolve_records_all_domains() {
+        let record = |id: u32, name: &str, typ: &str| DomainRecord {
+            id,
+            typ: typ.to_string(),
+            name: name.to_string(),
+            data: "1.1.1.1".to_string(),
+            priority: None,
+            port: None,
+            ttl: 60,
+            weight: None,
+            flags: None,
+            tag: None,
+        };
+        let client = Rc::new(
+            MockDnsClient::new()
+                .with_domain("example.com")
+                .with_domain("example.net")
+                .with_domain("example.org")
+                .with_record("example.com", record(1, "home", "A"))
+                .with_record("example.net", record(2, "home", "TXT"))
+                .with_record("example.net", record(3, "vpn", "A"))
+                .with_record("example.org", record(4, "home", "A")),
+        );
+        let ip: IpAddr = Ipv4Addr::new(8, 8, 8, 8).into();
+        let dns_args = |name: &str| DnsArgs {
+            record: name.to_string(),
+            domain: None,
+            rtype: "A".to_string(),
+            data: None,
+            from_droplet: None,
+            from_load_balancer: None,
+            priority: None,
+            port: None,
+            weight: None,
+            ttl: 60,
+            lower_ttl: None,
+            aliases: vec![],
+            with_www: false,
+            check_delegation: false,
+            force: false,
+            create_if_missing: true,
+            create_domain: false,
+            all_domains: true,
+            dual_stack: false,
+            fqdn: false,
+            audit_txt: false,
+            provider: Provider::DigitalOcean,
+        };
+        let values = vec![("A".to_string(), RecordValue::from(ip))];
+
+        assert_eq!(
+            vec![
+                ("home".to_string(), "example.com".to_string()),
+                ("home".to_string(), "example.org".to_string())
+            ],
+            resolve_records(client.clone(), &dns_args("home"), &values)
+                .await
+                .unwrap()
+        );
+        assert!(matches!(
+            resolve_records(client, &dns_args("mail"), &values).await,
+            Err(crate::Error::RecordNotFound(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_droplet_ips() {
+        let droplet = |name: &str, ipv6: Option<&str>| -> Droplet {
+            serde_json::from_value(json!({
+                "id": 1,
+                "name": name,
+                "networks": {
+                    "v4": [
+                        {"ip_address": "10.0.0.2", "netmask": "255.255.0.0", "type": "private"},
+                        {"ip_address": "1.2.3.4", "netmask": "255.255.240.0", "type": "public"},
+                    ],
+                    "v6": ipv6
+                        .map(|ip| vec![json!({"ip_address": ip, "netmask": 64, "type": "public"})])
+                        .unwrap_or_default(),
+                },
+            }))
+            .unwrap()
+        };
+        let client = Rc::new(
+            MockDropletClient::new()
+                .with_droplet(droplet("web", Some("2604:a880::1")))
+                .with_droplet(droplet("db", None)),
+        );
+        let dns_args = |rtype: &str, dual_stack: bool| DnsArgs {
+            record: "home".to_string(),
+            domain: Some("example.com".to_string()),
+            rtype: rtype.to_string(),
+            data: None,
+            from_droplet: None,
+            from_load_balancer: None,
+            priority: None,
+            port: None,
+            weight: None,
+            ttl: 60,
+            lower_ttl: None,
+            aliases: vec![],
+            with_www: false,
+            check_delegation: false,
+            force: false,
+            create_if_missing: true,
+            create_domain: false,
+            all_domains: false,
+            dual_stack,
+            fqdn: false,
+            audit_txt: false,
+            provider: Provider::DigitalOcean,
+        };
+        let v4: IpAddr = "1.2.3.4".parse().unwrap();
+        let v6: IpAddr = "2604:a880::1".parse().unwrap();
+
+        assert_eq!(
+            vec![v4],
+            droplet_ips(client.clone(), "web", &dns_args("A", false))
+                .await
+                .unwrap()
+        );
+        assert_eq!(
+            vec![v6],
+            droplet_ips(client.clone(), "web", &dns_args("AAAA", false))
+                .await
+                .unwrap()
+        );
+        assert_eq!(
+            vec![v4, v6],
+            droplet_ips(client.clone(), "web", &dns_args("A", true))
+                .await
+                .unwrap()
+        );
+        assert!(matches!(
+            droplet_ips(client.clone(), "db", &dns_args("AAAA", false)).await,
+            Err(crate::Error::NoPublicAddress(_))
+        ));
+        assert!(matches!(
+            droplet_ips(client, "cache", &dns_args("A", false)).await,
+            Err(crate::Error::DropletNotFound())
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_load_balancer_ips() {
+        let load_balancer = |name: &str, ip: &str| -> Loadbalancer {
+            serde_json::from_value(json!({"id": name, "name": name, "ip": ip})).unwrap()
+        };
+        let client = Rc::new(
+            MockLoadbalancerClient::new()
+                .with_load_balancer(load_balancer("web", "45.55.96.47"))
+                .with_load_balancer(load_balancer("new", "")),
+        );
+        let dns_args = |rtype: &str| DnsArgs {
+            record: "@".to_string(),
+            domain: Some("example.com".to_string()),
+            rtype: rtype.to_string(),
+            data: None,
+            from_droplet: None,
+            from_load_balancer: None,
+            priority: None,
+            port: None,
+            weight: None,
+            ttl: 60,
+            lower_ttl: None,
+            aliases: vec![],
+            with_www: false,
+            check_delegation: false,
+            force: false,
+            create_if_missing: true,
+            create_domain: false,
+            all_domains: false,
+            dual_stack: false,
+            fqdn: false,
+            audit_txt: false,
+            provider: Provider::DigitalOcean,
+        };
+
+        assert_eq!(
+            vec!["45.55.96.47".parse::<IpAddr>().unwrap()],
+            load_balancer_ips(client.clone(), "web", &dns_args("A"))
+                .await
+                .unwrap()
+        );
+        assert!(matches!(
+            load_balancer_ips(client.clone(), "web", &dns_args("AAAA")).await,
+            Err(crate::Error::NoPublicAddress(_))
+        ));
+        assert!(matches!(
+            load_balancer_ips(client.clone(), "new", &dns_args("A")).await,
+            Err(crate::Error::NoPublicAddress(_))
+        ));
+        assert!(matches!(
+            load_balancer_ips(client, "old", &dns_args("A")).await,
+            Err(crate::Error::ObjectNotFound { .. })
+        ));
+    }
+
+    struct TestDnsClientImpl {
+        id: u32,
+        domain: String,
+        record: String,
+        rtype: String,
+        ip_addr: IpAddr,
+        get_domain_is_ok: bool,
+        get_domain_is_some: bool,
+        get_record_is_ok: bool,
+        get_record_is_some: bool,
+        update_record_is_ok: bool,
+        create_record_is_ok: bool,
+        record_ttl: u16,
+        ttl_updates: RefCell<Vec<u16>>,
+    }
+
+    #[async_trait(?Send)]
+    impl DigitalOceanDnsClient for TestDnsClientImpl {
+        async fn get_domains(&self) -> Result<Vec<Domain>, Error> {
+            Ok(self.get_domain("").await?.into_iter().collect())
+        }
+
+        async fn get_domain(&self, _: &str) -> Result<Option<Domain>, Error> {
+            if self.get_domain_is_ok {
+                if self.get_domain_is_some {
+                    Ok(Some(Domain {
+                        name: self.domain.clone(),
+                        ttl: 60,
+                        zone_file: "foobar".to_string(),
+                    }))
+                } else {
+                    Ok(None)
+                }
+            } else {
+                Err(Error::CreateDns(Failure::Unexpected("foo".to_string())))
+            }
+        }
+
+        async fn get_all_records(&self, _: &str) -> Result<Vec<DomainRecord>, Error> {
+            panic!("Unexpected listing of every record")
+        }
+
+        async fn create_domain(&self, _: &str, _: &bool) -> Result<Domain, Error> {
+            panic!("Unexpected creation of a domain")
+        }
+
+        async fn delete_record(&self, _: &str, _: &DomainRecord, _: &bool) -> Result<(), Error> {
+            panic!("Unexpected deletion of a record")
+        }
+
+        async fn get_record(
+            &self,
+            _: &str,
+            _: &str,
+            _: &str,
+        ) -> Result<Option<DomainRecord>, Error> {
+            if self.get_record_is_ok {
+                if self.get_record_is_some {
+                    Ok(Some(DomainRecord {
+                        id: self.id,
+                        typ: self.rtype.clone(),
+                        name: self.record.clone(),
+                        data: self.ip_addr.to_string(),
+                        priority: None,
+                        port: None,
+                        ttl: self.record_ttl,
+                        weight: None,
+                        flags: None,
+                        tag: None,
+                    }))
+                } else {
+                    Ok(None)
+                }
+            } else {
+                Err(Error::CreateDns(Failure::Unexpected("foo".to_string())))
+            }
+        }
+
+        async fn get_records(&self, _: &str, _: &str) -> Result<Vec<DomainRecord>, Error> {
+            Ok(vec![])
+        }
+
+        async fn update_record(
+            &self,
+            _: &str,
+            record: &DomainRecord,
+            value: &RecordValue,
+            ttl: &u16,
+            _dry_run: &bool,
+        ) -> Result<DomainRecord, Error> {
+            if self.update_record_is_ok {
+                Ok(DomainRecord {
+                    id: record.id,
+                    typ: record.typ.clone(),
+                    name: record.name.clone(),
+                    data: value.data.clone(),
+                    priority: None,
+                    port: None,
+                    ttl: *ttl,
+                    weight: None,
+                    flags: None,
+                    tag: None,
+                })
+            } else {
+                Err(Error::UpdateDns(Failure::Unexpected("foo".to_string())))
+            }
+        }
+
+        async fn update_record_ttl(
+            &self,
+            _: &str,
+            record: &DomainRecord,
+            ttl: &u16,
+            _dry_run: &bool,
+        ) -> Result<DomainRecord, Error> {
+            self.ttl_updates.borrow_mut().push(*ttl);
+            Ok(DomainRecord {
+                ttl: *ttl,
+                ..record.clone()
+            })
+        }
+
+        async fn create_record(
+            &self,
+            _: &str,
+            record: &str,
+            rtype: &str,
+            value: &RecordValue,
+            ttl: &u16,
+            _dry_run: &bool,
+        ) -> Result<DomainRecord, Error> {
+            if self.create_record_is_ok {
+                Ok(DomainRecord {
+                    id: 123,
+                    typ: rtype.to_string(),
+                    name: record.to_string(),
+                    data: value.data.clone(),
+                    priority: None,
+                    port: None,
+                    ttl: *ttl,
+                    weight: None,
+                    flags: None,
+                    tag: None,
+                })
+            } else {
+                Err(Error::CreateDns(Failure::Unexpected("foo".to_string())))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod fw_test {
+    use crate::cli::SubcmdArgs;
+    use crate::cli::{Direction, FirewallArgs};
+    use crate::digitalocean::droplet::{
+        DigitalOceanDropletClient, Droplet, DropletImage, DropletNetworks, DropletRegion,
+        DropletSize, DropletSummary,
+    };
+    use crate::digitalocean::error::{Error, Failure};
+    use crate::digitalocean::firewall::{
+        DigitalOceanFirewallClient, Firewall, FirewallInboundRule, FirewallOutboundRule,
+        FirewallRuleTarget,
+    };
+    use crate::digitalocean::kubernetes::{
+        DigitalOceanKubernetesClient, KubernetesCluster, KubernetesClusterStatus,
+    };
+    use crate::digitalocean::loadbalancer::{
+        DigitalOceanLoadbalancerClient, Loadbalancer, LoadbalancerFirewall,
+        LoadbalancerHealthCheck, LoadbalancerRegion, LoadbalancerStickySessions,
+    };
+    use crate::digitalocean::mock::{
+        MockFirewallClient, MockProjectsClient, MockTagsClient, MockVpcClient,
+    };
+    use crate::digitalocean::project::{DigitalOceanProjectsClient, Project, ProjectResource};
+    use crate::digitalocean::tags::{DigitalOceanTagsClient, Tag, TagResources};
+    use crate::digitalocean::vpc::{DigitalOceanVpcClient, Vpc};
+    use crate::hooks::Change;
+    use crate::names::ResourceNames;
+    use crate::state::{now, RetiredIp, TargetState};
+    use crate::Error::{Client, ObjectNotFound, RuleNotFound, TrackedFirewall};
+    use crate::{
+        attach_droplets, build_firewall_args, find_firewall, firewall_address, firewall_change,
+        glob_matches, merge_ids, names_to_ids, ports_match, project_resources,
+        prune_dangling_references, replace_firewall_rules, resolve_firewalls, resolve_rule_targets,
+        rule_addresses, rule_tags, select_rule, tags_to_droplet_ids, targets_match,
+        update_firewall, verify_firewall_rules, with_previous_ips, DigitalOceanClient,
+        UpdateOptions,
+    };
+    use async_trait::async_trait;
+    use std::cell::Cell;
+    use std::collections::HashSet;
+    use std::net::{IpAddr, Ipv4Addr};
+    use std::rc::Rc;
+
+    /// A client with the given firewall, droplet, Kubernetes, load balancer, and VPC clients
+    fn test_client(
+        fw_client: Rc<dyn DigitalOceanFirewallClient>,
+        droplet_client: Rc<dyn DigitalOceanDropletClient>,
+        kubernetes_client: Rc<dyn DigitalOceanKubernetesClient>,
+        load_balancer_client: Rc<dyn DigitalOceanLoadbalancerClient>,
+        vpc_client: Rc<dyn DigitalOceanVpcClient>,
+    ) -> DigitalOceanClient {
+        let mut client =
+            DigitalOceanClient::new_for_test("foo".to_string(), "http://localhost".to_string());
+        client.firewall = fw_client;
+        client.droplet = droplet_client;
+        client.kubernetes = kubernetes_client;
+        client.load_balancer = load_balancer_client;
+        client.project = Rc::new(MockProjectsClient::new());
+        client.tags = Rc::new(MockTagsClient::new());
+        client.vpc = vpc_client;
+        client
+    }
+
+    /// A target updating the rule for one port and protocol of a firewall with nothing but the
+    /// detected address
+    fn rule_args(
+        name: String,
+        direction: Direction,
+        port: String,
+        protocol: String,
+    ) -> FirewallArgs {
+        FirewallArgs {
+            name,
+            all_matching: false,
+            direction,
+            port,
+            protocols: vec![protocol],
+            addresses: None,
+            vpcs: None,
+            droplets: None,
+            droplet_tags: None,
+            kubernetes_clusters: None,
+            load_balancers: None,
+            project: None,
+            prune_dangling: false,
+            dual_stack: false,
+            ipv6_prefix: None,
+            old_ips: None,
+            retain_days: None,
+            no_verify: false,
+            atomic: false,
+            rule_index: None,
+            match_source_tag: None,
+            tags: None,
+            replace_tags: false,
+        }
+    }
+
+    /// Look up the firewall and resolve the target's names, then build the rule for its port and
+    /// protocol the way a run does
+    #[allow(clippy::type_complexity)]
+    async fn build_rules(
+        client: DigitalOceanClient,
+        fw_args: FirewallArgs,
+        ips: &[IpAddr],
+    ) -> Result<
+        (
+            Firewall,
+            Option<(FirewallInboundRule, FirewallInboundRule)>,
+            Option<(FirewallOutboundRule, FirewallOutboundRule)>,
+        ),
+        crate::Error,
+    > {
+        let firewall = find_firewall(&client, &fw_args.name, None).await?;
+        let targets = resolve_rule_targets(&client, &fw_args).await?;
+        let (inbound_rule, outbound_rule) = build_firewall_args(
+            &client,
+            &fw_args,
+            &firewall,
+            &targets,
+            &fw_args.port,
+            &fw_args.protocols[0],
+            ips,
+        )
+        .await?;
+        Ok((firewall, inbound_rule, outbound_rule))
+    }
+
+    #[tokio::test]
+    async fn test_attach_droplets() {
+        let fw_client = Rc::new(MockFirewallClient::new().with_firewall(Firewall {
+            id: "fw".to_string(),
+            status: "succeeded".to_string(),
+            created_at: "".to_string(),
+            pending_changes: vec![],
+            name: "web".to_string(),
+            droplet_ids: Some(vec![1, 2]),
+            tags: None,
+            inbound_rules: None,
+            outbound_rules: None,
+        }));
+        let droplet_client = Rc::new(TestDropletSummaryClientImpl {
+            droplets: vec![(1, "web-1"), (2, "web-2"), (3, "web-3")],
+        });
+        let attach = |droplets: &[&str], exclusive: bool, dry_run: bool| {
+            attach_droplets(
+                fw_client.clone(),
+                droplet_client.clone(),
+                "web".to_string(),
+                droplets.iter().map(|d| d.to_string()).collect(),
+                exclusive,
+                dry_run,
+            )
+        };
+
+        attach(&["web-1", "web-3"], false, true).await.unwrap();
+        assert_eq!(Some(vec![1, 2]), fw_client.firewalls()[0].droplet_ids);
+
+        attach(&["web-1", "web-3"], false, false).await.unwrap();
+        assert_eq!(Some(vec![1, 2, 3]), fw_client.firewalls()[0].droplet_ids);
+
+        attach(&["web-3"], true, false).await.unwrap();
+        assert_eq!(Some(vec![3]), fw_client.firewalls()[0].droplet_ids);
+
+        assert!(matches!(
+            attach(&["db"], false, false).await,
+            Err(ObjectNotFound { .. })
+        ));
+    }
+
+    #[test]
+    fn test_with_previous_ips() {
+        let target = SubcmdArgs::Firewall(rule_args(
+            "web".to_string(),
+            Direction::Inbound,
+            "22".to_string(),
+            "tcp".to_string(),
+        ));
+        let previous = |target: &SubcmdArgs| TargetState {
+            target: target.clone(),
+            ips: vec!["1.2.3.4".parse().unwrap()],
+            published_at: 0,
+            confirmed_at: 0,
+            retired: vec![
+                RetiredIp {
+                    ip: "5.6.7.8".parse().unwrap(),
+                    retired_at: now() - 3 * 86400,
+                },
+                RetiredIp {
+                    ip: "9.10.11.12".parse().unwrap(),
+                    retired_at: now() - 86400,
+                },
+            ],
+        };
+        let old_ips = |target: SubcmdArgs| match target {
+            SubcmdArgs::Firewall(fw_args) => fw_args.old_ips,
+            _ => unreachable!(),
+        };
+
+        // without a retention period the current address is replaced at once
+        assert_eq!(
+            Some(vec!["1.2.3.4".parse::<IpAddr>().unwrap()]),
+            old_ips(with_previous_ips(target.clone(), &previous(&target)))
+        );
+
+        // with one, only the addresses retired longer ago than that are removed
+        let retaining = match target {
+            SubcmdArgs::Firewall(fw_args) => SubcmdArgs::Firewall(FirewallArgs {
+                retain_days: Some(2),
+                ..fw_args
+            }),
+            _ => unreachable!(),
+        };
+        assert_eq!(
+            Some(vec!["5.6.7.8".parse::<IpAddr>().unwrap()]),
+            old_ips(with_previous_ips(retaining.clone(), &previous(&retaining)))
+        );
+    }
+
+    #[test]
+    fn test_glob_matches() {
+        assert!(glob_matches("home-*", "home-droplets"));
+        assert!(glob_matches("home-*", "home-"));
+        assert!(glob_matches("*-k8s", "home-lab-k8s"));
+        assert!(glob_matches("home-?", "home-1"));
+        assert!(glob_matches("*a*b*", "xaybzb"));
+        assert!(glob_matches("web", "web"));
+        assert!(!glob_matches("home-*", "office-droplets"));
+        assert!(!glob_matches("home-?", "home-12"));
+        assert!(!glob_matches("web", "web-1"));
+    }
+
+    #[tokio::test]
+    async fn test_resolve_firewalls() {
+        let firewall = |id: &str, name: &str| Firewall {
+            id: id.to_string(),
+            status: "succeeded".to_string(),
+            created_at: "".to_string(),
+            pending_changes: vec![],
+            name: name.to_string(),
+            droplet_ids: None,
+            tags: None,
+            inbound_rules: None,
+            outbound_rules: None,
+        };
+        let fw_client = Rc::new(
+            MockFirewallClient::new()
+                .with_firewall(firewall("fw1", "home-droplets"))
+                .with_firewall(firewall("fw2", "office"))
+                .with_firewall(firewall("fw3", "home-k8s")),
+        );
+        let fw_args = |name: &str, all_matching: bool| FirewallArgs {
+            name: name.to_string(),
+            all_matching,
+            direction: Direction::Inbound,
+            port: "22".to_string(),
+            protocols: vec!["tcp".to_string()],
+            addresses: None,
+            vpcs: None,
+            droplets: None,
+            droplet_tags: None,
+            kubernetes_clusters: None,
+            load_balancers: None,
+            project: None,
+            prune_dangling: false,
+            dual_stack: false,
+            ipv6_prefix: None,
+            old_ips: None,
+            retain_days: None,
+            no_verify: false,
+            atomic: false,
+            rule_index: None,
+            match_source_tag: None,
+            tags: None,
+            replace_tags: false,
+        };
+
+        assert_eq!(
+            vec![fw_args("home-droplets", false), fw_args("home-k8s", false)],
+            resolve_firewalls(fw_client.clone(), fw_args("home-*", true))
+                .await
+                .unwrap()
+        );
+        // without --all-matching the name is used as given
+        assert_eq!(
+            vec![fw_args("home-*", false)],
+            resolve_firewalls(fw_client.clone(), fw_args("home-*", false))
+                .await
+                .unwrap()
+        );
+        assert!(matches!(
+            resolve_firewalls(fw_client, fw_args("lab-*", true)).await,
+            Err(ObjectNotFound { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_find_firewall_by_tracked_id() {
+        let firewall = |id: &str, name: &str| Firewall {
+            id: id.to_string(),
+            status: "succeeded".to_string(),
+            created_at: "".to_string(),
+            pending_changes: vec![],
+            name: name.to_string(),
+            droplet_ids: None,
+            tags: None,
+            inbound_rules: None,
+            outbound_rules: None,
+        };
+        let mut client =
+            DigitalOceanClient::new_for_test("foo".to_string(), "http://localhost".to_string());
+        client.firewall = Rc::new(
+            MockFirewallClient::new()
+                .with_firewall(firewall("fw1", "home"))
+                .with_firewall(firewall("fw2", "office")),
+        );
+
+        let found = |result: Result<Firewall, crate::Error>| result.map(|firewall| firewall.id);
+        assert_eq!(
+            "fw1",
+            found(find_firewall(&client, "home", None).await).unwrap()
+        );
+        assert_eq!(
+            "fw1",
+            found(find_firewall(&client, "home", Some("fw1")).await).unwrap()
+        );
+        // another firewall that took the name of a tracked one is not matched
+        assert!(matches!(
+            find_firewall(&client, "office", Some("fw1")).await,
+            Err(TrackedFirewall(ref e)) if e.contains("renamed to home")
+        ));
+        assert!(matches!(
+            find_firewall(&client, "home", Some("fw9")).await,
+            Err(TrackedFirewall(ref e)) if e.contains("is missing")
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_names_to_ids_in_project() {
+        let project = |id: &str, name: &str| Project {
+            id: id.to_string(),
+            name: name.to_string(),
+            description: "".to_string(),
+            purpose: "".to_string(),
+            environment: "".to_string(),
+            is_default: false,
+        };
+        let resource = |urn: String| ProjectResource {
+            urn,
+            assigned_at: "".to_string(),
+            status: "ok".to_string(),
+        };
+        let project_client: Rc<dyn DigitalOceanProjectsClient> = Rc::new(
+            MockProjectsClient::new()
+                .with_project(
+                    project("1", "home"),
+                    vec![resource(ProjectResource::droplet_urn(2))],
+                )
+                .with_project(
+                    project("2", "lab"),
+                    vec![resource(ProjectResource::droplet_urn(1))],
+                ),
+        );
+        let scope = project_resources(&project_client, "home").await.unwrap();
+        assert_eq!(HashSet::from(["do:droplet:2".to_string()]), scope);
+        assert!(matches!(
+            project_resources(&project_client, "work").await,
+            Err(ObjectNotFound {
+                kind: "project",
+                ..
+            })
+        ));
+
+        // the same name is used in both projects, but only the droplet in the project is picked
+        let droplets = || async {
+            Ok(vec![
+                DropletSummary {
+                    id: 1,
+                    name: "web".to_string(),
+                },
+                DropletSummary {
+                    id: 2,
+                    name: "web".to_string(),
+                },
+            ])
+        };
+        assert_eq!(
+            Some(vec![2]),
+            names_to_ids(
+                "droplet",
+                droplets,
+                Some(vec!["web".to_string()]),
+                |d| scope.contains(&ProjectResource::droplet_urn(d.id)),
+                |d| d.name.clone(),
+                |d| d.id,
+            )
+            .await
+            .unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_tags_to_droplet_ids() {
+        struct TaggedDropletClient {}
+
+        #[async_trait(?Send)]
+        impl DigitalOceanDropletClient for TaggedDropletClient {
+            async fn get_droplets(&self) -> Result<Vec<Droplet>, Error> {
+                panic!("Should not list every droplet when looking up tagged droplets")
+            }
+
+            async fn get_droplet_summaries_by_tag(
+                &self,
+                tag: &str,
+            ) -> Result<Vec<DropletSummary>, Error> {
+                let ids: &[u32] = match tag {
+                    "web" => &[1, 2],
+                    "db" => &[2, 3],
+                    _ => &[],
+                };
+                Ok(ids
+                    .iter()
+                    .map(|id| DropletSummary {
+                        id: *id,
+                        name: format!("droplet-{id}"),
+                    })
+                    .collect())
+            }
+        }
+
+        let tag = |name: &str| Tag {
+            name: name.to_string(),
+            resources: TagResources::default(),
+        };
+        let tags_client: Rc<dyn DigitalOceanTagsClient> = Rc::new(
+            MockTagsClient::new()
+                .with_tag(tag("web"))
+                .with_tag(tag("db"))
+                .with_tag(tag("unused")),
+        );
+        let droplet_client: Rc<dyn DigitalOceanDropletClient> = Rc::new(TaggedDropletClient {});
+        let tags = |tags: &[&str]| Some(tags.iter().map(|t| t.to_string()).collect());
+
+        assert_eq!(
+            None,
+            tags_to_droplet_ids(&tags_client, &droplet_client, None, |_| true)
+                .await
+                .unwrap()
+        );
+        assert_eq!(
+            Some(vec![1, 2, 3]),
+            merge_ids(
+                None,
+                tags_to_droplet_ids(&tags_client, &droplet_client, tags(&["web", "db"]), |_| {
+                    true
+                })
+                .await
+                .unwrap()
+            )
+        );
+        assert_eq!(
+            Some(vec![2]),
+            tags_to_droplet_ids(&tags_client, &droplet_client, tags(&["web"]), |d| d.id != 1)
+                .await
+                .unwrap()
+        );
+        assert_eq!(
+            Some(vec![]),
+            tags_to_droplet_ids(&tags_client, &droplet_client, tags(&["unused"]), |_| true)
+                .await
+                .unwrap()
+        );
+        assert!(matches!(
+            tags_to_droplet_ids(&tags_client, &droplet_client, tags(&["nope"]), |_| true).await,
+            Err(ObjectNotFound { kind: "tag", .. })
+        ));
+    }
+
+    #[test]
+    fn test_merge_ids() {
+        assert_eq!(None, merge_ids::<u32>(None, None));
+        assert_eq!(Some(vec![1, 2]), merge_ids(Some(vec![1, 2]), None));
+        assert_eq!(Some(vec![3]), merge_ids(None, Some(vec![3])));
+        assert_eq!(
+            Some(vec![1, 2, 3]),
+            merge_ids(Some(vec![1, 2]), Some(vec![2, 3]))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_translate_args_basic_in() {
+        base_translate_inbound_test(None, None, None, None).await
+    }
+
+    #[tokio::test]
+    async fn test_translate_args_basic_out() {
+        let fw_id = "foo".to_string();
+        let fw_name = "Foo".to_string();
+        let fw_addrs = Some(vec!["1.1.1.1".to_string()]);
+        let fw_tags = Some(vec!["bar".to_string()]);
+        let host_addr = Ipv4Addr::new(8, 8, 8, 8);
+        let expected_addrs = vec![host_addr.to_string()];
+        let curr_inbound_rule = None;
+        let curr_outbound_rule = FirewallOutboundRule {
+            protocol: "http".to_string(),
+            ports: "80".to_string(),
+            destinations: FirewallRuleTarget {
+                addresses: fw_addrs.clone(),
+                droplet_ids: None,
+                load_balancer_uids: None,
+                kubernetes_ids: None,
+                tags: fw_tags.clone(),
+            },
+        };
+        let firewall = Firewall {
+            id: fw_id.clone(),
+            status: "succeeded".to_string(),
+            created_at: "2024-01-01T00:00Z".to_string(),
+            pending_changes: vec![],
+            name: fw_name.clone(),
+            droplet_ids: None,
+            tags: None,
+            inbound_rules: curr_inbound_rule,
+            outbound_rules: Some(vec![curr_outbound_rule.clone()]),
+        };
+
+        let fw_client = TestFwClientImpl {
+            expected_get_firewall_name: Some(fw_name.clone()),
+            firewall: Some(firewall.clone()),
+            expected_delete_firewall_id: None,
+            expected_delete_inbound_rules: None,
+            expected_delete_outbound_rules: None,
+            delete_rule_is_ok: false,
+            expected_add_firewall_id: None,
+            expected_add_inbound_rules: None,
+            expected_add_outbound_rules: None,
+            add_rule_is_ok: false,
+            rolled_back: Cell::new(false),
+        };
+        let droplet_client = TestDropletClientImpl { droplets: vec![] };
+        let kubernetes_client = TestKubeClientImpl { clusters: vec![] };
+        let load_balancer_client = TestLbClientImpl {
+            loadbalancers: vec![],
+        };
+
+        match build_rules(
+            test_client(
+                Rc::new(fw_client),
+                Rc::new(droplet_client),
+                Rc::new(kubernetes_client),
+                Rc::new(load_balancer_client),
+                Rc::new(MockVpcClient::new()),
+            ),
+            rule_args(
+                fw_name,
+                Direction::Outbound,
+                "80".to_string(),
+                "http".to_string(),
+            ),
+            &[IpAddr::V4(host_addr)],
+        )
+        .await
+        .expect("Unexpected failure in build_firewall_args")
+        {
+            (actual_fw, None, Some((actual_curr_outbound_rule, actual_new_outbound_rule))) => {
+                assert_eq!(firewall, actual_fw);
+                assert_eq!(curr_outbound_rule, actual_curr_outbound_rule);
+                assert_eq!(
+                    FirewallOutboundRule {
+                        protocol: curr_outbound_rule.protocol,
+                        ports: curr_outbound_rule.ports,
+                        destinations: FirewallRuleTarget {
+                            addresses: Some(expected_addrs),
+                            droplet_ids: None,
+                            load_balancer_uids: None,
+                            kubernetes_ids: None,
+                            tags: curr_outbound_rule.destinations.tags,
+                        },
+                    },
+                    actual_new_outbound_rule
+                );
+            }
+            x => panic!(
+                "Failed to get correct return values from build_firewall_args (got {:?}",
+                x
+            ),
+        };
+    }
+
+    #[tokio::test]
+    async fn test_translate_args_vpc() {
+        let fw_id = "foo".to_string();
+        let fw_name = "Foo".to_string();
+        let fw_addrs = Some(vec!["1.1.1.1".to_string()]);
+        let fw_tags = Some(vec!["bar".to_string()]);
+        let host_addr = Ipv4Addr::new(8, 8, 8, 8);
+        let vpc = Vpc {
+            id: "5a4981aa-9653-4bd1-bef5-d6bff52042e4".to_string(),
+            name: "office".to_string(),
+            ip_range: "10.10.10.0/24".to_string(),
+            region: "nyc1".to_string(),
+            description: "".to_string(),
+            urn: "".to_string(),
+            default: false,
+            created_at: "".to_string(),
+        };
+        let expected_addrs = vec![vpc.ip_range.clone(), host_addr.to_string()];
+        let curr_inbound_rule = None;
+        let curr_outbound_rule = FirewallOutboundRule {
+            protocol: "http".to_string(),
+            ports: "80".to_string(),
+            destinations: FirewallRuleTarget {
+                addresses: fw_addrs.clone(),
+                droplet_ids: None,
+                load_balancer_uids: None,
+                kubernetes_ids: None,
+                tags: fw_tags.clone(),
+            },
+        };
+        let firewall = Firewall {
+            id: fw_id.clone(),
+            status: "succeeded".to_string(),
+            created_at: "2024-01-01T00:00Z".to_string(),
+            pending_changes: vec![],
+            name: fw_name.clone(),
+            droplet_ids: None,
+            tags: None,
+            inbound_rules: curr_inbound_rule,
+            outbound_rules: Some(vec![curr_outbound_rule.clone()]),
+        };
+
+        let fw_client = TestFwClientImpl {
+            expected_get_firewall_name: Some(fw_name.clone()),
+            firewall: Some(firewall.clone()),
+            expected_delete_firewall_id: None,
+            expected_delete_inbound_rules: None,
+            expected_delete_outbound_rules: None,
+            delete_rule_is_ok: false,
+            expected_add_firewall_id: None,
+            expected_add_inbound_rules: None,
+            expected_add_outbound_rules: None,
+            add_rule_is_ok: false,
+            rolled_back: Cell::new(false),
+        };
+        let droplet_client = TestDropletClientImpl { droplets: vec![] };
+        let kubernetes_client = TestKubeClientImpl { clusters: vec![] };
+        let load_balancer_client = TestLbClientImpl {
+            loadbalancers: vec![],
+        };
+
+        match build_rules(
+            test_client(
+                Rc::new(fw_client),
+                Rc::new(droplet_client),
+                Rc::new(kubernetes_client),
+                Rc::new(load_balancer_client),
+                Rc::new(MockVpcClient::new().with_vpc(vpc.clone())),
+            ),
+            FirewallArgs {
+                vpcs: Some(vec![vpc.name.clone()]),
+                ..rule_args(
+                    fw_name,
+                    Direction::Outbound,
+                    "80".to_string(),
+                    "http".to_string(),
+                )
+            },
+            &[IpAddr::V4(host_addr)],
+        )
+        .await
+        .expect("Unexpected failure in build_firewall_args")
+        {
+            (actual_fw, None, Some((actual_curr_outbound_rule, actual_new_outbound_rule))) => {
+                assert_eq!(firewall, actual_fw);
+                assert_eq!(curr_outbound_rule, actual_curr_outbound_rule);
+                assert_eq!(
+                    FirewallOutboundRule {
+                        protocol: curr_outbound_rule.protocol,
+                        ports: curr_outbound_rule.ports,
+                        destinations: FirewallRuleTarget {
+                            addresses: Some(expected_addrs),
+                            droplet_ids: None,
+                            load_balancer_uids: None,
+                            kubernetes_ids: None,
+                            tags: curr_outbound_rule.destinations.tags,
+                        },
+                    },
+                    actual_new_outbound_rule
+                );
+            }
+            x => panic!(
+                "Failed to get correct return values from build_firewall_args (got {:?}",
+                x
+            ),
+        };
+    }
+
+    #[tokio::test]
+    async fn test_translate_args_not_found() {
+        let fw_name = "Foo".to_string();
+        let firewall = Firewall {
+            id: "foo".to_string(),
+            status: "succeeded".to_string(),
+            created_at: "2024-01-01T00:00Z".to_string(),
+            pending_changes: vec![],
+            name: fw_name.clone(),
+            droplet_ids: None,
+            tags: None,
+            inbound_rules: Some(vec![FirewallInboundRule {
+                protocol: "http".to_string(),
+                ports: "80".to_string(),
+                sources: FirewallRuleTarget {
+                    addresses: None,
+                    droplet_ids: None,
+                    load_balancer_uids: None,
+                    kubernetes_ids: None,
+                    tags: None,
+                },
+            }]),
+            outbound_rules: None,
+        };
+        let ips = [IpAddr::V4(Ipv4Addr::new(8, 8, 8, 8))];
+        let build = |direction: Direction, port: &str, droplet_names: Option<Vec<String>>| {
+            build_rules(
+                test_client(
+                    Rc::new(TestFwClientImpl {
+                        expected_get_firewall_name: Some(fw_name.clone()),
+                        firewall: Some(firewall.clone()),
+                        expected_delete_firewall_id: None,
+                        expected_delete_inbound_rules: None,
+                        expected_delete_outbound_rules: None,
+                        delete_rule_is_ok: false,
+                        expected_add_firewall_id: None,
+                        expected_add_inbound_rules: None,
+                        expected_add_outbound_rules: None,
+                        add_rule_is_ok: false,
+                        rolled_back: Cell::new(false),
+                    }),
+                    Rc::new(TestDropletClientImpl { droplets: vec![] }),
+                    Rc::new(TestKubeClientImpl { clusters: vec![] }),
+                    Rc::new(TestLbClientImpl {
+                        loadbalancers: vec![],
+                    }),
+                    Rc::new(MockVpcClient::new()),
+                ),
+                FirewallArgs {
+                    droplets: droplet_names,
+                    ..rule_args(
+                        fw_name.clone(),
+                        direction,
+                        port.to_string(),
+                        "http".to_string(),
+                    )
+                },
+                &ips,
+            )
+        };
+
+        match build(Direction::Inbound, "443", None).await {
+            Err(RuleNotFound {
+                firewall,
+                direction: Direction::Inbound,
+                port,
+                protocol,
+            }) => {
+                assert_eq!(fw_name, firewall);
+                assert_eq!("443", port);
+                assert_eq!("http", protocol);
+            }
+            x => panic!("Expected RuleNotFound, got {:?}", x),
+        }
+        assert!(matches!(
+            build(Direction::Outbound, "80", None).await,
+            Err(RuleNotFound {
+                direction: Direction::Outbound,
+                ..
+            })
+        ));
+        match build(Direction::Inbound, "80", Some(vec!["snake".to_string()])).await {
+            Err(e @ ObjectNotFound { .. }) => assert_eq!(
+                "no droplet named snake exists; check the name for typos",
+                e.to_string()
+            ),
+            x => panic!("Expected ObjectNotFound, got {:?}", x),
+        }
+    }
+
+    #[test]
+    fn test_rule_tags() {
+        let tags = |tags: &[&str]| Some(tags.iter().map(|t| t.to_string()).collect::<Vec<_>>());
+        let current = tags(&["web", "office"]);
+
+        assert_eq!(current, rule_tags(&current, &None, false));
+        assert_eq!(current, rule_tags(&current, &None, true));
+        assert_eq!(
+            tags(&["web", "office", "vpn"]),
+            rule_tags(&current, &tags(&["office", "vpn"]), false)
+        );
+        assert_eq!(
+            tags(&["office", "vpn"]),
+            rule_tags(&current, &tags(&["office", "vpn"]), true)
+        );
+        assert_eq!(tags(&["vpn"]), rule_tags(&None, &tags(&["vpn"]), false));
+    }
+
+    #[test]
+    fn test_select_rule() {
+        let rule = |address: &str, tag: Option<&str>| FirewallInboundRule {
+            protocol: "tcp".to_string(),
+            ports: "22".to_string(),
+            sources: FirewallRuleTarget {
+                addresses: Some(vec![address.to_string()]),
+                droplet_ids: None,
+                load_balancer_uids: None,
+                kubernetes_ids: None,
+                tags: tag.map(|t| vec![t.to_string()]),
+            },
+        };
+        let rules = [
+            rule("1.1.1.1", None),
+            rule("2.2.2.2", Some("office")),
+            rule("3.3.3.3", Some("office")),
+        ];
+        let select = |rule_index: Option<u32>, match_tag: Option<&str>| {
+            select_rule(
+                rules.iter().collect(),
+                |rule| &rule.sources,
+                rule_index,
+                match_tag,
+            )
+            .cloned()
+        };
+
+        assert_eq!(Some(rules[0].clone()), select(None, None));
+        assert_eq!(Some(rules[1].clone()), select(Some(2), None));
+        assert_eq!(Some(rules[1].clone()), select(None, Some("office")));
+        assert_eq!(Some(rules[2].clone()), select(Some(2), Some("office")));
+        assert_eq!(None, select(Some(4), None));
+        assert_eq!(None, select(Some(0), None));
+        assert_eq!(None, select(None, Some("home")));
+    }
+
+    #[test]
+    fn test_rule_addresses() {
+        let strings = |v: &[&str]| Some(v.iter().map(|s| s.to_string()).collect::<Vec<_>>());
+        let current = strings(&["1.1.1.1", "2.2.2.2", "2001:db8:0:1::/64"]);
+        let ips: Vec<IpAddr> = vec![
+            "3.3.3.3".parse().unwrap(),
+            "2001:db8:0:2::1".parse().unwrap(),
+        ];
+
+        // without the old addresses the rule's addresses are replaced
+        assert_eq!(
+            strings(&["9.9.9.9", "3.3.3.3", "2001:db8:0:2::/64"]).unwrap(),
+            rule_addresses(&current, &strings(&["9.9.9.9"]), &ips, &None, Some(64))
+        );
+        // with them only the stale addresses are removed
+        assert_eq!(
+            strings(&["1.1.1.1", "3.3.3.3", "2001:db8:0:2::/64"]).unwrap(),
+            rule_addresses(
+                &current,
+                &strings(&["1.1.1.1"]),
+                &ips,
+                &Some(vec![
+                    "2.2.2.2".parse().unwrap(),
+                    "2001:db8:0:1::1".parse().unwrap()
+                ]),
+                Some(64)
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn test_translate_args_addresses() {
+        base_translate_inbound_test(Some(vec!["1.1.1.1".to_string()]), None, None, None).await
+    }
+
+    #[tokio::test]
+    #[allow(deprecated)]
+    async fn test_translate_args_droplet() {
+        let droplet_id: u32 = 42;
+        let droplet_name = "snake".to_string();
+        base_translate_inbound_test(
+            None,
+            Some((
+                vec![Droplet {
+                    id: droplet_id,
+                    name: droplet_name.clone(),
+                    memory: 0,
+                    vcpus: 0,
+                    disk: 0,
+                    locked: false,
+                    status: "".to_string(),
+                    kernel: None,
+                    created_at: "".to_string(),
+                    features: vec![],
+                    backup_ids: vec![],
+                    next_backup_window: None,
+                    snapshot_ids: vec![],
+                    image: DropletImage {
+                        id: 0,
+                        name: "".to_string(),
+                        typ: "".to_string(),
+                        distribution: "".to_string(),
+                        slug: None,
+                        public: false,
+                        regions: vec![],
+                        created_at: "".to_string(),
+                        min_disk_size: None,
+                        size_gigabytes: None,
+                        description: None,
+                        tags: vec![],
+                        status: "".to_string(),
+                        error_message: None,
+                    },
+                    volume_ids: vec![],
+                    size: DropletSize {
+                        slug: "".to_string(),
+                        memory: 0,
+                        vcpus: 0,
+                        disk: 0,
+                        transfer: 0.0,
+                        price_monthly: 0.0,
+                        price_hourly: 0.0,
+                        regions: vec![],
+                        available: false,
+                        description: "".to_string(),
+                    },
+                    size_slug: "".to_string(),
+                    networks: DropletNetworks {
+                        v4: vec![],
+                        v6: vec![],
+                    },
+                    region: DropletRegion {
+                        name: "".to_string(),
+                        slug: "".to_string(),
+                        features: vec![],
+                        available: false,
+                        sizes: vec![],
+                    },
+                    tags: vec![],
+                    vpc_uuid: "".to_string(),
+                }],
+                vec![droplet_name],
+                vec![droplet_id],
+            )),
+            None,
+            None,
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn test_translate_args_kube() {
+        let kube_name = "foo".to_string();
+        let kube_id = "123-456-789".to_string();
+        base_translate_inbound_test(
+            None,
+            None,
+            Some((
+                vec![KubernetesCluster {
+                    id: kube_id.clone(),
+                    name: kube_name.clone(),
+                    region: "".to_string(),
+                    version: "".to_string(),
+                    cluster_subnet: "".to_string(),
+                    service_subnet: "".to_string(),
+                    vpc_uuid: "".to_string(),
+                    ipv4: None,
+                    endpoint: "".to_string(),
+                    tags: vec![],
+                    node_pools: vec![],
+                    maintenance_policy: None,
+                    auto_upgrade: false,
+                    status: KubernetesClusterStatus {
+                        state: "".to_string(),
+                        message: None,
+                    },
+                    created_at: "".to_string(),
+                    updated_at: "".to_string(),
+                    surge_upgrade: false,
+                    ha: false,
+                    registry_enabled: false,
+                }],
+                vec![kube_name],
+                vec![kube_id],
+            )),
+            None,
+        )
+        .await
+    }
+
+    #[tokio::test]
+    #[allow(deprecated)]
+    async fn test_translate_args_lb() {
+        let lb_name = "foo".to_string();
+        let lb_id = "123-456-789".to_string();
+        base_translate_inbound_test(
+            None,
+            None,
+            None,
+            Some((
+                vec![Loadbalancer {
+                    id: lb_id.clone(),
+                    name: lb_name.clone(),
+                    project_id: "".to_string(),
+                    ip: "".to_string(),
+                    size_unit: 0,
+                    size: None,
+                    algorithm: None,
+                    status: "".to_string(),
+                    created_at: "".to_string(),
+                    forwarding_rules: vec![],
+                    health_check: LoadbalancerHealthCheck {
+                        protocol: "".to_string(),
+                        port: 0,
+                        path: "".to_string(),
+                        check_interval_seconds: 0,
+                        response_timeout_seconds: 0,
+                        unhealthy_threshold: 0,
+                        healthy_threshold: 0,
+                    },
+                    sticky_sessions: LoadbalancerStickySessions {
+                        typ: "".to_string(),
+                        cookie_name: None,
+                        cookie_ttl_seconds: None,
+                    },
+                    redirect_http_to_https: false,
+                    enable_proxy_protocol: false,
+                    enable_backend_keepalive: false,
+                    http_idle_timeout_seconds: 0,
+                    vpc_uuid: "".to_string(),
+                    disable_lets_encrypt_dns_records: false,
+                    firewall: LoadbalancerFirewall {
+                        deny: vec![],
+                        allow: vec![],
+                    },
+                    region: LoadbalancerRegion {
+                        name: "".to_string(),
+                        slug: "".to_string(),
+                        features: vec![],
+                        available: false,
+                        sizes: vec![],
+                    },
+                    droplet_ids: vec![],
+                    tag: "".to_string(),
+                }],
+                vec![lb_name],
+                vec![lb_id],
+            )),
+        )
+        .await
+    }
+
+    async fn base_translate_inbound_test(
+        extra_addrs: Option<Vec<String>>,
+        droplet_data: Option<(Vec<Droplet>, Vec<String>, Vec<u32>)>,
+        kube_data: Option<(Vec<KubernetesCluster>, Vec<String>, Vec<String>)>,
+        lb_data: Option<(Vec<Loadbalancer>, Vec<String>, Vec<String>)>,
+    ) {
+        let (droplets, droplet_names, droplet_ids) = match droplet_data {
+            Some((d, n, i)) => (Some(d), Some(n), Some(i)),
+            None => (None, None, None),
+        };
+        let (kube_clusters, kube_cluster_names, kube_cluster_ids) = match kube_data {
+            Some((k, n, i)) => (Some(k), Some(n), Some(i)),
+            None => (None, None, None),
+        };
+        let (lbs, lb_names, lb_ids) = match lb_data {
+            Some((l, n, i)) => (Some(l), Some(n), Some(i)),
+            None => (None, None, None),
+        };
+
+        let fw_id = "foo".to_string();
+        let fw_name = "Foo".to_string();
+        let fw_addrs = Some(vec!["1.1.1.1".to_string()]);
+        let fw_tags = Some(vec!["bar".to_string()]);
+        let host_addr = Ipv4Addr::new(8, 8, 8, 8);
+        let expected_addrs = {
+            let mut expected_addrs: Vec<String> = Vec::new();
+            if let Some(addrs) = extra_addrs.clone() {
+                addrs.iter().for_each(|a| expected_addrs.push(a.clone()))
+            };
+            expected_addrs.push(host_addr.to_string());
+            expected_addrs
+        };
+        let curr_inbound_rule = FirewallInboundRule {
+            protocol: "http".to_string(),
+            ports: "80".to_string(),
+            sources: FirewallRuleTarget {
+                addresses: fw_addrs.clone(),
+                droplet_ids: None,
+                load_balancer_uids: None,
+                kubernetes_ids: None,
+                tags: fw_tags.clone(),
+            },
+        };
+        let curr_outbound_rule = None;
+        let firewall = Firewall {
+            id: fw_id.clone(),
+            status: "succeeded".to_string(),
+            created_at: "2024-01-01T00:00Z".to_string(),
+            pending_changes: vec![],
+            name: fw_name.clone(),
+            droplet_ids: None,
+            tags: None,
+            inbound_rules: Some(vec![curr_inbound_rule.clone()]),
+            outbound_rules: curr_outbound_rule,
+        };
+
+        let fw_client = TestFwClientImpl {
+            expected_get_firewall_name: Some(fw_name.clone()),
+            firewall: Some(firewall.clone()),
+            expected_delete_firewall_id: None,
+            expected_delete_inbound_rules: None,
+            expected_delete_outbound_rules: None,
+            delete_rule_is_ok: false,
+            expected_add_firewall_id: None,
+            expected_add_inbound_rules: None,
+            expected_add_outbound_rules: None,
+            add_rule_is_ok: false,
+            rolled_back: Cell::new(false),
+        };
+        let droplet_client = TestDropletClientImpl {
+            droplets: droplets.unwrap_or_else(std::vec::Vec::new),
+        };
+        let kubernetes_client = TestKubeClientImpl {
+            clusters: kube_clusters.unwrap_or_else(std::vec::Vec::new),
+        };
+        let load_balancer_client = TestLbClientImpl {
+            loadbalancers: lbs.unwrap_or_else(std::vec::Vec::new),
+        };
+
+        match build_rules(
+            test_client(
+                Rc::new(fw_client),
+                Rc::new(droplet_client),
+                Rc::new(kubernetes_client),
+                Rc::new(load_balancer_client),
+                Rc::new(MockVpcClient::new()),
+            ),
+            FirewallArgs {
+                addresses: extra_addrs,
+                droplets: droplet_names,
+                kubernetes_clusters: kube_cluster_names,
+                load_balancers: lb_names,
+                ..rule_args(
+                    fw_name,
+                    Direction::Inbound,
+                    "80".to_string(),
+                    "http".to_string(),
+                )
+            },
+            &[IpAddr::V4(host_addr)],
+        )
+        .await
+        .expect("Unexpected failure in build_firewall_args")
+        {
+            (actual_fw, Some((actual_curr_inbound_rule, actual_new_inbound_rule)), None) => {
+                assert_eq!(firewall, actual_fw);
+                assert_eq!(curr_inbound_rule, actual_curr_inbound_rule);
+                assert_eq!(
+                    FirewallInboundRule {
+                        protocol: curr_inbound_rule.protocol,
+                        ports: curr_inbound_rule.ports,
+                        sources: FirewallRuleTarget {
+                            addresses: Some(expected_addrs),
+                            droplet_ids,
+                            load_balancer_uids: lb_ids,
+                            kubernetes_ids: kube_cluster_ids,
+                            tags: curr_inbound_rule.sources.tags,
+                        },
+                    },
+                    actual_new_inbound_rule
+                );
+            }
+            x => panic!(
+                "Failed to get correct return values from build_firewall_args (got {:?}",
+                x
+            ),
+        };
+    }
+
+    #[tokio::test]
+    async fn test_translate_args_no_dupe_addresses() {
+        let fw_id = "foo".to_string();
+        let fw_name = "Foo".to_string();
+        let fw_addrs = Some(vec!["8.8.8.8".to_string()]);
+        let fw_tags = Some(vec!["bar".to_string()]);
+        let host_addr = Ipv4Addr::new(8, 8, 8, 8);
+        let expected_addrs = vec![host_addr.to_string()];
+        let curr_inbound_rule = FirewallInboundRule {
+            protocol: "http".to_string(),
+            ports: "80".to_string(),
+            sources: FirewallRuleTarget {
+                addresses: fw_addrs.clone(),
+                droplet_ids: None,
+                load_balancer_uids: None,
+                kubernetes_ids: None,
+                tags: fw_tags.clone(),
+            },
+        };
+        let curr_outbound_rule = None;
+        let firewall = Firewall {
+            id: fw_id.clone(),
+            status: "succeeded".to_string(),
+            created_at: "2024-01-01T00:00Z".to_string(),
+            pending_changes: vec![],
+            name: fw_name.clone(),
+            droplet_ids: None,
+            tags: None,
+            inbound_rules: Some(vec![curr_inbound_rule.clone()]),
+            outbound_rules: curr_outbound_rule,
+        };
+
+        let fw_client = TestFwClientImpl {
+            expected_get_firewall_name: Some(fw_name.clone()),
+            firewall: Some(firewall.clone()),
+            expected_delete_firewall_id: None,
+            expected_delete_inbound_rules: None,
+            expected_delete_outbound_rules: None,
+            delete_rule_is_ok: false,
+            expected_add_firewall_id: None,
+            expected_add_inbound_rules: None,
+            expected_add_outbound_rules: None,
+            add_rule_is_ok: false,
+            rolled_back: Cell::new(false),
+        };
+        let droplet_client = TestDropletClientImpl { droplets: vec![] };
+        let kubernetes_client = TestKubeClientImpl { clusters: vec![] };
+        let load_balancer_client = TestLbClientImpl {
+            loadbalancers: vec![],
+        };
+
+        match build_rules(
+            test_client(
+                Rc::new(fw_client),
+                Rc::new(droplet_client),
+                Rc::new(kubernetes_client),
+                Rc::new(load_balancer_client),
+                Rc::new(MockVpcClient::new()),
+            ),
+            FirewallArgs {
+                addresses: Some(vec!["8.8.8.8".to_string()]),
+                ..rule_args(
+                    fw_name,
+                    Direction::Inbound,
+                    "80".to_string(),
+                    "http".to_string(),
+                )
+            },
+            &[IpAddr::V4(host_addr)],
+        )
+        .await
+        .expect("Unexpected failure in build_firewall_args")
+        {
+            (actual_fw, Some((actual_curr_inbound_rule, actual_new_inbound_rule)), None) => {
+                assert_eq!(firewall, actual_fw);
+                assert_eq!(curr_inbound_rule, actual_curr_inbound_rule);
+                assert_eq!(
+                    FirewallInboundRule {
+                        protocol: curr_inbound_rule.protocol,
+                        ports: curr_inbound_rule.ports,
+                        sources: FirewallRuleTarget {
+                            addresses: Some(expected_addrs),
+                            droplet_ids: None,
+                            load_balancer_uids: None,
+                            kubernetes_ids: None,
+                            tags: curr_inbound_rule.sources.tags,
+                        },
+                    },
+                    actual_new_inbound_rule
+                );
+            }
+            x => panic!(
+                "Failed to get correct return values from build_firewall_args (got {:?}",
+                x
+            ),
+        };
+    }
+
+    #[tokio::test]
+    async fn test_translate_args_dual_stack() {
+        let fw_id = "foo".to_string();
+        let fw_name = "Foo".to_string();
+        let host_addrs: Vec<IpAddr> =
+            vec!["8.8.8.8".parse().unwrap(), "2001:db8::2".parse().unwrap()];
+        let expected_addrs = vec!["8.8.8.8".to_string(), "2001:db8::2".to_string()];
+        let curr_inbound_rule = FirewallInboundRule {
+            protocol: "http".to_string(),
+            ports: "80".to_string(),
+            sources: FirewallRuleTarget {
+                addresses: Some(vec!["1.1.1.1".to_string(), "2001:db8::1".to_string()]),
+                droplet_ids: None,
+                load_balancer_uids: None,
+                kubernetes_ids: None,
+                tags: None,
+            },
+        };
+        let firewall = Firewall {
+            id: fw_id.clone(),
+            status: "succeeded".to_string(),
+            created_at: "2024-01-01T00:00Z".to_string(),
+            pending_changes: vec![],
+            name: fw_name.clone(),
+            droplet_ids: None,
+            tags: None,
+            inbound_rules: Some(vec![curr_inbound_rule.clone()]),
+            outbound_rules: None,
+        };
+
+        let fw_client = TestFwClientImpl {
+            expected_get_firewall_name: Some(fw_name.clone()),
+            firewall: Some(firewall.clone()),
+            expected_delete_firewall_id: None,
+            expected_delete_inbound_rules: None,
+            expected_delete_outbound_rules: None,
+            delete_rule_is_ok: false,
+            expected_add_firewall_id: None,
+            expected_add_inbound_rules: None,
+            expected_add_outbound_rules: None,
+            add_rule_is_ok: false,
+            rolled_back: Cell::new(false),
+        };
+        let droplet_client = TestDropletClientImpl { droplets: vec![] };
+        let kubernetes_client = TestKubeClientImpl { clusters: vec![] };
+        let load_balancer_client = TestLbClientImpl {
+            loadbalancers: vec![],
+        };
+
+        match build_rules(
+            test_client(
+                Rc::new(fw_client),
+                Rc::new(droplet_client),
+                Rc::new(kubernetes_client),
+                Rc::new(load_balancer_client),
+                Rc::new(MockVpcClient::new()),
+            ),
+            rule_args(
+                fw_name,
+                Direction::Inbound,
+                "80".to_string(),
+                "http".to_string(),
+            ),
+            &host_addrs,
+        )
+        .await
+        .expect("Unexpected failure in build_firewall_args")
+        {
+            (_, Some((_, actual_new_inbound_rule)), None) => {
+                assert_eq!(
+                    Some(expected_addrs),
+                    actual_new_inbound_rule.sources.addresses
+                );
+            }
+            x => panic!(
+                "Failed to get correct return values from build_firewall_args (got {:?}",
+                x
+            ),
+        };
+    }
+
+    #[test]
+    fn test_firewall_address() {
+        let v4: IpAddr = "1.2.3.4".parse().unwrap();
+        let v6: IpAddr = "2001:db8:aaaa:bbcc:1234:5678:9abc:def0".parse().unwrap();
+        assert_eq!("1.2.3.4", firewall_address(&v4, None));
+        assert_eq!("1.2.3.4", firewall_address(&v4, Some(64)));
+        assert_eq!(
+            "2001:db8:aaaa:bbcc:1234:5678:9abc:def0",
+            firewall_address(&v6, None)
+        );
+        assert_eq!("2001:db8:aaaa:bbcc::/64", firewall_address(&v6, Some(64)));
+        assert_eq!("2001:db8:aaaa:bb00::/56", firewall_address(&v6, Some(56)));
+    }
+
+    #[test]
+    fn test_ports_match() {
+        assert!(ports_match("80", "80"));
+        assert!(!ports_match("8000-9000", "80"));
+        assert!(ports_match("0", "0"));
+        assert!(ports_match("", "0"));
+        assert!(ports_match("all", "0"));
+        assert!(!ports_match("80", "0"));
+        assert!(!ports_match("", "80"));
+    }
+
+    #[test]
+    fn test_targets_match() {
+        let target = FirewallRuleTarget {
+            addresses: Some(vec!["1.2.3.4".to_string(), "5.6.7.8".to_string()]),
+            droplet_ids: Some(vec![1, 2]),
+            load_balancer_uids: None,
+            kubernetes_ids: Some(vec![]),
+            tags: None,
+        };
+        assert!(targets_match(&target, &target));
+        assert!(targets_match(
+            &target,
+            &FirewallRuleTarget {
+                addresses: Some(vec!["5.6.7.8".to_string(), "1.2.3.4".to_string()]),
+                droplet_ids: Some(vec![2, 1]),
+                load_balancer_uids: Some(vec![]),
+                kubernetes_ids: None,
+                tags: None,
+            }
+        ));
+        assert!(!targets_match(
+            &target,
+            &FirewallRuleTarget {
+                addresses: Some(vec!["1.2.3.4".to_string()]),
+                ..target.clone()
+            }
+        ));
+        assert!(!targets_match(
+            &target,
+            &FirewallRuleTarget {
+                droplet_ids: None,
+                ..target.clone()
+            }
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_update_firewall() {
+        let fw_id = "foo".to_string();
+        let fw_name = "Foo".to_string();
+        let cur_inbound_rule = FirewallInboundRule {
+            protocol: "http".to_string(),
+            ports: "80".to_string(),
+            sources: FirewallRuleTarget {
+                addresses: None,
+                droplet_ids: None,
+                load_balancer_uids: None,
+                kubernetes_ids: None,
+                tags: None,
+            },
+        };
+        let new_inbound_rule = FirewallInboundRule {
+            protocol: "http".to_string(),
+            ports: "80".to_string(),
+            sources: FirewallRuleTarget {
+                addresses: Some(vec!["1.1.1.1".to_string()]),
+                droplet_ids: None,
+                load_balancer_uids: None,
+                kubernetes_ids: None,
+                tags: None,
+            },
+        };
+        let firewall = Firewall {
+            id: fw_id.clone(),
+            status: "".to_string(),
+            created_at: "".to_string(),
+            pending_changes: vec![],
+            name: fw_name.clone(),
+            droplet_ids: None,
+            tags: None,
+            inbound_rules: Some(vec![cur_inbound_rule.clone()]),
+            outbound_rules: None,
+        };
+        let fw_client = TestFwClientImpl {
+            expected_get_firewall_name: Some(fw_name.clone()),
+            firewall: Some(firewall.clone()),
+            expected_delete_firewall_id: Some(fw_id.clone()),
+            expected_delete_inbound_rules: Some(vec![cur_inbound_rule.clone()]),
+            expected_delete_outbound_rules: None,
+            delete_rule_is_ok: true,
+            expected_add_firewall_id: Some(fw_id.clone()),
+            expected_add_inbound_rules: Some(vec![new_inbound_rule.clone()]),
+            expected_add_outbound_rules: None,
+            add_rule_is_ok: true,
+            rolled_back: Cell::new(false),
+        };
+
+        match replace_firewall_rules(
+            Rc::new(fw_client),
+            firewall.clone(),
+            vec![(cur_inbound_rule, new_inbound_rule)],
+            vec![],
+            &ResourceNames::default(),
+            false,
+            true,
+            false,
+        )
+        .await
+        {
+            Ok(new_fw) => assert_eq!(new_fw, firewall),
+            Err(e) => panic!("Unexpected error while updating firewall: {:?}", e),
+        };
+    }
+
+    #[tokio::test]
+    async fn test_update_firewall_skips_unchanged_rule() {
+        let rule = FirewallInboundRule {
+            protocol: "tcp".to_string(),
+            ports: "22".to_string(),
+            sources: FirewallRuleTarget {
+                addresses: Some(vec!["1.1.1.1".to_string()]),
+                droplet_ids: None,
+                load_balancer_uids: None,
+                kubernetes_ids: None,
+                tags: None,
+            },
+        };
+        let mut client =
+            DigitalOceanClient::new_for_test("foo".to_string(), "http://localhost".to_string());
+        // deleting or adding a rule fails the test
+        client.firewall = Rc::new(TestFwClientImpl {
+            expected_get_firewall_name: Some("Foo".to_string()),
+            firewall: Some(Firewall {
+                id: "foo".to_string(),
+                status: "".to_string(),
+                created_at: "".to_string(),
+                pending_changes: vec![],
+                name: "Foo".to_string(),
+                droplet_ids: None,
+                tags: None,
+                inbound_rules: Some(vec![rule]),
+                outbound_rules: None,
+            }),
+            expected_delete_firewall_id: None,
+            expected_delete_inbound_rules: None,
+            expected_delete_outbound_rules: None,
+            delete_rule_is_ok: false,
+            expected_add_firewall_id: None,
+            expected_add_inbound_rules: None,
+            expected_add_outbound_rules: None,
+            add_rule_is_ok: false,
+            rolled_back: Cell::new(false),
+        });
+
+        update_firewall(
+            &client,
+            FirewallArgs {
+                name: "Foo".to_string(),
+                all_matching: false,
+                direction: Direction::Inbound,
+                port: "22".to_string(),
+                protocols: vec!["tcp".to_string()],
+                addresses: None,
+                vpcs: None,
+                droplets: None,
+                droplet_tags: None,
+                kubernetes_clusters: None,
+                load_balancers: None,
+                project: None,
+                prune_dangling: false,
+                dual_stack: false,
+                ipv6_prefix: None,
+                old_ips: None,
+                retain_days: None,
+                no_verify: false,
+                atomic: false,
+                rule_index: None,
+                match_source_tag: None,
+                tags: None,
+                replace_tags: false,
+            },
+            &[IpAddr::V4(Ipv4Addr::new(1, 1, 1, 1))],
+            &UpdateOptions::default(),
+        )
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_update_firewall_batches_ports() {
+        let rule = |port: &str, address: &str| FirewallInboundRule {
+            protocol: "tcp".to_string(),
+            ports: port.to_string(),
+            sources: FirewallRuleTarget {
+                addresses: Some(vec![address.to_string()]),
+                droplet_ids: None,
+                load_balancer_uids: None,
+                kubernetes_ids: None,
+                tags: None,
+            },
+        };
+        let mut client =
+            DigitalOceanClient::new_for_test("foo".to_string(), "http://localhost".to_string());
+        // both rules are replaced with a single deletion and a single addition
+        client.firewall = Rc::new(TestFwClientImpl {
+            expected_get_firewall_name: Some("Foo".to_string()),
+            firewall: Some(Firewall {
+                id: "foo".to_string(),
+                status: "".to_string(),
+                created_at: "".to_string(),
+                pending_changes: vec![],
+                name: "Foo".to_string(),
+                droplet_ids: None,
+                tags: None,
+                inbound_rules: Some(vec![rule("22", "1.1.1.1"), rule("80", "1.1.1.1")]),
+                outbound_rules: None,
+            }),
+            expected_delete_firewall_id: Some("foo".to_string()),
+            expected_delete_inbound_rules: Some(vec![rule("22", "1.1.1.1"), rule("80", "1.1.1.1")]),
+            expected_delete_outbound_rules: None,
+            delete_rule_is_ok: true,
+            expected_add_firewall_id: Some("foo".to_string()),
+            expected_add_inbound_rules: Some(vec![rule("22", "2.2.2.2"), rule("80", "2.2.2.2")]),
+            expected_add_outbound_rules: None,
+            add_rule_is_ok: true,
+            rolled_back: Cell::new(false),
+        });
+
+        update_firewall(
+            &client,
+            FirewallArgs {
+                name: "Foo".to_string(),
+                all_matching: false,
+                direction: Direction::Inbound,
+                port: "22, 80".to_string(),
+                protocols: vec!["tcp".to_string()],
+                addresses: None,
+                vpcs: None,
+                droplets: None,
+                droplet_tags: None,
+                kubernetes_clusters: None,
+                load_balancers: None,
+                project: None,
+                prune_dangling: false,
+                dual_stack: false,
+                ipv6_prefix: None,
+                old_ips: None,
+                retain_days: None,
+                no_verify: true,
+                atomic: false,
+                rule_index: None,
+                match_source_tag: None,
+                tags: None,
+                replace_tags: false,
+            },
+            &[IpAddr::V4(Ipv4Addr::new(2, 2, 2, 2))],
+            &UpdateOptions::default(),
+        )
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_update_firewall_delete_fail() {
+        let fw_id = "foo".to_string();
+        let fw_name = "Foo".to_string();
+        let cur_inbound_rule = FirewallInboundRule {
+            protocol: "http".to_string(),
+            ports: "80".to_string(),
+            sources: FirewallRuleTarget {
+                addresses: None,
+                droplet_ids: None,
+                load_balancer_uids: None,
+                kubernetes_ids: None,
+                tags: None,
+            },
+        };
+        let new_inbound_rule = FirewallInboundRule {
+            protocol: "http".to_string(),
+            ports: "80".to_string(),
+            sources: FirewallRuleTarget {
+                addresses: Some(vec!["1.1.1.1".to_string()]),
+                droplet_ids: None,
+                load_balancer_uids: None,
+                kubernetes_ids: None,
+                tags: None,
+            },
+        };
+        let firewall = Firewall {
+            id: fw_id.clone(),
+            status: "".to_string(),
+            created_at: "".to_string(),
+            pending_changes: vec![],
+            name: fw_name.clone(),
+            droplet_ids: None,
+            tags: None,
+            inbound_rules: Some(vec![cur_inbound_rule.clone()]),
+            outbound_rules: None,
+        };
+        let fw_client = TestFwClientImpl {
+            expected_get_firewall_name: Some(fw_name.clone()),
+            firewall: Some(firewall.clone()),
+            expected_delete_firewall_id: Some(fw_id.clone()),
+            expected_delete_inbound_rules: Some(vec![cur_inbound_rule.clone()]),
+            expected_delete_outbound_rules: None,
+            delete_rule_is_ok: false,
+            expected_add_firewall_id: Some(fw_id.clone()),
+            expected_add_inbound_rules: Some(vec![new_inbound_rule.clone()]),
+            expected_add_outbound_rules: None,
+            add_rule_is_ok: true,
+            rolled_back: Cell::new(false),
+        };
+
+        match replace_firewall_rules(
+            Rc::new(fw_client),
+            firewall.clone(),
+            vec![(cur_inbound_rule, new_inbound_rule)],
+            vec![],
+            &ResourceNames::default(),
+            false,
+            true,
+            false,
+        )
+        .await
+        {
+            Ok(_) => panic!("Expected delete call to fail!"),
+            Err(Client(Error::DeleteFirewallRule(_))) => (),
+            Err(e) => panic!("Unexpected failure reason: {:?}", e),
+        };
+    }
+
+    #[test]
+    fn test_firewall_change() {
+        let rule = FirewallInboundRule {
+            protocol: "tcp".to_string(),
+            ports: "80".to_string(),
+            sources: FirewallRuleTarget {
+                addresses: Some(vec!["1.1.1.1".to_string()]),
+                droplet_ids: None,
+                load_balancer_uids: None,
+                kubernetes_ids: None,
+                tags: None,
+            },
+        };
+        let new_rule = FirewallInboundRule {
+            sources: FirewallRuleTarget {
+                addresses: Some(vec!["8.8.8.8".to_string(), "2001:db8::1".to_string()]),
+                ..rule.sources.clone()
+            },
+            ..rule.clone()
+        };
+        let firewall = Firewall {
+            id: "foo".to_string(),
+            status: "".to_string(),
+            created_at: "".to_string(),
+            pending_changes: vec![],
+            name: "Foo".to_string(),
+            droplet_ids: None,
+            tags: None,
+            inbound_rules: Some(vec![rule.clone()]),
+            outbound_rules: None,
+        };
+
+        assert_eq!(
+            Some(Change {
+                kind: "firewall",
+                resource: "Foo inbound rule for port 80 (tcp)".to_string(),
+                old_ip: "1.1.1.1".to_string(),
+                new_ip: "8.8.8.8,2001:db8::1".to_string(),
+            }),
+            firewall_change(&firewall, &Some((rule.clone(), new_rule)), &None)
+        );
+        assert_eq!(
+            None,
+            firewall_change(&firewall, &Some((rule.clone(), rule)), &None)
+        );
+    }
+
+    #[test]
+    fn test_verify_firewall_rules() {
+        let rule = FirewallInboundRule {
+            protocol: "tcp".to_string(),
+            ports: "80".to_string(),
+            sources: FirewallRuleTarget {
+                addresses: Some(vec!["1.1.1.1".to_string(), "2.2.2.2".to_string()]),
+                droplet_ids: None,
+                load_balancer_uids: None,
+                kubernetes_ids: None,
+                tags: None,
+            },
+        };
+        let firewall = Firewall {
+            id: "foo".to_string(),
+            status: "".to_string(),
+            created_at: "".to_string(),
+            pending_changes: vec![],
+            name: "Foo".to_string(),
+            droplet_ids: None,
+            tags: None,
+            inbound_rules: Some(vec![rule.clone()]),
+            outbound_rules: None,
+        };
+
+        // the API may return addresses in a different order
+        let reordered = FirewallInboundRule {
+            sources: FirewallRuleTarget {
+                addresses: Some(vec!["2.2.2.2".to_string(), "1.1.1.1".to_string()]),
+                ..rule.sources.clone()
+            },
+            ..rule.clone()
+        };
+        assert!(verify_firewall_rules(&firewall, Some(&reordered), None).is_ok());
+
+        let missing_address = FirewallInboundRule {
+            sources: FirewallRuleTarget {
+                addresses: Some(vec!["3.3.3.3".to_string()]),
+                ..rule.sources.clone()
+            },
+            ..rule.clone()
+        };
+        match verify_firewall_rules(&firewall, Some(&missing_address), None) {
+            Err(crate::Error::VerificationFailed(_)) => (),
+            x => panic!("Expected verification failure but got {:?}", x),
+        }
+        let other_ports = FirewallOutboundRule {
+            protocol: "tcp".to_string(),
+            ports: "80".to_string(),
+            destinations: rule.sources.clone(),
+        };
+        assert!(verify_firewall_rules(&firewall, None, Some(&other_ports)).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_update_firewall_verify() {
+        let cur_inbound_rule = FirewallInboundRule {
+            protocol: "http".to_string(),
+            ports: "80".to_string(),
+            sources: FirewallRuleTarget {
+                addresses: None,
+                droplet_ids: None,
+                load_balancer_uids: None,
+                kubernetes_ids: None,
+                tags: None,
+            },
+        };
+        let new_inbound_rule = FirewallInboundRule {
+            protocol: "http".to_string(),
+            ports: "80".to_string(),
+            sources: FirewallRuleTarget {
+                addresses: Some(vec!["1.1.1.1".to_string()]),
+                ..cur_inbound_rule.sources.clone()
+            },
+        };
+        let firewall = Firewall {
+            id: "foo".to_string(),
+            status: "".to_string(),
+            created_at: "".to_string(),
+            pending_changes: vec![],
+            name: "Foo".to_string(),
+            droplet_ids: None,
+            tags: None,
+            inbound_rules: Some(vec![cur_inbound_rule.clone()]),
+            outbound_rules: None,
+        };
+
+        for verify in [true, false] {
+            let fw_client = Rc::new(MockFirewallClient::new().with_firewall(firewall.clone()));
+            let returned = replace_firewall_rules(
+                fw_client.clone(),
+                firewall.clone(),
+                vec![(cur_inbound_rule.clone(), new_inbound_rule.clone())],
+                vec![],
+                &ResourceNames::default(),
+                false,
+                verify,
+                false,
+            )
+            .await
+            .expect("Unexpected error while updating firewall");
+            let expected_rules = if verify {
+                vec![new_inbound_rule.clone()]
+            } else {
+                vec![cur_inbound_rule.clone()]
+            };
+            assert_eq!(Some(expected_rules), returned.inbound_rules);
+            assert_eq!(
+                Some(vec![new_inbound_rule.clone()]),
+                fw_client.firewalls()[0].inbound_rules
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_update_firewall_atomic() {
+        let ssh_rule = FirewallInboundRule {
+            protocol: "tcp".to_string(),
+            ports: "22".to_string(),
+            sources: FirewallRuleTarget {
+                addresses: Some(vec!["10.0.0.0/8".to_string()]),
+                droplet_ids: None,
+                load_balancer_uids: None,
+                kubernetes_ids: None,
+                tags: None,
+            },
+        };
+        let cur_inbound_rule = FirewallInboundRule {
+            protocol: "http".to_string(),
+            ports: "80".to_string(),
+            sources: FirewallRuleTarget {
+                addresses: Some(vec!["2.2.2.2".to_string()]),
+                ..ssh_rule.sources.clone()
+            },
+        };
+        let new_inbound_rule = FirewallInboundRule {
+            protocol: "http".to_string(),
+            ports: "80".to_string(),
+            sources: FirewallRuleTarget {
+                addresses: Some(vec!["1.1.1.1".to_string()]),
+                ..ssh_rule.sources.clone()
+            },
+        };
+        let firewall = Firewall {
+            id: "foo".to_string(),
+            status: "".to_string(),
+            created_at: "".to_string(),
+            pending_changes: vec![],
+            name: "Foo".to_string(),
+            droplet_ids: Some(vec![42]),
+            tags: None,
+            inbound_rules: Some(vec![cur_inbound_rule.clone(), ssh_rule.clone()]),
+            outbound_rules: None,
+        };
+
+        let fw_client = Rc::new(MockFirewallClient::new().with_firewall(firewall.clone()));
+        replace_firewall_rules(
+            fw_client.clone(),
+            firewall.clone(),
+            vec![(cur_inbound_rule, new_inbound_rule.clone())],
+            vec![],
+            &ResourceNames::default(),
+            true,
+            true,
+            false,
+        )
+        .await
+        .expect("Unexpected error while updating firewall");
+        assert_eq!(
+            vec![Firewall {
+                inbound_rules: Some(vec![new_inbound_rule, ssh_rule]),
+                ..firewall
+            }],
+            fw_client.firewalls()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_update_firewall_concurrent_change() {
+        let fw_id = "foo".to_string();
+        let fw_name = "Foo".to_string();
+        let cur_inbound_rule = FirewallInboundRule {
+            protocol: "http".to_string(),
+            ports: "80".to_string(),
+            sources: FirewallRuleTarget {
+                addresses: None,
+                droplet_ids: None,
+                load_balancer_uids: None,
+                kubernetes_ids: None,
+                tags: None,
+            },
+        };
+        let new_inbound_rule = FirewallInboundRule {
+            protocol: "http".to_string(),
+            ports: "80".to_string(),
+            sources: FirewallRuleTarget {
+                addresses: Some(vec!["1.1.1.1".to_string()]),
+                ..cur_inbound_rule.sources.clone()
+            },
+        };
+        let firewall = Firewall {
+            id: fw_id.clone(),
+            status: "".to_string(),
+            created_at: "".to_string(),
+            pending_changes: vec![],
+            name: fw_name.clone(),
+            droplet_ids: None,
+            tags: None,
+            inbound_rules: Some(vec![cur_inbound_rule.clone()]),
+            outbound_rules: None,
+        };
+        let fw_client = TestFwClientImpl {
+            expected_get_firewall_name: Some(fw_name.clone()),
+            // the rule was edited elsewhere after the firewall was first read
+            firewall: Some(Firewall {
+                inbound_rules: Some(vec![new_inbound_rule.clone()]),
+                ..firewall.clone()
+            }),
+            expected_delete_firewall_id: None,
+            expected_delete_inbound_rules: None,
+            expected_delete_outbound_rules: None,
+            delete_rule_is_ok: false,
+            expected_add_firewall_id: None,
+            expected_add_inbound_rules: None,
+            expected_add_outbound_rules: None,
+            add_rule_is_ok: false,
+            rolled_back: Cell::new(false),
+        };
+
+        match replace_firewall_rules(
+            Rc::new(fw_client),
+            firewall,
+            vec![(cur_inbound_rule, new_inbound_rule)],
+            vec![],
+            &ResourceNames::default(),
+            false,
+            true,
+            false,
+        )
+        .await
+        {
+            Ok(_) => panic!("Expected concurrent change to be detected!"),
+            Err(crate::Error::ConcurrentModification(_)) => (),
+            Err(e) => panic!("Unexpected failure reason: {:?}", e),
+        };
+    }
+
+    #[tokio::test]
+    async fn test_update_firewall_add_fail() {
+        let fw_id = "foo".to_string();
+        let fw_name = "Foo".to_string();
+        let cur_inbound_rule = FirewallInboundRule {
+            protocol: "http".to_string(),
+            ports: "80".to_string(),
+            sources: FirewallRuleTarget {
+                addresses: None,
+                droplet_ids: None,
+                load_balancer_uids: None,
+                kubernetes_ids: None,
+                tags: None,
+            },
+        };
+        let new_inbound_rule = FirewallInboundRule {
+            protocol: "http".to_string(),
+            ports: "80".to_string(),
+            sources: FirewallRuleTarget {
+                addresses: Some(vec!["1.1.1.1".to_string()]),
+                droplet_ids: None,
+                load_balancer_uids: None,
+                kubernetes_ids: None,
+                tags: None,
+            },
+        };
+        let firewall = Firewall {
+            id: fw_id.clone(),
+            status: "".to_string(),
+            created_at: "".to_string(),
+            pending_changes: vec![],
+            name: fw_name.clone(),
+            droplet_ids: None,
+            tags: None,
+            inbound_rules: Some(vec![cur_inbound_rule.clone()]),
+            outbound_rules: None,
+        };
+        let fw_client = Rc::new(TestFwClientImpl {
+            expected_get_firewall_name: Some(fw_name.clone()),
+            firewall: Some(firewall.clone()),
+            expected_delete_firewall_id: Some(fw_id.clone()),
+            expected_delete_inbound_rules: Some(vec![cur_inbound_rule.clone()]),
+            expected_delete_outbound_rules: None,
+            delete_rule_is_ok: true,
+            expected_add_firewall_id: Some(fw_id.clone()),
+            expected_add_inbound_rules: Some(vec![new_inbound_rule.clone()]),
+            expected_add_outbound_rules: None,
+            add_rule_is_ok: false,
+            rolled_back: Cell::new(false),
+        });
+
+        match replace_firewall_rules(
+            fw_client.clone(),
+            firewall.clone(),
+            vec![(cur_inbound_rule, new_inbound_rule)],
+            vec![],
+            &ResourceNames::default(),
+            false,
+            true,
+            false,
+        )
+        .await
+        {
+            Ok(_) => panic!("Expected create/add call to fail!"),
+            Err(Client(Error::CreateFirewallRule(_))) => (),
+            Err(e) => panic!("Unexpected failure reason: {:?}", e),
+        };
+        assert!(fw_client.rolled_back.get());
+    }
+
+    #[tokio::test]
+    async fn test_prune_dangling_references() {
+        let fw_id = "foo".to_string();
+        let fw_name = "Foo".to_string();
+        let cluster_id = "123-456-789".to_string();
+        let deleted_cluster_id = "987-654-321".to_string();
+        let cur_inbound_rule = FirewallInboundRule {
+            protocol: "tcp".to_string(),
+            ports: "443".to_string(),
+            sources: FirewallRuleTarget {
+                addresses: None,
+                droplet_ids: None,
+                load_balancer_uids: None,
+                kubernetes_ids: Some(vec![cluster_id.clone(), deleted_cluster_id.clone()]),
+                tags: None,
+            },
+        };
+        let orphaned_inbound_rule = FirewallInboundRule {
+            protocol: "tcp".to_string(),
+            ports: "22".to_string(),
+            sources: FirewallRuleTarget {
+                addresses: None,
+                droplet_ids: None,
+                load_balancer_uids: None,
+                kubernetes_ids: Some(vec![deleted_cluster_id.clone()]),
+                tags: None,
+            },
+        };
+        let untouched_inbound_rule = FirewallInboundRule {
+            protocol: "tcp".to_string(),
+            ports: "80".to_string(),
+            sources: FirewallRuleTarget {
+                addresses: Some(vec!["1.1.1.1".to_string()]),
+                droplet_ids: None,
+                load_balancer_uids: None,
+                kubernetes_ids: None,
+                tags: None,
+            },
+        };
+        let new_inbound_rule = FirewallInboundRule {
+            protocol: "tcp".to_string(),
+            ports: "443".to_string(),
+            sources: FirewallRuleTarget {
+                addresses: None,
+                droplet_ids: None,
+                load_balancer_uids: None,
+                kubernetes_ids: Some(vec![cluster_id.clone()]),
+                tags: None,
+            },
+        };
+        let firewall = Firewall {
+            id: fw_id.clone(),
+            status: "".to_string(),
+            created_at: "".to_string(),
+            pending_changes: vec![],
+            name: fw_name.clone(),
+            droplet_ids: None,
+            tags: None,
+            inbound_rules: Some(vec![
+                cur_inbound_rule.clone(),
+                orphaned_inbound_rule.clone(),
+                untouched_inbound_rule,
+            ]),
+            outbound_rules: None,
+        };
+        let fw_client = TestFwClientImpl {
+            expected_get_firewall_name: Some(fw_name),
+            firewall: Some(firewall.clone()),
+            expected_delete_firewall_id: Some(fw_id.clone()),
+            expected_delete_inbound_rules: Some(vec![cur_inbound_rule, orphaned_inbound_rule]),
+            expected_delete_outbound_rules: None,
+            delete_rule_is_ok: true,
+            expected_add_firewall_id: Some(fw_id),
+            expected_add_inbound_rules: Some(vec![new_inbound_rule]),
+            expected_add_outbound_rules: None,
+            add_rule_is_ok: true,
+            rolled_back: Cell::new(false),
+        };
+        let kubernetes_client = TestKubeClientImpl {
+            clusters: vec![KubernetesCluster {
+                id: cluster_id,
+                name: "foo".to_string(),
+                region: "".to_string(),
+                version: "".to_string(),
+                cluster_subnet: "".to_string(),
+                service_subnet: "".to_string(),
+                vpc_uuid: "".to_string(),
+                ipv4: None,
+                endpoint: "".to_string(),
+                tags: vec![],
+                node_pools: vec![],
+                maintenance_policy: None,
+                auto_upgrade: false,
+                status: KubernetesClusterStatus {
+                    state: "".to_string(),
+                    message: None,
+                },
+                created_at: "".to_string(),
+                updated_at: "".to_string(),
+                surge_upgrade: false,
+                ha: false,
+                registry_enabled: false,
+            }],
+        };
+
+        match prune_dangling_references(
+            Rc::new(fw_client),
+            Rc::new(TestDropletClientImpl { droplets: vec![] }),
+            Rc::new(kubernetes_client),
+            Rc::new(TestLbClientImpl {
+                loadbalancers: vec![],
+            }),
+            firewall.clone(),
+            false,
+        )
+        .await
+        {
+            Ok(new_fw) => assert_eq!(new_fw, firewall),
+            Err(e) => panic!("Unexpected error while pruning firewall: {:?}", e),
+        };
+    }
+
+    struct TestFwClientImpl {
+        expected_get_firewall_name: Option<String>,
+        firewall: Option<Firewall>,
+        expected_delete_firewall_id: Option<String>,
+        expected_delete_inbound_rules: Option<Vec<FirewallInboundRule>>,
+        expected_delete_outbound_rules: Option<Vec<FirewallOutboundRule>>,
+        delete_rule_is_ok: bool,
+        expected_add_firewall_id: Option<String>,
+        expected_add_inbound_rules: Option<Vec<FirewallInboundRule>>,
+        expected_add_outbound_rules: Option<Vec<FirewallOutboundRule>>,
+        add_rule_is_ok: bool,
+        /// Set when the deleted rules are added back after adding the new rules failed
+        rolled_back: Cell<bool>,
+    }
+
+    #[async_trait(?Send)]
+    impl DigitalOceanFirewallClient for TestFwClientImpl {
+        async fn get_firewalls(&self) -> Result<Vec<Firewall>, Error> {
+            Ok(self.firewall.clone().into_iter().collect())
+        }
+
+        async fn get_firewall(&self, name: String) -> Result<Option<Firewall>, Error> {
+            match self.expected_get_firewall_name.clone() {
+                Some(expected_name) => assert_eq!(name, expected_name),
+                None => panic!("Must define expected_get_firewall_name"),
+            };
+
+            Ok(self.firewall.clone())
+        }
+
+        async fn get_firewall_by_id(&self, id: &str) -> Result<Option<Firewall>, Error> {
+            Ok(self.firewall.clone().filter(|f| f.id == id))
+        }
+
+        async fn delete_firewall_rule(
+            &self,
+            id: &str,
+            inbound_rules: Option<Vec<FirewallInboundRule>>,
+            outbound_rules: Option<Vec<FirewallOutboundRule>>,
+            _dry_run: &bool,
+        ) -> Result<(), Error> {
+            match self.expected_delete_firewall_id.clone() {
+                Some(expected_id) => assert_eq!(id, expected_id),
+                None => panic!("Must define expected_firewall_delete_id"),
+            };
+            assert_eq!(inbound_rules, self.expected_delete_inbound_rules);
+            assert_eq!(outbound_rules, self.expected_delete_outbound_rules);
+
+            if self.delete_rule_is_ok {
+                Ok(())
+            } else {
+                Err(Error::DeleteFirewallRule(Failure::Unexpected(
+                    "test".to_string(),
+                )))
+            }
+        }
+
+        async fn add_firewall_rule(
+            &self,
+            id: &str,
+            inbound_rules: Option<Vec<FirewallInboundRule>>,
+            outbound_rules: Option<Vec<FirewallOutboundRule>>,
+            _dry_run: &bool,
+        ) -> Result<(), Error> {
+            match self.expected_add_firewall_id.clone() {
+                Some(expected_id) => assert_eq!(id, expected_id),
+                None => panic!("Must define expected_add_firewall_id"),
+            };
+            if !self.add_rule_is_ok
+                && inbound_rules == self.expected_delete_inbound_rules
+                && outbound_rules == self.expected_delete_outbound_rules
+            {
+                self.rolled_back.set(true);
+                return Ok(());
+            }
+            assert_eq!(inbound_rules, self.expected_add_inbound_rules);
+            assert_eq!(outbound_rules, self.expected_add_outbound_rules);
+
+            if self.add_rule_is_ok {
+                Ok(())
+            } else {
+                Err(Error::CreateFirewallRule(Failure::Unexpected(
+                    "test".to_string(),
+                )))
+            }
+        }
+
+        async fn update_firewall(
+            &self,
+            _firewall: &Firewall,
+            _dry_run: &bool,
+        ) -> Result<Firewall, Error> {
+            panic!("Unexpected update of the whole firewall")
+        }
+
+        async fn assign_droplets(
+            &self,
+            _id: &str,
+            _droplet_ids: &[u32],
+            _dry_run: &bool,
+        ) -> Result<(), Error> {
+            panic!("Unexpected assignment of droplets")
+        }
+
+        async fn unassign_droplets(
+            &self,
+            _id: &str,
+            _droplet_ids: &[u32],
+            _dry_run: &bool,
+        ) -> Result<(), Error> {
+            panic!("Unexpected removal of droplets")
+        }
+    }
+
+    struct TestDropletClientImpl {
+        droplets: Vec<Droplet>,
+    }
+
+    #[async_trait(?Send)]
+    impl DigitalOceanDropletClient for TestDropletClientImpl {
+        async fn get_droplets(&self) -> Result<Vec<Droplet>, Error> {
+            Ok(self.droplets.clone())
+        }
+    }
+
+    struct TestDropletSummaryClientImpl {
+        droplets: Vec<(u32, &'static str)>,
+    }
+
+    #[async_trait(?Send)]
+    impl DigitalOceanDropletClient for TestDropletSummaryClientImpl {
+        async fn get_droplets(&self) -> Result<Vec<Droplet>, Error> {
+            panic!("Only the droplet summaries should be fetched")
+        }
+
+        async fn get_droplet_summaries(&self) -> Result<Vec<DropletSummary>, Error> {
+            Ok(self
+                .droplets
+                .iter()
+                .map(|(id, name)| DropletSummary {
+                    id: *id,
+                    name: name.to_string(),
+                })
+                .collect())
+        }
+    }
+
+    struct TestKubeClientImpl {
+        clusters: Vec<KubernetesCluster>,
+    }
+
+    #[async_trait(?Send)]
+    impl DigitalOceanKubernetesClient for TestKubeClientImpl {
+        async fn get_kubernetes_clusters(&self) -> Result<Vec<KubernetesCluster>, Error> {
+            Ok(self.clusters.clone())
+        }
+    }
+
+    struct TestLbClientImpl {
+        loadbalancers: Vec<Loadbalancer>,
+    }
+
+    #[async_trait(?Send)]
+    impl DigitalOceanLoadbalancerClient for TestLbClientImpl {
+        async fn get_load_balancers(&self) -> Result<Vec<Loadbalancer>, Error> {
+            Ok(self.loadbalancers.clone())
+        }
+    }
+}
+
+#[cfg(test)]
+mod reserved_ip_test {
+    use async_trait::async_trait;
+    use std::net::IpAddr;
+    use std::rc::Rc;
+
+    use crate::cli::ReservedIpArgs;
+    use crate::digitalocean::droplet::{DigitalOceanDropletClient, Droplet, DropletSummary};
+    use crate::digitalocean::error::Error;
+    use crate::digitalocean::mock::MockReservedIpClient;
+    use crate::digitalocean::reserved_ip::{ReservedIp, ReservedIpDroplet, ReservedIpRegion};
+    use crate::stats::RunStats;
+    use crate::{check_reserved_ip, run_reserved_ip};
+
+    fn reserved_ip(address: &str, droplet: Option<(u32, &str)>, locked: bool) -> ReservedIp {
+        ReservedIp {
+            ip: address.parse().unwrap(),
+            droplet: droplet.map(|(id, name)| ReservedIpDroplet {
+                id,
+                name: name.to_string(),
+            }),
+            region: ReservedIpRegion::default(),
+            locked,
+        }
+    }
+
+    fn args(droplet: &str, address: Option<&str>, from_droplet: Option<&str>) -> ReservedIpArgs {
+        ReservedIpArgs {
+            droplet: droplet.to_string(),
+            address: address.map(|a| a.parse::<IpAddr>().unwrap()),
+            from_droplet: from_droplet.map(|d| d.to_string()),
+        }
+    }
+
+    fn droplets() -> Rc<dyn DigitalOceanDropletClient> {
+        Rc::new(TestDropletClientImpl {
+            droplets: vec![(1, "old"), (2, "new")],
+        })
+    }
+
+    #[tokio::test]
+    async fn test_run_reserved_ip_reassigns() {
+        let client = Rc::new(
+            MockReservedIpClient::new()
+                .with_reserved_ip(reserved_ip("1.1.1.1", Some((1, "old")), false))
+                .with_reserved_ip(reserved_ip("2.2.2.2", None, false)),
+        );
+
+        run_reserved_ip(
+            client.clone(),
+            droplets(),
+            args("new", None, Some("old")),
+            true,
+            &RunStats::start(),
+            false,
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            Some(2),
+            client.reserved_ips()[0].droplet.as_ref().map(|d| d.id)
+        );
+
+        run_reserved_ip(
+            client.clone(),
+            droplets(),
+            args("old", Some("2.2.2.2"), None),
+            false,
+            &RunStats::start(),
+            true,
+        )
+        .await
+        .unwrap();
+        assert_eq!(None, client.reserved_ips()[1].droplet);
+    }
+
+    #[tokio::test]
+    async fn test_run_reserved_ip_errors() {
+        let client = Rc::new(MockReservedIpClient::new().with_reserved_ip(reserved_ip(
+            "1.1.1.1",
+            Some((1, "old")),
+            true,
+        )));
+
+        let stats = RunStats::start();
+        let run = |args: ReservedIpArgs| {
+            run_reserved_ip(client.clone(), droplets(), args, false, &stats, false)
+        };
+        assert!(matches!(
+            run(args("new", Some("1.1.1.1"), None)).await,
+            Err(crate::Error::ConcurrentModification(_))
+        ));
+        assert!(matches!(
+            run(args("new", Some("3.3.3.3"), None)).await,
+            Err(crate::Error::ReservedIpNotFound())
+        ));
+        assert!(matches!(
+            run(args("missing", Some("1.1.1.1"), None)).await,
+            Err(crate::Error::DropletNotFound())
+        ));
+        // already assigned, so the pending action doesn't matter
+        assert!(run(args("old", Some("1.1.1.1"), None)).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_check_reserved_ip() {
+        let client = Rc::new(MockReservedIpClient::new().with_reserved_ip(reserved_ip(
+            "1.1.1.1",
+            Some((1, "old")),
+            false,
+        )));
+
+        assert!(check_reserved_ip(
+            client.clone(),
+            droplets(),
+            args("old", Some("1.1.1.1"), None)
+        )
+        .await
+        .unwrap());
+        assert!(
+            !check_reserved_ip(client.clone(), droplets(), args("new", None, Some("old")))
+                .await
+                .unwrap()
+        );
+    }
+
+    struct TestDropletClientImpl {
+        droplets: Vec<(u32, &'static str)>,
+    }
+
+    #[async_trait(?Send)]
+    impl DigitalOceanDropletClient for TestDropletClientImpl {
+        async fn get_droplets(&self) -> Result<Vec<Droplet>, Error> {
+            panic!("Only the droplet summaries should be fetched")
+        }
+
+        async fn get_droplet_summaries(&self) -> Result<Vec<DropletSummary>, Error> {
+            Ok(self
+                .droplets
+                .iter()
+                .map(|(id, name)| DropletSummary {
+                    id: *id,
+                    name: name.to_string(),
+                })
+                .collect())
+        }
+    }
+}
@@ -0,0 +1,105 @@
+use std::fs::{File, OpenOptions, TryLockError};
+use std::io;
+use std::io::Write;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use tracing::info;
+
+/// How often a run waiting for the lock tries to take it again
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// An exclusive lock on the `--lock-file`, held while a run changes its targets so that runs
+/// started by overlapping cron jobs cannot interleave their changes to the same resources, such as
+/// the removal and addition of the addresses of a firewall rule.  The lock is released when this is
+/// dropped, or by the operating system if the process dies.
+#[derive(Debug)]
+pub struct RunLock {
+    _file: File,
+}
+
+impl RunLock {
+    /// Take the lock, waiting up to `wait` for another run to release it.  Returns `None` if the
+    /// lock is still held by another run after waiting.
+    pub async fn acquire(path: &Path, wait: Duration) -> Result<Option<RunLock>, io::Error> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(path)?;
+        let deadline = Instant::now() + wait;
+        let mut waiting = false;
+        loop {
+            match file.try_lock() {
+                Ok(()) => break,
+                Err(TryLockError::WouldBlock) if Instant::now() < deadline => {
+                    if !waiting {
+                        info!(
+                            "Waiting up to {}s for another run to release {}",
+                            wait.as_secs(),
+                            path.display()
+                        );
+                        waiting = true;
+                    }
+                    tokio::time::sleep(POLL_INTERVAL).await;
+                }
+                Err(TryLockError::WouldBlock) => return Ok(None),
+                Err(TryLockError::Error(e)) => return Err(e),
+            }
+        }
+        // record who holds the lock, for whoever finds it held
+        file.set_len(0)?;
+        writeln!(file, "{}", std::process::id())?;
+        Ok(Some(RunLock { _file: file }))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    use crate::lock::RunLock;
+
+    fn lock_path(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("dyn-dns-lock-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir.join(name)
+    }
+
+    #[tokio::test]
+    async fn test_acquire() {
+        let path = lock_path("acquire.lock");
+
+        let held = RunLock::acquire(&path, Duration::ZERO).await.unwrap();
+        assert!(held.is_some());
+        assert_eq!(
+            format!("{}\n", std::process::id()),
+            fs::read_to_string(&path).unwrap()
+        );
+        assert!(RunLock::acquire(&path, Duration::ZERO)
+            .await
+            .unwrap()
+            .is_none());
+
+        drop(held);
+        assert!(RunLock::acquire(&path, Duration::ZERO)
+            .await
+            .unwrap()
+            .is_some());
+    }
+
+    #[tokio::test]
+    async fn test_acquire_waits_for_release() {
+        let path = lock_path("wait.lock");
+
+        let held = RunLock::acquire(&path, Duration::ZERO).await.unwrap();
+        let release = async {
+            tokio::time::sleep(Duration::from_millis(250)).await;
+            drop(held);
+        };
+        let (_, waited) = tokio::join!(release, RunLock::acquire(&path, Duration::from_secs(5)));
+        assert!(waited.unwrap().is_some());
+    }
+}
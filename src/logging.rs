@@ -0,0 +1,210 @@
+use std::fmt;
+use std::fs::OpenOptions;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use tracing::field::{Field, Visit};
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::fmt::format::Writer;
+use tracing_subscriber::fmt::time::{FormatTime, SystemTime};
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
+use tracing_subscriber::fmt::{FmtContext, FormatEvent, FormatFields};
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::FmtSubscriber;
+
+/// How each log line is written
+#[derive(Deserialize, Serialize, Debug, Default, Clone, Copy, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Human readable text
+    #[default]
+    Text,
+    /// One JSON object per line, for ingestion into log aggregators
+    Json,
+}
+
+impl LogFormat {
+    pub fn parse(name: &str) -> Result<LogFormat, String> {
+        match name {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!("unknown log format {name}, expected text or json")),
+        }
+    }
+}
+
+/// Where to log and how much
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct LogArgs {
+    pub level: Level,
+    /// Append to this file instead of writing to stderr
+    pub file: Option<PathBuf>,
+    pub format: LogFormat,
+}
+
+impl Default for LogArgs {
+    fn default() -> Self {
+        LogArgs {
+            level: Level::INFO,
+            file: None,
+            format: LogFormat::Text,
+        }
+    }
+}
+
+impl LogArgs {
+    /// The level to log at: debug output when verbose, only warnings and errors when quiet
+    pub fn level(verbose: bool, quiet: bool) -> Level {
+        if verbose {
+            Level::DEBUG
+        } else if quiet {
+            Level::WARN
+        } else {
+            Level::INFO
+        }
+    }
+
+    /// Install the global subscriber described by these settings.  Colours are only used when
+    /// `ansi` is set and the log goes to stderr.
+    pub fn init(&self, ansi: bool) -> Result<(), String> {
+        let (writer, ansi) = match self.file {
+            Some(ref path) => {
+                let file = OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .map_err(|e| format!("unable to open log file {}: {}", path.display(), e))?;
+                (BoxMakeWriter::new(Mutex::new(file)), false)
+            }
+            None => (BoxMakeWriter::new(std::io::stderr), ansi),
+        };
+        let builder = FmtSubscriber::builder()
+            .with_max_level(self.level)
+            .with_ansi(ansi)
+            .with_writer(writer);
+        let result = match self.format {
+            LogFormat::Text => tracing::subscriber::set_global_default(builder.finish()),
+            LogFormat::Json => {
+                tracing::subscriber::set_global_default(builder.event_format(JsonFormat).finish())
+            }
+        };
+        result.map_err(|e| format!("unable to set up logging: {e}"))
+    }
+}
+
+/// Formats each event as a JSON object with its timestamp, level, target, and fields
+struct JsonFormat;
+
+impl<S, N> FormatEvent<S, N> for JsonFormat
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(
+        &self,
+        _ctx: &FmtContext<'_, S, N>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> fmt::Result {
+        let mut timestamp = String::new();
+        SystemTime.format_time(&mut Writer::new(&mut timestamp))?;
+
+        let mut line = Map::new();
+        line.insert("timestamp".to_string(), Value::String(timestamp));
+        line.insert(
+            "level".to_string(),
+            Value::String(event.metadata().level().to_string()),
+        );
+        line.insert(
+            "target".to_string(),
+            Value::String(event.metadata().target().to_string()),
+        );
+        event.record(&mut JsonFields(&mut line));
+        writeln!(writer, "{}", Value::Object(line))
+    }
+}
+
+/// Collects the fields of an event into a JSON object
+struct JsonFields<'a>(&'a mut Map<String, Value>);
+
+impl Visit for JsonFields<'_> {
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.0
+            .insert(field.name().to_string(), format!("{value:?}").into());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io;
+    use std::sync::{Arc, Mutex};
+
+    use tracing::Level;
+    use tracing_subscriber::FmtSubscriber;
+
+    use crate::logging::{JsonFormat, LogArgs, LogFormat};
+
+    #[test]
+    fn test_level() {
+        assert_eq!(Level::INFO, LogArgs::level(false, false));
+        assert_eq!(Level::DEBUG, LogArgs::level(true, false));
+        assert_eq!(Level::WARN, LogArgs::level(false, true));
+        assert_eq!(Ok(LogFormat::Json), LogFormat::parse("json"));
+        assert!(LogFormat::parse("xml").is_err());
+    }
+
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl io::Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_json_format() {
+        let buffer = Buffer::default();
+        let writer = buffer.clone();
+        let subscriber = FmtSubscriber::builder()
+            .with_writer(move || writer.clone())
+            .event_format(JsonFormat)
+            .finish();
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::warn!(attempt = 2, "Retrying {}", "request");
+        });
+
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let line: serde_json::Value = serde_json::from_str(&output).unwrap();
+        assert_eq!("WARN", line["level"]);
+        assert_eq!("Retrying request", line["message"]);
+        assert_eq!(2, line["attempt"]);
+        assert!(line["timestamp"].is_string());
+    }
+}
@@ -0,0 +1,247 @@
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::rc::Rc;
+
+use tracing::debug;
+
+use crate::digitalocean::droplet::DigitalOceanDropletClient;
+use crate::digitalocean::error::Error;
+use crate::digitalocean::firewall::{
+    FirewallInboundRule, FirewallOutboundRule, FirewallRuleTarget,
+};
+use crate::digitalocean::kubernetes::DigitalOceanKubernetesClient;
+use crate::digitalocean::loadbalancer::DigitalOceanLoadbalancerClient;
+
+/// The names of the droplets, Kubernetes clusters and load balancers that firewall rules refer to
+/// by ID, so that the rules can be logged in a form a person can review
+#[derive(Debug, Default)]
+pub struct ResourceNames {
+    droplets: HashMap<u32, String>,
+    kubernetes_clusters: HashMap<String, String>,
+    load_balancers: HashMap<String, String>,
+}
+
+impl ResourceNames {
+    /// Look up the names of the resources referenced by the targets, listing only the kinds of
+    /// resource that are referenced.  A listing that fails leaves its resources known by ID alone,
+    /// since the names are only used for logging.
+    pub async fn lookup(
+        droplet_client: &Rc<dyn DigitalOceanDropletClient>,
+        kubernetes_client: &Rc<dyn DigitalOceanKubernetesClient>,
+        load_balancer_client: &Rc<dyn DigitalOceanLoadbalancerClient>,
+        targets: &[&FirewallRuleTarget],
+    ) -> ResourceNames {
+        fn referenced<T>(
+            targets: &[&FirewallRuleTarget],
+            f: impl Fn(&FirewallRuleTarget) -> &Option<Vec<T>>,
+        ) -> bool {
+            targets
+                .iter()
+                .any(|t| f(t).as_ref().is_some_and(|ids| !ids.is_empty()))
+        }
+
+        fn or_unnamed<K>(
+            kind: &str,
+            names: Result<HashMap<K, String>, Error>,
+        ) -> HashMap<K, String> {
+            names.unwrap_or_else(|e| {
+                debug!("Unable to look up the names of {}: {}", kind, e);
+                HashMap::new()
+            })
+        }
+
+        let droplets = async {
+            if referenced(targets, |t| &t.droplet_ids) {
+                let droplets = droplet_client.get_droplet_summaries().await?;
+                Ok(droplets.into_iter().map(|d| (d.id, d.name)).collect())
+            } else {
+                Ok::<_, Error>(HashMap::new())
+            }
+        };
+        let kubernetes_clusters = async {
+            if referenced(targets, |t| &t.kubernetes_ids) {
+                let clusters = kubernetes_client.get_kubernetes_cluster_summaries().await?;
+                Ok(clusters.into_iter().map(|k| (k.id, k.name)).collect())
+            } else {
+                Ok::<_, Error>(HashMap::new())
+            }
+        };
+        let load_balancers = async {
+            if referenced(targets, |t| &t.load_balancer_uids) {
+                let load_balancers = load_balancer_client.get_load_balancer_summaries().await?;
+                Ok(load_balancers.into_iter().map(|l| (l.id, l.name)).collect())
+            } else {
+                Ok::<_, Error>(HashMap::new())
+            }
+        };
+        let (droplets, kubernetes_clusters, load_balancers) =
+            tokio::join!(droplets, kubernetes_clusters, load_balancers);
+
+        ResourceNames {
+            droplets: or_unnamed("droplets", droplets),
+            kubernetes_clusters: or_unnamed("Kubernetes clusters", kubernetes_clusters),
+            load_balancers: or_unnamed("load balancers", load_balancers),
+        }
+    }
+
+    /// Describe an inbound rule, e.g. `tcp port 22 from 203.0.113.7, droplet web-1 (1234)`
+    pub fn describe_inbound(&self, rule: &FirewallInboundRule) -> String {
+        format!(
+            "{} port {} from {}",
+            rule.protocol,
+            rule.ports,
+            self.describe_target(&rule.sources)
+        )
+    }
+
+    /// Describe an outbound rule, e.g. `tcp port 443 to load balancer ingress (4de7ac8b-...)`
+    pub fn describe_outbound(&self, rule: &FirewallOutboundRule) -> String {
+        format!(
+            "{} port {} to {}",
+            rule.protocol,
+            rule.ports,
+            self.describe_target(&rule.destinations)
+        )
+    }
+
+    /// List everything a rule allows traffic from or to, naming the resources given by ID
+    fn describe_target(&self, target: &FirewallRuleTarget) -> String {
+        fn named<K: Display>(kind: &str, id: &K, name: Option<&String>) -> String {
+            match name {
+                Some(name) => format!("{kind} {name} ({id})"),
+                None => format!("{kind} {id}"),
+            }
+        }
+
+        let parts: Vec<String> =
+            target
+                .addresses
+                .iter()
+                .flatten()
+                .cloned()
+                .chain(
+                    target
+                        .droplet_ids
+                        .iter()
+                        .flatten()
+                        .map(|id| named("droplet", id, self.droplets.get(id))),
+                )
+                .chain(
+                    target.kubernetes_ids.iter().flatten().map(|id| {
+                        named("Kubernetes cluster", id, self.kubernetes_clusters.get(id))
+                    }),
+                )
+                .chain(
+                    target
+                        .load_balancer_uids
+                        .iter()
+                        .flatten()
+                        .map(|id| named("load balancer", id, self.load_balancers.get(id))),
+                )
+                .chain(target.tags.iter().flatten().map(|tag| format!("tag {tag}")))
+                .collect();
+        if parts.is_empty() {
+            "nothing".to_string()
+        } else {
+            parts.join(", ")
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::rc::Rc;
+
+    use async_trait::async_trait;
+
+    use crate::digitalocean::droplet::{DigitalOceanDropletClient, Droplet, DropletSummary};
+    use crate::digitalocean::error::Error;
+    use crate::digitalocean::firewall::{
+        FirewallInboundRule, FirewallOutboundRule, FirewallRuleTarget,
+    };
+    use crate::digitalocean::kubernetes::{DigitalOceanKubernetesClient, KubernetesCluster};
+    use crate::digitalocean::loadbalancer::{DigitalOceanLoadbalancerClient, Loadbalancer};
+    use crate::names::ResourceNames;
+
+    struct TestDropletClient;
+
+    #[async_trait(?Send)]
+    impl DigitalOceanDropletClient for TestDropletClient {
+        async fn get_droplets(&self) -> Result<Vec<Droplet>, Error> {
+            panic!("Should only list droplet summaries")
+        }
+
+        async fn get_droplet_summaries(&self) -> Result<Vec<DropletSummary>, Error> {
+            Ok(vec![DropletSummary {
+                id: 1234,
+                name: "web-1".to_string(),
+            }])
+        }
+    }
+
+    struct FailingKubernetesClient;
+
+    #[async_trait(?Send)]
+    impl DigitalOceanKubernetesClient for FailingKubernetesClient {
+        async fn get_kubernetes_clusters(&self) -> Result<Vec<KubernetesCluster>, Error> {
+            Err(Error::PageLimit("too many pages".to_string()))
+        }
+    }
+
+    struct UnusedLoadbalancerClient;
+
+    #[async_trait(?Send)]
+    impl DigitalOceanLoadbalancerClient for UnusedLoadbalancerClient {
+        async fn get_load_balancers(&self) -> Result<Vec<Loadbalancer>, Error> {
+            panic!("No load balancer is referenced")
+        }
+    }
+
+    #[tokio::test]
+    async fn test_describe_rules() {
+        let sources = FirewallRuleTarget {
+            addresses: Some(vec!["203.0.113.7".to_string()]),
+            droplet_ids: Some(vec![1234, 5678]),
+            load_balancer_uids: None,
+            kubernetes_ids: Some(vec!["bd5f5959-5e1e-4205-a714-a914373942af".to_string()]),
+            tags: Some(vec!["web".to_string()]),
+        };
+        let droplet_client: Rc<dyn DigitalOceanDropletClient> = Rc::new(TestDropletClient);
+        let kubernetes_client: Rc<dyn DigitalOceanKubernetesClient> =
+            Rc::new(FailingKubernetesClient);
+        let load_balancer_client: Rc<dyn DigitalOceanLoadbalancerClient> =
+            Rc::new(UnusedLoadbalancerClient);
+        let names = ResourceNames::lookup(
+            &droplet_client,
+            &kubernetes_client,
+            &load_balancer_client,
+            &[&sources],
+        )
+        .await;
+
+        // the cluster cannot be named, so is left with its ID
+        assert_eq!(
+            "tcp port 22 from 203.0.113.7, droplet web-1 (1234), droplet 5678, Kubernetes \
+                cluster bd5f5959-5e1e-4205-a714-a914373942af, tag web",
+            names.describe_inbound(&FirewallInboundRule {
+                protocol: "tcp".to_string(),
+                ports: "22".to_string(),
+                sources,
+            })
+        );
+        assert_eq!(
+            "udp port 53 to nothing",
+            names.describe_outbound(&FirewallOutboundRule {
+                protocol: "udp".to_string(),
+                ports: "53".to_string(),
+                destinations: FirewallRuleTarget {
+                    addresses: None,
+                    droplet_ids: None,
+                    load_balancer_uids: None,
+                    kubernetes_ids: None,
+                    tags: None,
+                },
+            })
+        );
+    }
+}
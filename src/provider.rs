@@ -0,0 +1,561 @@
+//! The DNS services whose records can be kept pointed at this host.  DigitalOcean is the default;
+//! a DNS target can instead name another provider, so that one config file can keep records
+//! hosted in several places up to date.
+
+use std::fmt::{Display, Formatter};
+use std::net::IpAddr;
+use std::rc::Rc;
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use tracing::{error, info, warn};
+
+use crate::cli::DnsArgs;
+use crate::cloudflare::{CloudflareDnsClient, CloudflareDnsClientImpl, CloudflareRecord, Zone};
+use crate::digitalocean::dns::{record_fqdn, relative_record_name, RecordValue};
+use crate::hooks::{Change, Hooks};
+use crate::http::HttpOptions;
+use crate::secret::Secret;
+use crate::stats::RunStats;
+use crate::{
+    check_dns_target, dns_values, run_dns_target, split_fqdn, with_aliases, DigitalOceanClient,
+    Error, UpdateOptions,
+};
+
+/// The service that hosts the records of a DNS target
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default, Eq, PartialEq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum Provider {
+    #[default]
+    DigitalOcean,
+    Cloudflare,
+}
+
+impl Provider {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Provider::DigitalOcean => "digitalocean",
+            Provider::Cloudflare => "cloudflare",
+        }
+    }
+
+    /// Parse the name of a provider
+    pub fn parse(name: &str) -> Result<Provider, String> {
+        match name {
+            "digitalocean" => Ok(Provider::DigitalOcean),
+            "cloudflare" => Ok(Provider::Cloudflare),
+            _ => Err(format!("'{name}' is not one of digitalocean or cloudflare")),
+        }
+    }
+
+    pub fn is_default(&self) -> bool {
+        *self == Provider::default()
+    }
+}
+
+impl Display for Provider {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// A DNS service that can bring the records of a DNS target in line with the IP address(es)
+#[async_trait(?Send)]
+pub trait DynDnsProvider {
+    /// Bring the record named by the arguments, and its aliases, in line with the IP address(es)
+    async fn update_dns(
+        &self,
+        dns_args: DnsArgs,
+        ips: &[IpAddr],
+        options: &UpdateOptions,
+        stats: &RunStats,
+    ) -> Result<(), Error>;
+
+    /// Check whether the record named by the arguments, and its aliases, already hold the IP
+    /// address(es) without changing anything
+    async fn check_dns(&self, dns_args: DnsArgs, ips: &[IpAddr]) -> Result<bool, Error>;
+}
+
+/// Records hosted by DigitalOcean
+pub struct DigitalOceanProvider<'a> {
+    pub client: &'a DigitalOceanClient,
+}
+
+#[async_trait(?Send)]
+impl DynDnsProvider for DigitalOceanProvider<'_> {
+    async fn update_dns(
+        &self,
+        dns_args: DnsArgs,
+        ips: &[IpAddr],
+        options: &UpdateOptions,
+        stats: &RunStats,
+    ) -> Result<(), Error> {
+        run_dns_target(self.client, dns_args, ips, options, stats).await
+    }
+
+    async fn check_dns(&self, dns_args: DnsArgs, ips: &[IpAddr]) -> Result<bool, Error> {
+        check_dns_target(self.client, dns_args, ips).await
+    }
+}
+
+/// Records hosted by Cloudflare.  The name of a target is resolved among the zones the Cloudflare
+/// token has access to.  Records are matched by name and type only; proxying and any other
+/// Cloudflare settings of an existing record are left as they are.
+pub struct CloudflareProvider {
+    pub client: Rc<dyn CloudflareDnsClient>,
+}
+
+impl CloudflareProvider {
+    pub fn new(token: Secret, http: &HttpOptions) -> CloudflareProvider {
+        CloudflareProvider {
+            client: Rc::new(CloudflareDnsClientImpl::new(token, http)),
+        }
+    }
+
+    /// The zone that holds the record named by the arguments, and the name of the record within
+    /// the zone
+    async fn resolve_record(&self, dns_args: &DnsArgs) -> Result<(Zone, String), Error> {
+        match dns_args.domain {
+            Some(ref domain) => {
+                let zone = self
+                    .client
+                    .get_zone(domain)
+                    .await?
+                    .ok_or(Error::DomainNotFound())?;
+                let record = relative_record_name(&dns_args.record, &zone.name, dns_args.fqdn)
+                    .ok_or_else(|| Error::RecordOutsideDomain {
+                        record: dns_args.record.clone(),
+                        domain: domain.clone(),
+                    })?;
+                Ok((zone, record))
+            }
+            None => {
+                let zones = self.client.get_zones().await?;
+                let names: Vec<String> = zones.iter().map(|z| z.name.clone()).collect();
+                let (record, name) =
+                    split_fqdn(&dns_args.record, &names).ok_or(Error::DomainNotFound())?;
+                info!(
+                    "Resolved {} to record {} in Cloudflare zone {}",
+                    dns_args.record, record, name
+                );
+                let zone = zones.into_iter().find(|z| z.name == name).unwrap();
+                Ok((zone, record))
+            }
+        }
+    }
+
+    /// The record of a type with a fully qualified name, if there is one
+    async fn get_record(
+        &self,
+        zone: &Zone,
+        fqdn: &str,
+        rtype: &str,
+    ) -> Result<Option<CloudflareRecord>, Error> {
+        Ok(self
+            .client
+            .get_records(zone, fqdn, rtype)
+            .await?
+            .into_iter()
+            .next())
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn update_record(
+        &self,
+        zone: &Zone,
+        fqdn: &str,
+        rtype: &str,
+        value: &RecordValue,
+        dns_args: &DnsArgs,
+        options: &UpdateOptions,
+        stats: &RunStats,
+    ) -> Result<(), Error> {
+        let existing = stats
+            .time("record lookup", self.get_record(zone, fqdn, rtype))
+            .await?;
+        let record = match existing {
+            Some(record) if record.holds(value) && record.ttl == u32::from(dns_args.ttl) => {
+                info!("Record {} ({}) already set to {}", fqdn, rtype, value);
+                return Ok(());
+            }
+            Some(record) => {
+                info!("Will update record {} ({}) to {}", fqdn, rtype, value);
+                let change = change(fqdn, rtype, &record.content, value);
+                run_hooks(
+                    &options.hooks,
+                    &change,
+                    options.dry_run,
+                    stats,
+                    Hooks::before,
+                )
+                .await?;
+                let record = stats
+                    .time(
+                        "mutation",
+                        self.client.update_record(
+                            zone,
+                            &record,
+                            value,
+                            &dns_args.ttl,
+                            &options.dry_run,
+                        ),
+                    )
+                    .await?;
+                info!("Successfully updated record!");
+                (record, change)
+            }
+            None if !dns_args.create_if_missing => {
+                return Err(Error::RecordNotFound(format!("{} ({})", fqdn, rtype)));
+            }
+            None => {
+                info!("Will create new record {} ({}) -> {}", fqdn, rtype, value);
+                let change = change(fqdn, rtype, "", value);
+                run_hooks(
+                    &options.hooks,
+                    &change,
+                    options.dry_run,
+                    stats,
+                    Hooks::before,
+                )
+                .await?;
+                let record = stats
+                    .time(
+                        "mutation",
+                        self.client.create_record(
+                            zone,
+                            fqdn,
+                            rtype,
+                            value,
+                            &dns_args.ttl,
+                            &options.dry_run,
+                        ),
+                    )
+                    .await?;
+                info!("Successfully created new record! (TTL {}s)", record.ttl);
+                (record, change)
+            }
+        };
+        let (record, change) = record;
+        stats.record_change(Some(&change));
+        if options.verify && !options.dry_run {
+            stats
+                .time("verification", self.verify_record(zone, &record, value))
+                .await?;
+        }
+        run_hooks(
+            &options.hooks,
+            &change,
+            options.dry_run,
+            stats,
+            Hooks::after,
+        )
+        .await
+    }
+
+    /// Re-query a record after writing it and fail if it does not hold what was written
+    async fn verify_record(
+        &self,
+        zone: &Zone,
+        written: &CloudflareRecord,
+        value: &RecordValue,
+    ) -> Result<(), Error> {
+        match self.get_record(zone, &written.name, &written.typ).await? {
+            Some(stored) if stored.holds(value) && stored.ttl == written.ttl => {
+                info!("Verified record {} ({})", written.name, written.typ);
+                Ok(())
+            }
+            Some(stored) => Err(Error::VerificationFailed(format!(
+                "record {} ({}) holds {} with a TTL of {} rather than {} with a TTL of {}",
+                written.name, written.typ, stored.content, stored.ttl, value, written.ttl
+            ))),
+            None => Err(Error::VerificationFailed(format!(
+                "record {} ({}) not found after writing it",
+                written.name, written.typ
+            ))),
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl DynDnsProvider for CloudflareProvider {
+    async fn update_dns(
+        &self,
+        dns_args: DnsArgs,
+        ips: &[IpAddr],
+        options: &UpdateOptions,
+        stats: &RunStats,
+    ) -> Result<(), Error> {
+        async {
+            let values = dns_values(&dns_args, ips)?;
+            let (zone, record) = stats
+                .time("domain lookup", self.resolve_record(&dns_args))
+                .await?;
+            for record in with_aliases(record, &dns_args.aliases, dns_args.with_www) {
+                let fqdn = record_fqdn(&record, &zone.name);
+                for (rtype, value) in &values {
+                    self.update_record(&zone, &fqdn, rtype, value, &dns_args, options, stats)
+                        .await?;
+                }
+            }
+            Ok(())
+        }
+        .await
+        .inspect_err(|e| {
+            error!(
+                "Encountered error while updating Cloudflare DNS record: {}",
+                e
+            )
+        })
+    }
+
+    async fn check_dns(&self, dns_args: DnsArgs, ips: &[IpAddr]) -> Result<bool, Error> {
+        async {
+            let values = dns_values(&dns_args, ips)?;
+            let (zone, record) = self.resolve_record(&dns_args).await?;
+            let mut in_sync = true;
+            for record in with_aliases(record, &dns_args.aliases, dns_args.with_www) {
+                let fqdn = record_fqdn(&record, &zone.name);
+                for (rtype, value) in &values {
+                    in_sync &= match self.get_record(&zone, &fqdn, rtype).await? {
+                        Some(r) if r.holds(value) && r.ttl == u32::from(dns_args.ttl) => {
+                            info!("Record {} ({}) matches {}", fqdn, rtype, value);
+                            true
+                        }
+                        Some(r) => {
+                            warn!(
+                                "Record {} ({}) is set to {} with a TTL of {} but should be {} \
+                                    with a TTL of {}",
+                                fqdn, rtype, r.content, r.ttl, value, dns_args.ttl
+                            );
+                            false
+                        }
+                        None => {
+                            warn!(
+                                "Record {} ({}) does not exist but should be {}",
+                                fqdn, rtype, value
+                            );
+                            false
+                        }
+                    };
+                }
+            }
+            Ok(in_sync)
+        }
+        .await
+        .inspect_err(|e| {
+            error!(
+                "Encountered error while checking Cloudflare DNS record: {}",
+                e
+            )
+        })
+    }
+}
+
+fn change(fqdn: &str, rtype: &str, old: &str, value: &RecordValue) -> Change {
+    Change {
+        kind: "dns",
+        resource: format!("{} ({})", fqdn, rtype),
+        old_ip: old.to_string(),
+        new_ip: value.data.clone(),
+    }
+}
+
+async fn run_hooks(
+    hooks: &Hooks,
+    change: &Change,
+    dry_run: bool,
+    stats: &RunStats,
+    hook: fn(&Hooks, &Change, bool) -> Result<(), String>,
+) -> Result<(), Error> {
+    stats
+        .time("hooks", async { hook(hooks, change, dry_run) })
+        .await
+        .map_err(Error::Hook)
+}
+
+/// The providers the DNS targets of a run can use.  Cloudflare is only available when its token
+/// was given.
+pub struct DnsProviders<'a> {
+    digitalocean: DigitalOceanProvider<'a>,
+    cloudflare: Option<CloudflareProvider>,
+}
+
+impl<'a> DnsProviders<'a> {
+    pub fn new(
+        client: &'a DigitalOceanClient,
+        cloudflare_token: Option<Secret>,
+        http: &HttpOptions,
+    ) -> DnsProviders<'a> {
+        DnsProviders {
+            digitalocean: DigitalOceanProvider { client },
+            cloudflare: cloudflare_token.map(|token| CloudflareProvider::new(token, http)),
+        }
+    }
+
+    pub fn get(&self, provider: Provider) -> Result<&dyn DynDnsProvider, Error> {
+        match provider {
+            Provider::DigitalOcean => Ok(&self.digitalocean),
+            Provider::Cloudflare => self
+                .cloudflare
+                .as_ref()
+                .map(|p| p as &dyn DynDnsProvider)
+                .ok_or(Error::ProviderNotConfigured(provider)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::IpAddr;
+    use std::rc::Rc;
+
+    use crate::cli::DnsArgs;
+    use crate::cloudflare::fixtures::{record_json, response, zone_json, RECORD_ID, ZONE_ID};
+    use crate::cloudflare::CloudflareDnsClientImpl;
+    use crate::http::HttpOptions;
+    use crate::provider::{CloudflareProvider, DnsProviders, DynDnsProvider, Provider};
+    use crate::stats::RunStats;
+    use crate::{DigitalOceanClient, Error, UpdateOptions};
+
+    fn dns_args(record: &str, domain: Option<&str>) -> DnsArgs {
+        DnsArgs {
+            record: record.to_string(),
+            domain: domain.map(str::to_string),
+            rtype: "A".to_string(),
+            data: None,
+            from_droplet: None,
+            from_load_balancer: None,
+            priority: None,
+            port: None,
+            weight: None,
+            ttl: 60,
+            lower_ttl: None,
+            aliases: vec![],
+            with_www: false,
+            check_delegation: false,
+            force: false,
+            create_if_missing: false,
+            create_domain: false,
+            all_domains: false,
+            dual_stack: false,
+            fqdn: false,
+            audit_txt: false,
+            provider: Provider::Cloudflare,
+        }
+    }
+
+    fn provider(server_url: String) -> CloudflareProvider {
+        CloudflareProvider {
+            client: Rc::new(CloudflareDnsClientImpl::new_for_test(
+                "foo".to_string(),
+                server_url,
+            )),
+        }
+    }
+
+    async fn mock_zone(server: &mut mockito::Server) -> mockito::Mock {
+        server
+            .mock("GET", "/client/v4/zones?name=example.com")
+            .with_status(200)
+            .with_header("Content-Type", "application/json")
+            .with_body(response(json!([zone_json()])))
+            .create_async()
+            .await
+    }
+
+    async fn mock_records(server: &mut mockito::Server, content: &str) -> mockito::Mock {
+        server
+            .mock(
+                "GET",
+                format!("/client/v4/zones/{ZONE_ID}/dns_records?name=home.example.com&type=A")
+                    .as_str(),
+            )
+            .with_status(200)
+            .with_header("Content-Type", "application/json")
+            .with_body(response(json!([record_json(content)])))
+            .create_async()
+            .await
+    }
+
+    #[test]
+    fn test_parse_provider() {
+        assert_eq!(Ok(Provider::DigitalOcean), Provider::parse("digitalocean"));
+        assert_eq!(Ok(Provider::Cloudflare), Provider::parse("cloudflare"));
+        assert!(Provider::parse("route53").is_err());
+        assert!(Provider::default().is_default());
+    }
+
+    #[tokio::test]
+    async fn test_update_cloudflare_record() {
+        let mut server = mockito::Server::new_async().await;
+        let _m_zone = mock_zone(&mut server).await;
+        let _m_records = mock_records(&mut server, "1.2.3.4").await;
+        let _m_update = server
+            .mock(
+                "PATCH",
+                format!("/client/v4/zones/{ZONE_ID}/dns_records/{RECORD_ID}").as_str(),
+            )
+            .match_body(mockito::Matcher::Json(json!({
+                "content": "2.3.4.5",
+                "ttl": 60
+            })))
+            .with_status(200)
+            .with_header("Content-Type", "application/json")
+            .with_body(response(record_json("2.3.4.5")))
+            .create_async()
+            .await;
+
+        let stats = RunStats::start();
+        let ip = "2.3.4.5".parse::<IpAddr>().unwrap();
+        let resp = provider(server.url())
+            .update_dns(
+                dns_args("Home", Some("example.com")),
+                &[ip],
+                &UpdateOptions::default(),
+                &stats,
+            )
+            .await;
+        assert!(resp.is_ok(), "{:?}", resp);
+        _m_update.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_update_cloudflare_record_in_sync() {
+        let mut server = mockito::Server::new_async().await;
+        let _m_zone = mock_zone(&mut server).await;
+        let _m_records = mock_records(&mut server, "1.2.3.4").await;
+        let _m_update = server
+            .mock("PATCH", mockito::Matcher::Any)
+            .expect(0)
+            .create_async()
+            .await;
+
+        let ip = "1.2.3.4".parse::<IpAddr>().unwrap();
+        let provider = provider(server.url());
+        let resp = provider
+            .check_dns(dns_args("home", Some("example.com")), &[ip])
+            .await;
+        assert!(matches!(resp, Ok(true)), "{:?}", resp);
+        let resp = provider
+            .update_dns(
+                dns_args("home", Some("example.com")),
+                &[ip],
+                &UpdateOptions::default(),
+                &RunStats::start(),
+            )
+            .await;
+        assert!(resp.is_ok(), "{:?}", resp);
+        _m_update.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_provider_not_configured() {
+        let client = DigitalOceanClient::new_for_test("foo".to_string(), "http://localhost".into());
+        let providers = DnsProviders::new(&client, None, &HttpOptions::default());
+        assert!(providers.get(Provider::DigitalOcean).is_ok());
+        assert!(matches!(
+            providers.get(Provider::Cloudflare),
+            Err(Error::ProviderNotConfigured(Provider::Cloudflare))
+        ));
+    }
+}
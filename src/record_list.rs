@@ -0,0 +1,127 @@
+use serde::Serialize;
+
+use crate::digitalocean::dns::DomainRecord;
+
+/// The details of one DNS record, as printed by `dns list`
+#[derive(Serialize, Debug, Clone, Eq, PartialEq)]
+pub struct RecordRow {
+    pub id: u32,
+    pub name: String,
+    #[serde(rename = "type")]
+    pub typ: String,
+    pub data: String,
+    pub ttl: u16,
+    pub priority: Option<u16>,
+    pub port: Option<u16>,
+    pub weight: Option<u16>,
+}
+
+impl From<&DomainRecord> for RecordRow {
+    fn from(record: &DomainRecord) -> Self {
+        RecordRow {
+            id: record.id,
+            name: record.name.clone(),
+            typ: record.typ.clone(),
+            data: record.data.clone(),
+            ttl: record.ttl,
+            priority: record.priority,
+            port: record.port,
+            weight: record.weight,
+        }
+    }
+}
+
+/// Whether a record passes the `--rtype` and `--name` filters of `dns list`
+pub fn matches_filters(record: &DomainRecord, rtype: Option<&str>, name: Option<&str>) -> bool {
+    rtype.is_none_or(|rtype| record.typ.eq_ignore_ascii_case(rtype))
+        && name.is_none_or(|name| record.name.eq_ignore_ascii_case(name))
+}
+
+/// Lay out the records as a table with a header and one aligned line per record.  The priority,
+/// port, and weight of MX and SRV records are shown before their data, as in a zone file.
+pub fn format_table(rows: &[RecordRow]) -> String {
+    let cells: Vec<[String; 5]> =
+        std::iter::once(["ID", "NAME", "TYPE", "TTL", "DATA"].map(String::from))
+            .chain(rows.iter().map(|row| {
+                let data = [row.priority, row.weight, row.port]
+                    .iter()
+                    .flatten()
+                    .map(|n| n.to_string())
+                    .chain(std::iter::once(row.data.clone()))
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                [
+                    row.id.to_string(),
+                    row.name.clone(),
+                    row.typ.clone(),
+                    row.ttl.to_string(),
+                    data,
+                ]
+            }))
+            .collect();
+    let widths: Vec<usize> = (0..5)
+        .map(|i| cells.iter().map(|c| c[i].len()).max().unwrap_or(0))
+        .collect();
+
+    cells
+        .iter()
+        .map(|line| {
+            let padded: Vec<String> = line
+                .iter()
+                .zip(&widths)
+                .map(|(cell, width)| format!("{cell:width$}"))
+                .collect();
+            format!("{}\n", padded.join("  ").trim_end())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::digitalocean::dns::DomainRecord;
+    use crate::record_list::{format_table, matches_filters, RecordRow};
+
+    fn record(id: u32, name: &str, typ: &str, data: &str) -> DomainRecord {
+        DomainRecord {
+            id,
+            typ: typ.to_string(),
+            name: name.to_string(),
+            data: data.to_string(),
+            priority: None,
+            port: None,
+            ttl: 1800,
+            weight: None,
+            flags: None,
+            tag: None,
+        }
+    }
+
+    #[test]
+    fn test_matches_filters() {
+        let home = record(1, "home", "A", "1.2.3.4");
+        assert!(matches_filters(&home, None, None));
+        assert!(matches_filters(&home, Some("a"), Some("HOME")));
+        assert!(!matches_filters(&home, Some("AAAA"), None));
+        assert!(!matches_filters(&home, None, Some("vpn")));
+    }
+
+    #[test]
+    fn test_format_table() {
+        let rows: Vec<RecordRow> = [
+            record(12, "home", "A", "1.2.3.4"),
+            DomainRecord {
+                priority: Some(10),
+                ..record(345, "@", "MX", "mail.example.com.")
+            },
+        ]
+        .iter()
+        .map(Into::into)
+        .collect();
+        assert_eq!(
+            "ID   NAME  TYPE  TTL   DATA\n\
+             12   home  A     1800  1.2.3.4\n\
+             345  @     MX    1800  10 mail.example.com.\n",
+            format_table(&rows)
+        );
+    }
+}
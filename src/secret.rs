@@ -0,0 +1,97 @@
+//! A wrapper for API tokens that keeps them out of logs, panics, and printed settings.  The value
+//! can only be read with [`Secret::expose`], so that every place it leaves the process is explicit.
+
+use std::fmt::{Debug, Display, Formatter};
+
+use serde::{Deserialize, Serialize, Serializer};
+
+const REDACTED: &str = "<redacted>";
+
+/// A value that is written as `<redacted>` whenever it is formatted or serialized
+#[derive(Deserialize, Clone, Eq, PartialEq)]
+#[serde(transparent)]
+pub struct Secret(String);
+
+impl Secret {
+    pub fn new(value: String) -> Secret {
+        Secret(value)
+    }
+
+    /// The actual value, for sending it to the service it authenticates with
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+
+    /// A fingerprint telling secrets apart in files written to disk without revealing them, the
+    /// 64-bit FNV-1a hash of the value
+    pub fn fingerprint(&self) -> String {
+        let hash = self.0.bytes().fold(0xcbf29ce484222325_u64, |hash, byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(0x100000001b3)
+        });
+        format!("{hash:016x}")
+    }
+
+    /// Parse a secret given on the command line or in an environment variable
+    pub fn parse(value: &str) -> Result<Secret, String> {
+        Ok(Secret(value.to_string()))
+    }
+}
+
+impl From<String> for Secret {
+    fn from(value: String) -> Secret {
+        Secret(value)
+    }
+}
+
+impl Debug for Secret {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(REDACTED)
+    }
+}
+
+impl Display for Secret {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(REDACTED)
+    }
+}
+
+impl Serialize for Secret {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(REDACTED)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::secret::Secret;
+
+    #[test]
+    fn test_secret_is_redacted() {
+        let secret = Secret::new("dop_v1_abc123".to_string());
+        assert_eq!("dop_v1_abc123", secret.expose());
+        assert_eq!("<redacted>", format!("{secret}"));
+        assert_eq!("<redacted>", format!("{secret:?}"));
+        assert_eq!("Some(<redacted>)", format!("{:?}", Some(secret.clone())));
+        assert_eq!(
+            "\"<redacted>\"",
+            serde_json::to_string(&secret).unwrap().as_str()
+        );
+        assert_eq!(
+            secret,
+            serde_json::from_str::<Secret>("\"dop_v1_abc123\"").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_fingerprint() {
+        let secret = Secret::new("dop_v1_abc123".to_string());
+        assert_eq!(16, secret.fingerprint().len());
+        assert!(!secret.fingerprint().contains("abc123"));
+        assert_eq!(secret.fingerprint(), secret.clone().fingerprint());
+        assert_ne!(
+            secret.fingerprint(),
+            Secret::new("dop_v1_abc124".to_string()).fingerprint()
+        );
+        assert_eq!("cbf29ce484222325", Secret::new(String::new()).fingerprint());
+    }
+}
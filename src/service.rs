@@ -0,0 +1,317 @@
+//! Running under the service manager of the host with `--service`.  On Linux the daemon reports its
+//! readiness, status and watchdog pings to systemd over the `NOTIFY_SOCKET` of a `Type=notify`
+//! unit.  On Windows it runs under the Service Control Manager, which stops it through a control
+//! request rather than a signal.
+
+use std::cell::Cell;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use tracing::{debug, info};
+
+use crate::cli::ServiceInstallArgs;
+
+/// The name the service is installed under unless another is given
+pub const DEFAULT_SERVICE_NAME: &str = "digitalocean-dyn-dns";
+
+/// Reports the state of the daemon to systemd.  Every report is a no-op unless the daemon runs with
+/// `--service` under a unit that gave it a notification socket, or on platforms without systemd.
+#[derive(Debug, Default)]
+pub struct ServiceNotifier {
+    enabled: bool,
+    /// How often systemd expects a watchdog ping, if the unit sets `WatchdogSec`
+    watchdog: Option<Duration>,
+    last_ping: Cell<Option<Instant>>,
+}
+
+impl ServiceNotifier {
+    pub fn new(enabled: bool) -> ServiceNotifier {
+        ServiceNotifier {
+            enabled,
+            watchdog: if enabled { watchdog_interval() } else { None },
+            last_ping: Cell::new(None),
+        }
+    }
+
+    /// Tell the service manager that startup has finished
+    pub fn ready(&self) {
+        if let Some(watchdog) = self.watchdog {
+            info!(
+                "Sending watchdog pings to systemd every {}s",
+                (watchdog / 2).as_secs_f32()
+            );
+        }
+        self.notify(Notification::Ready);
+        self.last_ping.set(Some(Instant::now()));
+    }
+
+    /// Describe what the daemon last did, as shown by `systemctl status`
+    pub fn status(&self, status: &str) {
+        self.notify(Notification::Status(status));
+    }
+
+    /// Send a watchdog ping if half of the watchdog interval has passed since the last one
+    pub fn keep_alive(&self) {
+        let Some(watchdog) = self.watchdog else {
+            return;
+        };
+        if self
+            .last_ping
+            .get()
+            .is_none_or(|last| last.elapsed() >= watchdog / 2)
+        {
+            self.notify(Notification::Watchdog);
+            self.last_ping.set(Some(Instant::now()));
+        }
+    }
+
+    /// Tell the service manager that the daemon is shutting down
+    pub fn stopping(&self) {
+        self.notify(Notification::Stopping);
+    }
+
+    fn notify(&self, notification: Notification) {
+        if self.enabled {
+            if let Err(e) = send(&notification) {
+                debug!("Unable to notify the service manager: {}", e);
+            }
+        }
+    }
+}
+
+enum Notification<'a> {
+    Ready,
+    Status(&'a str),
+    Watchdog,
+    Stopping,
+}
+
+#[cfg(target_os = "linux")]
+fn send(notification: &Notification) -> std::io::Result<()> {
+    use sd_notify::NotifyState;
+
+    let state = match notification {
+        Notification::Ready => NotifyState::Ready,
+        Notification::Status(status) => NotifyState::Status(status),
+        Notification::Watchdog => NotifyState::Watchdog,
+        Notification::Stopping => NotifyState::Stopping,
+    };
+    sd_notify::notify(false, &[state])
+}
+
+#[cfg(not(target_os = "linux"))]
+fn send(_notification: &Notification) -> std::io::Result<()> {
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn watchdog_interval() -> Option<Duration> {
+    let mut usec = 0;
+    sd_notify::watchdog_enabled(false, &mut usec).then(|| Duration::from_micros(usec))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn watchdog_interval() -> Option<Duration> {
+    None
+}
+
+/// A systemd unit that runs the targets of the config file as a `Type=notify` service with a
+/// watchdog
+pub fn unit_file(exe: &Path, config: &Path) -> String {
+    format!(
+        "[Unit]\n\
+         Description=Keep DigitalOcean resources pointed at the IP address of this host\n\
+         Wants=network-online.target\n\
+         After=network-online.target\n\
+         \n\
+         [Service]\n\
+         Type=notify\n\
+         ExecStart=\"{}\" --config \"{}\" --service\n\
+         WatchdogSec=120\n\
+         Restart=on-failure\n\
+         # keep the API token out of the environment and the config file\n\
+         #LoadCredential=digitalocean-token:/etc/digitalocean-dyn-dns/token\n\
+         #Environment=DIGITAL_OCEAN_TOKEN_FILE=%d/digitalocean-token\n\
+         \n\
+         [Install]\n\
+         WantedBy=multi-user.target\n",
+        exe.display(),
+        config.display()
+    )
+}
+
+/// Install the service: on Linux print a systemd unit for it, and on Windows register it with the
+/// Service Control Manager
+pub fn install(install_args: &ServiceInstallArgs) -> Result<(), String> {
+    let exe = std::env::current_exe()
+        .map_err(|e| format!("unable to find the path of this program: {e}"))?;
+    let config = std::fs::canonicalize(&install_args.config).map_err(|e| {
+        format!(
+            "unable to find the config file {}: {}",
+            install_args.config.display(),
+            e
+        )
+    })?;
+    install_service(&install_args.name, &exe, &config)
+}
+
+#[cfg(target_os = "linux")]
+fn install_service(name: &str, exe: &Path, config: &Path) -> Result<(), String> {
+    print!("{}", unit_file(exe, config));
+    eprintln!(
+        "Save the unit as /etc/systemd/system/{name}.service, then enable it with \
+        `systemctl enable --now {name}`"
+    );
+    Ok(())
+}
+
+#[cfg(windows)]
+fn install_service(name: &str, exe: &Path, config: &Path) -> Result<(), String> {
+    windows::install(name, exe, config).map_err(|e| format!("unable to install the service: {e}"))
+}
+
+#[cfg(not(any(target_os = "linux", windows)))]
+fn install_service(_name: &str, _exe: &Path, _config: &Path) -> Result<(), String> {
+    Err("installing a service is only supported on Linux and Windows".to_string())
+}
+
+#[cfg(windows)]
+pub mod windows {
+    use std::ffi::OsString;
+    use std::path::Path;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use tracing::error;
+    use windows_service::service::{
+        ServiceAccess, ServiceControl, ServiceControlAccept, ServiceErrorControl, ServiceExitCode,
+        ServiceInfo, ServiceStartType, ServiceState, ServiceStatus, ServiceType,
+    };
+    use windows_service::service_control_handler::{self, ServiceControlHandlerResult};
+    use windows_service::service_dispatcher;
+    use windows_service::service_manager::{ServiceManager, ServiceManagerAccess};
+
+    use crate::cli::Invocation;
+    use crate::service::{ServiceNotifier, DEFAULT_SERVICE_NAME};
+    use crate::{exit_code, run_daemon};
+
+    /// Hand the process over to the Service Control Manager, which runs the daemon on a thread of
+    /// its own until the service is stopped.  Returns once the service has stopped.
+    pub fn run() -> windows_service::Result<()> {
+        service_dispatcher::start(DEFAULT_SERVICE_NAME, service_main)
+    }
+
+    /// The entry point called by the Service Control Manager.  The arguments given when starting
+    /// the service are ignored; the daemon is configured by the arguments it was installed with.
+    extern "system" fn service_main(_argc: u32, _argv: *mut *mut u16) {
+        if let Err(e) = run_service() {
+            error!("Unable to run as a Windows service: {}", e);
+        }
+    }
+
+    fn run_service() -> windows_service::Result<()> {
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let stop = Arc::clone(&shutdown);
+        let status_handle = service_control_handler::register(
+            DEFAULT_SERVICE_NAME,
+            move |control| match control {
+                ServiceControl::Stop | ServiceControl::Shutdown => {
+                    stop.store(true, Ordering::SeqCst);
+                    ServiceControlHandlerResult::NoError
+                }
+                ServiceControl::Interrogate => ServiceControlHandlerResult::NoError,
+                _ => ServiceControlHandlerResult::NotImplemented,
+            },
+        )?;
+        let set_state = |state: ServiceState, exit: u32| {
+            status_handle.set_service_status(ServiceStatus {
+                service_type: ServiceType::OWN_PROCESS,
+                current_state: state,
+                controls_accepted: if state == ServiceState::Running {
+                    ServiceControlAccept::STOP | ServiceControlAccept::SHUTDOWN
+                } else {
+                    ServiceControlAccept::empty()
+                },
+                exit_code: ServiceExitCode::ServiceSpecific(exit),
+                checkpoint: 0,
+                wait_hint: Duration::default(),
+                process_id: None,
+            })
+        };
+        set_state(ServiceState::Running, 0)?;
+
+        let exit = match Invocation::parse_args() {
+            Invocation::Update(args) => tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .expect("Unable to start the async runtime")
+                .block_on(run_daemon(&args, &shutdown, &ServiceNotifier::default()))
+                .map_or_else(|e| exit_code(&e) as u32, |()| 0),
+            _ => 1,
+        };
+        set_state(ServiceState::Stopped, exit)
+    }
+
+    /// Register the service with the Service Control Manager to start with the system
+    pub fn install(name: &str, exe: &Path, config: &Path) -> windows_service::Result<()> {
+        let manager = ServiceManager::local_computer(
+            None::<&str>,
+            ServiceManagerAccess::CONNECT | ServiceManagerAccess::CREATE_SERVICE,
+        )?;
+        let service = manager.create_service(
+            &ServiceInfo {
+                name: OsString::from(name),
+                display_name: OsString::from("DigitalOcean dynamic DNS"),
+                service_type: ServiceType::OWN_PROCESS,
+                start_type: ServiceStartType::AutoStart,
+                error_control: ServiceErrorControl::Normal,
+                executable_path: exe.to_path_buf(),
+                launch_arguments: vec![
+                    OsString::from("--config"),
+                    config.as_os_str().to_os_string(),
+                    OsString::from("--service"),
+                ],
+                dependencies: vec![],
+                account_name: None,
+                account_password: None,
+            },
+            ServiceAccess::CHANGE_CONFIG,
+        )?;
+        service.set_description(
+            "Keep DigitalOcean resources pointed at the IP address of this host",
+        )?;
+        println!("Installed service {name}; start it with `sc start {name}`");
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use crate::service::{unit_file, ServiceNotifier};
+
+    #[test]
+    fn test_unit_file() {
+        let unit = unit_file(
+            Path::new("/usr/local/bin/digitalocean-dyn-dns"),
+            Path::new("/etc/digitalocean-dyn-dns/config.yaml"),
+        );
+        assert!(unit.contains("Type=notify\n"));
+        assert!(unit.contains(
+            "ExecStart=\"/usr/local/bin/digitalocean-dyn-dns\" --config \
+                \"/etc/digitalocean-dyn-dns/config.yaml\" --service\n"
+        ));
+        assert!(unit.contains("WatchdogSec="));
+    }
+
+    #[test]
+    fn test_disabled_notifier() {
+        let notifier = ServiceNotifier::new(false);
+        assert_eq!(None, notifier.watchdog);
+        notifier.ready();
+        notifier.keep_alive();
+        notifier.stopping();
+    }
+}
@@ -0,0 +1,338 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::io::Write;
+use std::net::IpAddr;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::cli::SubcmdArgs;
+use crate::secret::Secret;
+
+/// What was last published to each target, as kept in the `--state-file` between runs
+#[derive(Deserialize, Serialize, Debug, Default, Clone, PartialEq)]
+pub struct State {
+    #[serde(default)]
+    pub targets: Vec<TargetState>,
+    /// The firewalls matched by each API token, keyed by the fingerprint of the token, as tokens
+    /// of different accounts can match different firewalls of the same name
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub firewalls: BTreeMap<String, FirewallIds>,
+}
+
+/// The IDs of the firewalls that targets have matched by name, so that later runs look them up by
+/// ID and notice when a firewall is renamed or deleted
+#[derive(Deserialize, Serialize, Debug, Default, Clone, PartialEq)]
+#[serde(transparent)]
+pub struct FirewallIds(BTreeMap<String, String>);
+
+/// The address(es) last published to one target
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct TargetState {
+    /// The target exactly as configured, so that changing any of its settings publishes it again
+    pub target: SubcmdArgs,
+    pub ips: Vec<IpAddr>,
+    /// Seconds since the Unix epoch when the addresses were first published to the target
+    pub published_at: u64,
+    /// Seconds since the Unix epoch when the target was last confirmed to hold the addresses
+    pub confirmed_at: u64,
+    /// Addresses published to the target before its current ones
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub retired: Vec<RetiredIp>,
+}
+
+/// An address that a target no longer holds as its current address
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct RetiredIp {
+    pub ip: IpAddr,
+    /// Seconds since the Unix epoch when the address stopped being published
+    pub retired_at: u64,
+}
+
+impl TargetState {
+    /// The retired addresses that stopped being published at least `retention` before `now`
+    pub fn expired(&self, retention: Duration, now: u64) -> Vec<IpAddr> {
+        self.retired
+            .iter()
+            .filter(|retired| now.saturating_sub(retired.retired_at) >= retention.as_secs())
+            .map(|retired| retired.ip)
+            .collect()
+    }
+}
+
+impl FirewallIds {
+    /// The ID of the firewall last matched by this name
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.0.get(name).map(String::as_str)
+    }
+
+    /// Track a firewall by its ID under its current name, forgetting any name it had before
+    pub fn track(&mut self, name: &str, id: &str) {
+        self.0.retain(|_, tracked| tracked != id);
+        self.0.insert(name.to_string(), id.to_string());
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl State {
+    /// Read the state file, starting afresh if it does not exist yet.  A file that cannot be
+    /// parsed is also ignored with a warning, so that it gets replaced by the next successful run
+    /// rather than blocking every run after it.
+    pub fn load(path: &Path) -> Result<State, io::Error> {
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(State::default()),
+            Err(e) => return Err(e),
+        };
+        Ok(serde_json::from_str(&contents).unwrap_or_else(|e| {
+            warn!("Ignoring unreadable state file {}: {}", path.display(), e);
+            State::default()
+        }))
+    }
+
+    /// Write the state file, replacing it atomically so that an interrupted write does not lose
+    /// the previous state
+    pub fn save(&self, path: &Path) -> Result<(), io::Error> {
+        save_json(self, path)
+    }
+
+    /// The IDs of the firewalls matched by the targets updated with the API token
+    pub fn firewall_ids(&self, token: &Secret) -> FirewallIds {
+        self.firewalls
+            .get(&token.fingerprint())
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Replace the IDs of the firewalls matched by the targets updated with the API token
+    pub fn set_firewall_ids(&mut self, token: &Secret, ids: FirewallIds) {
+        if ids.is_empty() {
+            self.firewalls.remove(&token.fingerprint());
+        } else {
+            self.firewalls.insert(token.fingerprint(), ids);
+        }
+    }
+
+    /// What was last published to a target, if it has been published with its current settings
+    pub fn get(&self, target: &SubcmdArgs) -> Option<&TargetState> {
+        self.targets.iter().find(|t| t.target == *target)
+    }
+
+    /// Record that a target now holds the given address(es).  The addresses it held before are
+    /// retired if the target retains them, and otherwise forgotten.
+    pub fn record(&mut self, target: &SubcmdArgs, ips: &[IpAddr]) {
+        let now = now();
+        match self.targets.iter_mut().find(|t| t.target == *target) {
+            Some(state) => {
+                if state.ips != ips {
+                    let retiring = state.ips.iter().filter(|ip| !ips.contains(ip));
+                    for ip in retiring.filter(|_| target.retains_addresses()) {
+                        state.retired.retain(|retired| retired.ip != *ip);
+                        state.retired.push(RetiredIp {
+                            ip: *ip,
+                            retired_at: now,
+                        });
+                    }
+                    state.retired.retain(|retired| !ips.contains(&retired.ip));
+                    state.ips = ips.to_vec();
+                    state.published_at = now;
+                }
+                if !target.retains_addresses() {
+                    state.retired.clear();
+                }
+                state.confirmed_at = now;
+            }
+            None => self.targets.push(TargetState {
+                target: target.clone(),
+                ips: ips.to_vec(),
+                published_at: now,
+                confirmed_at: now,
+                retired: Vec::new(),
+            }),
+        }
+    }
+
+    /// Forget retired addresses of a target once they have been removed from it
+    pub fn forget(&mut self, target: &SubcmdArgs, ips: &[IpAddr]) {
+        if let Some(state) = self.targets.iter_mut().find(|t| t.target == *target) {
+            state.retired.retain(|retired| !ips.contains(&retired.ip));
+        }
+    }
+}
+
+/// Seconds since the Unix epoch
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+/// Write a value to a JSON file, replacing the file atomically by writing a temporary file next
+/// to it and renaming that over it
+pub fn save_json<T: Serialize>(value: &T, path: &Path) -> Result<(), io::Error> {
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "path is not a file"))?;
+    let mut tmp_name = file_name.to_os_string();
+    tmp_name.push(".tmp");
+    let tmp_path = path.with_file_name(tmp_name);
+
+    let mut file = fs::File::create(&tmp_path)?;
+    serde_json::to_writer_pretty(&mut file, value)?;
+    writeln!(file)?;
+    file.sync_all()?;
+    fs::rename(&tmp_path, path)
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+    use std::net::IpAddr;
+    use std::time::Duration;
+
+    use crate::cli::{ReservedIpArgs, SubcmdArgs};
+    use crate::secret::Secret;
+    use crate::state::{now, FirewallIds, RetiredIp, State};
+
+    fn target(droplet: &str) -> SubcmdArgs {
+        SubcmdArgs::ReservedIp(ReservedIpArgs {
+            droplet: droplet.to_string(),
+            address: Some("45.55.96.47".parse().unwrap()),
+            from_droplet: None,
+        })
+    }
+
+    #[test]
+    fn test_record() {
+        let ip1: Vec<IpAddr> = vec!["1.2.3.4".parse().unwrap()];
+        let ip2: Vec<IpAddr> = vec!["5.6.7.8".parse().unwrap()];
+        let mut state = State::default();
+        state.record(&target("web"), &ip1);
+        state.record(&target("db"), &ip1);
+        state.record(&target("web"), &ip2);
+
+        assert_eq!(2, state.targets.len());
+        assert_eq!(ip2, state.get(&target("web")).unwrap().ips);
+        assert_eq!(ip1, state.get(&target("db")).unwrap().ips);
+        assert_eq!(None, state.get(&target("cache")));
+    }
+
+    #[test]
+    fn test_track_firewalls() {
+        let mut ids = FirewallIds::default();
+        ids.track("home", "fw1");
+        ids.track("office", "fw2");
+        assert_eq!(Some("fw1"), ids.get("home"));
+        // a firewall renamed in the targets is tracked under its new name only
+        ids.track("house", "fw1");
+        assert_eq!(None, ids.get("home"));
+        assert_eq!(Some("fw1"), ids.get("house"));
+        assert_eq!(Some("fw2"), ids.get("office"));
+
+        let token = Secret::new("dop_v1_abc123".to_string());
+        let mut state = State::default();
+        state.set_firewall_ids(&token, ids);
+        let json = serde_json::to_string(&state).unwrap();
+        assert!(json.contains(r#"{"house":"fw1","office":"fw2"}"#));
+        assert!(!json.contains("abc123"));
+        assert_eq!(state, serde_json::from_str(&json).unwrap());
+        // state files written before firewalls were tracked are still read
+        assert_eq!(
+            State::default(),
+            serde_json::from_str(r#"{"targets":[]}"#).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_track_firewalls_per_token() {
+        // two jobs updating firewalls of the same name in different accounts
+        let home_token = Secret::new("dop_v1_home".to_string());
+        let office_token = Secret::new("dop_v1_office".to_string());
+        let mut state = State::default();
+        for (token, id) in [(&home_token, "fw1"), (&office_token, "fw2")] {
+            let mut ids = state.firewall_ids(token);
+            assert_eq!(None, ids.get("edge"));
+            ids.track("edge", id);
+            state.set_firewall_ids(token, ids);
+        }
+        assert_eq!(Some("fw1"), state.firewall_ids(&home_token).get("edge"));
+        assert_eq!(Some("fw2"), state.firewall_ids(&office_token).get("edge"));
+
+        state.set_firewall_ids(&office_token, FirewallIds::default());
+        assert_eq!(1, state.firewalls.len());
+        assert_eq!(Some("fw1"), state.firewall_ids(&home_token).get("edge"));
+    }
+
+    /// A firewall target keeping the addresses it held before for a day
+    fn retaining(name: &str) -> SubcmdArgs {
+        SubcmdArgs::from_fields(vec![
+            ("type", "firewall".into()),
+            ("name", name.into()),
+            ("direction", "inbound".into()),
+            ("port", "22".into()),
+            ("protocols", vec!["tcp"].into()),
+            ("retain_days", 1.into()),
+        ])
+    }
+
+    #[test]
+    fn test_retire_and_expire() {
+        let ip1: IpAddr = "1.2.3.4".parse().unwrap();
+        let ip2: IpAddr = "5.6.7.8".parse().unwrap();
+        let ip3: IpAddr = "9.10.11.12".parse().unwrap();
+        let mut state = State::default();
+        for ips in [ip1, ip2, ip3] {
+            state.record(&retaining("web"), &[ips]);
+            state.record(&target("web"), &[ips]);
+        }
+        // an address published again is no longer retired
+        state.record(&retaining("web"), &[ip1]);
+        // a target that does not retain addresses does not keep them
+        assert_eq!(
+            Vec::<RetiredIp>::new(),
+            state.get(&target("web")).unwrap().retired
+        );
+
+        let web = state.get(&retaining("web")).unwrap();
+        let retired: Vec<IpAddr> = web.retired.iter().map(|r| r.ip).collect();
+        assert_eq!(vec![ip2, ip3], retired);
+        let day = Duration::from_secs(86400);
+        assert_eq!(Vec::<IpAddr>::new(), web.expired(day, now()));
+        assert_eq!(vec![ip2, ip3], web.expired(day, now() + 86400));
+
+        state.forget(&retaining("web"), &[ip2]);
+        let retired: Vec<IpAddr> = state
+            .get(&retaining("web"))
+            .unwrap()
+            .retired
+            .iter()
+            .map(|r| r.ip)
+            .collect();
+        assert_eq!(vec![ip3], retired);
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let dir = std::env::temp_dir().join(format!("dyn-dns-state-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("state.json");
+
+        assert_eq!(State::default(), State::load(&path).unwrap());
+
+        let mut state = State::default();
+        state.record(&target("web"), &["1.2.3.4".parse().unwrap()]);
+        state.save(&path).unwrap();
+        assert_eq!(state, State::load(&path).unwrap());
+
+        fs::write(&path, "not json").unwrap();
+        assert_eq!(State::default(), State::load(&path).unwrap());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,170 @@
+use std::cell::{Cell, RefCell};
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+use tracing::info;
+
+use crate::digitalocean::api::ApiStats;
+use crate::hooks::Change;
+
+/// Timing of the phases of a run, reported together with the API traffic counters.  Phases that
+/// happen more than once, such as looking up each of several records, are added together.
+#[derive(Debug)]
+pub struct RunStats {
+    started: Instant,
+    phases: RefCell<Vec<(&'static str, Duration)>>,
+    changes: Cell<u32>,
+    /// The changes of address among them, for the notifications of the run
+    changed: RefCell<Vec<Change>>,
+}
+
+/// The statistics of a finished run, as printed with `--stats-json`
+#[derive(Serialize, Debug, PartialEq)]
+pub struct RunReport {
+    pub api_calls: u32,
+    pub pages_fetched: u32,
+    pub bytes_received: u64,
+    pub duration_ms: u128,
+    pub phases_ms: Vec<PhaseReport>,
+    /// How many DNS records, firewall rules, and reserved IPs were changed (or would have been in
+    /// a dry run)
+    pub changes: u32,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct PhaseReport {
+    pub phase: &'static str,
+    pub duration_ms: u128,
+}
+
+impl RunStats {
+    pub fn start() -> RunStats {
+        RunStats {
+            started: Instant::now(),
+            phases: RefCell::new(Vec::new()),
+            changes: Cell::new(0),
+            changed: RefCell::new(Vec::new()),
+        }
+    }
+
+    /// Count a resource that was changed by the run, keeping the `change` of address if it had one
+    pub fn record_change(&self, change: Option<&Change>) {
+        self.changes.set(self.changes.get() + 1);
+        self.changed.borrow_mut().extend(change.cloned());
+    }
+
+    /// The changes of address the run has made so far
+    pub fn changed(&self) -> Vec<Change> {
+        self.changed.borrow().clone()
+    }
+
+    /// How many resources the run has changed so far
+    pub fn changes(&self) -> u32 {
+        self.changes.get()
+    }
+
+    /// Run one phase of the run, recording how long it took
+    pub async fn time<T, F: Future<Output = T>>(&self, phase: &'static str, f: F) -> T {
+        let started = Instant::now();
+        let result = f.await;
+        let elapsed = started.elapsed();
+        let mut phases = self.phases.borrow_mut();
+        match phases.iter_mut().find(|(p, _)| *p == phase) {
+            Some((_, total)) => *total += elapsed,
+            None => phases.push((phase, elapsed)),
+        }
+        result
+    }
+
+    pub fn report(&self, api: &ApiStats) -> RunReport {
+        RunReport {
+            api_calls: api.calls(),
+            pages_fetched: api.pages(),
+            bytes_received: api.bytes(),
+            duration_ms: self.started.elapsed().as_millis(),
+            phases_ms: self
+                .phases
+                .borrow()
+                .iter()
+                .map(|(phase, duration)| PhaseReport {
+                    phase,
+                    duration_ms: duration.as_millis(),
+                })
+                .collect(),
+            changes: self.changes.get(),
+        }
+    }
+}
+
+impl RunReport {
+    /// Log a summary of the API traffic of the run
+    pub fn log(&self) {
+        info!(
+            "Made {} API call(s) fetching {} page(s) and {} byte(s) in {}ms",
+            self.api_calls, self.pages_fetched, self.bytes_received, self.duration_ms
+        );
+    }
+
+    /// Log how long each phase of the run took and its share of the whole run
+    pub fn log_profile(&self) {
+        info!("Time spent in each phase:");
+        for phase in &self.phases_ms {
+            info!(
+                "  {:<16} {:>6}ms {:>5.1}%",
+                phase.phase,
+                phase.duration_ms,
+                percent(phase.duration_ms, self.duration_ms)
+            );
+        }
+        let accounted: u128 = self.phases_ms.iter().map(|p| p.duration_ms).sum();
+        let other = self.duration_ms.saturating_sub(accounted);
+        info!(
+            "  {:<16} {:>6}ms {:>5.1}%",
+            "other",
+            other,
+            percent(other, self.duration_ms)
+        );
+    }
+}
+
+fn percent(part: u128, whole: u128) -> f64 {
+    if whole == 0 {
+        0.0
+    } else {
+        part as f64 * 100.0 / whole as f64
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::digitalocean::api::ApiStats;
+    use crate::hooks::Change;
+    use crate::stats::RunStats;
+
+    #[tokio::test]
+    async fn test_report() {
+        let stats = RunStats::start();
+        assert_eq!(42, stats.time("detect", async { 42 }).await);
+        stats.time("update", async {}).await;
+        stats.time("detect", async {}).await;
+        stats.record_change(None);
+        let change = Change {
+            kind: "dns",
+            resource: "home.example.com (A)".to_string(),
+            old_ip: "1.2.3.4".to_string(),
+            new_ip: "5.6.7.8".to_string(),
+        };
+        stats.record_change(Some(&change));
+        assert_eq!(vec![change], stats.changed());
+
+        let report = stats.report(&ApiStats::default());
+        assert_eq!(0, report.api_calls);
+        assert_eq!(2, report.changes);
+        assert_eq!(
+            vec!["detect", "update"],
+            report.phases_ms.iter().map(|p| p.phase).collect::<Vec<_>>()
+        );
+        assert!(report.phases_ms.iter().map(|p| p.duration_ms).sum::<u128>() <= report.duration_ms);
+    }
+}